@@ -0,0 +1,385 @@
+//! Operator commands on the enclave's admin port.
+//!
+//! An enclave started with an operator key listens on a second port, apart
+//! from the data path, for [`AdminCommand`]s: status, the epochs of every
+//! key, key rotation, flushing caches, reloading settings and shutdown,
+//! and the key management requests of the data path, which it then refuses
+//! (see [`Message::is_key_management`]). Each connection opens with
+//! [`AdminRequest::Challenge`], answered with a random challenge, and every
+//! command after it is signed with the operator's Ed25519 key over
+//!
+//! ```text
+//! "nitro-oprf-admin-v1" || challenge || sequence || SHA-256(JSON of the command)
+//! ```
+//!
+//! where `sequence` counts the commands sent before it on the connection,
+//! big-endian. A recorded command so cannot be replayed, neither on another
+//! connection nor twice on the same one. The operator key is set on the
+//! enclave command line, which is part of the measured image, so the parent
+//! cannot swap it, and answers are not attested beyond what their data
+//! path counterparts carry.
+
+use crate::ed25519::{SigningKey, VerifyingKey, SIGNATURE_LEN};
+use crate::{
+    bounded, BeginKeyInjectionRequest, BeginKeyInjectionResponse, BeginReplicationResponse,
+    CiphersuiteId, ExportKeysRequest, ExportKeysResponse, ExportReplicaRequest,
+    ExportReplicaResponse, ImportKeysRequest, ImportKeysResponse, ImportReplicaRequest,
+    ImportReplicaResponse, InjectKeyRequest, InjectKeyResponse, Message, OprfError,
+    ProvisionSeedRequest, ProvisionSeedResponse, RotateKeyRequest, RotateKeyResponse,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Length of the challenge of an admin connection
+pub const CHALLENGE_LEN: usize = 32;
+
+/// Domain separating admin commands from other Ed25519 messages
+const COMMAND_DOMAIN: &[u8] = b"nitro-oprf-admin-v1";
+
+/// Operation an operator asks of the enclave
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum AdminCommand {
+    /// -> [`StatusResponse`]
+    Status,
+    /// -> [`KeyListResponse`]
+    ListKeys,
+    /// -> [`RotateKeyResponse`]
+    RotateKey(RotateKeyRequest),
+    /// -> [`FlushCachesResponse`]
+    FlushCaches,
+    /// -> [`ShutdownResponse`], then the enclave stops accepting
+    /// connections, answers the requests in progress and exits
+    Shutdown,
+    /// -> [`ReloadResponse`]
+    Reload(ReloadRequest),
+    /// -> [`ExportKeysResponse`]
+    ExportKeys(ExportKeysRequest),
+    /// -> [`ImportKeysResponse`]
+    ImportKeys(ImportKeysRequest),
+    /// -> [`ProvisionSeedResponse`]
+    ProvisionSeed(ProvisionSeedRequest),
+    /// -> [`BeginKeyInjectionResponse`]
+    BeginKeyInjection(BeginKeyInjectionRequest),
+    /// -> [`InjectKeyResponse`]
+    InjectKey(InjectKeyRequest),
+    /// -> [`BeginReplicationResponse`]
+    BeginReplication,
+    /// -> [`ExportReplicaResponse`]
+    ExportReplica(ExportReplicaRequest),
+    /// -> [`ImportReplicaResponse`]
+    ImportReplica(ImportReplicaRequest),
+}
+
+impl AdminCommand {
+    /// The command carrying a key management request of the data path,
+    /// `None` for other messages
+    pub fn from_message(message: &Message) -> Option<Self> {
+        Some(match message {
+            Message::RotateKey(request) => AdminCommand::RotateKey(request.clone()),
+            Message::ExportKeys(request) => AdminCommand::ExportKeys(request.clone()),
+            Message::ImportKeys(request) => AdminCommand::ImportKeys(request.clone()),
+            Message::ProvisionSeed(request) => AdminCommand::ProvisionSeed(request.clone()),
+            Message::BeginKeyInjection(request) => {
+                AdminCommand::BeginKeyInjection(request.clone())
+            }
+            Message::InjectKey(request) => AdminCommand::InjectKey(request.clone()),
+            Message::BeginReplication => AdminCommand::BeginReplication,
+            Message::ExportReplica(request) => AdminCommand::ExportReplica(request.clone()),
+            Message::ImportReplica(request) => AdminCommand::ImportReplica(request.clone()),
+            _ => return None,
+        })
+    }
+}
+
+/// Evaluation rate limit of a [`ReloadRequest`]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateSetting {
+    Unlimited,
+    /// `per_sec` evaluations a second, in bursts of at most `burst`
+    Limited { per_sec: u64, burst: u64 },
+}
+
+/// Settings of [`AdminCommand::Reload`] to change, keeping those left out.
+/// The keys and their evaluation budgets, the workers, listen addresses and
+/// operator key are fixed for the life of the enclave.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReloadRequest {
+    /// Rate of evaluations on one connection
+    pub connection_rate: Option<RateSetting>,
+    /// Rate of evaluations naming one client id, across connections
+    pub client_rate: Option<RateSetting>,
+    /// Data path connections open at once, queued ones included
+    pub max_connections: Option<usize>,
+    /// How long a connection may wait between requests
+    pub idle_timeout_secs: Option<u64>,
+    /// How long a request frame may take to arrive from its first byte
+    pub frame_timeout_secs: Option<u64>,
+    /// How long an evaluation attestation is reused for identical content
+    pub attestation_ttl_secs: Option<u64>,
+    /// Log sensitive values in full, see [`crate::redact`]
+    pub log_sensitive: Option<bool>,
+}
+
+/// An [`AdminCommand`] with the operator's signature over
+/// [`command_message`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SignedCommand {
+    pub command: AdminCommand,
+    #[serde(
+        serialize_with = "serde_bytes::serialize",
+        deserialize_with = "bounded::bytes::<_, SIGNATURE_LEN>"
+    )]
+    pub signature: Vec<u8>,
+}
+
+/// Any request the admin port accepts
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum AdminRequest {
+    /// Opening request of a connection -> [`ChallengeResponse`]
+    Challenge,
+    Command(Box<SignedCommand>),
+}
+
+/// Response to [`AdminRequest::Challenge`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChallengeResponse {
+    #[serde(
+        serialize_with = "serde_bytes::serialize",
+        deserialize_with = "bounded::bytes::<_, CHALLENGE_LEN>"
+    )]
+    pub challenge: Vec<u8>,
+}
+
+/// Response to [`AdminCommand::Status`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StatusResponse {
+    /// Version of the enclave server crate
+    pub version: String,
+    /// Seconds since the enclave started
+    pub uptime_secs: u64,
+    /// Number of named keys over every ciphersuite
+    pub key_count: usize,
+    /// Whether keys are derived from a provisioned seed
+    pub seed_provisioned: bool,
+    /// Data path connections open, queued ones included
+    pub open_connections: usize,
+    /// Requests answered on the data path, refused ones included
+    pub requests: u64,
+    /// Points evaluated
+    pub evaluations: u64,
+}
+
+/// One epoch of a named key
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EpochInfo {
+    pub epoch: u64,
+    /// Public key g^k serialized
+    pub public_key: Vec<u8>,
+    /// Evaluations made with the key
+    pub evaluations: u64,
+    /// Evaluation requests for the key that were refused or failed
+    #[serde(default)]
+    pub errors: u64,
+    /// Unix time of the last evaluation request for the key, refused ones
+    /// included; none if it was never used
+    #[serde(default)]
+    pub last_used: Option<u64>,
+    /// Unix time the key stops being accepted, for a previous key
+    pub expires_at: Option<u64>,
+}
+
+/// Epochs of one named key
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeyEpochs {
+    pub ciphersuite: CiphersuiteId,
+    pub key_id: String,
+    pub current: EpochInfo,
+    /// Key replaced by the last rotation, while it has not been dropped
+    pub previous: Option<EpochInfo>,
+}
+
+/// Response to [`AdminCommand::ListKeys`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyListResponse {
+    /// Every named key, by ciphersuite and key id
+    pub keys: Vec<KeyEpochs>,
+}
+
+/// Response to [`AdminCommand::FlushCaches`]: the entries dropped
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FlushCachesResponse {
+    /// Cached attestation documents
+    pub attestations: usize,
+    /// Cached evaluations, over every key epoch
+    pub evaluations: usize,
+}
+
+/// Response to [`AdminCommand::Shutdown`], sent before the enclave exits
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShutdownResponse {
+    /// Data path connections open as the shutdown began, closed once their
+    /// request in progress is answered
+    pub open_connections: usize,
+}
+
+/// Response to [`AdminCommand::Reload`]: the settings in force after it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReloadResponse {
+    pub connection_rate: RateSetting,
+    pub client_rate: RateSetting,
+    pub max_connections: usize,
+    pub idle_timeout_secs: u64,
+    pub frame_timeout_secs: u64,
+    pub attestation_ttl_secs: u64,
+    pub log_sensitive: bool,
+}
+
+/// Response to an [`AdminRequest`], encoded as the inner response
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum AdminResponse {
+    Challenge(ChallengeResponse),
+    Status(StatusResponse),
+    Keys(KeyListResponse),
+    RotateKey(RotateKeyResponse),
+    FlushCaches(FlushCachesResponse),
+    Shutdown(ShutdownResponse),
+    Reload(ReloadResponse),
+    ExportKeys(ExportKeysResponse),
+    ImportKeys(ImportKeysResponse),
+    ProvisionSeed(ProvisionSeedResponse),
+    BeginKeyInjection(BeginKeyInjectionResponse),
+    InjectKey(InjectKeyResponse),
+    BeginReplication(BeginReplicationResponse),
+    ExportReplica(ExportReplicaResponse),
+    ImportReplica(ImportReplicaResponse),
+}
+
+/// Message the operator signs for the `sequence`-th command on the
+/// connection of `challenge`
+pub fn command_message(challenge: &[u8], sequence: u64, command: &AdminCommand) -> Vec<u8> {
+    let encoded = serde_json::to_vec(command).expect("admin commands serialize");
+    let mut message = COMMAND_DOMAIN.to_vec();
+    message.extend_from_slice(challenge);
+    message.extend_from_slice(&sequence.to_be_bytes());
+    message.extend_from_slice(&Sha256::digest(encoded));
+    message
+}
+
+/// Sign `command` as the `sequence`-th command on the connection of
+/// `challenge`
+pub fn sign_command(
+    key: &SigningKey,
+    challenge: &[u8],
+    sequence: u64,
+    command: AdminCommand,
+) -> SignedCommand {
+    let signature = key.sign(&command_message(challenge, sequence, &command)).to_vec();
+    SignedCommand { command, signature }
+}
+
+/// Check that `signed` is the `sequence`-th command on the connection of
+/// `challenge`, signed by the operator `key`
+pub fn verify_command(
+    key: &VerifyingKey,
+    challenge: &[u8],
+    sequence: u64,
+    signed: &SignedCommand,
+) -> Result<(), OprfError> {
+    key.verify(&command_message(challenge, sequence, &signed.command), &signed.signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_std::test_rng;
+    use crate::ed25519::{SigningKey, VerifyingKey};
+    use crate::frame::WireFormat;
+    use crate::DEFAULT_KEY_ID;
+
+    #[test]
+    fn test_admin_command_signing() {
+        let mut rng = test_rng();
+        let key = SigningKey::generate(&mut rng);
+        let operator = VerifyingKey::from_bytes(&key.public_key()).unwrap();
+        let challenge = [3u8; CHALLENGE_LEN];
+        let signed = sign_command(&key, &challenge, 1, AdminCommand::FlushCaches);
+        verify_command(&operator, &challenge, 1, &signed).unwrap();
+
+        // The signature survives both wire formats
+        for format in [WireFormat::Json, WireFormat::Cbor] {
+            let encoded = format.encode(&AdminRequest::Command(Box::new(signed.clone()))).unwrap();
+            let AdminRequest::Command(decoded) = format.decode(&encoded).unwrap() else {
+                panic!("decoded another request");
+            };
+            verify_command(&operator, &challenge, 1, &decoded).unwrap();
+        }
+
+        // A command is bound to its connection, position and content
+        assert!(verify_command(&operator, &[4u8; CHALLENGE_LEN], 1, &signed).is_err());
+        assert!(verify_command(&operator, &challenge, 2, &signed).is_err());
+        let mut swapped = signed.clone();
+        swapped.command = AdminCommand::Shutdown;
+        assert!(verify_command(&operator, &challenge, 1, &swapped).is_err());
+        let rotate = |grace_period_secs| {
+            AdminCommand::RotateKey(RotateKeyRequest {
+                ciphersuite: CiphersuiteId::Bn254Sha256,
+                key_id: DEFAULT_KEY_ID.to_string(),
+                grace_period_secs,
+            })
+        };
+        let mut altered = sign_command(&key, &challenge, 0, rotate(Some(60)));
+        verify_command(&operator, &challenge, 0, &altered).unwrap();
+        altered.command = rotate(Some(0));
+        assert!(verify_command(&operator, &challenge, 0, &altered).is_err());
+
+        // Only the operator key signs commands
+        let other = SigningKey::generate(&mut rng);
+        let forged = sign_command(&other, &challenge, 1, AdminCommand::FlushCaches);
+        assert!(verify_command(&operator, &challenge, 1, &forged).is_err());
+
+        // Epochs listed by enclaves before usage counts read as unused
+        let info: EpochInfo = serde_json::from_str(
+            r#"{"epoch": 2, "public_key": [1, 2], "evaluations": 5, "expires_at": null}"#,
+        )
+        .unwrap();
+        assert_eq!((info.evaluations, info.errors, info.last_used), (5, 0, None));
+    }
+
+    #[test]
+    fn test_reload_request() {
+        // Settings left out are kept
+        let request: ReloadRequest = serde_json::from_str(
+            r#"{"client_rate": {"Limited": {"per_sec": 10, "burst": 20}}, "idle_timeout_secs": 5}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            request,
+            ReloadRequest {
+                client_rate: Some(RateSetting::Limited {
+                    per_sec: 10,
+                    burst: 20
+                }),
+                idle_timeout_secs: Some(5),
+                ..ReloadRequest::default()
+            }
+        );
+        assert!(serde_json::from_str::<ReloadRequest>(r#"{"workers": 8}"#).is_err());
+
+        for format in [WireFormat::Json, WireFormat::Cbor] {
+            let command = AdminCommand::Reload(ReloadRequest {
+                connection_rate: Some(RateSetting::Unlimited),
+                log_sensitive: Some(false),
+                ..request.clone()
+            });
+            let encoded = format.encode(&command).unwrap();
+            let AdminCommand::Reload(decoded) = format.decode(&encoded).unwrap() else {
+                panic!("decoded another command");
+            };
+            assert_eq!(decoded.connection_rate, Some(RateSetting::Unlimited));
+            assert_eq!(decoded.client_rate, request.client_rate);
+        }
+    }
+}
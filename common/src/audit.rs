@@ -0,0 +1,183 @@
+//! Hash-chained audit log of evaluations.
+//!
+//! The enclave appends an [`AuditRecord`] for every evaluation it performs
+//! and keeps the running head of the chain: starting from [`GENESIS`], each
+//! record replaces the head with
+//!
+//! ```text
+//! SHA-256("nitro-oprf-audit-v1" || head || encoding of the record)
+//! ```
+//!
+//! with integers big-endian and strings and byte strings prefixed by their
+//! 2-byte length. Periodically the enclave attests the head as a
+//! [`AuditCheckpoint`] over [`checkpoint_binding`]. Whoever holds the
+//! records up to a checkpoint can recompute its head with [`verify_chain`]
+//! and so prove, with the attestation, that the enclave performed exactly
+//! those evaluations in that order: a record cannot be left out, altered or
+//! reordered without changing every later head.
+
+use crate::{bounded, AttestationDocument, CiphersuiteId, OprfError};
+use crate::{MAX_CLIENT_NONCE_LEN, MAX_KEY_ID_LEN};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Head of the chain before its first record
+pub const GENESIS: [u8; 32] = [0; 32];
+
+/// Domain separating record hashes from other SHA-256 inputs
+const RECORD_DOMAIN: &[u8] = b"nitro-oprf-audit-v1";
+/// Domain separating checkpoints from other attested user data
+const CHECKPOINT_DOMAIN: &[u8] = b"nitro-oprf-audit-checkpoint-v1";
+
+/// One evaluation request the enclave served
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct AuditRecord {
+    /// Position in the log, from 0
+    pub sequence: u64,
+    /// Enclave clock when the evaluation was made, unix seconds
+    pub timestamp: u64,
+    /// Ciphersuite of the evaluated key
+    pub ciphersuite: CiphersuiteId,
+    /// Named key that evaluated the queries
+    #[serde(deserialize_with = "bounded::string::<_, MAX_KEY_ID_LEN>")]
+    pub key_id: String,
+    /// Epoch of that key
+    pub epoch: u64,
+    /// Number of blinded points evaluated
+    pub count: u64,
+    /// SHA-256 of the concatenated blinded points
+    #[serde(
+        serialize_with = "serde_bytes::serialize",
+        deserialize_with = "bounded::bytes::<_, 32>"
+    )]
+    pub query_hash: Vec<u8>,
+    /// Nonce sent by the client with the request
+    #[serde(
+        default,
+        serialize_with = "serde_bytes::serialize",
+        deserialize_with = "bounded::option_bytes::<_, MAX_CLIENT_NONCE_LEN>"
+    )]
+    pub client_nonce: Option<Vec<u8>>,
+}
+
+/// Attested head of the chain
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AuditCheckpoint {
+    /// Number of records the head covers
+    pub sequence: u64,
+    /// Head after the record `sequence - 1`
+    #[serde(
+        serialize_with = "serde_bytes::serialize",
+        deserialize_with = "bounded::bytes::<_, 32>"
+    )]
+    pub head: Vec<u8>,
+    /// Attestation over [`checkpoint_binding`] of the head
+    pub attestation: AttestationDocument,
+}
+
+fn length_prefixed(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+impl AuditRecord {
+    /// Head of the chain after this record, given the head before it
+    pub fn chain(&self, head: &[u8; 32]) -> [u8; 32] {
+        let mut encoded = Vec::with_capacity(128);
+        encoded.extend_from_slice(&self.sequence.to_be_bytes());
+        encoded.extend_from_slice(&self.timestamp.to_be_bytes());
+        length_prefixed(&mut encoded, self.ciphersuite.identifier().as_bytes());
+        length_prefixed(&mut encoded, self.key_id.as_bytes());
+        encoded.extend_from_slice(&self.epoch.to_be_bytes());
+        encoded.extend_from_slice(&self.count.to_be_bytes());
+        length_prefixed(&mut encoded, &self.query_hash);
+        match &self.client_nonce {
+            None => encoded.push(0),
+            Some(nonce) => {
+                encoded.push(1);
+                length_prefixed(&mut encoded, nonce);
+            }
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(RECORD_DOMAIN);
+        hasher.update(head);
+        hasher.update(&encoded);
+        hasher.finalize().into()
+    }
+}
+
+/// Attested user data of a checkpoint: `"nitro-oprf-audit-checkpoint-v1" ||
+/// I2OSP(sequence, 8) || head`
+pub fn checkpoint_binding(sequence: u64, head: &[u8]) -> Vec<u8> {
+    [CHECKPOINT_DOMAIN, &sequence.to_be_bytes(), head].concat()
+}
+
+/// Head of the chain after `records`, which must follow one another from
+/// sequence number `from`, given the head before them
+pub fn verify_chain(
+    head: &[u8; 32],
+    from: u64,
+    records: &[AuditRecord],
+) -> Result<[u8; 32], OprfError> {
+    let mut head = *head;
+    for (expected, record) in (from..).zip(records) {
+        if record.sequence != expected {
+            return Err(OprfError::InvalidAuditLog(format!(
+                "expected record {}, got {}",
+                expected, record.sequence
+            )));
+        }
+        head = record.chain(&head);
+    }
+    Ok(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::WireFormat;
+
+    #[test]
+    fn test_audit_chain() {
+        let records: Vec<AuditRecord> = (0..3)
+            .map(|sequence| AuditRecord {
+                sequence,
+                timestamp: 1_700_000_000 + sequence,
+                ciphersuite: CiphersuiteId::Bn254Sha256,
+                key_id: "billing".to_string(),
+                epoch: 0,
+                count: 1,
+                query_hash: vec![sequence as u8; 32],
+                client_nonce: (sequence != 1).then(|| vec![7; 16]),
+            })
+            .collect();
+        let head = verify_chain(&GENESIS, 0, &records).unwrap();
+        let middle = verify_chain(&GENESIS, 0, &records[..1]).unwrap();
+        assert_eq!(verify_chain(&middle, 1, &records[1..]).unwrap(), head);
+        assert_eq!(verify_chain(&head, 3, &[]).unwrap(), head);
+
+        // Any change to a record, or to their order, changes the head
+        let mut altered = records.clone();
+        altered[1].client_nonce = Some(Vec::new());
+        assert_ne!(verify_chain(&GENESIS, 0, &altered).unwrap(), head);
+        let mut altered = records.clone();
+        altered[2].key_id = "billin".to_string();
+        assert_ne!(verify_chain(&GENESIS, 0, &altered).unwrap(), head);
+        assert!(verify_chain(&GENESIS, 0, &records[1..]).is_err());
+        let mut reordered = records.clone();
+        reordered.swap(0, 1);
+        assert!(verify_chain(&GENESIS, 0, &reordered).is_err());
+
+        let binding = checkpoint_binding(3, &head);
+        assert_eq!(&binding[binding.len() - 40..binding.len() - 32], &3u64.to_be_bytes());
+        assert_eq!(&binding[binding.len() - 32..], &head);
+
+        for format in [WireFormat::Json, WireFormat::Cbor] {
+            let decoded: AuditRecord = format.decode(&format.encode(&records[0]).unwrap()).unwrap();
+            assert_eq!(decoded, records[0]);
+        }
+    }
+}
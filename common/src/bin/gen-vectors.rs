@@ -0,0 +1,22 @@
+//! Regenerates the golden test vectors of `testdata/`, see
+//! `oprf_common::vectors`.
+//!
+//! Usage: gen-vectors [DIR], writing to the repository's `testdata/` by
+//! default.
+
+use oprf_common::vectors::Vectors;
+use std::path::PathBuf;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = match std::env::args().nth(1) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../testdata"),
+    };
+    std::fs::create_dir_all(&dir)?;
+    for (name, contents) in Vectors::generate()?.to_files() {
+        let path = dir.join(name);
+        std::fs::write(&path, contents)?;
+        println!("Wrote {}", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,358 @@
+//! Attested secure channel between the parent and the enclave.
+//!
+//! The Noise NK handshake (`<- s`, `-> e, es`, `<- e, ee`) over P-256 with
+//! SHA-256 and Noise's AESGCM cipher functions (AES-256-GCM). The enclave
+//! generates its static key `s` at startup and attests it with
+//! [`channel_binding`]; a parent that verified the attestation knows that
+//! only the attested enclave can complete the handshake. Both ephemeral keys
+//! give forward secrecy.
+//!
+//! After the handshake every frame payload is sealed under the key of its
+//! direction, with the request id and format of the frame as associated data
+//! and the count of earlier frames in that direction as nonce, so frames
+//! cannot be modified, replayed, reordered or dropped unnoticed.
+
+use crate::frame::{Frame, WireFormat};
+use crate::kdf::hkdf;
+use crate::{Ciphersuite, OprfError, P256Sha256};
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use ark_std::rand::Rng;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+/// Group of the handshake keys
+type Group = P256Sha256;
+type Scalar = <Group as Ciphersuite>::Scalar;
+
+/// Noise protocol name, hashed into the initial handshake state
+const PROTOCOL_NAME: &[u8] = b"Noise_NK_P256_AESGCM_SHA256";
+/// Prologue mixed into the handshake hash before any key
+const PROLOGUE: &[u8] = b"nitro-oprf-channel-v1";
+/// Length of a cipher key in bytes
+const KEY_LEN: usize = 32;
+/// Length of the AES-GCM tag ending every sealed message
+const TAG_LEN: usize = 16;
+
+/// Length of each handshake message: an ephemeral key and an empty sealed
+/// payload
+pub const HANDSHAKE_MESSAGE_LEN: usize = <Group as Ciphersuite>::ELEMENT_LEN + TAG_LEN;
+
+/// Attestation user data binding the enclave's static channel key and the
+/// protocol versions the parent offered in its `Hello`:
+/// `"nitro-oprf-channel-v1" || public_key || count || versions`, the count
+/// and every version 4 bytes big-endian. A parent that finds other versions
+/// than it offered knows the `Hello` was tampered with. The first two bytes
+/// read as a key id length far above [`crate::MAX_KEY_ID_LEN`], so it never
+/// equals an [`crate::attested_user_data`] binding.
+pub fn channel_binding(public_key: &[u8], offered_versions: &[u32]) -> Vec<u8> {
+    let mut binding = [PROLOGUE, public_key].concat();
+    binding.extend_from_slice(&(offered_versions.len() as u32).to_be_bytes());
+    for version in offered_versions {
+        binding.extend_from_slice(&version.to_be_bytes());
+    }
+    binding
+}
+
+fn channel_error(message: &str) -> OprfError {
+    OprfError::Channel(message.to_string())
+}
+
+/// Diffie-Hellman: the serialized point `public^secret`
+fn dh(secret: &Scalar, public: &[u8]) -> Result<Zeroizing<Vec<u8>>, OprfError> {
+    let public = Group::deserialize_element(public)?;
+    Ok(Zeroizing::new(Group::serialize_element(&Group::scalar_mul(&public, secret))?))
+}
+
+/// A fresh key pair: the secret scalar and the serialized public key
+fn generate_key_pair<R: Rng>(rng: &mut R) -> Result<(Zeroizing<Scalar>, Vec<u8>), OprfError> {
+    let secret = Zeroizing::new(Group::random_scalar(rng));
+    let public_key = Group::serialize_element(&Group::scalar_mul_generator(&secret))?;
+    Ok((secret, public_key))
+}
+
+/// Split a handshake message into the sender's ephemeral key and its payload
+fn split_message(message: &[u8]) -> Result<(&[u8], &[u8]), OprfError> {
+    if message.len() != HANDSHAKE_MESSAGE_LEN {
+        return Err(channel_error("Handshake message has the wrong length"));
+    }
+    Ok(message.split_at(HANDSHAKE_MESSAGE_LEN - TAG_LEN))
+}
+
+/// Key of one direction and the count of messages sealed under it
+struct CipherState {
+    key: Zeroizing<[u8; KEY_LEN]>,
+    counter: u64,
+}
+
+impl CipherState {
+    fn new(key: &[u8]) -> Self {
+        let mut state = Self {
+            key: Zeroizing::new([0u8; KEY_LEN]),
+            counter: 0,
+        };
+        state.key.copy_from_slice(key);
+        state
+    }
+
+    /// Nonce of the next message, 32 zero bits and the big-endian counter,
+    /// then advance the counter
+    fn next_nonce(&mut self) -> Result<[u8; 12], OprfError> {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&self.counter.to_be_bytes());
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| channel_error("Channel nonces exhausted"))?;
+        Ok(nonce)
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.key[..]))
+    }
+
+    fn seal(&mut self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, OprfError> {
+        let nonce = self.next_nonce()?;
+        self.cipher()
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad })
+            .map_err(|_| channel_error("Message too long"))
+    }
+
+    fn open(&mut self, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, OprfError> {
+        let nonce = self.next_nonce()?;
+        self.cipher()
+            .decrypt(Nonce::from_slice(&nonce), Payload { msg: sealed, aad })
+            .map_err(|_| channel_error("Message failed authentication"))
+    }
+}
+
+/// Noise symmetric state: chaining key, handshake hash and current key
+struct SymmetricState {
+    chaining_key: Zeroizing<[u8; 32]>,
+    hash: [u8; 32],
+    cipher: Option<CipherState>,
+}
+
+impl SymmetricState {
+    /// Initial state of a handshake with the responder static key
+    /// `responder_static`, known to both sides in advance
+    fn new(responder_static: &[u8]) -> Self {
+        // The name is longer than a hash, so the initial hash is its hash
+        let hash: [u8; 32] = Sha256::digest(PROTOCOL_NAME).into();
+        let mut state = Self {
+            chaining_key: Zeroizing::new(hash),
+            hash,
+            cipher: None,
+        };
+        state.mix_hash(PROLOGUE);
+        state.mix_hash(responder_static);
+        state
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        self.hash = Sha256::new().chain_update(self.hash).chain_update(data).finalize().into();
+    }
+
+    /// Noise `HKDF(chaining_key, ikm)` with two outputs
+    fn derive(&self, ikm: &[u8]) -> Zeroizing<Vec<u8>> {
+        let mut okm = Zeroizing::new(vec![0u8; 2 * KEY_LEN]);
+        hkdf(&self.chaining_key[..], ikm, &[], &mut okm);
+        okm
+    }
+
+    fn mix_key(&mut self, ikm: &[u8]) {
+        let okm = self.derive(ikm);
+        self.chaining_key.copy_from_slice(&okm[..KEY_LEN]);
+        self.cipher = Some(CipherState::new(&okm[KEY_LEN..]));
+    }
+
+    fn encrypt_and_hash(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, OprfError> {
+        let cipher = self.cipher.as_mut().ok_or_else(|| channel_error("No handshake key"))?;
+        let sealed = cipher.seal(&self.hash, plaintext)?;
+        self.mix_hash(&sealed);
+        Ok(sealed)
+    }
+
+    fn decrypt_and_hash(&mut self, sealed: &[u8]) -> Result<Vec<u8>, OprfError> {
+        let cipher = self.cipher.as_mut().ok_or_else(|| channel_error("No handshake key"))?;
+        let plaintext = cipher.open(&self.hash, sealed)?;
+        self.mix_hash(sealed);
+        Ok(plaintext)
+    }
+
+    /// Keys of the two directions: initiator to responder, then back
+    fn split(self) -> (CipherState, CipherState) {
+        let okm = self.derive(&[]);
+        (
+            CipherState::new(&okm[..KEY_LEN]),
+            CipherState::new(&okm[KEY_LEN..]),
+        )
+    }
+}
+
+/// The enclave's static channel key, generated at startup
+pub struct StaticKey {
+    secret: Zeroizing<Scalar>,
+    public_key: Vec<u8>,
+}
+
+impl StaticKey {
+    pub fn generate<R: Rng>(rng: &mut R) -> Result<Self, OprfError> {
+        let (secret, public_key) = generate_key_pair(rng)?;
+        Ok(Self { secret, public_key })
+    }
+
+    /// Serialized public key, attested with [`channel_binding`]
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    /// Answer the parent's handshake message, returning the established
+    /// channel and the reply to send back
+    pub fn respond<R: Rng>(
+        &self,
+        message: &[u8],
+        rng: &mut R,
+    ) -> Result<(Channel, Vec<u8>), OprfError> {
+        let mut state = SymmetricState::new(&self.public_key);
+        let (initiator_ephemeral, payload) = split_message(message)?;
+        state.mix_hash(initiator_ephemeral);
+        state.mix_key(&dh(&self.secret, initiator_ephemeral)?);
+        state.decrypt_and_hash(payload)?;
+
+        let (ephemeral, mut reply) = generate_key_pair(rng)?;
+        state.mix_hash(&reply);
+        state.mix_key(&dh(&ephemeral, initiator_ephemeral)?);
+        reply.extend(state.encrypt_and_hash(&[])?);
+
+        let (receive, send) = state.split();
+        Ok((Channel { send, receive }, reply))
+    }
+}
+
+/// The parent's side of a handshake awaiting the enclave's reply
+pub struct Initiator {
+    state: SymmetricState,
+    ephemeral: Zeroizing<Scalar>,
+}
+
+impl Initiator {
+    /// Start a handshake with the enclave whose attested static key is
+    /// `responder_static`. Returns the message to send.
+    pub fn start<R: Rng>(
+        responder_static: &[u8],
+        rng: &mut R,
+    ) -> Result<(Self, Vec<u8>), OprfError> {
+        let mut state = SymmetricState::new(responder_static);
+        let (ephemeral, mut message) = generate_key_pair(rng)?;
+        state.mix_hash(&message);
+        state.mix_key(&dh(&ephemeral, responder_static)?);
+        message.extend(state.encrypt_and_hash(&[])?);
+        Ok((Self { state, ephemeral }, message))
+    }
+
+    /// Complete the handshake with the enclave's reply
+    pub fn finish(mut self, reply: &[u8]) -> Result<Channel, OprfError> {
+        let (responder_ephemeral, payload) = split_message(reply)?;
+        self.state.mix_hash(responder_ephemeral);
+        self.state.mix_key(&dh(&self.ephemeral, responder_ephemeral)?);
+        self.state.decrypt_and_hash(payload)?;
+
+        let (send, receive) = self.state.split();
+        Ok(Channel { send, receive })
+    }
+}
+
+/// An established channel, sealing outgoing and opening incoming frames
+pub struct Channel {
+    send: CipherState,
+    receive: CipherState,
+}
+
+/// Associated data of a frame payload: its request id and format
+fn frame_aad(request_id: u64, format: WireFormat) -> [u8; 9] {
+    let mut aad = [0u8; 9];
+    aad[..8].copy_from_slice(&request_id.to_be_bytes());
+    aad[8] = format.id();
+    aad
+}
+
+impl Channel {
+    /// Seal the payload of the next outgoing frame
+    pub fn seal(
+        &mut self,
+        request_id: u64,
+        format: WireFormat,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, OprfError> {
+        self.send.seal(&frame_aad(request_id, format), payload)
+    }
+
+    /// Open the payload of the next incoming frame in place
+    pub fn open(&mut self, frame: &mut Frame) -> Result<(), OprfError> {
+        let aad = frame_aad(frame.request_id, frame.format);
+        frame.payload = Zeroizing::new(self.receive.open(&aad, &frame.payload)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_std::test_rng;
+    use crate::frame::{Frame, WireFormat};
+    use crate::MAX_KEY_ID_LEN;
+    use zeroize::Zeroizing;
+
+    #[test]
+    fn test_secure_channel() {
+        let mut rng = test_rng();
+        let enclave_key = StaticKey::generate(&mut rng).unwrap();
+        let (initiator, message) = Initiator::start(enclave_key.public_key(), &mut rng).unwrap();
+        assert_eq!(message.len(), HANDSHAKE_MESSAGE_LEN);
+        let (mut enclave, reply) = enclave_key.respond(&message, &mut rng).unwrap();
+        let mut parent = initiator.finish(&reply).unwrap();
+
+        let frame = |request_id: u64, payload: Vec<u8>| Frame {
+            request_id,
+            format: WireFormat::Cbor,
+            payload: Zeroizing::new(payload),
+        };
+        for id in 1..=3u64 {
+            let sealed = parent.seal(id, WireFormat::Cbor, b"request").unwrap();
+            assert_ne!(&sealed[..7], b"request");
+            let mut request = frame(id, sealed);
+            enclave.open(&mut request).unwrap();
+            assert_eq!(request.payload.as_slice(), b"request");
+
+            let mut response = frame(id, enclave.seal(id, WireFormat::Cbor, b"reply").unwrap());
+            parent.open(&mut response).unwrap();
+            assert_eq!(response.payload.as_slice(), b"reply");
+        }
+
+        // A frame with another header, a replayed frame or a frame sealed for
+        // the other direction is rejected
+        let sealed = parent.seal(4, WireFormat::Cbor, b"request").unwrap();
+        assert!(enclave.open(&mut frame(5, sealed.clone())).is_err());
+        assert!(enclave.open(&mut frame(4, sealed)).is_err());
+        let sealed = parent.seal(6, WireFormat::Cbor, b"request").unwrap();
+        assert!(parent.open(&mut frame(6, sealed)).is_err());
+
+        // Only the holder of the attested static key completes a handshake
+        let impostor = StaticKey::generate(&mut rng).unwrap();
+        let (initiator, message) = Initiator::start(enclave_key.public_key(), &mut rng).unwrap();
+        assert!(impostor.respond(&message, &mut rng).is_err());
+        let (_, reply) = impostor
+            .respond(&Initiator::start(impostor.public_key(), &mut rng).unwrap().1, &mut rng)
+            .unwrap();
+        assert!(initiator.finish(&reply).is_err());
+        assert!(enclave_key.respond(&message[1..], &mut rng).is_err());
+
+        // The binding cannot be mistaken for a key binding
+        let binding = channel_binding(enclave_key.public_key(), &[3, 4]);
+        assert!(u16::from_be_bytes([binding[0], binding[1]]) as usize > MAX_KEY_ID_LEN);
+
+        // Nor for other offered versions
+        assert_ne!(binding, channel_binding(enclave_key.public_key(), &[3]));
+    }
+}
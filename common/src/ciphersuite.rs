@@ -0,0 +1,677 @@
+//! Prime-order group abstraction used by the OPRF protocol.
+//!
+//! Protocol code (blinding, evaluation, DLEQ proofs) is written against the
+//! [`Ciphersuite`] trait so new curves can be added by implementing it,
+//! without touching the enclave or parent logic.
+
+use crate::{ct, OprfError, OUTPUT_LEN};
+// Constant-time multiplication of BN254 points, split by the endomorphism
+// with the `glv` feature
+#[cfg(not(feature = "glv"))]
+use crate::ct::{mul as g1_mul, mul_many as g1_mul_many};
+#[cfg(feature = "glv")]
+use crate::glv::{mul as g1_mul, mul_many as g1_mul_many};
+use ark_bn254::{Fq, Fr, G1Affine, G1Projective};
+use ark_ec::{AffineRepr, CurveGroup, Group, VariableBaseMSM};
+use ark_ff::field_hashers::{DefaultFieldHasher, HashToField};
+use ark_ff::{BigInteger, Field, PrimeField, UniformRand};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Compress, Validate};
+use ark_std::rand::Rng;
+use ark_std::Zero;
+use p256::elliptic_curve::group::GroupEncoding;
+use p256::elliptic_curve::hash2curve::{ExpandMsgXmd, GroupDigest};
+use p256::elliptic_curve::sec1::{FromEncodedPoint, ToEncodedPoint};
+use p256::elliptic_curve::{Field as _, PrimeField as _};
+use p256::{EncodedPoint, NistP256, ProjectivePoint, Scalar};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::Debug;
+use std::ops::{Add, Mul, Sub};
+use std::sync::OnceLock;
+use zeroize::Zeroize;
+
+/// Wire identifier selecting the ciphersuite of a request
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum CiphersuiteId {
+    /// [`Bn254Sha256`]
+    #[default]
+    Bn254Sha256,
+    /// [`P256Sha256`]
+    P256Sha256,
+}
+
+impl CiphersuiteId {
+    /// All supported ciphersuites
+    pub const ALL: [CiphersuiteId; 2] = [CiphersuiteId::Bn254Sha256, CiphersuiteId::P256Sha256];
+
+    /// RFC 9497 identifier of the suite
+    pub fn identifier(&self) -> &'static str {
+        match self {
+            CiphersuiteId::Bn254Sha256 => Bn254Sha256::IDENTIFIER,
+            CiphersuiteId::P256Sha256 => P256Sha256::IDENTIFIER,
+        }
+    }
+}
+
+impl std::str::FromStr for CiphersuiteId {
+    type Err = OprfError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bn254" | "bn254-sha256" => Ok(CiphersuiteId::Bn254Sha256),
+            "p256" | "p-256" | "p256-sha256" => Ok(CiphersuiteId::P256Sha256),
+            _ => Err(OprfError::UnsupportedCiphersuite(s.to_string())),
+        }
+    }
+}
+
+/// Encoding of the group elements in requests and responses, negotiated per
+/// connection in the [`crate::HelloRequest`]. Public keys, proofs and the
+/// DLEQ transcript always use the compressed encoding.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PointEncoding {
+    /// One coordinate and the sign of the other, see
+    /// [`Ciphersuite::serialize_element`]
+    #[default]
+    Compressed,
+    /// Both coordinates, see [`Ciphersuite::serialize_element_uncompressed`]:
+    /// twice the bytes, but decoding checks the curve equation instead of
+    /// taking a square root
+    Uncompressed,
+}
+
+impl PointEncoding {
+    pub fn is_compressed(&self) -> bool {
+        *self == PointEncoding::Compressed
+    }
+}
+
+impl std::str::FromStr for PointEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "compressed" => Ok(PointEncoding::Compressed),
+            "uncompressed" => Ok(PointEncoding::Uncompressed),
+            _ => Err(format!("Unknown point encoding: {}", s)),
+        }
+    }
+}
+
+/// A prime-order group together with its hash functions (RFC 9497 Section 4)
+pub trait Ciphersuite: Sized + Send + Sync + 'static {
+    /// Wire identifier carried in requests
+    const ID: CiphersuiteId;
+    /// Identifier used in the RFC 9497 context string
+    const IDENTIFIER: &'static str;
+    /// Length of a serialized element in bytes
+    const ELEMENT_LEN: usize;
+    /// Length of an uncompressed element in bytes
+    const UNCOMPRESSED_ELEMENT_LEN: usize;
+    /// Length of a serialized scalar in bytes
+    const SCALAR_LEN: usize;
+
+    /// Scalar field element; `Default` is zero
+    type Scalar: Copy
+        + Debug
+        + Default
+        + PartialEq
+        + Send
+        + Sync
+        + Zeroize
+        + Add<Output = Self::Scalar>
+        + Sub<Output = Self::Scalar>
+        + Mul<Output = Self::Scalar>;
+
+    /// Group element
+    type Element: Copy
+        + Debug
+        + PartialEq
+        + Send
+        + Sync
+        + Add<Output = Self::Element>
+        + Mul<Self::Scalar, Output = Self::Element>;
+
+    /// Get the group generator
+    fn generator() -> Self::Element;
+
+    /// Get the identity element
+    fn identity() -> Self::Element;
+
+    /// Sample a random non-zero scalar
+    fn random_scalar<R: Rng>(rng: &mut R) -> Self::Scalar;
+
+    /// Compute the multiplicative inverse of a scalar
+    fn scalar_inverse(scalar: &Self::Scalar) -> Option<Self::Scalar>;
+
+    /// Serialize an element to its canonical encoding
+    fn serialize_element(element: &Self::Element) -> Result<Vec<u8>, OprfError>;
+
+    /// Serialize every element, as [`Ciphersuite::serialize_element`] does
+    /// one but normalizing them together where the curve allows
+    fn serialize_elements(elements: &[Self::Element]) -> Result<Vec<Vec<u8>>, OprfError> {
+        elements.iter().map(Self::serialize_element).collect()
+    }
+
+    /// Deserialize an element from its canonical encoding. The identity,
+    /// points off the curve and any other encoding of a point are rejected
+    /// with [`OprfError::InvalidPoint`].
+    fn deserialize_element(bytes: &[u8]) -> Result<Self::Element, OprfError>;
+
+    /// Serialize an element with both of its affine coordinates
+    fn serialize_element_uncompressed(element: &Self::Element) -> Result<Vec<u8>, OprfError>;
+
+    /// Serialize every element uncompressed, normalizing them together where
+    /// the curve allows
+    fn serialize_elements_uncompressed(
+        elements: &[Self::Element],
+    ) -> Result<Vec<Vec<u8>>, OprfError> {
+        elements.iter().map(Self::serialize_element_uncompressed).collect()
+    }
+
+    /// Deserialize an element from its uncompressed encoding, rejecting what
+    /// [`Ciphersuite::deserialize_element`] rejects
+    fn deserialize_element_uncompressed(bytes: &[u8]) -> Result<Self::Element, OprfError>;
+
+    /// Serialize every element in `encoding`
+    fn encode_elements(
+        elements: &[Self::Element],
+        encoding: PointEncoding,
+    ) -> Result<Vec<Vec<u8>>, OprfError> {
+        match encoding {
+            PointEncoding::Compressed => Self::serialize_elements(elements),
+            PointEncoding::Uncompressed => Self::serialize_elements_uncompressed(elements),
+        }
+    }
+
+    /// Deserialize an element in `encoding`
+    fn decode_element(bytes: &[u8], encoding: PointEncoding) -> Result<Self::Element, OprfError> {
+        match encoding {
+            PointEncoding::Compressed => Self::deserialize_element(bytes),
+            PointEncoding::Uncompressed => Self::deserialize_element_uncompressed(bytes),
+        }
+    }
+
+    /// Serialize a scalar to its canonical encoding
+    fn serialize_scalar(scalar: &Self::Scalar) -> Result<Vec<u8>, OprfError>;
+
+    /// Deserialize a scalar from its canonical encoding
+    fn deserialize_scalar(bytes: &[u8]) -> Result<Self::Scalar, OprfError>;
+
+    /// Hash an input to a group element under a domain separation tag
+    fn hash_to_group(input: &[u8], dst: &[u8]) -> Self::Element;
+
+    /// Hash an input to a scalar under a domain separation tag
+    fn hash_to_scalar(input: &[u8], dst: &[u8]) -> Self::Scalar;
+
+    /// Hash function output, used for seeds and finalization
+    fn hash(data: &[u8]) -> [u8; OUTPUT_LEN];
+
+    /// Compute g^scalar, in time independent of `scalar`
+    fn scalar_mul_generator(scalar: &Self::Scalar) -> Self::Element {
+        Self::generator() * *scalar
+    }
+
+    /// Compute point^scalar, in time independent of `scalar`. Secret
+    /// scalars are only ever multiplied through this and
+    /// [`Ciphersuite::scalar_mul_generator`]; the `Mul` operator of the
+    /// element may take time depending on the scalar.
+    fn scalar_mul(point: &Self::Element, scalar: &Self::Scalar) -> Self::Element {
+        *point * *scalar
+    }
+
+    /// Compute point^scalar for every point, in time independent of
+    /// `scalar`. Batch evaluation multiplies all its points by one key
+    /// through this.
+    fn scalar_mul_many(points: &[Self::Element], scalar: &Self::Scalar) -> Vec<Self::Element> {
+        points.iter().map(|point| Self::scalar_mul(point, scalar)).collect()
+    }
+
+    /// Compute the sum of `points[i]^scalars[i]`, zipping the two slices.
+    /// The time taken depends on the scalars, which must be public, such as
+    /// the DLEQ composite weights.
+    fn multi_scalar_mul(points: &[Self::Element], scalars: &[Self::Scalar]) -> Self::Element {
+        points
+            .iter()
+            .zip(scalars)
+            .fold(Self::identity(), |sum, (point, scalar)| sum + *point * *scalar)
+    }
+}
+
+fn check_len<C: Ciphersuite>(bytes: &[u8], encoding: PointEncoding) -> Result<(), OprfError> {
+    let len = match encoding {
+        PointEncoding::Compressed => C::ELEMENT_LEN,
+        PointEncoding::Uncompressed => C::UNCOMPRESSED_ELEMENT_LEN,
+    };
+    if bytes.len() != len {
+        return Err(OprfError::InvalidPoint(format!(
+            "{} element must be {} bytes, got {}",
+            C::IDENTIFIER,
+            len,
+            bytes.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Reject the identity, and encodings the element does not serialize back
+/// to, such as flag bits the decoder ignores
+fn check_element<C: Ciphersuite>(
+    bytes: &[u8],
+    element: C::Element,
+    encoding: PointEncoding,
+) -> Result<C::Element, OprfError> {
+    if element == C::identity() {
+        return Err(OprfError::InvalidPoint("identity element".to_string()));
+    }
+    let canonical = match encoding {
+        PointEncoding::Compressed => C::serialize_element(&element)?,
+        PointEncoding::Uncompressed => C::serialize_element_uncompressed(&element)?,
+    };
+    if canonical != bytes {
+        return Err(OprfError::InvalidPoint("non-canonical encoding".to_string()));
+    }
+    Ok(element)
+}
+
+/// Encoding of a BN254 G1 point, compressed or not
+fn serialize_affine(affine: &G1Affine, compress: Compress) -> Result<Vec<u8>, OprfError> {
+    let mut bytes = Vec::new();
+    affine
+        .serialize_with_mode(&mut bytes, compress)
+        .map_err(|e| OprfError::Serialization(e.to_string()))?;
+    Ok(bytes)
+}
+
+/// BN254 G1 with SHA-256 (not an RFC 9497 registered suite)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bn254Sha256;
+
+impl Ciphersuite for Bn254Sha256 {
+    const ID: CiphersuiteId = CiphersuiteId::Bn254Sha256;
+    const IDENTIFIER: &'static str = "BN254-SHA256";
+    const ELEMENT_LEN: usize = 32;
+    const UNCOMPRESSED_ELEMENT_LEN: usize = 64;
+    const SCALAR_LEN: usize = 32;
+
+    type Scalar = Fr;
+    type Element = G1Projective;
+
+    fn generator() -> G1Projective {
+        G1Projective::generator()
+    }
+
+    fn identity() -> G1Projective {
+        G1Projective::zero()
+    }
+
+    fn random_scalar<R: Rng>(rng: &mut R) -> Fr {
+        loop {
+            let scalar = Fr::rand(rng);
+            if !scalar.is_zero() {
+                return scalar;
+            }
+        }
+    }
+
+    fn scalar_inverse(scalar: &Fr) -> Option<Fr> {
+        scalar.inverse()
+    }
+
+    fn serialize_element(element: &G1Projective) -> Result<Vec<u8>, OprfError> {
+        serialize_affine(&element.into_affine(), Compress::Yes)
+    }
+
+    /// One field inversion for all the points with `normalize_batch`, where
+    /// converting each to affine coordinates takes one per point
+    fn serialize_elements(elements: &[G1Projective]) -> Result<Vec<Vec<u8>>, OprfError> {
+        G1Projective::normalize_batch(elements)
+            .iter()
+            .map(|affine| serialize_affine(affine, Compress::Yes))
+            .collect()
+    }
+
+    fn deserialize_element(bytes: &[u8]) -> Result<G1Projective, OprfError> {
+        check_len::<Self>(bytes, PointEncoding::Compressed)?;
+        let affine = G1Affine::deserialize_compressed(bytes)
+            .map_err(|e| OprfError::InvalidPoint(e.to_string()))?;
+        check_element::<Self>(bytes, affine.into_group(), PointEncoding::Compressed)
+    }
+
+    /// Little-endian x then y, with the flags of arkworks in the top bits of y
+    fn serialize_element_uncompressed(element: &G1Projective) -> Result<Vec<u8>, OprfError> {
+        serialize_affine(&element.into_affine(), Compress::No)
+    }
+
+    fn serialize_elements_uncompressed(
+        elements: &[G1Projective],
+    ) -> Result<Vec<Vec<u8>>, OprfError> {
+        G1Projective::normalize_batch(elements)
+            .iter()
+            .map(|affine| serialize_affine(affine, Compress::No))
+            .collect()
+    }
+
+    /// G1 has cofactor 1, so every point on the curve is in the group: only
+    /// the curve equation is checked, not the multiplication by the group
+    /// order that arkworks' validation does
+    fn deserialize_element_uncompressed(bytes: &[u8]) -> Result<G1Projective, OprfError> {
+        check_len::<Self>(bytes, PointEncoding::Uncompressed)?;
+        let affine = G1Affine::deserialize_with_mode(bytes, Compress::No, Validate::No)
+            .map_err(|e| OprfError::InvalidPoint(e.to_string()))?;
+        if !affine.is_on_curve() {
+            return Err(OprfError::InvalidPoint("not on BN254 G1".to_string()));
+        }
+        check_element::<Self>(bytes, affine.into_group(), PointEncoding::Uncompressed)
+    }
+
+    fn serialize_scalar(scalar: &Fr) -> Result<Vec<u8>, OprfError> {
+        let mut bytes = Vec::new();
+        scalar
+            .serialize_compressed(&mut bytes)
+            .map_err(|e| OprfError::Serialization(e.to_string()))?;
+        Ok(bytes)
+    }
+
+    fn deserialize_scalar(bytes: &[u8]) -> Result<Fr, OprfError> {
+        Fr::deserialize_compressed(bytes).map_err(|e| OprfError::Deserialization(e.to_string()))
+    }
+
+    /// Try-and-increment: `hash_to_field` with `expand_message_xmd` picks a
+    /// candidate x-coordinate until it lies on the curve, with the y-coordinate
+    /// chosen by the parity of x. BN254 G1 has cofactor 1, so every curve point
+    /// is in the prime-order group. The number of attempts depends on the
+    /// input, so this is not constant time with respect to `input`.
+    fn hash_to_group(input: &[u8], dst: &[u8]) -> G1Projective {
+        let hasher = <DefaultFieldHasher<Sha256> as HashToField<Fq>>::new(dst);
+
+        let mut msg = input.to_vec();
+        msg.extend_from_slice(&[0u8; 4]);
+        let counter_offset = input.len();
+        let mut counter: u32 = 0;
+        loop {
+            msg[counter_offset..].copy_from_slice(&counter.to_be_bytes());
+            let x: Fq = hasher.hash_to_field(&msg, 1)[0];
+            let greatest = x.into_bigint().is_odd();
+            if let Some(point) = G1Affine::get_point_from_x_unchecked(x, greatest) {
+                return point.into_group();
+            }
+            counter += 1;
+        }
+    }
+
+    fn hash_to_scalar(input: &[u8], dst: &[u8]) -> Fr {
+        let hasher = <DefaultFieldHasher<Sha256> as HashToField<Fr>>::new(dst);
+        hasher.hash_to_field(input, 1)[0]
+    }
+
+    fn hash(data: &[u8]) -> [u8; OUTPUT_LEN] {
+        Sha256::digest(data).into()
+    }
+
+    /// Fixed-base table of [`crate::ct::FixedBase`], built on first use, as
+    /// arkworks' double-and-add follows the bits of the scalar
+    fn scalar_mul_generator(scalar: &Fr) -> G1Projective {
+        static GENERATOR: OnceLock<ct::FixedBase<G1Projective>> = OnceLock::new();
+        GENERATOR.get_or_init(|| ct::FixedBase::new(&Self::generator())).mul(scalar)
+    }
+
+    fn scalar_mul(point: &G1Projective, scalar: &Fr) -> G1Projective {
+        g1_mul(point, scalar)
+    }
+
+    fn scalar_mul_many(points: &[G1Projective], scalar: &Fr) -> Vec<G1Projective> {
+        g1_mul_many(points, scalar)
+    }
+
+    /// Pippenger's bucket method of arkworks' [`VariableBaseMSM`], which
+    /// for large batches costs a fraction of one multiplication per point
+    fn multi_scalar_mul(points: &[G1Projective], scalars: &[Fr]) -> G1Projective {
+        let len = points.len().min(scalars.len());
+        let bases = G1Projective::normalize_batch(&points[..len]);
+        G1Projective::msm_unchecked(&bases, &scalars[..len])
+    }
+}
+
+/// Decode a SEC1 encoded P-256 point, checking that it is on the curve
+fn decode_sec1(bytes: &[u8]) -> Result<ProjectivePoint, OprfError> {
+    let encoded =
+        EncodedPoint::from_bytes(bytes).map_err(|e| OprfError::InvalidPoint(e.to_string()))?;
+    Option::<p256::AffinePoint>::from(p256::AffinePoint::from_encoded_point(&encoded))
+        .map(ProjectivePoint::from)
+        .ok_or_else(|| OprfError::InvalidPoint("not on P-256".to_string()))
+}
+
+/// OPRF(P-256, SHA-256) from RFC 9497 Section 4.3
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct P256Sha256;
+
+impl Ciphersuite for P256Sha256 {
+    const ID: CiphersuiteId = CiphersuiteId::P256Sha256;
+    const IDENTIFIER: &'static str = "P256-SHA256";
+    const ELEMENT_LEN: usize = 33;
+    const UNCOMPRESSED_ELEMENT_LEN: usize = 65;
+    const SCALAR_LEN: usize = 32;
+
+    type Scalar = Scalar;
+    type Element = ProjectivePoint;
+
+    fn generator() -> ProjectivePoint {
+        ProjectivePoint::GENERATOR
+    }
+
+    fn identity() -> ProjectivePoint {
+        ProjectivePoint::IDENTITY
+    }
+
+    fn random_scalar<R: Rng>(rng: &mut R) -> Scalar {
+        loop {
+            let scalar = Scalar::random(&mut *rng);
+            if !bool::from(scalar.is_zero()) {
+                return scalar;
+            }
+        }
+    }
+
+    fn scalar_inverse(scalar: &Scalar) -> Option<Scalar> {
+        scalar.invert().into()
+    }
+
+    /// SEC1 compressed encoding
+    fn serialize_element(element: &ProjectivePoint) -> Result<Vec<u8>, OprfError> {
+        Ok(element.to_bytes().to_vec())
+    }
+
+    fn deserialize_element(bytes: &[u8]) -> Result<ProjectivePoint, OprfError> {
+        check_len::<Self>(bytes, PointEncoding::Compressed)?;
+        check_element::<Self>(bytes, decode_sec1(bytes)?, PointEncoding::Compressed)
+    }
+
+    /// SEC1 uncompressed encoding, 0x04 then x and y big-endian
+    fn serialize_element_uncompressed(element: &ProjectivePoint) -> Result<Vec<u8>, OprfError> {
+        Ok(element.to_affine().to_encoded_point(false).as_bytes().to_vec())
+    }
+
+    fn deserialize_element_uncompressed(bytes: &[u8]) -> Result<ProjectivePoint, OprfError> {
+        check_len::<Self>(bytes, PointEncoding::Uncompressed)?;
+        check_element::<Self>(bytes, decode_sec1(bytes)?, PointEncoding::Uncompressed)
+    }
+
+    /// 32-byte big-endian encoding
+    fn serialize_scalar(scalar: &Scalar) -> Result<Vec<u8>, OprfError> {
+        Ok(scalar.to_bytes().to_vec())
+    }
+
+    fn deserialize_scalar(bytes: &[u8]) -> Result<Scalar, OprfError> {
+        let repr: [u8; 32] = bytes.try_into().map_err(|_| {
+            OprfError::Deserialization(format!("P-256 scalar must be 32 bytes, got {}", bytes.len()))
+        })?;
+        Option::from(Scalar::from_repr(repr.into()))
+            .ok_or_else(|| OprfError::Deserialization("Scalar out of range".to_string()))
+    }
+
+    /// `P256_XMD:SHA-256_SSWU_RO_` from RFC 9380
+    fn hash_to_group(input: &[u8], dst: &[u8]) -> ProjectivePoint {
+        NistP256::hash_from_bytes::<ExpandMsgXmd<Sha256>>(&[input], &[dst])
+            .expect("DST length is within expand_message_xmd bounds")
+    }
+
+    fn hash_to_scalar(input: &[u8], dst: &[u8]) -> Scalar {
+        NistP256::hash_to_scalar::<ExpandMsgXmd<Sha256>>(&[input], &[dst])
+            .expect("DST length is within expand_message_xmd bounds")
+    }
+
+    fn hash(data: &[u8]) -> [u8; OUTPUT_LEN] {
+        Sha256::digest(data).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_std::test_rng;
+    use crate::{
+        deserialize_g1, deserialize_g2, g1_generator, generate_proof, hash_to_group, pairing,
+        serialize_g1, serialize_g2, verify_proof, DleqProof, OprfMode,
+    };
+
+    #[test]
+    fn test_invalid_points() {
+        fn check<C: Ciphersuite>(identity: &[u8], out_of_range: &[u8]) {
+            let invalid = |bytes: &[u8]| {
+                matches!(C::deserialize_element(bytes), Err(OprfError::InvalidPoint(_)))
+            };
+            let mut rng = test_rng();
+            let point = C::scalar_mul_generator(&C::random_scalar(&mut rng));
+            let bytes = C::serialize_element(&point).unwrap();
+            assert_eq!(C::deserialize_element(&bytes).unwrap(), point);
+
+            assert!(invalid(identity));
+            assert!(invalid(out_of_range));
+            assert!(invalid(&bytes[1..]));
+            assert!(invalid(&[bytes.as_slice(), &[0]].concat()));
+            assert!(invalid(&[]));
+        }
+        // BN254: x = 0 with the infinity flag, and x = 2^254 - 1 >= p
+        let mut identity = vec![0u8; 32];
+        identity[31] = 0x40;
+        let mut out_of_range = vec![0xff; 32];
+        out_of_range[31] = 0x3f;
+        check::<Bn254Sha256>(&identity, &out_of_range);
+        // P-256: the all-zero encoding, and x = 2^256 - 1 >= p
+        check::<P256Sha256>(&[0; 33], &[[0x02].as_slice(), &[0xff; 32]].concat());
+
+        // The infinity flag on a point's own x, which arkworks decodes as the
+        // identity
+        let mut bytes = serialize_g1(&g1_generator()).unwrap();
+        bytes[31] |= 0x40;
+        assert!(matches!(deserialize_g1(&bytes), Err(OprfError::InvalidPoint(_))));
+
+        let g2 = serialize_g2(&pairing::g2_generator()).unwrap();
+        assert_eq!(deserialize_g2(&g2).unwrap(), pairing::g2_generator());
+        let identity = serialize_g2(&(pairing::g2_generator() * Fr::from(0u64))).unwrap();
+        assert!(matches!(deserialize_g2(&identity), Err(OprfError::InvalidPoint(_))));
+        assert!(deserialize_g2(&[g2.as_slice(), &[0]].concat()).is_err());
+    }
+
+    #[test]
+    fn test_multi_scalar_mul() {
+        fn check<C: Ciphersuite>() {
+            let mut rng = test_rng();
+            let points: Vec<_> =
+                (0..40u8).map(|i| C::hash_to_group(&[i], b"msm")).collect();
+            let scalars: Vec<_> = points.iter().map(|_| C::random_scalar(&mut rng)).collect();
+
+            let naive = points
+                .iter()
+                .zip(&scalars)
+                .fold(C::identity(), |sum, (p, s)| sum + C::scalar_mul(p, s));
+            assert_eq!(C::multi_scalar_mul(&points, &scalars), naive);
+            assert_eq!(C::multi_scalar_mul(&[], &[]), C::identity());
+
+            let k = C::random_scalar(&mut rng);
+            let each: Vec<_> = points.iter().map(|p| C::scalar_mul(p, &k)).collect();
+            assert_eq!(C::scalar_mul_many(&points, &k), each);
+        }
+        check::<Bn254Sha256>();
+        check::<P256Sha256>();
+    }
+
+    #[test]
+    fn test_serialize_elements() {
+        fn check<C: Ciphersuite>() {
+            let mut points: Vec<_> =
+                (0..16u8).map(|i| C::hash_to_group(&[i], b"serialize")).collect();
+            points.push(C::identity());
+            let each = points.iter().map(C::serialize_element).collect::<Result<Vec<_>, _>>();
+            assert_eq!(C::serialize_elements(&points).unwrap(), each.unwrap());
+            assert!(C::serialize_elements(&[]).unwrap().is_empty());
+        }
+        check::<Bn254Sha256>();
+        check::<P256Sha256>();
+    }
+
+    #[test]
+    fn test_uncompressed_points() {
+        // `y_low` indexes the byte holding the lowest bits of y
+        fn check<C: Ciphersuite>(y_low: usize) {
+            let points: Vec<_> =
+                (0..8u8).map(|i| C::hash_to_group(&[i], b"uncompressed")).collect();
+            let encoded = C::encode_elements(&points, PointEncoding::Uncompressed).unwrap();
+            for (point, bytes) in points.iter().zip(&encoded) {
+                assert_eq!(bytes.len(), C::UNCOMPRESSED_ELEMENT_LEN);
+                assert_eq!(*bytes, C::serialize_element_uncompressed(point).unwrap());
+                assert_eq!(C::decode_element(bytes, PointEncoding::Uncompressed).unwrap(), *point);
+
+                // Neither encoding decodes as the other
+                let compressed = C::serialize_element(point).unwrap();
+                assert!(C::decode_element(&compressed, PointEncoding::Uncompressed).is_err());
+                assert!(C::decode_element(bytes, PointEncoding::Compressed).is_err());
+
+                // Off the curve
+                let mut off_curve = bytes.clone();
+                off_curve[y_low] ^= 1;
+                assert!(matches!(
+                    C::deserialize_element_uncompressed(&off_curve),
+                    Err(OprfError::InvalidPoint(_))
+                ));
+            }
+            let identity = C::serialize_element_uncompressed(&C::identity()).unwrap();
+            assert!(C::deserialize_element_uncompressed(&identity).is_err());
+        }
+        // BN254: little-endian x then y; P-256: 0x04, then big-endian x and y
+        check::<Bn254Sha256>(32);
+        check::<P256Sha256>(64);
+
+        // Flag bits the decoder reads past are rejected
+        let mut bytes = Bn254Sha256::serialize_element_uncompressed(&g1_generator()).unwrap();
+        bytes[63] |= 0x40;
+        assert!(Bn254Sha256::deserialize_element_uncompressed(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_p256_oprf_with_proof() {
+        let mut rng = test_rng();
+
+        let k = P256Sha256::random_scalar(&mut rng);
+        let pk = P256Sha256::scalar_mul_generator(&k);
+
+        let input_point = hash_to_group::<P256Sha256>(b"alice@example.com", OprfMode::Voprf);
+        let b = P256Sha256::random_scalar(&mut rng);
+        let blinded = P256Sha256::scalar_mul(&input_point, &b);
+
+        let bytes = P256Sha256::serialize_element(&blinded).unwrap();
+        assert_eq!(bytes.len(), P256Sha256::ELEMENT_LEN);
+        assert_eq!(P256Sha256::deserialize_element(&bytes).unwrap(), blinded);
+
+        let evaluated = P256Sha256::scalar_mul(&blinded, &k);
+        let proof =
+            generate_proof::<P256Sha256, _>(&k, &pk, &[blinded], &[evaluated], &mut rng).unwrap();
+        let proof = DleqProof::<P256Sha256>::from_bytes(&proof.to_bytes().unwrap()).unwrap();
+        assert!(verify_proof::<P256Sha256>(&pk, &[blinded], &[evaluated], &proof).is_ok());
+
+        let b_inv = P256Sha256::scalar_inverse(&b).unwrap();
+        let unblinded = P256Sha256::scalar_mul(&evaluated, &b_inv);
+        assert_eq!(unblinded, P256Sha256::scalar_mul(&input_point, &k));
+    }
+}
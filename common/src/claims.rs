@@ -0,0 +1,96 @@
+//! Claims of an attestation document, read only once it is authenticated.
+//!
+//! NSM documents are authenticated by their certificate chain to a trusted
+//! root and their COSE signature, see [`crate::nitro`]. Mock documents of
+//! local mode are signed with the enclave's mock attestation key and are
+//! refused without it. The parent's policy and an enclave checking a
+//! replication peer both start from [`Claims::verify`] and then apply their
+//! own checks to what it returns: nothing in a document that fails
+//! verification is ever looked at.
+
+use crate::ed25519::VerifyingKey;
+use crate::{nitro, AttestationDocument, AttestationError, SignedMockDocument};
+use std::collections::BTreeMap;
+
+/// The authenticated claims of an attestation document
+#[derive(Debug, Clone)]
+pub struct Claims {
+    /// PCR values by index
+    pub pcrs: BTreeMap<u64, Vec<u8>>,
+    /// Creation time, unix milliseconds
+    pub timestamp_ms: u64,
+    /// User data signed into the document, see [`crate::nsm_user_data`]
+    pub user_data: Option<Vec<u8>>,
+    /// Fingerprint of the trusted root an NSM document chains to, `None`
+    /// for a mock document
+    pub root: Option<[u8; 32]>,
+}
+
+impl Claims {
+    /// Authenticate `attestation` and return its claims: an NSM document by
+    /// its chain to one of `trusted_roots`, a mock document by its signature
+    /// with `mock_signing_key`
+    pub fn verify(
+        attestation: &AttestationDocument,
+        trusted_roots: &[[u8; 32]],
+        mock_signing_key: Option<&VerifyingKey>,
+    ) -> Result<Self, AttestationError> {
+        if attestation.is_mock {
+            return Self::verify_mock(&attestation.document, mock_signing_key);
+        }
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| AttestationError::Clock(e.to_string()))?
+            .as_secs();
+        let document = nitro::verify_document(&attestation.document, trusted_roots, now)?;
+        Ok(Self {
+            pcrs: document.pcrs,
+            timestamp_ms: document.timestamp_ms,
+            user_data: document.user_data,
+            root: Some(document.root),
+        })
+    }
+
+    /// Mock documents are a JSON payload with a timestamp in seconds, signed
+    /// with the enclave's mock attestation key
+    fn verify_mock(document: &[u8], key: Option<&VerifyingKey>) -> Result<Self, AttestationError> {
+        let key = key.ok_or_else(|| {
+            AttestationError::NotConfigured(
+                "no mock signing key configured for mock attestations".to_string(),
+            )
+        })?;
+        let invalid = |what: String| AttestationError::MalformedDocument(what);
+        let document: SignedMockDocument = serde_json::from_slice(document)
+            .map_err(|e| invalid(format!("mock attestation: {}", e)))?;
+        let payload = document.verify(key)?;
+
+        let timestamp = payload["timestamp"]
+            .as_u64()
+            .ok_or_else(|| invalid("mock attestation has no timestamp".to_string()))?;
+        let pcrs = payload["pcrs"]
+            .as_array()
+            .ok_or_else(|| invalid("mock attestation has no PCRs".to_string()))?
+            .iter()
+            .enumerate()
+            .map(|(index, pcr)| {
+                let pcr = pcr
+                    .as_str()
+                    .ok_or_else(|| invalid("mock PCR is not a string".to_string()))?;
+                let pcr = hex::decode(pcr).map_err(|e| invalid(format!("mock PCR: {}", e)))?;
+                Ok((index as u64, pcr))
+            })
+            .collect::<Result<_, AttestationError>>()?;
+        let user_data = payload["user_data"]
+            .as_str()
+            .ok_or_else(|| invalid("mock attestation has no user data".to_string()))?;
+        let user_data =
+            hex::decode(user_data).map_err(|e| invalid(format!("mock user data: {}", e)))?;
+
+        Ok(Self {
+            pcrs,
+            timestamp_ms: timestamp.saturating_mul(1000),
+            user_data: Some(user_data),
+            root: None,
+        })
+    }
+}
@@ -0,0 +1,199 @@
+//! Client side of the OPRF: blinding inputs and unblinding evaluations.
+//!
+//! Each step has its own type so the values of a run cannot be mixed up: a
+//! [`Blind`] only unblinds an [`EvaluatedElement`], only a
+//! [`BlindedElement`] is sent to the server, and the [`OprfOutput`] of
+//! [`unblind`] is always finalized with the input it was blinded from.
+//!
+//! ```text
+//! (blind, blinded) = blind(input, mode)      blinded = H_mode(input)^b
+//! evaluated        = server(blinded)         evaluated = blinded^k
+//! output           = unblind(blind, evaluated)
+//!                                            output = Finalize(input, evaluated^(1/b))
+//! ```
+
+use crate::{
+    finalize, hash_to_group, Ciphersuite, OprfError, OprfMode, PointEncoding, OUTPUT_LEN,
+};
+use ark_std::rand::Rng;
+use zeroize::Zeroizing;
+
+/// Blinding factor b of one input, kept by the client until the evaluation
+/// comes back. Holds the input as well, which [`unblind`] finalizes with.
+/// Both are wiped on drop.
+pub struct Blind<C: Ciphersuite> {
+    input: Zeroizing<Vec<u8>>,
+    scalar: Zeroizing<C::Scalar>,
+}
+
+impl<C: Ciphersuite> Blind<C> {
+    /// Input this blind was drawn for
+    pub fn input(&self) -> &[u8] {
+        &self.input
+    }
+}
+
+/// Blinded input H(input)^b, the only value of a run sent to the server
+pub struct BlindedElement<C: Ciphersuite>(C::Element);
+
+impl<C: Ciphersuite> BlindedElement<C> {
+    pub fn element(&self) -> &C::Element {
+        &self.0
+    }
+
+    /// Canonical encoding, as carried in requests
+    pub fn to_bytes(&self) -> Result<Vec<u8>, OprfError> {
+        C::serialize_element(&self.0)
+    }
+
+    /// Encoding in the point encoding of the connection
+    pub fn encode(&self, encoding: PointEncoding) -> Result<Vec<u8>, OprfError> {
+        let mut encoded = C::encode_elements(std::slice::from_ref(&self.0), encoding)?;
+        Ok(encoded.remove(0))
+    }
+}
+
+/// Server's evaluation blinded^k of a [`BlindedElement`]
+pub struct EvaluatedElement<C: Ciphersuite>(C::Element);
+
+impl<C: Ciphersuite> EvaluatedElement<C> {
+    /// Decode an evaluated element received from the server
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, OprfError> {
+        C::deserialize_element(bytes).map(Self)
+    }
+
+    /// Decode an evaluated element in the point encoding of the connection
+    pub fn decode(bytes: &[u8], encoding: PointEncoding) -> Result<Self, OprfError> {
+        C::decode_element(bytes, encoding).map(Self)
+    }
+
+    pub fn element(&self) -> &C::Element {
+        &self.0
+    }
+}
+
+// Implemented by hand: derives would require the suite marker type itself
+// to be `Copy`, `Debug` and `PartialEq`
+macro_rules! element_impls {
+    ($name:ident) => {
+        impl<C: Ciphersuite> Clone for $name<C> {
+            fn clone(&self) -> Self {
+                *self
+            }
+        }
+
+        impl<C: Ciphersuite> Copy for $name<C> {}
+
+        impl<C: Ciphersuite> PartialEq for $name<C> {
+            fn eq(&self, other: &Self) -> bool {
+                self.0 == other.0
+            }
+        }
+
+        impl<C: Ciphersuite> std::fmt::Debug for $name<C> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.debug_tuple(stringify!($name)).field(&self.0).finish()
+            }
+        }
+    };
+}
+
+element_impls!(BlindedElement);
+element_impls!(EvaluatedElement);
+
+/// Final PRF output of one input
+#[derive(Clone, PartialEq, Eq)]
+pub struct OprfOutput([u8; OUTPUT_LEN]);
+
+impl OprfOutput {
+    pub fn as_bytes(&self) -> &[u8; OUTPUT_LEN] {
+        &self.0
+    }
+
+    /// Compare with an output recorded earlier, in constant time
+    pub fn matches(&self, expected: &[u8]) -> bool {
+        self.0.len() == expected.len()
+            && self
+                .0
+                .iter()
+                .zip(expected)
+                .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
+impl std::fmt::Debug for OprfOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("OprfOutput(..)")
+    }
+}
+
+/// Map `input` to the group under the DST of `mode`, the mode it is
+/// evaluated in, and blind it with a fresh factor b
+pub fn blind<C: Ciphersuite, R: Rng>(
+    input: &[u8],
+    mode: OprfMode,
+    rng: &mut R,
+) -> (Blind<C>, BlindedElement<C>) {
+    let scalar = Zeroizing::new(C::random_scalar(rng));
+    let blinded = C::scalar_mul(&hash_to_group::<C>(input, mode), &scalar);
+    let blind = Blind {
+        input: Zeroizing::new(input.to_vec()),
+        scalar,
+    };
+    (blind, BlindedElement(blinded))
+}
+
+/// Remove the blinding factor from the server's evaluation and finalize the
+/// result with the input: `Finalize(input, evaluated^(1/b))`. The evaluation
+/// should be verified first (see [`crate::verify_proof`]).
+pub fn unblind<C: Ciphersuite>(
+    blind: Blind<C>,
+    evaluated: &EvaluatedElement<C>,
+) -> Result<OprfOutput, OprfError> {
+    let inverse =
+        Zeroizing::new(C::scalar_inverse(&blind.scalar).expect("blinding factors are non-zero"));
+    let unblinded = C::scalar_mul(&evaluated.0, &inverse);
+    finalize::<C>(&blind.input, &unblinded).map(OprfOutput)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_std::test_rng;
+    use crate::{Bn254Sha256, P256Sha256};
+
+    #[test]
+    fn test_blind_unblind() {
+        fn run<C: Ciphersuite>(mode: OprfMode) -> OprfOutput {
+            let mut rng = test_rng();
+            let k = C::random_scalar(&mut rng);
+
+            let (blind_a, blinded_a) = blind::<C, _>(b"alice@example.com", mode, &mut rng);
+            let (blind_b, blinded_b) = blind::<C, _>(b"alice@example.com", mode, &mut rng);
+            assert_eq!(blind_a.input(), b"alice@example.com");
+            assert_ne!(blinded_a, blinded_b);
+
+            // The server sees only the encoding of the blinded element
+            let evaluate = |blinded: &BlindedElement<C>| {
+                let point = C::deserialize_element(&blinded.to_bytes().unwrap()).unwrap();
+                let evaluated = C::serialize_element(&C::scalar_mul(&point, &k)).unwrap();
+                EvaluatedElement::<C>::from_bytes(&evaluated).unwrap()
+            };
+
+            // Differently blinded runs of one input give the same output
+            let output = unblind(blind_a, &evaluate(&blinded_a)).unwrap();
+            assert_eq!(output, unblind(blind_b, &evaluate(&blinded_b)).unwrap());
+            let expected = C::scalar_mul(&hash_to_group::<C>(b"alice@example.com", mode), &k);
+            let expected = finalize::<C>(b"alice@example.com", &expected).unwrap();
+            assert_eq!(output.as_bytes(), expected.as_slice());
+
+            let (blind_c, blinded_c) = blind::<C, _>(b"bob@example.com", mode, &mut rng);
+            assert_ne!(output, unblind(blind_c, &evaluate(&blinded_c)).unwrap());
+            output
+        }
+        // Each mode hashes to the group under its own DST
+        assert_ne!(run::<Bn254Sha256>(OprfMode::Oprf), run::<Bn254Sha256>(OprfMode::Voprf));
+        assert_ne!(run::<P256Sha256>(OprfMode::Oprf), run::<P256Sha256>(OprfMode::Voprf));
+    }
+}
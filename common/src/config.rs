@@ -0,0 +1,379 @@
+//! Configuration files of the enclave and parent binaries.
+//!
+//! Both binaries read a TOML file, named by `--config <file>` or the
+//! `OPRF_CONFIG` environment variable, into a [`Config`]: its `[enclave]`
+//! table configures `oprf-enclave` and its `[parent]` table `oprf-parent`,
+//! so one file can describe a deployment. Every setting defaults to the
+//! value the binaries were built with before, so a missing table, or no file
+//! at all, changes nothing. Unknown settings are refused, so a typo does not
+//! silently leave a default in place.
+//!
+//! Environment variables `OPRF_ENCLAVE_<SETTING>` and `OPRF_PARENT_<SETTING>`
+//! override the file, e.g. `OPRF_ENCLAVE_WORKERS=8`. Their values are read
+//! as TOML values, and as strings when they are not one, so
+//! `OPRF_PARENT_WIRE_FORMAT=json` needs no quotes. The command-line flags of
+//! each binary override both.
+//!
+//! The enclave's settings bound what the parent may do, so in Nitro mode its
+//! file and environment belong in the enclave image, where they are
+//! measured like its command line.
+
+use crate::{CiphersuiteId, PointEncoding, MAX_BATCH_SIZE};
+use crate::frame::WireFormat;
+use crate::heartbeat::{DEFAULT_HEARTBEAT_INTERVAL_SECS, DEFAULT_MAX_MISSED_HEARTBEATS};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Environment variable naming the configuration file when `--config` does not
+pub const CONFIG_ENV: &str = "OPRF_CONFIG";
+
+/// vsock port, or local TCP port, of the enclave
+pub const DEFAULT_PORT: u32 = 5000;
+/// Port of the enclave's admin listener
+pub const DEFAULT_ADMIN_PORT: u32 = 5001;
+/// Port the enclave publishes heartbeats on
+pub const DEFAULT_HEARTBEAT_PORT: u32 = 5003;
+/// CID the parent reaches the enclave at
+pub const DEFAULT_ENCLAVE_CID: u32 = 16;
+/// CID the enclave binds: any
+pub const VSOCK_CID_ANY: u32 = 0xFFFFFFFF;
+/// Connection handler threads of the enclave
+pub const DEFAULT_WORKERS: usize = 4;
+/// Seconds between attestations of the audit log head
+pub const DEFAULT_AUDIT_CHECKPOINT_SECS: u64 = 60;
+/// vsock port of the `vsock-proxy` forwarding the enclave's KMS traffic
+pub const DEFAULT_KMS_PROXY_PORT: u32 = 8000;
+/// Requests the parent keeps in flight over one connection
+pub const DEFAULT_PIPELINE_DEPTH: usize = 32;
+/// Connections evaluating chunks of an input file at once, the enclave's
+/// default number of workers
+pub const DEFAULT_PARALLEL: usize = DEFAULT_WORKERS;
+/// Milliseconds the parent waits before its first retry, doubled on each
+pub const DEFAULT_RETRY_BACKOFF_MS: u64 = 200;
+/// Bound in milliseconds on the parent's wait between retries
+pub const DEFAULT_MAX_RETRY_BACKOFF_MS: u64 = 10_000;
+/// Idle connections the parent keeps open to the enclave
+pub const DEFAULT_POOL_SIZE: usize = 16;
+/// Seconds the parent keeps an idle connection open, below the enclave's
+/// default idle timeout
+pub const DEFAULT_POOL_IDLE_TIMEOUT_SECS: u64 = 20;
+
+/// Failure to read the configuration
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to read config {path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("Invalid config {path}: {message}")]
+    Invalid { path: String, message: String },
+    #[error("{0} requires a file")]
+    MissingPath(String),
+}
+
+/// Settings of both binaries
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub enclave: EnclaveConfig,
+    pub parent: ParentConfig,
+}
+
+/// `[enclave]`: settings of `oprf-enclave`, named after its flags. Those
+/// left out keep the defaults of the server library.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnclaveConfig {
+    /// vsock CID to bind; unused in local mode
+    pub cid: u32,
+    /// vsock port, or TCP port on 127.0.0.1 in local mode
+    pub port: u32,
+    /// Extra TCP port on 127.0.0.1 served alongside `port`
+    pub tcp_port: Option<u32>,
+    /// Port of the admin listener, [`DEFAULT_ADMIN_PORT`] if unset; requires
+    /// `admin_key`
+    pub admin_port: Option<u32>,
+    /// Hex Ed25519 public key admin commands are signed with
+    pub admin_key: Option<String>,
+    /// KMS key (id, ARN or alias) backups are made under and backups and
+    /// seeds are decrypted with
+    pub kms_key_id: Option<String>,
+    /// Port to publish heartbeats on, none if unset
+    pub heartbeat_port: Option<u32>,
+    pub heartbeat_interval_secs: u64,
+    pub workers: usize,
+    /// Threads one batch evaluation may use, every vCPU if unset
+    pub evaluation_threads: Option<usize>,
+    /// Evaluations cached per key epoch
+    pub evaluation_cache: usize,
+    pub max_evaluations: Option<u64>,
+    pub max_evaluations_per_window: Option<u64>,
+    pub window_secs: Option<u64>,
+    pub connection_rate: Option<u64>,
+    pub connection_burst: Option<u64>,
+    pub client_rate: Option<u64>,
+    pub client_burst: Option<u64>,
+    pub attestation_ttl_secs: u64,
+    pub audit_checkpoint_secs: u64,
+    pub max_connections: Option<usize>,
+    pub idle_timeout_secs: Option<u64>,
+    pub frame_timeout_secs: Option<u64>,
+    /// How long a shutdown waits for the requests in progress
+    pub drain_timeout_secs: Option<u64>,
+    pub log_sensitive: bool,
+}
+
+impl Default for EnclaveConfig {
+    fn default() -> Self {
+        Self {
+            cid: VSOCK_CID_ANY,
+            port: DEFAULT_PORT,
+            tcp_port: None,
+            admin_port: None,
+            admin_key: None,
+            kms_key_id: None,
+            heartbeat_port: None,
+            heartbeat_interval_secs: DEFAULT_HEARTBEAT_INTERVAL_SECS,
+            workers: DEFAULT_WORKERS,
+            evaluation_threads: None,
+            evaluation_cache: 0,
+            max_evaluations: None,
+            max_evaluations_per_window: None,
+            window_secs: None,
+            connection_rate: None,
+            connection_burst: None,
+            client_rate: None,
+            client_burst: None,
+            attestation_ttl_secs: 0,
+            audit_checkpoint_secs: DEFAULT_AUDIT_CHECKPOINT_SECS,
+            max_connections: None,
+            idle_timeout_secs: None,
+            frame_timeout_secs: None,
+            drain_timeout_secs: None,
+            log_sensitive: false,
+        }
+    }
+}
+
+/// `[parent]`: settings of `oprf-parent`, named after its flags. Names of
+/// ciphersuites, wire formats and point encodings are spelled as on the
+/// command line.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParentConfig {
+    /// CID of the enclave; unused in local mode
+    pub cid: u32,
+    /// vsock port, or TCP port on 127.0.0.1 in local mode, of the enclave
+    pub port: u32,
+    /// Endpoints to fail over to, `<cid>:<port>` each, in turn
+    pub failover: Vec<String>,
+    /// Spread connections over the endpoints rather than failing over
+    pub balance: bool,
+    /// Endpoints of the keys served by other enclaves, `<cid>:<port>` each,
+    /// by key ID
+    pub shards: BTreeMap<String, Vec<String>>,
+    /// Admin port of the enclave
+    pub admin_port: u32,
+    /// vsock port of the KMS proxy, unless `KMS_PROXY_PORT` is set
+    pub kms_proxy_port: u32,
+    /// Heartbeat port of the enclave; the API servers watch it if set, and
+    /// `watch` falls back to [`DEFAULT_HEARTBEAT_PORT`]
+    pub heartbeat_port: Option<u32>,
+    pub max_missed_heartbeats: u32,
+    #[serde(deserialize_with = "from_str")]
+    pub suite: CiphersuiteId,
+    #[serde(deserialize_with = "from_str")]
+    pub wire_format: WireFormat,
+    #[serde(deserialize_with = "from_str")]
+    pub point_encoding: PointEncoding,
+    /// Allowlist of enclave PCRs
+    pub policy: Option<String>,
+    pub max_attestation_age_secs: Option<u64>,
+    /// Lowest protocol version the parent accepts, over the policy's
+    pub min_protocol_version: Option<u32>,
+    pub connect_timeout_secs: Option<u64>,
+    pub timeout_secs: Option<u64>,
+    /// Retries of a failed connection attempt, or of a request whose
+    /// connection broke
+    pub retries: u32,
+    pub retry_backoff_ms: u64,
+    pub max_retry_backoff_ms: u64,
+    /// Idle connections kept open for later requests, none if zero
+    pub pool_size: usize,
+    pub pool_idle_timeout_secs: u64,
+    /// File of the operator key signing admin commands
+    pub operator_key: Option<String>,
+    pub pipeline_depth: usize,
+    /// Inputs per batch request, at most [`MAX_BATCH_SIZE`]
+    pub chunk_size: usize,
+    pub parallel: usize,
+    pub log_sensitive: bool,
+    /// Detach `serve-http` and `serve-jsonrpc` from the terminal
+    pub daemon: bool,
+    /// File the serving process writes its id to
+    pub pid_file: Option<String>,
+    /// API keys file of `serve-http`, keys are not required if unset
+    pub api_keys: Option<String>,
+}
+
+impl Default for ParentConfig {
+    fn default() -> Self {
+        Self {
+            cid: DEFAULT_ENCLAVE_CID,
+            port: DEFAULT_PORT,
+            failover: Vec::new(),
+            balance: false,
+            shards: BTreeMap::new(),
+            admin_port: DEFAULT_ADMIN_PORT,
+            kms_proxy_port: DEFAULT_KMS_PROXY_PORT,
+            heartbeat_port: None,
+            max_missed_heartbeats: DEFAULT_MAX_MISSED_HEARTBEATS,
+            suite: CiphersuiteId::default(),
+            wire_format: WireFormat::default(),
+            point_encoding: PointEncoding::default(),
+            policy: None,
+            max_attestation_age_secs: None,
+            min_protocol_version: None,
+            connect_timeout_secs: None,
+            timeout_secs: None,
+            retries: 0,
+            retry_backoff_ms: DEFAULT_RETRY_BACKOFF_MS,
+            max_retry_backoff_ms: DEFAULT_MAX_RETRY_BACKOFF_MS,
+            pool_size: DEFAULT_POOL_SIZE,
+            pool_idle_timeout_secs: DEFAULT_POOL_IDLE_TIMEOUT_SECS,
+            operator_key: None,
+            pipeline_depth: DEFAULT_PIPELINE_DEPTH,
+            chunk_size: MAX_BATCH_SIZE,
+            parallel: DEFAULT_PARALLEL,
+            log_sensitive: false,
+            daemon: false,
+            pid_file: None,
+            api_keys: None,
+        }
+    }
+}
+
+/// A setting spelled as on the command line
+fn from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let text = String::deserialize(deserializer)?;
+    text.parse().map_err(D::Error::custom)
+}
+
+impl Config {
+    /// Parse the TOML `text` of the file `path`, with the `OPRF_ENCLAVE_*`
+    /// and `OPRF_PARENT_*` variables of `env` applied over it
+    pub fn parse<I>(path: &str, text: &str, env: I) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let invalid = |message: String| ConfigError::Invalid {
+            path: path.to_string(),
+            message,
+        };
+        let mut table: toml::Table = text.parse().map_err(|e: toml::de::Error| {
+            invalid(e.message().to_string())
+        })?;
+        for (name, value) in env {
+            let Some((section, setting)) = ["enclave", "parent"].into_iter().find_map(|section| {
+                let prefix = format!("OPRF_{}_", section.to_ascii_uppercase());
+                name.strip_prefix(&prefix).map(|setting| (section, setting.to_ascii_lowercase()))
+            }) else {
+                continue;
+            };
+            let value = format!("value = {}", value)
+                .parse::<toml::Table>()
+                .ok()
+                .and_then(|mut parsed| parsed.remove("value"))
+                .unwrap_or(toml::Value::String(value));
+            let section = table
+                .entry(section)
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            let toml::Value::Table(section) = section else {
+                return Err(invalid(format!("{} overrides a setting that is not a table", name)));
+            };
+            section.insert(setting, value);
+        }
+        Config::deserialize(table).map_err(|e| invalid(e.message().to_string()))
+    }
+
+    /// Read the file named by `--config <file>` in `args`, or by
+    /// [`CONFIG_ENV`], with the environment applied over it. Without either
+    /// only the environment applies.
+    pub fn load(args: &[String]) -> Result<Self, ConfigError> {
+        let path = args
+            .iter()
+            .position(|arg| arg == "--config")
+            .map(|index| {
+                args.get(index + 1)
+                    .cloned()
+                    .ok_or_else(|| ConfigError::MissingPath("--config".to_string()))
+            })
+            .transpose()?;
+        Self::read(path)
+    }
+
+    /// Read the file `path`, or else the one named by [`CONFIG_ENV`], with
+    /// the environment applied over it
+    pub fn read(path: Option<String>) -> Result<Self, ConfigError> {
+        let path = path.or_else(|| std::env::var(CONFIG_ENV).ok().filter(|path| !path.is_empty()));
+        let text = match &path {
+            Some(path) => std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+                path: path.clone(),
+                source,
+            })?,
+            None => String::new(),
+        };
+        Config::parse(path.as_deref().unwrap_or("environment"), &text, std::env::vars())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::WireFormat;
+
+    #[test]
+    fn test_config() {
+        let no_env = std::iter::empty::<(String, String)>;
+        // The example documents the defaults
+        let example = include_str!("../../oprf.example.toml");
+        assert_eq!(Config::parse("example", example, no_env()).unwrap(), Config::default());
+        assert_eq!(Config::parse("empty", "", no_env()).unwrap(), Config::default());
+
+        let text = "[enclave]\nworkers = 8\nmax_evaluations = 100\n[parent]\nsuite = \"p256\"\n";
+        let env = [
+            ("OPRF_ENCLAVE_WORKERS", "2"),
+            ("OPRF_PARENT_WIRE_FORMAT", "json"),
+            ("OPRF_PARENT_POLICY", "policy.json"),
+            ("OPRF_PARENT_PORT", "6000"),
+            ("OPRF_PARENT_SHARDS", r#"{ payments = ["17:5000", "18:5000"] }"#),
+            ("OPRF_OTHER", "ignored"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+        let config = Config::parse("test", text, env).unwrap();
+        assert_eq!(config.enclave.workers, 2);
+        assert_eq!(config.enclave.max_evaluations, Some(100));
+        assert_eq!(config.parent.suite, CiphersuiteId::P256Sha256);
+        assert_eq!(config.parent.wire_format, WireFormat::Json);
+        assert_eq!(config.parent.policy.as_deref(), Some("policy.json"));
+        assert_eq!(config.parent.port, 6000);
+        assert_eq!(config.parent.shards["payments"], ["17:5000", "18:5000"]);
+        assert_eq!(config.parent.cid, Config::default().parent.cid);
+
+        for text in ["[enclave]\nworker = 8\n", "[parent]\nsuite = \"p384\"\n", "[other]\n"] {
+            let result = Config::parse("test", text, no_env());
+            assert!(matches!(result, Err(ConfigError::Invalid { .. })), "{}", text);
+        }
+        let env = [("OPRF_ENCLAVE_PORT".to_string(), "-1".to_string())];
+        assert!(Config::parse("test", "", env).is_err());
+    }
+}
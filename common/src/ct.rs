@@ -0,0 +1,412 @@
+//! Constant-time comparisons and scalar multiplication.
+//!
+//! Byte strings are compared with `subtle`, so the time taken does not tell
+//! how long a prefix of a guess was right. Comparisons of scalars go through
+//! their canonical encoding for the same reason.
+//!
+//! Arkworks multiplies points by scalars with double-and-add over the bits
+//! of the scalar from its highest set bit, so both the number of iterations
+//! and the additions performed follow the secret. [`mul`] recodes the scalar
+//! into a fixed number of odd signed 4-bit digits and performs, for every
+//! digit, four doublings and one addition of a table entry read with a masked
+//! scan of the whole table: the sequence of operations and memory accesses
+//! is the same for every scalar.
+//!
+//! That leaves the arkworks field arithmetic, whose reductions end with a
+//! subtraction of the modulus taken or skipped by a branch on the value, and
+//! the short Weierstrass group law, which branches on the identity and on
+//! adding a point to itself. [`mul`] therefore first scales the projective
+//! coordinates of the point by a random field element: the point is the
+//! same, but every intermediate value, and with it every such branch, is
+//! fresh on each call whatever the scalar. The P-256 ciphersuite uses the
+//! constant-time arithmetic of the `p256` crate instead.
+//!
+//! Multiples of the generator use a [`FixedBase`] table precomputed once,
+//! which replaces the doublings by further table lookups.
+
+use crate::Ciphersuite;
+use ark_ec::{short_weierstrass, CurveGroup};
+use ark_ff::Field;
+use ark_ff::{BigInt, BigInteger, Fp, FpConfig, PrimeField, QuadExtConfig, QuadExtField};
+use std::marker::PhantomData;
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq};
+use zeroize::Zeroizing;
+
+/// Width in bits of a digit of the recoded scalar
+pub(crate) const WINDOW: usize = 4;
+/// Entries of the table of odd multiples `P, 3P, ..., 15P`
+const TABLE_LEN: usize = 1 << (WINDOW - 1);
+
+/// Whether `a` and `b` are equal, in time independent of their contents.
+/// Lengths are not secret.
+pub fn eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// Whether two scalars are equal, in time independent of their values
+pub fn scalar_eq<C: Ciphersuite>(a: &C::Scalar, b: &C::Scalar) -> bool {
+    let a = Zeroizing::new(C::serialize_scalar(a).expect("scalars serialize"));
+    let b = Zeroizing::new(C::serialize_scalar(b).expect("scalars serialize"));
+    eq(&a, &b)
+}
+
+/// Field elements chosen between without branching
+pub(crate) trait Select: Sized {
+    /// `b` if `choice` is set, otherwise `a`
+    fn select(a: &Self, b: &Self, choice: Choice) -> Self;
+}
+
+impl<P: FpConfig<N>, const N: usize> Select for Fp<P, N> {
+    fn select(a: &Self, b: &Self, choice: Choice) -> Self {
+        let mut limbs = [0u64; N];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            *limb = u64::conditional_select(&a.0 .0[i], &b.0 .0[i], choice);
+        }
+        Fp(BigInt(limbs), PhantomData)
+    }
+}
+
+impl<P: QuadExtConfig> Select for QuadExtField<P>
+where
+    P::BaseField: Select,
+{
+    fn select(a: &Self, b: &Self, choice: Choice) -> Self {
+        QuadExtField::new(
+            Select::select(&a.c0, &b.c0, choice),
+            Select::select(&a.c1, &b.c1, choice),
+        )
+    }
+}
+
+/// Points in projective coordinates, chosen between without branching
+pub(crate) trait CtPoint: CurveGroup {
+    /// `b` if `choice` is set, otherwise `a`
+    fn select(a: &Self, b: &Self, choice: Choice) -> Self;
+
+    /// The same point with its coordinates scaled by `lambda`, non-zero
+    fn rescale(&self, lambda: &Self::BaseField) -> Self;
+}
+
+/// Jacobian coordinates: `(X, Y, Z)` is `(λ²X, λ³Y, λZ)`
+impl<P: short_weierstrass::SWCurveConfig> CtPoint for short_weierstrass::Projective<P>
+where
+    P::BaseField: Select,
+{
+    fn select(a: &Self, b: &Self, choice: Choice) -> Self {
+        Self::new_unchecked(
+            Select::select(&a.x, &b.x, choice),
+            Select::select(&a.y, &b.y, choice),
+            Select::select(&a.z, &b.z, choice),
+        )
+    }
+
+    fn rescale(&self, lambda: &P::BaseField) -> Self {
+        let lambda2 = lambda.square();
+        Self::new_unchecked(self.x * lambda2, self.y * lambda2 * lambda, self.z * lambda)
+    }
+}
+
+/// The little-endian limbs of an odd scalar `e` with `e * point ==
+/// scalar * point` and whether the result must be negated: `scalar` itself
+/// if it is odd, otherwise the odd `n - scalar` for the odd group order `n`
+fn odd_limbs<F: PrimeField>(scalar: &F) -> (Zeroizing<Vec<u64>>, Choice) {
+    let k = Zeroizing::new(scalar.into_bigint());
+    let mut negated = Zeroizing::new(F::MODULUS);
+    negated.sub_with_borrow(&k);
+    let even = !Choice::from((k.as_ref()[0] & 1) as u8);
+    let limbs = k
+        .as_ref()
+        .iter()
+        .zip(negated.as_ref())
+        .map(|(k, negated)| u64::conditional_select(k, negated, even))
+        .collect();
+    (Zeroizing::new(limbs), even)
+}
+
+/// Recode an odd `e < 2^bits` into digits `d_i` in `{±1, ±3, ..., ±15}`,
+/// least significant first, with `e == sum(d_i * 16^i)`. The count of digits
+/// depends only on `bits`.
+pub(crate) fn recode(limbs: &mut [u64], bits: usize) -> Zeroizing<Vec<i8>> {
+    let steps = (bits - 1) / WINDOW;
+    let mut digits = Zeroizing::new(Vec::with_capacity(steps + 1));
+    for _ in 0..steps {
+        // d = (e mod 32) - 16 is odd, and e - d is e with its low five bits
+        // replaced by 16, so (e - d) / 16 is odd again
+        let low = (limbs[0] & 0x1f) as i8;
+        digits.push(low - 16);
+        limbs[0] = (limbs[0] & !0x1f) | 0x10;
+        for i in 0..limbs.len() {
+            let next = limbs.get(i + 1).copied().unwrap_or(0);
+            limbs[i] = (limbs[i] >> WINDOW) | (next << (64 - WINDOW));
+        }
+    }
+    // What remains is odd and below 16
+    digits.push(limbs[0] as i8);
+    digits
+}
+
+/// The digits of [`recode`] for `scalar`, and whether the product must be
+/// negated
+fn digits<F: PrimeField>(scalar: &F) -> (Zeroizing<Vec<i8>>, Choice) {
+    let (mut limbs, negate) = odd_limbs(scalar);
+    (recode(&mut limbs, F::MODULUS_BIT_SIZE as usize), negate)
+}
+
+/// `scalar * point`, with the same operations and memory accesses for every
+/// scalar, on coordinates rescaled at random
+pub(crate) fn mul<G: CtPoint>(point: &G, scalar: &G::ScalarField) -> G {
+    let (digits, negate) = digits(scalar);
+    mul_digits(point, &digits, negate)
+}
+
+/// `scalar * point` for every point, as [`mul`] but recoding the scalar
+/// once for all of them
+#[cfg_attr(feature = "glv", allow(dead_code))]
+pub(crate) fn mul_many<G: CtPoint>(points: &[G], scalar: &G::ScalarField) -> Vec<G> {
+    let (digits, negate) = digits(scalar);
+    points.iter().map(|point| mul_digits(point, &digits, negate)).collect()
+}
+
+/// A random non-zero field element to rescale coordinates by
+pub(crate) fn random_lambda<F: Field>() -> F {
+    let mut rng = rand::thread_rng();
+    loop {
+        let lambda = F::rand(&mut rng);
+        if !lambda.is_zero() {
+            break lambda;
+        }
+    }
+}
+
+/// Odd multiples `P, 3P, ..., 15P` of `point`
+pub(crate) fn odd_multiples<G: CtPoint>(point: G) -> [G; TABLE_LEN] {
+    let double = point + point;
+    let mut table = [point; TABLE_LEN];
+    for i in 1..TABLE_LEN {
+        table[i] = table[i - 1] + double;
+    }
+    table
+}
+
+/// `digit * P` from the odd multiples of `P`, read with a masked scan of the
+/// whole table
+pub(crate) fn lookup<G: CtPoint>(table: &[G; TABLE_LEN], digit: i8) -> G {
+    let sign = digit >> 7;
+    let index = (((digit ^ sign) - sign) >> 1) as u8;
+    let mut entry = table[0];
+    for (i, candidate) in table.iter().enumerate() {
+        entry = G::select(&entry, candidate, (i as u8).ct_eq(&index));
+    }
+    G::select(&entry, &-entry, Choice::from((sign & 1) as u8))
+}
+
+fn mul_digits<G: CtPoint>(point: &G, digits: &[i8], negate: Choice) -> G {
+    let table = odd_multiples(point.rescale(&random_lambda()));
+    let (top, rest) = digits.split_last().expect("at least one digit");
+    let mut acc = lookup(&table, *top);
+    for digit in rest.iter().rev() {
+        for _ in 0..WINDOW {
+            acc.double_in_place();
+        }
+        acc += lookup(&table, *digit);
+    }
+    G::select(&acc, &-acc, negate)
+}
+
+/// Multiples of a fixed point: for every digit position `i` of a recoded
+/// scalar, the odd multiples of `16^i * P`. A product then takes one masked
+/// lookup and one addition per digit and no doublings.
+pub(crate) struct FixedBase<G> {
+    windows: Vec<[G; TABLE_LEN]>,
+}
+
+impl<G: CtPoint> FixedBase<G> {
+    pub(crate) fn new(point: &G) -> Self {
+        let count = (G::ScalarField::MODULUS_BIT_SIZE as usize - 1) / WINDOW + 1;
+        let mut windows = Vec::with_capacity(count);
+        let mut point = *point;
+        for _ in 0..count {
+            windows.push(odd_multiples(point));
+            for _ in 0..WINDOW {
+                point.double_in_place();
+            }
+        }
+        Self { windows }
+    }
+
+    /// `scalar * P`, with the same operations and memory accesses for every
+    /// scalar. The entries are fixed, so the sum starts from a first entry
+    /// rescaled at random, which makes every later intermediate value fresh.
+    pub(crate) fn mul(&self, scalar: &G::ScalarField) -> G {
+        let (digits, negate) = digits(scalar);
+        let mut terms = self.windows.iter().zip(digits.iter());
+        let (table, digit) = terms.next().expect("at least one digit");
+        let mut acc = lookup(table, *digit).rescale(&random_lambda());
+        for (table, digit) in terms {
+            acc += lookup(table, *digit);
+        }
+        G::select(&acc, &-acc, negate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_ec::Group;
+    use ark_ff::PrimeField;
+    use ark_std::rand::Rng;
+    use ark_std::test_rng;
+    use crate::{hash_to_g1, pairing, random_scalar, Bn254Sha256, Fr, G1Projective, P256Sha256};
+    use std::hint::black_box;
+    use std::time::Instant;
+
+    #[test]
+    fn test_constant_time_mul() {
+        let mut rng = test_rng();
+        let mut scalars: Vec<Fr> = (0..32).map(|_| random_scalar(&mut rng)).collect();
+        let minus_one = -Fr::from(1u64);
+        scalars.extend([0u64, 1, 2, 15, 16, 17, 31, 32].map(Fr::from));
+        scalars.extend([minus_one, minus_one - Fr::from(1u64)]);
+        let point = hash_to_g1(b"input");
+        for k in &scalars {
+            assert_eq!(mul(&point, k), point * k);
+            assert_eq!(Bn254Sha256::scalar_mul_generator(k), G1Projective::generator() * k);
+            assert_eq!(pairing::public_key_g2(k), pairing::g2_generator() * k);
+        }
+
+        let table = FixedBase::new(&point);
+        for k in &scalars {
+            assert_eq!(table.mul(k), point * k);
+        }
+        let k = Fr::from_le_bytes_mod_order(&[0xff; 32]);
+        assert_eq!(mul(&point, &k), point * k);
+        assert_eq!(table.mul(&k), point * k);
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(eq(b"query hash", b"query hash"));
+        assert!(!eq(b"query hash", b"query hasi"));
+        assert!(!eq(b"query hash", b"query has"));
+        assert!(eq(b"", b""));
+
+        let mut rng = test_rng();
+        let k = P256Sha256::random_scalar(&mut rng);
+        assert!(scalar_eq::<P256Sha256>(&k, &k));
+        assert!(!scalar_eq::<P256Sha256>(&k, &Default::default()));
+        let k = Bn254Sha256::random_scalar(&mut rng);
+        assert!(scalar_eq::<Bn254Sha256>(&k, &k));
+        assert!(!scalar_eq::<Bn254Sha256>(&k, &(k + Fr::from(1u64))));
+    }
+
+    /// Welch's t statistic of timings of `op` on a fixed input class against
+    /// a random one, as in dudect: the classes are interleaved at random and
+    /// the slowest tenth of all timings, mostly interrupts, is dropped
+    fn timing_t_statistic<T, R>(
+        samples: usize,
+        mut fixed: impl FnMut() -> T,
+        mut random: impl FnMut() -> T,
+        mut op: impl FnMut(&T) -> R,
+    ) -> f64 {
+
+        let mut rng = test_rng();
+        let mut timings = Vec::with_capacity(samples);
+        for _ in 0..samples {
+            let class = rng.gen::<bool>();
+            let input = if class { fixed() } else { random() };
+            let start = Instant::now();
+            black_box(op(black_box(&input)));
+            timings.push((class, start.elapsed().as_nanos() as f64));
+        }
+        let mut sorted: Vec<f64> = timings.iter().map(|(_, t)| *t).collect();
+        sorted.sort_by(f64::total_cmp);
+        let cutoff = sorted[samples * 9 / 10];
+
+        let stats = |class: bool| {
+            let values: Vec<f64> = timings
+                .iter()
+                .filter(|(c, t)| *c == class && *t <= cutoff)
+                .map(|(_, t)| *t)
+                .collect();
+            let n = values.len() as f64;
+            let mean = values.iter().sum::<f64>() / n;
+            let variance = values.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / (n - 1.0);
+            (n, mean, variance)
+        };
+        let (n1, mean1, var1) = stats(true);
+        let (n2, mean2, var2) = stats(false);
+        (mean1 - mean2) / (var1 / n1 + var2 / n2).sqrt()
+    }
+
+    /// Timing of the scalar multiplication used with secret scalars, with
+    /// the scalar 1 as the fixed class. Slow and sensitive to machine load,
+    /// so run on demand: `cargo test --release -p oprf-common --
+    /// --ignored timing`. A |t| above 10 is dudect's threshold for a leak.
+    #[test]
+    #[ignore]
+    fn test_scalar_mul_timing() {
+        const SAMPLES: usize = 20_000;
+        let point = hash_to_g1(b"input");
+        let mut rng = test_rng();
+        let mut random = || random_scalar(&mut rng);
+
+        // The harness sees the leak of arkworks' double-and-add
+        let t = timing_t_statistic(SAMPLES, || Fr::from(1u64), &mut random, |k| point * k);
+        println!("arkworks double-and-add: t = {:.2}", t);
+        assert!(t.abs() > 10.0);
+
+        let t = timing_t_statistic(SAMPLES, || Fr::from(1u64), &mut random, |k| {
+            Bn254Sha256::scalar_mul(&point, k)
+        });
+        println!("Bn254Sha256::scalar_mul: t = {:.2}", t);
+        assert!(t.abs() < 10.0);
+
+        let t = timing_t_statistic(SAMPLES, || Fr::from(1u64), &mut random, |k| {
+            Bn254Sha256::scalar_mul_generator(k)
+        });
+        println!("Bn254Sha256::scalar_mul_generator: t = {:.2}", t);
+        assert!(t.abs() < 10.0);
+
+        let point = P256Sha256::hash_to_group(b"input", b"timing");
+        let mut rng = test_rng();
+        let t = timing_t_statistic(
+            SAMPLES,
+            || <P256Sha256 as Ciphersuite>::Scalar::ONE,
+            || P256Sha256::random_scalar(&mut rng),
+            |k| P256Sha256::scalar_mul(&point, k),
+        );
+        println!("P256Sha256::scalar_mul: t = {:.2}", t);
+        assert!(t.abs() < 10.0);
+    }
+
+    /// Speed of multiplying the generator with its fixed-base table against
+    /// the variable-base multiplication. Run on demand: `cargo test --release
+    /// -p oprf-common -- --ignored --nocapture generator_speed`.
+    #[test]
+    #[ignore]
+    fn test_generator_speed() {
+        const RUNS: u32 = 1024;
+        let mut rng = test_rng();
+        let scalars: Vec<_> = (0..RUNS).map(|_| random_scalar(&mut rng)).collect();
+        let generator = Bn254Sha256::generator();
+        let _ = Bn254Sha256::scalar_mul_generator(&scalars[0]);
+
+        let start = Instant::now();
+        for k in &scalars {
+            let _ = black_box(mul(&generator, k));
+        }
+        let naive = start.elapsed();
+        let start = Instant::now();
+        for k in &scalars {
+            let _ = black_box(Bn254Sha256::scalar_mul_generator(k));
+        }
+        let fast = start.elapsed();
+        println!(
+            "generator multiple: {:?}, {:?} with the fixed-base table ({:.2}x)",
+            naive / RUNS,
+            fast / RUNS,
+            naive.as_secs_f64() / fast.as_secs_f64()
+        );
+        assert!(fast < naive);
+    }
+}
@@ -0,0 +1,550 @@
+//! Discrete-log equality (DLEQ) proofs for the verifiable OPRF mode.
+//!
+//! Follows the construction in RFC 9497 Section 2.2: the enclave proves that
+//! the evaluated point was computed with the same key `k` as its published
+//! public key `g^k`, i.e. `log_g(g^k) == log_{blinded}(evaluated)`.
+//!
+//! Proofs cover a whole batch at once: the blinded and evaluated points are
+//! folded into a single pair `(M, Z)` by a random linear combination whose
+//! coefficients are derived from the transcript, so a batch of any size is
+//! proven and verified with one `(c, s)` pair.
+
+use crate::{context_string, ct, Ciphersuite, OprfError, OprfMode};
+use ark_std::rand::Rng;
+use zeroize::Zeroizing;
+
+/// A DLEQ proof `(c, s)`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DleqProof<C: Ciphersuite> {
+    /// Challenge scalar
+    pub c: C::Scalar,
+    /// Response scalar
+    pub s: C::Scalar,
+}
+
+impl<C: Ciphersuite> DleqProof<C> {
+    /// Serialize the proof as `c || s`
+    pub fn to_bytes(&self) -> Result<Vec<u8>, OprfError> {
+        let mut bytes = C::serialize_scalar(&self.c)?;
+        bytes.extend_from_slice(&C::serialize_scalar(&self.s)?);
+        Ok(bytes)
+    }
+
+    /// Deserialize a proof from `c || s`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, OprfError> {
+        if bytes.len() != 2 * C::SCALAR_LEN {
+            return Err(OprfError::Deserialization(format!(
+                "DLEQ proof must be {} bytes, got {}",
+                2 * C::SCALAR_LEN,
+                bytes.len()
+            )));
+        }
+        Ok(Self {
+            c: C::deserialize_scalar(&bytes[..C::SCALAR_LEN])?,
+            s: C::deserialize_scalar(&bytes[C::SCALAR_LEN..])?,
+        })
+    }
+}
+
+/// Append `I2OSP(len(data), 2) || data` to a transcript
+fn append_prefixed(transcript: &mut Vec<u8>, data: &[u8]) {
+    transcript.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    transcript.extend_from_slice(data);
+}
+
+/// Hash a transcript to a scalar using the `HashToScalar-` DST for this mode
+fn hash_transcript<C: Ciphersuite>(transcript: &[u8], mode: OprfMode) -> C::Scalar {
+    let mut dst = b"HashToScalar-".to_vec();
+    dst.extend_from_slice(&context_string::<C>(mode));
+    C::hash_to_scalar(transcript, &dst)
+}
+
+/// Combine the blinded and evaluated points into a single pair `(M, Z)`.
+///
+/// When the secret key is known (prover side) `Z` is computed as `k * M`,
+/// matching `ComputeCompositesFast`; otherwise it is accumulated from `d`.
+fn compute_composites<C: Ciphersuite>(
+    k: Option<&C::Scalar>,
+    public_key: &C::Element,
+    blinded: &[C::Element],
+    evaluated: &[C::Element],
+    mode: OprfMode,
+) -> Result<(C::Element, C::Element), OprfError> {
+    if blinded.len() != evaluated.len() || blinded.len() > u16::MAX as usize {
+        return Err(OprfError::InvalidProof);
+    }
+
+    let mut seed_dst = b"Seed-".to_vec();
+    seed_dst.extend_from_slice(&context_string::<C>(mode));
+
+    let mut seed_transcript = Vec::new();
+    append_prefixed(&mut seed_transcript, &C::serialize_element(public_key)?);
+    append_prefixed(&mut seed_transcript, &seed_dst);
+    let seed = C::hash(&seed_transcript);
+
+    let blinded_bytes = C::serialize_elements(blinded)?;
+    let evaluated_bytes = C::serialize_elements(evaluated)?;
+    let mut d = Vec::with_capacity(blinded.len());
+    for (i, (c_i, d_i)) in blinded_bytes.iter().zip(&evaluated_bytes).enumerate() {
+        let mut transcript = Vec::new();
+        append_prefixed(&mut transcript, &seed);
+        transcript.extend_from_slice(&(i as u16).to_be_bytes());
+        append_prefixed(&mut transcript, c_i);
+        append_prefixed(&mut transcript, d_i);
+        transcript.extend_from_slice(b"Composite");
+        d.push(hash_transcript::<C>(&transcript, mode));
+    }
+
+    // The weights `d` are public, so both sums may take variable time
+    let m = C::multi_scalar_mul(blinded, &d);
+    let z = match k {
+        Some(k) => C::scalar_mul(&m, k),
+        None => C::multi_scalar_mul(evaluated, &d),
+    };
+    Ok((m, z))
+}
+
+/// Compute the Fiat-Shamir challenge over `(B, M, Z, t2, t3)`
+fn compute_challenge<C: Ciphersuite>(
+    points: [&C::Element; 5],
+    mode: OprfMode,
+) -> Result<C::Scalar, OprfError> {
+    let mut transcript = Vec::new();
+    for point in points {
+        append_prefixed(&mut transcript, &C::serialize_element(point)?);
+    }
+    transcript.extend_from_slice(b"Challenge");
+    Ok(hash_transcript::<C>(&transcript, mode))
+}
+
+/// Prove that every `evaluated[i] == blinded[i]^k` where `public_key == g^k`.
+///
+/// A single proof is produced for the whole batch.
+pub fn generate_proof<C: Ciphersuite, R: Rng>(
+    k: &C::Scalar,
+    public_key: &C::Element,
+    blinded: &[C::Element],
+    evaluated: &[C::Element],
+    rng: &mut R,
+) -> Result<DleqProof<C>, OprfError> {
+    let r = Zeroizing::new(C::random_scalar(rng));
+    generate_proof_with_scalar(k, public_key, blinded, evaluated, &*r)
+}
+
+/// [`generate_proof`] with the proof's random scalar `r` given, as in the
+/// RFC 9497 test vectors
+pub(crate) fn generate_proof_with_scalar<C: Ciphersuite>(
+    k: &C::Scalar,
+    public_key: &C::Element,
+    blinded: &[C::Element],
+    evaluated: &[C::Element],
+    r: &C::Scalar,
+) -> Result<DleqProof<C>, OprfError> {
+    let mode = OprfMode::Voprf;
+    let (m, z) = compute_composites::<C>(Some(k), public_key, blinded, evaluated, mode)?;
+
+    let t2 = C::scalar_mul_generator(r);
+    let t3 = C::scalar_mul(&m, r);
+
+    let c = compute_challenge::<C>([public_key, &m, &z, &t2, &t3], mode)?;
+    let s = *r - c * *k;
+    Ok(DleqProof { c, s })
+}
+
+/// Verify a (batch) proof produced by [`generate_proof`].
+///
+/// The slices must be in the same order as when the proof was generated.
+pub fn verify_proof<C: Ciphersuite>(
+    public_key: &C::Element,
+    blinded: &[C::Element],
+    evaluated: &[C::Element],
+    proof: &DleqProof<C>,
+) -> Result<(), OprfError> {
+    let mode = OprfMode::Voprf;
+    let (m, z) = compute_composites::<C>(None, public_key, blinded, evaluated, mode)?;
+
+    let t2 = C::scalar_mul_generator(&proof.s) + *public_key * proof.c;
+    let t3 = m * proof.s + z * proof.c;
+
+    let expected_c = compute_challenge::<C>([public_key, &m, &z, &t2, &t3], mode)?;
+    if !ct::scalar_eq::<C>(&expected_c, &proof.c) {
+        return Err(OprfError::InvalidProof);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{self, unblind, EvaluatedElement};
+    use crate::{
+        hash_to_g1, random_scalar, scalar_mul, scalar_mul_generator, serialize_g1, Bn254Sha256,
+        G1Projective, P256Sha256,
+    };
+    use ark_std::test_rng;
+    use rand::RngCore;
+    use std::hint::black_box;
+    use std::time::Instant;
+
+    #[test]
+    fn test_dleq_proof() {
+        let mut rng = test_rng();
+
+        let k = random_scalar(&mut rng);
+        let pk = scalar_mul_generator(&k);
+        let blinded = scalar_mul_generator(&random_scalar(&mut rng));
+        let evaluated = scalar_mul(&blinded, &k);
+
+        let proof =
+            generate_proof::<Bn254Sha256, _>(&k, &pk, &[blinded], &[evaluated], &mut rng).unwrap();
+        let bytes = proof.to_bytes().unwrap();
+        let recovered = DleqProof::<Bn254Sha256>::from_bytes(&bytes).unwrap();
+        assert!(verify_proof::<Bn254Sha256>(&pk, &[blinded], &[evaluated], &recovered).is_ok());
+
+        // A different key must not verify
+        let wrong = scalar_mul(&blinded, &random_scalar(&mut rng));
+        assert!(verify_proof::<Bn254Sha256>(&pk, &[blinded], &[wrong], &proof).is_err());
+    }
+
+    #[test]
+    fn test_batch_dleq_proof() {
+        let mut rng = test_rng();
+
+        let k = random_scalar(&mut rng);
+        let pk = scalar_mul_generator(&k);
+        let blinded: Vec<_> = (0..16u8).map(|i| hash_to_g1(&[i])).collect();
+        let mut evaluated: Vec<_> = blinded.iter().map(|p| scalar_mul(p, &k)).collect();
+
+        let proof = generate_proof::<Bn254Sha256, _>(&k, &pk, &blinded, &evaluated, &mut rng)
+            .unwrap();
+        assert!(verify_proof::<Bn254Sha256>(&pk, &blinded, &evaluated, &proof).is_ok());
+
+        // Tampering with a single element invalidates the aggregated proof
+        evaluated[7] = scalar_mul(&blinded[7], &random_scalar(&mut rng));
+        assert!(verify_proof::<Bn254Sha256>(&pk, &blinded, &evaluated, &proof).is_err());
+        // So does reordering or truncating the batch
+        assert!(verify_proof::<Bn254Sha256>(&pk, &blinded[1..], &evaluated[1..], &proof).is_err());
+    }
+
+    /// RNG replaying `bytes`, so that a scalar drawn from it is the one they
+    /// encode, as the blinds and proof scalars of the RFC 9497 vectors are
+    struct Replay(Vec<u8>);
+
+    impl RngCore for Replay {
+        fn next_u32(&mut self) -> u32 {
+            let mut bytes = [0; 4];
+            self.fill_bytes(&mut bytes);
+            u32::from_be_bytes(bytes)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            let mut bytes = [0; 8];
+            self.fill_bytes(&mut bytes);
+            u64::from_be_bytes(bytes)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            dest.iter_mut().zip(self.0.iter().cycle()).for_each(|(byte, b)| *byte = *b);
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    /// A run of the protocol, hex-encoded
+    struct Run {
+        blinded: Vec<String>,
+        evaluated: Vec<String>,
+        proof: String,
+        outputs: Vec<String>,
+    }
+
+    /// Blind `inputs` in `mode` with the hex-encoded `blinds`, evaluate them
+    /// under `k` as the enclave does, prove the batch with the random scalar
+    /// `r` and unblind the verified evaluations, through the API the parent
+    /// and the enclave use
+    fn run<C: Ciphersuite>(
+        mode: OprfMode,
+        k: &C::Scalar,
+        inputs: &[&[u8]],
+        blinds: &[&str],
+        r: &str,
+    ) -> Run {
+        let replay = |hex: &str| Replay(hex::decode(hex).unwrap());
+        let (blinds, blinded): (Vec<_>, Vec<_>) = inputs
+            .iter()
+            .zip(blinds)
+            .map(|(input, blind)| client::blind::<C, _>(input, mode, &mut replay(blind)))
+            .unzip();
+
+        let points: Vec<_> = blinded.iter().map(|blinded| *blinded.element()).collect();
+        let public_key = C::scalar_mul_generator(k);
+        let evaluated = C::scalar_mul_many(&points, k);
+        let proof =
+            generate_proof::<C, _>(k, &public_key, &points, &evaluated, &mut replay(r)).unwrap();
+        let evaluated = C::serialize_elements(&evaluated).unwrap();
+
+        let elements = evaluated
+            .iter()
+            .map(|bytes| EvaluatedElement::<C>::from_bytes(bytes).unwrap())
+            .collect::<Vec<_>>();
+        let proof = DleqProof::<C>::from_bytes(&proof.to_bytes().unwrap()).unwrap();
+        let received: Vec<_> = elements.iter().map(|element| *element.element()).collect();
+        verify_proof::<C>(&public_key, &points, &received, &proof).unwrap();
+        let outputs = blinds
+            .into_iter()
+            .zip(&elements)
+            .map(|(blind, evaluated)| hex::encode(unblind(blind, evaluated).unwrap().as_bytes()))
+            .collect();
+        let blinded = blinded.iter().map(|blinded| hex::encode(blinded.to_bytes().unwrap()));
+        Run {
+            blinded: blinded.collect(),
+            evaluated: evaluated.iter().map(hex::encode).collect(),
+            proof: hex::encode(proof.to_bytes().unwrap()),
+            outputs,
+        }
+    }
+
+    /// RFC 9497 Appendix A.3 test vectors of OPRF(P-256, SHA-256), which the
+    /// `voprf` crate is tested against too, run through [`client::blind`],
+    /// the enclave's evaluation and [`unblind`]: elements, scalars and proofs
+    /// must encode to the same bytes for its clients to talk to the enclave
+    #[test]
+    fn test_p256_rfc9497_vectors() {
+        // BN254 is not an RFC 9497 suite and has no official vectors
+        type C = P256Sha256;
+        let scalar = |hex: &str| C::deserialize_scalar(&hex::decode(hex).unwrap()).unwrap();
+        let blinds = [
+            "3338fa65ec36e0290022b48eb562889d89dbfa691d1cde91517fa222ed7ad364",
+            "f9db001266677f62c095021db018cd8cbb55941d4073698ce45c405d1348b7b1",
+        ];
+        let inputs: [&[u8]; 2] = [&[0], &[0x5a; 17]];
+
+        // DeriveKeyPair(seed, "test key") under each mode's context string
+        let derive_key_pair = |mode: OprfMode| {
+            let mut derive_input = vec![0xa3; 32];
+            derive_input.extend_from_slice(&8u16.to_be_bytes());
+            derive_input.extend_from_slice(b"test key");
+            derive_input.push(0);
+            let mut dst = b"DeriveKeyPair".to_vec();
+            dst.extend_from_slice(&context_string::<C>(mode));
+            C::hash_to_scalar(&derive_input, &dst)
+        };
+
+        // A.3.1, base mode: vectors 1 and 2 blind both inputs with the first
+        // blind
+        let k = derive_key_pair(OprfMode::Oprf);
+        assert_eq!(k, scalar("159749d750713afe245d2d39ccfaae8381c53ce92d098a9375ee70739c7ac0bf"));
+        let base = [
+            (
+                "03723a1e5c09b8b9c18d1dcbca29e8007e95f14f4732d9346d490ffc195110368d",
+                "030de02ffec47a1fd53efcdd1c6faf5bdc270912b8749e783c7ca75bb412958832",
+                "a0b34de5fa4c5b6da07e72af73cc507cceeb48981b97b7285fc375345fe495dd",
+            ),
+            (
+                "03cc1df781f1c2240a64d1c297b3f3d16262ef5d4cf102734882675c26231b0838",
+                "03a0395fe3828f2476ffcd1f4fe540e5a8489322d398be3c4e5a869db7fcb7c52c",
+                "c748ca6dd327f0ce85f4ae3a8cd6d4d5390bbb804c9e12dcf94f853fece3dcce",
+            ),
+        ];
+        for (input, (blinded, evaluated, output)) in inputs.into_iter().zip(base) {
+            let vector = run::<C>(OprfMode::Oprf, &k, &[input], &blinds[..1], blinds[1]);
+            assert_eq!(vector.blinded, [blinded]);
+            assert_eq!(vector.evaluated, [evaluated]);
+            assert_eq!(vector.outputs, [output]);
+        }
+
+        // A.3.2, verifiable mode: inputs are hashed under the mode's own
+        // context string
+        let k = derive_key_pair(OprfMode::Voprf);
+        assert_eq!(k, scalar("ca5d94c8807817669a51b196c34c1b7f8442fde4334a7121ae4736364312fca6"));
+        assert_eq!(
+            hex::encode(C::serialize_element(&C::scalar_mul_generator(&k)).unwrap()),
+            "03e17e70604bcabe198882c0a1f27a92441e774224ed9c702e51dd17038b102462"
+        );
+        let outputs = [
+            "0412e8f78b02c415ab3a288e228978376f99927767ff37c5718d420010a645a1",
+            "771e10dcd6bcd3664e23b8f2a710cfaaa8357747c4a8cbba03133967b5c24f18",
+        ];
+
+        // Vector 1: one input, with its blinded and evaluated elements and
+        // proof
+        let r = "f9db001266677f62c095021db018cd8cbb55941d4073698ce45c405d1348b7b1";
+        let vector = run::<C>(OprfMode::Voprf, &k, &inputs[..1], &blinds[..1], r);
+        assert_eq!(
+            vector.blinded,
+            ["02dd05901038bb31a6fae01828fd8d0e49e35a486b5c5d4b4994013648c01277da"]
+        );
+        assert_eq!(
+            vector.evaluated,
+            ["0209f33cab60cf8fe69239b0afbcfcd261af4c1c5632624f2e9ba29b90ae83e4a2"]
+        );
+        assert_eq!(
+            vector.proof,
+            "e7c2b3c5c954c035949f1f74e6bce2ed539a3be267d1481e9ddb178533df4c26\
+             64f69d065c604a4fd953e100b856ad83804eb3845189babfa5a702090d6fc5fa"
+        );
+        assert_eq!(vector.outputs, outputs[..1]);
+
+        // Vector 2: the second input under the first blind
+        let vector = run::<C>(OprfMode::Voprf, &k, &inputs[1..], &blinds[..1], r);
+        assert_eq!(vector.outputs, outputs[1..]);
+
+        // Vector 3: both inputs in one batch, each under its own blind, with
+        // one proof over both evaluations
+        let r = "350e8040f828bf6ceca27405420cdf3d63cb3aef005f40ba51943c8026877963";
+        let vector = run::<C>(OprfMode::Voprf, &k, &inputs, &blinds, r);
+        assert_eq!(
+            vector.proof,
+            "bdcc351707d02a72ce49511c7db990566d29d6153ad6f8982fad2b435d6ce4d6\
+             0da1e6b3fa740811bde34dd4fe0aa1b5fe6600d0440c9ddee95ea7fad7a60cf2"
+        );
+        assert_eq!(vector.outputs, outputs);
+    }
+
+    /// Both modes against the `voprf` crate's `P256` suite: its clients'
+    /// blinded elements are evaluated and proven as by the enclave and
+    /// finalize to the outputs of the parent's, and its server's
+    /// evaluations verify and unblind on the parent's side
+    #[test]
+    fn test_p256_voprf_crate_interop() {
+        use p256::NistP256;
+        use rand::rngs::OsRng;
+        use voprf::{BlindedElement, EvaluationElement, Proof};
+
+        type C = P256Sha256;
+        let k = C::random_scalar(&mut OsRng);
+        let public_key = C::scalar_mul_generator(&k);
+        let key = C::serialize_scalar(&k).unwrap();
+        let input = b"alice@example.com";
+        let evaluate = |blinded: &[u8]| {
+            let point = C::deserialize_element(blinded).unwrap();
+            let evaluated = C::scalar_mul_many(&[point], &k);
+            let proof = generate_proof::<C, _>(&k, &public_key, &[point], &evaluated, &mut OsRng);
+            let evaluated = C::serialize_element(&evaluated[0]).unwrap();
+            (evaluated, proof.unwrap().to_bytes().unwrap())
+        };
+        // The parent's output, from its own blinding and unblinding
+        let output = |mode: OprfMode| {
+            let (blind, blinded) = client::blind::<C, _>(input, mode, &mut OsRng);
+            let (evaluated, _) = evaluate(&blinded.to_bytes().unwrap());
+            let evaluated = EvaluatedElement::<C>::from_bytes(&evaluated).unwrap();
+            unblind(blind, &evaluated).unwrap()
+        };
+        let voprf_output = output(OprfMode::Voprf);
+        let oprf_output = output(OprfMode::Oprf);
+        assert_ne!(voprf_output, oprf_output);
+
+        // A `voprf` client against the enclave
+        let blinded = voprf::VoprfClient::<NistP256>::blind(input, &mut OsRng).unwrap();
+        let (evaluated, proof) = evaluate(&blinded.message.serialize());
+        let finalized = blinded.state.finalize(
+            input,
+            &EvaluationElement::deserialize(&evaluated).unwrap(),
+            &Proof::deserialize(&proof).unwrap(),
+            public_key,
+        );
+        assert_eq!(finalized.unwrap().as_slice(), voprf_output.as_bytes());
+
+        let blinded = voprf::OprfClient::<NistP256>::blind(input, &mut OsRng).unwrap();
+        let (evaluated, _) = evaluate(&blinded.message.serialize());
+        let evaluated = EvaluationElement::deserialize(&evaluated).unwrap();
+        let finalized = blinded.state.finalize(input, &evaluated).unwrap();
+        assert_eq!(finalized.as_slice(), oprf_output.as_bytes());
+
+        // The parent against a `voprf` server
+        let server = voprf::VoprfServer::<NistP256>::new_with_key(&key).unwrap();
+        let (blind, blinded) = client::blind::<C, _>(input, OprfMode::Voprf, &mut OsRng);
+        let blinded_element = BlindedElement::deserialize(&blinded.to_bytes().unwrap()).unwrap();
+        let result = server.blind_evaluate(&mut OsRng, &blinded_element);
+        let evaluated = EvaluatedElement::<C>::from_bytes(&result.message.serialize()).unwrap();
+        let proof = DleqProof::<C>::from_bytes(&result.proof.serialize()).unwrap();
+        verify_proof::<C>(&public_key, &[*blinded.element()], &[*evaluated.element()], &proof)
+            .unwrap();
+        assert_eq!(unblind(blind, &evaluated).unwrap(), voprf_output);
+
+        let server = voprf::OprfServer::<NistP256>::new_with_key(&key).unwrap();
+        let (blind, blinded) = client::blind::<C, _>(input, OprfMode::Oprf, &mut OsRng);
+        let blinded_element = BlindedElement::deserialize(&blinded.to_bytes().unwrap()).unwrap();
+        let evaluated = server.blind_evaluate(&blinded_element).serialize();
+        let evaluated = EvaluatedElement::<C>::from_bytes(&evaluated).unwrap();
+        assert_eq!(unblind(blind, &evaluated).unwrap(), oprf_output);
+    }
+
+    /// Speed of a batch evaluation of 1024 points, evaluation, serialization
+    /// and DLEQ proof, against handling every point on its own and summing
+    /// the composites term by term. Run on demand: `cargo test --release -p
+    /// oprf-common -- --ignored --nocapture batch_speed`.
+    #[test]
+    #[ignore]
+    fn test_batch_speed() {
+        const BATCH: usize = 1024;
+        let mut rng = test_rng();
+        let k = random_scalar(&mut rng);
+        let pk = scalar_mul_generator(&k);
+        let blinded: Vec<_> = (0..BATCH as u32).map(|i| hash_to_g1(&i.to_be_bytes())).collect();
+        let weights: Vec<_> = blinded.iter().map(|_| random_scalar(&mut rng)).collect();
+        let term_by_term = |points: &[G1Projective]| {
+            let terms = points.iter().zip(&weights);
+            terms.fold(Bn254Sha256::identity(), |sum, (p, d)| sum + *p * d)
+        };
+
+        let start = Instant::now();
+        let evaluated: Vec<_> = blinded.iter().map(|p| scalar_mul(p, &k)).collect();
+        let naive_sum = term_by_term(&blinded);
+        let naive = start.elapsed();
+
+        let start = Instant::now();
+        let batched = Bn254Sha256::scalar_mul_many(&blinded, &k);
+        let msm = Bn254Sha256::multi_scalar_mul(&blinded, &weights);
+        let fast = start.elapsed();
+        assert_eq!(batched, evaluated);
+        assert_eq!(msm, naive_sum);
+        println!(
+            "evaluate and combine {} points: {:?} per point, {:?} with MSM ({:.2}x)",
+            BATCH,
+            naive / BATCH as u32,
+            fast / BATCH as u32,
+            naive.as_secs_f64() / fast.as_secs_f64()
+        );
+
+        let start = Instant::now();
+        let each = evaluated.iter().map(serialize_g1).collect::<Result<Vec<_>, _>>().unwrap();
+        let naive = start.elapsed();
+        let start = Instant::now();
+        let batched = Bn254Sha256::serialize_elements(&evaluated).unwrap();
+        let fast = start.elapsed();
+        assert_eq!(batched, each);
+        println!(
+            "serialize {} points: {:?}, {:?} normalized together ({:.2}x)",
+            BATCH,
+            naive,
+            fast,
+            naive.as_secs_f64() / fast.as_secs_f64()
+        );
+
+        let start = Instant::now();
+        let proof = generate_proof::<Bn254Sha256, _>(&k, &pk, &blinded, &evaluated, &mut rng)
+            .unwrap();
+        println!("prove {} points: {:?}", BATCH, start.elapsed());
+
+        // Verification needs both composites, so gains the most
+        let start = Instant::now();
+        let _ = black_box([term_by_term(&blinded), term_by_term(&evaluated)]);
+        let naive = start.elapsed();
+        let start = Instant::now();
+        verify_proof::<Bn254Sha256>(&pk, &blinded, &evaluated, &proof).unwrap();
+        let fast = start.elapsed();
+        println!(
+            "verify {} points: composites alone {:?} term by term, whole proof {:?} with MSM \
+             ({:.2}x)",
+            BATCH,
+            naive,
+            fast,
+            naive.as_secs_f64() / fast.as_secs_f64()
+        );
+        assert!(fast < naive);
+    }
+}
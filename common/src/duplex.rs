@@ -0,0 +1,179 @@
+//! In-memory connection between two ends in one process.
+//!
+//! [`pair`] returns two connected [`DuplexStream`]s: what one writes, the
+//! other reads, in order. They stand in for the socket between the parent
+//! and the enclave, so the enclave's request loop and a client can be run
+//! against each other in one process, without ports or timing of their own.
+//!
+//! Writes never block: each direction buffers whatever was written and not
+//! yet read, so pipelined requests cannot deadlock. Reads block until data
+//! arrives, or for at most the read timeout if one is set. Once either end
+//! is dropped, the other reads what was left and then end of stream, and
+//! its writes fail with `BrokenPipe`, as over a closed socket.
+
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Bytes written in one direction and not yet read
+#[derive(Default)]
+struct Buffer {
+    bytes: VecDeque<u8>,
+    /// One of the ends was dropped
+    closed: bool,
+}
+
+/// One direction of a connection
+#[derive(Default)]
+struct Direction {
+    buffer: Mutex<Buffer>,
+    readable: Condvar,
+}
+
+impl Direction {
+    fn lock(&self) -> MutexGuard<'_, Buffer> {
+        self.buffer.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.readable.notify_all();
+    }
+}
+
+/// One end of an in-memory connection, see [`pair`]
+pub struct DuplexStream {
+    incoming: Arc<Direction>,
+    outgoing: Arc<Direction>,
+    read_timeout: Option<Duration>,
+}
+
+/// Two connected ends
+pub fn pair() -> (DuplexStream, DuplexStream) {
+    let forward = Arc::new(Direction::default());
+    let backward = Arc::new(Direction::default());
+    (
+        DuplexStream {
+            incoming: Arc::clone(&backward),
+            outgoing: Arc::clone(&forward),
+            read_timeout: None,
+        },
+        DuplexStream {
+            incoming: forward,
+            outgoing: backward,
+            read_timeout: None,
+        },
+    )
+}
+
+impl DuplexStream {
+    /// Fail reads with `WouldBlock` after waiting `timeout` for data, like
+    /// `TcpStream::set_read_timeout`; `None` waits for ever
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+}
+
+impl Read for DuplexStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let deadline = self.read_timeout.map(|timeout| Instant::now() + timeout);
+        let mut buffer = self.incoming.lock();
+        while buffer.bytes.is_empty() && !buffer.closed {
+            buffer = match deadline {
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return Err(Error::new(ErrorKind::WouldBlock, "Read timed out"));
+                    }
+                    self.incoming
+                        .readable
+                        .wait_timeout(buffer, left)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .0
+                }
+                None => self
+                    .incoming
+                    .readable
+                    .wait(buffer)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            };
+        }
+        let len = buf.len().min(buffer.bytes.len());
+        for (byte, read) in buf.iter_mut().zip(buffer.bytes.drain(..len)) {
+            *byte = read;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for DuplexStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut buffer = self.outgoing.lock();
+        if buffer.closed {
+            return Err(Error::new(ErrorKind::BrokenPipe, "Peer closed the connection"));
+        }
+        buffer.bytes.extend(buf);
+        drop(buffer);
+        self.outgoing.readable.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for DuplexStream {
+    fn drop(&mut self) {
+        self.incoming.close();
+        self.outgoing.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{read_frame, write_frame, WireFormat};
+    use std::io::{ErrorKind, Read, Write};
+    use std::time::Duration;
+
+    #[test]
+    fn test_duplex_stream() {
+        let (mut client, mut server) = pair();
+        // Pipelined writes are buffered before the peer reads any
+        for id in 1..=3u64 {
+            write_frame(&mut client, id, WireFormat::Cbor, &id.to_be_bytes()).unwrap();
+        }
+        let echo = std::thread::spawn(move || {
+            while let Some(frame) = read_frame(&mut server).unwrap() {
+                write_frame(&mut server, frame.request_id, frame.format, &frame.payload).unwrap();
+            }
+        });
+        for id in 1..=3u64 {
+            let frame = read_frame(&mut client).unwrap().unwrap();
+            assert_eq!(frame.request_id, id);
+            assert_eq!(frame.payload.as_slice(), &id.to_be_bytes());
+        }
+
+        client.set_read_timeout(Some(Duration::from_millis(10)));
+        let mut byte = [0u8];
+        assert_eq!(client.read(&mut byte).unwrap_err().kind(), ErrorKind::WouldBlock);
+
+        // Dropping an end is a clean close for the reader and a broken
+        // pipe for the writer
+        let (mut left, mut right) = pair();
+        left.write_all(b"last").unwrap();
+        drop(left);
+        let mut rest = Vec::new();
+        right.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"last");
+        assert_eq!(right.write(b"x").unwrap_err().kind(), ErrorKind::BrokenPipe);
+
+        drop(client);
+        echo.join().unwrap();
+    }
+}
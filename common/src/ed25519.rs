@@ -0,0 +1,145 @@
+//! Ed25519 signatures (RFC 8032), from `ed25519-dalek`.
+//!
+//! Mock attestations of local mode, the response signatures of protocol
+//! version 4 ([`crate::signing`]) and operator commands ([`crate::admin`])
+//! are all signed with these keys. The wrappers keep the key and signature
+//! encodings as byte arrays, so the protocol types stay free of the
+//! library's. Verification is strict: non-canonical `S` and small-order `R`
+//! or public keys are rejected, so signatures are not malleable.
+
+use crate::OprfError;
+use ed25519_dalek::{Signature, Signer};
+use rand::Rng;
+use zeroize::Zeroizing;
+
+/// Length of public keys
+pub const PUBLIC_KEY_LEN: usize = ed25519_dalek::PUBLIC_KEY_LENGTH;
+/// Length of signatures `R || S`
+pub const SIGNATURE_LEN: usize = ed25519_dalek::SIGNATURE_LENGTH;
+
+/// An Ed25519 signing key, zeroized on drop
+pub struct SigningKey(ed25519_dalek::SigningKey);
+
+impl SigningKey {
+    /// Derive the key from a 32-byte secret seed
+    pub fn from_seed(seed: &[u8; 32]) -> Self {
+        Self(ed25519_dalek::SigningKey::from_bytes(seed))
+    }
+
+    /// Generate a fresh key
+    pub fn generate<R: Rng>(rng: &mut R) -> Self {
+        let mut seed = Zeroizing::new([0u8; 32]);
+        rng.fill_bytes(&mut seed[..]);
+        Self::from_seed(&seed)
+    }
+
+    /// Encoded public key `A`
+    pub fn public_key(&self) -> [u8; PUBLIC_KEY_LEN] {
+        self.0.verifying_key().to_bytes()
+    }
+
+    /// Sign `message`, returning `R || S`
+    pub fn sign(&self, message: &[u8]) -> [u8; SIGNATURE_LEN] {
+        self.0.sign(message).to_bytes()
+    }
+}
+
+/// An Ed25519 public key
+#[derive(Debug, Clone)]
+pub struct VerifyingKey(ed25519_dalek::VerifyingKey);
+
+impl VerifyingKey {
+    /// Decode an encoded public key
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, OprfError> {
+        let bytes: &[u8; PUBLIC_KEY_LEN] = bytes.try_into().map_err(|_| {
+            OprfError::Deserialization(format!(
+                "Ed25519 public key must be {} bytes, got {}",
+                PUBLIC_KEY_LEN,
+                bytes.len()
+            ))
+        })?;
+        let key = ed25519_dalek::VerifyingKey::from_bytes(bytes)
+            .map_err(|_| OprfError::InvalidPoint("not an Ed25519 public key".to_string()))?;
+        if key.is_weak() {
+            return Err(OprfError::InvalidPoint("Ed25519 public key of small order".to_string()));
+        }
+        Ok(Self(key))
+    }
+
+    /// Encoded public key
+    pub fn to_bytes(&self) -> [u8; PUBLIC_KEY_LEN] {
+        self.0.to_bytes()
+    }
+
+    /// Verify a signature `R || S` over `message`
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), OprfError> {
+        let signature = Signature::from_slice(signature).map_err(|_| OprfError::InvalidSignature)?;
+        self.0.verify_strict(message, &signature).map_err(|_| OprfError::InvalidSignature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_std::test_rng;
+
+    /// Order L of the Ed25519 base point, little-endian
+    const ED25519_ORDER_LE: &str =
+        "edd3f55c1a631258d69cf7a2def9de1400000000000000000000000000000010";
+
+    #[test]
+    fn test_ed25519_rfc8032_vectors() {
+        // RFC 8032 Section 7.1, TEST 1 and TEST 2
+        let vectors = [
+            (
+                "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+                "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+                "",
+                "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555f\
+                 b8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+            ),
+            (
+                "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+                "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+                "72",
+                "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da08\
+                 5ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+            ),
+        ];
+        for (seed, public_key, message, signature) in vectors {
+            let seed: [u8; 32] = hex::decode(seed).unwrap().try_into().unwrap();
+            let message = hex::decode(message).unwrap();
+            let key = SigningKey::from_seed(&seed);
+            assert_eq!(hex::encode(key.public_key()), public_key);
+            assert_eq!(hex::encode(key.sign(&message)), signature);
+
+            let verifying_key = VerifyingKey::from_bytes(&key.public_key()).unwrap();
+            assert!(verifying_key.verify(&message, &hex::decode(signature).unwrap()).is_ok());
+        }
+
+        let key = SigningKey::generate(&mut test_rng());
+        let verifying_key = VerifyingKey::from_bytes(&key.public_key()).unwrap();
+        let signature = key.sign(b"attestation");
+        assert!(verifying_key.verify(b"attestation", &signature).is_ok());
+        assert!(verifying_key.verify(b"attestatioN", &signature).is_err());
+        assert!(verifying_key.verify(b"attestation", &signature[..63]).is_err());
+
+        let mut tampered = signature;
+        tampered[40] ^= 1;
+        assert!(verifying_key.verify(b"attestation", &tampered).is_err());
+
+        // S + L is congruent to S but not canonical
+        let mut malleated = signature;
+        let mut carry = 0u16;
+        let order = hex::decode(ED25519_ORDER_LE).unwrap();
+        for (byte, l) in malleated[32..].iter_mut().zip(order) {
+            let sum = *byte as u16 + l as u16 + carry;
+            *byte = sum as u8;
+            carry = sum >> 8;
+        }
+        assert!(verifying_key.verify(b"attestation", &malleated).is_err());
+
+        let other = VerifyingKey::from_bytes(&SigningKey::from_seed(&[7u8; 32]).public_key());
+        assert!(other.unwrap().verify(b"attestation", &signature).is_err());
+    }
+}
@@ -0,0 +1,92 @@
+//! Symmetric sealing of key backups under a KMS data key.
+//!
+//! AES-256-GCM, the cipher KMS data keys are generated for, with the caller's
+//! associated data authenticated alongside the ciphertext.
+
+use crate::OprfError;
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+
+/// Length of a data key in bytes
+pub const DATA_KEY_LEN: usize = 32;
+/// Length of a nonce in bytes
+pub const NONCE_LEN: usize = 12;
+/// Length of the authentication tag appended to the ciphertext
+pub const TAG_LEN: usize = 16;
+
+/// Encrypt and authenticate `plaintext`, returning `ciphertext || tag`.
+///
+/// A nonce must never be reused with the same data key.
+pub fn seal(
+    data_key: &[u8; DATA_KEY_LEN],
+    nonce: &[u8; NONCE_LEN],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Vec<u8> {
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(data_key))
+        .encrypt(Nonce::from_slice(nonce), Payload { msg: plaintext, aad })
+        .expect("AES-GCM plaintext too long")
+}
+
+/// Verify and decrypt the output of [`seal`]
+pub fn open(
+    data_key: &[u8; DATA_KEY_LEN],
+    nonce: &[u8; NONCE_LEN],
+    aad: &[u8],
+    sealed: &[u8],
+) -> Result<Vec<u8>, OprfError> {
+    if sealed.len() < TAG_LEN {
+        return Err(OprfError::InvalidBackup("Sealed data too short".to_string()));
+    }
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(data_key))
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad })
+        .map_err(|_| OprfError::InvalidBackup("Authentication tag mismatch".to_string()))
+}
+
+/// Wrapping key of the local mock KMS. It is public, so mock-wrapped data has
+/// no protection; only local mode uses it.
+const MOCK_KMS_KEY: [u8; DATA_KEY_LEN] = *b"oprf-local-mock-kms-wrapping-key";
+
+/// Wrap `plaintext` the way the local mock KMS does: `nonce || sealed`
+pub fn mock_kms_encrypt(plaintext: &[u8], nonce: &[u8; NONCE_LEN]) -> Vec<u8> {
+    let mut ciphertext = nonce.to_vec();
+    ciphertext.extend_from_slice(&seal(&MOCK_KMS_KEY, nonce, b"mock-kms", plaintext));
+    ciphertext
+}
+
+/// Unwrap the output of [`mock_kms_encrypt`]
+pub fn mock_kms_decrypt(ciphertext: &[u8]) -> Result<Vec<u8>, OprfError> {
+    if ciphertext.len() < NONCE_LEN {
+        return Err(OprfError::InvalidBackup("Mock KMS ciphertext too short".to_string()));
+    }
+    let (nonce, sealed) = ciphertext.split_at(NONCE_LEN);
+    let nonce: [u8; NONCE_LEN] = nonce.try_into().expect("split at NONCE_LEN");
+    open(&MOCK_KMS_KEY, &nonce, b"mock-kms", sealed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_seal_open() {
+        let key = [7u8; DATA_KEY_LEN];
+        let nonce = [9u8; NONCE_LEN];
+        let plaintext = vec![0x42u8; 100];
+        let sealed = seal(&key, &nonce, b"aad", &plaintext);
+        assert_eq!(sealed.len(), plaintext.len() + TAG_LEN);
+        assert_ne!(&sealed[..plaintext.len()], plaintext.as_slice());
+        assert_eq!(open(&key, &nonce, b"aad", &sealed).unwrap(), plaintext);
+
+        // Any change to the ciphertext, aad, nonce or key is rejected
+        let mut tampered = sealed.clone();
+        tampered[5] ^= 1;
+        assert!(open(&key, &nonce, b"aad", &tampered).is_err());
+        assert!(open(&key, &nonce, b"other", &sealed).is_err());
+        assert!(open(&key, &[0u8; NONCE_LEN], b"aad", &sealed).is_err());
+        assert!(open(&[8u8; DATA_KEY_LEN], &nonce, b"aad", &sealed).is_err());
+
+        let wrapped = mock_kms_encrypt(b"seed", &nonce);
+        assert_eq!(mock_kms_decrypt(&wrapped).unwrap(), b"seed");
+    }
+}
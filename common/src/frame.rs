@@ -0,0 +1,245 @@
+//! Framing of the parent-enclave connection.
+//!
+//! Every message travels in a frame: an 8-byte request id, a format byte
+//! and a 4-byte payload length, integers big-endian, then the payload. A
+//! response carries the id of its request, so a client can keep several
+//! requests outstanding on one connection and match the responses whatever
+//! their order. Ids are chosen by the client and only need to be unique
+//! among its outstanding requests.
+//!
+//! The format byte selects the payload encoding, see [`WireFormat`]. The
+//! enclave answers in the format of the request, so each client picks its
+//! own: CBOR by default, JSON as a fallback.
+
+use crate::OprfError;
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::{ErrorKind, Read, Write};
+use zeroize::Zeroizing;
+
+/// Length of the frame header: request id, format and payload length
+pub const FRAME_HEADER_LEN: usize = 13;
+
+/// Largest payload accepted, well above a full batch or key backup
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// Payload bytes read before the buffer grows, so memory follows the bytes
+/// received rather than the length a peer announces
+const READ_CHUNK_LEN: usize = 64 * 1024;
+
+/// Encoding of a frame payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WireFormat {
+    /// JSON, where byte fields are arrays of numbers
+    Json,
+    /// CBOR (RFC 8949), where byte fields are byte strings: a fraction of
+    /// the size of JSON and cheaper to parse
+    #[default]
+    Cbor,
+}
+
+impl WireFormat {
+    /// Format byte of the frame header
+    pub fn id(self) -> u8 {
+        match self {
+            WireFormat::Json => 0x00,
+            WireFormat::Cbor => 0x01,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0x00 => Some(WireFormat::Json),
+            0x01 => Some(WireFormat::Cbor),
+            _ => None,
+        }
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, OprfError> {
+        match self {
+            WireFormat::Json => {
+                serde_json::to_vec(value).map_err(|e| OprfError::Serialization(e.to_string()))
+            }
+            WireFormat::Cbor => {
+                serde_cbor::to_vec(value).map_err(|e| OprfError::Serialization(e.to_string()))
+            }
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, payload: &[u8]) -> Result<T, OprfError> {
+        match self {
+            WireFormat::Json => serde_json::from_slice(payload)
+                .map_err(|e| OprfError::Deserialization(e.to_string())),
+            WireFormat::Cbor => serde_cbor::from_slice(payload)
+                .map_err(|e| OprfError::Deserialization(e.to_string())),
+        }
+    }
+}
+
+impl std::str::FromStr for WireFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(WireFormat::Json),
+            "cbor" => Ok(WireFormat::Cbor),
+            _ => Err(format!("Unknown wire format: {}", s)),
+        }
+    }
+}
+
+/// A received frame
+pub struct Frame {
+    pub request_id: u64,
+    pub format: WireFormat,
+    /// Requests and responses may carry keys or credentials, so the payload
+    /// is wiped on drop
+    pub payload: Zeroizing<Vec<u8>>,
+}
+
+/// Write one frame and flush it
+pub fn write_frame<W: Write>(
+    writer: &mut W,
+    request_id: u64,
+    format: WireFormat,
+    payload: &[u8],
+) -> std::io::Result<()> {
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(std::io::Error::new(ErrorKind::InvalidInput, "Frame payload too large"));
+    }
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    header[..8].copy_from_slice(&request_id.to_be_bytes());
+    header[8] = format.id();
+    header[9..].copy_from_slice(&(payload.len() as u32).to_be_bytes());
+    writer.write_all(&header)?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+/// Read one frame. Returns `None` if the peer closed the connection before
+/// a new frame.
+pub fn read_frame<R: Read>(reader: &mut R) -> std::io::Result<Option<Frame>> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    let mut filled = 0;
+    while filled < FRAME_HEADER_LEN {
+        match reader.read(&mut header[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let request_id = u64::from_be_bytes(header[..8].try_into().expect("8 bytes"));
+    let format = WireFormat::from_id(header[8]).ok_or_else(|| {
+        std::io::Error::new(ErrorKind::InvalidData, format!("Unknown wire format {}", header[8]))
+    })?;
+    let len = u32::from_be_bytes(header[9..].try_into().expect("4 bytes")) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(std::io::Error::new(ErrorKind::InvalidData, "Frame payload too large"));
+    }
+    // Each step at most doubles the buffer, into a fresh allocation so the
+    // old one is zeroized rather than left behind by a reallocation
+    let mut payload = Zeroizing::new(Vec::new());
+    while payload.len() < len {
+        let chunk = (len - payload.len()).min(payload.len().max(READ_CHUNK_LEN));
+        let mut grown = Zeroizing::new(Vec::with_capacity(payload.len() + chunk));
+        grown.extend_from_slice(&payload);
+        if reader.by_ref().take(chunk as u64).read_to_end(&mut grown)? < chunk {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        payload = grown;
+    }
+    Ok(Some(Frame { request_id, format, payload }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{default_key_id, CiphersuiteId, Message, OprfMode, OprfRequest};
+
+    #[test]
+    fn test_read_frame_in_chunks() {
+        let payload: Vec<u8> = (0..3 * READ_CHUNK_LEN + 17).map(|i| i as u8).collect();
+        let mut wire = Vec::new();
+        write_frame(&mut wire, 9, WireFormat::Cbor, &payload).unwrap();
+        let frame = read_frame(&mut wire.as_slice()).unwrap().unwrap();
+        assert_eq!(frame.payload.as_slice(), payload.as_slice());
+
+        // A frame announcing the largest payload and sending a few bytes
+        // fails once they run out
+        let mut truncated = 1u64.to_be_bytes().to_vec();
+        truncated.push(WireFormat::Json.id());
+        truncated.extend_from_slice(&(MAX_FRAME_LEN as u32).to_be_bytes());
+        truncated.extend_from_slice(b"abc");
+        let error = read_frame(&mut truncated.as_slice()).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        let mut wire = Vec::new();
+        write_frame(&mut wire, 7, WireFormat::Cbor, b"first").unwrap();
+        write_frame(&mut wire, 3, WireFormat::Json, b"").unwrap();
+        assert_eq!(wire.len(), 2 * FRAME_HEADER_LEN + 5);
+
+        let mut reader = wire.as_slice();
+        let frame = read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!((frame.request_id, frame.payload.as_slice()), (7, &b"first"[..]));
+        assert_eq!(frame.format, WireFormat::Cbor);
+        let frame = read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!((frame.request_id, frame.payload.len()), (3, 0));
+        assert_eq!(frame.format, WireFormat::Json);
+        assert!(read_frame(&mut reader).unwrap().is_none());
+
+        // A truncated header or payload is an error, not a clean close
+        assert!(read_frame(&mut &wire[..5]).is_err());
+        assert!(read_frame(&mut &wire[..FRAME_HEADER_LEN + 2]).is_err());
+
+        let mut oversized = 1u64.to_be_bytes().to_vec();
+        oversized.push(WireFormat::Json.id());
+        oversized.extend_from_slice(&(MAX_FRAME_LEN as u32 + 1).to_be_bytes());
+        assert!(read_frame(&mut oversized.as_slice()).is_err());
+
+        let mut unknown_format = wire[..FRAME_HEADER_LEN + 5].to_vec();
+        unknown_format[8] = 0x7f;
+        assert!(read_frame(&mut unknown_format.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_wire_formats() {
+        let request = Message::Evaluate(OprfRequest {
+            blinded_query: vec![0xab; 32],
+            query_hash: "00".repeat(32),
+            mode: OprfMode::default(),
+            ciphersuite: CiphersuiteId::default(),
+            epoch: None,
+            key_id: default_key_id(),
+            force_fresh: false,
+            client_nonce: Some(vec![7; 32]),
+            client_id: None,
+            request_id: None,
+        });
+        let json = WireFormat::Json.encode(&request).unwrap();
+        let cbor = WireFormat::Cbor.encode(&request).unwrap();
+        assert!(cbor.len() < json.len());
+        // JSON keeps byte fields as arrays of numbers, as older clients send
+        assert_eq!(json, serde_json::to_vec(&request).unwrap());
+        assert!(String::from_utf8(json.clone()).unwrap().contains("[171,171,"));
+
+        for (format, bytes) in [(WireFormat::Json, json), (WireFormat::Cbor, cbor)] {
+            match format.decode::<Message>(&bytes).unwrap() {
+                Message::Evaluate(decoded) => {
+                    assert_eq!(decoded.blinded_query, vec![0xab; 32]);
+                    assert_eq!(decoded.client_nonce, Some(vec![7; 32]));
+                }
+                _ => panic!("Decoded the wrong request type"),
+            }
+            assert!(format.decode::<Message>(b"\xff\x00").is_err());
+        }
+
+        assert_eq!("JSON".parse::<WireFormat>(), Ok(WireFormat::Json));
+        assert_eq!("cbor".parse::<WireFormat>(), Ok(WireFormat::Cbor));
+        assert!("xml".parse::<WireFormat>().is_err());
+        assert_eq!(WireFormat::from_id(WireFormat::Json.id()), Some(WireFormat::Json));
+    }
+}
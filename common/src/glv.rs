@@ -0,0 +1,189 @@
+//! GLV multiplication of BN254 G1 points, enabled by the `glv` feature.
+//!
+//! G1 has the endomorphism `φ(x, y) = (β·x, y)` for a cube root of unity
+//! `β` of the base field, which maps every point `P` to `λ·P` for a cube
+//! root of unity `λ` of the scalar field. Following Gallant, Lambert and
+//! Vanstone, a scalar `k` is split into `k1 + k2·λ` with halves below 2^128,
+//! and `k·P` computed as `k1·P + k2·φ(P)`: both halves share one run of
+//! doublings, half as long as that of [`crate::ct::mul`].
+//!
+//! The result is the same and so is the time independence from the scalar:
+//! the split takes fixed-width integer products, the signs and parities of
+//! the halves are applied by masked selection, and both tables of odd
+//! multiples are read with masked scans of the whole table.
+
+use crate::ct::{lookup, odd_multiples, random_lambda, recode, CtPoint, WINDOW};
+use ark_bn254::{Fq, Fr, G1Projective};
+use ark_ec::Group;
+use ark_ff::{BigInteger, MontFp, PrimeField};
+use subtle::{Choice, ConditionallySelectable};
+use zeroize::Zeroizing;
+
+/// Cube root of unity of the base field with `φ(P) = λ·P` for
+/// `λ = 4407920970296243842393367215006156084916469457145843978461`
+const BETA: Fq = MontFp!("2203960485148121921418603742825762020974279258880205651966");
+
+// Reduced basis `(A1, -B1), (A2, B2)` of the lattice of `(a, b)` with
+// `a + b·λ = 0 mod r`
+const A1: Fr = MontFp!("9931322734385697763");
+const B1: Fr = MontFp!("147946756881789319000765030803803410728");
+const A2: Fr = MontFp!("147946756881789319010696353538189108491");
+const B2: Fr = MontFp!("9931322734385697763");
+
+/// `⌊B2·2^256 / r⌋` and `⌊B1·2^256 / r⌋`, little-endian limbs
+const G1: [u64; 3] = [0xd91d232ec7e0b3d7, 0x2, 0x0];
+const G2: [u64; 3] = [0x7a7bd9d4391eb18d, 0x4ccef014a773d2cf, 0x2];
+
+/// Bits of a half made odd, which is below `1.25·2^127 + 1`
+const HALF_BITS: usize = 129;
+
+/// A half of a split scalar: recoded digits of its odd magnitude, whether
+/// it is negative, and whether it was even and made odd by adding one
+type Half = (Zeroizing<Vec<i8>>, Choice, Choice);
+
+/// `⌊k·g / 2^256⌋`, below 2^128 for `k < r`, with the same operations for
+/// every `k`
+fn mul_shift(k: &[u64], g: &[u64; 3]) -> u128 {
+    let mut product = Zeroizing::new([0u64; 7]);
+    for (i, k) in k.iter().enumerate() {
+        let mut carry = 0u128;
+        for (j, g) in g.iter().enumerate() {
+            let t = product[i + j] as u128 + (*k as u128) * (*g as u128) + carry;
+            product[i + j] = t as u64;
+            carry = t >> 64;
+        }
+        product[i + g.len()] = carry as u64;
+    }
+    product[4] as u128 | (product[5] as u128) << 64
+}
+
+fn half(value: &Fr) -> Half {
+    let k = Zeroizing::new(value.into_bigint());
+    let mut above = Fr::MODULUS_MINUS_ONE_DIV_TWO;
+    let negative = Choice::from(above.sub_with_borrow(&k) as u8);
+    let mut magnitude = Zeroizing::new(Fr::MODULUS);
+    magnitude.sub_with_borrow(&k);
+    let mut limbs: Zeroizing<Vec<u64>> = Zeroizing::new(
+        k.as_ref()
+            .iter()
+            .zip(magnitude.as_ref())
+            .map(|(k, magnitude)| u64::conditional_select(k, magnitude, negative))
+            .collect(),
+    );
+    let even = !Choice::from((limbs[0] & 1) as u8);
+    // Adding one to an even number sets its lowest bit
+    limbs[0] |= 1;
+    (recode(&mut limbs, HALF_BITS), negative, even)
+}
+
+/// The halves `k1` and `k2` of `scalar == k1 + k2·λ`
+fn split(scalar: &Fr) -> [Half; 2] {
+    let k = Zeroizing::new(scalar.into_bigint());
+    let c1 = Zeroizing::new(Fr::from(mul_shift(k.as_ref(), &G1)));
+    let c2 = Zeroizing::new(Fr::from(mul_shift(k.as_ref(), &G2)));
+    let k1 = Zeroizing::new(*scalar - *c1 * A1 - *c2 * A2);
+    let k2 = Zeroizing::new(*c1 * B1 - *c2 * B2);
+    [half(&k1), half(&k2)]
+}
+
+/// `φ(P)`: in Jacobian coordinates `(X, Y, Z)` maps to `(β·X, Y, Z)`
+fn endomorphism(point: &G1Projective) -> G1Projective {
+    G1Projective::new_unchecked(point.x * BETA, point.y, point.z)
+}
+
+fn mul_split(point: &G1Projective, halves: &[Half; 2]) -> G1Projective {
+    let [(digits1, negative1, even1), (digits2, negative2, even2)] = halves;
+    let p = point.rescale(&random_lambda());
+    let q = endomorphism(&p);
+    let p = G1Projective::select(&p, &-p, *negative1);
+    let q = G1Projective::select(&q, &-q, *negative2);
+    let (table1, table2) = (odd_multiples(p), odd_multiples(q));
+
+    let mut digits = digits1.iter().zip(digits2.iter()).rev();
+    let (top1, top2) = digits.next().expect("at least one digit");
+    let mut acc = lookup(&table1, *top1) + lookup(&table2, *top2);
+    for (digit1, digit2) in digits {
+        for _ in 0..WINDOW {
+            acc.double_in_place();
+        }
+        acc += lookup(&table1, *digit1);
+        acc += lookup(&table2, *digit2);
+    }
+    let acc = G1Projective::select(&acc, &(acc - p), *even1);
+    G1Projective::select(&acc, &(acc - q), *even2)
+}
+
+/// `scalar * point`, as [`crate::ct::mul`] computes it
+pub(crate) fn mul(point: &G1Projective, scalar: &Fr) -> G1Projective {
+    mul_split(point, &split(scalar))
+}
+
+/// `scalar * point` for every point, splitting the scalar once
+pub(crate) fn mul_many(points: &[G1Projective], scalar: &Fr) -> Vec<G1Projective> {
+    let halves = split(scalar);
+    points.iter().map(|point| mul_split(point, &halves)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_ff::{MontFp, PrimeField};
+    use ark_std::test_rng;
+    use crate::{ct, g1_generator, hash_to_g1, random_scalar, Bn254Sha256, Ciphersuite};
+    use std::hint::black_box;
+    use std::time::Instant;
+
+    #[test]
+    fn test_glv_mul() {
+        let lambda: Fr = MontFp!("4407920970296243842393367215006156084916469457145843978461");
+        let mut rng = test_rng();
+        let mut scalars: Vec<Fr> = (0..256).map(|_| random_scalar(&mut rng)).collect();
+        let minus_one = -Fr::from(1u64);
+        scalars.extend([0u64, 1, 2, 15, 16, 17, u64::MAX].map(Fr::from));
+        scalars.extend([minus_one, minus_one - Fr::from(1u64), lambda, -lambda]);
+        scalars.extend([lambda * lambda, lambda + Fr::from(1u64), Fr::from(1u128 << 127)]);
+        let half = Fr::from_bigint(Fr::MODULUS_MINUS_ONE_DIV_TWO).unwrap();
+        scalars.extend([half, half + Fr::from(1u64)]);
+
+        let points = [hash_to_g1(b"input"), g1_generator()];
+        for point in &points {
+            for k in &scalars {
+                assert_eq!(mul(point, k), *point * k);
+            }
+        }
+        let k = random_scalar(&mut rng);
+        let each: Vec<_> = points.iter().map(|point| *point * k).collect();
+        assert_eq!(mul_many(&points, &k), each);
+        assert_eq!(Bn254Sha256::scalar_mul(&points[0], &k), each[0]);
+    }
+
+    /// Speed of the GLV multiplication against [`ct::mul`]. Run on demand:
+    /// `cargo test --release -p oprf-common --features glv -- --ignored
+    /// --nocapture glv_speed`.
+    #[test]
+    #[ignore]
+    fn test_glv_speed() {
+        const RUNS: u32 = 1024;
+        let mut rng = test_rng();
+        let scalars: Vec<_> = (0..RUNS).map(|_| random_scalar(&mut rng)).collect();
+        let point = hash_to_g1(b"input");
+
+        let start = Instant::now();
+        for k in &scalars {
+            let _ = black_box(ct::mul(&point, k));
+        }
+        let naive = start.elapsed();
+        let start = Instant::now();
+        for k in &scalars {
+            let _ = black_box(mul(&point, k));
+        }
+        let fast = start.elapsed();
+        println!(
+            "scalar_mul: {:?}, {:?} with GLV ({:.2}x)",
+            naive / RUNS,
+            fast / RUNS,
+            naive.as_secs_f64() / fast.as_secs_f64()
+        );
+        assert!(fast < naive);
+    }
+}
@@ -0,0 +1,396 @@
+//! The `Oprf` gRPC service of `proto/oprf.proto`, on tonic.
+//!
+//! [`OprfServer`] answers `Evaluate`, `BatchEvaluate`, `GetPublicKey` and
+//! `GetAttestation` by handing the [`Message`] of each request to a
+//! [`Backend`]: the parent proxies them to the enclave, and an enclave with
+//! networking, such as a TDX guest, can answer them from its own process.
+//! Messages are encoded with the hand-written codec of [`crate::proto`]
+//! through [`ProtoCodec`], so no protoc or prost is needed. A request the
+//! enclave refuses fails with the status of its [`ErrorCode`], whose
+//! details hold the encoded [`ErrorResponse`], and one that does not reach
+//! it with `UNAVAILABLE`, see [`status`]. Built with the `grpc` feature.
+
+use crate::proto::ProtoMessage;
+use crate::{
+    AttestationResponse, BatchOprfRequest, BatchOprfResponse, ErrorCode, ErrorResponse,
+    GetAttestationRequest, GetPublicKeyRequest, Message, OprfRequest, OprfResponse,
+    PublicKeyResponse,
+};
+use bytes::{Buf, BufMut};
+use serde::de::DeserializeOwned;
+use std::convert::Infallible;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use tonic::body::BoxBody;
+use tonic::codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder};
+use tonic::codegen::{empty_body, http, Body, BoxFuture, Bytes, Context, Poll, Service, StdError};
+use tonic::server::{Grpc, NamedService, UnaryService};
+use tonic::transport::server::TcpIncoming;
+use tonic::{Code, Status};
+
+/// Where the requests of an [`OprfServer`] are answered
+pub trait Backend: Send + Sync + 'static {
+    /// Answer of the enclave to `message`, or the error of a request it did
+    /// not answer: a refusal is one whose source is its [`ErrorResponse`].
+    /// Called on a blocking thread.
+    fn request<Resp: DeserializeOwned>(&self, message: &Message) -> std::io::Result<Resp>;
+}
+
+/// Status of a request that failed with `error`
+pub fn status(error: &std::io::Error) -> Status {
+    let Some(error) = error.get_ref().and_then(|e| e.downcast_ref::<ErrorResponse>()) else {
+        return Status::unavailable(error.to_string());
+    };
+    let code = match error.code {
+        ErrorCode::BadPoint | ErrorCode::HashMismatch | ErrorCode::BadRequest => {
+            Code::InvalidArgument
+        }
+        ErrorCode::RateLimited => Code::ResourceExhausted,
+        ErrorCode::Unauthorized => Code::PermissionDenied,
+        ErrorCode::InternalError => Code::Internal,
+    };
+    Status::with_details(code, error.message.clone(), Bytes::from(error.encode_to_vec()))
+}
+
+/// Tonic codec encoding `E` and decoding `D` with [`ProtoMessage`]
+pub struct ProtoCodec<E, D>(PhantomData<fn(E) -> D>);
+
+impl<E, D> Default for ProtoCodec<E, D> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<E, D> Codec for ProtoCodec<E, D>
+where
+    E: ProtoMessage + Send + 'static,
+    D: ProtoMessage + Send + 'static,
+{
+    type Encode = E;
+    type Decode = D;
+    type Encoder = ProtoCodec<E, ()>;
+    type Decoder = ProtoCodec<(), D>;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        ProtoCodec(PhantomData)
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        ProtoCodec(PhantomData)
+    }
+}
+
+impl<E: ProtoMessage> Encoder for ProtoCodec<E, ()> {
+    type Item = E;
+    type Error = Status;
+
+    fn encode(&mut self, item: E, dst: &mut EncodeBuf<'_>) -> Result<(), Status> {
+        dst.put_slice(&item.encode_to_vec());
+        Ok(())
+    }
+}
+
+impl<D: ProtoMessage> Decoder for ProtoCodec<(), D> {
+    type Item = D;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<D>, Status> {
+        let bytes = src.copy_to_bytes(src.remaining());
+        D::decode(&bytes).map(Some).map_err(|e| Status::invalid_argument(e.to_string()))
+    }
+}
+
+/// One RPC: its request wrapped in a [`Message`] for the backend, answered
+/// with `Resp`
+struct Unary<B, Req, Resp> {
+    backend: Arc<B>,
+    message: fn(Req) -> Message,
+    response: PhantomData<fn() -> Resp>,
+}
+
+impl<B, Req, Resp> UnaryService<Req> for Unary<B, Req, Resp>
+where
+    B: Backend,
+    Resp: DeserializeOwned + Send + 'static,
+{
+    type Response = Resp;
+    type Future = BoxFuture<tonic::Response<Resp>, Status>;
+
+    fn call(&mut self, request: tonic::Request<Req>) -> Self::Future {
+        let backend = Arc::clone(&self.backend);
+        let message = (self.message)(request.into_inner());
+        Box::pin(async move {
+            let response = tokio::task::spawn_blocking(move || backend.request::<Resp>(&message))
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?;
+            response.map(tonic::Response::new).map_err(|e| status(&e))
+        })
+    }
+}
+
+/// The `Oprf` service, answering from `B`
+pub struct OprfServer<B> {
+    backend: Arc<B>,
+}
+
+impl<B> OprfServer<B> {
+    pub fn new(backend: B) -> Self {
+        Self {
+            backend: Arc::new(backend),
+        }
+    }
+
+    /// Answer `request` with the `Resp` of the backend to its [`Message`]
+    fn unary<Req, Resp, Bd>(
+        &self,
+        message: fn(Req) -> Message,
+        request: http::Request<Bd>,
+    ) -> BoxFuture<http::Response<BoxBody>, Infallible>
+    where
+        B: Backend,
+        Req: ProtoMessage + Send + 'static,
+        Resp: ProtoMessage + DeserializeOwned + Send + 'static,
+        Bd: Body + Send + 'static,
+        Bd::Error: Into<StdError> + Send + 'static,
+    {
+        let service = Unary {
+            backend: Arc::clone(&self.backend),
+            message,
+            response: PhantomData::<fn() -> Resp>,
+        };
+        Box::pin(async move {
+            let mut grpc = Grpc::new(ProtoCodec::<Resp, Req>::default());
+            Ok(grpc.unary(service, request).await)
+        })
+    }
+}
+
+impl<B> Clone for OprfServer<B> {
+    fn clone(&self) -> Self {
+        Self {
+            backend: Arc::clone(&self.backend),
+        }
+    }
+}
+
+impl<B> NamedService for OprfServer<B> {
+    const NAME: &'static str = "oprf.v1.Oprf";
+}
+
+impl<B, Bd> Service<http::Request<Bd>> for OprfServer<B>
+where
+    B: Backend,
+    Bd: Body + Send + 'static,
+    Bd::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Infallible>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<Bd>) -> Self::Future {
+        match request.uri().path() {
+            "/oprf.v1.Oprf/Evaluate" => {
+                self.unary::<OprfRequest, OprfResponse, _>(Message::Evaluate, request)
+            }
+            "/oprf.v1.Oprf/BatchEvaluate" => {
+                self.unary::<BatchOprfRequest, BatchOprfResponse, _>(
+                    Message::BatchEvaluate,
+                    request,
+                )
+            }
+            "/oprf.v1.Oprf/GetPublicKey" => {
+                self.unary::<GetPublicKeyRequest, PublicKeyResponse, _>(
+                    Message::GetPublicKey,
+                    request,
+                )
+            }
+            "/oprf.v1.Oprf/GetAttestation" => {
+                self.unary::<GetAttestationRequest, AttestationResponse, _>(
+                    Message::GetAttestation,
+                    request,
+                )
+            }
+            _ => Box::pin(async {
+                let mut response = http::Response::new(empty_body());
+                let headers = response.headers_mut();
+                headers.insert(Status::GRPC_STATUS, (Code::Unimplemented as i32).into());
+                headers.insert(
+                    http::header::CONTENT_TYPE,
+                    tonic::metadata::GRPC_CONTENT_TYPE,
+                );
+                Ok(response)
+            }),
+        }
+    }
+}
+
+/// Serve the `Oprf` service on `listener` until the process is stopped,
+/// answering from `backend`
+pub fn serve<B: Backend>(listener: std::net::TcpListener, backend: B) -> std::io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        let incoming =
+            TcpIncoming::from_listener(listener, true, None).map_err(std::io::Error::other)?;
+        tonic::transport::Server::builder()
+            .add_service(OprfServer::new(backend))
+            .serve_with_incoming(incoming)
+            .await
+            .map_err(std::io::Error::other)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AttestationDocument, CiphersuiteId, OprfMode};
+    use std::net::{SocketAddr, TcpListener};
+    use tonic::codegen::http::uri::PathAndQuery;
+    use tonic::transport::Endpoint;
+
+    /// Answers evaluations with their blinded query, refuses public keys as
+    /// unknown and anything else but attestations, which it cannot reach,
+    /// as over budget
+    struct Echo;
+
+    impl Backend for Echo {
+        fn request<Resp: DeserializeOwned>(&self, message: &Message) -> std::io::Result<Resp> {
+            let refused = |code, message: String| {
+                Err(std::io::Error::other(ErrorResponse::new(code, message)))
+            };
+            let response = match message {
+                Message::Evaluate(request) => OprfResponse {
+                    evaluated_point: request.blinded_query.clone(),
+                    public_key: vec![2; 33],
+                    attestation: AttestationDocument {
+                        is_mock: true,
+                        document: vec![1; 8],
+                        pcrs: None,
+                        user_data: vec![],
+                    },
+                    proof: None,
+                    public_key_g2: None,
+                    epoch: 7,
+                    key_id: request.key_id.clone(),
+                    request_id: request.request_id.clone(),
+                },
+                Message::GetPublicKey(request) => {
+                    return refused(ErrorCode::BadRequest, format!("Unknown key {}", request.key_id))
+                }
+                Message::GetAttestation(_) => {
+                    return Err(std::io::Error::other("Connection refused"));
+                }
+                _ => return refused(ErrorCode::RateLimited, "budget used".to_string()),
+            };
+            Ok(serde_json::from_value(serde_json::to_value(response).unwrap()).unwrap())
+        }
+    }
+
+    /// Address of the service answering from `backend` on a thread
+    fn start(backend: impl Backend) -> SocketAddr {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || serve(listener, backend));
+        address
+    }
+
+    /// Answer of the service at `address` to `request` on `method`
+    fn call<Req, Resp>(
+        address: SocketAddr,
+        method: &str,
+        request: Req,
+    ) -> Result<Resp, Box<Status>>
+    where
+        Req: ProtoMessage + Send + Sync + 'static,
+        Resp: ProtoMessage + Send + Sync + 'static,
+    {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let endpoint = Endpoint::from_shared(format!("http://{}", address)).unwrap();
+            let mut grpc = tonic::client::Grpc::new(endpoint.connect().await.unwrap());
+            grpc.ready().await.unwrap();
+            let path = PathAndQuery::try_from(format!("/oprf.v1.Oprf/{}", method)).unwrap();
+            let codec = ProtoCodec::<Req, Resp>::default();
+            let response = grpc.unary(tonic::Request::new(request), path, codec).await;
+            response.map(tonic::Response::into_inner).map_err(Box::new)
+        })
+    }
+
+    fn evaluation() -> OprfRequest {
+        OprfRequest {
+            blinded_query: vec![3; 33],
+            query_hash: String::new(),
+            mode: OprfMode::Voprf,
+            ciphersuite: CiphersuiteId::P256Sha256,
+            epoch: None,
+            key_id: "app".to_string(),
+            force_fresh: false,
+            client_nonce: None,
+            client_id: None,
+            request_id: Some("req-1".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_grpc_round_trip() {
+        let address = start(Echo);
+        let response: OprfResponse = call(address, "Evaluate", evaluation()).unwrap();
+        assert_eq!(response.evaluated_point, vec![3; 33]);
+        assert_eq!((response.epoch, response.key_id.as_str()), (7, "app"));
+        assert_eq!(response.request_id.as_deref(), Some("req-1"));
+        assert_eq!(response.attestation.document, vec![1; 8]);
+    }
+
+    #[test]
+    fn test_grpc_refusals() {
+        let address = start(Echo);
+        let request = GetPublicKeyRequest {
+            ciphersuite: CiphersuiteId::P256Sha256,
+            key_id: "other".to_string(),
+        };
+        let refused = call::<_, PublicKeyResponse>(address, "GetPublicKey", request).unwrap_err();
+        assert_eq!(refused.code(), Code::InvalidArgument);
+        assert_eq!(refused.message(), "Unknown key other");
+        let error = ErrorResponse::decode(refused.details()).unwrap();
+        assert_eq!(error.code, ErrorCode::BadRequest);
+
+        let request = BatchOprfRequest {
+            blinded_queries: vec![vec![3; 33]],
+            query_hash: String::new(),
+            mode: OprfMode::Oprf,
+            ciphersuite: CiphersuiteId::P256Sha256,
+            epoch: None,
+            key_id: "app".to_string(),
+            force_fresh: false,
+            client_nonce: None,
+            client_id: None,
+            request_id: None,
+        };
+        let refused = call::<_, BatchOprfResponse>(address, "BatchEvaluate", request).unwrap_err();
+        assert_eq!(refused.code(), Code::ResourceExhausted);
+        let request = GetAttestationRequest {
+            ciphersuite: CiphersuiteId::P256Sha256,
+            key_id: "app".to_string(),
+            nonce: vec![1; 16],
+        };
+        let failed = call::<_, AttestationResponse>(address, "GetAttestation", request);
+        let failed = failed.unwrap_err();
+        assert_eq!(failed.code(), Code::Unavailable);
+        assert!(failed.details().is_empty());
+
+        // Unknown methods, and requests that are not the method's message
+        let unknown = call::<_, OprfResponse>(address, "Evaluate2", evaluation()).unwrap_err();
+        assert_eq!(unknown.code(), Code::Unimplemented);
+        let request = GetPublicKeyRequest {
+            ciphersuite: CiphersuiteId::P256Sha256,
+            key_id: "app".to_string(),
+        };
+        let malformed = call::<_, OprfResponse>(address, "Evaluate", request).unwrap_err();
+        assert_eq!(malformed.code(), Code::InvalidArgument);
+    }
+}
@@ -0,0 +1,74 @@
+//! Heartbeats the enclave publishes on its heartbeat port.
+//!
+//! An enclave started with a heartbeat port listens on it, apart from the
+//! data path and the admin port, and writes a [`Heartbeat`] frame to every
+//! connection on it once an interval, in CBOR; nothing is read from them.
+//! Each beat is taken holding the enclave state the requests are served
+//! under, so beats stop both when the enclave is gone and when its request
+//! path is stuck. The last beat before a graceful shutdown is marked
+//! [`Heartbeat::stopping`].
+//!
+//! Beats carry no secrets and are not attested: only the enclave can listen
+//! on its own vsock port, and what a forged beat could do is hide an outage
+//! the data path still reports.
+
+use serde::{Deserialize, Serialize};
+
+/// Seconds between heartbeats unless configured
+pub const DEFAULT_HEARTBEAT_INTERVAL_SECS: u64 = 5;
+
+/// Consecutive heartbeats a watchdog misses before it reports the enclave
+/// lost, unless configured
+pub const DEFAULT_MAX_MISSED_HEARTBEATS: u32 = 3;
+
+/// One beat of the enclave, sent as the payload of a frame whose request id
+/// is its sequence number
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Heartbeat {
+    /// Beats published before this one since the enclave started
+    pub sequence: u64,
+    /// Enclave clock, unix seconds
+    pub timestamp: u64,
+    /// Seconds until the next beat
+    pub interval_secs: u64,
+    /// Seconds since the enclave started
+    pub uptime_secs: u64,
+    /// Whether the latest attestation succeeded, see
+    /// [`crate::HealthResponse::is_ready`]
+    pub ready: bool,
+    /// Data path connections open, queued ones included
+    pub open_connections: usize,
+    /// Set on the last beat before the enclave shuts down
+    #[serde(default)]
+    pub stopping: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{read_frame, write_frame, WireFormat};
+
+    #[test]
+    fn test_heartbeat() {
+        let beat = Heartbeat {
+            sequence: 7,
+            timestamp: 1_700_000_000,
+            interval_secs: 5,
+            uptime_secs: 35,
+            ready: true,
+            open_connections: 2,
+            stopping: false,
+        };
+        let payload = WireFormat::Cbor.encode(&beat).unwrap();
+        let mut stream = Vec::new();
+        write_frame(&mut stream, beat.sequence, WireFormat::Cbor, &payload).unwrap();
+        let frame = read_frame(&mut stream.as_slice()).unwrap().unwrap();
+        assert_eq!(frame.request_id, beat.sequence);
+        assert_eq!(frame.format.decode::<Heartbeat>(&frame.payload).unwrap(), beat);
+
+        // A beat without the flag is not the last one
+        let json = r#"{"sequence":7,"timestamp":1700000000,"interval_secs":5,"uptime_secs":35,
+            "ready":true,"open_connections":2}"#;
+        assert_eq!(serde_json::from_str::<Heartbeat>(json).unwrap(), beat);
+    }
+}
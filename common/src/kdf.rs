@@ -0,0 +1,89 @@
+//! HKDF-SHA256 (RFC 5869), on the `hkdf` crate, and deterministic derivation
+//! of OPRF keys from an operator-supplied seed.
+//!
+//! Each secret key is derived from the seed with an info string naming the
+//! ciphersuite, key id and epoch, so replicas provisioned with the same seed
+//! hold identical keys, including after rotations, while distinct keys stay
+//! independent.
+
+use crate::{context_string, ct, Ciphersuite, OprfMode};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+/// Minimum seed length in bytes
+pub const MIN_SEED_LEN: usize = 32;
+
+/// HKDF-SHA256: fill `okm`, at most `255 * 32` bytes, with the output keying
+/// material of `ikm` under `salt` and `info`
+pub fn hkdf(salt: &[u8], ikm: &[u8], info: &[u8], okm: &mut [u8]) {
+    Hkdf::<Sha256>::new(Some(salt), ikm)
+        .expand(info, okm)
+        .expect("HKDF-Expand output too long");
+}
+
+/// Derive the secret key of `key_id` at `epoch` from `seed`.
+///
+/// `okm = HKDF(salt = "nitro-oprf-seed-v1", ikm = seed, info)` with
+/// `info = "nitro-oprf key" || len || identifier || len || key_id || epoch`,
+/// mapped to a non-zero scalar with `HashToScalar` under the
+/// `DeriveKeyPair` DST of RFC 9497.
+pub fn derive_secret_key<C: Ciphersuite>(seed: &[u8], key_id: &str, epoch: u64) -> C::Scalar {
+    let mut info = b"nitro-oprf key".to_vec();
+    for label in [C::IDENTIFIER.as_bytes(), key_id.as_bytes()] {
+        info.extend_from_slice(&(label.len() as u16).to_be_bytes());
+        info.extend_from_slice(label);
+    }
+    info.extend_from_slice(&epoch.to_be_bytes());
+
+    let mut dst = b"DeriveKeyPair".to_vec();
+    dst.extend_from_slice(&context_string::<C>(OprfMode::Oprf));
+
+    let zero = C::Scalar::default();
+    let mut okm = Zeroizing::new([0u8; 33]);
+    hkdf(b"nitro-oprf-seed-v1", seed, &info, &mut okm[..32]);
+    for counter in 0..=u8::MAX {
+        okm[32] = counter;
+        let scalar = C::hash_to_scalar(&okm[..], &dst);
+        if !ct::scalar_eq::<C>(&scalar, &zero) {
+            return scalar;
+        }
+    }
+    panic!("DeriveKeyPair failed for 256 counters");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Bn254Sha256, P256Sha256, DEFAULT_KEY_ID};
+
+    #[test]
+    fn test_hkdf_derive_key() {
+        // RFC 5869 test case 1
+        let salt: Vec<u8> = (0x00..=0x0c).collect();
+        let info: Vec<u8> = (0xf0..=0xf9).collect();
+        let (prk, _) = Hkdf::<Sha256>::extract(Some(&salt), &[0x0b; 22]);
+        assert_eq!(
+            hex::encode(prk),
+            "077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5"
+        );
+        let mut okm = [0u8; 42];
+        hkdf(&salt, &[0x0b; 22], &info, &mut okm);
+        assert_eq!(
+            hex::encode(okm),
+            "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865"
+        );
+
+        // Same seed, key id and epoch give the same key; any change gives another
+        let seed = [0x5eu8; MIN_SEED_LEN];
+        let k = derive_secret_key::<Bn254Sha256>(&seed, DEFAULT_KEY_ID, 0);
+        assert_eq!(k, derive_secret_key::<Bn254Sha256>(&seed, DEFAULT_KEY_ID, 0));
+        assert_ne!(k, derive_secret_key::<Bn254Sha256>(&seed, DEFAULT_KEY_ID, 1));
+        assert_ne!(k, derive_secret_key::<Bn254Sha256>(&seed, "other", 0));
+        assert_ne!(k, derive_secret_key::<Bn254Sha256>(&[0u8; 32], DEFAULT_KEY_ID, 0));
+
+        let k = derive_secret_key::<P256Sha256>(&seed, DEFAULT_KEY_ID, 0);
+        assert_eq!(k, derive_secret_key::<P256Sha256>(&seed, DEFAULT_KEY_ID, 0));
+        assert_ne!(k, derive_secret_key::<P256Sha256>(&seed, DEFAULT_KEY_ID, 1));
+    }
+}
@@ -1,11 +1,48 @@
-use ark_bn254::{Fr, G1Affine, G1Projective};
-use ark_ec::{AffineRepr, CurveGroup, Group};
-use ark_ff::{Field, PrimeField, UniformRand};
-use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_bn254::{Fr, G1Projective};
+use ark_ff::PrimeField;
 use ark_std::rand::Rng;
 use serde::{Deserialize, Serialize};
+use serde_cbor::Value;
 use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use thiserror::Error;
+use zeroize::Zeroize;
+
+pub mod admin;
+pub mod audit;
+pub mod channel;
+pub mod ciphersuite;
+pub mod claims;
+pub mod client;
+pub mod config;
+pub mod ct;
+pub mod dleq;
+pub mod duplex;
+pub mod ed25519;
+pub mod envelope;
+pub mod frame;
+pub mod heartbeat;
+#[cfg(feature = "glv")]
+pub mod glv;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod kdf;
+pub mod nitro;
+pub mod pairing;
+pub mod proto;
+pub mod redact;
+pub mod rng;
+pub mod selftest;
+pub mod signing;
+pub mod transport;
+pub mod vectors;
+
+pub use ciphersuite::{Bn254Sha256, Ciphersuite, CiphersuiteId, P256Sha256, PointEncoding};
+pub use client::{blind, unblind, Blind, BlindedElement, EvaluatedElement, OprfOutput};
+pub use dleq::{generate_proof, verify_proof, DleqProof};
+pub use pairing::{
+    deserialize_g2, public_key_g2, serialize_g2, verify_key_pair, verify_pairing,
+};
 
 /// Errors that can occur in OPRF operations
 #[derive(Error, Debug)]
@@ -14,74 +51,1610 @@ pub enum OprfError {
     Serialization(String),
     #[error("Deserialization error: {0}")]
     Deserialization(String),
-    #[error("Invalid point")]
-    InvalidPoint,
-    #[error("Attestation verification failed: {0}")]
-    AttestationFailed(String),
+    /// An encoded element that is not the canonical encoding of a
+    /// non-identity group element
+    #[error("Invalid point: {0}")]
+    InvalidPoint(String),
+    #[error(transparent)]
+    Attestation(#[from] AttestationError),
+    #[error("Invalid DLEQ proof")]
+    InvalidProof,
+    #[error("Invalid signature")]
+    InvalidSignature,
+    #[error("Unsupported ciphersuite: {0}")]
+    UnsupportedCiphersuite(String),
+    #[error("Invalid key id: {0}")]
+    InvalidKeyId(String),
+    #[error("Invalid request id: {0:?}")]
+    InvalidRequestId(String),
+    #[error("Invalid key backup: {0}")]
+    InvalidBackup(String),
+    #[error("Invalid attested user data: {0}")]
+    InvalidUserData(String),
+    #[error("Secure channel error: {0}")]
+    Channel(String),
+    #[error("Invalid audit log: {0}")]
+    InvalidAuditLog(String),
+}
+
+/// Why an attestation was rejected
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AttestationError {
+    #[error("Malformed attestation document: {0}")]
+    MalformedDocument(String),
+    #[error("Invalid attestation signature: {0}")]
+    SignatureInvalid(String),
+    #[error("Attestation chains to untrusted root certificate {0}")]
+    UntrustedRoot(String),
+    #[error(
+        "PCR{index} is {}, expected {expected}",
+        .got.as_deref().unwrap_or("not measured")
+    )]
+    PcrMismatch {
+        index: u64,
+        /// Expected value, hex-encoded
+        expected: String,
+        /// Measured value, hex-encoded, if the document has the PCR
+        got: Option<String>,
+    },
+    #[error("PCRs match no allowed enclave image")]
+    NoAllowedImage,
+    #[error("Attestation is {age_secs}s old, more than the allowed {max_age_secs}s")]
+    Expired { age_secs: u64, max_age_secs: u64 },
+    #[error("Attestation timestamp is {0}s in the future")]
+    NotYetValid(u64),
+    #[error("Client nonce mismatch in attestation")]
+    NonceMismatch,
+    #[error("User data mismatch in attestation: {0}")]
+    UserDataMismatch(String),
+    #[error("Attestation refused: {0}")]
+    NotConfigured(String),
+    #[error("System clock error: {0}")]
+    Clock(String),
+}
+
+impl AttestationError {
+    /// Whether the attestation was well-formed and authentic but refused by
+    /// the policy (measurements, trust anchors, freshness, bindings), as
+    /// opposed to unparseable or unauthenticated
+    pub fn is_policy_violation(&self) -> bool {
+        !matches!(
+            self,
+            AttestationError::MalformedDocument(_)
+                | AttestationError::SignatureInvalid(_)
+                | AttestationError::Clock(_)
+        )
+    }
+}
+
+/// OPRF protocol variant (RFC 9497 Section 3.1)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OprfMode {
+    /// Base mode: the evaluation is trusted on the strength of attestation alone
+    #[default]
+    Oprf,
+    /// Verifiable mode: the enclave attaches a DLEQ proof against its public key
+    Voprf,
+}
+
+impl OprfMode {
+    /// Mode identifier byte used in the context string
+    pub fn id(&self) -> u8 {
+        match self {
+            OprfMode::Oprf => 0x00,
+            OprfMode::Voprf => 0x01,
+        }
+    }
+}
+
+/// Serde adapter for lists of byte strings: CBOR byte strings on the wire,
+/// JSON arrays of numbers as for a plain `Vec<u8>`
+mod byte_list {
+    use super::bounded::{Bytes, List};
+    use super::{MAX_BATCH_SIZE, MAX_ELEMENT_LEN};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(items: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(items.iter().map(|item| serde_bytes::Bytes::new(item)))
+    }
+
+    /// At most [`MAX_BATCH_SIZE`] items of at most [`MAX_ELEMENT_LEN`] bytes
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let items = List::<Bytes<MAX_ELEMENT_LEN>, MAX_BATCH_SIZE>::deserialize(deserializer)?;
+        Ok(items.0.into_iter().map(|item| item.0).collect())
+    }
+}
+
+/// Serde adapters that refuse byte strings, strings and lists longer than a
+/// limit as they are read, before anything is allocated for them. Byte
+/// strings are CBOR byte strings or, in JSON, arrays of numbers, as with
+/// `serde_bytes`.
+mod bounded {
+    use serde::de::{Error, Expected, SeqAccess, Visitor};
+    use serde::{Deserialize, Deserializer};
+    use std::fmt;
+    use std::marker::PhantomData;
+
+    /// A byte string of at most `MAX` bytes
+    pub struct Bytes<const MAX: usize>(pub Vec<u8>);
+
+    /// A string of at most `MAX` bytes
+    pub struct Str<const MAX: usize>(pub String);
+
+    /// A list of at most `MAX` items
+    pub struct List<T, const MAX: usize>(pub Vec<T>);
+
+    fn too_long<E: Error>(expected: &dyn Expected) -> E {
+        E::custom(format_args!("invalid length, expected {}", expected))
+    }
+
+    /// The items of `seq`, failing at the first one past `MAX`
+    fn read_seq<'de, A, T, const MAX: usize>(
+        mut seq: A,
+        expected: &dyn Expected,
+    ) -> Result<Vec<T>, A::Error>
+    where
+        A: SeqAccess<'de>,
+        T: Deserialize<'de>,
+    {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(MAX));
+        while let Some(item) = seq.next_element()? {
+            if items.len() == MAX {
+                return Err(too_long(expected));
+            }
+            items.push(item);
+        }
+        Ok(items)
+    }
+
+    impl<'de, const MAX: usize> Deserialize<'de> for Bytes<MAX> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct BytesVisitor<const MAX: usize>;
+
+            impl<'de, const MAX: usize> Visitor<'de> for BytesVisitor<MAX> {
+                type Value = Vec<u8>;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    write!(f, "a byte string of at most {} bytes", MAX)
+                }
+
+                fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+                    self.visit_byte_buf(v.to_vec())
+                }
+
+                fn visit_byte_buf<E: Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+                    if v.len() > MAX {
+                        return Err(too_long(&self));
+                    }
+                    Ok(v)
+                }
+
+                fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Vec<u8>, A::Error> {
+                    read_seq::<_, _, MAX>(seq, &self)
+                }
+            }
+
+            deserializer.deserialize_byte_buf(BytesVisitor::<MAX>).map(Bytes)
+        }
+    }
+
+    impl<'de, const MAX: usize> Deserialize<'de> for Str<MAX> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct StrVisitor<const MAX: usize>;
+
+            impl<'de, const MAX: usize> Visitor<'de> for StrVisitor<MAX> {
+                type Value = String;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    write!(f, "a string of at most {} bytes", MAX)
+                }
+
+                fn visit_str<E: Error>(self, v: &str) -> Result<String, E> {
+                    self.visit_string(v.to_string())
+                }
+
+                fn visit_string<E: Error>(self, v: String) -> Result<String, E> {
+                    if v.len() > MAX {
+                        return Err(too_long(&self));
+                    }
+                    Ok(v)
+                }
+            }
+
+            deserializer.deserialize_string(StrVisitor::<MAX>).map(Str)
+        }
+    }
+
+    impl<'de, T: Deserialize<'de>, const MAX: usize> Deserialize<'de> for List<T, MAX> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct ListVisitor<T, const MAX: usize>(PhantomData<T>);
+
+            impl<'de, T: Deserialize<'de>, const MAX: usize> Visitor<'de> for ListVisitor<T, MAX> {
+                type Value = Vec<T>;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    write!(f, "a list of at most {} items", MAX)
+                }
+
+                fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Vec<T>, A::Error> {
+                    read_seq::<_, _, MAX>(seq, &self)
+                }
+            }
+
+            deserializer.deserialize_seq(ListVisitor::<T, MAX>(PhantomData)).map(List)
+        }
+    }
+
+    pub fn bytes<'de, D: Deserializer<'de>, const MAX: usize>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        Ok(Bytes::<MAX>::deserialize(deserializer)?.0)
+    }
+
+    pub fn option_bytes<'de, D: Deserializer<'de>, const MAX: usize>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        Ok(Option::<Bytes<MAX>>::deserialize(deserializer)?.map(|bytes| bytes.0))
+    }
+
+    pub fn string<'de, D: Deserializer<'de>, const MAX: usize>(
+        deserializer: D,
+    ) -> Result<String, D::Error> {
+        Ok(Str::<MAX>::deserialize(deserializer)?.0)
+    }
+
+    pub fn option_string<'de, D: Deserializer<'de>, const MAX: usize>(
+        deserializer: D,
+    ) -> Result<Option<String>, D::Error> {
+        Ok(Option::<Str<MAX>>::deserialize(deserializer)?.map(|string| string.0))
+    }
+
+    pub fn list<'de, D: Deserializer<'de>, T: Deserialize<'de>, const MAX: usize>(
+        deserializer: D,
+    ) -> Result<Vec<T>, D::Error> {
+        Ok(List::<T, MAX>::deserialize(deserializer)?.0)
+    }
+
+    /// An optional list of at most `COUNT` strings of at most `LEN` bytes
+    pub fn option_strings<'de, D: Deserializer<'de>, const COUNT: usize, const LEN: usize>(
+        deserializer: D,
+    ) -> Result<Option<Vec<String>>, D::Error> {
+        let list = Option::<List<Str<LEN>, COUNT>>::deserialize(deserializer)?;
+        Ok(list.map(|list| list.0.into_iter().map(|string| string.0).collect()))
+    }
 }
 
 /// Request from parent to enclave
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct OprfRequest {
-    /// Blinded query point g^(m*b) serialized
+    /// Blinded query point g^(m*b) serialized in the point encoding of the
+    /// connection
+    #[serde(
+        serialize_with = "serde_bytes::serialize",
+        deserialize_with = "bounded::bytes::<_, MAX_ELEMENT_LEN>"
+    )]
     pub blinded_query: Vec<u8>,
     /// Hash of the query for integrity
+    #[serde(deserialize_with = "bounded::string::<_, MAX_QUERY_HASH_LEN>")]
     pub query_hash: String,
+    /// Protocol mode; `Voprf` requests a DLEQ proof in the response
+    #[serde(default)]
+    pub mode: OprfMode,
+    /// Ciphersuite of `blinded_query`; selects which enclave key evaluates it
+    #[serde(default)]
+    pub ciphersuite: CiphersuiteId,
+    /// Key epoch to evaluate under; the current key when absent
+    #[serde(default)]
+    pub epoch: Option<u64>,
+    /// Named key to evaluate under, e.g. one per application
+    #[serde(default = "default_key_id", deserialize_with = "bounded::string::<_, MAX_KEY_ID_LEN>")]
+    pub key_id: String,
+    /// Require a newly generated attestation instead of a cached one
+    #[serde(default)]
+    pub force_fresh: bool,
+    /// Client-chosen nonce of at most [`MAX_CLIENT_NONCE_LEN`] bytes, echoed
+    /// in the attested [`EvaluationUserData`]
+    #[serde(
+        default,
+        serialize_with = "serde_bytes::serialize",
+        deserialize_with = "bounded::option_bytes::<_, MAX_CLIENT_NONCE_LEN>"
+    )]
+    pub client_nonce: Option<Vec<u8>>,
+    /// Client the evaluation is made for, at most [`MAX_CLIENT_ID_LEN`]
+    /// bytes, which the enclave rate-limits across connections. Left out of
+    /// the encoding when absent, as enclaves before it refuse the field.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "bounded::option_string::<_, MAX_CLIENT_ID_LEN>"
+    )]
+    pub client_id: Option<String>,
+    /// Id tagging the evaluation in the logs of both sides, in the response
+    /// and in its attested [`EvaluationUserData`], see [`validate_request_id`].
+    /// Only sent on connections whose Hello agreed to request ids.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "bounded::option_string::<_, MAX_REQUEST_ID_LEN>"
+    )]
+    pub request_id: Option<String>,
 }
 
 /// Response from enclave to parent
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct OprfResponse {
-    /// Evaluated point (blinded_query)^k serialized
+    /// Evaluated point (blinded_query)^k serialized in the point encoding of
+    /// the connection
+    #[serde(
+        serialize_with = "serde_bytes::serialize",
+        deserialize_with = "bounded::bytes::<_, MAX_ELEMENT_LEN>"
+    )]
     pub evaluated_point: Vec<u8>,
     /// Public key g^k serialized
+    #[serde(
+        serialize_with = "serde_bytes::serialize",
+        deserialize_with = "bounded::bytes::<_, MAX_ELEMENT_LEN>"
+    )]
+    pub public_key: Vec<u8>,
+    /// Attestation over the CBOR encoding of [`EvaluationUserData`]
+    pub attestation: AttestationDocument,
+    /// Serialized DLEQ proof, present in `Voprf` mode
+    #[serde(
+        default,
+        serialize_with = "serde_bytes::serialize",
+        deserialize_with = "bounded::option_bytes::<_, MAX_PROOF_LEN>"
+    )]
+    pub proof: Option<Vec<u8>>,
+    /// G2 public key g2^k serialized, present for BN254 when the enclave
+    /// publishes it for pairing-based verification
+    #[serde(
+        default,
+        serialize_with = "serde_bytes::serialize",
+        deserialize_with = "bounded::option_bytes::<_, MAX_ELEMENT_LEN>"
+    )]
+    pub public_key_g2: Option<Vec<u8>>,
+    /// Epoch of the key that evaluated the query
+    #[serde(default)]
+    pub epoch: u64,
+    /// Named key that evaluated the query
+    #[serde(default = "default_key_id", deserialize_with = "bounded::string::<_, MAX_KEY_ID_LEN>")]
+    pub key_id: String,
+    /// Request id of the request, echoed when it had one
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "bounded::option_string::<_, MAX_REQUEST_ID_LEN>"
+    )]
+    pub request_id: Option<String>,
+}
+
+/// Batch request: several blinded queries evaluated under one key and proof
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BatchOprfRequest {
+    /// Blinded query points serialized in the point encoding of the connection
+    #[serde(with = "byte_list")]
+    pub blinded_queries: Vec<Vec<u8>>,
+    /// SHA256 hash of the concatenated blinded queries
+    #[serde(deserialize_with = "bounded::string::<_, MAX_QUERY_HASH_LEN>")]
+    pub query_hash: String,
+    /// Protocol mode; `Voprf` requests one aggregated DLEQ proof for the batch
+    #[serde(default)]
+    pub mode: OprfMode,
+    /// Ciphersuite of every query in the batch
+    #[serde(default)]
+    pub ciphersuite: CiphersuiteId,
+    /// Key epoch to evaluate under; the current key when absent
+    #[serde(default)]
+    pub epoch: Option<u64>,
+    /// Named key to evaluate under, e.g. one per application
+    #[serde(default = "default_key_id", deserialize_with = "bounded::string::<_, MAX_KEY_ID_LEN>")]
+    pub key_id: String,
+    /// Require a newly generated attestation instead of a cached one
+    #[serde(default)]
+    pub force_fresh: bool,
+    /// Client-chosen nonce of at most [`MAX_CLIENT_NONCE_LEN`] bytes, echoed
+    /// in the attested [`EvaluationUserData`]
+    #[serde(
+        default,
+        serialize_with = "serde_bytes::serialize",
+        deserialize_with = "bounded::option_bytes::<_, MAX_CLIENT_NONCE_LEN>"
+    )]
+    pub client_nonce: Option<Vec<u8>>,
+    /// Client the evaluation is made for, at most [`MAX_CLIENT_ID_LEN`]
+    /// bytes, which the enclave rate-limits across connections. Left out of
+    /// the encoding when absent, as enclaves before it refuse the field.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "bounded::option_string::<_, MAX_CLIENT_ID_LEN>"
+    )]
+    pub client_id: Option<String>,
+    /// Id tagging the evaluation in the logs of both sides, in the response
+    /// and in its attested [`EvaluationUserData`], see [`validate_request_id`].
+    /// Only sent on connections whose Hello agreed to request ids.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "bounded::option_string::<_, MAX_REQUEST_ID_LEN>"
+    )]
+    pub request_id: Option<String>,
+}
+
+/// Batch response, in the same order as the request's queries
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BatchOprfResponse {
+    /// Evaluated points (blinded_queries[i])^k serialized in the point
+    /// encoding of the connection
+    #[serde(with = "byte_list")]
+    pub evaluated_points: Vec<Vec<u8>>,
+    /// Public key g^k serialized
+    #[serde(
+        serialize_with = "serde_bytes::serialize",
+        deserialize_with = "bounded::bytes::<_, MAX_ELEMENT_LEN>"
+    )]
+    pub public_key: Vec<u8>,
+    /// Attestation over the CBOR encoding of [`EvaluationUserData`] of the
+    /// concatenated evaluated points
+    pub attestation: AttestationDocument,
+    /// Single DLEQ proof covering every element, present in `Voprf` mode
+    #[serde(
+        default,
+        serialize_with = "serde_bytes::serialize",
+        deserialize_with = "bounded::option_bytes::<_, MAX_PROOF_LEN>"
+    )]
+    pub proof: Option<Vec<u8>>,
+    /// G2 public key g2^k serialized, see [`OprfResponse::public_key_g2`]
+    #[serde(
+        default,
+        serialize_with = "serde_bytes::serialize",
+        deserialize_with = "bounded::option_bytes::<_, MAX_ELEMENT_LEN>"
+    )]
+    pub public_key_g2: Option<Vec<u8>>,
+    /// Epoch of the key that evaluated the batch
+    #[serde(default)]
+    pub epoch: u64,
+    /// Named key that evaluated the batch
+    #[serde(default = "default_key_id", deserialize_with = "bounded::string::<_, MAX_KEY_ID_LEN>")]
+    pub key_id: String,
+    /// Request id of the request, echoed when it had one
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "bounded::option_string::<_, MAX_REQUEST_ID_LEN>"
+    )]
+    pub request_id: Option<String>,
+}
+
+/// Admin request: replace the key of a ciphersuite with a fresh one under the
+/// next epoch. The previous key keeps serving requests that name its epoch
+/// until the grace period ends.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RotateKeyRequest {
+    /// Ciphersuite whose key is rotated
+    pub ciphersuite: CiphersuiteId,
+    /// Named key to rotate
+    #[serde(default = "default_key_id")]
+    pub key_id: String,
+    /// Grace period for the previous key in seconds; the enclave default
+    /// applies when absent
+    #[serde(default)]
+    pub grace_period_secs: Option<u64>,
+}
+
+/// Response to a [`RotateKeyRequest`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RotateKeyResponse {
+    /// Named key that was rotated
+    pub key_id: String,
+    /// Epoch of the new key
+    pub epoch: u64,
+    /// New public key g^k serialized
+    pub public_key: Vec<u8>,
+    /// Unix time at which the previous key stops being accepted
+    pub previous_expires_at: u64,
+    /// Attestation over the new public key
+    pub attestation: AttestationDocument,
+}
+
+/// AWS credentials for KMS calls made from inside the enclave. The enclave
+/// has no instance role of its own, so the parent passes them in.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct KmsConfig {
+    /// AWS region of the KMS key
+    pub region: String,
+    /// AWS access key id
+    pub access_key_id: String,
+    /// AWS secret access key
+    pub secret_access_key: String,
+    /// Session token for temporary credentials
+    #[serde(default)]
+    pub session_token: Option<String>,
+    /// vsock port of the parent's `vsock-proxy` to the KMS endpoint
+    pub proxy_port: u32,
+}
+
+impl std::fmt::Debug for KmsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KmsConfig")
+            .field("region", &self.region)
+            .field("access_key_id", &self.access_key_id)
+            .field("proxy_port", &self.proxy_port)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for KmsConfig {
+    fn drop(&mut self) {
+        self.secret_access_key.zeroize();
+        self.session_token.zeroize();
+    }
+}
+
+/// Every enclave key, sealed under a KMS data key
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyBackup {
+    /// KMS ciphertext blob of the data key
+    pub encrypted_data_key: Vec<u8>,
+    /// Nonce used to seal `ciphertext`
+    pub nonce: Vec<u8>,
+    /// Sealed key material, see [`envelope::seal`]
+    pub ciphertext: Vec<u8>,
+    /// Whether the data key comes from the local mock KMS
+    pub is_mock: bool,
+}
+
+/// Admin request: export every key wrapped under a KMS data key
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ExportKeysRequest {
+    /// Credentials for the KMS call
+    pub kms: KmsConfig,
+    /// KMS key (id, ARN or alias) that wraps the data key: empty for the
+    /// enclave's own, which is the only one it accepts once it has one
+    pub kms_key_id: String,
+}
+
+/// Response to an [`ExportKeysRequest`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExportKeysResponse {
+    /// Sealed keys, to be stored by the operator
+    pub backup: KeyBackup,
+}
+
+/// Admin request: replace every key with the contents of a backup. KMS only
+/// releases the data key to an enclave whose attestation satisfies the key
+/// policy.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ImportKeysRequest {
+    /// Credentials for the KMS call
+    pub kms: KmsConfig,
+    /// Backup produced by an [`ExportKeysRequest`]
+    pub backup: KeyBackup,
+}
+
+/// Public part of one restored key
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PublicKeyInfo {
+    /// Ciphersuite of the key
+    pub ciphersuite: CiphersuiteId,
+    /// Name of the key
+    pub key_id: String,
+    /// Current epoch of the key
+    pub epoch: u64,
+    /// Current public key g^k serialized
+    pub public_key: Vec<u8>,
+}
+
+/// Response to an [`ImportKeysRequest`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImportKeysResponse {
+    /// Restored keys, sorted by ciphersuite and key id
+    pub keys: Vec<PublicKeyInfo>,
+    /// Attestation over the JSON encoding of `keys`
+    pub attestation: AttestationDocument,
+}
+
+/// Admin request: derive every key from a seed from now on. The seed arrives
+/// encrypted under KMS, so it is only ever in plaintext inside an enclave
+/// that KMS released it to.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ProvisionSeedRequest {
+    /// Credentials for the KMS call
+    pub kms: KmsConfig,
+    /// KMS ciphertext blob of the seed
+    pub encrypted_seed: Vec<u8>,
+}
+
+/// Response to a [`ProvisionSeedRequest`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProvisionSeedResponse {
+    /// Keys derived from the seed, sorted by ciphersuite and key id
+    pub keys: Vec<PublicKeyInfo>,
+    /// Attestation over the JSON encoding of `keys`
+    pub attestation: AttestationDocument,
+}
+
+/// Admin request, first step of injecting an existing secret key: the
+/// enclave creates an ephemeral transport key for installing a key as
+/// `key_id` at `epoch`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BeginKeyInjectionRequest {
+    /// Ciphersuite of the key to inject
+    pub ciphersuite: CiphersuiteId,
+    /// Named key to install the injected key as
+    pub key_id: String,
+    /// Epoch to install the injected key under
+    pub epoch: u64,
+}
+
+/// Response to a [`BeginKeyInjectionRequest`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BeginKeyInjectionResponse {
+    /// Transport public key to seal the secret key to, see [`transport`]
+    pub transport_public_key: Vec<u8>,
+    /// Attestation over [`key_injection_binding`] of the transport key
+    pub attestation: AttestationDocument,
+}
+
+/// Admin request, second step of injecting a secret key: the serialized
+/// scalar sealed to the transport key of the preceding
+/// [`BeginKeyInjectionRequest`], with [`key_injection_binding`] of the empty
+/// string as associated data. Each transport key opens at most one request.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct InjectKeyRequest {
+    /// Ciphersuite, key id and epoch, matching the preceding
+    /// [`BeginKeyInjectionRequest`]
+    pub ciphersuite: CiphersuiteId,
+    pub key_id: String,
+    pub epoch: u64,
+    /// Sender's ephemeral public key
+    pub sender_public_key: Vec<u8>,
+    /// Nonce of the sealed secret key
+    pub nonce: Vec<u8>,
+    /// Sealed secret key, `ciphertext || tag`
+    pub ciphertext: Vec<u8>,
+}
+
+/// Response to an [`InjectKeyRequest`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InjectKeyResponse {
+    /// Named key the injected key was installed as
+    pub key_id: String,
+    /// Epoch of the injected key
+    pub epoch: u64,
+    /// Public key g^k of the injected key
+    pub public_key: Vec<u8>,
+    /// Attestation over [`attested_user_data`] of the public key
+    pub attestation: AttestationDocument,
+}
+
+/// Response to [`Message::BeginReplication`], sent to the standby: the
+/// transport key the primary seals its keys to
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BeginReplicationResponse {
+    /// Transport public key (P-256) to seal the keys to, see [`transport`]
+    pub transport_public_key: Vec<u8>,
+    /// Attestation over [`replication_binding`] of [`REPLICA_STANDBY_LABEL`]
+    /// and the transport key
+    pub attestation: AttestationDocument,
+}
+
+/// Request to the primary to seal every key to the transport key of a
+/// standby. The primary first checks that the standby's attestation
+/// measures the same enclave image as its own.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ExportReplicaRequest {
+    /// The standby's [`BeginReplicationResponse`]
+    pub transport_public_key: Vec<u8>,
+    pub attestation: AttestationDocument,
+    /// Public key of the standby's mock attestations, accepted in local
+    /// mode only
+    #[serde(default)]
+    pub peer_mock_signing_key: Option<Vec<u8>>,
+}
+
+/// Response to an [`ExportReplicaRequest`]: the keys sealed to the standby
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExportReplicaResponse {
+    /// Public part of every sealed key, sorted by ciphersuite and key id
+    pub keys: Vec<PublicKeyInfo>,
+    /// Sender's ephemeral public key
+    pub sender_public_key: Vec<u8>,
+    /// Nonce of the sealed keys
+    pub nonce: Vec<u8>,
+    /// Sealed keys, `ciphertext || tag`
+    pub ciphertext: Vec<u8>,
+    /// Attestation over [`replication_binding`] of [`REPLICA_PRIMARY_LABEL`],
+    /// the transport key, the sender key and the JSON encoding of `keys`
+    pub attestation: AttestationDocument,
+}
+
+/// Request to the standby to replace every key with those a primary sealed
+/// to its transport key. The standby first checks that the primary's
+/// attestation measures the same enclave image as its own.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ImportReplicaRequest {
+    /// The primary's [`ExportReplicaResponse`]
+    pub replica: ExportReplicaResponse,
+    /// Public key of the primary's mock attestations, accepted in local
+    /// mode only
+    #[serde(default)]
+    pub peer_mock_signing_key: Option<Vec<u8>>,
+}
+
+/// Response to an [`ImportReplicaRequest`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImportReplicaResponse {
+    /// Replicated keys, sorted by ciphersuite and key id
+    pub keys: Vec<PublicKeyInfo>,
+    /// Attestation over the JSON encoding of `keys`
+    pub attestation: AttestationDocument,
+}
+
+/// Request for the current public key of a named key, without evaluating
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GetPublicKeyRequest {
+    /// Ciphersuite of the key
+    pub ciphersuite: CiphersuiteId,
+    /// Named key to look up
+    #[serde(default = "default_key_id")]
+    pub key_id: String,
+}
+
+/// Response to a [`GetPublicKeyRequest`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PublicKeyResponse {
+    /// Named key that was looked up
+    pub key_id: String,
+    /// Current epoch of the key
+    pub epoch: u64,
+    /// Public key g^k serialized
+    #[serde(with = "serde_bytes")]
+    pub public_key: Vec<u8>,
+    /// Public key g2^k serialized, for BN254 keys
+    #[serde(default, with = "serde_bytes")]
+    pub public_key_g2: Option<Vec<u8>>,
+    /// Attestation over [`attested_user_data`] of the public key, possibly
+    /// served from the enclave's attestation cache
+    pub attestation: AttestationDocument,
+}
+
+/// Request for a newly generated attestation of a named key, bound to a
+/// client nonce so it cannot be a replayed one
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GetAttestationRequest {
+    /// Ciphersuite of the key
+    pub ciphersuite: CiphersuiteId,
+    /// Named key to attest
+    #[serde(default = "default_key_id")]
+    pub key_id: String,
+    /// Client-chosen nonce of at most [`MAX_CLIENT_NONCE_LEN`] bytes
+    #[serde(with = "serde_bytes")]
+    pub nonce: Vec<u8>,
+}
+
+/// Response to a [`GetAttestationRequest`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AttestationResponse {
+    /// Named key that was attested
+    pub key_id: String,
+    /// Current epoch of the key
+    pub epoch: u64,
+    /// Public key g^k serialized
+    #[serde(with = "serde_bytes")]
     pub public_key: Vec<u8>,
-    /// Attestation document (NSM attestation in Nitro, mock in local)
+    /// Attestation over [`key_attestation_binding`] of the public key and
+    /// nonce
     pub attestation: AttestationDocument,
 }
 
+/// Response to [`Message::Health`]. It is not attested: it only tells that
+/// the enclave is up and serving, and whether it can attest. Enclaves before
+/// the readiness fields leave them out; they read as an enclave of unknown
+/// uptime whose attestations have not failed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HealthResponse {
+    /// Enclave clock, unix seconds
+    pub timestamp: u64,
+    /// Number of named keys over every ciphersuite
+    pub key_count: usize,
+    /// Seconds since the enclave started
+    #[serde(default)]
+    pub uptime_secs: u64,
+    /// Current epoch of the default key of every ciphersuite
+    #[serde(default, deserialize_with = "bounded::list::<_, _, 8>")]
+    pub epochs: Vec<SuiteEpoch>,
+    /// Outcome of the enclave's latest attestations
+    #[serde(default)]
+    pub attestation: AttestationHealth,
+}
+
+impl HealthResponse {
+    /// Whether the enclave can serve evaluations: it is up, and the last
+    /// attestation it made succeeded
+    pub fn is_ready(&self) -> bool {
+        !self.attestation.failing
+    }
+}
+
+/// Epoch of the default key of a ciphersuite
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuiteEpoch {
+    pub ciphersuite: CiphersuiteId,
+    pub epoch: u64,
+}
+
+/// State of the attestation backend, the NSM or the mock key, as seen by
+/// the attestations the enclave made
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct AttestationHealth {
+    /// Whether the latest attestation failed
+    pub failing: bool,
+    /// Enclave clock at the latest attestation that succeeded
+    pub last_success: Option<u64>,
+    /// Error of the latest attestation that failed
+    #[serde(default, deserialize_with = "bounded::option_string::<_, 1024>")]
+    pub last_error: Option<String>,
+}
+
+/// Stage of serving an evaluation request that the enclave times
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Decoding the request and its blinded points
+    Deserialize,
+    /// Multiplying the points by the key, and the DLEQ proof
+    Evaluate,
+    /// Attesting the evaluation, or finding a cached attestation
+    Attest,
+    /// Encoding the evaluated points and the response, signing and sealing it
+    Serialize,
+    /// From the request frame read in full to the response frame written
+    Total,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] =
+        [Stage::Deserialize, Stage::Evaluate, Stage::Attest, Stage::Serialize, Stage::Total];
+}
+
+/// Number of buckets of a [`StageStats`] histogram
+pub const LATENCY_BUCKETS: usize = 32;
+
+/// Histogram of the time one [`Stage`] took over every evaluation request
+/// since the enclave started. `buckets[i]` counts the durations of at least
+/// 2^(i-1) and under 2^i microseconds; the last bucket also counts every
+/// longer one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StageStats {
+    pub stage: Stage,
+    pub count: u64,
+    pub total_micros: u64,
+    pub max_micros: u64,
+    #[serde(deserialize_with = "bounded::list::<_, _, LATENCY_BUCKETS>")]
+    pub buckets: Vec<u64>,
+}
+
+impl StageStats {
+    /// Bucket of a duration of `micros`
+    pub fn bucket(micros: u64) -> usize {
+        (u64::BITS - micros.leading_zeros()).min(LATENCY_BUCKETS as u32 - 1) as usize
+    }
+
+    /// Mean duration in microseconds, 0 before the first request
+    pub fn mean_micros(&self) -> u64 {
+        self.total_micros.checked_div(self.count).unwrap_or(0)
+    }
+
+    /// Upper bound of the `q` quantile in microseconds: the end of the bucket
+    /// it falls in, or the maximum if that is lower. 0 before the first
+    /// request.
+    pub fn quantile_micros(&self, q: f64) -> u64 {
+        let rank = (q.clamp(0.0, 1.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return (1u64 << i).min(self.max_micros);
+            }
+        }
+        self.max_micros
+    }
+}
+
+/// Response to [`Message::GetStats`]: counters and stage latencies since the
+/// enclave started. Like [`HealthResponse`] it is not attested.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StatsResponse {
+    /// Seconds since the enclave started
+    pub uptime_secs: u64,
+    /// Requests answered, refused ones included
+    pub requests: u64,
+    /// Requests answered with an error
+    pub errors: u64,
+    /// Evaluation and batch evaluation requests served, refused ones left out
+    pub evaluation_requests: u64,
+    /// Points evaluated, over every evaluation request
+    pub evaluations: u64,
+    /// One histogram per [`Stage`], in the order of [`Stage::ALL`]
+    #[serde(deserialize_with = "bounded::list::<_, _, 8>")]
+    pub stages: Vec<StageStats>,
+}
+
+/// Response to [`Message::GetSelfTest`]: the known-answer tests the enclave
+/// ran on boot, see [`selftest`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SelfTestResponse {
+    pub report: selftest::SelfTestReport,
+    /// Attestation over [`selftest::report_binding`] of the report, the
+    /// first the enclave made
+    pub attestation: AttestationDocument,
+}
+
+/// Request for the records of the enclave's audit log from sequence number
+/// `from` on, see [`audit`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GetAuditLogRequest {
+    pub from: u64,
+}
+
+/// Response to a [`GetAuditLogRequest`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AuditLogResponse {
+    /// Head of the chain before record `from`, [`audit::GENESIS`] for the
+    /// first record
+    #[serde(
+        serialize_with = "serde_bytes::serialize",
+        deserialize_with = "bounded::bytes::<_, 32>"
+    )]
+    pub previous_head: Vec<u8>,
+    /// Records from `from` on, at most [`MAX_AUDIT_RECORDS_PER_RESPONSE`]
+    #[serde(deserialize_with = "bounded::list::<_, _, MAX_AUDIT_RECORDS_PER_RESPONSE>")]
+    pub records: Vec<audit::AuditRecord>,
+    /// Sequence number of the next record the enclave will append
+    pub next_sequence: u64,
+    /// Latest attested head, absent before the first
+    pub checkpoint: Option<audit::AuditCheckpoint>,
+}
+
+/// Opening message of a connection: the protocol versions the client speaks
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HelloRequest {
+    pub versions: Vec<u32>,
+    /// Encoding of the blinded and evaluated points of the connection. Left
+    /// out when compressed, as enclaves before it refuse the field.
+    #[serde(default, skip_serializing_if = "PointEncoding::is_compressed")]
+    pub point_encoding: PointEncoding,
+    /// The client tags evaluations with [`OprfRequest::request_id`]. Left out
+    /// when false, as enclaves before it refuse the field.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub request_ids: bool,
+}
+
+/// Response to a [`HelloRequest`]: the version used for the rest of the
+/// connection
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HelloResponse {
+    pub version: u32,
+    /// Static key of the secure channel, from version 3 on, see
+    /// [`channel`]
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "serde_bytes")]
+    pub channel_key: Option<Vec<u8>>,
+    /// Session key signing every later response, from version 4 on, see
+    /// [`signing`]
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "serde_bytes")]
+    pub signing_key: Option<Vec<u8>>,
+    /// Attestation over [`channel::channel_binding`] of `channel_key` and
+    /// the offered versions, or from version 4 on over
+    /// [`signing::session_binding`] of both keys and the offered versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<AttestationDocument>,
+    /// Point encoding the enclave agreed to. Enclaves before it leave it out
+    /// and keep to compressed points.
+    #[serde(default, skip_serializing_if = "PointEncoding::is_compressed")]
+    pub point_encoding: PointEncoding,
+    /// The enclave accepts request ids. Enclaves before them leave it out.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub request_ids: bool,
+}
+
+/// First message of the secure channel handshake, sent in the clear right
+/// after the [`HelloRequest`] on a version 3 connection
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HandshakeRequest {
+    #[serde(with = "serde_bytes")]
+    pub message: Vec<u8>,
+}
+
+/// The enclave's handshake reply; every later frame is sealed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HandshakeResponse {
+    #[serde(with = "serde_bytes")]
+    pub message: Vec<u8>,
+}
+
+/// Any request the enclave accepts, tagged with its operation
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Message {
+    Hello(HelloRequest),
+    Handshake(HandshakeRequest),
+    Evaluate(OprfRequest),
+    BatchEvaluate(BatchOprfRequest),
+    GetPublicKey(GetPublicKeyRequest),
+    GetAttestation(GetAttestationRequest),
+    Health,
+    RotateKey(RotateKeyRequest),
+    ExportKeys(ExportKeysRequest),
+    ImportKeys(ImportKeysRequest),
+    ProvisionSeed(ProvisionSeedRequest),
+    BeginKeyInjection(BeginKeyInjectionRequest),
+    InjectKey(InjectKeyRequest),
+    BeginReplication,
+    ExportReplica(ExportReplicaRequest),
+    ImportReplica(ImportReplicaRequest),
+    GetAuditLog(GetAuditLogRequest),
+    GetStats,
+    GetSelfTest,
+}
+
+impl Message {
+    /// Ciphersuite whose keys handle the message, `None` for messages that
+    /// cover every key
+    pub fn ciphersuite(&self) -> Option<CiphersuiteId> {
+        match self {
+            Message::Evaluate(request) => Some(request.ciphersuite),
+            Message::BatchEvaluate(request) => Some(request.ciphersuite),
+            Message::GetPublicKey(request) => Some(request.ciphersuite),
+            Message::GetAttestation(request) => Some(request.ciphersuite),
+            Message::RotateKey(request) => Some(request.ciphersuite),
+            Message::BeginKeyInjection(request) => Some(request.ciphersuite),
+            Message::InjectKey(request) => Some(request.ciphersuite),
+            Message::Hello(_)
+            | Message::Handshake(_)
+            | Message::Health
+            | Message::ExportKeys(_)
+            | Message::ImportKeys(_)
+            | Message::ProvisionSeed(_)
+            | Message::BeginReplication
+            | Message::ExportReplica(_)
+            | Message::ImportReplica(_)
+            | Message::GetAuditLog(_)
+            | Message::GetStats
+            | Message::GetSelfTest => None,
+        }
+    }
+
+    /// Named key that handles the message, `None` for messages that cover
+    /// every key
+    pub fn key_id(&self) -> Option<&str> {
+        match self {
+            Message::Evaluate(request) => Some(&request.key_id),
+            Message::BatchEvaluate(request) => Some(&request.key_id),
+            Message::GetPublicKey(request) => Some(&request.key_id),
+            Message::GetAttestation(request) => Some(&request.key_id),
+            Message::RotateKey(request) => Some(&request.key_id),
+            Message::BeginKeyInjection(request) => Some(&request.key_id),
+            Message::InjectKey(request) => Some(&request.key_id),
+            Message::Hello(_)
+            | Message::Handshake(_)
+            | Message::Health
+            | Message::ExportKeys(_)
+            | Message::ImportKeys(_)
+            | Message::ProvisionSeed(_)
+            | Message::BeginReplication
+            | Message::ExportReplica(_)
+            | Message::ImportReplica(_)
+            | Message::GetAuditLog(_)
+            | Message::GetStats
+            | Message::GetSelfTest => None,
+        }
+    }
+
+    /// Whether the message manages keys: rotates, backs up, restores,
+    /// provisions, injects or replicates them. An enclave with an admin port
+    /// only accepts these there, see [`crate::admin`].
+    pub fn is_key_management(&self) -> bool {
+        matches!(
+            self,
+            Message::RotateKey(_)
+                | Message::ExportKeys(_)
+                | Message::ImportKeys(_)
+                | Message::ProvisionSeed(_)
+                | Message::BeginKeyInjection(_)
+                | Message::InjectKey(_)
+                | Message::BeginReplication
+                | Message::ExportReplica(_)
+                | Message::ImportReplica(_)
+        )
+    }
+
+    /// Client an evaluation is made for, see [`OprfRequest::client_id`]
+    pub fn client_id(&self) -> Option<&str> {
+        match self {
+            Message::Evaluate(request) => request.client_id.as_deref(),
+            Message::BatchEvaluate(request) => request.client_id.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Id of an evaluation, see [`OprfRequest::request_id`]
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            Message::Evaluate(request) => request.request_id.as_deref(),
+            Message::BatchEvaluate(request) => request.request_id.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// Response to a [`Message`], encoded as the inner response
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum EnclaveResponse {
+    Hello(HelloResponse),
+    Handshake(HandshakeResponse),
+    Evaluate(OprfResponse),
+    BatchEvaluate(BatchOprfResponse),
+    PublicKey(PublicKeyResponse),
+    Attestation(AttestationResponse),
+    Health(HealthResponse),
+    RotateKey(RotateKeyResponse),
+    ExportKeys(ExportKeysResponse),
+    ImportKeys(ImportKeysResponse),
+    ProvisionSeed(ProvisionSeedResponse),
+    BeginKeyInjection(BeginKeyInjectionResponse),
+    InjectKey(InjectKeyResponse),
+    BeginReplication(BeginReplicationResponse),
+    ExportReplica(ExportReplicaResponse),
+    ImportReplica(ImportReplicaResponse),
+    AuditLog(AuditLogResponse),
+    Stats(StatsResponse),
+    SelfTest(SelfTestResponse),
+}
+
+/// Class of a request the enclave refused
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// A blinded query is not a valid point of the ciphersuite's group
+    BadPoint,
+    /// `query_hash` does not match the blinded queries
+    HashMismatch,
+    /// The key has used its evaluation budget
+    RateLimited,
+    /// The request is malformed or names an unknown key, epoch or version
+    BadRequest,
+    /// The enclave failed to serve a valid request
+    InternalError,
+    /// An admin command is not signed by the operator key, see [`admin`]
+    Unauthorized,
+}
+
+/// Error returned in place of a response, from protocol version 2 on
+#[derive(Serialize, Deserialize, Error, Debug, Clone, PartialEq, Eq)]
+#[error("Enclave refused the request ({code:?}): {message}")]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ErrorResponse {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Errors not classified where they occur are internal
+impl From<String> for ErrorResponse {
+    fn from(message: String) -> Self {
+        Self::new(ErrorCode::InternalError, message)
+    }
+}
+
+/// Payload of a response frame from protocol version 2 on, encoded as
+/// `{"Ok": response}` or `{"Err": ErrorResponse}`. Version 1 responses are
+/// the bare response, and an error closes the connection instead.
+pub type OprfResult<T> = Result<T, ErrorResponse>;
+
 /// Attestation document structure
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct AttestationDocument {
     /// Whether this is a real Nitro attestation or mock
     pub is_mock: bool,
-    /// The attestation data (CBOR encoded NSM doc in real mode)
+    /// The attestation data (CBOR encoded NSM doc in real mode, a JSON
+    /// [`SignedMockDocument`] in local mode)
+    #[serde(
+        serialize_with = "serde_bytes::serialize",
+        deserialize_with = "bounded::bytes::<_, MAX_ATTESTATION_DOC_LEN>"
+    )]
     pub document: Vec<u8>,
-    /// PCR values (Platform Configuration Registers)
+    /// PCR values (Platform Configuration Registers), hex-encoded
+    #[serde(deserialize_with = "bounded::option_strings::<_, MAX_PCRS, MAX_PCR_HEX_LEN>")]
     pub pcrs: Option<Vec<String>>,
     /// User data included in attestation
+    #[serde(
+        serialize_with = "serde_bytes::serialize",
+        deserialize_with = "bounded::bytes::<_, MAX_ATTESTATION_USER_DATA_LEN>"
+    )]
     pub user_data: Vec<u8>,
 }
 
+/// Local-mode attestation document: a JSON payload signed with the
+/// enclave's ephemeral Ed25519 mock attestation key, whose public key the
+/// parent is given out of band. The payload mirrors the NSM document fields
+/// the parent checks: `module_id`, `timestamp` (unix seconds), `pcrs` (hex)
+/// and `user_data` (hex of [`nsm_user_data`]).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignedMockDocument {
+    /// Payload JSON, kept as the exact signed string
+    pub payload: String,
+    /// Ed25519 signature over `payload`, hex-encoded
+    pub signature: String,
+}
+
+impl SignedMockDocument {
+    /// Sign `payload` with the mock attestation key
+    pub fn sign(key: &ed25519::SigningKey, payload: &serde_json::Value) -> Self {
+        let payload = payload.to_string();
+        let signature = hex::encode(key.sign(payload.as_bytes()));
+        Self { payload, signature }
+    }
+
+    /// Verify the signature with `key` and return the payload
+    pub fn verify(
+        &self,
+        key: &ed25519::VerifyingKey,
+    ) -> Result<serde_json::Value, AttestationError> {
+        let signature = hex::decode(&self.signature)
+            .map_err(|e| AttestationError::MalformedDocument(e.to_string()))?;
+        key.verify(self.payload.as_bytes(), &signature)
+            .map_err(|e| AttestationError::SignatureInvalid(e.to_string()))?;
+        serde_json::from_str(&self.payload)
+            .map_err(|e| AttestationError::MalformedDocument(e.to_string()))
+    }
+}
+
 /// Serialize a G1 point to bytes
 pub fn serialize_g1(point: &G1Projective) -> Result<Vec<u8>, OprfError> {
-    let affine = point.into_affine();
-    let mut bytes = Vec::new();
-    affine
-        .serialize_compressed(&mut bytes)
-        .map_err(|e| OprfError::Serialization(e.to_string()))?;
-    Ok(bytes)
+    Bn254Sha256::serialize_element(point)
 }
 
 /// Deserialize bytes to a G1 point
 pub fn deserialize_g1(bytes: &[u8]) -> Result<G1Projective, OprfError> {
-    let affine = G1Affine::deserialize_compressed(bytes)
-        .map_err(|e| OprfError::Deserialization(e.to_string()))?;
-    Ok(affine. into_group())
+    Bn254Sha256::deserialize_element(bytes)
 }
 
 /// Serialize a scalar field element to bytes
 pub fn serialize_fr(scalar: &Fr) -> Result<Vec<u8>, OprfError> {
-    let mut bytes = Vec::new();
-    scalar
-        .serialize_compressed(&mut bytes)
-        .map_err(|e| OprfError::Serialization(e.to_string()))?;
-    Ok(bytes)
+    Bn254Sha256::serialize_scalar(scalar)
 }
 
 /// Deserialize bytes to a scalar field element
 pub fn deserialize_fr(bytes: &[u8]) -> Result<Fr, OprfError> {
-    Fr::deserialize_compressed(bytes). map_err(|e| OprfError::Deserialization(e.to_string()))
+    Bn254Sha256::deserialize_scalar(bytes)
+}
+
+/// Maximum number of queries in a batch request
+pub const MAX_BATCH_SIZE: usize = 1024;
+
+/// Maximum number of audit records in an [`AuditLogResponse`]
+pub const MAX_AUDIT_RECORDS_PER_RESPONSE: usize = 1024;
+
+/// Maximum length of a serialized group element: an uncompressed BN254 G2
+/// point, the largest element of any ciphersuite
+pub const MAX_ELEMENT_LEN: usize = 128;
+
+/// Maximum length of a serialized DLEQ proof, two scalars
+pub const MAX_PROOF_LEN: usize = 64;
+
+/// PRF output size in bytes, a SHA-256 digest for every ciphersuite
+pub const OUTPUT_LEN: usize = 32;
+
+/// Maximum length of a query hash, hex-encoded SHA-256
+pub const MAX_QUERY_HASH_LEN: usize = 64;
+
+/// Maximum length of an attestation document; NSM documents with their
+/// certificate chain take about 5 KiB
+pub const MAX_ATTESTATION_DOC_LEN: usize = 16 * 1024;
+
+/// Maximum length of attested user data, room for the public keys of every
+/// key of an enclave
+pub const MAX_ATTESTATION_USER_DATA_LEN: usize = 256 * 1024;
+
+/// Maximum number of PCRs in an attestation document, as many as the NSM has
+pub const MAX_PCRS: usize = 32;
+
+/// Maximum length of a hex-encoded PCR, a SHA-384 digest
+pub const MAX_PCR_HEX_LEN: usize = 96;
+
+/// Key used by requests that do not name one
+pub const DEFAULT_KEY_ID: &str = "default";
+
+/// Maximum length of a key id
+pub const MAX_KEY_ID_LEN: usize = 64;
+
+fn default_key_id() -> String {
+    DEFAULT_KEY_ID.to_string()
+}
+
+/// Check that a key id is 1..=64 characters of `[A-Za-z0-9._-]`
+pub fn validate_key_id(key_id: &str) -> Result<(), OprfError> {
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-');
+    if key_id.is_empty() || key_id.len() > MAX_KEY_ID_LEN || !key_id.chars().all(valid_char) {
+        return Err(OprfError::InvalidKeyId(key_id.to_string()));
+    }
+    Ok(())
+}
+
+/// Attestation user data binding `data` to the key that produced it:
+/// `I2OSP(len(key_id), 2) || key_id || data`
+pub fn attested_user_data(key_id: &str, data: &[u8]) -> Vec<u8> {
+    let mut user_data = Vec::with_capacity(2 + key_id.len() + data.len());
+    user_data.extend_from_slice(&(key_id.len() as u16).to_be_bytes());
+    user_data.extend_from_slice(key_id.as_bytes());
+    user_data.extend_from_slice(data);
+    user_data
+}
+
+/// Binding of key injection data to its target key:
+/// `attested_user_data(key_id, I2OSP(epoch, 8) || data)`
+pub fn key_injection_binding(key_id: &str, epoch: u64, data: &[u8]) -> Vec<u8> {
+    let mut bound = epoch.to_be_bytes().to_vec();
+    bound.extend_from_slice(data);
+    attested_user_data(key_id, &bound)
+}
+
+/// Label of the standby's attestation of its replication transport key
+pub const REPLICA_STANDBY_LABEL: &[u8] = b"oprf-replica-standby-v1";
+
+/// Label of the primary's attestation of the keys it sealed to a standby,
+/// also the associated data of the sealed keys
+pub const REPLICA_PRIMARY_LABEL: &[u8] = b"oprf-replica-primary-v1";
+
+/// Attestation user data of a replication step:
+/// `label || I2OSP(len(part), 4) || part` for each part
+pub fn replication_binding(label: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+    let mut user_data = label.to_vec();
+    for part in parts {
+        user_data.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        user_data.extend_from_slice(part);
+    }
+    user_data
+}
+
+/// Binding of a [`GetAttestationRequest`] nonce to the attested key:
+/// `attested_user_data(key_id, public_key || nonce)`
+pub fn key_attestation_binding(key_id: &str, public_key: &[u8], nonce: &[u8]) -> Vec<u8> {
+    attested_user_data(key_id, &[public_key, nonce].concat())
+}
+
+/// Wire protocol version of this build. Version 2 wraps responses in
+/// [`OprfResult`]; version 3 runs the connection in an attested
+/// [`channel`]; version 4 adds [`signing`] of every response.
+pub const PROTOCOL_VERSION: u32 = 4;
+
+/// Protocol versions this build speaks, see [`negotiate_version`]
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[u32] = &[1, 2, 3, PROTOCOL_VERSION];
+
+/// Protocol version of a connection that opens without a [`HelloRequest`],
+/// as parents that predate the handshake do
+pub const DEFAULT_PROTOCOL_VERSION: u32 = 1;
+
+/// Highest version offered by the client that this build speaks, `None` if
+/// there is none
+pub fn negotiate_version(offered: &[u32]) -> Option<u32> {
+    offered
+        .iter()
+        .copied()
+        .filter(|version| SUPPORTED_PROTOCOL_VERSIONS.contains(version))
+        .max()
+}
+
+/// Layout version of [`EvaluationUserData`]
+pub const USER_DATA_VERSION: u64 = 2;
+
+/// Maximum length of a client nonce in bytes
+pub const MAX_CLIENT_NONCE_LEN: usize = 64;
+
+/// Maximum length of a client id in bytes
+pub const MAX_CLIENT_ID_LEN: usize = 64;
+
+/// Maximum length of a request id in bytes
+pub const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// Check that a request id is 1..=64 characters of `[A-Za-z0-9._:-]`, safe
+/// to tag log lines with
+pub fn validate_request_id(request_id: &str) -> Result<(), OprfError> {
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | ':' | '-');
+    if request_id.is_empty()
+        || request_id.len() > MAX_REQUEST_ID_LEN
+        || !request_id.chars().all(valid_char)
+    {
+        return Err(OprfError::InvalidRequestId(request_id.to_string()));
+    }
+    Ok(())
+}
+
+/// Prefix of the log lines about an evaluation, `[<request id>] ` if it has
+/// one
+pub fn request_tag(request_id: Option<&str>) -> String {
+    request_id.map(|id| format!("[{}] ", id)).unwrap_or_default()
+}
+
+/// Maximum length of the user data of an NSM attestation
+pub const MAX_NSM_USER_DATA_LEN: usize = 512;
+
+/// User data embedded in an NSM attestation: `user_data` itself when it fits,
+/// its SHA-256 digest otherwise
+pub fn nsm_user_data(user_data: &[u8]) -> Vec<u8> {
+    if user_data.len() <= MAX_NSM_USER_DATA_LEN {
+        user_data.to_vec()
+    } else {
+        Sha256::digest(user_data).to_vec()
+    }
+}
+
+/// Attested user data of an evaluation, encoded as a CBOR map with one text
+/// key per field so that verifiers check each field on its own:
+///
+/// ```text
+/// { "version": 2, "protocol_version": uint, "key_id": tstr, "epoch": uint,
+///   "public_key": bstr, "evaluated_hash": bstr .size 32,
+///   "client_nonce": bstr / null, ? "request_id": tstr }
+/// ```
+///
+/// `request_id` is only present when the request had one, so the user data
+/// of other requests reads as before.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvaluationUserData {
+    /// Layout version, [`USER_DATA_VERSION`]
+    pub version: u64,
+    /// Protocol version negotiated on the connection that carried the request
+    pub protocol_version: u32,
+    /// Named key that evaluated the queries
+    pub key_id: String,
+    /// Epoch of that key
+    pub epoch: u64,
+    /// Public key g^k serialized
+    pub public_key: Vec<u8>,
+    /// SHA-256 of the concatenated evaluated points
+    pub evaluated_hash: [u8; 32],
+    /// Nonce sent by the client with the request
+    pub client_nonce: Option<Vec<u8>>,
+    /// Id of the request, see [`OprfRequest::request_id`]
+    pub request_id: Option<String>,
+}
+
+impl EvaluationUserData {
+    /// User data of the current version for `evaluated_points`
+    pub fn new(
+        protocol_version: u32,
+        key_id: &str,
+        epoch: u64,
+        public_key: &[u8],
+        evaluated_points: &[Vec<u8>],
+        client_nonce: Option<&[u8]>,
+        request_id: Option<&str>,
+    ) -> Self {
+        Self {
+            version: USER_DATA_VERSION,
+            protocol_version,
+            key_id: key_id.to_string(),
+            epoch,
+            public_key: public_key.to_vec(),
+            evaluated_hash: Sha256::digest(evaluated_points.concat()).into(),
+            client_nonce: client_nonce.map(<[u8]>::to_vec),
+            request_id: request_id.map(str::to_string),
+        }
+    }
+
+    /// Deterministic CBOR encoding, map keys in canonical order
+    pub fn to_cbor(&self) -> Vec<u8> {
+        let field = |name: &str| Value::Text(name.to_string());
+        let mut map = BTreeMap::from([
+            (field("version"), Value::Integer(self.version.into())),
+            (field("protocol_version"), Value::Integer(self.protocol_version.into())),
+            (field("key_id"), Value::Text(self.key_id.clone())),
+            (field("epoch"), Value::Integer(self.epoch.into())),
+            (field("public_key"), Value::Bytes(self.public_key.clone())),
+            (field("evaluated_hash"), Value::Bytes(self.evaluated_hash.to_vec())),
+            (
+                field("client_nonce"),
+                self.client_nonce.clone().map_or(Value::Null, Value::Bytes),
+            ),
+        ]);
+        if let Some(request_id) = &self.request_id {
+            map.insert(field("request_id"), Value::Text(request_id.clone()));
+        }
+        serde_cbor::to_vec(&Value::Map(map)).expect("CBOR encoding of a map cannot fail")
+    }
+
+    /// Decode user data, rejecting other versions, missing or unknown fields
+    /// and fields of the wrong type
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self, OprfError> {
+        let invalid = |what: String| OprfError::InvalidUserData(what);
+        let mut map = match serde_cbor::from_slice(bytes) {
+            Ok(Value::Map(map)) => map,
+            Ok(_) => return Err(invalid("not a CBOR map".to_string())),
+            Err(e) => return Err(invalid(e.to_string())),
+        };
+        let mut take = |name: &str| {
+            map.remove(&Value::Text(name.to_string()))
+                .ok_or_else(|| invalid(format!("missing {}", name)))
+        };
+        let uint = |name: &str, value: Value| match value {
+            Value::Integer(n) => u64::try_from(n).map_err(|_| invalid(format!("invalid {}", name))),
+            _ => Err(invalid(format!("{} is not an integer", name))),
+        };
+        let bytes = |name: &str, value: Value| match value {
+            Value::Bytes(bytes) => Ok(bytes),
+            _ => Err(invalid(format!("{} is not a byte string", name))),
+        };
+
+        let version = uint("version", take("version")?)?;
+        if version != USER_DATA_VERSION {
+            return Err(invalid(format!("unsupported version {}", version)));
+        }
+        let protocol_version = uint("protocol_version", take("protocol_version")?)?
+            .try_into()
+            .map_err(|_| invalid("invalid protocol_version".to_string()))?;
+        let key_id = match take("key_id")? {
+            Value::Text(key_id) => key_id,
+            _ => return Err(invalid("key_id is not a text string".to_string())),
+        };
+        let epoch = uint("epoch", take("epoch")?)?;
+        let public_key = bytes("public_key", take("public_key")?)?;
+        let evaluated_hash = bytes("evaluated_hash", take("evaluated_hash")?)?
+            .try_into()
+            .map_err(|_| invalid("evaluated_hash is not 32 bytes".to_string()))?;
+        let client_nonce = match take("client_nonce")? {
+            Value::Null => None,
+            nonce => Some(bytes("client_nonce", nonce)?),
+        };
+        let request_id = match map.remove(&Value::Text("request_id".to_string())) {
+            None => None,
+            Some(Value::Text(request_id)) => Some(request_id),
+            Some(_) => return Err(invalid("request_id is not a text string".to_string())),
+        };
+        if !map.is_empty() {
+            return Err(invalid(format!("{} unknown fields", map.len())));
+        }
+
+        Ok(Self {
+            version,
+            protocol_version,
+            key_id,
+            epoch,
+            public_key,
+            evaluated_hash,
+            client_nonce,
+            request_id,
+        })
+    }
 }
 
 /// Compute SHA256 hash and return hex string
@@ -93,27 +1666,27 @@ pub fn sha256_hex(data: &[u8]) -> String {
 
 /// Get the generator of G1
 pub fn g1_generator() -> G1Projective {
-    G1Projective::generator()
+    Bn254Sha256::generator()
 }
 
 /// Sample a random scalar field element
 pub fn random_scalar<R: Rng>(rng: &mut R) -> Fr {
-    Fr::rand(rng)
+    Bn254Sha256::random_scalar(rng)
 }
 
 /// Compute g^scalar
 pub fn scalar_mul_generator(scalar: &Fr) -> G1Projective {
-    g1_generator() * scalar
+    Bn254Sha256::scalar_mul_generator(scalar)
 }
 
 /// Compute point^scalar
 pub fn scalar_mul(point: &G1Projective, scalar: &Fr) -> G1Projective {
-    *point * scalar
+    Bn254Sha256::scalar_mul(point, scalar)
 }
 
 /// Compute the multiplicative inverse of a scalar
 pub fn scalar_inverse(scalar: &Fr) -> Option<Fr> {
-    scalar.inverse()
+    Bn254Sha256::scalar_inverse(scalar)
 }
 
 /// Hash arbitrary data to a scalar field element
@@ -124,6 +1697,51 @@ pub fn hash_to_scalar(data: &[u8]) -> Fr {
     Fr::from_be_bytes_mod_order(&hash)
 }
 
+/// Hash an arbitrary input to a group element (RFC 9497 `HashToGroup`), with
+/// the DST `"HashToGroup-" || contextString` of `mode`: base and verifiable
+/// evaluations of the same input give different PRF outputs.
+pub fn hash_to_group<C: Ciphersuite>(input: &[u8], mode: OprfMode) -> C::Element {
+    let mut dst = b"HashToGroup-".to_vec();
+    dst.extend_from_slice(&context_string::<C>(mode));
+    C::hash_to_group(input, &dst)
+}
+
+/// Hash an arbitrary input to a G1 point, in base mode
+pub fn hash_to_g1(input: &[u8]) -> G1Projective {
+    hash_to_group::<Bn254Sha256>(input, OprfMode::Oprf)
+}
+
+/// Compute the final PRF output (RFC 9497 `Finalize`).
+///
+/// Hashes `I2OSP(len(input), 2) || input || I2OSP(len(element), 2) || element
+/// || "Finalize"` with the suite hash, giving an output of [`OUTPUT_LEN`] bytes
+/// that binds the unblinded element to the original input.
+pub fn finalize<C: Ciphersuite>(
+    input: &[u8],
+    unblinded_element: &C::Element,
+) -> Result<[u8; OUTPUT_LEN], OprfError> {
+    let element = C::serialize_element(unblinded_element)?;
+    let input_len = u16::try_from(input.len())
+        .map_err(|_| OprfError::Serialization("Input longer than 65535 bytes".to_string()))?;
+
+    let mut transcript = Vec::with_capacity(input.len() + element.len() + 12);
+    transcript.extend_from_slice(&input_len.to_be_bytes());
+    transcript.extend_from_slice(input);
+    transcript.extend_from_slice(&(element.len() as u16).to_be_bytes());
+    transcript.extend_from_slice(&element);
+    transcript.extend_from_slice(b"Finalize");
+    Ok(C::hash(&transcript))
+}
+
+/// RFC 9497 context string: `"OPRFV1-" || I2OSP(mode, 1) || "-" || identifier`
+pub fn context_string<C: Ciphersuite>(mode: OprfMode) -> Vec<u8> {
+    let mut context = b"OPRFV1-".to_vec();
+    context.push(mode.id());
+    context.push(b'-');
+    context.extend_from_slice(C::IDENTIFIER.as_bytes());
+    context
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -158,7 +1776,7 @@ mod tests {
         
         // Enclave secret key
         let k = random_scalar(&mut rng);
-        let pk = scalar_mul_generator(&k); // g^k
+        let _pk = scalar_mul_generator(&k); // g^k
         
         // Parent input and blinding
         let m = random_scalar(&mut rng);
@@ -179,4 +1797,1254 @@ mod tests {
         
         assert_eq!(unblinded, expected);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_hash_to_g1() {
+        let mut rng = test_rng();
+
+        let p1 = hash_to_g1(b"alice@example.com");
+        let p2 = hash_to_g1(b"alice@example.com");
+        let p3 = hash_to_g1(b"bob@example.com");
+        assert_eq!(p1, p2);
+        assert_ne!(p1, p3);
+        assert!(ark_ec::CurveGroup::into_affine(p1).is_on_curve());
+
+        // Blind with b, evaluate with k, unblind: H(x)^k
+        let k = random_scalar(&mut rng);
+        let b = random_scalar(&mut rng);
+        let evaluated = scalar_mul(&scalar_mul(&p1, &b), &k);
+        let unblinded = scalar_mul(&evaluated, &scalar_inverse(&b).unwrap());
+        assert_eq!(unblinded, scalar_mul(&p1, &k));
+    }
+
+    #[test]
+    fn test_finalize() {
+        let mut rng = test_rng();
+        let k = random_scalar(&mut rng);
+        let element = scalar_mul(&hash_to_g1(b"input"), &k);
+
+        let output = finalize::<Bn254Sha256>(b"input", &element).unwrap();
+        assert_eq!(output.len(), 32);
+        assert_eq!(output, finalize::<Bn254Sha256>(b"input", &element).unwrap());
+        assert_ne!(output, finalize::<Bn254Sha256>(b"other", &element).unwrap());
+    }
+
+    #[test]
+    fn test_stage_stats() {
+        assert_eq!(StageStats::bucket(0), 0);
+        assert_eq!(StageStats::bucket(1), 1);
+        assert_eq!(StageStats::bucket(3), 2);
+        assert_eq!(StageStats::bucket(1024), 11);
+        assert_eq!(StageStats::bucket(u64::MAX), LATENCY_BUCKETS - 1);
+
+        let mut stats = StageStats {
+            stage: Stage::Evaluate,
+            count: 0,
+            total_micros: 0,
+            max_micros: 0,
+            buckets: vec![0; LATENCY_BUCKETS],
+        };
+        assert_eq!((stats.mean_micros(), stats.quantile_micros(0.5)), (0, 0));
+        // 90 requests of 100us and 10 of 5ms
+        for micros in [100u64; 90].into_iter().chain([5000; 10]) {
+            stats.count += 1;
+            stats.total_micros += micros;
+            stats.max_micros = stats.max_micros.max(micros);
+            stats.buckets[StageStats::bucket(micros)] += 1;
+        }
+        assert_eq!(stats.mean_micros(), 590);
+        assert_eq!(stats.quantile_micros(0.5), 128);
+        assert_eq!(stats.quantile_micros(0.9), 128);
+        assert_eq!(stats.quantile_micros(0.99), 5000);
+
+        let decoded: Message = serde_json::from_str(r#""GetStats""#).unwrap();
+        assert!(matches!(decoded, Message::GetStats));
+    }
+
+    #[test]
+    fn test_health_readiness() {
+        // Enclaves before the readiness fields read as ready
+        let old: HealthResponse =
+            serde_json::from_str(r#"{"timestamp": 5, "key_count": 2}"#).unwrap();
+        assert!(old.is_ready());
+        assert_eq!(old.uptime_secs, 0);
+        assert!(old.epochs.is_empty());
+
+        let mut health = HealthResponse {
+            timestamp: 5,
+            key_count: 2,
+            uptime_secs: 3,
+            epochs: vec![SuiteEpoch {
+                ciphersuite: CiphersuiteId::P256Sha256,
+                epoch: 1,
+            }],
+            attestation: AttestationHealth {
+                failing: true,
+                last_success: Some(4),
+                last_error: Some("NSM unavailable".to_string()),
+            },
+        };
+        assert!(!health.is_ready());
+        let encoded = serde_json::to_string(&health).unwrap();
+        let decoded: HealthResponse = serde_json::from_str(&encoded).unwrap();
+        assert_eq!(decoded.epochs, health.epochs);
+        assert_eq!(decoded.attestation, health.attestation);
+
+        health.attestation.failing = false;
+        assert!(health.is_ready());
+    }
+
+    #[test]
+    fn test_negotiate_version() {
+        assert_eq!(negotiate_version(&[PROTOCOL_VERSION]), Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate_version(&[0, PROTOCOL_VERSION, 999]), Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate_version(&[1]), Some(1));
+        assert_eq!(negotiate_version(&[999]), None);
+        assert_eq!(negotiate_version(&[]), None);
+        assert!(SUPPORTED_PROTOCOL_VERSIONS.contains(&DEFAULT_PROTOCOL_VERSION));
+
+        let hello = r#"{"Hello":{"versions":[1,2]}}"#;
+        assert!(matches!(
+            serde_json::from_str::<Message>(hello).unwrap(),
+            Message::Hello(HelloRequest { versions, point_encoding, request_ids })
+                if versions == [1, 2] && point_encoding.is_compressed() && !request_ids
+        ));
+        // Compressed points are left out, so enclaves before the field accept
+        // the Hello
+        let request = HelloRequest {
+            versions: vec![PROTOCOL_VERSION],
+            point_encoding: PointEncoding::Compressed,
+            request_ids: false,
+        };
+        assert!(!serde_json::to_string(&request).unwrap().contains("point_encoding"));
+        // So is the request id option unless asked for
+        assert!(!serde_json::to_string(&request).unwrap().contains("request_ids"));
+        let request = HelloRequest { request_ids: true, ..request };
+        let json = serde_json::to_string(&request).unwrap();
+        assert!(serde_json::from_str::<HelloRequest>(&json).unwrap().request_ids);
+    }
+
+    #[test]
+    fn test_error_response() {
+        use frame::WireFormat;
+
+        let error = ErrorResponse::new(ErrorCode::HashMismatch, "Query hash mismatch");
+        let result: OprfResult<HealthResponse> = Err(error.clone());
+        let json = serde_json::to_string(&result).unwrap();
+        assert_eq!(json, r#"{"Err":{"code":"HashMismatch","message":"Query hash mismatch"}}"#);
+
+        for format in [WireFormat::Json, WireFormat::Cbor] {
+            let encoded = format.encode(&result).unwrap();
+            let decoded: OprfResult<HealthResponse> = format.decode(&encoded).unwrap();
+            assert_eq!(decoded.unwrap_err(), error);
+
+            let ok: OprfResult<HealthResponse> = Ok(HealthResponse {
+                timestamp: 5,
+                key_count: 2,
+                uptime_secs: 1,
+                epochs: Vec::new(),
+                attestation: AttestationHealth::default(),
+            });
+            let decoded: OprfResult<HealthResponse> =
+                format.decode(&format.encode(&ok).unwrap()).unwrap();
+            assert_eq!(decoded.unwrap().key_count, 2);
+        }
+
+        let internal = ErrorResponse::from("NSM error".to_string());
+        assert_eq!(internal.code, ErrorCode::InternalError);
+        assert!(internal.to_string().contains("NSM error"));
+    }
+
+    #[test]
+    fn test_message_dispatch() {
+        let evaluate =
+            r#"{"Evaluate":{"blinded_query":[1],"query_hash":"00","ciphersuite":"P256Sha256"}}"#;
+        assert!(matches!(
+            serde_json::from_str::<Message>(evaluate).unwrap(),
+            Message::Evaluate(_)
+        ));
+
+        let rotate = r#"{"RotateKey":{"ciphersuite":"Bn254Sha256","grace_period_secs":60}}"#;
+        assert!(matches!(
+            serde_json::from_str::<Message>(rotate).unwrap(),
+            Message::RotateKey(_)
+        ));
+
+        // Requests with the same fields are told apart by their tag
+        let public_key = r#"{"GetPublicKey":{"ciphersuite":"Bn254Sha256"}}"#;
+        let message = serde_json::from_str::<Message>(public_key).unwrap();
+        assert!(matches!(&message, Message::GetPublicKey(r) if r.key_id == DEFAULT_KEY_ID));
+        assert_eq!(message.ciphersuite(), Some(CiphersuiteId::Bn254Sha256));
+        assert_eq!(message.key_id(), Some(DEFAULT_KEY_ID));
+        let health = serde_json::from_str::<Message>(r#""Health""#).unwrap();
+        assert!(matches!(health, Message::Health));
+        assert_eq!(health.ciphersuite(), None);
+        assert_eq!(health.key_id(), None);
+
+        // A malformed evaluation is rejected, as is an untagged request
+        let malformed = r#"{"Evaluate":{"blinded_query":[1],"ciphersuite":"Bn254Sha256"}}"#;
+        assert!(serde_json::from_str::<Message>(malformed).is_err());
+        let untagged = r#"{"ciphersuite":"Bn254Sha256","grace_period_secs":60}"#;
+        assert!(serde_json::from_str::<Message>(untagged).is_err());
+    }
+
+    #[test]
+    fn test_key_id() {
+        assert!(validate_key_id(DEFAULT_KEY_ID).is_ok());
+        assert!(validate_key_id("billing-v2.prod_eu").is_ok());
+        assert!(validate_key_id("").is_err());
+        assert!(validate_key_id("has space").is_err());
+        assert!(validate_key_id(&"a".repeat(MAX_KEY_ID_LEN + 1)).is_err());
+
+        assert!(validate_request_id("4f2a9c01-3").is_ok());
+        assert!(validate_request_id("trace:span.1_a").is_ok());
+        assert!(validate_request_id("").is_err());
+        assert!(validate_request_id("line\nbreak").is_err());
+        assert!(validate_request_id(&"a".repeat(MAX_REQUEST_ID_LEN + 1)).is_err());
+        assert_eq!(request_tag(Some("abc")), "[abc] ");
+        assert_eq!(request_tag(None), "");
+
+        // The key id is length-prefixed so it cannot run into the payload
+        assert_eq!(attested_user_data("ab", b"c"), b"\x00\x02abc".to_vec());
+        assert_ne!(attested_user_data("ab", b"c"), attested_user_data("a", b"bc"));
+
+        let request: OprfRequest =
+            serde_json::from_str(r#"{"blinded_query":[1],"query_hash":"00"}"#).unwrap();
+        assert_eq!(request.key_id, DEFAULT_KEY_ID);
+    }
+
+    #[test]
+    fn test_evaluation_user_data() {
+        let points = vec![vec![1u8; 33], vec![2u8; 33]];
+        let user_data =
+            EvaluationUserData::new(1, "billing", 3, &[4u8; 33], &points, Some(&[5; 16]), None);
+        let encoded = user_data.to_cbor();
+        assert_eq!(EvaluationUserData::from_cbor(&encoded).unwrap(), user_data);
+        assert_eq!(user_data.evaluated_hash, <[u8; 32]>::from(Sha256::digest(points.concat())));
+
+        let without_nonce = EvaluationUserData { client_nonce: None, ..user_data.clone() };
+        assert_eq!(
+            EvaluationUserData::from_cbor(&without_nonce.to_cbor()).unwrap(),
+            without_nonce
+        );
+
+        // A request id is attested when there is one, and absent otherwise
+        let tagged =
+            EvaluationUserData { request_id: Some("4f2a-1".to_string()), ..user_data.clone() };
+        let tagged_encoded = tagged.to_cbor();
+        assert_eq!(EvaluationUserData::from_cbor(&tagged_encoded).unwrap(), tagged);
+        assert_ne!(tagged_encoded, encoded);
+        assert!(!encoded.windows(b"request_id".len()).any(|w| w == b"request_id"));
+
+        // Other versions, unknown fields and mistyped fields are rejected
+        let next_version = EvaluationUserData { version: 3, ..user_data.clone() };
+        assert!(EvaluationUserData::from_cbor(&next_version.to_cbor()).is_err());
+        let Value::Map(mut map) = serde_cbor::from_slice(&encoded).unwrap() else {
+            panic!("user data is not a map");
+        };
+        map.remove(&Value::Text("protocol_version".to_string()));
+        assert!(EvaluationUserData::from_cbor(&serde_cbor::to_vec(&map).unwrap()).is_err());
+        let Value::Map(mut map) = serde_cbor::from_slice(&encoded).unwrap() else {
+            panic!("user data is not a map");
+        };
+        map.insert(Value::Text("extra".to_string()), Value::Null);
+        assert!(EvaluationUserData::from_cbor(&serde_cbor::to_vec(&map).unwrap()).is_err());
+        map.remove(&Value::Text("extra".to_string()));
+        map.insert(Value::Text("epoch".to_string()), Value::Text("3".to_string()));
+        assert!(EvaluationUserData::from_cbor(&serde_cbor::to_vec(&map).unwrap()).is_err());
+        assert!(EvaluationUserData::from_cbor(b"not cbor").is_err());
+
+        assert_eq!(nsm_user_data(&encoded), encoded);
+        assert_eq!(nsm_user_data(&[0u8; 600]).len(), 32);
+    }
+
+    #[test]
+    fn test_replication_binding() {
+        let binding = replication_binding(REPLICA_STANDBY_LABEL, &[b"pk"]);
+        assert_eq!(binding, [REPLICA_STANDBY_LABEL, &[0, 0, 0, 2], b"pk"].concat());
+
+        // Parts are length-prefixed, so they cannot be shifted into each other
+        assert_ne!(
+            replication_binding(REPLICA_PRIMARY_LABEL, &[b"ab", b"c"]),
+            replication_binding(REPLICA_PRIMARY_LABEL, &[b"a", b"bc"])
+        );
+        assert_ne!(
+            replication_binding(REPLICA_PRIMARY_LABEL, &[b"pk"]),
+            replication_binding(REPLICA_STANDBY_LABEL, &[b"pk"])
+        );
+
+        let message = serde_json::from_str::<Message>(r#""BeginReplication""#).unwrap();
+        assert!(matches!(message, Message::BeginReplication));
+        assert_eq!(message.ciphersuite(), None);
+    }
+
+    #[test]
+    fn test_signed_mock_document_errors() {
+        use ed25519::{SigningKey, VerifyingKey};
+
+        let key = SigningKey::from_seed(&[1u8; 32]);
+        let verifying_key = VerifyingKey::from_bytes(&key.public_key()).unwrap();
+        let document = SignedMockDocument::sign(&key, &serde_json::json!({ "timestamp": 1 }));
+        assert_eq!(document.verify(&verifying_key).unwrap()["timestamp"], 1);
+
+        let other = VerifyingKey::from_bytes(&SigningKey::from_seed(&[2u8; 32]).public_key());
+        let err = document.verify(&other.unwrap()).unwrap_err();
+        assert!(matches!(err, AttestationError::SignatureInvalid(_)));
+        assert!(!err.is_policy_violation());
+
+        let mut malformed = document.clone();
+        malformed.signature.push('x');
+        let err = malformed.verify(&verifying_key).unwrap_err();
+        assert!(matches!(err, AttestationError::MalformedDocument(_)));
+
+        let err = AttestationError::PcrMismatch { index: 8, expected: "ab".into(), got: None };
+        assert!(err.is_policy_violation());
+        assert_eq!(err.to_string(), "PCR8 is not measured, expected ab");
+        let err = OprfError::from(AttestationError::NonceMismatch);
+        assert!(matches!(err, OprfError::Attestation(AttestationError::NonceMismatch)));
+    }
+
+    #[test]
+    fn test_strict_deserialization() {
+        use frame::WireFormat;
+
+        let request = OprfRequest {
+            blinded_query: vec![0xab; 33],
+            query_hash: "00".repeat(32),
+            mode: OprfMode::default(),
+            ciphersuite: CiphersuiteId::P256Sha256,
+            epoch: Some(1),
+            key_id: default_key_id(),
+            force_fresh: false,
+            client_nonce: Some(vec![7; MAX_CLIENT_NONCE_LEN]),
+            client_id: Some("tenant-1".to_string()),
+            request_id: None,
+        };
+        let json = serde_json::to_value(&request).unwrap();
+        let decode = |value: &serde_json::Value| {
+            let cbor = serde_cbor::to_vec(value).unwrap();
+            let from_json = serde_json::from_value::<OprfRequest>(value.clone());
+            let from_cbor = WireFormat::Cbor.decode::<OprfRequest>(&cbor);
+            assert_eq!(from_json.is_ok(), from_cbor.is_ok());
+            from_json.map_err(|e| e.to_string())
+        };
+        assert!(decode(&json).is_ok());
+        // Enclaves that predate client ids only accept requests without one
+        let anonymous = OprfRequest {
+            client_id: None,
+            ..request.clone()
+        };
+        assert!(!serde_json::to_string(&anonymous).unwrap().contains("client_id"));
+
+        // Unknown fields
+        let mut unknown = json.clone();
+        unknown["extra"] = 1.into();
+        assert!(decode(&unknown).unwrap_err().contains("unknown field"));
+
+        // Byte strings, strings and lists over their limits
+        let mut long = json.clone();
+        long["client_nonce"] = vec![7; MAX_CLIENT_NONCE_LEN + 1].into();
+        assert!(decode(&long).unwrap_err().contains("at most 64 bytes"));
+        let mut long = json.clone();
+        long["blinded_query"] = vec![1; MAX_ELEMENT_LEN + 1].into();
+        assert!(decode(&long).is_err());
+        let mut long = json.clone();
+        long["query_hash"] = "0".repeat(MAX_QUERY_HASH_LEN + 1).into();
+        assert!(decode(&long).is_err());
+        let mut long = json.clone();
+        long["key_id"] = "k".repeat(MAX_KEY_ID_LEN + 1).into();
+        assert!(decode(&long).is_err());
+
+        // CBOR byte strings over their limit
+        let mut cbor = serde_cbor::to_vec(&request).unwrap();
+        assert!(WireFormat::Cbor.decode::<OprfRequest>(&cbor).is_ok());
+        let nonce = serde_cbor::to_vec(&serde_bytes::Bytes::new(&[7; MAX_CLIENT_NONCE_LEN]))
+            .unwrap();
+        let at = cbor.windows(nonce.len()).position(|w| w == nonce).unwrap();
+        let longer = serde_cbor::to_vec(&serde_bytes::Bytes::new(&[7; MAX_CLIENT_NONCE_LEN + 1]))
+            .unwrap();
+        cbor.splice(at..at + nonce.len(), longer);
+        assert!(WireFormat::Cbor.decode::<OprfRequest>(&cbor).is_err());
+
+        // Duplicate fields, in either format
+        let duplicate = r#"{"blinded_query":[1],"query_hash":"00","query_hash":"00"}"#;
+        let error = serde_json::from_str::<OprfRequest>(duplicate).unwrap_err();
+        assert!(error.to_string().contains("duplicate field"));
+        let mut map = BTreeMap::new();
+        map.insert(Value::Text("blinded_query".into()), Value::Bytes(vec![1]));
+        map.insert(Value::Text("query_hash".into()), Value::Text("00".into()));
+        let mut cbor = serde_cbor::to_vec(&Value::Map(map)).unwrap();
+        assert!(WireFormat::Cbor.decode::<OprfRequest>(&cbor).is_ok());
+        cbor[0] += 1; // one more map entry
+        cbor.extend(serde_cbor::to_vec(&Value::Text("query_hash".into())).unwrap());
+        cbor.extend(serde_cbor::to_vec(&Value::Text("00".into())).unwrap());
+        assert!(WireFormat::Cbor.decode::<OprfRequest>(&cbor).is_err());
+
+        // Batches of more than MAX_BATCH_SIZE queries
+        let batch = |count: usize| BatchOprfRequest {
+            blinded_queries: vec![vec![1; 33]; count],
+            query_hash: "00".repeat(32),
+            mode: OprfMode::default(),
+            ciphersuite: CiphersuiteId::default(),
+            epoch: None,
+            key_id: default_key_id(),
+            force_fresh: false,
+            client_nonce: None,
+            client_id: None,
+            request_id: None,
+        };
+        for format in [WireFormat::Json, WireFormat::Cbor] {
+            let full = format.encode(&batch(MAX_BATCH_SIZE)).unwrap();
+            assert!(format.decode::<BatchOprfRequest>(&full).is_ok());
+            let over = format.encode(&batch(MAX_BATCH_SIZE + 1)).unwrap();
+            assert!(format.decode::<BatchOprfRequest>(&over).is_err());
+        }
+
+        // Responses and attestation documents
+        let document = AttestationDocument {
+            is_mock: true,
+            document: vec![0; 100],
+            pcrs: Some(vec!["0".repeat(MAX_PCR_HEX_LEN); 3]),
+            user_data: vec![1; 10],
+        };
+        let response = OprfResponse {
+            evaluated_point: vec![2; 32],
+            public_key: vec![3; 32],
+            attestation: document.clone(),
+            proof: Some(vec![4; MAX_PROOF_LEN]),
+            public_key_g2: None,
+            epoch: 0,
+            key_id: default_key_id(),
+            request_id: None,
+        };
+        let json = serde_json::to_value(&response).unwrap();
+        assert!(serde_json::from_value::<OprfResponse>(json.clone()).is_ok());
+        let mut unknown = json.clone();
+        unknown["attestation"]["signature"] = "00".into();
+        assert!(serde_json::from_value::<OprfResponse>(unknown).is_err());
+        let mut long = json.clone();
+        long["proof"] = vec![4; MAX_PROOF_LEN + 1].into();
+        assert!(serde_json::from_value::<OprfResponse>(long).is_err());
+        let mut long = json;
+        long["attestation"]["pcrs"] = vec!["00"; MAX_PCRS + 1].into();
+        assert!(serde_json::from_value::<OprfResponse>(long).is_err());
+        let oversized = AttestationDocument {
+            document: vec![0; MAX_ATTESTATION_DOC_LEN + 1],
+            ..document
+        };
+        let cbor = serde_cbor::to_vec(&oversized).unwrap();
+        assert!(WireFormat::Cbor.decode::<AttestationDocument>(&cbor).is_err());
+    }
+
+    /// Property tests over generated protocol values, inputs and frames.
+    /// Generated values stay within the limits the decoders enforce, so every
+    /// one of them must survive encoding.
+    mod properties {
+        use crate::audit::{AuditCheckpoint, AuditRecord};
+        use crate::frame::{read_frame, write_frame, WireFormat, FRAME_HEADER_LEN};
+        use crate::proto::ProtoMessage;
+        use crate::selftest::{KnownAnswer, KnownAnswerTest, SelfTestReport};
+        use crate::*;
+        use proptest::collection::vec;
+        use proptest::option;
+        use proptest::prelude::*;
+        use proptest::sample::{select, Index};
+        use rand::SeedableRng;
+        use serde::{de::DeserializeOwned, Serialize};
+        use std::io::ErrorKind;
+
+        fn bytes(max: usize) -> impl Strategy<Value = Vec<u8>> {
+            vec(any::<u8>(), 0..=max)
+        }
+
+        /// Text of at most 16 characters, so at most 64 bytes
+        fn text() -> impl Strategy<Value = String> {
+            "\\PC{0,16}"
+        }
+
+        /// Protobuf reads an empty key id as the default one
+        fn key_id() -> impl Strategy<Value = String> {
+            "[a-z0-9_-]{1,16}"
+        }
+
+        fn suite() -> impl Strategy<Value = CiphersuiteId> {
+            select(CiphersuiteId::ALL.to_vec())
+        }
+
+        fn mode() -> impl Strategy<Value = OprfMode> {
+            select(vec![OprfMode::Oprf, OprfMode::Voprf])
+        }
+
+        fn encoding() -> impl Strategy<Value = PointEncoding> {
+            select(vec![PointEncoding::Compressed, PointEncoding::Uncompressed])
+        }
+
+        fn wire_format() -> impl Strategy<Value = WireFormat> {
+            select(vec![WireFormat::Json, WireFormat::Cbor])
+        }
+
+        fn attestation() -> impl Strategy<Value = AttestationDocument> {
+            (any::<bool>(), bytes(256), option::of(vec("[0-9a-f]{96}", 0..4)), bytes(256))
+                .prop_map(|(is_mock, document, pcrs, user_data)| AttestationDocument {
+                    is_mock,
+                    document,
+                    pcrs,
+                    user_data,
+                })
+        }
+
+        fn kms() -> impl Strategy<Value = KmsConfig> {
+            (text(), text(), text(), option::of(text()), any::<u32>()).prop_map(
+                |(region, access_key_id, secret_access_key, session_token, proxy_port)| {
+                    KmsConfig {
+                        region,
+                        access_key_id,
+                        secret_access_key,
+                        session_token,
+                        proxy_port,
+                    }
+                },
+            )
+        }
+
+        fn backup() -> impl Strategy<Value = KeyBackup> {
+            (bytes(64), bytes(12), bytes(256), any::<bool>()).prop_map(
+                |(encrypted_data_key, nonce, ciphertext, is_mock)| KeyBackup {
+                    encrypted_data_key,
+                    nonce,
+                    ciphertext,
+                    is_mock,
+                },
+            )
+        }
+
+        fn public_keys() -> impl Strategy<Value = Vec<PublicKeyInfo>> {
+            let key = (suite(), key_id(), any::<u64>(), bytes(MAX_ELEMENT_LEN));
+            vec(key, 0..4).prop_map(|keys| {
+                keys.into_iter()
+                    .map(|(ciphersuite, key_id, epoch, public_key)| PublicKeyInfo {
+                        ciphersuite,
+                        key_id,
+                        epoch,
+                        public_key,
+                    })
+                    .collect()
+            })
+        }
+
+        fn oprf_request() -> impl Strategy<Value = OprfRequest> {
+            (
+                bytes(MAX_ELEMENT_LEN),
+                "[0-9a-f]{64}",
+                mode(),
+                suite(),
+                option::of(any::<u64>()),
+                key_id(),
+                any::<bool>(),
+                option::of(bytes(MAX_CLIENT_NONCE_LEN)),
+                option::of(text()),
+                option::of(text()),
+            )
+                .prop_map(
+                    |(
+                        blinded_query,
+                        query_hash,
+                        mode,
+                        ciphersuite,
+                        epoch,
+                        key_id,
+                        force_fresh,
+                        client_nonce,
+                        client_id,
+                        request_id,
+                    )| OprfRequest {
+                        blinded_query,
+                        query_hash,
+                        mode,
+                        ciphersuite,
+                        epoch,
+                        key_id,
+                        force_fresh,
+                        client_nonce,
+                        client_id,
+                        request_id,
+                    },
+                )
+        }
+
+        fn batch_request() -> impl Strategy<Value = BatchOprfRequest> {
+            (oprf_request(), vec(bytes(MAX_ELEMENT_LEN), 0..8)).prop_map(
+                |(request, blinded_queries)| BatchOprfRequest {
+                    blinded_queries,
+                    query_hash: request.query_hash,
+                    mode: request.mode,
+                    ciphersuite: request.ciphersuite,
+                    epoch: request.epoch,
+                    key_id: request.key_id,
+                    force_fresh: request.force_fresh,
+                    client_nonce: request.client_nonce,
+                    client_id: request.client_id,
+                    request_id: request.request_id,
+                },
+            )
+        }
+
+        fn oprf_response() -> impl Strategy<Value = OprfResponse> {
+            (
+                bytes(MAX_ELEMENT_LEN),
+                bytes(MAX_ELEMENT_LEN),
+                attestation(),
+                option::of(bytes(MAX_PROOF_LEN)),
+                option::of(bytes(MAX_ELEMENT_LEN)),
+                any::<u64>(),
+                key_id(),
+                option::of(text()),
+            )
+                .prop_map(
+                    |(
+                        evaluated_point,
+                        public_key,
+                        attestation,
+                        proof,
+                        public_key_g2,
+                        epoch,
+                        key_id,
+                        request_id,
+                    )| OprfResponse {
+                        evaluated_point,
+                        public_key,
+                        attestation,
+                        proof,
+                        public_key_g2,
+                        epoch,
+                        key_id,
+                        request_id,
+                    },
+                )
+        }
+
+        fn batch_response() -> impl Strategy<Value = BatchOprfResponse> {
+            (oprf_response(), vec(bytes(MAX_ELEMENT_LEN), 0..8)).prop_map(
+                |(response, evaluated_points)| BatchOprfResponse {
+                    evaluated_points,
+                    public_key: response.public_key,
+                    attestation: response.attestation,
+                    proof: response.proof,
+                    public_key_g2: response.public_key_g2,
+                    epoch: response.epoch,
+                    key_id: response.key_id,
+                    request_id: response.request_id,
+                },
+            )
+        }
+
+        fn get_public_key() -> impl Strategy<Value = GetPublicKeyRequest> {
+            (suite(), key_id())
+                .prop_map(|(ciphersuite, key_id)| GetPublicKeyRequest { ciphersuite, key_id })
+        }
+
+        fn get_attestation() -> impl Strategy<Value = GetAttestationRequest> {
+            (suite(), key_id(), bytes(MAX_CLIENT_NONCE_LEN)).prop_map(
+                |(ciphersuite, key_id, nonce)| GetAttestationRequest {
+                    ciphersuite,
+                    key_id,
+                    nonce,
+                },
+            )
+        }
+
+        fn public_key_response() -> impl Strategy<Value = PublicKeyResponse> {
+            (
+                key_id(),
+                any::<u64>(),
+                bytes(MAX_ELEMENT_LEN),
+                option::of(bytes(MAX_ELEMENT_LEN)),
+                attestation(),
+            )
+                .prop_map(|(key_id, epoch, public_key, public_key_g2, attestation)| {
+                    PublicKeyResponse {
+                        key_id,
+                        epoch,
+                        public_key,
+                        public_key_g2,
+                        attestation,
+                    }
+                })
+        }
+
+        fn attestation_response() -> impl Strategy<Value = AttestationResponse> {
+            (key_id(), any::<u64>(), bytes(MAX_ELEMENT_LEN), attestation()).prop_map(
+                |(key_id, epoch, public_key, attestation)| AttestationResponse {
+                    key_id,
+                    epoch,
+                    public_key,
+                    attestation,
+                },
+            )
+        }
+
+        fn error_response() -> impl Strategy<Value = ErrorResponse> {
+            let code = select(vec![
+                ErrorCode::BadPoint,
+                ErrorCode::HashMismatch,
+                ErrorCode::RateLimited,
+                ErrorCode::BadRequest,
+                ErrorCode::InternalError,
+                ErrorCode::Unauthorized,
+            ]);
+            (code, text()).prop_map(|(code, message)| ErrorResponse::new(code, message))
+        }
+
+        fn export_replica_response() -> impl Strategy<Value = ExportReplicaResponse> {
+            (public_keys(), bytes(65), bytes(12), bytes(256), attestation()).prop_map(
+                |(keys, sender_public_key, nonce, ciphertext, attestation)| {
+                    ExportReplicaResponse {
+                        keys,
+                        sender_public_key,
+                        nonce,
+                        ciphertext,
+                        attestation,
+                    }
+                },
+            )
+        }
+
+        fn message() -> impl Strategy<Value = Message> {
+            prop_oneof![
+                (vec(any::<u32>(), 0..4), encoding(), any::<bool>()).prop_map(
+                    |(versions, point_encoding, request_ids)| Message::Hello(HelloRequest {
+                        versions,
+                        point_encoding,
+                        request_ids,
+                    })
+                ),
+                bytes(96).prop_map(|message| Message::Handshake(HandshakeRequest { message })),
+                oprf_request().prop_map(Message::Evaluate),
+                batch_request().prop_map(Message::BatchEvaluate),
+                get_public_key().prop_map(Message::GetPublicKey),
+                get_attestation().prop_map(Message::GetAttestation),
+                Just(Message::Health),
+                (suite(), key_id(), option::of(any::<u64>())).prop_map(
+                    |(ciphersuite, key_id, grace_period_secs)| {
+                        Message::RotateKey(RotateKeyRequest {
+                            ciphersuite,
+                            key_id,
+                            grace_period_secs,
+                        })
+                    }
+                ),
+                (kms(), text()).prop_map(|(kms, kms_key_id)| {
+                    Message::ExportKeys(ExportKeysRequest { kms, kms_key_id })
+                }),
+                (kms(), backup()).prop_map(|(kms, backup)| {
+                    Message::ImportKeys(ImportKeysRequest { kms, backup })
+                }),
+                (kms(), bytes(256)).prop_map(|(kms, encrypted_seed)| {
+                    Message::ProvisionSeed(ProvisionSeedRequest { kms, encrypted_seed })
+                }),
+                (suite(), key_id(), any::<u64>()).prop_map(|(ciphersuite, key_id, epoch)| {
+                    Message::BeginKeyInjection(BeginKeyInjectionRequest {
+                        ciphersuite,
+                        key_id,
+                        epoch,
+                    })
+                }),
+                (suite(), key_id(), any::<u64>(), bytes(65), bytes(12), bytes(64)).prop_map(
+                    |(ciphersuite, key_id, epoch, sender_public_key, nonce, ciphertext)| {
+                        Message::InjectKey(InjectKeyRequest {
+                            ciphersuite,
+                            key_id,
+                            epoch,
+                            sender_public_key,
+                            nonce,
+                            ciphertext,
+                        })
+                    }
+                ),
+                Just(Message::BeginReplication),
+                (bytes(65), attestation(), option::of(bytes(32))).prop_map(
+                    |(transport_public_key, attestation, peer_mock_signing_key)| {
+                        Message::ExportReplica(ExportReplicaRequest {
+                            transport_public_key,
+                            attestation,
+                            peer_mock_signing_key,
+                        })
+                    }
+                ),
+                (export_replica_response(), option::of(bytes(32))).prop_map(
+                    |(replica, peer_mock_signing_key)| {
+                        Message::ImportReplica(ImportReplicaRequest {
+                            replica,
+                            peer_mock_signing_key,
+                        })
+                    }
+                ),
+                any::<u64>().prop_map(|from| Message::GetAuditLog(GetAuditLogRequest { from })),
+                Just(Message::GetStats),
+                Just(Message::GetSelfTest),
+            ]
+        }
+
+        fn audit_log() -> impl Strategy<Value = AuditLogResponse> {
+            let record = (
+                any::<u64>(),
+                any::<u64>(),
+                suite(),
+                key_id(),
+                any::<u64>(),
+                any::<u64>(),
+                bytes(32),
+                option::of(bytes(MAX_CLIENT_NONCE_LEN)),
+            )
+                .prop_map(
+                    |(sequence, timestamp, ciphersuite, key_id, epoch, count, query_hash, nonce)| {
+                        AuditRecord {
+                            sequence,
+                            timestamp,
+                            ciphersuite,
+                            key_id,
+                            epoch,
+                            count,
+                            query_hash,
+                            client_nonce: nonce,
+                        }
+                    },
+                );
+            let checkpoint = (any::<u64>(), bytes(32), attestation()).prop_map(
+                |(sequence, head, attestation)| AuditCheckpoint {
+                    sequence,
+                    head,
+                    attestation,
+                },
+            );
+            (bytes(32), vec(record, 0..4), any::<u64>(), option::of(checkpoint)).prop_map(
+                |(previous_head, records, next_sequence, checkpoint)| AuditLogResponse {
+                    previous_head,
+                    records,
+                    next_sequence,
+                    checkpoint,
+                },
+            )
+        }
+
+        fn stats() -> impl Strategy<Value = StatsResponse> {
+            let stage = (
+                select(Stage::ALL.to_vec()),
+                any::<u64>(),
+                any::<u64>(),
+                any::<u64>(),
+                vec(any::<u64>(), 0..=LATENCY_BUCKETS),
+            )
+                .prop_map(|(stage, count, total_micros, max_micros, buckets)| StageStats {
+                    stage,
+                    count,
+                    total_micros,
+                    max_micros,
+                    buckets,
+                });
+            (any::<[u64; 5]>(), vec(stage, 0..=Stage::ALL.len())).prop_map(|(counters, stages)| {
+                let [uptime_secs, requests, errors, evaluation_requests, evaluations] = counters;
+                StatsResponse {
+                    uptime_secs,
+                    requests,
+                    errors,
+                    evaluation_requests,
+                    evaluations,
+                    stages,
+                }
+            })
+        }
+
+        fn health() -> impl Strategy<Value = HealthResponse> {
+            let epoch = (suite(), any::<u64>())
+                .prop_map(|(ciphersuite, epoch)| SuiteEpoch { ciphersuite, epoch });
+            let attestation = (any::<bool>(), option::of(any::<u64>()), option::of(text()))
+                .prop_map(|(failing, last_success, last_error)| AttestationHealth {
+                    failing,
+                    last_success,
+                    last_error,
+                });
+            (any::<u64>(), any::<usize>(), any::<u64>(), vec(epoch, 0..4), attestation).prop_map(
+                |(timestamp, key_count, uptime_secs, epochs, attestation)| HealthResponse {
+                    timestamp,
+                    key_count,
+                    uptime_secs,
+                    epochs,
+                    attestation,
+                },
+            )
+        }
+
+        fn self_test() -> impl Strategy<Value = SelfTestResponse> {
+            let result = (suite(), select(KnownAnswerTest::ALL.to_vec()), any::<bool>())
+                .prop_map(|(ciphersuite, test, passed)| KnownAnswer {
+                    ciphersuite,
+                    test,
+                    passed,
+                });
+            (vec(result, 0..10), attestation()).prop_map(|(results, attestation)| {
+                SelfTestResponse {
+                    report: SelfTestReport { results },
+                    attestation,
+                }
+            })
+        }
+
+        fn response() -> impl Strategy<Value = EnclaveResponse> {
+            let hello = (
+                any::<u32>(),
+                option::of(bytes(33)),
+                option::of(bytes(32)),
+                option::of(attestation()),
+                encoding(),
+                any::<bool>(),
+            )
+                .prop_map(
+                    |(version, channel_key, signing_key, attestation, point_encoding, ids)| {
+                        EnclaveResponse::Hello(HelloResponse {
+                            version,
+                            channel_key,
+                            signing_key,
+                            attestation,
+                            point_encoding,
+                            request_ids: ids,
+                        })
+                    },
+                );
+            let rotate = (key_id(), any::<u64>(), bytes(MAX_ELEMENT_LEN), any::<u64>())
+                .prop_flat_map(|key| (Just(key), attestation()))
+                .prop_map(|((key_id, epoch, public_key, previous_expires_at), attestation)| {
+                    EnclaveResponse::RotateKey(RotateKeyResponse {
+                        key_id,
+                        epoch,
+                        public_key,
+                        previous_expires_at,
+                        attestation,
+                    })
+                });
+            let inject = (key_id(), any::<u64>(), bytes(MAX_ELEMENT_LEN), attestation()).prop_map(
+                |(key_id, epoch, public_key, attestation)| {
+                    EnclaveResponse::InjectKey(InjectKeyResponse {
+                        key_id,
+                        epoch,
+                        public_key,
+                        attestation,
+                    })
+                },
+            );
+            prop_oneof![
+                hello,
+                bytes(96).prop_map(|message| EnclaveResponse::Handshake(HandshakeResponse {
+                    message
+                })),
+                oprf_response().prop_map(EnclaveResponse::Evaluate),
+                batch_response().prop_map(EnclaveResponse::BatchEvaluate),
+                public_key_response().prop_map(EnclaveResponse::PublicKey),
+                attestation_response().prop_map(EnclaveResponse::Attestation),
+                health().prop_map(EnclaveResponse::Health),
+                rotate,
+                backup().prop_map(|backup| EnclaveResponse::ExportKeys(ExportKeysResponse {
+                    backup
+                })),
+                (public_keys(), attestation()).prop_map(|(keys, attestation)| {
+                    EnclaveResponse::ImportKeys(ImportKeysResponse { keys, attestation })
+                }),
+                (public_keys(), attestation()).prop_map(|(keys, attestation)| {
+                    EnclaveResponse::ProvisionSeed(ProvisionSeedResponse { keys, attestation })
+                }),
+                (bytes(65), attestation()).prop_map(|(transport_public_key, attestation)| {
+                    EnclaveResponse::BeginKeyInjection(BeginKeyInjectionResponse {
+                        transport_public_key,
+                        attestation,
+                    })
+                }),
+                inject,
+                (bytes(65), attestation()).prop_map(|(transport_public_key, attestation)| {
+                    EnclaveResponse::BeginReplication(BeginReplicationResponse {
+                        transport_public_key,
+                        attestation,
+                    })
+                }),
+                export_replica_response().prop_map(EnclaveResponse::ExportReplica),
+                (public_keys(), attestation()).prop_map(|(keys, attestation)| {
+                    EnclaveResponse::ImportReplica(ImportReplicaResponse { keys, attestation })
+                }),
+                audit_log().prop_map(EnclaveResponse::AuditLog),
+                stats().prop_map(EnclaveResponse::Stats),
+                self_test().prop_map(EnclaveResponse::SelfTest),
+            ]
+        }
+
+        /// Encode `value` in both wire formats, decode it as a `T` and encode
+        /// it again: each format must give back the same bytes, and the value
+        /// read from JSON must encode to the same CBOR
+        fn round_trip<T: Serialize + DeserializeOwned>(value: &T) -> Result<(), TestCaseError> {
+            let json = WireFormat::Json.encode(value).unwrap();
+            let cbor = WireFormat::Cbor.encode(value).unwrap();
+            for (format, encoded) in [(WireFormat::Json, &json), (WireFormat::Cbor, &cbor)] {
+                let decoded: T = format.decode(encoded).map_err(|e| {
+                    TestCaseError::fail(format!("{:?} did not decode: {}", format, e))
+                })?;
+                prop_assert_eq!(&format.encode(&decoded).unwrap(), encoded);
+            }
+            let from_json: T = WireFormat::Json.decode(&json).unwrap();
+            prop_assert_eq!(WireFormat::Cbor.encode(&from_json).unwrap(), cbor);
+            Ok(())
+        }
+
+        /// [`round_trip`] of the response as the client reads it: as the
+        /// response type of its request, in an [`OprfResult`]
+        fn response_round_trip(response: &EnclaveResponse) -> Result<(), TestCaseError> {
+            fn ok<T: Clone>(response: &T) -> OprfResult<T> {
+                Ok(response.clone())
+            }
+            match response {
+                EnclaveResponse::Hello(response) => round_trip(&ok(response)),
+                EnclaveResponse::Handshake(response) => round_trip(&ok(response)),
+                EnclaveResponse::Evaluate(response) => round_trip(&ok(response)),
+                EnclaveResponse::BatchEvaluate(response) => round_trip(&ok(response)),
+                EnclaveResponse::PublicKey(response) => round_trip(&ok(response)),
+                EnclaveResponse::Attestation(response) => round_trip(&ok(response)),
+                EnclaveResponse::Health(response) => round_trip(&ok(response)),
+                EnclaveResponse::RotateKey(response) => round_trip(&ok(response)),
+                EnclaveResponse::ExportKeys(response) => round_trip(&ok(response)),
+                EnclaveResponse::ImportKeys(response) => round_trip(&ok(response)),
+                EnclaveResponse::ProvisionSeed(response) => round_trip(&ok(response)),
+                EnclaveResponse::BeginKeyInjection(response) => round_trip(&ok(response)),
+                EnclaveResponse::InjectKey(response) => round_trip(&ok(response)),
+                EnclaveResponse::BeginReplication(response) => round_trip(&ok(response)),
+                EnclaveResponse::ExportReplica(response) => round_trip(&ok(response)),
+                EnclaveResponse::ImportReplica(response) => round_trip(&ok(response)),
+                EnclaveResponse::AuditLog(response) => round_trip(&ok(response)),
+                EnclaveResponse::Stats(response) => round_trip(&ok(response)),
+                EnclaveResponse::SelfTest(response) => round_trip(&ok(response)),
+            }
+        }
+
+        /// Protobuf encoding, decoding and encoding again gives the same bytes
+        fn proto_round_trip<T: ProtoMessage>(value: &T) -> Result<(), TestCaseError> {
+            let encoded = value.encode_to_vec();
+            let decoded = T::decode(&encoded)
+                .map_err(|e| TestCaseError::fail(format!("protobuf did not decode: {}", e)))?;
+            prop_assert_eq!(decoded.encode_to_vec(), encoded);
+            Ok(())
+        }
+
+        /// Blind `inputs`, evaluate them as the enclave does in `encoding`
+        /// with a proof, and unblind them: each output must be the PRF of its
+        /// input under the key
+        fn blind_evaluate_unblind<C: Ciphersuite>(
+            seed: u64,
+            inputs: &[Vec<u8>],
+            encoding: PointEncoding,
+        ) -> Result<(), TestCaseError> {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+            let k = C::random_scalar(&mut rng);
+            let pk = C::scalar_mul_generator(&k);
+
+            let (blinds, blinded): (Vec<_>, Vec<_>) =
+                inputs.iter().map(|input| blind::<C, _>(input, OprfMode::Voprf, &mut rng)).unzip();
+            let encoded: Vec<_> =
+                blinded.iter().map(|blinded| blinded.encode(encoding).unwrap()).collect();
+
+            // The enclave decodes the points, evaluates and proves
+            let points = encoded
+                .iter()
+                .map(|bytes| C::decode_element(bytes, encoding))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let evaluated: Vec<_> = points.iter().map(|point| C::scalar_mul(point, &k)).collect();
+            let proof = generate_proof::<C, _>(&k, &pk, &points, &evaluated, &mut rng).unwrap();
+            let encoded = C::encode_elements(&evaluated, encoding).unwrap();
+
+            let proof = DleqProof::<C>::from_bytes(&proof.to_bytes().unwrap()).unwrap();
+            prop_assert!(verify_proof::<C>(&pk, &points, &evaluated, &proof).is_ok());
+            let other = C::scalar_mul_generator(&C::random_scalar(&mut rng));
+            prop_assert!(verify_proof::<C>(&other, &points, &evaluated, &proof).is_err());
+
+            let mut outputs = Vec::new();
+            for ((blind, bytes), input) in blinds.into_iter().zip(&encoded).zip(inputs) {
+                let evaluated = EvaluatedElement::<C>::decode(bytes, encoding).unwrap();
+                let output = unblind(blind, &evaluated).unwrap();
+                let expected = C::scalar_mul(&hash_to_group::<C>(input, OprfMode::Voprf), &k);
+                prop_assert_eq!(output.as_bytes(), &finalize::<C>(input, &expected).unwrap()[..]);
+                outputs.push(output);
+            }
+            for (i, j) in (0..inputs.len()).flat_map(|i| (0..i).map(move |j| (i, j))) {
+                prop_assert_eq!(inputs[i] == inputs[j], outputs[i] == outputs[j]);
+            }
+            Ok(())
+        }
+
+        /// The frame of an encoded message
+        fn framed() -> impl Strategy<Value = (u64, WireFormat, Vec<u8>)> {
+            (any::<u64>(), wire_format(), message()).prop_map(|(request_id, format, message)| {
+                (request_id, format, format.encode(&message).unwrap())
+            })
+        }
+
+        fn frame_bytes(request_id: u64, format: WireFormat, payload: &[u8]) -> Vec<u8> {
+            let mut stream = Vec::new();
+            write_frame(&mut stream, request_id, format, payload).unwrap();
+            stream
+        }
+
+        proptest! {
+            #[test]
+            fn test_message_round_trip(message in message()) {
+                round_trip(&message)?;
+                // The operation survives, and the routing read from it
+                let decoded: Message =
+                    WireFormat::Cbor.decode(&WireFormat::Cbor.encode(&message).unwrap()).unwrap();
+                prop_assert_eq!(decoded.ciphersuite(), message.ciphersuite());
+                prop_assert_eq!(decoded.key_id(), message.key_id());
+                prop_assert_eq!(decoded.request_id(), message.request_id());
+            }
+
+            #[test]
+            fn test_response_round_trip(response in response(), error in error_response()) {
+                response_round_trip(&response)?;
+                round_trip(&OprfResult::<HealthResponse>::Err(error.clone()))?;
+                let refused = OprfResult::<()>::Err(error.clone());
+                let encoded = WireFormat::Cbor.encode(&refused).unwrap();
+                let decoded: OprfResult<OprfResponse> = WireFormat::Cbor.decode(&encoded).unwrap();
+                prop_assert_eq!(decoded.unwrap_err(), error);
+            }
+
+            #[test]
+            fn test_proto_round_trip(
+                request in oprf_request(),
+                response in oprf_response(),
+                batch_request in batch_request(),
+                batch_response in batch_response(),
+                (get_public_key, public_key) in (get_public_key(), public_key_response()),
+                (get_attestation, attestation) in (get_attestation(), attestation_response()),
+                error in error_response(),
+            ) {
+                proto_round_trip(&request)?;
+                proto_round_trip(&response)?;
+                proto_round_trip(&batch_request)?;
+                proto_round_trip(&batch_response)?;
+                proto_round_trip(&get_public_key)?;
+                proto_round_trip(&public_key)?;
+                proto_round_trip(&get_attestation)?;
+                proto_round_trip(&attestation)?;
+                proto_round_trip(&attestation.attestation)?;
+                proto_round_trip(&error)?;
+            }
+
+            #[test]
+            fn test_decode_garbage(garbage in bytes(512)) {
+                // Refused or read, but never a panic
+                for format in [WireFormat::Json, WireFormat::Cbor] {
+                    let _ = format.decode::<Message>(&garbage);
+                    let _ = format.decode::<OprfResult<BatchOprfResponse>>(&garbage);
+                }
+                let _ = OprfRequest::decode(&garbage);
+                let _ = BatchOprfResponse::decode(&garbage);
+                let mut reader = garbage.as_slice();
+                while let Ok(Some(_)) = read_frame(&mut reader) {}
+            }
+
+            #[test]
+            fn test_frame_round_trip(frames in vec(framed(), 1..4)) {
+                let mut stream = Vec::new();
+                for (request_id, format, payload) in &frames {
+                    stream.extend(frame_bytes(*request_id, *format, payload));
+                }
+                let mut reader = stream.as_slice();
+                for (request_id, format, payload) in &frames {
+                    let frame = read_frame(&mut reader).unwrap().unwrap();
+                    prop_assert_eq!(frame.request_id, *request_id);
+                    prop_assert_eq!(frame.format, *format);
+                    prop_assert_eq!(&frame.payload[..], &payload[..]);
+                    prop_assert!(frame.format.decode::<Message>(&frame.payload).is_ok());
+                }
+                prop_assert!(read_frame(&mut reader).unwrap().is_none());
+            }
+
+            #[test]
+            fn test_frame_truncation(
+                (request_id, format, payload) in framed(),
+                cut in any::<Index>(),
+            ) {
+                let stream = frame_bytes(request_id, format, &payload);
+                let cut = cut.index(stream.len());
+                match read_frame(&mut &stream[..cut]) {
+                    Ok(None) => prop_assert_eq!(cut, 0),
+                    Ok(Some(_)) => {
+                        return Err(TestCaseError::fail(format!("read a frame cut at {}", cut)))
+                    }
+                    Err(e) => prop_assert_eq!(e.kind(), ErrorKind::UnexpectedEof),
+                }
+                // Nor does a cut payload decode
+                let cut = cut.min(payload.len().saturating_sub(1));
+                prop_assert!(format.decode::<Message>(&payload[..cut]).is_err());
+            }
+
+            #[test]
+            fn test_frame_corruption(
+                (request_id, format, payload) in framed(),
+                at in any::<Index>(),
+                flip in 1..=u8::MAX,
+            ) {
+                let mut stream = frame_bytes(request_id, format, &payload);
+                let at = at.index(stream.len());
+                stream[at] ^= flip;
+                let result = read_frame(&mut stream.as_slice());
+                match at {
+                    0..=7 => {
+                        let frame = result.unwrap().unwrap();
+                        prop_assert_ne!(frame.request_id, request_id);
+                        prop_assert_eq!(&frame.payload[..], &payload[..]);
+                    }
+                    8 => match WireFormat::from_id(stream[8]) {
+                        Some(other) => prop_assert_eq!(result.unwrap().unwrap().format, other),
+                        None => {
+                            prop_assert_eq!(result.err().unwrap().kind(), ErrorKind::InvalidData)
+                        }
+                    },
+                    9..=12 => {
+                        let len = u32::from_be_bytes(stream[9..13].try_into().unwrap()) as usize;
+                        match result {
+                            Ok(frame) => {
+                                prop_assert!(len < payload.len());
+                                prop_assert_eq!(&frame.unwrap().payload[..], &payload[..len]);
+                            }
+                            Err(_) => prop_assert!(len > payload.len()),
+                        }
+                    }
+                    _ => {
+                        let frame = result.unwrap().unwrap();
+                        prop_assert_eq!(frame.request_id, request_id);
+                        prop_assert_eq!(frame.payload[at - FRAME_HEADER_LEN], stream[at]);
+                        // A corrupt payload may decode, but must not panic
+                        let _ = format.decode::<Message>(&frame.payload);
+                    }
+                }
+            }
+        }
+
+        proptest! {
+            // Hashing to the curve and the proofs make these the slowest cases
+            #![proptest_config(ProptestConfig::with_cases(64))]
+
+            #[test]
+            fn test_blind_evaluate_unblind(
+                seed in any::<u64>(),
+                suite in suite(),
+                inputs in vec(bytes(64), 1..4),
+                encoding in encoding(),
+            ) {
+                match suite {
+                    CiphersuiteId::Bn254Sha256 => {
+                        blind_evaluate_unblind::<Bn254Sha256>(seed, &inputs, encoding)?
+                    }
+                    CiphersuiteId::P256Sha256 => {
+                        blind_evaluate_unblind::<P256Sha256>(seed, &inputs, encoding)?
+                    }
+                }
+            }
+        }
+    }
+}
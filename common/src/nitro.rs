@@ -0,0 +1,761 @@
+//! Verification of NSM attestation documents.
+//!
+//! The Nitro Secure Module signs its documents as COSE_Sign1 (RFC 9052)
+//! with ES384. The payload, a CBOR map, carries the PCRs, the timestamp and
+//! the user data, with the signing certificate and the chain above it
+//! (`cabundle`, root first). [`verify_document`] reads none of the claims
+//! before the document is authenticated:
+//!
+//! 1. The first certificate of the bundle must be a trust anchor, matched by
+//!    the SHA-256 fingerprint of its DER encoding, and sign itself.
+//! 2. Every later certificate, the signing certificate last, must be issued
+//!    and signed by the one before it, which must be a CA. All of them must
+//!    be valid at the time of verification.
+//! 3. The COSE signature must verify with the key of the signing
+//!    certificate.
+//!
+//! The NSM signs everything with ecdsa-with-SHA384 over P-384, the only
+//! algorithm accepted here.
+
+use crate::AttestationError;
+use base64ct::{Base64, Encoding};
+use p384::ecdsa::signature::Verifier;
+use p384::ecdsa::{Signature, VerifyingKey};
+use serde_cbor::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+/// SHA-256 fingerprint of the AWS Nitro Enclaves root certificate (G1), as
+/// published in the AWS Nitro Enclaves documentation
+pub const AWS_NITRO_ROOT_G1_SHA256: &str =
+    "641a0321a3e244efe456463195d606317ed7cdcc3c1756e09893f3c68f79bb5b";
+
+/// The AWS Nitro Enclaves root certificate (G1), `CN=aws.nitro-enclaves`
+pub const AWS_NITRO_ROOT_G1_PEM: &str = "-----BEGIN CERTIFICATE-----
+MIICETCCAZagAwIBAgIRAPkxdWgbkK/hHUbMtOTn+FYwCgYIKoZIzj0EAwMwSTEL
+MAkGA1UEBhMCVVMxDzANBgNVBAoMBkFtYXpvbjEMMAoGA1UECwwDQVdTMRswGQYD
+VQQDDBJhd3Mubml0cm8tZW5jbGF2ZXMwHhcNMTkxMDI4MTMyODA1WhcNNDkxMDI4
+MTQyODA1WjBJMQswCQYDVQQGEwJVUzEPMA0GA1UECgwGQW1hem9uMQwwCgYDVQQL
+DANBV1MxGzAZBgNVBAMMEmF3cy5uaXRyby1lbmNsYXZlczB2MBAGByqGSM49AgEG
+BSuBBAAiA2IABPwCVOumCMHzaHDimtqQvkY4MpJzbolL//Zy2YlES1BR5TSksfbb
+48C8WBoyt7F2Bw7eEtaaP+ohG2bnUs990d0JX28TcPQXCEPZ3BABIeTPYwEoCWZE
+h8l5YoQwTcU/9KNCMEAwDwYDVR0TAQH/BAUwAwEB/zAdBgNVHQ4EFgQUkCW1DdkF
+R+eWw5b6cp3PmanfS5YwDgYDVR0PAQH/BAQDAgGGMAoGCCqGSM49BAMDA2kAMGYC
+MQCjfy+Rocm9Xue4YnwWmNJVA44fA0P5W2OpYow9OYCVRaEevL8uO1XYru5xtMPW
+rfMCMQCi85sWBbJwKKXdS6BptQFuZbT73o/gBh1qUxl/nNr12UO8Yfwr6wPLb+6N
+IwLz3/Y=
+-----END CERTIFICATE-----
+";
+
+/// COSE algorithm identifier of ES384
+const COSE_ALG_ES384: i128 = -35;
+/// COSE header parameter of the algorithm
+const COSE_HEADER_ALG: i128 = 1;
+
+const TAG_BOOLEAN: u8 = 0x01;
+const TAG_INTEGER: u8 = 0x02;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_OID: u8 = 0x06;
+const TAG_UTC_TIME: u8 = 0x17;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_VERSION: u8 = 0xa0;
+const TAG_EXTENSIONS: u8 = 0xa3;
+
+/// ecdsa-with-SHA384, 1.2.840.10045.4.3.3
+const OID_ECDSA_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+/// id-ecPublicKey, 1.2.840.10045.2.1
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+/// secp384r1, 1.3.132.0.34
+const OID_P384: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+/// basicConstraints, 2.5.29.19
+const OID_BASIC_CONSTRAINTS: &[u8] = &[0x55, 0x1d, 0x13];
+/// keyUsage, 2.5.29.15
+const OID_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x0f];
+
+const PEM_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+/// SHA-256 fingerprint of a DER certificate
+pub fn fingerprint(der: &[u8]) -> [u8; 32] {
+    Sha256::digest(der).into()
+}
+
+/// DER encoding of the embedded AWS root, see [`AWS_NITRO_ROOT_G1_PEM`]
+pub fn aws_root() -> Vec<u8> {
+    parse_certificates(AWS_NITRO_ROOT_G1_PEM.as_bytes())
+        .expect("embedded root is PEM")
+        .remove(0)
+}
+
+/// DER certificates of a PEM bundle, or the contents themselves if they are
+/// DER
+pub fn parse_certificates(contents: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    if contents.first() == Some(&TAG_SEQUENCE) {
+        return Ok(vec![contents.to_vec()]);
+    }
+
+    let text = std::str::from_utf8(contents).map_err(|_| "neither PEM nor DER".to_string())?;
+    let mut certificates = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(PEM_BEGIN) {
+        let body = &rest[start + PEM_BEGIN.len()..];
+        let end = body.find(PEM_END).ok_or("unterminated PEM certificate")?;
+        let base64: String = body[..end].split_whitespace().collect();
+        let der = Base64::decode_vec(&base64).map_err(|e| format!("invalid base64: {}", e))?;
+        if der.first() != Some(&TAG_SEQUENCE) {
+            return Err("PEM block is not a DER certificate".to_string());
+        }
+        certificates.push(der);
+        rest = &body[end + PEM_END.len()..];
+    }
+    if certificates.is_empty() {
+        return Err("no certificate found".to_string());
+    }
+    Ok(certificates)
+}
+
+/// The claims of an authenticated NSM attestation document
+#[derive(Debug, Clone)]
+pub struct NsmDocument {
+    /// Enclave the document was issued to
+    pub module_id: String,
+    /// Creation time, unix milliseconds
+    pub timestamp_ms: u64,
+    /// PCR values by index
+    pub pcrs: BTreeMap<u64, Vec<u8>>,
+    /// User data, see [`crate::nsm_user_data`]
+    pub user_data: Option<Vec<u8>>,
+    pub nonce: Option<Vec<u8>>,
+    pub public_key: Option<Vec<u8>>,
+    /// Fingerprint of the trust anchor the document chains to
+    pub root: [u8; 32],
+}
+
+/// Authenticate an NSM attestation document with the certificate chain to
+/// one of `trusted_roots`, checked at unix time `now_secs`, and return its
+/// claims
+pub fn verify_document(
+    document: &[u8],
+    trusted_roots: &[[u8; 32]],
+    now_secs: u64,
+) -> Result<NsmDocument, AttestationError> {
+    let sign1 = CoseSign1::decode(document)?;
+    let payload = Payload::decode(&sign1.payload)?;
+
+    let root = fingerprint(&payload.cabundle[0]);
+    if !trusted_roots.contains(&root) {
+        return Err(AttestationError::UntrustedRoot(hex::encode(root)));
+    }
+    let chain = payload
+        .cabundle
+        .iter()
+        .chain([&payload.certificate])
+        .map(|der| Certificate::from_der(der))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AttestationError::MalformedDocument(format!("certificate: {}", e)))?;
+    verify_chain(&chain, now_secs)
+        .map_err(|e| AttestationError::SignatureInvalid(format!("certificate chain: {}", e)))?;
+
+    let leaf = chain.last().expect("chain holds the signing certificate");
+    let signature = Signature::from_slice(&sign1.signature)
+        .map_err(|_| AttestationError::SignatureInvalid("malformed ES384 signature".to_string()))?;
+    leaf.public_key
+        .verify(&sig_structure(&sign1.protected, &sign1.payload), &signature)
+        .map_err(|_| {
+            AttestationError::SignatureInvalid("COSE signature does not verify".to_string())
+        })?;
+
+    Ok(NsmDocument {
+        module_id: payload.module_id,
+        timestamp_ms: payload.timestamp_ms,
+        pcrs: payload.pcrs,
+        user_data: payload.user_data,
+        nonce: payload.nonce,
+        public_key: payload.public_key,
+        root,
+    })
+}
+
+/// `Sig_structure` of a COSE_Sign1 without external data, the bytes signed
+fn sig_structure(protected: &[u8], payload: &[u8]) -> Vec<u8> {
+    let structure = Value::Array(vec![
+        Value::Text("Signature1".to_string()),
+        Value::Bytes(protected.to_vec()),
+        Value::Bytes(Vec::new()),
+        Value::Bytes(payload.to_vec()),
+    ]);
+    serde_cbor::to_vec(&structure).expect("CBOR values serialize")
+}
+
+fn malformed(what: &str) -> AttestationError {
+    AttestationError::MalformedDocument(what.to_string())
+}
+
+/// `COSE_Sign1 = [protected, unprotected, payload, signature]`, maybe tagged
+struct CoseSign1 {
+    protected: Vec<u8>,
+    payload: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl CoseSign1 {
+    fn decode(document: &[u8]) -> Result<Self, AttestationError> {
+        let document: Value =
+            serde_cbor::from_slice(document).map_err(|e| malformed(&e.to_string()))?;
+        let document = match document {
+            Value::Tag(_, inner) => *inner,
+            document => document,
+        };
+        let [protected, _, payload, signature] = match document {
+            Value::Array(items) => <[Value; 4]>::try_from(items)
+                .map_err(|_| malformed("not a COSE_Sign1 structure"))?,
+            _ => return Err(malformed("not a COSE_Sign1 structure")),
+        };
+        let (Value::Bytes(protected), Value::Bytes(payload), Value::Bytes(signature)) =
+            (protected, payload, signature)
+        else {
+            return Err(malformed("COSE_Sign1 fields are not byte strings"));
+        };
+
+        let header: Value =
+            serde_cbor::from_slice(&protected).map_err(|e| malformed(&e.to_string()))?;
+        let alg = match &header {
+            Value::Map(header) => header.get(&Value::Integer(COSE_HEADER_ALG)),
+            _ => None,
+        };
+        if alg != Some(&Value::Integer(COSE_ALG_ES384)) {
+            return Err(AttestationError::SignatureInvalid(
+                "COSE algorithm is not ES384".to_string(),
+            ));
+        }
+        Ok(Self { protected, payload, signature })
+    }
+}
+
+/// The CBOR payload of an NSM document, not yet authenticated
+struct Payload {
+    module_id: String,
+    timestamp_ms: u64,
+    pcrs: BTreeMap<u64, Vec<u8>>,
+    certificate: Vec<u8>,
+    /// Never empty
+    cabundle: Vec<Vec<u8>>,
+    user_data: Option<Vec<u8>>,
+    nonce: Option<Vec<u8>>,
+    public_key: Option<Vec<u8>>,
+}
+
+impl Payload {
+    fn decode(payload: &[u8]) -> Result<Self, AttestationError> {
+        let Value::Map(mut map) =
+            serde_cbor::from_slice(payload).map_err(|e| malformed(&e.to_string()))?
+        else {
+            return Err(malformed("payload is not a map"));
+        };
+        let mut field = |name: &str| map.remove(&Value::Text(name.to_string()));
+        let optional_bytes = |value: Option<Value>, name: &str| match value {
+            Some(Value::Bytes(bytes)) => Ok(Some(bytes)),
+            Some(Value::Null) | None => Ok(None),
+            Some(_) => Err(malformed(&format!("{} is not a byte string", name))),
+        };
+
+        let module_id = match field("module_id") {
+            Some(Value::Text(module_id)) => module_id,
+            _ => return Err(malformed("payload has no module id")),
+        };
+        if field("digest") != Some(Value::Text("SHA384".to_string())) {
+            return Err(malformed("PCR digest is not SHA384"));
+        }
+        let timestamp_ms = match field("timestamp") {
+            Some(Value::Integer(timestamp)) => {
+                u64::try_from(timestamp).map_err(|_| malformed("timestamp out of range"))?
+            }
+            _ => return Err(malformed("payload has no timestamp")),
+        };
+        let pcrs = match field("pcrs") {
+            Some(Value::Map(pcrs)) => pcrs
+                .into_iter()
+                .map(|(index, pcr)| match (index, pcr) {
+                    (Value::Integer(index), Value::Bytes(pcr)) if index >= 0 => {
+                        Ok((index as u64, pcr))
+                    }
+                    _ => Err(malformed("malformed PCR entry")),
+                })
+                .collect::<Result<_, _>>()?,
+            _ => return Err(malformed("payload has no PCRs")),
+        };
+        let certificate = match field("certificate") {
+            Some(Value::Bytes(certificate)) => certificate,
+            _ => return Err(malformed("payload has no signing certificate")),
+        };
+        let cabundle = match field("cabundle") {
+            Some(Value::Array(bundle)) if !bundle.is_empty() => bundle
+                .into_iter()
+                .map(|certificate| match certificate {
+                    Value::Bytes(der) => Ok(der),
+                    _ => Err(malformed("CA bundle entry is not a byte string")),
+                })
+                .collect::<Result<_, _>>()?,
+            _ => return Err(malformed("payload has no CA bundle")),
+        };
+        let user_data = optional_bytes(field("user_data"), "user data")?;
+        let nonce = optional_bytes(field("nonce"), "nonce")?;
+        let public_key = optional_bytes(field("public_key"), "public key")?;
+
+        Ok(Self {
+            module_id,
+            timestamp_ms,
+            pcrs,
+            certificate,
+            cabundle,
+            user_data,
+            nonce,
+            public_key,
+        })
+    }
+}
+
+/// Verify `chain`, root first, at unix time `now`: the root signs itself,
+/// every certificate is valid and issued by the one before it, and issuers
+/// are CAs whose path length constraints hold
+fn verify_chain(chain: &[Certificate], now: u64) -> Result<(), String> {
+    let root = chain.first().ok_or("empty certificate chain")?;
+    root.verify_issued_by(root).map_err(|e| format!("root: {}", e))?;
+    for (index, certificate) in chain.iter().enumerate() {
+        if now < certificate.not_before || now > certificate.not_after {
+            return Err(format!("certificate {} is not valid now", index));
+        }
+        if index == 0 {
+            continue;
+        }
+        let issuer = &chain[index - 1];
+        certificate
+            .verify_issued_by(issuer)
+            .map_err(|e| format!("certificate {}: {}", index, e))?;
+        // CA certificates below the issuer, not counting the signing one
+        let below = chain.len() - 1 - index;
+        if issuer.path_len.is_some_and(|limit| below as u64 > limit) {
+            return Err(format!("certificate {} exceeds its path length", index - 1));
+        }
+    }
+    Ok(())
+}
+
+/// One DER TLV
+#[derive(Debug, Clone, Copy)]
+struct Tlv<'a> {
+    tag: u8,
+    value: &'a [u8],
+    /// The whole encoding, tag and length included
+    raw: &'a [u8],
+}
+
+/// Reader over a sequence of DER TLVs
+struct Der<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Der<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Next TLV, whatever its tag
+    fn next(&mut self) -> Result<Tlv<'a>, String> {
+        let truncated = || "truncated DER".to_string();
+        let tag = *self.data.first().ok_or_else(truncated)?;
+        let first = *self.data.get(1).ok_or_else(truncated)?;
+        let (len, header) = if first < 0x80 {
+            (first as usize, 2)
+        } else {
+            let count = (first & 0x7f) as usize;
+            if count == 0 || count > 4 {
+                return Err("unsupported DER length".to_string());
+            }
+            let bytes = self.data.get(2..2 + count).ok_or_else(truncated)?;
+            (bytes.iter().fold(0usize, |len, b| len << 8 | *b as usize), 2 + count)
+        };
+        let end = header.checked_add(len).ok_or_else(truncated)?;
+        let raw = self.data.get(..end).ok_or_else(truncated)?;
+        self.data = &self.data[end..];
+        Ok(Tlv { tag, value: &raw[header..], raw })
+    }
+
+    /// Next TLV, which must have tag `tag`
+    fn expect(&mut self, tag: u8) -> Result<Tlv<'a>, String> {
+        let tlv = self.next()?;
+        if tlv.tag != tag {
+            return Err(format!("expected DER tag {:#04x}, got {:#04x}", tag, tlv.tag));
+        }
+        Ok(tlv)
+    }
+
+    /// Next TLV if it has tag `tag`
+    fn optional(&mut self, tag: u8) -> Result<Option<Tlv<'a>>, String> {
+        if self.data.first() == Some(&tag) {
+            self.next().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// The parts of an X.509 certificate chain verification uses
+struct Certificate {
+    tbs: Vec<u8>,
+    /// DER ECDSA-Sig-Value
+    signature: Vec<u8>,
+    issuer: Vec<u8>,
+    subject: Vec<u8>,
+    /// Validity period, unix seconds
+    not_before: u64,
+    not_after: u64,
+    public_key: VerifyingKey,
+    /// Whether basicConstraints marks it a CA
+    ca: bool,
+    /// Most CA certificates that may follow it in a chain
+    path_len: Option<u64>,
+    /// Whether keyUsage, if present, allows signing certificates
+    key_cert_sign: bool,
+}
+
+impl Certificate {
+    /// Parse a DER certificate signed with ecdsa-with-SHA384 for a P-384 key
+    fn from_der(der: &[u8]) -> Result<Self, String> {
+        let mut outer = Der::new(der);
+        let signed = outer.expect(TAG_SEQUENCE)?;
+        if !outer.is_empty() {
+            return Err("trailing data after the certificate".to_string());
+        }
+        let mut signed = Der::new(signed.value);
+        let tbs = signed.expect(TAG_SEQUENCE)?;
+        let algorithm = signed.expect(TAG_SEQUENCE)?;
+        if Der::new(algorithm.value).expect(TAG_OID)?.value != OID_ECDSA_SHA384 {
+            return Err("signature algorithm is not ecdsa-with-SHA384".to_string());
+        }
+        let signature = signed.expect(TAG_BIT_STRING)?.value;
+        let signature = signature.strip_prefix(&[0]).ok_or("invalid signature bit string")?;
+
+        let mut fields = Der::new(tbs.value);
+        fields.optional(TAG_VERSION)?;
+        fields.expect(TAG_INTEGER)?;
+        if fields.expect(TAG_SEQUENCE)?.value != algorithm.value {
+            return Err("inner and outer signature algorithms differ".to_string());
+        }
+        let issuer = fields.expect(TAG_SEQUENCE)?.raw.to_vec();
+        let mut validity = Der::new(fields.expect(TAG_SEQUENCE)?.value);
+        let not_before = parse_time(validity.next()?)?;
+        let not_after = parse_time(validity.next()?)?;
+        let subject = fields.expect(TAG_SEQUENCE)?.raw.to_vec();
+
+        let mut spki = Der::new(fields.expect(TAG_SEQUENCE)?.value);
+        let mut key_algorithm = Der::new(spki.expect(TAG_SEQUENCE)?.value);
+        if key_algorithm.expect(TAG_OID)?.value != OID_EC_PUBLIC_KEY
+            || key_algorithm.expect(TAG_OID)?.value != OID_P384
+        {
+            return Err("key is not on P-384".to_string());
+        }
+        let key = spki.expect(TAG_BIT_STRING)?.value;
+        let key = key.strip_prefix(&[0]).ok_or("invalid key bit string")?;
+        let public_key = VerifyingKey::from_sec1_bytes(key).map_err(|_| "invalid P-384 key")?;
+
+        let mut certificate = Self {
+            tbs: tbs.raw.to_vec(),
+            signature: signature.to_vec(),
+            issuer,
+            subject,
+            not_before,
+            not_after,
+            public_key,
+            ca: false,
+            path_len: None,
+            key_cert_sign: true,
+        };
+        while !fields.is_empty() {
+            let field = fields.next()?;
+            if field.tag == TAG_EXTENSIONS {
+                certificate.read_extensions(field.value)?;
+            }
+        }
+        Ok(certificate)
+    }
+
+    /// Read basicConstraints and keyUsage, rejecting unknown critical
+    /// extensions
+    fn read_extensions(&mut self, extensions: &[u8]) -> Result<(), String> {
+        let mut list = Der::new(Der::new(extensions).expect(TAG_SEQUENCE)?.value);
+        while !list.is_empty() {
+            let mut extension = Der::new(list.expect(TAG_SEQUENCE)?.value);
+            let oid = extension.expect(TAG_OID)?.value;
+            let critical = match extension.optional(TAG_BOOLEAN)? {
+                Some(critical) => critical.value != [0],
+                None => false,
+            };
+            let value = extension.expect(TAG_OCTET_STRING)?.value;
+            match oid {
+                OID_BASIC_CONSTRAINTS => {
+                    let mut constraints = Der::new(Der::new(value).expect(TAG_SEQUENCE)?.value);
+                    self.ca = constraints
+                        .optional(TAG_BOOLEAN)?
+                        .is_some_and(|ca| ca.value != [0]);
+                    if let Some(limit) = constraints.optional(TAG_INTEGER)? {
+                        let limit = match limit.value {
+                            [limit] if *limit < 0x80 => *limit as u64,
+                            _ => return Err("unsupported path length".to_string()),
+                        };
+                        self.path_len = Some(limit);
+                    }
+                }
+                OID_KEY_USAGE => {
+                    let bits = Der::new(value).expect(TAG_BIT_STRING)?.value;
+                    // keyCertSign is bit 5, of the first byte after the
+                    // count of unused bits
+                    self.key_cert_sign = bits.get(1).is_some_and(|bits| bits & 0x04 != 0);
+                }
+                _ if critical => return Err("unknown critical extension".to_string()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that `issuer` issued and signed this certificate
+    fn verify_issued_by(&self, issuer: &Certificate) -> Result<(), String> {
+        if self.issuer != issuer.subject {
+            return Err("issuer does not match the certificate above".to_string());
+        }
+        if !issuer.ca || !issuer.key_cert_sign {
+            return Err("issuer is not a CA".to_string());
+        }
+        let signature = Signature::from_der(&self.signature).map_err(|_| "malformed signature")?;
+        issuer
+            .public_key
+            .verify(&self.tbs, &signature)
+            .map_err(|_| "signature does not verify".to_string())
+    }
+}
+
+/// UTCTime or GeneralizedTime in UTC as unix seconds
+fn parse_time(tlv: Tlv) -> Result<u64, String> {
+    let text = std::str::from_utf8(tlv.value).map_err(|_| "invalid certificate time")?;
+    let digits = text.strip_suffix('Z').ok_or("certificate time is not UTC")?;
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("invalid certificate time {:?}", text));
+    }
+    let full = match (tlv.tag, digits.len()) {
+        (TAG_UTC_TIME, 12) => {
+            // Two-digit years 50-99 are 19xx
+            let century = if &digits[..2] >= "50" { "19" } else { "20" };
+            format!("{}{}", century, digits)
+        }
+        (TAG_GENERALIZED_TIME, 14) => digits.to_string(),
+        _ => return Err(format!("unsupported certificate time {:?}", text)),
+    };
+    let field = |range: std::ops::Range<usize>| full[range].parse::<u64>().unwrap_or(0);
+    let (month, day) = (field(4..6), field(6..8));
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(format!("invalid certificate time {:?}", text));
+    }
+    Ok(unix_time(
+        field(0..4),
+        month,
+        day,
+        field(8..10) * 3600 + field(10..12) * 60 + field(12..14),
+    ))
+}
+
+/// Unix seconds of a proleptic Gregorian date plus `seconds` into the day
+fn unix_time(year: u64, month: u64, day: u64, seconds: u64) -> u64 {
+    // Days from civil, counting years from March so leap days come last
+    let year = if month <= 2 { year.saturating_sub(1) } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146_097 + day_of_era).saturating_sub(719_468);
+    days * 86_400 + seconds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::TestAuthority;
+    use super::*;
+
+    /// 2026-01-01, within the validity of the test certificates
+    const NOW: u64 = 1_767_225_600;
+
+    fn pcrs() -> BTreeMap<u64, Vec<u8>> {
+        (0..3).map(|index| (index, vec![index as u8; 48])).collect()
+    }
+
+    /// Re-encode `document` with its payload passed through `edit`, keeping
+    /// the signature
+    fn tamper(document: &[u8], edit: impl FnOnce(&mut BTreeMap<Value, Value>)) -> Vec<u8> {
+        let Value::Array(mut items) = serde_cbor::from_slice(document).unwrap() else {
+            panic!("not an array");
+        };
+        let Value::Bytes(payload) = &items[2] else { panic!("no payload") };
+        let Value::Map(mut payload) = serde_cbor::from_slice(payload).unwrap() else {
+            panic!("payload is not a map");
+        };
+        edit(&mut payload);
+        items[2] = Value::Bytes(serde_cbor::to_vec(&Value::Map(payload)).unwrap());
+        serde_cbor::to_vec(&Value::Array(items)).unwrap()
+    }
+
+    #[test]
+    fn test_aws_root() {
+        let root = aws_root();
+        assert_eq!(hex::encode(fingerprint(&root)), AWS_NITRO_ROOT_G1_SHA256);
+        let root = Certificate::from_der(&root).unwrap();
+        assert!(root.ca);
+        root.verify_issued_by(&root).unwrap();
+        verify_chain(&[root], NOW).unwrap();
+    }
+
+    #[test]
+    fn test_verify_document() {
+        let authority = TestAuthority::new(1);
+        let document = authority.document(&pcrs(), 1_000, b"user data");
+        let claims = verify_document(&document, &[authority.root_fingerprint()], NOW).unwrap();
+        assert_eq!(claims.pcrs, pcrs());
+        assert_eq!(claims.timestamp_ms, 1_000);
+        assert_eq!(claims.user_data.as_deref(), Some(&b"user data"[..]));
+        assert_eq!(claims.root, authority.root_fingerprint());
+
+        // Tagged COSE_Sign1 (tag 18) is accepted too
+        let mut tagged = vec![0xd2];
+        tagged.extend_from_slice(&document);
+        assert!(verify_document(&tagged, &[authority.root_fingerprint()], NOW).is_ok());
+    }
+
+    #[test]
+    fn test_untrusted_root() {
+        let authority = TestAuthority::new(1);
+        let document = authority.document(&pcrs(), 1_000, b"");
+        let other = TestAuthority::new(2).root_fingerprint();
+        assert!(matches!(
+            verify_document(&document, &[other], NOW),
+            Err(AttestationError::UntrustedRoot(_))
+        ));
+        assert!(matches!(
+            verify_document(&document, &[], NOW),
+            Err(AttestationError::UntrustedRoot(_))
+        ));
+    }
+
+    #[test]
+    fn test_tampered_payload() {
+        let authority = TestAuthority::new(1);
+        let trusted = [authority.root_fingerprint()];
+        let document = authority.document(&pcrs(), 1_000, b"user data");
+
+        for edit in [
+            Box::new(|payload: &mut BTreeMap<Value, Value>| {
+                let mut pcrs = pcrs();
+                pcrs.insert(0, vec![0xff; 48]);
+                let pcrs = pcrs
+                    .into_iter()
+                    .map(|(index, pcr)| (Value::Integer(index as i128), Value::Bytes(pcr)));
+                payload.insert(Value::Text("pcrs".into()), Value::Map(pcrs.collect()));
+            }) as Box<dyn FnOnce(&mut BTreeMap<Value, Value>)>,
+            Box::new(|payload| {
+                payload.insert(Value::Text("timestamp".into()), Value::Integer(2_000));
+            }),
+            Box::new(|payload| {
+                payload.insert(Value::Text("user_data".into()), Value::Bytes(b"other".to_vec()));
+            }),
+        ] {
+            let tampered = tamper(&document, edit);
+            assert!(matches!(
+                verify_document(&tampered, &trusted, NOW),
+                Err(AttestationError::SignatureInvalid(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_broken_chain() {
+        let authority = TestAuthority::new(1);
+        let other = TestAuthority::new(2);
+        let trusted = [authority.root_fingerprint()];
+
+        // A signing certificate from another authority, under the trusted
+        // root and an intermediate of the same name
+        let document = other.document(&pcrs(), 1_000, b"");
+        let swapped = tamper(&document, |payload| {
+            let bundle = authority.cabundle().into_iter().map(Value::Bytes).collect();
+            payload.insert(Value::Text("cabundle".into()), Value::Array(bundle));
+        });
+        assert!(matches!(
+            verify_document(&swapped, &trusted, NOW),
+            Err(AttestationError::SignatureInvalid(_))
+        ));
+
+        // The intermediate left out of the bundle
+        let document = authority.document(&pcrs(), 1_000, b"");
+        let shortened = tamper(&document, |payload| {
+            let bundle = vec![Value::Bytes(authority.root().to_vec())];
+            payload.insert(Value::Text("cabundle".into()), Value::Array(bundle));
+        });
+        assert!(matches!(
+            verify_document(&shortened, &trusted, NOW),
+            Err(AttestationError::SignatureInvalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_expired_certificate() {
+        let authority = TestAuthority::with_leaf_validity(1, "20250101000000Z", "20250102000000Z");
+        let document = authority.document(&pcrs(), 1_000, b"");
+        let trusted = [authority.root_fingerprint()];
+        assert!(matches!(
+            verify_document(&document, &trusted, NOW),
+            Err(AttestationError::SignatureInvalid(_))
+        ));
+        let during = unix_time(2025, 1, 1, 12 * 3600);
+        assert!(verify_document(&document, &trusted, during).is_ok());
+    }
+
+    #[test]
+    fn test_malformed_documents() {
+        let authority = TestAuthority::new(1);
+        let trusted = [authority.root_fingerprint()];
+        let document = authority.document(&pcrs(), 1_000, b"");
+
+        for malformed in [&b""[..], b"\x80", &document[..document.len() - 1]] {
+            assert!(matches!(
+                verify_document(malformed, &trusted, NOW),
+                Err(AttestationError::MalformedDocument(_))
+            ));
+        }
+        let no_bundle = tamper(&document, |payload| {
+            payload.insert(Value::Text("cabundle".into()), Value::Array(Vec::new()));
+        });
+        assert!(matches!(
+            verify_document(&no_bundle, &trusted, NOW),
+            Err(AttestationError::MalformedDocument(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let utc = Tlv { tag: TAG_UTC_TIME, value: b"491028142805Z", raw: &[] };
+        assert_eq!(parse_time(utc).unwrap(), unix_time(2049, 10, 28, 14 * 3600 + 28 * 60 + 5));
+        let utc = Tlv { tag: TAG_UTC_TIME, value: b"700101000000Z", raw: &[] };
+        assert_eq!(parse_time(utc).unwrap(), 0);
+        let generalized = Tlv { tag: TAG_GENERALIZED_TIME, value: b"20260101000000Z", raw: &[] };
+        assert_eq!(parse_time(generalized).unwrap(), NOW);
+        let local = Tlv { tag: TAG_GENERALIZED_TIME, value: b"20260101000000", raw: &[] };
+        assert!(parse_time(local).is_err());
+    }
+}
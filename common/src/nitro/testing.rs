@@ -0,0 +1,224 @@
+//! Certificate authorities issuing NSM-shaped attestation documents, for the
+//! tests of verifiers.
+//!
+//! A [`TestAuthority`] holds a root, an intermediate and a signing
+//! certificate, all ecdsa-with-SHA384 over P-384 like the NSM's, with keys
+//! derived from a seed. Its documents verify with [`super::verify_document`]
+//! given [`TestAuthority::root_fingerprint`] as the trust anchor. Built with
+//! the `testing` feature.
+
+use super::{
+    fingerprint, sig_structure, COSE_ALG_ES384, COSE_HEADER_ALG, OID_BASIC_CONSTRAINTS,
+    OID_EC_PUBLIC_KEY, OID_ECDSA_SHA384, OID_P384, TAG_BIT_STRING, TAG_BOOLEAN,
+    TAG_EXTENSIONS, TAG_GENERALIZED_TIME, TAG_INTEGER, TAG_OCTET_STRING, TAG_OID, TAG_SEQUENCE,
+    TAG_VERSION,
+};
+use p384::ecdsa::signature::Signer;
+use p384::ecdsa::{Signature, SigningKey};
+use serde_cbor::Value;
+use std::collections::BTreeMap;
+
+/// Validity of the certificates unless given otherwise
+const NOT_BEFORE: &str = "20200101000000Z";
+const NOT_AFTER: &str = "20491231235959Z";
+
+const TAG_SET: u8 = 0x31;
+const TAG_UTF8_STRING: u8 = 0x0c;
+/// commonName, 2.5.4.3
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+
+/// A root, intermediate and signing certificate with their keys
+pub struct TestAuthority {
+    root: Vec<u8>,
+    intermediate: Vec<u8>,
+    leaf: Vec<u8>,
+    leaf_key: SigningKey,
+}
+
+impl TestAuthority {
+    /// Certificates valid from 2020 to 2049, with keys derived from `seed`
+    pub fn new(seed: u8) -> Self {
+        Self::with_leaf_validity(seed, NOT_BEFORE, NOT_AFTER)
+    }
+
+    /// The same, with the signing certificate valid between two
+    /// GeneralizedTimes (`YYYYMMDDHHMMSSZ`)
+    pub fn with_leaf_validity(seed: u8, not_before: &str, not_after: &str) -> Self {
+        let key = |index: u8| {
+            let mut bytes = [seed; 48];
+            bytes[47] = index;
+            SigningKey::from_slice(&bytes).expect("seed makes a valid key")
+        };
+        let (root_key, intermediate_key, leaf_key) = (key(1), key(2), key(3));
+        let validity = (NOT_BEFORE, NOT_AFTER);
+        let root = certificate("Test Root", &root_key, "Test Root", &root_key, true, validity);
+        let intermediate = certificate(
+            "Test Intermediate",
+            &intermediate_key,
+            "Test Root",
+            &root_key,
+            true,
+            validity,
+        );
+        let leaf = certificate(
+            "Test Enclave",
+            &leaf_key,
+            "Test Intermediate",
+            &intermediate_key,
+            false,
+            (not_before, not_after),
+        );
+        Self { root, intermediate, leaf, leaf_key }
+    }
+
+    /// DER encoding of the root certificate
+    pub fn root(&self) -> &[u8] {
+        &self.root
+    }
+
+    /// Fingerprint of the root, the trust anchor of its documents
+    pub fn root_fingerprint(&self) -> [u8; 32] {
+        fingerprint(&self.root)
+    }
+
+    /// The CA bundle of its documents, root first
+    pub fn cabundle(&self) -> Vec<Vec<u8>> {
+        vec![self.root.clone(), self.intermediate.clone()]
+    }
+
+    /// Payload of a document attesting `pcrs` and `user_data` at
+    /// `timestamp_ms`
+    pub fn payload(
+        &self,
+        pcrs: &BTreeMap<u64, Vec<u8>>,
+        timestamp_ms: u64,
+        user_data: &[u8],
+    ) -> BTreeMap<Value, Value> {
+        let text = |text: &str| Value::Text(text.to_string());
+        let pcrs = pcrs
+            .iter()
+            .map(|(index, pcr)| (Value::Integer(*index as i128), Value::Bytes(pcr.clone())));
+        let cabundle = self.cabundle().into_iter().map(Value::Bytes).collect();
+        BTreeMap::from([
+            (text("module_id"), text("i-0123456789abcdef0-enc0123456789abcdef")),
+            (text("digest"), text("SHA384")),
+            (text("timestamp"), Value::Integer(timestamp_ms as i128)),
+            (text("pcrs"), Value::Map(pcrs.collect())),
+            (text("certificate"), Value::Bytes(self.leaf.clone())),
+            (text("cabundle"), Value::Array(cabundle)),
+            (text("public_key"), Value::Null),
+            (text("user_data"), Value::Bytes(user_data.to_vec())),
+            (text("nonce"), Value::Null),
+        ])
+    }
+
+    /// Sign `payload` as a COSE_Sign1 with the signing certificate's key
+    pub fn sign(&self, payload: &BTreeMap<Value, Value>) -> Vec<u8> {
+        let header = BTreeMap::from([(
+            Value::Integer(COSE_HEADER_ALG),
+            Value::Integer(COSE_ALG_ES384),
+        )]);
+        let protected = serde_cbor::to_vec(&Value::Map(header)).expect("CBOR serializes");
+        let payload = serde_cbor::to_vec(&Value::Map(payload.clone())).expect("CBOR serializes");
+        let signature: Signature = self.leaf_key.sign(&sig_structure(&protected, &payload));
+        let sign1 = Value::Array(vec![
+            Value::Bytes(protected),
+            Value::Map(BTreeMap::new()),
+            Value::Bytes(payload),
+            Value::Bytes(signature.to_bytes().to_vec()),
+        ]);
+        serde_cbor::to_vec(&sign1).expect("CBOR serializes")
+    }
+
+    /// A signed document attesting `pcrs` and `user_data` at `timestamp_ms`
+    pub fn document(
+        &self,
+        pcrs: &BTreeMap<u64, Vec<u8>>,
+        timestamp_ms: u64,
+        user_data: &[u8],
+    ) -> Vec<u8> {
+        self.sign(&self.payload(pcrs, timestamp_ms, user_data))
+    }
+}
+
+/// DER TLV of `tag` around the concatenated `parts`
+fn der(tag: u8, parts: &[&[u8]]) -> Vec<u8> {
+    let len: usize = parts.iter().map(|part| part.len()).sum();
+    let mut encoded = vec![tag];
+    match len {
+        0..=0x7f => encoded.push(len as u8),
+        0x80..=0xff => encoded.extend([0x81, len as u8]),
+        _ => encoded.extend([0x82, (len >> 8) as u8, len as u8]),
+    }
+    for part in parts {
+        encoded.extend_from_slice(part);
+    }
+    encoded
+}
+
+/// Name with a single common name
+fn name(common_name: &str) -> Vec<u8> {
+    let attribute = der(
+        TAG_SEQUENCE,
+        &[&der(TAG_OID, &[OID_COMMON_NAME]), &der(TAG_UTF8_STRING, &[common_name.as_bytes()])],
+    );
+    der(TAG_SEQUENCE, &[&der(TAG_SET, &[&attribute])])
+}
+
+/// Certificate for `subject` and its key, issued by `issuer` and signed with
+/// `issuer_key`
+fn certificate(
+    subject: &str,
+    key: &SigningKey,
+    issuer: &str,
+    issuer_key: &SigningKey,
+    ca: bool,
+    (not_before, not_after): (&str, &str),
+) -> Vec<u8> {
+    let algorithm = der(TAG_SEQUENCE, &[&der(TAG_OID, &[OID_ECDSA_SHA384])]);
+    let key_algorithm =
+        der(TAG_SEQUENCE, &[&der(TAG_OID, &[OID_EC_PUBLIC_KEY]), &der(TAG_OID, &[OID_P384])]);
+    let point = key.verifying_key().to_encoded_point(false);
+    let key_bits = der(TAG_BIT_STRING, &[&[0], point.as_bytes()]);
+    let spki = der(TAG_SEQUENCE, &[&key_algorithm, &key_bits]);
+    let validity = der(
+        TAG_SEQUENCE,
+        &[
+            &der(TAG_GENERALIZED_TIME, &[not_before.as_bytes()]),
+            &der(TAG_GENERALIZED_TIME, &[not_after.as_bytes()]),
+        ],
+    );
+    let constraints = if ca {
+        der(TAG_SEQUENCE, &[&der(TAG_BOOLEAN, &[&[0xff]])])
+    } else {
+        der(TAG_SEQUENCE, &[])
+    };
+    let basic_constraints = der(
+        TAG_SEQUENCE,
+        &[
+            &der(TAG_OID, &[OID_BASIC_CONSTRAINTS]),
+            &der(TAG_BOOLEAN, &[&[0xff]]),
+            &der(TAG_OCTET_STRING, &[&constraints]),
+        ],
+    );
+    let extensions = der(TAG_EXTENSIONS, &[&der(TAG_SEQUENCE, &[&basic_constraints])]);
+    let serial = fingerprint(subject.as_bytes());
+    let tbs = der(
+        TAG_SEQUENCE,
+        &[
+            &der(TAG_VERSION, &[&der(TAG_INTEGER, &[&[2]])]),
+            &der(TAG_INTEGER, &[&[0x01], &serial[..15]]),
+            &algorithm,
+            &name(issuer),
+            &validity,
+            &name(subject),
+            &spki,
+            &extensions,
+        ],
+    );
+    let signature: Signature = issuer_key.sign(&tbs);
+    der(
+        TAG_SEQUENCE,
+        &[&tbs, &algorithm, &der(TAG_BIT_STRING, &[&[0], signature.to_der().as_bytes()])],
+    )
+}
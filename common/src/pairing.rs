@@ -0,0 +1,121 @@
+//! Pairing-based verification of BN254 evaluations.
+//!
+//! Alongside `g^k` the enclave can publish `pk2 = g2^k` in G2. Anyone holding
+//! `pk2` can then check an evaluation with `e(evaluated, g2) == e(blinded, pk2)`,
+//! which makes evaluations publicly verifiable without any per-request prover
+//! work. This only applies to the BN254 ciphersuite.
+
+use crate::{ct, OprfError};
+use ark_bn254::{Bn254, Fr, G1Projective, G2Affine, G2Projective};
+use ark_ec::pairing::Pairing;
+use ark_ec::{AffineRepr, CurveGroup, Group};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_std::rand::Rng;
+use ark_std::{UniformRand, Zero};
+
+/// Get the G2 generator
+pub fn g2_generator() -> G2Projective {
+    G2Projective::generator()
+}
+
+/// Compute the G2 public key g2^k, in time independent of `secret_key`
+pub fn public_key_g2(secret_key: &Fr) -> G2Projective {
+    ct::mul(&g2_generator(), secret_key)
+}
+
+/// Serialize a G2 point to bytes (compressed, 64 bytes)
+pub fn serialize_g2(point: &G2Projective) -> Result<Vec<u8>, OprfError> {
+    let mut bytes = Vec::new();
+    point
+        .into_affine()
+        .serialize_compressed(&mut bytes)
+        .map_err(|e| OprfError::Serialization(e.to_string()))?;
+    Ok(bytes)
+}
+
+/// Deserialize a G2 point from bytes
+pub fn deserialize_g2(bytes: &[u8]) -> Result<G2Projective, OprfError> {
+    let affine = G2Affine::deserialize_compressed(bytes)
+        .map_err(|e| OprfError::InvalidPoint(e.to_string()))?;
+    if affine.is_zero() {
+        return Err(OprfError::InvalidPoint("identity element".to_string()));
+    }
+    if serialize_g2(&affine.into_group())? != bytes {
+        return Err(OprfError::InvalidPoint("non-canonical encoding".to_string()));
+    }
+    Ok(affine.into_group())
+}
+
+/// Check that `public_key == g^k` and `public_key_g2 == g2^k` share the same
+/// `k`, i.e. `e(public_key, g2) == e(g, public_key_g2)`
+pub fn verify_key_pair(
+    public_key: &G1Projective,
+    public_key_g2: &G2Projective,
+) -> Result<(), OprfError> {
+    let check = Bn254::multi_pairing(
+        [*public_key, -G1Projective::generator()],
+        [g2_generator(), *public_key_g2],
+    );
+    if !check.is_zero() {
+        return Err(OprfError::InvalidProof);
+    }
+    Ok(())
+}
+
+/// Check that every `evaluated[i] == blinded[i]^k` where `public_key_g2 == g2^k`.
+///
+/// The pairs are combined with random weights from `rng`, so a batch of any
+/// size costs two pairings: `e(sum r_i * evaluated_i, g2) == e(sum r_i * blinded_i, pk2)`.
+pub fn verify_pairing<R: Rng>(
+    public_key_g2: &G2Projective,
+    blinded: &[G1Projective],
+    evaluated: &[G1Projective],
+    rng: &mut R,
+) -> Result<(), OprfError> {
+    if blinded.len() != evaluated.len() {
+        return Err(OprfError::InvalidProof);
+    }
+
+    let mut blinded_sum = G1Projective::zero();
+    let mut evaluated_sum = G1Projective::zero();
+    for (b_i, e_i) in blinded.iter().zip(evaluated) {
+        let r = Fr::rand(rng);
+        blinded_sum += *b_i * r;
+        evaluated_sum += *e_i * r;
+    }
+
+    let check = Bn254::multi_pairing(
+        [evaluated_sum, -blinded_sum],
+        [g2_generator(), *public_key_g2],
+    );
+    if !check.is_zero() {
+        return Err(OprfError::InvalidProof);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_std::test_rng;
+    use crate::{hash_to_g1, random_scalar, scalar_mul, scalar_mul_generator};
+
+    #[test]
+    fn test_pairing_verification() {
+        let mut rng = test_rng();
+
+        let k = random_scalar(&mut rng);
+        let pk = scalar_mul_generator(&k);
+        let pk2 = public_key_g2(&k);
+        let pk2 = deserialize_g2(&serialize_g2(&pk2).unwrap()).unwrap();
+        assert!(verify_key_pair(&pk, &pk2).is_ok());
+        assert!(verify_key_pair(&scalar_mul_generator(&random_scalar(&mut rng)), &pk2).is_err());
+
+        let blinded: Vec<_> = (0..4u8).map(|i| hash_to_g1(&[i])).collect();
+        let mut evaluated: Vec<_> = blinded.iter().map(|p| scalar_mul(p, &k)).collect();
+        assert!(verify_pairing(&pk2, &blinded, &evaluated, &mut rng).is_ok());
+
+        evaluated[2] = scalar_mul(&blinded[2], &random_scalar(&mut rng));
+        assert!(verify_pairing(&pk2, &blinded, &evaluated, &mut rng).is_err());
+    }
+}
@@ -0,0 +1,617 @@
+//! Protocol Buffers encoding of the messages of `proto/oprf.proto`.
+//!
+//! The proto3 wire format is written by hand, as prost is not among the
+//! workspace's dependencies; the bytes are those protoc-generated code in
+//! any language reads and writes. Fields at their default value are left
+//! out, `optional` fields are written whenever present, and unknown fields
+//! are skipped on decoding so the schema can grow. Byte fields carry the
+//! same serialized points, proofs and attestation documents as CBOR.
+
+use crate::{
+    AttestationDocument, AttestationResponse, BatchOprfRequest, BatchOprfResponse, CiphersuiteId,
+    ErrorCode, ErrorResponse, GetAttestationRequest, GetPublicKeyRequest, OprfError, OprfMode,
+    OprfRequest, OprfResponse, PublicKeyResponse, DEFAULT_KEY_ID,
+};
+
+const VARINT: u8 = 0;
+const FIXED64: u8 = 1;
+const LENGTH_DELIMITED: u8 = 2;
+const FIXED32: u8 = 5;
+
+/// A message of `proto/oprf.proto`
+pub trait ProtoMessage: Sized {
+    /// Append the fields of the message to `writer`
+    fn write(&self, writer: &mut Writer);
+
+    /// Build the message from its decoded fields
+    fn read(fields: &Fields) -> Result<Self, OprfError>;
+
+    fn encode_to_vec(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        self.write(&mut writer);
+        writer.0
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, OprfError> {
+        Self::read(&Fields::parse(bytes)?)
+    }
+}
+
+fn malformed(what: impl Into<String>) -> OprfError {
+    OprfError::Deserialization(format!("protobuf: {}", what.into()))
+}
+
+/// Encoder of the fields of one message
+#[derive(Default)]
+pub struct Writer(Vec<u8>);
+
+impl Writer {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.varint(u64::from(field) << 3 | u64::from(wire_type));
+    }
+
+    fn delimited(&mut self, field: u32, bytes: &[u8]) {
+        self.key(field, LENGTH_DELIMITED);
+        self.varint(bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+    }
+
+    pub fn uint64(&mut self, field: u32, value: u64) {
+        if value != 0 {
+            self.optional_uint64(field, Some(value));
+        }
+    }
+
+    pub fn optional_uint64(&mut self, field: u32, value: Option<u64>) {
+        if let Some(value) = value {
+            self.key(field, VARINT);
+            self.varint(value);
+        }
+    }
+
+    pub fn bool(&mut self, field: u32, value: bool) {
+        self.uint64(field, value.into());
+    }
+
+    pub fn bytes(&mut self, field: u32, value: &[u8]) {
+        if !value.is_empty() {
+            self.delimited(field, value);
+        }
+    }
+
+    pub fn optional_bytes(&mut self, field: u32, value: Option<&[u8]>) {
+        if let Some(value) = value {
+            self.delimited(field, value);
+        }
+    }
+
+    /// Repeated fields are written element by element, empty ones included
+    pub fn repeated_bytes<T: AsRef<[u8]>>(&mut self, field: u32, values: &[T]) {
+        for value in values {
+            self.delimited(field, value.as_ref());
+        }
+    }
+
+    pub fn string(&mut self, field: u32, value: &str) {
+        self.bytes(field, value.as_bytes());
+    }
+
+    pub fn optional_string(&mut self, field: u32, value: Option<&str>) {
+        self.optional_bytes(field, value.map(str::as_bytes));
+    }
+
+    pub fn message<M: ProtoMessage>(&mut self, field: u32, message: &M) {
+        self.delimited(field, &message.encode_to_vec());
+    }
+}
+
+/// A decoded field value
+#[derive(Debug, Clone, Copy)]
+enum Value<'a> {
+    Varint(u64),
+    Delimited(&'a [u8]),
+}
+
+/// Decoded fields of one message, in order of appearance
+pub struct Fields<'a>(Vec<(u32, Value<'a>)>);
+
+impl<'a> Fields<'a> {
+    /// Split `bytes` into fields, skipping fixed-width ones, which no
+    /// message of the schema uses
+    fn parse(mut bytes: &'a [u8]) -> Result<Self, OprfError> {
+        fn varint(bytes: &mut &[u8]) -> Result<u64, OprfError> {
+            let mut value = 0u64;
+            for shift in (0..64).step_by(7) {
+                let (&byte, rest) = bytes.split_first().ok_or_else(|| malformed("truncated"))?;
+                *bytes = rest;
+                value |= u64::from(byte & 0x7f) << shift;
+                if byte & 0x80 == 0 {
+                    return Ok(value);
+                }
+            }
+            Err(malformed("varint longer than 10 bytes"))
+        }
+        fn take<'a>(bytes: &mut &'a [u8], len: u64) -> Result<&'a [u8], OprfError> {
+            let len = usize::try_from(len).map_err(|_| malformed("length out of range"))?;
+            if len > bytes.len() {
+                return Err(malformed("truncated"));
+            }
+            let (taken, rest) = bytes.split_at(len);
+            *bytes = rest;
+            Ok(taken)
+        }
+
+        let mut fields = Vec::new();
+        while !bytes.is_empty() {
+            let key = varint(&mut bytes)?;
+            let field = u32::try_from(key >> 3)
+                .ok()
+                .filter(|&field| field != 0)
+                .ok_or_else(|| malformed(format!("invalid field number {}", key >> 3)))?;
+            let value = match (key & 0x7) as u8 {
+                VARINT => Value::Varint(varint(&mut bytes)?),
+                LENGTH_DELIMITED => {
+                    let len = varint(&mut bytes)?;
+                    Value::Delimited(take(&mut bytes, len)?)
+                }
+                FIXED64 => {
+                    take(&mut bytes, 8)?;
+                    continue;
+                }
+                FIXED32 => {
+                    take(&mut bytes, 4)?;
+                    continue;
+                }
+                wire_type => return Err(malformed(format!("unsupported wire type {}", wire_type))),
+            };
+            fields.push((field, value));
+        }
+        Ok(Self(fields))
+    }
+
+    /// Every value of `field`
+    fn all(&self, field: u32) -> impl Iterator<Item = Value<'a>> + '_ {
+        self.0.iter().filter(move |(number, _)| *number == field).map(|(_, value)| *value)
+    }
+
+    /// The last value of `field`, which wins for singular fields
+    fn last(&self, field: u32) -> Option<Value<'a>> {
+        self.all(field).last()
+    }
+
+    pub fn optional_uint64(&self, field: u32) -> Result<Option<u64>, OprfError> {
+        match self.last(field) {
+            None => Ok(None),
+            Some(Value::Varint(value)) => Ok(Some(value)),
+            Some(Value::Delimited(_)) => Err(malformed(format!("field {} is not a varint", field))),
+        }
+    }
+
+    pub fn uint64(&self, field: u32) -> Result<u64, OprfError> {
+        Ok(self.optional_uint64(field)?.unwrap_or(0))
+    }
+
+    pub fn bool(&self, field: u32) -> Result<bool, OprfError> {
+        Ok(self.uint64(field)? != 0)
+    }
+
+    pub fn optional_bytes(&self, field: u32) -> Result<Option<Vec<u8>>, OprfError> {
+        match self.last(field) {
+            None => Ok(None),
+            Some(Value::Delimited(bytes)) => Ok(Some(bytes.to_vec())),
+            Some(Value::Varint(_)) => Err(malformed(format!("field {} is not bytes", field))),
+        }
+    }
+
+    pub fn bytes(&self, field: u32) -> Result<Vec<u8>, OprfError> {
+        Ok(self.optional_bytes(field)?.unwrap_or_default())
+    }
+
+    pub fn repeated_bytes(&self, field: u32) -> Result<Vec<Vec<u8>>, OprfError> {
+        self.all(field)
+            .map(|value| match value {
+                Value::Delimited(bytes) => Ok(bytes.to_vec()),
+                Value::Varint(_) => Err(malformed(format!("field {} is not bytes", field))),
+            })
+            .collect()
+    }
+
+    pub fn string(&self, field: u32) -> Result<String, OprfError> {
+        Ok(self.optional_string(field)?.unwrap_or_default())
+    }
+
+    pub fn optional_string(&self, field: u32) -> Result<Option<String>, OprfError> {
+        self.optional_bytes(field)?
+            .map(|bytes| {
+                String::from_utf8(bytes)
+                    .map_err(|_| malformed(format!("field {} is not UTF-8", field)))
+            })
+            .transpose()
+    }
+
+    pub fn repeated_string(&self, field: u32) -> Result<Vec<String>, OprfError> {
+        self.repeated_bytes(field)?
+            .into_iter()
+            .map(|bytes| {
+                String::from_utf8(bytes)
+                    .map_err(|_| malformed(format!("field {} is not UTF-8", field)))
+            })
+            .collect()
+    }
+
+    /// Key id of `field`, [`DEFAULT_KEY_ID`] when empty
+    fn key_id(&self, field: u32) -> Result<String, OprfError> {
+        let key_id = self.string(field)?;
+        Ok(if key_id.is_empty() { DEFAULT_KEY_ID.to_string() } else { key_id })
+    }
+
+    /// Embedded message of `field`, which every message of the schema
+    /// requires where it has one
+    pub fn message<M: ProtoMessage>(&self, field: u32) -> Result<M, OprfError> {
+        match self.optional_bytes(field)? {
+            Some(bytes) => M::decode(&bytes),
+            None => Err(malformed(format!("missing field {}", field))),
+        }
+    }
+}
+
+/// Enums of the schema, numbered as in `proto/oprf.proto`
+trait ProtoEnum: Copy + 'static {
+    const NAME: &'static str;
+    const VALUES: &'static [Self];
+
+    fn number(self) -> u64;
+
+    fn from_number(number: u64) -> Result<Self, OprfError> {
+        Self::VALUES
+            .iter()
+            .copied()
+            .find(|value| value.number() == number)
+            .ok_or_else(|| malformed(format!("unknown {} {}", Self::NAME, number)))
+    }
+}
+
+impl ProtoEnum for CiphersuiteId {
+    const NAME: &'static str = "Ciphersuite";
+    const VALUES: &'static [Self] = &CiphersuiteId::ALL;
+
+    fn number(self) -> u64 {
+        match self {
+            CiphersuiteId::Bn254Sha256 => 0,
+            CiphersuiteId::P256Sha256 => 1,
+        }
+    }
+}
+
+impl ProtoEnum for OprfMode {
+    const NAME: &'static str = "OprfMode";
+    const VALUES: &'static [Self] = &[OprfMode::Oprf, OprfMode::Voprf];
+
+    fn number(self) -> u64 {
+        match self {
+            OprfMode::Oprf => 0,
+            OprfMode::Voprf => 1,
+        }
+    }
+}
+
+impl ProtoEnum for ErrorCode {
+    const NAME: &'static str = "ErrorCode";
+    const VALUES: &'static [Self] = &[
+        ErrorCode::BadPoint,
+        ErrorCode::HashMismatch,
+        ErrorCode::RateLimited,
+        ErrorCode::BadRequest,
+        ErrorCode::InternalError,
+        ErrorCode::Unauthorized,
+    ];
+
+    fn number(self) -> u64 {
+        match self {
+            ErrorCode::BadPoint => 0,
+            ErrorCode::HashMismatch => 1,
+            ErrorCode::RateLimited => 2,
+            ErrorCode::BadRequest => 3,
+            ErrorCode::InternalError => 4,
+            ErrorCode::Unauthorized => 5,
+        }
+    }
+}
+
+impl ProtoMessage for AttestationDocument {
+    fn write(&self, writer: &mut Writer) {
+        writer.bool(1, self.is_mock);
+        writer.bytes(2, &self.document);
+        writer.repeated_bytes(3, self.pcrs.as_deref().unwrap_or_default());
+        writer.bytes(4, &self.user_data);
+    }
+
+    /// No PCRs decode as absent ones
+    fn read(fields: &Fields) -> Result<Self, OprfError> {
+        let pcrs = fields.repeated_string(3)?;
+        Ok(Self {
+            is_mock: fields.bool(1)?,
+            document: fields.bytes(2)?,
+            pcrs: (!pcrs.is_empty()).then_some(pcrs),
+            user_data: fields.bytes(4)?,
+        })
+    }
+}
+
+impl ProtoMessage for OprfRequest {
+    fn write(&self, writer: &mut Writer) {
+        writer.bytes(1, &self.blinded_query);
+        writer.string(2, &self.query_hash);
+        writer.uint64(3, self.mode.number());
+        writer.uint64(4, self.ciphersuite.number());
+        writer.optional_uint64(5, self.epoch);
+        writer.string(6, &self.key_id);
+        writer.bool(7, self.force_fresh);
+        writer.optional_bytes(8, self.client_nonce.as_deref());
+        writer.optional_string(9, self.client_id.as_deref());
+        writer.optional_string(10, self.request_id.as_deref());
+    }
+
+    fn read(fields: &Fields) -> Result<Self, OprfError> {
+        Ok(Self {
+            blinded_query: fields.bytes(1)?,
+            query_hash: fields.string(2)?,
+            mode: OprfMode::from_number(fields.uint64(3)?)?,
+            ciphersuite: CiphersuiteId::from_number(fields.uint64(4)?)?,
+            epoch: fields.optional_uint64(5)?,
+            key_id: fields.key_id(6)?,
+            force_fresh: fields.bool(7)?,
+            client_nonce: fields.optional_bytes(8)?,
+            client_id: fields.optional_string(9)?,
+            request_id: fields.optional_string(10)?,
+        })
+    }
+}
+
+impl ProtoMessage for OprfResponse {
+    fn write(&self, writer: &mut Writer) {
+        writer.bytes(1, &self.evaluated_point);
+        writer.bytes(2, &self.public_key);
+        writer.message(3, &self.attestation);
+        writer.optional_bytes(4, self.proof.as_deref());
+        writer.optional_bytes(5, self.public_key_g2.as_deref());
+        writer.uint64(6, self.epoch);
+        writer.string(7, &self.key_id);
+        writer.optional_string(8, self.request_id.as_deref());
+    }
+
+    fn read(fields: &Fields) -> Result<Self, OprfError> {
+        Ok(Self {
+            evaluated_point: fields.bytes(1)?,
+            public_key: fields.bytes(2)?,
+            attestation: fields.message(3)?,
+            proof: fields.optional_bytes(4)?,
+            public_key_g2: fields.optional_bytes(5)?,
+            epoch: fields.uint64(6)?,
+            key_id: fields.key_id(7)?,
+            request_id: fields.optional_string(8)?,
+        })
+    }
+}
+
+impl ProtoMessage for BatchOprfRequest {
+    fn write(&self, writer: &mut Writer) {
+        writer.repeated_bytes(1, &self.blinded_queries);
+        writer.string(2, &self.query_hash);
+        writer.uint64(3, self.mode.number());
+        writer.uint64(4, self.ciphersuite.number());
+        writer.optional_uint64(5, self.epoch);
+        writer.string(6, &self.key_id);
+        writer.bool(7, self.force_fresh);
+        writer.optional_bytes(8, self.client_nonce.as_deref());
+        writer.optional_string(9, self.client_id.as_deref());
+        writer.optional_string(10, self.request_id.as_deref());
+    }
+
+    fn read(fields: &Fields) -> Result<Self, OprfError> {
+        Ok(Self {
+            blinded_queries: fields.repeated_bytes(1)?,
+            query_hash: fields.string(2)?,
+            mode: OprfMode::from_number(fields.uint64(3)?)?,
+            ciphersuite: CiphersuiteId::from_number(fields.uint64(4)?)?,
+            epoch: fields.optional_uint64(5)?,
+            key_id: fields.key_id(6)?,
+            force_fresh: fields.bool(7)?,
+            client_nonce: fields.optional_bytes(8)?,
+            client_id: fields.optional_string(9)?,
+            request_id: fields.optional_string(10)?,
+        })
+    }
+}
+
+impl ProtoMessage for BatchOprfResponse {
+    fn write(&self, writer: &mut Writer) {
+        writer.repeated_bytes(1, &self.evaluated_points);
+        writer.bytes(2, &self.public_key);
+        writer.message(3, &self.attestation);
+        writer.optional_bytes(4, self.proof.as_deref());
+        writer.optional_bytes(5, self.public_key_g2.as_deref());
+        writer.uint64(6, self.epoch);
+        writer.string(7, &self.key_id);
+        writer.optional_string(8, self.request_id.as_deref());
+    }
+
+    fn read(fields: &Fields) -> Result<Self, OprfError> {
+        Ok(Self {
+            evaluated_points: fields.repeated_bytes(1)?,
+            public_key: fields.bytes(2)?,
+            attestation: fields.message(3)?,
+            proof: fields.optional_bytes(4)?,
+            public_key_g2: fields.optional_bytes(5)?,
+            epoch: fields.uint64(6)?,
+            key_id: fields.key_id(7)?,
+            request_id: fields.optional_string(8)?,
+        })
+    }
+}
+
+impl ProtoMessage for GetPublicKeyRequest {
+    fn write(&self, writer: &mut Writer) {
+        writer.uint64(1, self.ciphersuite.number());
+        writer.string(2, &self.key_id);
+    }
+
+    fn read(fields: &Fields) -> Result<Self, OprfError> {
+        Ok(Self {
+            ciphersuite: CiphersuiteId::from_number(fields.uint64(1)?)?,
+            key_id: fields.key_id(2)?,
+        })
+    }
+}
+
+impl ProtoMessage for PublicKeyResponse {
+    fn write(&self, writer: &mut Writer) {
+        writer.string(1, &self.key_id);
+        writer.uint64(2, self.epoch);
+        writer.bytes(3, &self.public_key);
+        writer.optional_bytes(4, self.public_key_g2.as_deref());
+        writer.message(5, &self.attestation);
+    }
+
+    fn read(fields: &Fields) -> Result<Self, OprfError> {
+        Ok(Self {
+            key_id: fields.key_id(1)?,
+            epoch: fields.uint64(2)?,
+            public_key: fields.bytes(3)?,
+            public_key_g2: fields.optional_bytes(4)?,
+            attestation: fields.message(5)?,
+        })
+    }
+}
+
+impl ProtoMessage for GetAttestationRequest {
+    fn write(&self, writer: &mut Writer) {
+        writer.uint64(1, self.ciphersuite.number());
+        writer.string(2, &self.key_id);
+        writer.bytes(3, &self.nonce);
+    }
+
+    fn read(fields: &Fields) -> Result<Self, OprfError> {
+        Ok(Self {
+            ciphersuite: CiphersuiteId::from_number(fields.uint64(1)?)?,
+            key_id: fields.key_id(2)?,
+            nonce: fields.bytes(3)?,
+        })
+    }
+}
+
+impl ProtoMessage for AttestationResponse {
+    fn write(&self, writer: &mut Writer) {
+        writer.string(1, &self.key_id);
+        writer.uint64(2, self.epoch);
+        writer.bytes(3, &self.public_key);
+        writer.message(4, &self.attestation);
+    }
+
+    fn read(fields: &Fields) -> Result<Self, OprfError> {
+        Ok(Self {
+            key_id: fields.key_id(1)?,
+            epoch: fields.uint64(2)?,
+            public_key: fields.bytes(3)?,
+            attestation: fields.message(4)?,
+        })
+    }
+}
+
+impl ProtoMessage for ErrorResponse {
+    fn write(&self, writer: &mut Writer) {
+        writer.uint64(1, self.code.number());
+        writer.string(2, &self.message);
+    }
+
+    fn read(fields: &Fields) -> Result<Self, OprfError> {
+        Ok(Self {
+            code: ErrorCode::from_number(fields.uint64(1)?)?,
+            message: fields.string(2)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protobuf_encoding() {
+        // Bytes as protoc-generated code writes them
+        let request = GetPublicKeyRequest {
+            ciphersuite: CiphersuiteId::P256Sha256,
+            key_id: "app".to_string(),
+        };
+        assert_eq!(request.encode_to_vec(), b"\x08\x01\x12\x03app");
+        let request = OprfRequest {
+            blinded_query: vec![0xab; 2],
+            query_hash: String::new(),
+            mode: OprfMode::Voprf,
+            ciphersuite: CiphersuiteId::Bn254Sha256,
+            epoch: Some(0),
+            key_id: String::new(),
+            force_fresh: false,
+            client_nonce: None,
+            client_id: None,
+            request_id: None,
+        };
+        assert_eq!(request.encode_to_vec(), b"\x0a\x02\xab\xab\x18\x01\x28\x00");
+        let decoded = OprfRequest::decode(&request.encode_to_vec()).unwrap();
+        assert_eq!(decoded.epoch, Some(0));
+        assert_eq!(decoded.key_id, DEFAULT_KEY_ID);
+        assert_eq!(decoded.client_id, None);
+        let request = OprfRequest {
+            client_id: Some("app".to_string()),
+            ..request
+        };
+        assert!(request.encode_to_vec().ends_with(b"\x4a\x03app"));
+        let decoded = OprfRequest::decode(&request.encode_to_vec()).unwrap();
+        assert_eq!(decoded.client_id.as_deref(), Some("app"));
+
+        let response = BatchOprfResponse {
+            evaluated_points: vec![vec![1; 33], vec![], vec![2; 33]],
+            public_key: vec![3; 33],
+            attestation: AttestationDocument {
+                is_mock: true,
+                document: vec![4; 300],
+                pcrs: Some(vec!["00".repeat(48)]),
+                user_data: vec![5; 32],
+            },
+            proof: Some(vec![6; 64]),
+            public_key_g2: None,
+            epoch: 300,
+            key_id: "app".to_string(),
+            request_id: Some("req-1".to_string()),
+        };
+        let decoded = BatchOprfResponse::decode(&response.encode_to_vec()).unwrap();
+        assert_eq!(decoded.evaluated_points, response.evaluated_points);
+        assert_eq!(decoded.attestation.document, response.attestation.document);
+        assert_eq!(decoded.attestation.pcrs, response.attestation.pcrs);
+        assert_eq!(decoded.proof, response.proof);
+        assert_eq!(decoded.public_key_g2, None);
+        assert_eq!((decoded.epoch, decoded.key_id.as_str()), (300, "app"));
+        assert_eq!(decoded.request_id.as_deref(), Some("req-1"));
+
+        let error = ErrorResponse::new(ErrorCode::RateLimited, "budget used");
+        assert_eq!(ErrorResponse::decode(&error.encode_to_vec()).unwrap(), error);
+
+        // Unknown fields are skipped; truncated fields, unknown enum values
+        // and missing attestations are rejected
+        let mut bytes = b"\x08\x01\x12\x03app".to_vec();
+        bytes.extend_from_slice(b"\x78\x05\x82\x01\x01x\x0d\x00\x00\x00\x00");
+        assert_eq!(GetPublicKeyRequest::decode(&bytes).unwrap().key_id, "app");
+        assert!(GetPublicKeyRequest::decode(b"\x12\x05app").is_err());
+        assert!(GetPublicKeyRequest::decode(b"\x08\x07").is_err());
+        assert!(GetPublicKeyRequest::decode(b"\x10\x01").is_err());
+        assert!(PublicKeyResponse::decode(b"\x0a\x03app").is_err());
+    }
+}
@@ -0,0 +1,91 @@
+//! Redaction of sensitive values in logs.
+//!
+//! Blinded queries, evaluated points, public keys and inputs are logged
+//! through [`sensitive`] and [`sensitive_text`], which print them as their
+//! length and a short SHA-256 prefix, e.g. `<32 bytes, sha256 3f2a9c01>`:
+//! enough to match a value across the parent and enclave logs, but not to
+//! recover it or link it to a client. [`set_log_sensitive`] turns on full
+//! values for debugging; the enclave and parent set it from their
+//! `--log-sensitive` flag, and it is off otherwise.
+
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Bytes of the SHA-256 digest a redacted value shows
+const DIGEST_PREFIX_LEN: usize = 4;
+
+static LOG_SENSITIVE: AtomicBool = AtomicBool::new(false);
+
+/// Log sensitive values in full from now on, or redact them again
+pub fn set_log_sensitive(enabled: bool) {
+    LOG_SENSITIVE.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether sensitive values are logged in full
+pub fn log_sensitive() -> bool {
+    LOG_SENSITIVE.load(Ordering::Relaxed)
+}
+
+/// A value that is only logged in full with [`log_sensitive`]
+pub struct Sensitive<'a> {
+    bytes: &'a [u8],
+    /// Shown as a quoted string rather than hex
+    text: bool,
+}
+
+/// Log `bytes` as hex, or redacted
+pub fn sensitive(bytes: &[u8]) -> Sensitive<'_> {
+    Sensitive { bytes, text: false }
+}
+
+/// Log `text` quoted, or redacted
+pub fn sensitive_text(text: &str) -> Sensitive<'_> {
+    Sensitive {
+        bytes: text.as_bytes(),
+        text: true,
+    }
+}
+
+impl fmt::Display for Sensitive<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if log_sensitive() {
+            return match self.text {
+                true => write!(f, "{:?}", String::from_utf8_lossy(self.bytes)),
+                false => f.write_str(&hex::encode(self.bytes)),
+            };
+        }
+        let digest = Sha256::digest(self.bytes);
+        write!(
+            f,
+            "<{} bytes, sha256 {}>",
+            self.bytes.len(),
+            hex::encode(&digest[..DIGEST_PREFIX_LEN])
+        )
+    }
+}
+
+impl fmt::Debug for Sensitive<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redaction() {
+        let redacted = sensitive(b"abc").to_string();
+        assert_eq!(redacted, "<3 bytes, sha256 ba7816bf>");
+        assert_eq!(sensitive_text("abc").to_string(), redacted);
+        assert_eq!(format!("{:?}", [sensitive(&[])]), "[<0 bytes, sha256 e3b0c442>]");
+
+        set_log_sensitive(true);
+        let full = (sensitive(b"abc").to_string(), sensitive_text("a\"b").to_string());
+        set_log_sensitive(false);
+        assert_eq!(full, ("616263".to_string(), r#""a\"b""#.to_string()));
+        assert_eq!(sensitive(b"abc").to_string(), redacted);
+    }
+}
@@ -0,0 +1,137 @@
+//! Randomness that tests can make reproducible.
+//!
+//! Secret keys, proof nonces and blinding factors are drawn from a
+//! [`SharedRng`]. It is the operating system's generator, unless the crate is
+//! built with the `deterministic-rng` feature and the RNG is created by
+//! [`SharedRng::seeded`]: then every clone draws from one ChaCha stream
+//! expanded from the seed, so the same seed and the same requests in the same
+//! order give the same keys, proofs and blinded queries. That is for
+//! end-to-end tests and test vectors only; a seeded RNG is as secret as its
+//! seed, which is why a build without the feature cannot create one.
+
+#[cfg(feature = "deterministic-rng")]
+use rand::SeedableRng;
+use rand::rngs::OsRng;
+use rand::{CryptoRng, RngCore};
+#[cfg(feature = "deterministic-rng")]
+use std::sync::{Arc, Mutex};
+
+/// Handle on the RNG of an enclave or client, shared by its clones
+#[derive(Clone, Default)]
+pub struct SharedRng {
+    #[cfg(feature = "deterministic-rng")]
+    seeded: Option<Arc<Mutex<rand::rngs::StdRng>>>,
+}
+
+impl SharedRng {
+    /// The operating system's generator
+    pub fn os() -> Self {
+        Self::default()
+    }
+
+    /// A reproducible stream expanded from `seed`
+    #[cfg(feature = "deterministic-rng")]
+    pub fn seeded(seed: [u8; 32]) -> Self {
+        Self {
+            seeded: Some(Arc::new(Mutex::new(rand::rngs::StdRng::from_seed(seed)))),
+        }
+    }
+
+    /// A reproducible stream expanded from the 32 hex-encoded bytes of
+    /// `seed`, as given on a command line. Fails unless built with the
+    /// `deterministic-rng` feature.
+    pub fn from_hex_seed(seed: &str) -> Result<Self, String> {
+        let seed: [u8; 32] = hex::decode(seed.trim())
+            .ok()
+            .and_then(|seed| seed.try_into().ok())
+            .ok_or("RNG seed must be 32 hex-encoded bytes")?;
+        #[cfg(feature = "deterministic-rng")]
+        return Ok(Self::seeded(seed));
+        #[cfg(not(feature = "deterministic-rng"))]
+        Err(format!(
+            "Cannot seed the RNG with {}: built without the deterministic-rng feature",
+            hex::encode(seed)
+        ))
+    }
+
+    /// Whether the RNG is seeded rather than the operating system's
+    pub fn is_seeded(&self) -> bool {
+        #[cfg(feature = "deterministic-rng")]
+        let seeded = self.seeded.is_some();
+        #[cfg(not(feature = "deterministic-rng"))]
+        let seeded = false;
+        seeded
+    }
+
+    /// Run `f` on the underlying generator
+    fn with<T>(&mut self, f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+        #[cfg(feature = "deterministic-rng")]
+        if let Some(seeded) = &self.seeded {
+            let mut rng = seeded.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            return f(&mut *rng);
+        }
+        f(&mut OsRng)
+    }
+}
+
+impl std::fmt::Debug for SharedRng {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(if self.is_seeded() { "SharedRng(seeded)" } else { "SharedRng(os)" })
+    }
+}
+
+impl RngCore for SharedRng {
+    fn next_u32(&mut self) -> u32 {
+        self.with(|rng| rng.next_u32())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.with(|rng| rng.next_u64())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.with(|rng| rng.fill_bytes(dest))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.with(|rng| rng.try_fill_bytes(dest))
+    }
+}
+
+impl CryptoRng for SharedRng {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{blind, Bn254Sha256, Ciphersuite, OprfMode, P256Sha256};
+    use rand::RngCore;
+
+    #[test]
+    fn test_seeded_rng() {
+        let seed = "07".repeat(32);
+        assert!(!SharedRng::os().is_seeded());
+        assert!(SharedRng::from_hex_seed("07").is_err());
+        assert!(SharedRng::from_hex_seed("not hex").is_err());
+        if cfg!(not(feature = "deterministic-rng")) {
+            assert!(SharedRng::from_hex_seed(&seed).is_err());
+            return;
+        }
+
+        // One seed blinds to the same element, whatever the ciphersuite
+        fn blinded<C: Ciphersuite>(seed: &str) -> Vec<u8> {
+            let mut rng = SharedRng::from_hex_seed(seed).unwrap();
+            blind::<C, _>(b"alice@example.com", OprfMode::Oprf, &mut rng).1.to_bytes().unwrap()
+        }
+        assert_eq!(blinded::<Bn254Sha256>(&seed), blinded::<Bn254Sha256>(&seed));
+        assert_eq!(blinded::<P256Sha256>(&seed), blinded::<P256Sha256>(&seed));
+        assert_ne!(blinded::<P256Sha256>(&seed), blinded::<P256Sha256>(&"08".repeat(32)));
+
+        // Clones draw from one stream rather than repeat it
+        let mut rng = SharedRng::from_hex_seed(&seed).unwrap();
+        assert!(rng.is_seeded());
+        let first = rng.clone().next_u64();
+        let second = rng.next_u64();
+        assert_ne!(first, second);
+        assert_eq!(SharedRng::from_hex_seed(&seed).unwrap().next_u64(), first);
+    }
+}
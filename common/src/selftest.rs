@@ -0,0 +1,243 @@
+//! Known-answer self-test of the group operations.
+//!
+//! The enclave runs [`run`] once on boot, before it accepts a connection,
+//! and refuses to serve if any test fails: a miscompiled or corrupted image
+//! would otherwise hand out evaluations and proofs no client can use, or
+//! worse, ones that leak the key. Each ciphersuite is checked against a
+//! fixed vector for every step of a verifiable evaluation, through the same
+//! constant-time multiplication the enclave evaluates with:
+//!
+//! - [`KnownAnswerTest::Serialization`]: scalars and elements decode and
+//!   encode back to the same bytes, compressed and uncompressed
+//! - [`KnownAnswerTest::ScalarMul`]: `g^k` and `blinded^k`
+//! - [`KnownAnswerTest::HashToCurve`]: the blinded element of the input
+//! - [`KnownAnswerTest::Dleq`]: the proof under a fixed nonce, and that it
+//!   verifies while a tampered one does not
+//! - [`KnownAnswerTest::Finalize`]: the PRF output of the unblinded element
+//!
+//! P-256 uses vector 1 of RFC 9497 Appendix A.3.2. BN254 has no official
+//! vectors; its vector was recorded from this implementation, so it catches
+//! a change in behavior rather than a departure from a standard. The report
+//! is attested once it has run, over [`report_binding`], and served in a
+//! [`crate::SelfTestResponse`].
+
+use crate::dleq::{self, DleqProof};
+use crate::{
+    bounded, context_string, finalize, Bn254Sha256, Ciphersuite, CiphersuiteId, OprfError,
+    OprfMode, P256Sha256,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Domain separating self-test reports from other attested user data
+const REPORT_DOMAIN: &[u8] = b"nitro-oprf-self-test-v1";
+
+/// Upper bound on the results of a report
+const MAX_RESULTS: usize = 32;
+
+/// Step of an evaluation checked against its known answer
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KnownAnswerTest {
+    Serialization,
+    ScalarMul,
+    HashToCurve,
+    Dleq,
+    Finalize,
+}
+
+impl KnownAnswerTest {
+    /// Every test, in the order they run
+    pub const ALL: [KnownAnswerTest; 5] = [
+        KnownAnswerTest::Serialization,
+        KnownAnswerTest::ScalarMul,
+        KnownAnswerTest::HashToCurve,
+        KnownAnswerTest::Dleq,
+        KnownAnswerTest::Finalize,
+    ];
+}
+
+/// Outcome of one test of one ciphersuite
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct KnownAnswer {
+    pub ciphersuite: CiphersuiteId,
+    pub test: KnownAnswerTest,
+    pub passed: bool,
+}
+
+/// Outcome of every test of every ciphersuite
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SelfTestReport {
+    #[serde(deserialize_with = "bounded::list::<_, _, MAX_RESULTS>")]
+    pub results: Vec<KnownAnswer>,
+}
+
+impl SelfTestReport {
+    /// Whether every test passed
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|result| result.passed)
+    }
+
+    /// Tests that failed
+    pub fn failures(&self) -> impl Iterator<Item = &KnownAnswer> {
+        self.results.iter().filter(|result| !result.passed)
+    }
+}
+
+/// Fixed inputs and expected outputs of a verifiable evaluation, hex-encoded
+struct Vector {
+    key: &'static str,
+    public_key: &'static str,
+    input: &'static [u8],
+    blind: &'static str,
+    blinded: &'static str,
+    evaluated: &'static str,
+    /// Random scalar of the proof
+    proof_scalar: &'static str,
+    proof: &'static str,
+    output: &'static str,
+}
+
+/// RFC 9497 Appendix A.3.2, vector 1
+const P256_VECTOR: Vector = Vector {
+    key: "ca5d94c8807817669a51b196c34c1b7f8442fde4334a7121ae4736364312fca6",
+    public_key: "03e17e70604bcabe198882c0a1f27a92441e774224ed9c702e51dd17038b102462",
+    input: &[0],
+    blind: "3338fa65ec36e0290022b48eb562889d89dbfa691d1cde91517fa222ed7ad364",
+    blinded: "02dd05901038bb31a6fae01828fd8d0e49e35a486b5c5d4b4994013648c01277da",
+    evaluated: "0209f33cab60cf8fe69239b0afbcfcd261af4c1c5632624f2e9ba29b90ae83e4a2",
+    proof_scalar: "f9db001266677f62c095021db018cd8cbb55941d4073698ce45c405d1348b7b1",
+    proof: "e7c2b3c5c954c035949f1f74e6bce2ed539a3be267d1481e9ddb178533df4c26\
+            64f69d065c604a4fd953e100b856ad83804eb3845189babfa5a702090d6fc5fa",
+    output: "0412e8f78b02c415ab3a288e228978376f99927767ff37c5718d420010a645a1",
+};
+
+/// Recorded from this implementation, with the key, blind and proof scalar
+/// hashed from labels as RFC 9497 `DeriveKeyPair` would
+const BN254_VECTOR: Vector = Vector {
+    key: "8d8a305fc58c61ec47449555cb5882e6cfde504e7c3fbf0eb2b82f2779756200",
+    public_key: "7467e133bdda36c791ccd065c925b090b3ffd2e23eaa2d5e53f56405ee995c12",
+    input: b"self-test input",
+    blind: "e8e3b76967b5350b05eae18de27ab7c3e9eb2d279c7c4d0d8ab0e129c76f5605",
+    blinded: "7284cf4ac1eb2a0eddb24b82ecfc8df0d69c5dc873bb8b2d3c1dfd48966ee404",
+    evaluated: "68043991eb9973f7c30383aadfe48e88a8cde5963048aaa6eff520e7c1491a8c",
+    proof_scalar: "02532ca0564d398f95bbeea8e08062d1c01f14219441ccd56ba34e48d2b9aa11",
+    proof: "4405a8b6999dc45ca14e16f82e7367b1c21953e1291d6b24fd5444f1036f2e27\
+            f5a30576ab25c1989964ad239936b2c1442905694f44f06072a871d9be6d4129",
+    output: "23b965043d4fdda5671a3fec814e47ce6bd702da93dd7ac61008803d4458ba49",
+};
+
+fn decode(hex_string: &str) -> Result<Vec<u8>, OprfError> {
+    hex::decode(hex_string).map_err(|e| OprfError::Deserialization(e.to_string()))
+}
+
+fn scalar<C: Ciphersuite>(hex_string: &str) -> Result<C::Scalar, OprfError> {
+    C::deserialize_scalar(&decode(hex_string)?)
+}
+
+fn element<C: Ciphersuite>(hex_string: &str) -> Result<C::Element, OprfError> {
+    C::deserialize_element(&decode(hex_string)?)
+}
+
+/// Whether `test` of ciphersuite `C` gives the answer of `vector`. Inputs
+/// that fail to decode fail the test.
+fn check<C: Ciphersuite>(vector: &Vector, test: KnownAnswerTest) -> Result<bool, OprfError> {
+    let key = scalar::<C>(vector.key)?;
+    let blind = scalar::<C>(vector.blind)?;
+    let public_key = element::<C>(vector.public_key)?;
+    let blinded = element::<C>(vector.blinded)?;
+    let evaluated = element::<C>(vector.evaluated)?;
+
+    match test {
+        KnownAnswerTest::Serialization => {
+            let uncompressed = C::serialize_element_uncompressed(&evaluated)?;
+            Ok(hex::encode(C::serialize_scalar(&key)?) == vector.key
+                && hex::encode(C::serialize_element(&public_key)?) == vector.public_key
+                && C::deserialize_element_uncompressed(&uncompressed)? == evaluated)
+        }
+        KnownAnswerTest::ScalarMul => Ok(C::scalar_mul_generator(&key) == public_key
+            && C::scalar_mul(&blinded, &key) == evaluated
+            && C::scalar_mul_many(&[blinded, blinded], &key) == [evaluated, evaluated]),
+        KnownAnswerTest::HashToCurve => {
+            let mut dst = b"HashToGroup-".to_vec();
+            dst.extend_from_slice(&context_string::<C>(OprfMode::Voprf));
+            let point = C::hash_to_group(vector.input, &dst);
+            Ok(C::scalar_mul(&point, &blind) == blinded)
+        }
+        KnownAnswerTest::Dleq => {
+            let r = scalar::<C>(vector.proof_scalar)?;
+            let proof = dleq::generate_proof_with_scalar::<C>(
+                &key,
+                &public_key,
+                &[blinded],
+                &[evaluated],
+                &r,
+            )?;
+            let tampered = DleqProof::<C> {
+                c: proof.c,
+                s: proof.s + key,
+            };
+            Ok(hex::encode(proof.to_bytes()?) == vector.proof
+                && dleq::verify_proof::<C>(&public_key, &[blinded], &[evaluated], &proof).is_ok()
+                && dleq::verify_proof::<C>(&public_key, &[blinded], &[evaluated], &tampered)
+                    .is_err())
+        }
+        KnownAnswerTest::Finalize => {
+            let inverse = C::scalar_inverse(&blind)
+                .ok_or_else(|| OprfError::Deserialization("Zero blind".to_string()))?;
+            let unblinded = C::scalar_mul(&evaluated, &inverse);
+            Ok(hex::encode(finalize::<C>(vector.input, &unblinded)?) == vector.output)
+        }
+    }
+}
+
+fn run_suite<C: Ciphersuite>(vector: &Vector, results: &mut Vec<KnownAnswer>) {
+    for test in KnownAnswerTest::ALL {
+        results.push(KnownAnswer {
+            ciphersuite: C::ID,
+            test,
+            passed: matches!(check::<C>(vector, test), Ok(true)),
+        });
+    }
+}
+
+/// Run every known-answer test of every ciphersuite
+pub fn run() -> SelfTestReport {
+    let mut results = Vec::with_capacity(KnownAnswerTest::ALL.len() * CiphersuiteId::ALL.len());
+    run_suite::<Bn254Sha256>(&BN254_VECTOR, &mut results);
+    run_suite::<P256Sha256>(&P256_VECTOR, &mut results);
+    SelfTestReport { results }
+}
+
+/// Attested user data of a report: `"nitro-oprf-self-test-v1" ||
+/// SHA-256(JSON of the report)`
+pub fn report_binding(report: &SelfTestReport) -> Vec<u8> {
+    let encoded = serde_json::to_vec(report).expect("self-test reports serialize");
+    [REPORT_DOMAIN, Sha256::digest(encoded).as_slice()].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Message;
+
+    #[test]
+    fn test_self_test() {
+        let report = run();
+        assert!(report.passed(), "{:?}", report.failures().collect::<Vec<_>>());
+        assert_eq!(report.results.len(), 2 * KnownAnswerTest::ALL.len());
+        let encoded = serde_json::to_vec(&report).unwrap();
+        assert_eq!(serde_json::from_slice::<SelfTestReport>(&encoded).unwrap(), report);
+
+        // A failure changes the attested binding
+        let mut failed = report.clone();
+        failed.results[3].passed = false;
+        assert!(!failed.passed());
+        assert_eq!(failed.failures().count(), 1);
+        assert_ne!(report_binding(&failed), report_binding(&report));
+
+        let decoded: Message = serde_json::from_str(r#""GetSelfTest""#).unwrap();
+        assert!(matches!(decoded, Message::GetSelfTest));
+    }
+}
@@ -0,0 +1,122 @@
+//! Enclave response signatures.
+//!
+//! The enclave generates an Ed25519 session key at startup and, from
+//! protocol version 4 on, attests it together with the channel key in the
+//! answer to `Hello` (see [`session_binding`]). Every later response frame
+//! then carries a signature over its request id, format and payload,
+//! appended to the payload before the frame is sealed. One attestation, made
+//! once and cached, so vouches for every response of the session without an
+//! NSM call per request, and a parent that keeps a response with its
+//! signature can show a third party that the attested enclave sent it.
+
+use crate::channel::channel_binding;
+use crate::ed25519::{SigningKey, VerifyingKey, SIGNATURE_LEN};
+use crate::frame::{Frame, WireFormat};
+use crate::OprfError;
+use sha2::{Digest, Sha256};
+
+/// Domain separating the session key in [`session_binding`]
+const SIGNING_PROLOGUE: &[u8] = b"nitro-oprf-signing-v1";
+/// Domain separating signed responses from other Ed25519 messages
+const RESPONSE_DOMAIN: &[u8] = b"nitro-oprf-response-v1";
+
+/// Attestation user data binding the channel key and the response signing
+/// key of a version 4 enclave along with the offered protocol versions:
+/// `channel_binding(channel_key, offered_versions) ||
+/// "nitro-oprf-signing-v1" || signing_key`. The channel key has a fixed
+/// length and the versions are counted, so the split is unambiguous.
+pub fn session_binding(
+    channel_key: &[u8],
+    offered_versions: &[u32],
+    signing_key: &[u8],
+) -> Vec<u8> {
+    [&channel_binding(channel_key, offered_versions), SIGNING_PROLOGUE, signing_key].concat()
+}
+
+/// Message signed for a response: `"nitro-oprf-response-v1" || request_id
+/// || format || SHA-256(payload)`, the request id big-endian
+fn signed_message(request_id: u64, format: WireFormat, payload: &[u8]) -> Vec<u8> {
+    let mut message = RESPONSE_DOMAIN.to_vec();
+    message.extend_from_slice(&request_id.to_be_bytes());
+    message.push(format.id());
+    message.extend_from_slice(&Sha256::digest(payload));
+    message
+}
+
+/// Signature over a response payload to request `request_id`, to append to
+/// the payload
+pub fn sign_response(
+    key: &SigningKey,
+    request_id: u64,
+    format: WireFormat,
+    payload: &[u8],
+) -> [u8; SIGNATURE_LEN] {
+    key.sign(&signed_message(request_id, format, payload))
+}
+
+/// Verify the signature at the end of a response frame and strip it, leaving
+/// the payload
+pub fn verify_response(key: &VerifyingKey, frame: &mut Frame) -> Result<(), OprfError> {
+    let len = frame
+        .payload
+        .len()
+        .checked_sub(SIGNATURE_LEN)
+        .ok_or(OprfError::InvalidSignature)?;
+    let (payload, signature) = frame.payload.split_at(len);
+    key.verify(&signed_message(frame.request_id, frame.format, payload), signature)?;
+    frame.payload.truncate(len);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_std::test_rng;
+    use crate::channel;
+    use crate::ed25519::{SigningKey, VerifyingKey, SIGNATURE_LEN};
+    use crate::frame::{Frame, WireFormat};
+    use zeroize::Zeroizing;
+
+    #[test]
+    fn test_response_signing() {
+        let mut rng = test_rng();
+        let key = SigningKey::generate(&mut rng);
+        let verifying_key = VerifyingKey::from_bytes(&key.public_key()).unwrap();
+        let signed = |request_id: u64, format: WireFormat, payload: &[u8]| {
+            let signature = sign_response(&key, request_id, format, payload);
+            Frame {
+                request_id,
+                format,
+                payload: Zeroizing::new([payload, &signature[..]].concat()),
+            }
+        };
+
+        let mut frame = signed(7, WireFormat::Cbor, b"reply");
+        verify_response(&verifying_key, &mut frame).unwrap();
+        assert_eq!(frame.payload.as_slice(), b"reply");
+
+        // The signature covers the request id, format and payload
+        let mut frame = signed(7, WireFormat::Cbor, b"reply");
+        frame.request_id = 8;
+        assert!(verify_response(&verifying_key, &mut frame).is_err());
+        let mut frame = signed(7, WireFormat::Cbor, b"reply");
+        frame.format = WireFormat::Json;
+        assert!(verify_response(&verifying_key, &mut frame).is_err());
+        let mut frame = signed(7, WireFormat::Cbor, b"reply");
+        frame.payload[0] ^= 1;
+        assert!(verify_response(&verifying_key, &mut frame).is_err());
+
+        // An unsigned or truncated payload, or another key's signature, is rejected
+        let mut frame = signed(7, WireFormat::Cbor, b"");
+        frame.payload.truncate(SIGNATURE_LEN - 1);
+        assert!(verify_response(&verifying_key, &mut frame).is_err());
+        let other = VerifyingKey::from_bytes(&SigningKey::generate(&mut rng).public_key()).unwrap();
+        assert!(verify_response(&other, &mut signed(7, WireFormat::Cbor, b"reply")).is_err());
+
+        // The session binding extends the channel binding
+        let channel_key = [2u8; 33];
+        let binding = session_binding(&channel_key, &[4], &key.public_key());
+        assert!(binding.starts_with(&channel::channel_binding(&channel_key, &[4])));
+        assert!(binding.ends_with(&key.public_key()));
+    }
+}
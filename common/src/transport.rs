@@ -0,0 +1,107 @@
+//! Sealing a secret key to an enclave's ephemeral transport key.
+//!
+//! ECIES over the ciphersuite group: the sender picks an ephemeral scalar `e`,
+//! computes the shared point `pk_t^e` for the enclave's transport key `pk_t`,
+//! and seals the payload with [`crate::envelope::seal`] under
+//! `HKDF(ikm = pk_t^e, info = identifier || g^e || pk_t)`. Only the holder of
+//! the transport secret key can recompute the shared point.
+
+use crate::envelope::{open, seal, DATA_KEY_LEN, NONCE_LEN};
+use crate::kdf::hkdf;
+use crate::{Ciphersuite, OprfError};
+use ark_std::rand::Rng;
+
+/// A payload sealed to a transport key
+pub struct SealedPayload {
+    /// Sender's ephemeral public key g^e, serialized
+    pub sender_public_key: Vec<u8>,
+    pub nonce: [u8; NONCE_LEN],
+    /// `ciphertext || tag`
+    pub ciphertext: Vec<u8>,
+}
+
+fn transport_data_key<C: Ciphersuite>(
+    shared: &C::Element,
+    sender_public_key: &[u8],
+    recipient_public_key: &[u8],
+) -> Result<[u8; DATA_KEY_LEN], OprfError> {
+    let mut info = C::IDENTIFIER.as_bytes().to_vec();
+    info.extend_from_slice(sender_public_key);
+    info.extend_from_slice(recipient_public_key);
+    let mut data_key = [0u8; DATA_KEY_LEN];
+    let shared = C::serialize_element(shared)?;
+    hkdf(b"nitro-oprf-transport-v1", &shared, &info, &mut data_key);
+    Ok(data_key)
+}
+
+/// Seal `plaintext` to the transport public key `recipient`
+pub fn seal_to<C: Ciphersuite, R: Rng>(
+    recipient: &C::Element,
+    aad: &[u8],
+    plaintext: &[u8],
+    rng: &mut R,
+) -> Result<SealedPayload, OprfError> {
+    let ephemeral = C::random_scalar(rng);
+    let sender_public_key = C::serialize_element(&C::scalar_mul_generator(&ephemeral))?;
+    let shared = C::scalar_mul(recipient, &ephemeral);
+    let data_key =
+        transport_data_key::<C>(&shared, &sender_public_key, &C::serialize_element(recipient)?)?;
+
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill_bytes(&mut nonce);
+    Ok(SealedPayload {
+        ciphertext: seal(&data_key, &nonce, aad, plaintext),
+        sender_public_key,
+        nonce,
+    })
+}
+
+/// Open a payload sealed by [`seal_to`] with the transport secret key
+pub fn open_with<C: Ciphersuite>(
+    secret_key: &C::Scalar,
+    sender_public_key: &[u8],
+    nonce: &[u8; NONCE_LEN],
+    aad: &[u8],
+    ciphertext: &[u8],
+) -> Result<Vec<u8>, OprfError> {
+    let sender = C::deserialize_element(sender_public_key)?;
+    let shared = C::scalar_mul(&sender, secret_key);
+    let recipient_public_key = C::serialize_element(&C::scalar_mul_generator(secret_key))?;
+    let data_key = transport_data_key::<C>(&shared, sender_public_key, &recipient_public_key)?;
+    open(&data_key, nonce, aad, ciphertext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_std::test_rng;
+    use crate::{key_injection_binding, P256Sha256};
+
+    #[test]
+    fn test_key_transport() {
+        let mut rng = test_rng();
+        let transport_key = P256Sha256::random_scalar(&mut rng);
+        let transport_public_key = P256Sha256::scalar_mul_generator(&transport_key);
+        let aad = key_injection_binding("migrated", 3, &[]);
+
+        let sealed =
+            seal_to::<P256Sha256, _>(&transport_public_key, &aad, b"k", &mut rng)
+                .unwrap();
+        let open = |secret_key: &<P256Sha256 as Ciphersuite>::Scalar, aad: &[u8]| {
+            open_with::<P256Sha256>(
+                secret_key,
+                &sealed.sender_public_key,
+                &sealed.nonce,
+                aad,
+                &sealed.ciphertext,
+            )
+        };
+        assert_eq!(open(&transport_key, &aad).unwrap(), b"k");
+
+        // Another transport key or target key cannot open it
+        let other_key = P256Sha256::random_scalar(&mut rng);
+        assert!(open(&other_key, &aad).is_err());
+        assert!(open(&transport_key, &key_injection_binding("migrated", 4, &[])).is_err());
+        assert!(open(&transport_key, &key_injection_binding("other", 3, &[])).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+//! gRPC listener of the enclave itself, for guests with networking such as
+//! TDX ones, where clients can reach the evaluator without a parent.
+//!
+//! Requests go to the workers over in-process connections of a
+//! [`LocalListener`](oprf_server::LocalListener), so they are served,
+//! rate-limited and logged like those of the other listeners. They speak
+//! protocol version 2: the connections never leave the process, so they
+//! need no secure channel.
+
+use oprf_common::frame::{read_frame, write_frame, WireFormat};
+use oprf_common::grpc::Backend;
+use oprf_common::{HelloRequest, HelloResponse, Message, OprfResult, PointEncoding};
+use oprf_server::LocalConnector;
+use serde::de::DeserializeOwned;
+use std::io::{Error, ErrorKind};
+use std::os::unix::net::UnixStream;
+use std::sync::{Mutex, MutexGuard};
+
+const PROTOCOL_VERSION: u32 = 2;
+
+/// Backend of the gRPC service answering from the enclave's own workers
+pub struct Local {
+    connector: LocalConnector,
+    /// Connections past their Hello, waiting for a request
+    idle: Mutex<Vec<UnixStream>>,
+}
+
+impl Local {
+    pub fn new(connector: LocalConnector) -> Self {
+        Self {
+            connector,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// A new connection, past its Hello
+    fn open(&self) -> std::io::Result<UnixStream> {
+        let mut stream = self.connector.connect()?;
+        let hello = Message::Hello(HelloRequest {
+            versions: vec![PROTOCOL_VERSION],
+            point_encoding: PointEncoding::default(),
+            request_ids: true,
+        });
+        let hello: HelloResponse = exchange(&mut stream, &hello)?;
+        if hello.version != PROTOCOL_VERSION {
+            let message = format!("Enclave chose protocol version {}", hello.version);
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+        Ok(stream)
+    }
+
+    fn pool(&self) -> MutexGuard<'_, Vec<UnixStream>> {
+        self.idle.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Answer to `message` on `stream`
+fn exchange<T: DeserializeOwned>(stream: &mut UnixStream, message: &Message) -> std::io::Result<T> {
+    let payload = WireFormat::Cbor.encode(message).map_err(Error::other)?;
+    write_frame(stream, 1, WireFormat::Cbor, &payload)?;
+    let frame = read_frame(stream)?
+        .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Enclave closed the connection"))?;
+    frame.format.decode(&frame.payload).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+impl Backend for Local {
+    fn request<Resp: DeserializeOwned>(&self, message: &Message) -> std::io::Result<Resp> {
+        // A pooled connection may have been closed as idle, so a request
+        // failing on one is sent again on a new connection
+        let pooled = self.pool().pop().and_then(|mut stream| {
+            let response = exchange::<OprfResult<Resp>>(&mut stream, message).ok()?;
+            Some((stream, response))
+        });
+        let (stream, response) = match pooled {
+            Some(answered) => answered,
+            None => {
+                let mut stream = self.open()?;
+                let response = exchange(&mut stream, message)?;
+                (stream, response)
+            }
+        };
+        self.pool().push(stream);
+        response.map_err(Error::other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oprf_common::ed25519::SigningKey;
+    use oprf_common::{
+        AttestationResponse, CiphersuiteId, ErrorCode, ErrorResponse, GetAttestationRequest,
+        GetPublicKeyRequest, PublicKeyResponse,
+    };
+    use oprf_server::{
+        local_listener, ConnectionLimits, Enclave, EvaluationOptions, Listener, MockAttestation,
+        RateLimits, Shutdown, UsageLimits,
+    };
+    use std::time::Duration;
+
+    /// The backend of an enclave served within `limits` until the returned
+    /// shutdown is requested
+    fn start(limits: ConnectionLimits) -> (Local, Shutdown) {
+        let key = SigningKey::generate(&mut rand::rngs::OsRng);
+        let enclave = Enclave::new(
+            UsageLimits::default(),
+            RateLimits::default(),
+            EvaluationOptions::default(),
+            0,
+            60,
+            Box::new(MockAttestation::new(key)),
+        );
+        let (listener, connector) = local_listener();
+        let shutdown = Shutdown::new();
+        let serving = shutdown.clone();
+        std::thread::spawn(move || {
+            let listeners: Vec<Box<dyn Listener>> = vec![Box::new(listener)];
+            oprf_server::serve(enclave, 2, limits, listeners, None, None, &serving)
+        });
+        (Local::new(connector), shutdown)
+    }
+
+    #[test]
+    fn test_local_requests() {
+        let limits = ConnectionLimits {
+            idle_timeout: Duration::from_secs(1),
+            ..ConnectionLimits::default()
+        };
+        let (local, shutdown) = start(limits);
+        let public_key = Message::GetPublicKey(GetPublicKeyRequest {
+            ciphersuite: CiphersuiteId::P256Sha256,
+            key_id: "app".to_string(),
+        });
+        let key: PublicKeyResponse = local.request(&public_key).unwrap();
+        assert_eq!(key.key_id, "app");
+
+        let attestation = Message::GetAttestation(GetAttestationRequest {
+            ciphersuite: CiphersuiteId::P256Sha256,
+            key_id: "app".to_string(),
+            nonce: vec![0; 65],
+        });
+        let refused = local.request::<AttestationResponse>(&attestation).unwrap_err();
+        let refused = refused.get_ref().and_then(|e| e.downcast_ref::<ErrorResponse>());
+        assert_eq!(refused.unwrap().code, ErrorCode::BadRequest);
+
+        // The pooled connection is closed as idle, and replaced
+        std::thread::sleep(Duration::from_millis(2500));
+        let again: PublicKeyResponse = local.request(&public_key).unwrap();
+        assert_eq!(again.public_key, key.public_key);
+        shutdown.request();
+    }
+}
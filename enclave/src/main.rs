@@ -1,273 +1,480 @@
-use ark_bn254::Fr;
-use ark_ff::UniformRand;
-use oprf_common::{
-    deserialize_g1, scalar_mul, scalar_mul_generator, serialize_g1,
-    sha256_hex, AttestationDocument, OprfRequest, OprfResponse,
+//! OPRF enclave binary: parses the command line, then serves an
+//! [`oprf_server::Enclave`] with the attestation provider and listeners of
+//! the build's mode.
+
+#[cfg(feature = "grpc")]
+mod grpc;
+
+use oprf_common::config::{Config as ConfigFile, EnclaveConfig, DEFAULT_ADMIN_PORT};
+use oprf_common::ed25519::VerifyingKey;
+use oprf_common::rng::SharedRng;
+#[cfg(feature = "chaos")]
+use oprf_server::{Chaos, Fault};
+use oprf_server::{
+    AdminPort, AttestationProvider, ConnectionLimits, Enclave, EvaluationOptions, Listener, Rate,
+    HeartbeatPort, RateLimits, Shutdown, UsageLimits,
 };
-use rand::rngs::OsRng;
-use std::io::{Read, Write};
+use std::time::Duration;
 
-#[cfg(feature = "nitro")]
-use aws_nitro_enclaves_nsm_api::api::{Request as NsmRequest, Response as NsmResponse};
-#[cfg(feature = "nitro")]
-use aws_nitro_enclaves_nsm_api::driver as nsm_driver;
-#[cfg(feature = "nitro")]
-use std::os::unix::io::AsRawFd;
-
-const VSOCK_PORT: u32 = 5000;
-const VSOCK_CID_ANY: u32 = 0xFFFFFFFF;
 #[allow(dead_code)]
 const VSOCK_CID_PARENT: u32 = 3;
-const LOCAL_PORT: u16 = 5000;
 
-/// Enclave state holding the secret key and public key
-struct EnclaveState {
-    /// Secret key k
-    secret_key: Fr,
-    /// Public key g^k (serialized)
-    public_key_bytes: Vec<u8>,
+/// Upper bound on `--workers`
+const MAX_WORKERS: usize = 256;
+
+/// Upper bound on `--evaluation-threads`
+const MAX_EVALUATION_THREADS: usize = 256;
+
+/// Upper bound on `--evaluation-cache`
+const MAX_CACHE_ENTRIES: usize = 1 << 20;
+
+/// Longest delay `--chaos` injects unless `--chaos-max-delay` is given
+#[cfg(feature = "chaos")]
+const DEFAULT_CHAOS_MAX_DELAY_MS: u64 = 2000;
+
+/// Where the server listens
+#[derive(Debug, Clone, Copy)]
+struct ListenAddress {
+    /// vsock CID to bind, any by default; unused in local mode
+    #[cfg_attr(not(feature = "nitro"), allow(dead_code))]
+    cid: u32,
+    /// vsock port, or TCP port on 127.0.0.1 in local mode
+    port: u32,
+    /// Extra TCP port on 127.0.0.1 served alongside `port`
+    tcp_port: Option<u32>,
+    /// Port of the admin listener, on the same CID, when an operator key is set
+    admin_port: u32,
+    /// Port heartbeats are published on, on the same CID, if any
+    heartbeat_port: Option<u32>,
 }
 
-impl EnclaveState {
-    fn new() -> Self {
-        let mut rng = OsRng;
-        let secret_key = Fr::rand(&mut rng);
-        let public_key = scalar_mul_generator(&secret_key);
-        let public_key_bytes = serialize_g1(&public_key). expect("Failed to serialize public key");
-
-        println!("[Enclave] Generated secret key and public key");
-        println!("[Enclave] Public key (hex): {}", hex::encode(&public_key_bytes));
+/// Enclave settings from the configuration file, environment and command
+/// line
+#[derive(Debug)]
+struct Config {
+    /// Evaluation budget of every key. It is set on the enclave command
+    /// line, which is part of the measured image, so a compromised parent
+    /// cannot raise it.
+    limits: UsageLimits,
+    /// Evaluation rates of every connection and client id, measured like
+    /// `limits`
+    rate_limits: RateLimits,
+    /// How long an evaluation attestation is reused for identical content
+    attestation_ttl_secs: u64,
+    /// Least time between attestations of the audit log head
+    audit_checkpoint_secs: u64,
+    /// Number of connection handler threads
+    workers: usize,
+    /// Threads one batch evaluation may use and the cache of evaluations
+    evaluation: EvaluationOptions,
+    /// Bounds on open connections and how slowly they may send
+    connection_limits: ConnectionLimits,
+    /// Time between heartbeats on the heartbeat port
+    heartbeat_interval: Duration,
+    address: ListenAddress,
+    /// Key operator commands on the admin port are signed with; no admin
+    /// port without one. Measured like `limits`, so the parent cannot
+    /// replace it.
+    operator_key: Option<VerifyingKey>,
+    /// KMS key of backups and seeds, measured like `limits`, so the parent
+    /// cannot have keys backed up under a key of its own
+    kms_key_id: Option<String>,
+    /// Log public keys and other sensitive values in full, see
+    /// [`oprf_common::redact`]
+    log_sensitive: bool,
+    /// Source of the enclave's keys: the operating system's, or one seeded
+    /// by `--rng-seed` for reproducible tests
+    rng: SharedRng,
+    /// Faults injected in responses, set by `--chaos`
+    #[cfg(feature = "chaos")]
+    chaos: Option<Chaos>,
+    /// Address the gRPC service is served on, set by `--grpc-addr`
+    #[cfg(feature = "grpc")]
+    grpc_addr: Option<String>,
+}
 
-        Self {
-            secret_key,
-            public_key_bytes,
-        }
+impl Config {
+    /// Settings of the `[enclave]` table of the configuration, which the
+    /// flags override
+    fn from_settings(settings: &EnclaveConfig) -> Result<Self, String> {
+        let limits = UsageLimits::default();
+        let connection_limits = ConnectionLimits::default();
+        let secs_or = |secs: Option<u64>, default| secs.map_or(default, Duration::from_secs);
+        Ok(Self {
+            limits: UsageLimits {
+                max_total: settings.max_evaluations,
+                max_per_window: settings.max_evaluations_per_window,
+                window_secs: settings.window_secs.unwrap_or(limits.window_secs),
+            },
+            // Checked along with the flags
+            rate_limits: RateLimits::default(),
+            attestation_ttl_secs: settings.attestation_ttl_secs,
+            audit_checkpoint_secs: settings.audit_checkpoint_secs,
+            workers: settings.workers,
+            evaluation: EvaluationOptions {
+                // Every vCPU the enclave was given
+                threads: settings.evaluation_threads.unwrap_or_else(|| {
+                    std::thread::available_parallelism()
+                        .map_or(1, |n| n.get().min(MAX_EVALUATION_THREADS))
+                }),
+                cache_entries: settings.evaluation_cache,
+            },
+            connection_limits: ConnectionLimits {
+                max_open: settings.max_connections.unwrap_or(connection_limits.max_open),
+                idle_timeout: secs_or(settings.idle_timeout_secs, connection_limits.idle_timeout),
+                frame_timeout: secs_or(
+                    settings.frame_timeout_secs,
+                    connection_limits.frame_timeout,
+                ),
+                drain_timeout: secs_or(
+                    settings.drain_timeout_secs,
+                    connection_limits.drain_timeout,
+                ),
+            },
+            heartbeat_interval: Duration::from_secs(settings.heartbeat_interval_secs),
+            address: ListenAddress {
+                cid: settings.cid,
+                port: settings.port,
+                tcp_port: settings.tcp_port,
+                admin_port: DEFAULT_ADMIN_PORT,
+                heartbeat_port: settings.heartbeat_port,
+            },
+            operator_key: settings
+                .admin_key
+                .as_deref()
+                .map(|key| operator_key(key, "admin_key"))
+                .transpose()?,
+            kms_key_id: settings.kms_key_id.clone(),
+            log_sensitive: settings.log_sensitive,
+            rng: SharedRng::os(),
+            #[cfg(feature = "chaos")]
+            chaos: None,
+            #[cfg(feature = "grpc")]
+            grpc_addr: None,
+        })
     }
+}
 
-    fn evaluate(&self, request: &OprfRequest) -> Result<OprfResponse, String> {
-        // Verify hash
-        let computed_hash = sha256_hex(&request.blinded_query);
-        if computed_hash != request.query_hash {
-            return Err("Query hash mismatch".to_string());
-        }
-
-        // Deserialize the blinded query point
-        let blinded_query = deserialize_g1(&request.blinded_query)
-            .map_err(|e| format!("Failed to deserialize query: {}", e))?;
-
-        println!("[Enclave] Received blinded query");
-
-        // Compute output = blinded_query^k
-        let evaluated = scalar_mul(&blinded_query, &self.secret_key);
-        let evaluated_bytes =
-            serialize_g1(&evaluated). map_err(|e| format!("Failed to serialize result: {}", e))?;
-
-        println!("[Enclave] Computed OPRF evaluation");
-
-        // Generate attestation
-        let attestation = self.generate_attestation(&evaluated_bytes)?;
+/// Values of the `--chaos` flags
+#[cfg(feature = "chaos")]
+#[derive(Debug, Default)]
+struct ChaosFlags {
+    probability: Option<f64>,
+    faults: Option<Vec<Fault>>,
+    max_delay_ms: Option<u64>,
+}
 
-        Ok(OprfResponse {
-            evaluated_point: evaluated_bytes,
-            public_key: self. public_key_bytes.clone(),
-            attestation,
-        })
+#[cfg(feature = "chaos")]
+impl ChaosFlags {
+    fn set(&mut self, flag: &str, value: &str) -> Result<(), String> {
+        let invalid = |e: &dyn std::fmt::Display| format!("Invalid value for {}: {}", flag, e);
+        match flag {
+            "--chaos" => self.probability = Some(value.parse().map_err(|e| invalid(&e))?),
+            "--chaos-faults" => {
+                let faults = value.split(',').map(str::parse).collect::<Result<_, String>>();
+                self.faults = Some(faults.map_err(|e| invalid(&e))?);
+            }
+            "--chaos-max-delay" => {
+                self.max_delay_ms = Some(value.parse().map_err(|e| invalid(&e))?)
+            }
+            _ => return Err(format!("Unknown argument {}", flag)),
+        }
+        Ok(())
     }
 
-    #[cfg(all(feature = "local", not(feature = "nitro")))]
-    fn generate_attestation(&self, user_data: &[u8]) -> Result<AttestationDocument, String> {
-        println!("[Enclave] Generating mock attestation (local mode)");
+    /// Faults to inject, if `--chaos` is given
+    fn chaos(self) -> Result<Option<Chaos>, String> {
+        let Some(probability) = self.probability else {
+            if self.faults.is_some() || self.max_delay_ms.is_some() {
+                return Err("--chaos-faults and --chaos-max-delay require --chaos".to_string());
+            }
+            return Ok(None);
+        };
+        let faults = self.faults.unwrap_or_else(|| Fault::ALL.to_vec());
+        let max_delay = self.max_delay_ms.unwrap_or(DEFAULT_CHAOS_MAX_DELAY_MS);
+        Chaos::new(probability, faults, Duration::from_millis(max_delay))
+            .map(Some)
+            .map_err(|e| format!("Invalid --chaos: {}", e))
+    }
+}
 
-        // Create a mock attestation for local testing
-        let mock_doc = serde_json::json!({
-            "module_id": "mock-enclave",
-            "timestamp": chrono_lite_timestamp(),
-            "public_key_hash": sha256_hex(&self. public_key_bytes),
-            "user_data_hash": sha256_hex(user_data),
-        });
+/// Operator key of the hex `key` given as `name`
+fn operator_key(key: &str, name: &str) -> Result<VerifyingKey, String> {
+    hex::decode(key.trim())
+        .map_err(|e| e.to_string())
+        .and_then(|key| VerifyingKey::from_bytes(&key).map_err(|e| e.to_string()))
+        .map_err(|e| format!("Invalid {}: {}", name, e))
+}
 
-        Ok(AttestationDocument {
-            is_mock: true,
-            document: serde_json::to_vec(&mock_doc). unwrap(),
-            pcrs: Some(vec![
-                "0". repeat(96), // PCR0 - mock
-                "0".repeat(96), // PCR1 - mock
-                "0".repeat(96), // PCR2 - mock
-            ]),
-            user_data: user_data.to_vec(),
-        })
+/// RNG seeded by `--rng-seed`, refused in Nitro mode, where the keys must
+/// be unpredictable
+fn seeded_rng(seed: &str) -> Result<SharedRng, String> {
+    if cfg!(feature = "nitro") {
+        return Err("--rng-seed is refused in Nitro mode".to_string());
     }
+    SharedRng::from_hex_seed(seed).map_err(|e| format!("Invalid --rng-seed: {}", e))
+}
 
-    #[cfg(feature = "nitro")]
-    fn generate_attestation(&self, user_data: &[u8]) -> Result<AttestationDocument, String> {
-        println!("[Enclave] Generating NSM attestation (Nitro mode)");
-
-        let nsm_fd = nsm_driver::nsm_init();
-        if nsm_fd < 0 {
-            return Err("Failed to initialize NSM driver".to_string());
+/// Parse the command line over the `[enclave]` settings of the configuration
+/// file and environment, see [`oprf_common::config`]:
+/// `[--config <file>] [--max-evaluations <n>] [--max-evaluations-per-window <n>]
+///  [--window-secs <secs>]
+///  [--connection-rate <n> [--connection-burst <n>]] [--client-rate <n> [--client-burst <n>]]
+///  [--attestation-ttl <secs>] [--audit-checkpoint-secs <secs>] [--workers <n>]
+///  [--evaluation-threads <n>] [--evaluation-cache <entries>] [--max-connections <n>]
+///  [--idle-timeout <secs>] [--frame-timeout <secs>] [--drain-timeout <secs>] [--cid <n>]
+///  [--port <n>] [--tcp-port <n>] [--admin-key <hex> [--admin-port <n>]] [--kms-key-id <key>]
+///  [--heartbeat-port <n> [--heartbeat-interval <secs>]] [--log-sensitive]
+///  [--rng-seed <hex>]
+///  [--chaos <probability> [--chaos-faults <fault,...>] [--chaos-max-delay <ms>]]
+///  [--grpc-addr <host:port>]`
+///
+/// Bursts default to one second's worth of the rate. The `--chaos` flags
+/// need the `chaos` feature; faults are `delay`, `truncate`, `corrupt` and
+/// `error`, all of them by default. `--grpc-addr` needs the `grpc` feature
+/// and is refused in Nitro mode, where the enclave has no network.
+/// The `ENCLAVE_CID` and `ENCLAVE_PORT` environment variables set the listen
+/// address where the flags do not, over the configuration.
+fn parse_args() -> Result<Config, String> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let settings = ConfigFile::load(&args).map_err(|e| e.to_string())?.enclave;
+    let mut config = Config::from_settings(&settings)?;
+    let address = &mut config.address;
+    for (name, field) in [("ENCLAVE_CID", &mut address.cid), ("ENCLAVE_PORT", &mut address.port)] {
+        if let Ok(value) = std::env::var(name) {
+            *field = value.parse().map_err(|e| format!("Invalid {}: {}", name, e))?;
         }
-
-        // Include public key and evaluated point hash in attestation
-        let mut attestation_data = self.public_key_bytes.clone();
-        attestation_data. extend_from_slice(sha256_hex(user_data). as_bytes());
-
-        let request = NsmRequest::Attestation {
-            user_data: Some(attestation_data. clone(). into()),
-            nonce: None,
-            public_key: None,
+    }
+    let limits = &mut config.limits;
+    let mut connection_rate = (settings.connection_rate, settings.connection_burst);
+    let mut client_rate = (settings.client_rate, settings.client_burst);
+    let mut args = args.into_iter();
+    let mut admin_port = settings.admin_port;
+    #[cfg(feature = "chaos")]
+    let mut chaos = ChaosFlags::default();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            // Read by `ConfigFile::load`
+            args.next();
+            continue;
+        }
+        if arg == "--admin-key" {
+            let key = args.next().ok_or("--admin-key requires a value")?;
+            config.operator_key = Some(operator_key(&key, "--admin-key")?);
+            continue;
+        }
+        if arg == "--kms-key-id" {
+            config.kms_key_id = Some(args.next().ok_or("--kms-key-id requires a value")?);
+            continue;
+        }
+        if arg == "--log-sensitive" {
+            config.log_sensitive = true;
+            continue;
+        }
+        if arg == "--rng-seed" {
+            let seed = args.next().ok_or("--rng-seed requires a value")?;
+            config.rng = seeded_rng(&seed)?;
+            continue;
+        }
+        if arg == "--grpc-addr" {
+            #[cfg(not(feature = "grpc"))]
+            return Err("--grpc-addr requires an enclave built with the grpc feature".to_string());
+            #[cfg(feature = "grpc")]
+            {
+                if cfg!(feature = "nitro") {
+                    return Err("--grpc-addr is refused in Nitro mode".to_string());
+                }
+                config.grpc_addr = Some(args.next().ok_or("--grpc-addr requires a value")?);
+                continue;
+            }
+        }
+        if arg == "--chaos" || arg.starts_with("--chaos-") {
+            #[cfg(not(feature = "chaos"))]
+            return Err(format!("{} requires an enclave built with the chaos feature", arg));
+            #[cfg(feature = "chaos")]
+            {
+                let value = args.next().ok_or(format!("{} requires a value", arg))?;
+                chaos.set(&arg, &value)?;
+                continue;
+            }
+        }
+        let mut value = || {
+            args.next()
+                .ok_or(format!("{} requires a value", arg))?
+                .parse::<u64>()
+                .map_err(|e| format!("Invalid value for {}: {}", arg, e))
         };
-
-        let response = nsm_driver::nsm_process_request(nsm_fd, request);
-
-        match response {
-            NsmResponse::Attestation { document } => {
-                // Parse CBOR to extract PCRs
-                let pcrs = extract_pcrs_from_attestation(&document);
-
-                Ok(AttestationDocument {
-                    is_mock: false,
-                    document,
-                    pcrs,
-                    user_data: user_data.to_vec(),
-                })
+        match arg.as_str() {
+            "--max-evaluations" => limits.max_total = Some(value()?),
+            "--max-evaluations-per-window" => limits.max_per_window = Some(value()?),
+            "--window-secs" => limits.window_secs = value()?,
+            "--connection-rate" => connection_rate.0 = Some(value()?),
+            "--connection-burst" => connection_rate.1 = Some(value()?),
+            "--client-rate" => client_rate.0 = Some(value()?),
+            "--client-burst" => client_rate.1 = Some(value()?),
+            "--attestation-ttl" => config.attestation_ttl_secs = value()?,
+            "--audit-checkpoint-secs" => config.audit_checkpoint_secs = value()?,
+            "--workers" => config.workers = value()? as usize,
+            "--evaluation-threads" => config.evaluation.threads = value()? as usize,
+            "--evaluation-cache" => config.evaluation.cache_entries = value()? as usize,
+            "--max-connections" => config.connection_limits.max_open = value()? as usize,
+            "--idle-timeout" => {
+                config.connection_limits.idle_timeout = Duration::from_secs(value()?)
+            }
+            "--frame-timeout" => {
+                config.connection_limits.frame_timeout = Duration::from_secs(value()?)
             }
-            NsmResponse::Error(e) => Err(format!("NSM error: {:?}", e)),
-            _ => Err("Unexpected NSM response".to_string()),
+            "--drain-timeout" => {
+                config.connection_limits.drain_timeout = Duration::from_secs(value()?)
+            }
+            "--heartbeat-interval" => config.heartbeat_interval = Duration::from_secs(value()?),
+            "--cid" | "--port" | "--tcp-port" | "--admin-port" | "--heartbeat-port" => {
+                let value = u32::try_from(value()?).map_err(|_| format!("{} out of range", arg))?;
+                match arg.as_str() {
+                    "--cid" => config.address.cid = value,
+                    "--port" => config.address.port = value,
+                    "--tcp-port" => config.address.tcp_port = Some(value),
+                    "--heartbeat-port" => config.address.heartbeat_port = Some(value),
+                    _ => admin_port = Some(value),
+                }
+            }
+            _ => return Err(format!("Unknown argument {}", arg)),
         }
     }
+    #[cfg(feature = "chaos")]
+    {
+        config.chaos = chaos.chaos()?;
+    }
+    config.rate_limits = RateLimits {
+        per_connection: rate(connection_rate, "--connection")?,
+        per_client: rate(client_rate, "--client")?,
+    };
+    if let Some(port) = admin_port {
+        if config.operator_key.is_none() {
+            return Err("--admin-port requires --admin-key".to_string());
+        }
+        config.address.admin_port = port;
+    }
+    if config.limits.window_secs == 0 {
+        return Err("--window-secs must be positive".to_string());
+    }
+    if config.heartbeat_interval.is_zero() {
+        return Err("--heartbeat-interval must be positive".to_string());
+    }
+    if !(1..=MAX_WORKERS).contains(&config.workers) {
+        return Err(format!("--workers must be between 1 and {}", MAX_WORKERS));
+    }
+    if !(1..=MAX_EVALUATION_THREADS).contains(&config.evaluation.threads) {
+        return Err(format!(
+            "--evaluation-threads must be between 1 and {}",
+            MAX_EVALUATION_THREADS
+        ));
+    }
+    if config.evaluation.cache_entries > MAX_CACHE_ENTRIES {
+        return Err(format!("--evaluation-cache must be at most {}", MAX_CACHE_ENTRIES));
+    }
+    let connection_limits = &config.connection_limits;
+    if connection_limits.max_open == 0 {
+        return Err("--max-connections must be positive".to_string());
+    }
+    if connection_limits.idle_timeout.is_zero() || connection_limits.frame_timeout.is_zero() {
+        return Err("--idle-timeout and --frame-timeout must be positive".to_string());
+    }
+    Ok(config)
 }
 
-#[cfg(feature = "nitro")]
-fn extract_pcrs_from_attestation(document: &[u8]) -> Option<Vec<String>> {
-    // Parse CBOR attestation document to extract PCRs
-    let value: serde_cbor::Value = serde_cbor::from_slice(document). ok()?;
-
-    if let serde_cbor::Value::Map(map) = value {
-        for (key, val) in map {
-            if let serde_cbor::Value::Text(k) = key {
-                if k == "pcrs" {
-                    if let serde_cbor::Value::Map(pcr_map) = val {
-                        let mut pcrs = Vec::new();
-                        for i in 0..3 {
-                            if let Some((_, serde_cbor::Value::Bytes(bytes))) =
-                                pcr_map. iter().find(|(k, _)| **k == serde_cbor::Value::Integer(i))
-                            {
-                                pcrs.push(hex::encode(bytes));
-                            }
-                        }
-                        return Some(pcrs);
-                    }
-                }
-            }
-        }
+/// Rate limit of the `<prefix>-rate` and `<prefix>-burst` flags
+fn rate(
+    (per_sec, burst): (Option<u64>, Option<u64>),
+    prefix: &str,
+) -> Result<Option<Rate>, String> {
+    match (per_sec, burst) {
+        (None, None) => Ok(None),
+        (None, Some(_)) => Err(format!("{}-burst requires {}-rate", prefix, prefix)),
+        (Some(0), _) | (_, Some(0)) => {
+            Err(format!("{}-rate and {}-burst must be positive", prefix, prefix))
+        },
+        (Some(per_sec), burst) => Ok(Some(Rate {
+            per_sec,
+            burst: burst.unwrap_or(per_sec),
+        })),
     }
-    None
 }
 
-fn chrono_lite_timestamp() -> u64 {
-    std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs()
+/// Attestation provider of the build's mode
+#[cfg(feature = "nitro")]
+fn attestation_provider() -> Box<dyn AttestationProvider> {
+    Box::new(oprf_server::NsmAttestation)
 }
 
+/// Attestation provider of the build's mode: mock attestations signed with
+/// a key generated at startup and printed for the parent's
+/// `--mock-signing-key`
 #[cfg(all(feature = "local", not(feature = "nitro")))]
-fn run_server(state: EnclaveState) -> std::io::Result<()> {
-    use std::net::TcpListener;
-
-    let listener = TcpListener::bind(format!("127.0.0.1:{}", LOCAL_PORT))?;
-    println!("[Enclave] Local server listening on 127.0.0.1:{}", LOCAL_PORT);
+fn attestation_provider() -> Box<dyn AttestationProvider> {
+    let key = oprf_common::ed25519::SigningKey::generate(&mut rand::rngs::OsRng);
+    let provider = oprf_server::MockAttestation::new(key);
+    println!("[Enclave] Mock attestation key (hex): {}", hex::encode(provider.public_key()));
+    Box::new(provider)
+}
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(mut stream) => {
-                println!("[Enclave] Connection received");
-                handle_connection(&mut stream, &state);
-            }
-            Err(e) => eprintln!("[Enclave] Connection error: {}", e),
-        }
+/// Bind the listeners of `address`: vsock in Nitro mode, loopback TCP in
+/// local mode, and the extra loopback TCP port if one is set
+fn bind_listeners(address: ListenAddress) -> std::io::Result<Vec<Box<dyn Listener>>> {
+    #[cfg(feature = "nitro")]
+    let mut listeners: Vec<Box<dyn Listener>> =
+        vec![Box::new(oprf_server::VsockListener::bind(address.cid, address.port)?)];
+    #[cfg(not(feature = "nitro"))]
+    let mut listeners: Vec<Box<dyn Listener>> =
+        vec![Box::new(oprf_server::bind_tcp(address.port)?)];
+    if let Some(port) = address.tcp_port {
+        listeners.push(Box::new(oprf_server::bind_tcp(port)?));
     }
-    Ok(())
+    Ok(listeners)
 }
 
-#[cfg(feature = "nitro")]
-fn run_server(state: EnclaveState) -> std::io::Result<()> {
-    use nix::sys::socket::{
-        accept, bind, listen, socket, AddressFamily, SockFlag, SockType, VsockAddr,
+/// Bind the heartbeat listener of `address`, if it has a heartbeat port,
+/// publishing a beat every `interval`
+fn bind_heartbeat(
+    address: ListenAddress,
+    interval: Duration,
+) -> std::io::Result<Option<HeartbeatPort>> {
+    let Some(port) = address.heartbeat_port else {
+        return Ok(None);
     };
-    use std::os::unix::io::FromRawFd;
-
-    let sock_fd = socket(
-        AddressFamily::Vsock,
-        SockType::Stream,
-        SockFlag::empty(),
-        None,
-    )
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
-    let addr = VsockAddr::new(VSOCK_CID_ANY, VSOCK_PORT);
-    bind(sock_fd. as_raw_fd(), &addr)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))? ;
-
-    listen(&sock_fd, 128)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))? ;
-
-    println!("[Enclave] Nitro vsock server listening on port {}", VSOCK_PORT);
-
-    loop {
-        match accept(sock_fd.as_raw_fd()) {
-            Ok(client_fd) => {
-                println!("[Enclave] Connection received");
-                let mut stream = unsafe { std::net::TcpStream::from_raw_fd(client_fd) };
-                handle_connection(&mut stream, &state);
-            }
-            Err(e) => eprintln!("[Enclave] Accept error: {}", e),
-        }
-    }
+    #[cfg(feature = "nitro")]
+    let listener: Box<dyn Listener> =
+        Box::new(oprf_server::VsockListener::bind(address.cid, port)?);
+    #[cfg(not(feature = "nitro"))]
+    let listener: Box<dyn Listener> = Box::new(oprf_server::bind_tcp(port)?);
+    println!("[Enclave] Heartbeat port {} publishes a beat every {:?}", port, interval);
+    Ok(Some(HeartbeatPort::new(listener, interval)))
 }
 
-fn handle_connection<S: Read + Write>(stream: &mut S, state: &EnclaveState) {
-    // Read length-prefixed message
-    let mut len_buf = [0u8; 4];
-    if stream. read_exact(&mut len_buf).is_err() {
-        eprintln!("[Enclave] Failed to read message length");
-        return;
-    }
-    let len = u32::from_be_bytes(len_buf) as usize;
-
-    let mut buf = vec![0u8; len];
-    if stream.read_exact(&mut buf).is_err() {
-        eprintln!("[Enclave] Failed to read message body");
-        return;
-    }
-
-    // Parse request
-    let request: OprfRequest = match serde_json::from_slice(&buf) {
-        Ok(r) => r,
-        Err(e) => {
-            eprintln!("[Enclave] Failed to parse request: {}", e);
-            return;
-        }
-    };
+/// Bind the admin listener of `address` for commands signed by `operator_key`
+fn bind_admin(address: ListenAddress, operator_key: VerifyingKey) -> std::io::Result<AdminPort> {
+    #[cfg(feature = "nitro")]
+    let listener: Box<dyn Listener> =
+        Box::new(oprf_server::VsockListener::bind(address.cid, address.admin_port)?);
+    #[cfg(not(feature = "nitro"))]
+    let listener: Box<dyn Listener> = Box::new(oprf_server::bind_tcp(address.admin_port)?);
+    println!("[Enclave] Admin port {} accepts operator commands", address.admin_port);
+    Ok(AdminPort::new(listener, operator_key))
+}
 
-    // Process request
-    let response = match state.evaluate(&request) {
-        Ok(r) => r,
-        Err(e) => {
-            eprintln!("[Enclave] Evaluation error: {}", e);
-            return;
+/// Serve the gRPC service on `addr` from a thread of its own, answering
+/// from the workers through the returned listener
+#[cfg(feature = "grpc")]
+fn serve_grpc(addr: &str) -> std::io::Result<oprf_server::LocalListener> {
+    let listener = std::net::TcpListener::bind(addr)?;
+    println!("[Enclave] Serving gRPC on {}", listener.local_addr()?);
+    let (local, connector) = oprf_server::local_listener();
+    std::thread::Builder::new().name("oprf-grpc".to_string()).spawn(move || {
+        if let Err(e) = oprf_common::grpc::serve(listener, grpc::Local::new(connector)) {
+            eprintln!("[Enclave] gRPC server error: {}", e);
         }
-    };
-
-    // Send response
-    let response_bytes = serde_json::to_vec(&response).unwrap();
-    let len_bytes = (response_bytes.len() as u32).to_be_bytes();
-
-    if stream.write_all(&len_bytes).is_err() || stream. write_all(&response_bytes). is_err() {
-        eprintln!("[Enclave] Failed to send response");
-    } else {
-        println!("[Enclave] Response sent successfully");
-    }
+    })?;
+    Ok(local)
 }
 
 fn main() {
@@ -279,10 +486,59 @@ fn main() {
     #[cfg(feature = "nitro")]
     println!("[Enclave] Running in NITRO mode");
 
-    let state = EnclaveState::new();
+    let provider = attestation_provider();
 
-    if let Err(e) = run_server(state) {
+    let config = match parse_args() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("[Enclave] {}", e);
+            std::process::exit(1);
+        }
+    };
+    println!("[Enclave] Evaluation budget per key: {:?}", config.limits);
+    println!("[Enclave] Evaluation rate limits: {:?}", config.rate_limits);
+    println!("[Enclave] Evaluation options: {:?}", config.evaluation);
+    println!("[Enclave] Attestation cache TTL: {}s", config.attestation_ttl_secs);
+    println!("[Enclave] Audit log checkpoint interval: {}s", config.audit_checkpoint_secs);
+    println!("[Enclave] Connection limits: {:?}", config.connection_limits);
+    oprf_common::redact::set_log_sensitive(config.log_sensitive);
+    if config.log_sensitive {
+        eprintln!("[Enclave] WARNING: logging sensitive values in full (--log-sensitive)");
+    }
+
+    let mut enclave = Enclave::with_rng(
+        config.limits,
+        config.rate_limits,
+        config.evaluation,
+        config.attestation_ttl_secs,
+        config.audit_checkpoint_secs,
+        provider,
+        config.rng,
+    );
+    #[cfg(feature = "chaos")]
+    if let Some(chaos) = config.chaos {
+        enclave.set_chaos(chaos);
+    }
+    if let Some(kms_key_id) = config.kms_key_id {
+        enclave.set_kms_key_id(kms_key_id);
+    }
+    let shutdown = Shutdown::new();
+    let address = config.address;
+    let admin = config.operator_key.map(|key| bind_admin(address, key)).transpose();
+    let result = admin.and_then(|admin| {
+        let heartbeat = bind_heartbeat(address, config.heartbeat_interval)?;
+        #[cfg_attr(not(feature = "grpc"), allow(unused_mut))]
+        let mut listeners = bind_listeners(address)?;
+        #[cfg(feature = "grpc")]
+        if let Some(grpc_addr) = &config.grpc_addr {
+            listeners.push(Box::new(serve_grpc(grpc_addr)?));
+        }
+        shutdown.on_signals()?;
+        let limits = config.connection_limits;
+        oprf_server::serve(enclave, config.workers, limits, listeners, admin, heartbeat, &shutdown)
+    });
+    if let Err(e) = result {
         eprintln!("[Enclave] Server error: {}", e);
         std::process::exit(1);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,146 @@
+/*
+ * C interface of the OPRF client (liboprf), see ffi/src/lib.rs.
+ *
+ * Blind inputs, verify and unblind the enclave's evaluations, finalize
+ * them into PRF outputs, and verify the enclave's attestations:
+ *
+ *     oprf_blind(mode, input)                      -> blind, blinded
+ *     enclave(blinded)                             -> evaluated, proof, attestation
+ *     oprf_verify_proof(public_key, blinded, evaluated, proof)
+ *     oprf_verify_evaluation_attestation(verifier, attestation, ...)
+ *     oprf_unblind(blind, evaluated)               -> unblinded
+ *     oprf_finalize(input, unblinded)              -> output
+ *
+ * Every function returns an OPRF_* status, OPRF_OK on success. The message
+ * of the last failure on the calling thread is read with oprf_last_error().
+ *
+ * Output buffers come with a pointer to their capacity, which is replaced
+ * by the length written, or by the length needed when the buffer is too
+ * small (OPRF_ERR_BUFFER_TOO_SMALL). Nothing is written on failure. Input
+ * buffers may be NULL when their length is 0.
+ *
+ * The blind is secret: keep it only until the evaluation is unblinded and
+ * wipe it afterwards.
+ */
+
+#ifndef OPRF_H
+#define OPRF_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Status codes */
+#define OPRF_OK 0
+#define OPRF_ERR_NULL_POINTER (-1)     /* A required pointer was NULL */
+#define OPRF_ERR_INVALID_ARGUMENT (-2) /* Unknown suite or mode, input too long, string not UTF-8 */
+#define OPRF_ERR_BUFFER_TOO_SMALL (-3) /* An output buffer is too small */
+#define OPRF_ERR_INVALID_ENCODING (-4) /* Invalid element, scalar, proof, key or document */
+#define OPRF_ERR_INVALID_PROOF (-5)    /* The DLEQ proof does not verify */
+#define OPRF_ERR_ATTESTATION (-6)      /* The attestation was rejected */
+#define OPRF_ERR_POLICY (-7)           /* The policy could not be loaded */
+#define OPRF_ERR_INTERNAL (-8)         /* The library panicked; a bug */
+
+/* Ciphersuites */
+#define OPRF_SUITE_BN254_SHA256 0u
+#define OPRF_SUITE_P256_SHA256 1u
+
+/* Modes, selecting the hash-to-group DST: blind in the mode requested */
+#define OPRF_MODE_OPRF 0u  /* No proof */
+#define OPRF_MODE_VOPRF 1u /* With a DLEQ proof */
+
+/* Lengths in bytes, the same for every suite */
+#define OPRF_SCALAR_LEN 32      /* Blind */
+#define OPRF_OUTPUT_LEN 32      /* PRF output */
+#define OPRF_PROOF_LEN 64       /* DLEQ proof */
+#define OPRF_MAX_ELEMENT_LEN 33 /* Elements, see oprf_element_len() */
+
+/* Attestation policy, made by oprf_verifier_new() */
+typedef struct Verifier oprf_verifier;
+
+/*
+ * Message of the last failed call on this thread, empty if none failed.
+ * Valid until the next failing call on the thread.
+ */
+const char *oprf_last_error(void);
+
+/* Length of a serialized element of suite: 32 (BN254) or 33 (P-256), 0 if unknown */
+size_t oprf_element_len(uint32_t suite);
+
+/*
+ * Map input to the group under the DST of mode, the mode the enclave is
+ * asked to evaluate in, and blind it with a fresh factor b. Writes b to
+ * blind (OPRF_SCALAR_LEN bytes) and H(input)^b, sent to the enclave, to
+ * blinded.
+ */
+int32_t oprf_blind(uint32_t suite, uint32_t mode,
+                   const uint8_t *input, size_t input_len,
+                   uint8_t *blind, size_t *blind_len,
+                   uint8_t *blinded, size_t *blinded_len);
+
+/*
+ * Remove the blinding factor from the enclave's evaluation: writes
+ * evaluated^(1/b) to unblinded. Verify the evaluation first.
+ */
+int32_t oprf_unblind(uint32_t suite,
+                     const uint8_t *blind, size_t blind_len,
+                     const uint8_t *evaluated, size_t evaluated_len,
+                     uint8_t *unblinded, size_t *unblinded_len);
+
+/* Finalize an unblinded element with its input into the PRF output (OPRF_OUTPUT_LEN bytes) */
+int32_t oprf_finalize(uint32_t suite,
+                      const uint8_t *input, size_t input_len,
+                      const uint8_t *unblinded, size_t unblinded_len,
+                      uint8_t *output, size_t *output_len);
+
+/* Check the DLEQ proof that evaluated is blinded raised to the key of public_key */
+int32_t oprf_verify_proof(uint32_t suite,
+                          const uint8_t *public_key, size_t public_key_len,
+                          const uint8_t *blinded, size_t blinded_len,
+                          const uint8_t *evaluated, size_t evaluated_len,
+                          const uint8_t *proof, size_t proof_len);
+
+/*
+ * Load the JSON policy file at policy_path (as the parent's --policy), or
+ * accept only mock attestations if it is NULL. mock_signing_key, if not
+ * NULL, is the 32-byte key a local-mode enclave prints. Stores the verifier
+ * in *verifier; release it with oprf_verifier_free().
+ */
+int32_t oprf_verifier_new(const char *policy_path,
+                          const uint8_t *mock_signing_key, size_t mock_signing_key_len,
+                          oprf_verifier **verifier);
+
+/* Release a verifier; NULL is ignored */
+void oprf_verifier_free(oprf_verifier *verifier);
+
+/*
+ * Verify an attestation document, the JSON "attestation" field of a
+ * response, against the policy and the user data it must carry.
+ */
+int32_t oprf_verify_attestation(const oprf_verifier *verifier,
+                                const uint8_t *attestation, size_t attestation_len,
+                                const uint8_t *user_data, size_t user_data_len);
+
+/*
+ * Verify the attestation of an evaluation response: the attested protocol
+ * version, key id, epoch, public key, evaluated element and client nonce
+ * (NULL for none) must be those given, of a request made without a request
+ * id.
+ */
+int32_t oprf_verify_evaluation_attestation(const oprf_verifier *verifier,
+                                           const uint8_t *attestation, size_t attestation_len,
+                                           uint32_t protocol_version,
+                                           const char *key_id,
+                                           uint64_t epoch,
+                                           const uint8_t *public_key, size_t public_key_len,
+                                           const uint8_t *evaluated, size_t evaluated_len,
+                                           const uint8_t *client_nonce, size_t client_nonce_len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* OPRF_H */
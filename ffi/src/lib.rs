@@ -0,0 +1,477 @@
+//! C bindings of the OPRF client, built as `liboprf.so` and `liboprf.a` and
+//! declared in `include/oprf.h`.
+//!
+//! Services that cannot link Rust blind their inputs, unblind and finalize
+//! the enclave's evaluations (as served by the parent's HTTP API), and
+//! check the DLEQ proof and the attestation with these functions:
+//!
+//! ```text
+//! oprf_blind(mode, input)                    -> blind, blinded
+//! enclave(blinded)                           -> evaluated, proof, attestation
+//! oprf_verify_proof(blinded, evaluated, proof)
+//! oprf_verify_evaluation_attestation(attestation, ...)
+//! oprf_unblind(blind, evaluated)             -> unblinded
+//! oprf_finalize(input, unblinded)            -> output
+//! ```
+//!
+//! Every function returns an `OPRF_*` status, 0 on success. The message of
+//! the last failure on the calling thread is read with [`oprf_last_error`].
+//! Output buffers come with a pointer to their capacity, which is replaced
+//! by the length written, or by the length needed when the buffer is too
+//! small. Nothing is written on failure. Panics are caught at the boundary.
+
+use oprf_common::ed25519::VerifyingKey;
+use oprf_common::{
+    finalize, hash_to_group, verify_proof, AttestationDocument, AttestationError, Bn254Sha256,
+    Ciphersuite, DleqProof, EvaluationUserData, OprfError, OprfMode, P256Sha256,
+};
+use oprf_parent::attestation::{verify_attestation, verify_evaluation_attestation};
+use oprf_parent::policy::Policy;
+use rand::rngs::OsRng;
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use zeroize::Zeroizing;
+
+pub const OPRF_OK: i32 = 0;
+/// A required pointer was null
+pub const OPRF_ERR_NULL_POINTER: i32 = -1;
+/// Unknown ciphersuite or mode, input too long, or a string not UTF-8
+pub const OPRF_ERR_INVALID_ARGUMENT: i32 = -2;
+/// An output buffer is smaller than the length written to its length
+pub const OPRF_ERR_BUFFER_TOO_SMALL: i32 = -3;
+/// An element, scalar, proof or key is not a valid encoding
+pub const OPRF_ERR_INVALID_ENCODING: i32 = -4;
+/// The DLEQ proof does not verify
+pub const OPRF_ERR_INVALID_PROOF: i32 = -5;
+/// The attestation was rejected
+pub const OPRF_ERR_ATTESTATION: i32 = -6;
+/// The policy could not be loaded
+pub const OPRF_ERR_POLICY: i32 = -7;
+/// The library panicked; a bug
+pub const OPRF_ERR_INTERNAL: i32 = -8;
+
+/// [`Bn254Sha256`]
+pub const OPRF_SUITE_BN254_SHA256: u32 = 0;
+/// [`P256Sha256`]
+pub const OPRF_SUITE_P256_SHA256: u32 = 1;
+
+/// [`OprfMode::Oprf`], evaluations without a proof
+pub const OPRF_MODE_OPRF: u32 = 0;
+/// [`OprfMode::Voprf`], evaluations with a DLEQ proof
+pub const OPRF_MODE_VOPRF: u32 = 1;
+
+/// Failure of a call: its status and the message kept for [`oprf_last_error`]
+struct Error {
+    status: i32,
+    message: String,
+}
+
+impl Error {
+    fn new(status: i32, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    fn null(name: &str) -> Self {
+        Self::new(OPRF_ERR_NULL_POINTER, format!("{} is null", name))
+    }
+}
+
+impl From<OprfError> for Error {
+    fn from(error: OprfError) -> Self {
+        let status = match &error {
+            OprfError::Deserialization(_) | OprfError::InvalidPoint(_) => OPRF_ERR_INVALID_ENCODING,
+            OprfError::InvalidProof => OPRF_ERR_INVALID_PROOF,
+            OprfError::Attestation(_) => OPRF_ERR_ATTESTATION,
+            _ => OPRF_ERR_INVALID_ARGUMENT,
+        };
+        Self::new(status, error.to_string())
+    }
+}
+
+impl From<AttestationError> for Error {
+    fn from(error: AttestationError) -> Self {
+        Self::new(OPRF_ERR_ATTESTATION, error.to_string())
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+/// Run `f`, recording its error or panic for [`oprf_last_error`]
+fn call(f: impl FnOnce() -> Result<(), Error>) -> i32 {
+    let error = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => return OPRF_OK,
+        Ok(Err(error)) => error,
+        Err(_) => Error::new(OPRF_ERR_INTERNAL, "panic in liboprf"),
+    };
+    let message = CString::new(error.message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    error.status
+}
+
+/// Run `$body` with `$C` the ciphersuite numbered `$suite`
+macro_rules! with_suite {
+    ($suite:expr, $C:ident => $body:expr) => {
+        match $suite {
+            OPRF_SUITE_BN254_SHA256 => {
+                type $C = Bn254Sha256;
+                $body
+            }
+            OPRF_SUITE_P256_SHA256 => {
+                type $C = P256Sha256;
+                $body
+            }
+            suite => Err(Error::new(OPRF_ERR_INVALID_ARGUMENT, format!("Unknown suite {}", suite))),
+        }
+    };
+}
+
+/// Borrow `len` bytes at `ptr`, which may be null when `len` is 0
+///
+/// # Safety
+///
+/// A non-null `ptr` must point to `len` readable bytes.
+unsafe fn bytes<'a>(ptr: *const u8, len: usize, name: &str) -> Result<&'a [u8], Error> {
+    match (ptr.is_null(), len) {
+        (true, 0) => Ok(&[]),
+        (true, _) => Err(Error::null(name)),
+        (false, _) => Ok(std::slice::from_raw_parts(ptr, len)),
+    }
+}
+
+/// Borrow the NUL-terminated UTF-8 string at `ptr`
+///
+/// # Safety
+///
+/// A non-null `ptr` must point to a NUL-terminated string.
+unsafe fn string<'a>(ptr: *const c_char, name: &str) -> Result<&'a str, Error> {
+    if ptr.is_null() {
+        return Err(Error::null(name));
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|_| Error::new(OPRF_ERR_INVALID_ARGUMENT, format!("{} is not UTF-8", name)))
+}
+
+/// An output buffer and its capacity, replaced by the length written
+struct Output {
+    ptr: *mut u8,
+    len: *mut usize,
+    name: &'static str,
+}
+
+/// Copy every value to its output, or none of them if any output is null
+/// or too small
+///
+/// # Safety
+///
+/// Non-null lengths must be writable, and non-null buffers hold their
+/// capacity.
+unsafe fn write(outputs: &[(Output, &[u8])]) -> Result<(), Error> {
+    for (output, value) in outputs {
+        if output.len.is_null() {
+            return Err(Error::null(output.name));
+        }
+        let capacity = std::mem::replace(&mut *output.len, value.len());
+        if capacity < value.len() {
+            return Err(Error::new(
+                OPRF_ERR_BUFFER_TOO_SMALL,
+                format!("{} needs {} bytes, got {}", output.name, value.len(), capacity),
+            ));
+        }
+        if output.ptr.is_null() {
+            return Err(Error::null(output.name));
+        }
+    }
+    for (output, value) in outputs {
+        std::ptr::copy_nonoverlapping(value.as_ptr(), output.ptr, value.len());
+    }
+    Ok(())
+}
+
+/// Message of the last failed call on this thread, empty if none failed.
+/// Valid until the next failing call on the thread.
+#[no_mangle]
+pub extern "C" fn oprf_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+/// Length of a serialized element of `suite`, 0 for an unknown suite
+#[no_mangle]
+pub extern "C" fn oprf_element_len(suite: u32) -> usize {
+    let len: Result<usize, Error> = with_suite!(suite, C => Ok(C::ELEMENT_LEN));
+    len.unwrap_or(0)
+}
+
+/// Map `input` to the group under the DST of `mode`, the mode it is
+/// evaluated in, and blind it with a fresh factor b. Writes b
+/// (`OPRF_SCALAR_LEN` bytes, secret) to `blind` and H(input)^b to `blinded`.
+///
+/// # Safety
+///
+/// Pointers must be null or valid for their lengths.
+#[no_mangle]
+pub unsafe extern "C" fn oprf_blind(
+    suite: u32,
+    mode: u32,
+    input: *const u8,
+    input_len: usize,
+    blind: *mut u8,
+    blind_len: *mut usize,
+    blinded: *mut u8,
+    blinded_len: *mut usize,
+) -> i32 {
+    call(|| {
+        let input = bytes(input, input_len, "input")?;
+        let mode = match mode {
+            OPRF_MODE_OPRF => OprfMode::Oprf,
+            OPRF_MODE_VOPRF => OprfMode::Voprf,
+            mode => {
+                let message = format!("Unknown mode {}", mode);
+                return Err(Error::new(OPRF_ERR_INVALID_ARGUMENT, message));
+            }
+        };
+        with_suite!(suite, C => {
+            let scalar = Zeroizing::new(C::random_scalar(&mut OsRng));
+            let element = C::scalar_mul(&hash_to_group::<C>(input, mode), &scalar);
+            let scalar = Zeroizing::new(C::serialize_scalar(&scalar)?);
+            let element = C::serialize_element(&element)?;
+            write(&[
+                (Output { ptr: blind, len: blind_len, name: "blind" }, &scalar),
+                (Output { ptr: blinded, len: blinded_len, name: "blinded" }, &element),
+            ])
+        })
+    })
+}
+
+/// Remove the blinding factor `blind` from the enclave's evaluation:
+/// writes evaluated^(1/b) to `unblinded`. Verify the evaluation first.
+///
+/// # Safety
+///
+/// Pointers must be null or valid for their lengths.
+#[no_mangle]
+pub unsafe extern "C" fn oprf_unblind(
+    suite: u32,
+    blind: *const u8,
+    blind_len: usize,
+    evaluated: *const u8,
+    evaluated_len: usize,
+    unblinded: *mut u8,
+    unblinded_len: *mut usize,
+) -> i32 {
+    call(|| {
+        let blind = bytes(blind, blind_len, "blind")?;
+        let evaluated = bytes(evaluated, evaluated_len, "evaluated")?;
+        with_suite!(suite, C => {
+            let scalar = Zeroizing::new(C::deserialize_scalar(blind)?);
+            let inverse = C::scalar_inverse(&scalar)
+                .map(Zeroizing::new)
+                .ok_or_else(|| Error::new(OPRF_ERR_INVALID_ENCODING, "blind is zero"))?;
+            let element = C::scalar_mul(&C::deserialize_element(evaluated)?, &inverse);
+            let element = C::serialize_element(&element)?;
+            write(&[(Output { ptr: unblinded, len: unblinded_len, name: "unblinded" }, &element)])
+        })
+    })
+}
+
+/// Finalize an unblinded element with its input into the PRF output
+/// (`OPRF_OUTPUT_LEN` bytes, RFC 9497 `Finalize`)
+///
+/// # Safety
+///
+/// Pointers must be null or valid for their lengths.
+#[no_mangle]
+pub unsafe extern "C" fn oprf_finalize(
+    suite: u32,
+    input: *const u8,
+    input_len: usize,
+    unblinded: *const u8,
+    unblinded_len: usize,
+    output: *mut u8,
+    output_len: *mut usize,
+) -> i32 {
+    call(|| {
+        let input = bytes(input, input_len, "input")?;
+        let unblinded = bytes(unblinded, unblinded_len, "unblinded")?;
+        with_suite!(suite, C => {
+            let digest = finalize::<C>(input, &C::deserialize_element(unblinded)?)?;
+            write(&[(Output { ptr: output, len: output_len, name: "output" }, &digest)])
+        })
+    })
+}
+
+/// Check the DLEQ proof that `evaluated` is `blinded` raised to the secret
+/// key of `public_key`
+///
+/// # Safety
+///
+/// Pointers must be null or valid for their lengths.
+#[no_mangle]
+pub unsafe extern "C" fn oprf_verify_proof(
+    suite: u32,
+    public_key: *const u8,
+    public_key_len: usize,
+    blinded: *const u8,
+    blinded_len: usize,
+    evaluated: *const u8,
+    evaluated_len: usize,
+    proof: *const u8,
+    proof_len: usize,
+) -> i32 {
+    call(|| {
+        let public_key = bytes(public_key, public_key_len, "public_key")?;
+        let blinded = bytes(blinded, blinded_len, "blinded")?;
+        let evaluated = bytes(evaluated, evaluated_len, "evaluated")?;
+        let proof = bytes(proof, proof_len, "proof")?;
+        with_suite!(suite, C => {
+            verify_proof::<C>(
+                &C::deserialize_element(public_key)?,
+                &[C::deserialize_element(blinded)?],
+                &[C::deserialize_element(evaluated)?],
+                &DleqProof::<C>::from_bytes(proof)?,
+            )
+            .map_err(Error::from)
+        })
+    })
+}
+
+/// Attestation policy of the enclave images a service accepts
+pub struct Verifier {
+    policy: Policy,
+}
+
+/// Load the policy file at `policy_path` (see the parent's `--policy`), or
+/// accept only mock attestations if it is null. `mock_signing_key`, if not
+/// null, is the 32-byte key a local-mode enclave prints. The verifier is
+/// stored in `*verifier` and released with [`oprf_verifier_free`].
+///
+/// # Safety
+///
+/// Pointers must be null or valid for their lengths, `policy_path`
+/// NUL-terminated.
+#[no_mangle]
+pub unsafe extern "C" fn oprf_verifier_new(
+    policy_path: *const c_char,
+    mock_signing_key: *const u8,
+    mock_signing_key_len: usize,
+    verifier: *mut *mut Verifier,
+) -> i32 {
+    call(|| {
+        if verifier.is_null() {
+            return Err(Error::null("verifier"));
+        }
+        let mut policy = if policy_path.is_null() {
+            Policy::default()
+        } else {
+            Policy::load(string(policy_path, "policy_path")?)
+                .map_err(|e| Error::new(OPRF_ERR_POLICY, e))?
+        };
+        if !mock_signing_key.is_null() {
+            let key = bytes(mock_signing_key, mock_signing_key_len, "mock_signing_key")?;
+            policy.mock_signing_key = Some(VerifyingKey::from_bytes(key)?);
+        }
+        *verifier = Box::into_raw(Box::new(Verifier { policy }));
+        Ok(())
+    })
+}
+
+/// Release a verifier of [`oprf_verifier_new`]; null is ignored
+///
+/// # Safety
+///
+/// `verifier` must be null or a verifier not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn oprf_verifier_free(verifier: *mut Verifier) {
+    if !verifier.is_null() {
+        drop(Box::from_raw(verifier));
+    }
+}
+
+/// Borrow the verifier at `ptr` and decode the JSON attestation document
+///
+/// # Safety
+///
+/// As for the callers.
+unsafe fn verifier_and_document<'a>(
+    verifier: *const Verifier,
+    attestation: *const u8,
+    attestation_len: usize,
+) -> Result<(&'a Verifier, AttestationDocument), Error> {
+    let verifier = verifier.as_ref().ok_or_else(|| Error::null("verifier"))?;
+    let attestation = bytes(attestation, attestation_len, "attestation")?;
+    let document = serde_json::from_slice(attestation).map_err(|e| {
+        Error::new(OPRF_ERR_INVALID_ENCODING, format!("Invalid attestation document: {}", e))
+    })?;
+    Ok((verifier, document))
+}
+
+/// Verify an attestation document, in its JSON form, against the policy
+/// and the user data it must carry
+///
+/// # Safety
+///
+/// Pointers must be null or valid for their lengths.
+#[no_mangle]
+pub unsafe extern "C" fn oprf_verify_attestation(
+    verifier: *const Verifier,
+    attestation: *const u8,
+    attestation_len: usize,
+    user_data: *const u8,
+    user_data_len: usize,
+) -> i32 {
+    call(|| {
+        let (verifier, document) = verifier_and_document(verifier, attestation, attestation_len)?;
+        let user_data = bytes(user_data, user_data_len, "user_data")?;
+        verify_attestation(&verifier.policy, &document, user_data).map_err(Error::from)
+    })
+}
+
+/// Verify the attestation of an evaluation response, in its JSON form: the
+/// attested protocol version, key, epoch, public key, evaluated element and
+/// client nonce (null for none) must be those given. The request must have
+/// been made without a request id.
+///
+/// # Safety
+///
+/// Pointers must be null or valid for their lengths, `key_id`
+/// NUL-terminated.
+#[no_mangle]
+pub unsafe extern "C" fn oprf_verify_evaluation_attestation(
+    verifier: *const Verifier,
+    attestation: *const u8,
+    attestation_len: usize,
+    protocol_version: u32,
+    key_id: *const c_char,
+    epoch: u64,
+    public_key: *const u8,
+    public_key_len: usize,
+    evaluated: *const u8,
+    evaluated_len: usize,
+    client_nonce: *const u8,
+    client_nonce_len: usize,
+) -> i32 {
+    call(|| {
+        let (verifier, document) = verifier_and_document(verifier, attestation, attestation_len)?;
+        let client_nonce = if client_nonce.is_null() {
+            None
+        } else {
+            Some(bytes(client_nonce, client_nonce_len, "client_nonce")?)
+        };
+        let expected = EvaluationUserData::new(
+            protocol_version,
+            string(key_id, "key_id")?,
+            epoch,
+            bytes(public_key, public_key_len, "public_key")?,
+            &[bytes(evaluated, evaluated_len, "evaluated")?.to_vec()],
+            client_nonce,
+            None,
+        );
+        verify_evaluation_attestation(&verifier.policy, &document, &expected).map_err(Error::from)
+    })
+}
@@ -0,0 +1,15 @@
+//! BN254 scalars, as imported keys and proofs carry them: every scalar
+//! accepted is in range and encoded canonically. Bytes past the scalar are
+//! left unread, as arkworks does.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use oprf_common::{deserialize_fr, serialize_fr};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(scalar) = deserialize_fr(data) {
+        let encoded = serialize_fr(&scalar).unwrap();
+        assert_eq!(encoded, data[..encoded.len()]);
+    }
+});
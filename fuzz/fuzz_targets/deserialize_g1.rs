@@ -0,0 +1,16 @@
+//! BN254 G1 points as a client sends them, compressed or uncompressed: every
+//! point accepted is a valid non-identity point and the only encoding of it.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use oprf_common::{deserialize_g1, serialize_g1, Bn254Sha256, Ciphersuite};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(point) = deserialize_g1(data) {
+        assert_eq!(serialize_g1(&point).unwrap(), data);
+    }
+    if let Ok(point) = Bn254Sha256::deserialize_element_uncompressed(data) {
+        assert_eq!(Bn254Sha256::serialize_element_uncompressed(&point).unwrap(), data);
+    }
+});
@@ -0,0 +1,20 @@
+//! A connection's bytes as the enclave and the parent read them: frames,
+//! then their payloads as requests and responses in the frame's format, and
+//! as the protobuf messages of the HTTP gateway.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use oprf_common::frame::read_frame;
+use oprf_common::proto::ProtoMessage;
+use oprf_common::{BatchOprfRequest, EnclaveResponse, Message, OprfRequest, OprfResult};
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = data;
+    while let Ok(Some(frame)) = read_frame(&mut reader) {
+        let _ = frame.format.decode::<Message>(&frame.payload);
+        let _ = frame.format.decode::<OprfResult<EnclaveResponse>>(&frame.payload);
+        let _ = OprfRequest::decode(&frame.payload);
+        let _ = BatchOprfRequest::decode(&frame.payload);
+    }
+});
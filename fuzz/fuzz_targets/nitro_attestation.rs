@@ -0,0 +1,21 @@
+//! NSM attestation documents, as the parent reads them from the enclave's
+//! responses: the COSE_Sign1 structure and its CBOR payload.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use oprf_common::AttestationDocument;
+use oprf_parent::policy::Policy;
+use std::sync::OnceLock;
+
+static POLICY: OnceLock<Policy> = OnceLock::new();
+
+fuzz_target!(|data: &[u8]| {
+    let attestation = AttestationDocument {
+        is_mock: false,
+        document: data.to_vec(),
+        pcrs: None,
+        user_data: Vec::new(),
+    };
+    let _ = POLICY.get_or_init(Policy::default).check(&attestation);
+});
@@ -0,0 +1,151 @@
+//! Connection to the enclave as the parent opens one, lean enough to load it.
+//!
+//! The opening Hello and, from protocol version 3 on, the attested channel
+//! handshake are those of the parent: the channel key and from version 4 on
+//! the response signing key must be attested by an image the policy allows.
+//! After that, requests are sent without waiting and their responses read in
+//! the order they were sent, which is the order the enclave answers in.
+
+use oprf_common::channel::{channel_binding, Channel, Initiator};
+use oprf_common::ed25519::VerifyingKey;
+use oprf_common::frame::{read_frame, write_frame, Frame, WireFormat};
+use oprf_common::signing::{session_binding, verify_response};
+use oprf_common::{
+    HandshakeRequest, HandshakeResponse, HelloRequest, HelloResponse, Message, OprfResult,
+    PointEncoding, SUPPORTED_PROTOCOL_VERSIONS,
+};
+use oprf_parent::attestation::verify_attestation;
+use oprf_parent::policy::Policy;
+use rand::rngs::OsRng;
+use serde::de::DeserializeOwned;
+use std::io::{Error, ErrorKind, Read, Write};
+
+/// A connection whose responses are read in the order of its requests
+pub struct Connection<S> {
+    stream: S,
+    format: WireFormat,
+    version: u32,
+    next_id: u64,
+    /// Id of the next response to read
+    next_response: u64,
+    channel: Option<Channel>,
+    signing_key: Option<VerifyingKey>,
+}
+
+fn invalid(e: impl std::fmt::Display) -> Error {
+    Error::new(ErrorKind::InvalidData, e.to_string())
+}
+
+impl<S: Read + Write> Connection<S> {
+    /// Open a connection over `stream`, negotiating the newest protocol
+    /// version the enclave speaks
+    pub fn open(stream: S, format: WireFormat, policy: &Policy) -> std::io::Result<Self> {
+        let mut connection = Self {
+            stream,
+            format,
+            version: 0,
+            next_id: 1,
+            next_response: 1,
+            channel: None,
+            signing_key: None,
+        };
+        let versions: Vec<u32> = SUPPORTED_PROTOCOL_VERSIONS
+            .iter()
+            .copied()
+            .filter(|version| *version >= policy.min_protocol_version)
+            .collect();
+        connection.send(&Message::Hello(HelloRequest {
+            versions: versions.clone(),
+            point_encoding: PointEncoding::Compressed,
+            request_ids: false,
+        }))?;
+        // The handshake answer is never wrapped in an OprfResult
+        let hello: HelloResponse = decode(connection.next_frame()?)?;
+        if !versions.contains(&hello.version) {
+            return Err(invalid(format!("Enclave chose unoffered version {}", hello.version)));
+        }
+        connection.version = hello.version;
+        if hello.version >= 3 {
+            connection.establish_channel(&versions, &hello, policy)?;
+        }
+        Ok(connection)
+    }
+
+    fn establish_channel(
+        &mut self,
+        offered: &[u32],
+        hello: &HelloResponse,
+        policy: &Policy,
+    ) -> std::io::Result<()> {
+        let (Some(channel_key), Some(attestation)) = (&hello.channel_key, &hello.attestation)
+        else {
+            return Err(invalid("Enclave offered no channel key"));
+        };
+        let binding = match (hello.version >= 4, &hello.signing_key) {
+            (true, Some(signing_key)) => session_binding(channel_key, offered, signing_key),
+            (true, None) => return Err(invalid("Enclave offered no signing key")),
+            (false, _) => channel_binding(channel_key, offered),
+        };
+        verify_attestation(policy, attestation, &binding).map_err(invalid)?;
+        if let Some(signing_key) = hello.signing_key.as_ref().filter(|_| hello.version >= 4) {
+            self.signing_key = Some(VerifyingKey::from_bytes(signing_key).map_err(invalid)?);
+        }
+
+        let (initiator, message) = Initiator::start(channel_key, &mut OsRng).map_err(invalid)?;
+        self.send(&Message::Handshake(HandshakeRequest { message }))?;
+        let response: HandshakeResponse = self.receive()?;
+        self.channel = Some(initiator.finish(&response.message).map_err(invalid)?);
+        Ok(())
+    }
+
+    /// Protocol version negotiated when the connection was opened
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Send a request without waiting for its response
+    pub fn send(&mut self, request: &Message) -> std::io::Result<()> {
+        let id = self.next_id;
+        let mut payload = self.format.encode(request).map_err(invalid)?;
+        if let Some(channel) = &mut self.channel {
+            payload = channel.seal(id, self.format, &payload).map_err(invalid)?;
+        }
+        write_frame(&mut self.stream, id, self.format, &payload)?;
+        self.next_id += 1;
+        Ok(())
+    }
+
+    /// Read the response to the oldest request not answered yet. An
+    /// [`oprf_common::ErrorResponse`] is returned as the source of an
+    /// `io::Error`.
+    pub fn receive<T: DeserializeOwned>(&mut self) -> std::io::Result<T> {
+        let frame = self.next_frame()?;
+        if self.version < 2 {
+            return decode(frame);
+        }
+        decode::<OprfResult<T>>(frame)?.map_err(Error::other)
+    }
+
+    fn next_frame(&mut self) -> std::io::Result<Frame> {
+        let mut frame = read_frame(&mut self.stream)?
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Enclave closed the connection"))?;
+        if let Some(channel) = &mut self.channel {
+            channel.open(&mut frame).map_err(invalid)?;
+        }
+        if let Some(key) = &self.signing_key {
+            verify_response(key, &mut frame).map_err(invalid)?;
+        }
+        if frame.request_id != self.next_response {
+            return Err(invalid(format!(
+                "Response to request {} while awaiting {}",
+                frame.request_id, self.next_response
+            )));
+        }
+        self.next_response += 1;
+        Ok(frame)
+    }
+}
+
+fn decode<T: DeserializeOwned>(frame: Frame) -> std::io::Result<T> {
+    frame.format.decode(&frame.payload).map_err(invalid)
+}
@@ -0,0 +1,353 @@
+//! Load generator for capacity planning: opens a number of connections to
+//! the enclave at once, pipelines evaluation requests over each, and reports
+//! throughput, latency percentiles and error rates.
+//!
+//! Every connection opens as the parent's does, attested channel included,
+//! then waits for the others so the clock starts with all of them ready.
+//! Queries are blinded before that too, each with a fresh random input, so
+//! neither blinding nor the enclave's cache of evaluations skews the
+//! numbers. Responses are decoded but their proofs and attestations are not
+//! verified: the point is to load the enclave, not the client.
+
+mod connection;
+mod report;
+
+use connection::Connection;
+use oprf_common::config::{DEFAULT_ENCLAVE_CID, DEFAULT_PIPELINE_DEPTH, DEFAULT_PORT};
+use oprf_common::ed25519::VerifyingKey;
+use oprf_common::frame::WireFormat;
+use oprf_common::{
+    blind, sha256_hex, BatchOprfRequest, BatchOprfResponse, Bn254Sha256, Ciphersuite,
+    CiphersuiteId, ErrorResponse, Message, OprfMode, OprfRequest, OprfResponse, P256Sha256,
+    DEFAULT_KEY_ID,
+};
+use oprf_parent::policy::Policy;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use report::{Outcomes, Report};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::net::TcpStream;
+use std::sync::Barrier;
+use std::time::{Duration, Instant};
+
+/// Connections opened unless `--connections` is given
+const DEFAULT_CONNECTIONS: usize = 4;
+
+/// Requests per connection unless `--requests` is given
+const DEFAULT_REQUESTS: usize = 1000;
+
+/// Length of the random inputs evaluated
+const INPUT_LEN: usize = 16;
+
+const USAGE: &str = "\
+Usage: oprf-loadgen [options]
+
+Opens N connections to the enclave, pipelines M evaluation requests over
+each, and reports throughput, latency percentiles and error rates.
+
+Options:
+  --connections <n>           Connections opened at once, N (4 by default)
+  --requests <n>              Requests sent over each connection, M (1000 by default)
+  --depth <n>                 Requests in flight at once on a connection (32 by default)
+  --batch <n>                 Inputs per request, sent as batch requests if more than 1
+  --suite <bn254|p256>        Ciphersuite (bn254 by default)
+  --key-id <id>               Named key (\"default\" by default)
+  --wire-format <cbor|json>   Encoding of messages (cbor by default)
+  --cid <n>, --port <n>       Where the enclave listens (also ENCLAVE_CID, ENCLAVE_PORT)
+  --policy <file>             Allowlist of enclave PCRs
+  --mock-signing-key <hex>    Key printed by a local-mode enclave
+  --timeout <secs>            Bound on every read and write
+  --output <file>             Also write the report as JSON to <file>
+";
+
+/// Settings of a run
+struct Settings {
+    connections: usize,
+    requests: usize,
+    depth: usize,
+    batch: usize,
+    ciphersuite: CiphersuiteId,
+    key_id: String,
+    wire_format: WireFormat,
+    cid: u32,
+    port: u32,
+    policy: Policy,
+    timeout: Option<Duration>,
+    /// Where to write the JSON report
+    output: Option<String>,
+}
+
+fn parse_args() -> Result<Settings, Box<dyn Error>> {
+    let mut settings = Settings {
+        connections: DEFAULT_CONNECTIONS,
+        requests: DEFAULT_REQUESTS,
+        depth: DEFAULT_PIPELINE_DEPTH,
+        batch: 1,
+        ciphersuite: CiphersuiteId::default(),
+        key_id: DEFAULT_KEY_ID.to_string(),
+        wire_format: WireFormat::default(),
+        cid: DEFAULT_ENCLAVE_CID,
+        port: DEFAULT_PORT,
+        policy: Policy::default(),
+        timeout: None,
+        output: None,
+    };
+    let fields = [("ENCLAVE_CID", &mut settings.cid), ("ENCLAVE_PORT", &mut settings.port)];
+    for (name, field) in fields {
+        if let Ok(value) = std::env::var(name) {
+            *field = value.parse().map_err(|e| format!("Invalid {}: {}", name, e))?;
+        }
+    }
+    let mut mock_signing_key = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "help" || arg == "--help" || arg == "-h" {
+            print!("{}", USAGE);
+            std::process::exit(0);
+        }
+        let value = args.next().ok_or(format!("{} requires a value", arg))?;
+        match arg.as_str() {
+            "--connections" => settings.connections = value.parse()?,
+            "--requests" => settings.requests = value.parse()?,
+            "--depth" => settings.depth = value.parse()?,
+            "--batch" => settings.batch = value.parse()?,
+            "--suite" => settings.ciphersuite = value.parse()?,
+            "--key-id" => settings.key_id = value,
+            "--wire-format" => settings.wire_format = value.parse()?,
+            "--cid" => settings.cid = value.parse()?,
+            "--port" => settings.port = value.parse()?,
+            "--policy" => settings.policy = Policy::load(&value)?,
+            "--mock-signing-key" => {
+                let key =
+                    hex::decode(value.trim()).map_err(|e| format!("Invalid mock key: {}", e))?;
+                mock_signing_key = Some(VerifyingKey::from_bytes(&key)?);
+            }
+            "--timeout" => settings.timeout = Some(Duration::from_secs(value.parse()?)),
+            "--output" => settings.output = Some(value),
+            _ => return Err(format!("Unknown option {}, see oprf-loadgen help", arg).into()),
+        }
+    }
+    settings.policy.mock_signing_key = mock_signing_key;
+    for (name, value) in [
+        ("--connections", settings.connections),
+        ("--depth", settings.depth),
+        ("--batch", settings.batch),
+    ] {
+        if value == 0 {
+            return Err(format!("{} must be positive", name).into());
+        }
+    }
+    Ok(settings)
+}
+
+/// Open a stream to the enclave: 127.0.0.1 in local mode, vsock in Nitro
+/// mode, as the parent does
+#[cfg(not(feature = "nitro"))]
+fn connect_stream(settings: &Settings) -> std::io::Result<TcpStream> {
+    let port = u16::try_from(settings.port).map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "Enclave port out of range")
+    })?;
+    let stream = TcpStream::connect(("127.0.0.1", port))?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+#[cfg(feature = "nitro")]
+fn connect_stream(settings: &Settings) -> std::io::Result<TcpStream> {
+    use nix::sys::socket::{connect, socket, AddressFamily, SockFlag, SockType, VsockAddr};
+    use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+
+    let sock_fd = socket(AddressFamily::Vsock, SockType::Stream, SockFlag::empty(), None)
+        .map_err(std::io::Error::other)?;
+    let addr = VsockAddr::new(settings.cid, settings.port);
+    connect(sock_fd.as_raw_fd(), &addr).map_err(std::io::Error::other)?;
+    Ok(unsafe { TcpStream::from_raw_fd(sock_fd.into_raw_fd()) })
+}
+
+fn open(settings: &Settings) -> std::io::Result<Connection<TcpStream>> {
+    let stream = connect_stream(settings)?;
+    stream.set_read_timeout(settings.timeout)?;
+    stream.set_write_timeout(settings.timeout)?;
+    Connection::open(stream, settings.wire_format, &settings.policy)
+}
+
+/// Blinded queries of `requests` evaluation requests of fresh random inputs
+fn blinded_requests<C: Ciphersuite>(
+    settings: &Settings,
+) -> Result<Vec<Message>, Box<dyn Error>> {
+    let mut requests = Vec::with_capacity(settings.requests);
+    for _ in 0..settings.requests {
+        let mut queries = Vec::with_capacity(settings.batch);
+        for _ in 0..settings.batch {
+            let mut input = [0u8; INPUT_LEN];
+            OsRng.fill_bytes(&mut input);
+            let (_, blinded) = blind::<C, _>(&input, OprfMode::Voprf, &mut OsRng);
+            queries.push(blinded.to_bytes()?);
+        }
+        let key_id = settings.key_id.clone();
+        requests.push(if settings.batch == 1 {
+            let blinded_query = queries.remove(0);
+            Message::Evaluate(OprfRequest {
+                query_hash: sha256_hex(&blinded_query),
+                blinded_query,
+                mode: OprfMode::Voprf,
+                ciphersuite: C::ID,
+                epoch: None,
+                key_id,
+                force_fresh: false,
+                client_nonce: None,
+                client_id: None,
+                request_id: None,
+            })
+        } else {
+            Message::BatchEvaluate(BatchOprfRequest {
+                query_hash: sha256_hex(&queries.concat()),
+                blinded_queries: queries,
+                mode: OprfMode::Voprf,
+                ciphersuite: C::ID,
+                epoch: None,
+                key_id,
+                force_fresh: false,
+                client_nonce: None,
+                client_id: None,
+                request_id: None,
+            })
+        });
+    }
+    Ok(requests)
+}
+
+/// Read the response to the oldest outstanding request, checking it holds
+/// an evaluation of every input
+fn receive(connection: &mut Connection<TcpStream>, batch: usize) -> std::io::Result<()> {
+    let evaluated = if batch == 1 {
+        connection.receive::<OprfResponse>().map(|_| 1)?
+    } else {
+        connection.receive::<BatchOprfResponse>()?.evaluated_points.len()
+    };
+    if evaluated != batch {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} evaluations of {} inputs", evaluated, batch),
+        ));
+    }
+    Ok(())
+}
+
+/// Send `requests` over `connection`, at most `depth` of them in flight
+fn pipeline(
+    connection: &mut Connection<TcpStream>,
+    requests: &[Message],
+    settings: &Settings,
+) -> Outcomes {
+    let mut outcomes = Outcomes::default();
+    let mut in_flight = VecDeque::with_capacity(settings.depth);
+    let mut next = 0;
+    loop {
+        while in_flight.len() < settings.depth && next < requests.len() {
+            let sent = Instant::now();
+            if let Err(e) = connection.send(&requests[next]) {
+                outcomes.fail_connection(&e, (in_flight.len() + requests.len() - next) as u64);
+                return outcomes;
+            }
+            in_flight.push_back(sent);
+            next += 1;
+        }
+        let Some(sent) = in_flight.pop_front() else {
+            return outcomes;
+        };
+        match receive(connection, settings.batch) {
+            Ok(()) => outcomes.latencies.push(sent.elapsed()),
+            Err(e) => match e.get_ref().and_then(|e| e.downcast_ref::<ErrorResponse>()) {
+                Some(refusal) => outcomes.refuse(refusal.code),
+                None => {
+                    outcomes.failed += 1;
+                    outcomes.fail_connection(&e, (in_flight.len() + requests.len() - next) as u64);
+                    return outcomes;
+                }
+            },
+        }
+    }
+}
+
+/// Run the connections of `settings` at once and report on them
+fn run<C: Ciphersuite>(settings: &Settings) -> Result<Report, Box<dyn Error>> {
+    let start = Barrier::new(settings.connections + 1);
+    let mut requests = Vec::with_capacity(settings.connections);
+    for _ in 0..settings.connections {
+        requests.push(blinded_requests::<C>(settings)?);
+    }
+    let (elapsed, results) = std::thread::scope(|scope| {
+        let threads: Vec<_> = requests
+            .iter()
+            .map(|requests| {
+                let start = &start;
+                scope.spawn(move || {
+                    let connection = open(settings);
+                    start.wait();
+                    match connection {
+                        Ok(mut connection) => {
+                            let outcomes = pipeline(&mut connection, requests, settings);
+                            (Some(connection.version()), outcomes)
+                        }
+                        Err(e) => {
+                            let mut outcomes = Outcomes {
+                                unopened: 1,
+                                ..Outcomes::default()
+                            };
+                            outcomes.fail_connection(&e, requests.len() as u64);
+                            (None, outcomes)
+                        }
+                    }
+                })
+            })
+            .collect();
+        start.wait();
+        let started = Instant::now();
+        let results: Vec<_> =
+            threads.into_iter().map(|thread| thread.join().expect("connection panicked")).collect();
+        (started.elapsed(), results)
+    });
+
+    let mut outcomes = Outcomes::default();
+    let mut version = None;
+    for (connection_version, connection_outcomes) in results {
+        version = version.or(connection_version);
+        outcomes.merge(connection_outcomes);
+    }
+    Ok(Report {
+        connections: settings.connections,
+        requests_per_connection: settings.requests,
+        depth: settings.depth,
+        batch: settings.batch,
+        version,
+        elapsed,
+        outcomes,
+    })
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let settings = parse_args()?;
+    println!(
+        "[Loadgen] {} connection(s) x {} request(s) to the enclave at {}",
+        settings.connections,
+        settings.requests,
+        if cfg!(feature = "nitro") {
+            format!("{}:{}", settings.cid, settings.port)
+        } else {
+            format!("127.0.0.1:{}", settings.port)
+        }
+    );
+    let report = match settings.ciphersuite {
+        CiphersuiteId::Bn254Sha256 => run::<Bn254Sha256>(&settings)?,
+        CiphersuiteId::P256Sha256 => run::<P256Sha256>(&settings)?,
+    };
+    report.print();
+    if let Some(path) = &settings.output {
+        let json = serde_json::to_string_pretty(&report.to_json())? + "\n";
+        std::fs::write(path, json).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        println!("[Loadgen] Wrote the report to {}", path);
+    }
+    Ok(())
+}
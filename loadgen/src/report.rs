@@ -0,0 +1,200 @@
+//! Outcomes of the requests of a run, and the report made of them.
+
+use oprf_common::ErrorCode;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Percentiles of the latency reported
+const PERCENTILES: [f64; 5] = [50.0, 90.0, 99.0, 99.9, 100.0];
+
+/// What became of the requests of one or more connections
+#[derive(Debug, Default)]
+pub struct Outcomes {
+    /// Latency of every request answered, from sending it to reading the
+    /// response
+    pub latencies: Vec<Duration>,
+    /// Requests the enclave refused, by error code
+    pub refused: BTreeMap<String, u64>,
+    /// Requests whose response could not be read, or was not a valid one
+    pub failed: u64,
+    /// Requests left unanswered, or not sent, when their connection failed
+    pub unanswered: u64,
+    /// Why connections failed, with the number of connections each time
+    pub errors: BTreeMap<String, u64>,
+    /// Connections that could not be opened
+    pub unopened: u64,
+}
+
+impl Outcomes {
+    pub fn refuse(&mut self, code: ErrorCode) {
+        *self.refused.entry(format!("{:?}", code)).or_default() += 1;
+    }
+
+    /// Record why a connection failed, leaving `unanswered` requests
+    pub fn fail_connection(&mut self, error: &std::io::Error, unanswered: u64) {
+        *self.errors.entry(error.to_string()).or_default() += 1;
+        self.unanswered += unanswered;
+    }
+
+    pub fn merge(&mut self, other: Outcomes) {
+        self.latencies.extend(other.latencies);
+        for (code, count) in other.refused {
+            *self.refused.entry(code).or_default() += count;
+        }
+        self.failed += other.failed;
+        self.unanswered += other.unanswered;
+        for (error, count) in other.errors {
+            *self.errors.entry(error).or_default() += count;
+        }
+        self.unopened += other.unopened;
+    }
+}
+
+/// Outcomes of a whole run
+pub struct Report {
+    pub connections: usize,
+    pub requests_per_connection: usize,
+    pub depth: usize,
+    pub batch: usize,
+    /// Protocol version the connections negotiated, if any was opened
+    pub version: Option<u32>,
+    pub elapsed: Duration,
+    pub outcomes: Outcomes,
+}
+
+impl Report {
+    fn total(&self) -> u64 {
+        (self.connections * self.requests_per_connection) as u64
+    }
+
+    fn share(&self, count: u64) -> f64 {
+        if self.total() == 0 {
+            return 0.0;
+        }
+        count as f64 / self.total() as f64
+    }
+
+    /// Latency percentiles in milliseconds, by nearest rank
+    fn percentiles(&self) -> Vec<(f64, f64)> {
+        let mut latencies = self.outcomes.latencies.clone();
+        latencies.sort_unstable();
+        PERCENTILES
+            .iter()
+            .filter(|_| !latencies.is_empty())
+            .map(|&p| {
+                let rank = ((p / 100.0 * latencies.len() as f64).ceil() as usize).max(1);
+                (p, millis(latencies[rank.min(latencies.len()) - 1]))
+            })
+            .collect()
+    }
+
+    fn mean_millis(&self) -> Option<f64> {
+        let latencies = &self.outcomes.latencies;
+        let total: Duration = latencies.iter().sum();
+        (!latencies.is_empty()).then(|| millis(total) / latencies.len() as f64)
+    }
+
+    fn per_sec(&self, count: u64) -> f64 {
+        count as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    fn counts(&self) -> (u64, u64) {
+        let succeeded = self.outcomes.latencies.len() as u64;
+        let refused = self.outcomes.refused.values().sum();
+        (succeeded, refused)
+    }
+
+    pub fn print(&self) {
+        let outcomes = &self.outcomes;
+        let (succeeded, refused) = self.counts();
+        let version = self.version.map_or("none".to_string(), |version| version.to_string());
+        println!(
+            "Connections: {} ({} could not be opened), protocol version {}",
+            self.connections, outcomes.unopened, version
+        );
+        println!(
+            "Requests:    {} x {} of {} input(s), pipelined {} deep, in {:.2}s",
+            self.connections,
+            self.requests_per_connection,
+            self.batch,
+            self.depth,
+            self.elapsed.as_secs_f64()
+        );
+        println!(
+            "Throughput:  {:.1} requests/s, {:.1} evaluations/s",
+            self.per_sec(succeeded),
+            self.per_sec(succeeded * self.batch as u64)
+        );
+        for (name, count) in [
+            ("Succeeded", succeeded),
+            ("Refused", refused),
+            ("Failed", outcomes.failed),
+            ("Unanswered", outcomes.unanswered),
+        ] {
+            println!("{:<12} {} ({:.2}%)", format!("{}:", name), count, 100.0 * self.share(count));
+        }
+        for (code, count) in &outcomes.refused {
+            println!("  {} {}", count, code);
+        }
+        if let Some(mean) = self.mean_millis() {
+            let percentiles: Vec<String> = self
+                .percentiles()
+                .iter()
+                .map(|(p, ms)| format!("{} {:.2}", label(*p), ms))
+                .collect();
+            println!("Latency ms:  mean {:.2}, {}", mean, percentiles.join(", "));
+        }
+        if !outcomes.errors.is_empty() {
+            println!("Connection errors:");
+            for (error, count) in &outcomes.errors {
+                println!("  {} x {}", count, error);
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let outcomes = &self.outcomes;
+        let (succeeded, refused) = self.counts();
+        let percentiles: serde_json::Map<String, serde_json::Value> = self
+            .percentiles()
+            .into_iter()
+            .map(|(p, ms)| (label(p), json!(ms)))
+            .collect();
+        json!({
+            "connections": self.connections,
+            "requests_per_connection": self.requests_per_connection,
+            "depth": self.depth,
+            "batch": self.batch,
+            "protocol_version": self.version,
+            "elapsed_secs": self.elapsed.as_secs_f64(),
+            "requests_per_sec": self.per_sec(succeeded),
+            "evaluations_per_sec": self.per_sec(succeeded * self.batch as u64),
+            "succeeded": succeeded,
+            "refused": refused,
+            "refused_by_code": outcomes.refused,
+            "failed": outcomes.failed,
+            "unanswered": outcomes.unanswered,
+            "error_rate": self.share(self.total() - succeeded),
+            "unopened_connections": outcomes.unopened,
+            "connection_errors": outcomes.errors,
+            "latency_ms": {
+                "mean": self.mean_millis(),
+                "percentiles": percentiles,
+            },
+        })
+    }
+}
+
+/// Name of percentile `p`
+fn label(p: f64) -> String {
+    if p < 100.0 {
+        format!("p{}", p)
+    } else {
+        "max".to_string()
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,228 @@
+//! Operator commands on the enclave's admin port, see
+//! [`oprf_common::admin`].
+//!
+//! The operator key is an Ed25519 seed kept hex-encoded in a file of its
+//! own, which `admin keygen` writes while printing the public key to start
+//! the enclave with (`--admin-key`). An [`AdminConnection`] draws the
+//! challenge of its connection when opened and signs every command over it.
+//! Commands are sent one at a time; the admin port does not pipeline.
+//!
+//! An enclave with an admin port refuses key management on its data path,
+//! so with an operator key the commands that manage keys go through
+//! [`AdminAccess::connect`] to the admin port instead.
+//!
+//! `admin reload` takes the settings to change as `<name>=<value>`
+//! arguments, parsed by [`parse_reload`].
+
+use crate::client::OprfClient;
+use crate::connection::Connection;
+use oprf_common::admin::{
+    sign_command, AdminCommand, AdminRequest, ChallengeResponse, RateSetting, ReloadRequest,
+};
+use oprf_common::ed25519::SigningKey;
+use oprf_common::frame::{read_frame, write_frame, WireFormat};
+use oprf_common::{Message, OprfResult};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use zeroize::Zeroizing;
+
+/// A connection to the admin port, signing commands with the operator key
+pub struct AdminConnection<S> {
+    stream: S,
+    format: WireFormat,
+    key: SigningKey,
+    /// Challenge the enclave drew for the connection
+    challenge: Vec<u8>,
+    /// Commands sent so far
+    sequence: u64,
+    next_id: u64,
+}
+
+impl<S: Read + Write> AdminConnection<S> {
+    /// Open an admin connection over `stream` and draw its challenge
+    pub fn open(stream: S, format: WireFormat, key: SigningKey) -> std::io::Result<Self> {
+        let mut connection = Self {
+            stream,
+            format,
+            key,
+            challenge: Vec::new(),
+            sequence: 0,
+            next_id: 0,
+        };
+        let response: ChallengeResponse = connection.request(&AdminRequest::Challenge)?;
+        connection.challenge = response.challenge;
+        Ok(connection)
+    }
+
+    /// Sign `command` and wait for its response
+    pub fn run<Resp: DeserializeOwned>(&mut self, command: AdminCommand) -> std::io::Result<Resp> {
+        let signed = sign_command(&self.key, &self.challenge, self.sequence, command);
+        self.sequence += 1;
+        self.request(&AdminRequest::Command(Box::new(signed)))
+    }
+
+    fn request<Resp: DeserializeOwned>(&mut self, request: &AdminRequest) -> std::io::Result<Resp> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let bytes = self
+            .format
+            .encode(request)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        write_frame(&mut self.stream, id, self.format, &bytes)?;
+        let frame = read_frame(&mut self.stream)?.ok_or_else(|| {
+            Error::new(ErrorKind::UnexpectedEof, "Enclave closed the admin connection")
+        })?;
+        if frame.request_id != id {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Response to unknown request {}", frame.request_id),
+            ));
+        }
+        let result: OprfResult<Resp> = frame
+            .format
+            .decode(&frame.payload)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        result.map_err(Error::other)
+    }
+}
+
+/// The operator key file and admin port, if the parent was given them
+pub struct AdminAccess {
+    pub operator_key: Option<String>,
+    pub port: u32,
+    pub format: WireFormat,
+}
+
+impl AdminAccess {
+    /// Open an admin connection to the enclave of `client`
+    pub fn connect_admin(
+        &self,
+        client: &OprfClient,
+    ) -> Result<AdminConnection<TcpStream>, Box<dyn std::error::Error>> {
+        let path = self.operator_key.as_ref().ok_or("admin requires --operator-key")?;
+        let key = load_key(path)?;
+        let stream = client.connect_admin(self.port)?;
+        Ok(AdminConnection::open(stream, self.format, key)?)
+    }
+
+    /// Connect to the enclave of `client` for key management: to its admin
+    /// port given an operator key, to its data path otherwise
+    pub fn connect(
+        &self,
+        client: &OprfClient,
+    ) -> Result<KeyManagement, Box<dyn std::error::Error>> {
+        match self.operator_key {
+            Some(_) => Ok(KeyManagement::Admin(self.connect_admin(client)?)),
+            None => Ok(KeyManagement::DataPath(client.connect()?)),
+        }
+    }
+}
+
+/// A connection carrying key management requests, see
+/// [`Message::is_key_management`]
+pub enum KeyManagement {
+    DataPath(Connection<TcpStream>),
+    Admin(AdminConnection<TcpStream>),
+}
+
+impl KeyManagement {
+    /// Send `request` and wait for its response, over the admin port as the
+    /// [`AdminCommand`] carrying it
+    pub fn request<Resp: DeserializeOwned>(&mut self, request: &Message) -> std::io::Result<Resp> {
+        match self {
+            KeyManagement::DataPath(connection) => connection.request(request),
+            KeyManagement::Admin(connection) => {
+                let command = AdminCommand::from_message(request).ok_or_else(|| {
+                    Error::new(ErrorKind::InvalidInput, "Not a key management request")
+                })?;
+                connection.run(command)
+            }
+        }
+    }
+}
+
+/// Settings of `admin reload`, each `<name>=<value>`:
+///
+/// - `connection-rate`, `client-rate`: `<per_sec>[/<burst>]`, the burst one
+///   second's worth by default, or `off`
+/// - `max-connections`, `idle-timeout`, `frame-timeout`, `attestation-ttl`:
+///   a count or seconds
+/// - `log-sensitive`: `true` or `false`
+///
+/// None reloads nothing and reports the settings in force.
+pub fn parse_reload(settings: &[&str]) -> Result<ReloadRequest, String> {
+    let mut request = ReloadRequest::default();
+    for setting in settings {
+        let (name, value) = setting
+            .split_once('=')
+            .ok_or_else(|| format!("Reload setting {:?} is not <name>=<value>", setting))?;
+        let invalid = |e: String| format!("Invalid {}: {}", name, e);
+        let number = || value.parse::<u64>().map_err(|e| invalid(e.to_string()));
+        match name {
+            "connection-rate" => {
+                request.connection_rate = Some(parse_rate(value).map_err(invalid)?)
+            }
+            "client-rate" => request.client_rate = Some(parse_rate(value).map_err(invalid)?),
+            "max-connections" => request.max_connections = Some(number()? as usize),
+            "idle-timeout" => request.idle_timeout_secs = Some(number()?),
+            "frame-timeout" => request.frame_timeout_secs = Some(number()?),
+            "attestation-ttl" => request.attestation_ttl_secs = Some(number()?),
+            "log-sensitive" => {
+                let enabled = value.parse().map_err(|e: std::str::ParseBoolError| e.to_string());
+                request.log_sensitive = Some(enabled.map_err(invalid)?);
+            }
+            _ => return Err(format!("Unknown reload setting {:?}, see oprf-parent help", name)),
+        }
+    }
+    Ok(request)
+}
+
+/// Rate of `admin reload`: `off`, `<per_sec>` or `<per_sec>/<burst>`
+fn parse_rate(value: &str) -> Result<RateSetting, String> {
+    if value == "off" {
+        return Ok(RateSetting::Unlimited);
+    }
+    let (per_sec, burst) = value.split_once('/').unwrap_or((value, value));
+    let number = |text: &str| text.parse::<u64>().map_err(|e| e.to_string());
+    Ok(RateSetting::Limited {
+        per_sec: number(per_sec)?,
+        burst: number(burst)?,
+    })
+}
+
+/// Read the operator key from the hex-encoded seed in `path`
+pub fn load_key(path: &str) -> Result<SigningKey, String> {
+    let hex_seed = Zeroizing::new(
+        std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read operator key {}: {}", path, e))?,
+    );
+    let seed = hex::decode(hex_seed.trim())
+        .map(Zeroizing::new)
+        .map_err(|e| format!("Invalid operator key {}: {}", path, e))?;
+    let seed: &[u8; 32] = seed
+        .as_slice()
+        .try_into()
+        .map_err(|_| format!("Operator key {} must be a 32-byte seed", path))?;
+    Ok(SigningKey::from_seed(seed))
+}
+
+/// Write a new operator key to `path`, readable by its owner only, and
+/// return it. An existing file is left alone.
+pub fn generate_key(path: &str) -> Result<SigningKey, String> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut seed = Zeroizing::new([0u8; 32]);
+    OsRng.fill_bytes(seed.as_mut());
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| format!("Failed to create operator key {}: {}", path, e))?;
+    file.write_all(Zeroizing::new(hex::encode(seed.as_ref())).as_bytes())
+        .map_err(|e| format!("Failed to write operator key {}: {}", path, e))?;
+    Ok(SigningKey::from_seed(&seed))
+}
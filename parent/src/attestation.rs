@@ -0,0 +1,81 @@
+//! Verification of the enclave's attestation documents.
+//!
+//! Mock and NSM documents are checked alike: the [`Policy`] authenticates
+//! the document and checks its claims, then the user data signed into it is
+//! compared with the expected one. Evaluation
+//! attestations carry an [`EvaluationUserData`], each of whose fields is
+//! compared with the response before the document itself is verified.
+
+use crate::policy::Policy;
+use oprf_common::{nsm_user_data, AttestationDocument, AttestationError, EvaluationUserData};
+
+/// Verify an attestation document against the policy and the user data it
+/// must carry
+pub fn verify_attestation(
+    policy: &Policy,
+    attestation: &AttestationDocument,
+    expected_user_data: &[u8],
+) -> Result<(), AttestationError> {
+    if attestation.is_mock {
+        println!("[Parent] Verifying mock attestation (local mode)");
+    } else {
+        println!("[Parent] Verifying NSM attestation (Nitro mode)");
+    }
+
+    if attestation.user_data != expected_user_data {
+        return Err(AttestationError::UserDataMismatch("unexpected user data".to_string()));
+    }
+
+    // The signature (certificate chain and COSE signature of an NSM
+    // document), then the timestamp and the PCRs against the policy
+    let claims = policy.check(attestation)?;
+
+    // The user data signed into the document must be the expected one
+    if claims.user_data != Some(nsm_user_data(expected_user_data)) {
+        return Err(AttestationError::UserDataMismatch("signed user data differs".to_string()));
+    }
+    Ok(())
+}
+
+/// Check every field of the [`EvaluationUserData`] attested with an
+/// evaluation, then the attestation itself over its encoding
+pub fn verify_evaluation_attestation(
+    policy: &Policy,
+    attestation: &AttestationDocument,
+    expected: &EvaluationUserData,
+) -> Result<(), AttestationError> {
+    let attested = EvaluationUserData::from_cbor(&attestation.user_data)
+        .map_err(|e| AttestationError::MalformedDocument(e.to_string()))?;
+    if attested.protocol_version != expected.protocol_version {
+        return Err(AttestationError::UserDataMismatch(format!(
+            "protocol version {}, expected {}",
+            attested.protocol_version, expected.protocol_version
+        )));
+    }
+    if attested.key_id != expected.key_id {
+        return Err(AttestationError::UserDataMismatch(format!(
+            "key id {:?}, expected {:?}",
+            attested.key_id, expected.key_id
+        )));
+    }
+    if attested.epoch != expected.epoch {
+        return Err(AttestationError::UserDataMismatch(format!(
+            "key epoch {}, expected {}",
+            attested.epoch, expected.epoch
+        )));
+    }
+    if attested.public_key != expected.public_key {
+        return Err(AttestationError::UserDataMismatch(
+            "public key does not match the response".to_string(),
+        ));
+    }
+    if attested.evaluated_hash != expected.evaluated_hash {
+        return Err(AttestationError::UserDataMismatch(
+            "evaluated point hash does not match the response".to_string(),
+        ));
+    }
+    if attested.client_nonce != expected.client_nonce {
+        return Err(AttestationError::NonceMismatch);
+    }
+    verify_attestation(policy, attestation, &expected.to_cbor())
+}
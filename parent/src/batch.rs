@@ -0,0 +1,155 @@
+//! Batch evaluation of input files.
+//!
+//! Inputs are read one per line: JSON Lines files (`.jsonl`, `.ndjson`)
+//! hold a JSON string, or an object with an `"input"` string, per line;
+//! other files hold the input itself. Blank lines are skipped.
+//!
+//! A file of at most one chunk is evaluated in a single batch request. Larger
+//! files are split into chunks of at most [`MAX_BATCH_SIZE`] inputs, which
+//! several connections evaluate in parallel, each sending its chunks in turn.
+//! Results are written as JSON Lines in input order,
+//! `{"input": "...", "output": "<hex>"}`, once every chunk has succeeded.
+
+use crate::client::OprfClient;
+use crate::trace::{self, Span, SpanKind};
+use crate::{run_batch, Options};
+use oprf_common::config::DEFAULT_PARALLEL;
+use oprf_common::{Ciphersuite, OprfOutput, MAX_BATCH_SIZE};
+use serde::Deserialize;
+use std::io::Write;
+use std::path::Path;
+
+/// How a large input file is split across requests
+#[derive(Debug, Clone, Copy)]
+pub struct Chunking {
+    /// Inputs per batch request, at most [`MAX_BATCH_SIZE`]
+    pub chunk_size: usize,
+    /// Connections evaluating chunks at once
+    pub parallel: usize,
+}
+
+impl Default for Chunking {
+    fn default() -> Self {
+        Self {
+            chunk_size: MAX_BATCH_SIZE,
+            parallel: DEFAULT_PARALLEL,
+        }
+    }
+}
+
+impl Chunking {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=MAX_BATCH_SIZE).contains(&self.chunk_size) {
+            return Err(format!("--chunk-size must be between 1 and {}", MAX_BATCH_SIZE));
+        }
+        if self.parallel == 0 {
+            return Err("--parallel must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// One line of a JSON Lines input file
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum InputLine {
+    Input(String),
+    Object { input: String },
+}
+
+/// Inputs of the file at `path`, one per line
+pub fn read_inputs(path: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let json_lines = matches!(
+        Path::new(path).extension().and_then(|extension| extension.to_str()),
+        Some("jsonl" | "ndjson")
+    );
+    let mut inputs = Vec::new();
+    for (number, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if !json_lines {
+            inputs.push(line.to_string());
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(InputLine::Input(input) | InputLine::Object { input }) => inputs.push(input),
+            Err(e) => return Err(format!("{} line {}: {}", path, number + 1, e).into()),
+        }
+    }
+    if inputs.is_empty() {
+        return Err(format!("{} holds no inputs", path).into());
+    }
+    Ok(inputs)
+}
+
+/// Write every input with its output to `path` as JSON Lines
+pub fn write_results(
+    path: &str,
+    inputs: &[String],
+    outputs: &[OprfOutput],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    for (input, output) in inputs.iter().zip(outputs) {
+        let line = serde_json::json!({
+            "input": input,
+            "output": hex::encode(output.as_bytes()),
+        });
+        writeln!(file, "{}", line)?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Evaluate `inputs` under ciphersuite `C` in chunks spread over parallel
+/// connections. The outputs are in input order.
+pub fn evaluate_chunked<C: Ciphersuite>(
+    client: &OprfClient,
+    inputs: &[String],
+    chunking: Chunking,
+    options: &Options,
+) -> Result<Vec<OprfOutput>, Box<dyn std::error::Error>> {
+    let chunks: Vec<&[String]> = inputs.chunks(chunking.chunk_size).collect();
+    let parallel = chunking.parallel.min(chunks.len());
+    println!(
+        "[Parent] Evaluating {} inputs in {} chunks over {} connections",
+        inputs.len(),
+        chunks.len(),
+        parallel
+    );
+
+    // Connection i evaluates chunks i, i + parallel, i + 2 * parallel, ...
+    let mut by_chunk: Vec<Option<Vec<OprfOutput>>> = vec![None; chunks.len()];
+    let parent = trace::current();
+    // Chunks are the parts of the command, when there are several
+    let part = |index: usize| (chunks.len() > 1).then_some(index);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..parallel)
+            .map(|worker| {
+                let chunks = &chunks;
+                scope.spawn(move || {
+                    let mut span = Span::child_of("oprf.worker", SpanKind::Internal, parent);
+                    span.set("oprf.worker", worker);
+                    let mut connection = client.connect().map_err(|e| e.to_string())?;
+                    let mut results = Vec::new();
+                    for index in (worker..chunks.len()).step_by(parallel) {
+                        let chunk = chunks[index];
+                        let outputs =
+                            run_batch::<C>(client, &mut connection, chunk, part(index), options)
+                                .map_err(|e| format!("Chunk {}: {}", index, e))?;
+                        results.push((index, outputs));
+                    }
+                    Ok::<_, String>(results)
+                })
+            })
+            .collect();
+        for worker in workers {
+            let results = worker.join().map_err(|_| "Worker panicked".to_string())??;
+            for (index, outputs) in results {
+                by_chunk[index] = Some(outputs);
+            }
+        }
+        Ok::<_, String>(())
+    })?;
+    Ok(by_chunk.into_iter().flatten().flatten().collect())
+}
@@ -0,0 +1,930 @@
+//! Configured client of the enclave.
+//!
+//! An [`OprfClient`] holds everything deciding how the parent talks to the
+//! enclave: where it listens, the ciphersuite, the attestation policy, the
+//! wire format, timeouts, how connecting is retried, and optionally the
+//! public key evaluations must be made with. It is made by an
+//! [`OprfClientBuilder`], whose defaults are those of the command line:
+//!
+//! - the enclave at CID 16, port 5000 (127.0.0.1:5000 in local mode), with
+//!   no endpoint to fail over to, unbalanced
+//! - BN254, CBOR and the default [`Policy`]
+//! - no timeouts and a single connection attempt
+//! - a pool of up to 16 idle connections, each kept for up to 20 seconds
+//!   (see [`crate::pool`])
+//! - no pinned key
+//!
+//! With failover endpoints, connections go to the endpoint that last
+//! answered, the first one at first. One that cannot be reached, or fails
+//! the handshake, is passed over for the next in turn, which then stays in
+//! use. Every new connection checks the attested public key and epoch of the
+//! client's key: the endpoint in use may rotate it, but another one must
+//! present the key and epoch last seen, or the connection is refused, so a
+//! failover cannot silently switch evaluations to another key.
+//!
+//! A balanced client instead opens each connection to the endpoint after the
+//! one the previous connection went to, passing over those that cannot be
+//! reached the same way, so parallel connections, such as those of a chunked
+//! batch, spread over all of them. Its endpoints must then hold the client's
+//! key at the same epoch: rotating it on one fails the connections to the
+//! others, so replicas are given the same key by injecting or importing it.
+//!
+//! Opening a connection is retried when it fails before the enclave could
+//! be reached or the handshake completed, waiting a jittered, exponentially
+//! growing delay between attempts. [`OprfClient::request`] also reconnects
+//! and sends its request again when the connection breaks before the
+//! response, e.g. as the enclave restarts; requests sent over a
+//! [`Connection`] of [`OprfClient::connect`] are not. A refused attestation
+//! is never retried.
+
+use crate::connection::Connection;
+use crate::pool::{ConnectionPool, PoolLimits};
+use crate::trace::{Span, SpanKind};
+use oprf_parent::policy::Policy;
+use oprf_common::config::{
+    DEFAULT_ENCLAVE_CID, DEFAULT_MAX_RETRY_BACKOFF_MS, DEFAULT_PORT, DEFAULT_RETRY_BACKOFF_MS,
+};
+use oprf_common::frame::WireFormat;
+use oprf_common::{
+    attested_user_data, Bn254Sha256, Ciphersuite, CiphersuiteId, ErrorResponse,
+    GetPublicKeyRequest, Message, P256Sha256, PointEncoding, PublicKeyResponse, DEFAULT_KEY_ID,
+};
+use oprf_parent::attestation::verify_attestation;
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use std::io::{Error, ErrorKind};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Delay before the first retry unless configured; it doubles on every retry
+pub const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(DEFAULT_RETRY_BACKOFF_MS);
+
+/// Bound on the delay between retries unless configured
+pub const DEFAULT_MAX_RETRY_BACKOFF: Duration =
+    Duration::from_millis(DEFAULT_MAX_RETRY_BACKOFF_MS);
+
+/// Where the enclave listens. Local mode connects to the port on 127.0.0.1
+/// and ignores the CID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EnclaveAddress {
+    #[cfg_attr(not(feature = "nitro"), allow(dead_code))]
+    pub cid: u32,
+    pub port: u32,
+}
+
+impl Default for EnclaveAddress {
+    fn default() -> Self {
+        Self {
+            cid: DEFAULT_ENCLAVE_CID,
+            port: DEFAULT_PORT,
+        }
+    }
+}
+
+/// `<cid>:<port>`
+impl std::str::FromStr for EnclaveAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid enclave address {:?}, expected <cid>:<port>", s);
+        let (cid, port) = s.split_once(':').ok_or_else(invalid)?;
+        Ok(Self {
+            cid: cid.parse().map_err(|_| invalid())?,
+            port: port.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl std::fmt::Display for EnclaveAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if cfg!(feature = "nitro") {
+            write!(f, "{}:{}", self.cid, self.port)
+        } else {
+            write!(f, "127.0.0.1:{}", self.port)
+        }
+    }
+}
+
+impl EnclaveAddress {
+    /// The address with the `ENCLAVE_CID` and `ENCLAVE_PORT` environment
+    /// variables applied
+    pub fn with_env(self) -> Result<Self, String> {
+        let mut address = self;
+        let fields = [("ENCLAVE_CID", &mut address.cid), ("ENCLAVE_PORT", &mut address.port)];
+        for (name, field) in fields {
+            if let Ok(value) = std::env::var(name) {
+                *field = value.parse().map_err(|e| format!("Invalid {}: {}", name, e))?;
+            }
+        }
+        Ok(address)
+    }
+}
+
+/// How often opening a connection, or sending a request whose connection
+/// broke, is attempted
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Attempts in total, at least one
+    pub attempts: u32,
+    /// Delay before the first retry, doubled before every further one
+    pub backoff: Duration,
+    /// Bound on the delay, however many retries came before
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 1,
+            backoff: DEFAULT_RETRY_BACKOFF,
+            max_backoff: DEFAULT_MAX_RETRY_BACKOFF,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry `retry`, counted from 1: the backoff doubled for
+    /// every retry before, up to the bound, of which a random half is waited.
+    /// The jitter keeps parents that lost the same enclave from reconnecting
+    /// in lockstep as it comes back.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let doublings = retry.saturating_sub(1).min(31);
+        let delay = self.backoff.saturating_mul(1 << doublings).min(self.max_backoff);
+        delay / 2 + delay.mul_f64(rand::thread_rng().gen_range(0.0..0.5))
+    }
+}
+
+/// Builder of an [`OprfClient`]
+#[derive(Debug, Clone, Default)]
+pub struct OprfClientBuilder {
+    address: EnclaveAddress,
+    failover: Vec<EnclaveAddress>,
+    balance: bool,
+    key_id: Option<String>,
+    ciphersuite: CiphersuiteId,
+    policy: Policy,
+    wire_format: WireFormat,
+    point_encoding: PointEncoding,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    pool: PoolLimits,
+    pinned_public_key: Option<Vec<u8>>,
+}
+
+impl OprfClientBuilder {
+    /// Where the enclave listens
+    pub fn address(mut self, address: EnclaveAddress) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// Endpoints to fail over to, in turn, when the one in use is unreachable
+    pub fn failover(mut self, endpoints: Vec<EnclaveAddress>) -> Self {
+        self.failover = endpoints;
+        self
+    }
+
+    /// Spread connections over the address and failover endpoints in turn,
+    /// rather than keeping to the one in use
+    pub fn balance(mut self, balance: bool) -> Self {
+        self.balance = balance;
+        self
+    }
+
+    /// Key whose attested public key and epoch every endpoint must present
+    /// alike, `default` unless set
+    pub fn key_id(mut self, key_id: impl Into<String>) -> Self {
+        self.key_id = Some(key_id.into());
+        self
+    }
+
+    /// Ciphersuite of evaluations and key operations
+    pub fn ciphersuite(mut self, ciphersuite: CiphersuiteId) -> Self {
+        self.ciphersuite = ciphersuite;
+        self
+    }
+
+    /// Enclave images whose attestations are accepted
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Encoding of requests and responses
+    pub fn wire_format(mut self, wire_format: WireFormat) -> Self {
+        self.wire_format = wire_format;
+        self
+    }
+
+    /// Encoding of the points of evaluations, asked for on every connection
+    pub fn point_encoding(mut self, point_encoding: PointEncoding) -> Self {
+        self.point_encoding = point_encoding;
+        self
+    }
+
+    /// Bound on connecting to the enclave over TCP. vsock connections keep
+    /// the kernel's own connect timeout.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Bound on every read from and write to the enclave
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// How often opening a connection is attempted
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// How many connections [`OprfClient::request`] keeps open between
+    /// requests, and for how long
+    pub fn pool(mut self, limits: PoolLimits) -> Self {
+        self.pool = limits;
+        self
+    }
+
+    /// Only accept responses made with this serialized public key
+    pub fn pin_public_key(mut self, public_key: Vec<u8>) -> Self {
+        self.pinned_public_key = Some(public_key);
+        self
+    }
+
+    /// Check the configuration and build the client
+    pub fn build(self) -> Result<OprfClient, String> {
+        if self.retry.attempts == 0 {
+            return Err("At least one connection attempt is required".to_string());
+        }
+        if self.retry.max_backoff < self.retry.backoff {
+            return Err("The retry backoff must not exceed its bound".to_string());
+        }
+        if [self.connect_timeout, self.timeout].contains(&Some(Duration::ZERO))
+            || self.pool.idle_timeout.is_zero()
+        {
+            return Err("Timeouts must be positive".to_string());
+        }
+        if let Some(public_key) = &self.pinned_public_key {
+            let valid = match self.ciphersuite {
+                CiphersuiteId::Bn254Sha256 => Bn254Sha256::deserialize_element(public_key).is_ok(),
+                CiphersuiteId::P256Sha256 => P256Sha256::deserialize_element(public_key).is_ok(),
+            };
+            if !valid {
+                return Err(format!(
+                    "Pinned key is not a {} public key",
+                    self.ciphersuite.identifier()
+                ));
+            }
+        }
+        let mut endpoints = vec![self.address];
+        for endpoint in self.failover {
+            if endpoints.contains(&endpoint) {
+                return Err(format!("Enclave endpoint {} is given twice", endpoint));
+            }
+            endpoints.push(endpoint);
+        }
+        Ok(OprfClient {
+            endpoints,
+            balance: self.balance,
+            failover: Arc::default(),
+            key_id: self.key_id.unwrap_or_else(|| DEFAULT_KEY_ID.to_string()),
+            ciphersuite: self.ciphersuite,
+            policy: self.policy,
+            wire_format: self.wire_format,
+            point_encoding: self.point_encoding,
+            connect_timeout: self.connect_timeout,
+            timeout: self.timeout,
+            retry: self.retry,
+            pool: Arc::new(ConnectionPool::new(self.pool)),
+            pinned_public_key: self.pinned_public_key,
+        })
+    }
+}
+
+/// Attested public key of the client's key, as an endpoint presented it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct KeyIdentity {
+    public_key: Vec<u8>,
+    epoch: u64,
+}
+
+/// Which endpoint is in use, shared by the clones of a client
+#[derive(Debug, Default)]
+struct Failover {
+    /// Index of the endpoint connections go to, or of the last one a
+    /// balanced client connected to
+    active: AtomicUsize,
+    /// Endpoints a balanced client has connected to so far
+    next: AtomicUsize,
+    /// Endpoint that last presented the client's key, and the key
+    identity: Mutex<Option<(usize, KeyIdentity)>>,
+}
+
+/// Client of the enclave, configured by an [`OprfClientBuilder`]
+#[derive(Debug, Clone)]
+pub struct OprfClient {
+    /// The address first, then the endpoints to fail over to
+    endpoints: Vec<EnclaveAddress>,
+    /// Whether connections go to every endpoint in turn
+    balance: bool,
+    failover: Arc<Failover>,
+    /// Key checked to be the same on every endpoint
+    key_id: String,
+    ciphersuite: CiphersuiteId,
+    policy: Policy,
+    wire_format: WireFormat,
+    point_encoding: PointEncoding,
+    #[cfg_attr(feature = "nitro", allow(dead_code))]
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    /// Idle connections, shared by the clones of the client
+    pool: Arc<ConnectionPool>,
+    pinned_public_key: Option<Vec<u8>>,
+}
+
+impl OprfClient {
+    pub fn builder() -> OprfClientBuilder {
+        OprfClientBuilder::default()
+    }
+
+    /// Ciphersuite of evaluations and key operations
+    pub fn ciphersuite(&self) -> CiphersuiteId {
+        self.ciphersuite
+    }
+
+    /// Enclave images whose attestations are accepted
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    /// The same client, accepting the images of `policy` instead. The
+    /// connections pooled so far were checked against the old policy, so
+    /// the client starts a pool of its own.
+    #[cfg_attr(
+        not(any(feature = "http", feature = "jsonrpc", feature = "grpc")),
+        allow(dead_code)
+    )]
+    pub fn with_policy(&self, policy: Policy) -> Self {
+        Self {
+            policy,
+            pool: Arc::new(ConnectionPool::new(self.pool.limits())),
+            ..self.clone()
+        }
+    }
+
+    /// Check that a response was made with the pinned public key, if any
+    pub fn check_public_key(&self, public_key: &[u8]) -> Result<(), String> {
+        match &self.pinned_public_key {
+            Some(pinned) if pinned.as_slice() != public_key => Err(format!(
+                "Enclave answered with public key {}, not the pinned key",
+                hex::encode(public_key)
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Endpoint connections go to
+    fn active(&self) -> EnclaveAddress {
+        self.endpoints[self.failover.active.load(Ordering::Relaxed) % self.endpoints.len()]
+    }
+
+    /// Open a connection to the enclave and negotiate the protocol, failing
+    /// over to the next endpoint and retrying as the retry policy allows
+    pub fn connect(&self) -> std::io::Result<Connection<TcpStream>> {
+        let mut span = Span::start("oprf.connect", SpanKind::Client);
+        for attempt in 1.. {
+            let result = self.connect_any();
+            let active = self.active();
+            span.set("oprf.enclave.cid", active.cid);
+            span.set("oprf.enclave.port", active.port);
+            match result {
+                Ok(connection) => {
+                    println!(
+                        "[Parent] Connected to enclave (protocol version {})",
+                        connection.version()
+                    );
+                    span.set("oprf.connect.attempts", attempt);
+                    span.set("oprf.protocol_version", connection.version());
+                    return Ok(connection);
+                }
+                Err(e) if attempt < self.retry.attempts && is_transient(&e) => {
+                    let delay = self.retry.delay(attempt);
+                    println!(
+                        "[Parent] Connection attempt {} failed: {}; retrying in {:?}",
+                        attempt, e, delay
+                    );
+                    std::thread::sleep(delay);
+                }
+                Err(e) => {
+                    span.set("oprf.connect.attempts", attempt);
+                    return span.check(Err(e));
+                }
+            }
+        }
+        unreachable!("the attempts are unbounded")
+    }
+
+    /// Open a connection to the endpoint in use, or else the first of the
+    /// others in turn that can be reached, which is then used from now on. A
+    /// balanced client starts from the endpoint after its last one instead.
+    fn connect_any(&self) -> std::io::Result<Connection<TcpStream>> {
+        let start = if self.balance {
+            self.failover.next.fetch_add(1, Ordering::Relaxed)
+        } else {
+            self.failover.active.load(Ordering::Relaxed)
+        };
+        let mut unreachable = None;
+        for index in (start..).take(self.endpoints.len()) {
+            let index = index % self.endpoints.len();
+            let address = self.endpoints[index];
+            let result = self.open_stream(address).and_then(|stream| {
+                Connection::open(stream, self.wire_format, self.point_encoding, &self.policy)
+            });
+            let result = match result {
+                Ok(connection) if self.endpoints.len() > 1 => {
+                    self.check_identity(index, connection)
+                }
+                result => result,
+            };
+            match result {
+                Ok(connection) => {
+                    if !self.balance && index != start % self.endpoints.len() {
+                        println!("[Parent] Failed over to the enclave at {}", address);
+                    }
+                    self.failover.active.store(index, Ordering::Relaxed);
+                    return Ok(connection);
+                }
+                Err(e) if is_transient(&e) => {
+                    println!("[Parent] Enclave at {} is unavailable: {}", address, e);
+                    unreachable = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(unreachable.expect("there is at least one endpoint"))
+    }
+
+    /// Check that endpoint `index` presents the same attested key as the
+    /// endpoint that last did, unless it is that endpoint, and record it. An
+    /// endpoint without the key, e.g. before it is injected, is not checked.
+    fn check_identity(
+        &self,
+        index: usize,
+        mut connection: Connection<TcpStream>,
+    ) -> std::io::Result<Connection<TcpStream>> {
+        let request = GetPublicKeyRequest {
+            ciphersuite: self.ciphersuite,
+            key_id: self.key_id.clone(),
+        };
+        let response: PublicKeyResponse =
+            match connection.request(&Message::GetPublicKey(request)) {
+                Ok(response) => response,
+                Err(e) if is_refused(&e) => return Ok(connection),
+                Err(e) => return Err(e),
+            };
+        let binding = attested_user_data(&self.key_id, &response.public_key);
+        verify_attestation(&self.policy, &response.attestation, &binding)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let seen = KeyIdentity {
+            public_key: response.public_key,
+            epoch: response.epoch,
+        };
+        let mut identity = self.failover.identity.lock().unwrap_or_else(|e| e.into_inner());
+        match &*identity {
+            Some((last, expected)) if *last != index && *expected != seen => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Enclave at {} presents key {:?} {} at epoch {}, not {} at epoch {} \
+                         as the enclave at {} did",
+                        self.endpoints[index],
+                        self.key_id,
+                        hex::encode(&seen.public_key),
+                        seen.epoch,
+                        hex::encode(&expected.public_key),
+                        expected.epoch,
+                        self.endpoints[*last]
+                    ),
+                ));
+            }
+            _ => *identity = Some((index, seen)),
+        }
+        Ok(connection)
+    }
+
+    /// Send `request` over a pooled connection, or a new one if none is
+    /// idle, and read its response; the connection then goes back to the
+    /// pool. If the connection breaks before the response, the request is
+    /// sent again over a new one, as often as the retry policy allows;
+    /// opening each is retried as by [`Self::connect`]. Only for requests
+    /// the enclave may see twice: reads, and evaluations, whose repetition it
+    /// refuses as a replayed nonce if the first arrived, or evaluates to the
+    /// same output.
+    pub fn request<Resp: DeserializeOwned>(&self, request: &Message) -> std::io::Result<Resp> {
+        let mut attempt = 1;
+        loop {
+            let pooled = self.pool.take();
+            let reused = pooled.is_some();
+            let mut connection = match pooled {
+                Some(connection) => connection,
+                None => self.connect()?,
+            };
+            let result = connection.request(request);
+            // A refused request leaves the connection usable
+            if result.as_ref().map_or_else(is_refused, |_| true) {
+                self.pool.put(connection);
+            }
+            match result {
+                // The enclave may have closed a pooled connection just now
+                Err(e) if reused && is_broken(&e) => {}
+                Err(e) if attempt < self.retry.attempts && is_broken(&e) => {
+                    let delay = self.retry.delay(attempt);
+                    println!(
+                        "[Parent] Connection to enclave broke: {}; resending in {:?}",
+                        e, delay
+                    );
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Connect to the admin port of the enclave, on `port` of the CID of
+    /// the endpoint in use. The connection is not retried and has no channel;
+    /// admin commands are signed instead, see [`crate::admin`].
+    pub fn connect_admin(&self, port: u32) -> std::io::Result<TcpStream> {
+        self.open_stream(EnclaveAddress { port, ..self.active() })
+    }
+
+    /// Connect to the heartbeat port of the enclave, on `port` of the CID of
+    /// the endpoint in use. Nothing is written to the connection and reads
+    /// are bounded by the watchdog, see [`crate::watchdog`].
+    pub fn connect_heartbeats(&self, port: u32) -> std::io::Result<TcpStream> {
+        self.connect_stream(EnclaveAddress { port, ..self.active() })
+    }
+
+    /// Connect to `address` and apply the read and write timeout
+    fn open_stream(&self, address: EnclaveAddress) -> std::io::Result<TcpStream> {
+        let stream = self.connect_stream(address)?;
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+        Ok(stream)
+    }
+
+    #[cfg(all(feature = "local", not(feature = "nitro")))]
+    fn connect_stream(&self, address: EnclaveAddress) -> std::io::Result<TcpStream> {
+        let port = u16::try_from(address.port)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "Enclave port out of range"))?;
+        println!("[Parent] Connecting to enclave at 127.0.0.1:{}", port);
+        match self.connect_timeout {
+            Some(timeout) => TcpStream::connect_timeout(&(([127, 0, 0, 1], port).into()), timeout),
+            None => TcpStream::connect(("127.0.0.1", port)),
+        }
+    }
+
+    #[cfg(feature = "nitro")]
+    fn connect_stream(&self, address: EnclaveAddress) -> std::io::Result<TcpStream> {
+        use nix::sys::socket::{connect, socket, AddressFamily, SockFlag, SockType, VsockAddr};
+        use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+
+        let sock_fd = socket(AddressFamily::Vsock, SockType::Stream, SockFlag::empty(), None)
+            .map_err(Error::other)?;
+
+        let EnclaveAddress { cid, port } = address;
+        let addr = VsockAddr::new(cid, port);
+
+        println!("[Parent] Connecting to enclave via vsock (CID: {}, Port: {})", cid, port);
+
+        connect(sock_fd.as_raw_fd(), &addr).map_err(Error::other)?;
+
+        Ok(unsafe { TcpStream::from_raw_fd(sock_fd.into_raw_fd()) })
+    }
+}
+
+/// Whether opening a connection failed in a way another attempt may not:
+/// attestation and protocol errors are final
+fn is_transient(error: &Error) -> bool {
+    !matches!(error.kind(), ErrorKind::InvalidData | ErrorKind::InvalidInput)
+}
+
+/// Whether the enclave refused a request, with an [`ErrorResponse`]
+fn is_refused(error: &Error) -> bool {
+    error.get_ref().is_some_and(|inner| inner.is::<ErrorResponse>())
+}
+
+/// Whether a request failed by the enclave dropping its connection, e.g. as
+/// it restarts, as opposed to refusing the request or timing out on it
+fn is_broken(error: &Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::UnexpectedEof
+    )
+}
+
+/// Clients of mock enclaves on loopback ports, for the tests of the modules
+/// sending through them
+#[cfg(all(test, feature = "local", not(feature = "nitro")))]
+pub(crate) mod tests {
+    use super::*;
+    use oprf_common::ed25519::{SigningKey, VerifyingKey};
+    use oprf_common::frame::{read_frame, write_frame, Frame};
+    use oprf_common::{HealthResponse, RotateKeyRequest, RotateKeyResponse};
+    use oprf_server::testing::LocalMockEnclave;
+    use oprf_server::ConnectionLimits;
+    use rand::rngs::OsRng;
+    use std::net::{Shutdown, TcpListener};
+    use std::time::Instant;
+
+    /// `count` mock enclaves within `limits`, attested with the same key, and
+    /// the policy accepting them
+    pub(crate) fn enclaves(
+        count: usize,
+        limits: ConnectionLimits,
+    ) -> (Vec<LocalMockEnclave>, Policy) {
+        let seed = OsRng.gen::<[u8; 32]>();
+        let key = SigningKey::from_seed(&seed).public_key();
+        let mut policy = Policy::default();
+        policy.mock_signing_key = Some(VerifyingKey::from_bytes(&key).unwrap());
+        let enclaves = (0..count)
+            .map(|_| LocalMockEnclave::start(SigningKey::from_seed(&seed), limits).unwrap())
+            .collect();
+        (enclaves, policy)
+    }
+
+    /// A mock enclave with the default limits, and the policy accepting it
+    pub(crate) fn enclave() -> (LocalMockEnclave, Policy) {
+        let (mut enclaves, policy) = enclaves(1, ConnectionLimits::default());
+        (enclaves.remove(0), policy)
+    }
+
+    pub(crate) fn address(enclave: &LocalMockEnclave) -> EnclaveAddress {
+        EnclaveAddress {
+            port: enclave.port().into(),
+            ..EnclaveAddress::default()
+        }
+    }
+
+    /// Address nothing listens on
+    pub(crate) fn closed_address() -> EnclaveAddress {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        EnclaveAddress {
+            port: listener.local_addr().unwrap().port().into(),
+            ..EnclaveAddress::default()
+        }
+    }
+
+    /// Builder of a client of `address` accepting the images of `policy`
+    pub(crate) fn builder(address: EnclaveAddress, policy: &Policy) -> OprfClientBuilder {
+        OprfClient::builder()
+            .address(address)
+            .policy(policy.clone())
+            .ciphersuite(CiphersuiteId::P256Sha256)
+            .timeout(Duration::from_secs(10))
+    }
+
+    /// A proxy to `enclave` whose first `broken` connections are closed when
+    /// the frame after their first `frames` arrives
+    fn flaky_proxy(enclave: &LocalMockEnclave, broken: usize, frames: usize) -> EnclaveAddress {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let proxy = EnclaveAddress {
+            port: listener.local_addr().unwrap().port().into(),
+            ..EnclaveAddress::default()
+        };
+        let target = enclave.port();
+        std::thread::spawn(move || {
+            for (index, client) in listener.incoming().enumerate() {
+                let (Ok(mut client), Ok(mut enclave)) =
+                    (client, TcpStream::connect(("127.0.0.1", target)))
+                else {
+                    return;
+                };
+                let (mut responses, mut to_client) =
+                    (enclave.try_clone().unwrap(), client.try_clone().unwrap());
+                std::thread::spawn(move || std::io::copy(&mut responses, &mut to_client));
+                let limit = (index < broken).then_some(frames);
+                std::thread::spawn(move || {
+                    let mut forwarded = 0;
+                    while let Ok(Some(frame)) = read_frame(&mut client) {
+                        if limit == Some(forwarded) {
+                            break;
+                        }
+                        let Frame { request_id, format, payload } = frame;
+                        if write_frame(&mut enclave, request_id, format, &payload).is_err() {
+                            break;
+                        }
+                        forwarded += 1;
+                    }
+                    let _ = client.shutdown(Shutdown::Both);
+                    let _ = enclave.shutdown(Shutdown::Both);
+                });
+            }
+        });
+        proxy
+    }
+
+    fn retry(attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            attempts,
+            backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(20),
+        }
+    }
+
+    #[test]
+    fn test_retry_delay_jittered_and_bounded() {
+        let retry = RetryPolicy {
+            attempts: 5,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+        };
+        let bounds = [(1, 100), (2, 200), (3, 400), (4, 800), (5, 1000), (64, 1000)];
+        for (retry_number, full_ms) in bounds {
+            let full = Duration::from_millis(full_ms);
+            for _ in 0..100 {
+                let delay = retry.delay(retry_number);
+                assert!(delay >= full / 2 && delay < full, "{:?} at {}", delay, retry_number);
+            }
+        }
+    }
+
+    #[test]
+    fn test_retry_policy_checked() {
+        let policy = Policy::default();
+        let error = builder(closed_address(), &policy).retry(retry(0)).build().unwrap_err();
+        assert_eq!(error, "At least one connection attempt is required");
+        let inverted = RetryPolicy {
+            max_backoff: Duration::from_millis(5),
+            ..retry(2)
+        };
+        let error = builder(closed_address(), &policy).retry(inverted).build().unwrap_err();
+        assert_eq!(error, "The retry backoff must not exceed its bound");
+    }
+
+    #[test]
+    fn test_connect_retried() {
+        let (enclave, policy) = enclave();
+        // Both of the first two connections close before the Hello is answered
+        let proxy = flaky_proxy(&enclave, 2, 0);
+        let client = builder(proxy, &policy).retry(retry(2)).build().unwrap();
+        let error = client.connect().err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        client.connect().unwrap();
+
+        let client = builder(closed_address(), &policy).retry(retry(3)).build().unwrap();
+        let error = client.connect().err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn test_refused_attestation_not_retried() {
+        let (enclave, _) = enclave();
+        let retry = RetryPolicy {
+            attempts: 5,
+            backoff: Duration::from_secs(10),
+            max_backoff: Duration::from_secs(10),
+        };
+        let client = builder(address(&enclave), &Policy::default()).retry(retry).build().unwrap();
+        let started = Instant::now();
+        let error = client.connect().err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_request_resent_when_connection_breaks() {
+        let (enclave, policy) = enclave();
+        // The first connection closes as the request after the Hello and the
+        // channel handshake arrives
+        let proxy = flaky_proxy(&enclave, 1, 2);
+        let client = builder(proxy, &policy).retry(retry(2)).build().unwrap();
+        let _: HealthResponse = client.request(&Message::Health).unwrap();
+
+        let proxy = flaky_proxy(&enclave, 1, 2);
+        let client = builder(proxy, &policy).build().unwrap();
+        let error = client.request::<HealthResponse>(&Message::Health).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        // A refused request is answered, not resent
+        let request = GetPublicKeyRequest {
+            ciphersuite: CiphersuiteId::P256Sha256,
+            key_id: "no such key".to_string(),
+        };
+        let error = client
+            .request::<PublicKeyResponse>(&Message::GetPublicKey(request))
+            .unwrap_err();
+        assert!(is_refused(&error));
+    }
+
+    #[test]
+    fn test_request_reuses_pooled_connection() {
+        let limits = ConnectionLimits {
+            idle_timeout: Duration::from_millis(300),
+            ..ConnectionLimits::default()
+        };
+        let (enclaves, policy) = enclaves(1, limits);
+        let pool = PoolLimits {
+            size: 1,
+            idle_timeout: Duration::from_secs(60),
+        };
+        let client = builder(address(&enclaves[0]), &policy).pool(pool).build().unwrap();
+        let port = |connection: &Connection<TcpStream>| {
+            connection.get_ref().local_addr().unwrap().port()
+        };
+        let _: HealthResponse = client.request(&Message::Health).unwrap();
+        let pooled = client.pool.take().unwrap();
+        let first = port(&pooled);
+        client.pool.put(pooled);
+        let _: HealthResponse = client.request(&Message::Health).unwrap();
+        let pooled = client.pool.take().unwrap();
+        assert_eq!(port(&pooled), first);
+        client.pool.put(pooled);
+
+        // Once the enclave closed it for idling, a new one is opened
+        std::thread::sleep(Duration::from_millis(600));
+        let _: HealthResponse = client.request(&Message::Health).unwrap();
+        assert_ne!(port(&client.pool.take().unwrap()), first);
+    }
+
+    #[test]
+    fn test_failover_to_reachable_endpoint() {
+        let (enclave, policy) = enclave();
+        let client = builder(closed_address(), &policy)
+            .failover(vec![address(&enclave)])
+            .build()
+            .unwrap();
+        assert_eq!(client.failover.active.load(Ordering::Relaxed), 0);
+        client.connect().unwrap();
+        // The endpoint that answered stays in use
+        assert_eq!(client.active(), address(&enclave));
+        client.connect().unwrap();
+
+        let twice = builder(address(&enclave), &policy).failover(vec![address(&enclave)]).build();
+        assert!(twice.unwrap_err().ends_with("is given twice"));
+    }
+
+    #[test]
+    fn test_failover_checks_key_identity() {
+        let (enclaves, policy) = enclaves(2, ConnectionLimits::default());
+        let client = builder(address(&enclaves[0]), &policy)
+            .failover(vec![address(&enclaves[1])])
+            .build()
+            .unwrap();
+        let mut connection = client.connect().unwrap();
+
+        // The endpoint in use may rotate the key
+        let rotate = Message::RotateKey(RotateKeyRequest {
+            ciphersuite: CiphersuiteId::P256Sha256,
+            key_id: DEFAULT_KEY_ID.to_string(),
+            grace_period_secs: None,
+        });
+        let rotated: RotateKeyResponse = connection.request(&rotate).unwrap();
+        assert_eq!(rotated.epoch, 1);
+        client.connect().unwrap();
+
+        // The other holds another key, so failing over to it is refused
+        enclaves[0].stop();
+        let error = client.connect().err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("at epoch 0, not"), "{}", error);
+    }
+
+    #[test]
+    fn test_balanced_connections_take_turns() {
+        let (enclaves, policy) = enclaves(2, ConnectionLimits::default());
+        let client = builder(address(&enclaves[0]), &policy)
+            .failover(vec![address(&enclaves[1])])
+            .balance(true)
+            .build()
+            .unwrap();
+        client.connect().unwrap();
+        assert_eq!(client.active(), address(&enclaves[0]));
+        // The next connection goes to the other endpoint, whose key differs
+        let error = client.connect().err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        client.connect().unwrap();
+        assert_eq!(client.active(), address(&enclaves[0]));
+
+        // Endpoints that cannot be reached are passed over
+        let client = builder(address(&enclaves[0]), &policy)
+            .failover(vec![closed_address()])
+            .balance(true)
+            .build()
+            .unwrap();
+        for _ in 0..3 {
+            client.connect().unwrap();
+            assert_eq!(client.active(), address(&enclaves[0]));
+        }
+    }
+}
@@ -0,0 +1,457 @@
+//! Persistent connection to the enclave with pipelined requests.
+//!
+//! Requests are tagged with increasing ids (see [`oprf_common::frame`]).
+//! Several can be sent before the first response is read; a response that
+//! arrives for another outstanding request than the one awaited is kept
+//! until that request is awaited. Requests are encoded in the wire format
+//! chosen for the connection and responses in the format of their frame.
+//!
+//! A connection opens with a handshake offering every protocol version the
+//! parent speaks from the policy's `min_protocol_version` on; the enclave
+//! answers with the one to use, and a lower answer is refused. The attested
+//! channel key is bound to the offered versions, so a man in the middle
+//! cannot strip the higher ones from the unauthenticated `Hello` either,
+//! short of the minimum. From version 2 on
+//! a refused request is answered with an [`oprf_common::ErrorResponse`],
+//! returned as the source of an `io::Error`, and the connection stays usable.
+//! From version 3 on the enclave offers an attested channel key; the
+//! connection checks its attestation against the policy, runs the channel
+//! handshake, and seals every later frame (see [`oprf_common::channel`]).
+//! From version 4 on the attestation also covers the enclave's response
+//! signing key, and every later response must carry its signature (see
+//! [`oprf_common::signing`]).
+//!
+//! The Hello may also ask for uncompressed points in evaluations; an enclave
+//! that does not know the option answers without it, and the connection
+//! keeps to compressed points. It also offers to tag evaluations with
+//! request ids, which only enclaves that accept them agree to.
+
+use crate::trace::{Span, SpanKind};
+use oprf_parent::policy::Policy;
+use oprf_parent::attestation::verify_attestation;
+use oprf_common::channel::{channel_binding, Channel, Initiator};
+use oprf_common::ed25519::VerifyingKey;
+use oprf_common::frame::{read_frame, write_frame, Frame, WireFormat};
+use oprf_common::signing::{session_binding, verify_response};
+use oprf_common::{
+    HandshakeRequest, HandshakeResponse, HelloRequest, HelloResponse, Message, OprfResult,
+    PointEncoding, SUPPORTED_PROTOCOL_VERSIONS,
+};
+use rand::rngs::OsRng;
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind, Read, Write};
+use zeroize::Zeroizing;
+
+/// A connection carrying any number of requests
+pub struct Connection<S> {
+    stream: S,
+    format: WireFormat,
+    /// Protocol version negotiated by the handshake
+    version: u32,
+    /// Encoding of evaluated points the enclave agreed to
+    point_encoding: PointEncoding,
+    /// The enclave accepts request ids in evaluations
+    request_ids: bool,
+    next_id: u64,
+    /// Ids sent whose response has not been read yet
+    outstanding: HashSet<u64>,
+    /// Responses read ahead of being awaited, by request id
+    received: HashMap<u64, Frame>,
+    /// Secure channel sealing every frame, from version 3 on
+    channel: Option<Channel>,
+    /// Attested key signing every response after the Hello, from version 4 on
+    signing_key: Option<VerifyingKey>,
+}
+
+impl<S: Read + Write> Connection<S> {
+    /// Open a connection over `stream` and negotiate the protocol version,
+    /// at least the minimum of `policy`, and, if it can, `point_encoding`.
+    /// The channel key of a version 3 enclave must be attested by an image
+    /// `policy` allows.
+    pub fn open(
+        stream: S,
+        format: WireFormat,
+        point_encoding: PointEncoding,
+        policy: &Policy,
+    ) -> std::io::Result<Self> {
+        let mut connection = Self {
+            stream,
+            format,
+            version: 0,
+            point_encoding: PointEncoding::Compressed,
+            request_ids: false,
+            next_id: 1,
+            outstanding: HashSet::new(),
+            received: HashMap::new(),
+            channel: None,
+            signing_key: None,
+        };
+        let versions: Vec<u32> = SUPPORTED_PROTOCOL_VERSIONS
+            .iter()
+            .copied()
+            .filter(|version| *version >= policy.min_protocol_version)
+            .collect();
+        let hello = HelloRequest {
+            versions: versions.clone(),
+            point_encoding,
+            request_ids: true,
+        };
+        // The handshake answer is never wrapped in an OprfResult
+        let id = connection.send(&Message::Hello(hello))?;
+        let response: HelloResponse = Self::decode(connection.next_frame(id)?)?;
+        if response.version < policy.min_protocol_version {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Enclave chose protocol version {}, below the minimum {}",
+                    response.version, policy.min_protocol_version
+                ),
+            ));
+        }
+        if !versions.contains(&response.version) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Enclave chose unsupported protocol version {}", response.version),
+            ));
+        }
+        if response.point_encoding != point_encoding && !response.point_encoding.is_compressed() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Enclave chose unrequested {:?} points", response.point_encoding),
+            ));
+        }
+        connection.version = response.version;
+        connection.point_encoding = response.point_encoding;
+        connection.request_ids = response.request_ids;
+        if response.version >= 3 {
+            connection.establish_channel(&versions, &response, policy)?;
+        }
+        Ok(connection)
+    }
+
+    /// Verify the enclave's attested channel key, and from version 4 on its
+    /// response signing key, both bound to the `offered` versions, then run
+    /// the handshake. As the version is at least the policy's minimum, a
+    /// minimum of 4 makes the signing key mandatory.
+    fn establish_channel(
+        &mut self,
+        offered: &[u32],
+        hello: &HelloResponse,
+        policy: &Policy,
+    ) -> std::io::Result<()> {
+        let (Some(channel_key), Some(attestation)) = (&hello.channel_key, &hello.attestation)
+        else {
+            return Err(Error::new(ErrorKind::InvalidData, "Enclave offered no channel key"));
+        };
+        let binding = match (hello.version >= 4, &hello.signing_key) {
+            (true, Some(signing_key)) => session_binding(channel_key, offered, signing_key),
+            (true, None) => {
+                return Err(Error::new(ErrorKind::InvalidData, "Enclave offered no signing key"))
+            }
+            (false, _) => channel_binding(channel_key, offered),
+        };
+        verify_attestation(policy, attestation, &binding)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        if let Some(signing_key) = hello.signing_key.as_ref().filter(|_| hello.version >= 4) {
+            let key = VerifyingKey::from_bytes(signing_key)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            self.signing_key = Some(key);
+        }
+
+        let (initiator, message) = Initiator::start(channel_key, &mut OsRng)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let response: HandshakeResponse =
+            self.request(&Message::Handshake(HandshakeRequest { message }))?;
+        let channel = initiator
+            .finish(&response.message)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        self.channel = Some(channel);
+        Ok(())
+    }
+
+    /// Stream the connection runs over
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Protocol version negotiated when the connection was opened
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Encoding of the points of evaluations on this connection
+    pub fn point_encoding(&self) -> PointEncoding {
+        self.point_encoding
+    }
+
+    /// Whether evaluations on this connection may carry a request id
+    pub fn request_ids(&self) -> bool {
+        self.request_ids
+    }
+
+    /// Send a request without waiting for its response. Returns its id.
+    pub fn send(&mut self, request: &Message) -> std::io::Result<u64> {
+        let mut span = Span::start("oprf.send", SpanKind::Client);
+        span.set("oprf.request_id", self.next_id);
+        span.set("oprf.protocol_version", self.version);
+        span.check(self.write_request(request))
+    }
+
+    fn write_request(&mut self, request: &Message) -> std::io::Result<u64> {
+        let id = self.next_id;
+        let mut request_bytes = Zeroizing::new(
+            self.format
+                .encode(request)
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?,
+        );
+        if let Some(channel) = &mut self.channel {
+            request_bytes = Zeroizing::new(
+                channel
+                    .seal(id, self.format, &request_bytes)
+                    .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?,
+            );
+        }
+        write_frame(&mut self.stream, id, self.format, &request_bytes)?;
+        self.next_id += 1;
+        self.outstanding.insert(id);
+        Ok(id)
+    }
+
+    /// Wait for the response to request `id`
+    pub fn receive<Resp: DeserializeOwned>(&mut self, id: u64) -> std::io::Result<Resp> {
+        let mut span = Span::start("oprf.receive", SpanKind::Client);
+        span.set("oprf.request_id", id);
+        span.check(self.read_response(id))
+    }
+
+    fn read_response<Resp: DeserializeOwned>(&mut self, id: u64) -> std::io::Result<Resp> {
+        let frame = self.next_frame(id)?;
+        if self.version < 2 {
+            return Self::decode(frame);
+        }
+        let result: OprfResult<Resp> = Self::decode(frame)?;
+        result.map_err(Error::other)
+    }
+
+    /// Wait for the response frame to request `id`
+    fn next_frame(&mut self, id: u64) -> std::io::Result<Frame> {
+        loop {
+            if let Some(frame) = self.received.remove(&id) {
+                return Ok(frame);
+            }
+            if !self.outstanding.contains(&id) {
+                return Err(Error::new(ErrorKind::InvalidInput, "No such outstanding request"));
+            }
+            let mut frame = read_frame(&mut self.stream)?.ok_or_else(|| {
+                Error::new(ErrorKind::UnexpectedEof, "Enclave closed the connection")
+            })?;
+            // Frames are opened in the order they arrive, which their nonces follow
+            if let Some(channel) = &mut self.channel {
+                channel
+                    .open(&mut frame)
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            }
+            if let Some(key) = &self.signing_key {
+                verify_response(key, &mut frame).map_err(|e| {
+                    Error::new(ErrorKind::InvalidData, format!("Response signature: {}", e))
+                })?;
+            }
+            if !self.outstanding.remove(&frame.request_id) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Response to unknown request {}", frame.request_id),
+                ));
+            }
+            self.received.insert(frame.request_id, frame);
+        }
+    }
+
+    /// Decode a response payload in the wire format of its frame
+    fn decode<T: DeserializeOwned>(frame: Frame) -> std::io::Result<T> {
+        frame
+            .format
+            .decode(&frame.payload)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    /// Send a request and wait for its response
+    pub fn request<Resp: DeserializeOwned>(&mut self, request: &Message) -> std::io::Result<Resp> {
+        let id = self.send(request)?;
+        self.receive(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oprf_common::duplex::DuplexStream;
+    use oprf_common::{
+        CiphersuiteId, ErrorCode, ErrorResponse, GetPublicKeyRequest, HealthResponse,
+        PublicKeyResponse, DEFAULT_KEY_ID,
+    };
+    use oprf_server::testing::MockEnclave;
+
+    /// A connection to `mock` over its attested channel
+    fn open(mock: &MockEnclave) -> Connection<DuplexStream> {
+        let mut policy = Policy::default();
+        policy.mock_signing_key = Some(mock.mock_signing_key().clone());
+        let stream = mock.connect().unwrap();
+        Connection::open(stream, WireFormat::Cbor, PointEncoding::Compressed, &policy).unwrap()
+    }
+
+    /// Relay the parent's `Hello` on `parent` to `mock` and the answer back,
+    /// each edited on the way as a man in the middle could
+    fn relay(
+        mock: &MockEnclave,
+        mut parent: DuplexStream,
+        edit_hello: impl FnOnce(&mut HelloRequest) + Send + 'static,
+        edit_answer: impl FnOnce(&mut HelloResponse) + Send + 'static,
+    ) {
+        let mut enclave = mock.connect().unwrap();
+        std::thread::spawn(move || {
+            let frame = read_frame(&mut parent).unwrap().unwrap();
+            let Message::Hello(mut hello) = frame.format.decode(&frame.payload).unwrap() else {
+                panic!("expected a Hello");
+            };
+            edit_hello(&mut hello);
+            let payload = frame.format.encode(&Message::Hello(hello)).unwrap();
+            write_frame(&mut enclave, frame.request_id, frame.format, &payload).unwrap();
+
+            let frame = read_frame(&mut enclave).unwrap().unwrap();
+            let mut answer: HelloResponse = frame.format.decode(&frame.payload).unwrap();
+            edit_answer(&mut answer);
+            let payload = frame.format.encode(&answer).unwrap();
+            write_frame(&mut parent, frame.request_id, frame.format, &payload).unwrap();
+        });
+    }
+
+    /// Relay with only the `kept` versions offered to the enclave
+    fn strip_versions(mock: &MockEnclave, parent: DuplexStream, kept: Vec<u32>) {
+        relay(mock, parent, |hello| hello.versions = kept, |_| {});
+    }
+
+    fn public_key(ciphersuite: CiphersuiteId, key_id: &str) -> Message {
+        Message::GetPublicKey(GetPublicKeyRequest {
+            ciphersuite,
+            key_id: key_id.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_requests_share_a_connection() {
+        let mock = MockEnclave::new().unwrap();
+        let mut connection = open(&mock);
+        assert_eq!(connection.version(), *SUPPORTED_PROTOCOL_VERSIONS.iter().max().unwrap());
+
+        let request = public_key(CiphersuiteId::P256Sha256, DEFAULT_KEY_ID);
+        let first: PublicKeyResponse = connection.request(&request).unwrap();
+        let second: PublicKeyResponse = connection.request(&request).unwrap();
+        assert_eq!(first.public_key, second.public_key);
+        let health: HealthResponse = connection.request(&Message::Health).unwrap();
+        assert!(health.key_count > 0);
+    }
+
+    #[test]
+    fn test_pipelined_responses_awaited_in_any_order() {
+        let mock = MockEnclave::new().unwrap();
+        let mut connection = open(&mock);
+
+        let p256 = connection.send(&public_key(CiphersuiteId::P256Sha256, DEFAULT_KEY_ID)).unwrap();
+        let health = connection.send(&Message::Health).unwrap();
+        let bn254 =
+            connection.send(&public_key(CiphersuiteId::Bn254Sha256, DEFAULT_KEY_ID)).unwrap();
+        assert!(p256 < health && health < bn254);
+
+        // Awaiting the last reads the others ahead and keeps them
+        let bn254: PublicKeyResponse = connection.receive(bn254).unwrap();
+        let p256: PublicKeyResponse = connection.receive(p256).unwrap();
+        assert_ne!(bn254.public_key, p256.public_key);
+        let _: HealthResponse = connection.receive(health).unwrap();
+
+        // Each response is read once
+        let error = connection.receive::<HealthResponse>(health).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_refused_request_leaves_connection_usable() {
+        let mock = MockEnclave::new().unwrap();
+        let mut connection = open(&mock);
+
+        let error = connection
+            .request::<PublicKeyResponse>(&public_key(CiphersuiteId::P256Sha256, "no such key"))
+            .unwrap_err();
+        let refusal = error.get_ref().and_then(|e| e.downcast_ref::<ErrorResponse>()).unwrap();
+        assert_eq!(refusal.code, ErrorCode::BadRequest);
+
+        let _: HealthResponse = connection.request(&Message::Health).unwrap();
+    }
+
+    #[test]
+    fn test_downgrade_refused() {
+        let mock = MockEnclave::new().unwrap();
+        let mut policy = Policy::default();
+        policy.mock_signing_key = Some(mock.mock_signing_key().clone());
+
+        // Below the minimum, which is not even offered
+        let (stream, relay) = oprf_common::duplex::pair();
+        strip_versions(&mock, relay, vec![2]);
+        let result = Connection::open(stream, WireFormat::Cbor, PointEncoding::Compressed, &policy);
+        let error = result.err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("below the minimum"), "{}", error);
+
+        // Above it, the attestation shows the versions were tampered with
+        policy.min_protocol_version = 3;
+        let (stream, relay) = oprf_common::duplex::pair();
+        strip_versions(&mock, relay, vec![3]);
+        let result = Connection::open(stream, WireFormat::Cbor, PointEncoding::Compressed, &policy);
+        let error = result.err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("User data mismatch"), "{}", error);
+
+        let stream = mock.connect().unwrap();
+        let connection =
+            Connection::open(stream, WireFormat::Cbor, PointEncoding::Compressed, &policy).unwrap();
+        assert_eq!(connection.version(), oprf_common::PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn test_signing_key_required() {
+        let mock = MockEnclave::new().unwrap();
+        let mut policy = Policy::default();
+        policy.mock_signing_key = Some(mock.mock_signing_key().clone());
+        let open = |stream| {
+            let result =
+                Connection::open(stream, WireFormat::Cbor, PointEncoding::Compressed, &policy);
+            result.err().unwrap().to_string()
+        };
+
+        // Dropped from a version 4 answer
+        let (stream, relay_end) = oprf_common::duplex::pair();
+        relay(&mock, relay_end, |_| {}, |answer| answer.signing_key = None);
+        assert!(open(stream).contains("no signing key"));
+
+        // Or dropped along with the version that requires it
+        let (stream, relay_end) = oprf_common::duplex::pair();
+        relay(&mock, relay_end, |_| {}, |answer| {
+            answer.version = 3;
+            answer.signing_key = None;
+        });
+        assert!(open(stream).contains("below the minimum"));
+    }
+
+    #[test]
+    fn test_unattested_enclave_refused() {
+        let mock = MockEnclave::new().unwrap();
+        let stream = mock.connect().unwrap();
+        let result = Connection::open(
+            stream,
+            WireFormat::Cbor,
+            PointEncoding::Compressed,
+            &Policy::default(),
+        );
+        assert_eq!(result.err().unwrap().kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,153 @@
+//! gRPC front-end to the enclave.
+//!
+//! Serves the `Oprf` service of `proto/oprf.proto` on tonic, see
+//! [`oprf_common::grpc`]: `Evaluate`, `BatchEvaluate`, `GetPublicKey` and
+//! `GetAttestation` are forwarded as the [`Message`] they mirror. As over
+//! HTTP and JSON-RPC, responses are passed through unverified. A request
+//! the enclave refuses fails with the status of its error code, with the
+//! [`ErrorResponse`] in the status details, and one that cannot reach the
+//! enclave fails with `UNAVAILABLE`. Calls go to the enclaves holding their
+//! key, see [`crate::router`].
+
+use crate::reload::Upstream;
+use oprf_common::grpc::Backend;
+use oprf_common::{ErrorResponse, Message};
+use serde::de::DeserializeOwned;
+use std::net::TcpListener;
+use std::sync::Arc;
+
+/// Forwards every call through the router of `upstream` in force as it
+/// arrives
+struct Proxy {
+    upstream: Arc<Upstream>,
+}
+
+impl Backend for Proxy {
+    fn request<Resp: DeserializeOwned>(&self, message: &Message) -> std::io::Result<Resp> {
+        let result = self.upstream.router().request::<Resp>(message);
+        if let Err(error) = &result {
+            if !error.get_ref().is_some_and(|e| e.is::<ErrorResponse>()) {
+                println!("[Parent] gRPC call failed at the enclave: {}", error);
+            }
+        }
+        result
+    }
+}
+
+/// Serve the gRPC service on `listener` until the process is stopped,
+/// reaching the enclave through `upstream`
+pub fn serve(
+    listener: TcpListener,
+    upstream: Arc<Upstream>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("[Parent] Serving gRPC on {}", listener.local_addr()?);
+    oprf_common::grpc::serve(listener, Proxy { upstream })?;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "local", not(feature = "nitro")))]
+mod tests {
+    use super::*;
+    use crate::client::tests::{address, builder, closed_address, enclave};
+    use crate::client::OprfClient;
+    use crate::router::Router;
+    use oprf_common::grpc::ProtoCodec;
+    use oprf_common::proto::ProtoMessage;
+    use oprf_common::{
+        blind, sha256_hex, AttestationResponse, CiphersuiteId, ErrorCode, GetAttestationRequest,
+        GetPublicKeyRequest, OprfMode, OprfRequest, OprfResponse, P256Sha256, PublicKeyResponse,
+    };
+    use rand::rngs::OsRng;
+    use std::collections::BTreeMap;
+    use std::net::SocketAddr;
+    use tonic::codegen::http::uri::PathAndQuery;
+    use tonic::transport::Endpoint;
+    use tonic::{Code, Status};
+
+    /// Address of the gRPC service forwarding through `client`
+    fn start(client: OprfClient) -> SocketAddr {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let upstream = Upstream::new(Router::new(client, BTreeMap::new()));
+        std::thread::spawn(move || serve(listener, upstream).unwrap());
+        address
+    }
+
+    /// Answer of the service at `address` to `request` on `method`
+    fn call<Req, Resp>(
+        address: SocketAddr,
+        method: &str,
+        request: Req,
+    ) -> Result<Resp, Box<Status>>
+    where
+        Req: ProtoMessage + Send + Sync + 'static,
+        Resp: ProtoMessage + Send + Sync + 'static,
+    {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let endpoint = Endpoint::from_shared(format!("http://{}", address)).unwrap();
+            let mut grpc = tonic::client::Grpc::new(endpoint.connect().await.unwrap());
+            grpc.ready().await.unwrap();
+            let path = PathAndQuery::try_from(format!("/oprf.v1.Oprf/{}", method)).unwrap();
+            let codec = ProtoCodec::<Req, Resp>::default();
+            let response = grpc.unary(tonic::Request::new(request), path, codec).await;
+            response.map(tonic::Response::into_inner).map_err(Box::new)
+        })
+    }
+
+    #[test]
+    fn test_grpc_forwarded() {
+        let (enclave, policy) = enclave();
+        let address = start(builder(address(&enclave), &policy).build().unwrap());
+
+        let request = GetPublicKeyRequest {
+            ciphersuite: CiphersuiteId::P256Sha256,
+            key_id: "app".to_string(),
+        };
+        let public_key: PublicKeyResponse = call(address, "GetPublicKey", request).unwrap();
+        assert_eq!(public_key.key_id, "app");
+
+        let (_, blinded) =
+            blind::<P256Sha256, _>(b"alice@example.com", OprfMode::Voprf, &mut OsRng);
+        let blinded_query = blinded.to_bytes().unwrap();
+        let request = OprfRequest {
+            query_hash: sha256_hex(&blinded_query),
+            blinded_query,
+            mode: OprfMode::Voprf,
+            ciphersuite: CiphersuiteId::P256Sha256,
+            epoch: None,
+            key_id: "app".to_string(),
+            force_fresh: false,
+            client_nonce: None,
+            client_id: None,
+            request_id: Some("grpc-1".to_string()),
+        };
+        let response: OprfResponse = call(address, "Evaluate", request).unwrap();
+        assert_eq!(response.public_key, public_key.public_key);
+        assert_eq!(response.request_id.as_deref(), Some("grpc-1"));
+        assert!(response.proof.is_some());
+
+        // Refused by the enclave
+        let request = GetAttestationRequest {
+            ciphersuite: CiphersuiteId::P256Sha256,
+            key_id: "app".to_string(),
+            nonce: vec![0; 65],
+        };
+        let refused = call::<_, AttestationResponse>(address, "GetAttestation", request);
+        let refused = refused.unwrap_err();
+        assert_eq!(refused.code(), Code::InvalidArgument);
+        let error = ErrorResponse::decode(refused.details()).unwrap();
+        assert_eq!(error.code, ErrorCode::BadRequest);
+    }
+
+    #[test]
+    fn test_grpc_unreachable_enclave() {
+        let address = start(OprfClient::builder().address(closed_address()).build().unwrap());
+        let request = GetPublicKeyRequest {
+            ciphersuite: CiphersuiteId::P256Sha256,
+            key_id: "app".to_string(),
+        };
+        let error = call::<_, PublicKeyResponse>(address, "GetPublicKey", request).unwrap_err();
+        assert_eq!(error.code(), Code::Unavailable);
+    }
+}
@@ -0,0 +1,595 @@
+//! HTTP/REST front-end to the enclave.
+//!
+//! Serves JSON over HTTP/1.1 on axum for web services that do not speak the
+//! framed protocol:
+//!
+//! - `POST /v1/evaluate` with an [`OprfRequest`] body, answered with an
+//!   [`OprfResponse`]
+//! - `GET /v1/public-key?suite=<name>&key_id=<id>`, answered with a
+//!   [`PublicKeyResponse`]
+//! - `GET /v1/attestation?suite=<name>&key_id=<id>&nonce=<hex>`, answered
+//!   with an [`AttestationResponse`]
+//! - `GET /healthz`, answered with the enclave's [`HealthResponse`], or
+//!   502 if the enclave cannot be reached
+//! - `GET /readyz`, the same but answered with 503 while the enclave cannot
+//!   attest
+//! - `GET /openapi.json`, answered with the OpenAPI 3.0 description of
+//!   these endpoints, `parent/openapi.json`
+//! - `GET /metrics`, answered with the usage of the API keys in the
+//!   Prometheus text format
+//!
+//! With API keys configured, the `/v1/` endpoints answer 401 unless the
+//! request carries a known key, and `/v1/evaluate` answers 429 once its
+//! quota is used up; see [`crate::quota`].
+//!
+//! Every HTTP request is forwarded over a pooled connection to the enclave,
+//! and sent again over a new one if that breaks before the response, as the
+//! retry policy allows (see [`crate::client::OprfClient::request`]). It goes
+//! to the enclaves holding its key, see [`crate::router`].
+//! Responses are passed through unverified: the client blinds its inputs and
+//! checks proofs and attestations itself, as the parent does. A refused
+//! request is answered with the enclave's [`ErrorResponse`] and a matching
+//! status code. The router and spans are synchronous, so every request is
+//! answered on a blocking thread of the runtime.
+//!
+//! An `X-Request-Id` header tags an evaluation whose body has no
+//! `request_id` of its own.
+//!
+//! With tracing configured, each request is traced as a server span under
+//! the span of its `traceparent` header, if it has a valid one; see
+//! [`crate::trace`].
+
+use crate::quota::ApiKeys;
+use crate::reload::Upstream;
+use crate::router::Router;
+use crate::trace::{self, Span, SpanContext, SpanKind};
+use axum::body::Bytes;
+use axum::extract::rejection::BytesRejection;
+use axum::extract::{DefaultBodyLimit, State};
+use axum::http::header::{CONTENT_TYPE, WWW_AUTHENTICATE};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use oprf_common::{
+    AttestationResponse, CiphersuiteId, ErrorCode, ErrorResponse, GetAttestationRequest,
+    GetPublicKeyRequest, HealthResponse, Message, OprfError, OprfRequest, OprfResponse,
+    PublicKeyResponse, DEFAULT_KEY_ID,
+};
+use serde::de::DeserializeOwned;
+use std::net::TcpListener;
+use std::sync::Arc;
+
+/// Largest request body accepted, enough for any single evaluation
+const MAX_BODY_LEN: usize = 64 * 1024;
+/// OpenAPI document of the endpoints, kept in step with [`app`] by hand
+const OPENAPI: &str = include_str!("../openapi.json");
+const JSON: &str = "application/json";
+/// Content type of the Prometheus text exposition format
+const PROMETHEUS: &str = "text/plain; version=0.0.4";
+
+/// HTTP response: status code and body, JSON unless it is the metrics
+#[derive(Debug)]
+struct Reply {
+    status: StatusCode,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Reply {
+    fn json(status: StatusCode, body: String) -> Self {
+        Self {
+            status,
+            content_type: JSON,
+            body,
+        }
+    }
+
+    /// JSON body `{"message": message}`
+    fn message(status: StatusCode, message: &str) -> Self {
+        Self::json(status, serde_json::json!({ "message": message }).to_string())
+    }
+
+    fn ok<T: serde::Serialize>(value: &T) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => Self::json(StatusCode::OK, body),
+            Err(e) => Self::error(ErrorResponse::from(e.to_string())),
+        }
+    }
+
+    fn error(error: ErrorResponse) -> Self {
+        let status = match error.code {
+            ErrorCode::BadPoint | ErrorCode::HashMismatch | ErrorCode::BadRequest => {
+                StatusCode::BAD_REQUEST
+            }
+            ErrorCode::Unauthorized => StatusCode::FORBIDDEN,
+            ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self::json(status, serde_json::to_string(&error).unwrap_or_default())
+    }
+
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self::error(ErrorResponse::new(ErrorCode::BadRequest, message))
+    }
+
+    /// The request carries no API key, or one that is not admitted
+    fn unauthenticated(message: &str) -> Self {
+        let mut reply = Self::error(ErrorResponse::new(ErrorCode::Unauthorized, message));
+        reply.status = StatusCode::UNAUTHORIZED;
+        reply
+    }
+
+    /// The enclave refused the request, or could not be reached at all
+    fn enclave_error(error: std::io::Error) -> Self {
+        if let Some(response) = error.get_ref().and_then(|e| e.downcast_ref::<ErrorResponse>()) {
+            return Self::error(response.clone());
+        }
+        println!("[Parent] HTTP request failed at the enclave: {}", error);
+        Self::message(StatusCode::BAD_GATEWAY, &error.to_string())
+    }
+}
+
+impl IntoResponse for Reply {
+    fn into_response(self) -> Response {
+        let mut response =
+            (self.status, [(CONTENT_TYPE, HeaderValue::from_static(self.content_type))], self.body)
+                .into_response();
+        if self.status == StatusCode::UNAUTHORIZED {
+            response.headers_mut().insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
+
+/// What the handlers read of a request besides its body
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    /// Span of the caller, from a valid `traceparent` header
+    traceparent: Option<SpanContext>,
+    /// Value of the `X-Request-Id` header
+    request_id: Option<String>,
+    /// API key of an `Authorization: Bearer` or `X-Api-Key` header
+    api_key: Option<String>,
+}
+
+impl Request {
+    fn from_parts(parts: &Parts) -> Result<Self, Reply> {
+        let query = parts
+            .uri
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                Ok((percent_decode(key)?, percent_decode(value)?))
+            })
+            .collect::<Result<_, Reply>>()?;
+        let header = |name| header(&parts.headers, name);
+        let bearer = header("authorization").and_then(|value| match value.split_once(' ') {
+            Some(("Bearer" | "bearer", key)) => Some(key.trim()),
+            _ => None,
+        });
+        Ok(Self {
+            method: parts.method.to_string(),
+            path: parts.uri.path().to_string(),
+            query,
+            // An invalid traceparent is ignored, as W3C Trace Context asks
+            traceparent: header("traceparent")
+                .and_then(|value| SpanContext::from_traceparent(value).ok()),
+            request_id: header("x-request-id").map(str::to_string),
+            api_key: header("x-api-key").or(bearer).map(str::to_string),
+        })
+    }
+
+    /// Value of query parameter `name`
+    fn param(&self, name: &str) -> Option<&str> {
+        self.query.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+}
+
+/// Trimmed value of header `name`, if it is text
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok()).map(str::trim)
+}
+
+/// Decode `%XX` escapes and `+` in a query component
+fn percent_decode(s: &str) -> Result<String, Reply> {
+    let mut decoded = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex: Vec<u8> = bytes.by_ref().take(2).collect();
+                let byte = std::str::from_utf8(&hex)
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .filter(|_| hex.len() == 2)
+                    .ok_or_else(|| Reply::bad_request("Invalid percent escape in query"))?;
+                decoded.push(byte);
+            }
+            _ => decoded.push(b),
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| Reply::bad_request("Query is not UTF-8"))
+}
+
+/// Forward `message` to the enclave and reply with its JSON response
+fn forward<Resp>(message: &Message, upstream: &Router) -> Reply
+where
+    Resp: DeserializeOwned + serde::Serialize,
+{
+    match upstream.request::<Resp>(message) {
+        Ok(response) => Reply::ok(&response),
+        Err(e) => Reply::enclave_error(e),
+    }
+}
+
+/// `suite` and `key_id` query parameters, defaulting like the CLI
+fn key_params(request: &Request) -> Result<(CiphersuiteId, String), Reply> {
+    let ciphersuite = match request.param("suite") {
+        Some(suite) => suite.parse().map_err(|e: OprfError| Reply::bad_request(e.to_string()))?,
+        None => CiphersuiteId::default(),
+    };
+    let key_id = request.param("key_id").unwrap_or(DEFAULT_KEY_ID).to_string();
+    Ok((ciphersuite, key_id))
+}
+
+/// Index of the API key of a `/v1/` request, `None` if keys are not
+/// required
+fn authenticate(request: &Request, api_keys: Option<&ApiKeys>) -> Result<Option<usize>, Reply> {
+    let Some(api_keys) = api_keys.filter(|_| request.path.starts_with("/v1/")) else {
+        return Ok(None);
+    };
+    let key = request.api_key.as_deref().ok_or_else(|| Reply::unauthenticated("Missing API key"))?;
+    let index = api_keys
+        .authenticate(key)
+        .ok_or_else(|| Reply::unauthenticated("Unknown API key"))?;
+    Ok(Some(index))
+}
+
+/// State of the handlers
+#[derive(Clone)]
+struct Api {
+    upstream: Arc<Upstream>,
+    api_keys: Option<Arc<ApiKeys>>,
+}
+
+impl Api {
+    /// Authenticate the request of `parts` and answer it with `handler` on a
+    /// blocking thread, in a server span under the caller's. `handler` gets
+    /// the router in force and, if keys are required, the API keys and the
+    /// index of the caller's.
+    async fn serve<F>(&self, parts: Parts, handler: F) -> Reply
+    where
+        F: FnOnce(&Request, &Router, Option<(&ApiKeys, usize)>) -> Result<Reply, Reply>
+            + Send
+            + 'static,
+    {
+        let upstream = self.upstream.router();
+        let api_keys = self.api_keys.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            let request = Request::from_parts(&parts);
+            let (name, parent) = match &request {
+                Ok(request) => {
+                    (format!("{} {}", request.method, request.path), request.traceparent)
+                }
+                Err(_) => ("HTTP".to_string(), None),
+            };
+            let mut span = Span::child_of(name, SpanKind::Server, parent);
+            let reply = request
+                .and_then(|request| {
+                    println!("[Parent] HTTP {} {}", request.method, request.path);
+                    let key = authenticate(&request, api_keys.as_deref())?;
+                    handler(&request, &upstream, api_keys.as_deref().zip(key))
+                })
+                .unwrap_or_else(|reply| reply);
+            span.set("http.response.status_code", u32::from(reply.status.as_u16()));
+            if reply.status.is_server_error() {
+                span.fail(reply.status.canonical_reason().unwrap_or("Server error"));
+            }
+            drop(span);
+            trace::flush();
+            reply
+        });
+        task.await.unwrap_or_else(|e| {
+            Reply::message(StatusCode::INTERNAL_SERVER_ERROR, &format!("Handler failed: {}", e))
+        })
+    }
+}
+
+async fn evaluate(
+    State(api): State<Api>,
+    parts: Parts,
+    body: Result<Bytes, BytesRejection>,
+) -> Reply {
+    let body = match body {
+        Ok(body) => body,
+        Err(rejection) => return Reply::message(rejection.status(), &rejection.body_text()),
+    };
+    api.serve(
+        parts,
+        move |request, upstream, key| {
+            let mut evaluation: OprfRequest = serde_json::from_slice(&body)
+                .map_err(|e| Reply::bad_request(format!("Invalid request body: {}", e)))?;
+            if evaluation.request_id.is_none() {
+                evaluation.request_id = request.request_id.clone();
+            }
+            if let Some((api_keys, index)) = key {
+                api_keys.charge(index, 1).map_err(Reply::error)?;
+            }
+            Ok(forward::<OprfResponse>(&Message::Evaluate(evaluation), upstream))
+        },
+    )
+    .await
+}
+
+async fn public_key(State(api): State<Api>, parts: Parts) -> Reply {
+    api.serve(
+        parts,
+        |request, upstream, _| {
+            let (ciphersuite, key_id) = key_params(request)?;
+            let message = Message::GetPublicKey(GetPublicKeyRequest {
+                ciphersuite,
+                key_id,
+            });
+            Ok(forward::<PublicKeyResponse>(&message, upstream))
+        },
+    )
+    .await
+}
+
+async fn attestation(State(api): State<Api>, parts: Parts) -> Reply {
+    api.serve(
+        parts,
+        |request, upstream, _| {
+            let (ciphersuite, key_id) = key_params(request)?;
+            let nonce = request.param("nonce").ok_or_else(|| Reply::bad_request("Missing nonce"))?;
+            let nonce =
+                hex::decode(nonce).map_err(|e| Reply::bad_request(format!("Bad nonce: {}", e)))?;
+            let message = Message::GetAttestation(GetAttestationRequest {
+                ciphersuite,
+                key_id,
+                nonce,
+            });
+            Ok(forward::<AttestationResponse>(&message, upstream))
+        },
+    )
+    .await
+}
+
+/// `/healthz`, and `/readyz`, which fails while the enclave cannot attest
+async fn health(State(api): State<Api>, parts: Parts) -> Reply {
+    api.serve(
+        parts,
+        |request, upstream, _| {
+            match upstream.request::<HealthResponse>(&Message::Health) {
+                Ok(health) if request.path == "/readyz" && !health.is_ready() => {
+                    let mut reply = Reply::ok(&health);
+                    reply.status = StatusCode::SERVICE_UNAVAILABLE;
+                    Ok(reply)
+                }
+                Ok(health) => Ok(Reply::ok(&health)),
+                Err(e) => Ok(Reply::enclave_error(e)),
+            }
+        },
+    )
+    .await
+}
+
+async fn openapi(State(api): State<Api>, parts: Parts) -> Reply {
+    api.serve(parts, |_, _, _| Ok(Reply::json(StatusCode::OK, OPENAPI.to_string()))).await
+}
+
+async fn metrics(State(api): State<Api>, parts: Parts) -> Reply {
+    let body = api.api_keys.as_deref().map(ApiKeys::metrics).unwrap_or_default();
+    api.serve(
+        parts,
+        move |_, _, _| {
+            Ok(Reply {
+                status: StatusCode::OK,
+                content_type: PROMETHEUS,
+                body,
+            })
+        },
+    )
+    .await
+}
+
+/// Routes of the API, answering through `api`
+fn app(api: Api) -> axum::Router {
+    axum::Router::new()
+        .route("/v1/evaluate", post(evaluate))
+        .route("/v1/public-key", get(public_key))
+        .route("/v1/attestation", get(attestation))
+        .route("/healthz", get(health))
+        .route("/readyz", get(health))
+        .route("/openapi.json", get(openapi))
+        .route("/metrics", get(metrics))
+        .fallback(|| async { Reply::message(StatusCode::NOT_FOUND, "Not found") })
+        .method_not_allowed_fallback(|| async {
+            Reply::message(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed")
+        })
+        .layer(DefaultBodyLimit::max(MAX_BODY_LEN))
+        .with_state(api)
+}
+
+/// Serve the HTTP API on `listener` until the process is stopped, reaching the
+/// enclave through the router of `upstream` in force as each request arrives,
+/// and requiring one of `api_keys` if given
+pub fn serve(
+    listener: TcpListener,
+    upstream: Arc<Upstream>,
+    api_keys: Option<Arc<ApiKeys>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("[Parent] Serving HTTP API on {}", listener.local_addr()?);
+    listener.set_nonblocking(true)?;
+    let app = app(Api { upstream, api_keys });
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        axum::serve(listener, app).await
+    })?;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "local", not(feature = "nitro")))]
+mod tests {
+    use super::*;
+    use crate::client::tests::{address, builder, closed_address, enclave};
+    use crate::client::OprfClient;
+    use oprf_common::sha256_hex;
+    use std::collections::BTreeMap;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+
+    /// Address of the API forwarding through `client`, requiring one of
+    /// `api_keys` if given
+    fn start(client: OprfClient, api_keys: Option<ApiKeys>) -> SocketAddr {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let upstream = Upstream::new(Router::new(client, BTreeMap::new()));
+        std::thread::spawn(move || serve(listener, upstream, api_keys.map(Arc::new)).unwrap());
+        address
+    }
+
+    /// Status and body of the answer to `method target` with `headers` and
+    /// `body`
+    fn send(
+        address: SocketAddr,
+        method: &str,
+        target: &str,
+        headers: &[&str],
+        body: &[u8],
+    ) -> (u16, String) {
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n",
+            method,
+            target,
+            body.len()
+        );
+        for header in headers {
+            request.push_str(header);
+            request.push_str("\r\n");
+        }
+        request.push_str("\r\n");
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(&[request.as_bytes(), body].concat()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.split(' ').nth(1).unwrap().parse().unwrap(), body.to_string())
+    }
+
+    fn get(address: SocketAddr, target: &str) -> (u16, String) {
+        send(address, "GET", target, &[], b"")
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%20b+c%2Fd").unwrap(), "a b c/d");
+        for invalid in ["%2", "%zz", "%ff"] {
+            assert_eq!(percent_decode(invalid).unwrap_err().status, 400, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_request_from_parts() {
+        let (parts, _) = axum::http::Request::post("/v1/evaluate?suite=p256&key_id=a%2Eb")
+            .header("X-Request-Id", "req-1 ")
+            .header("Authorization", "Bearer k1")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let request = Request::from_parts(&parts).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/v1/evaluate");
+        assert_eq!(request.param("key_id"), Some("a.b"));
+        assert_eq!(request.request_id.as_deref(), Some("req-1"));
+        assert_eq!(request.api_key.as_deref(), Some("k1"));
+
+        let (parts, _) = axum::http::Request::get("/healthz")
+            .header("X-Api-Key", "k2")
+            .body(())
+            .unwrap()
+            .into_parts();
+        assert_eq!(Request::from_parts(&parts).unwrap().api_key.as_deref(), Some("k2"));
+        let (parts, _) = axum::http::Request::get("/v1/public-key?suite=%zz")
+            .body(())
+            .unwrap()
+            .into_parts();
+        assert_eq!(Request::from_parts(&parts).err().unwrap().status, 400);
+    }
+
+    #[test]
+    fn test_routes() {
+        let (enclave, policy) = enclave();
+        let api = start(builder(address(&enclave), &policy).build().unwrap(), None);
+
+        let (status, public_key) = get(api, "/v1/public-key?suite=p256");
+        assert_eq!(status, 200);
+        let public_key: PublicKeyResponse = serde_json::from_str(&public_key).unwrap();
+        let (status, attestation) = get(api, "/v1/attestation?suite=p256&nonce=0102");
+        assert_eq!(status, 200);
+        let attestation: AttestationResponse = serde_json::from_str(&attestation).unwrap();
+        assert_eq!(attestation.public_key, public_key.public_key);
+        assert_eq!(get(api, "/healthz").0, 200);
+        assert_eq!(get(api, "/readyz").0, 200);
+        let (_, openapi) = get(api, "/openapi.json");
+        serde_json::from_str::<serde_json::Value>(&openapi).unwrap();
+
+        // Refusals of the parent and of the enclave
+        assert_eq!(get(api, "/v1/attestation").0, 400);
+        assert_eq!(get(api, "/v1/public-key?suite=p384").0, 400);
+        let (status, refused) = get(api, "/v1/public-key?key_id=no+such+key");
+        assert_eq!(status, 400);
+        serde_json::from_str::<ErrorResponse>(&refused).unwrap();
+        assert_eq!(send(api, "POST", "/v1/public-key", &[], b"").0, 405);
+        assert_eq!(get(api, "/v2/evaluate").0, 404);
+        let too_large = vec![b' '; MAX_BODY_LEN + 1];
+        assert_eq!(send(api, "POST", "/v1/evaluate", &[], &too_large).0, 413);
+    }
+
+    #[test]
+    fn test_unreachable_enclave() {
+        let api = start(OprfClient::builder().address(closed_address()).build().unwrap(), None);
+        assert_eq!(get(api, "/healthz").0, 502);
+        assert_eq!(get(api, "/v1/public-key").0, 502);
+    }
+
+    #[test]
+    fn test_api_keys_required() {
+        let path = std::env::temp_dir().join(format!("oprf-http-keys-{}", std::process::id()));
+        let keys = format!(
+            r#"{{"keys": [{{"name": "a", "key_sha256": "{}", "daily": 1}}]}}"#,
+            sha256_hex(b"secret")
+        );
+        std::fs::write(&path, keys).unwrap();
+        let api_keys = ApiKeys::load(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let client = OprfClient::builder().address(closed_address()).build().unwrap();
+        let api = start(client, Some(api_keys.unwrap()));
+
+        assert_eq!(get(api, "/v1/public-key").0, 401);
+        assert_eq!(send(api, "GET", "/v1/public-key", &["X-Api-Key: wrong"], b"").0, 401);
+        // A known key passes on to the enclave
+        let key = ["Authorization: Bearer secret"];
+        assert_eq!(send(api, "GET", "/v1/public-key", &key, b"").0, 502);
+
+        // Evaluations are charged against the quota before they are forwarded
+        let evaluate = serde_json::to_vec(&serde_json::json!({
+            "blinded_query": [],
+            "query_hash": sha256_hex(b""),
+        }))
+        .unwrap();
+        assert_eq!(send(api, "POST", "/v1/evaluate", &key, &evaluate).0, 502);
+        assert_eq!(send(api, "POST", "/v1/evaluate", &key, &evaluate).0, 429);
+
+        // Outside /v1/ no key is needed
+        assert_eq!(get(api, "/openapi.json").0, 200);
+    }
+}
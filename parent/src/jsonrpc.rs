@@ -0,0 +1,382 @@
+//! JSON-RPC 2.0 front-end to the enclave.
+//!
+//! Serves JSON-RPC over TCP, one request or batch per line and one response
+//! line for each, for scripts that would rather not speak HTTP:
+//!
+//! - `oprf.evaluate` with [`OprfRequest`] params, answered with an
+//!   [`OprfResponse`]
+//! - `oprf.publicKey` with `{"suite", "key_id"}` params, both optional,
+//!   answered with a [`PublicKeyResponse`]
+//! - `oprf.attestation` with `{"suite", "key_id", "nonce": "<hex>"}` params,
+//!   answered with an [`AttestationResponse`]
+//!
+//! The `oprf.evaluate` calls of a batch that agree on everything but their
+//! blinded query are evaluated together in [`BatchOprfRequest`]s of up to
+//! [`MAX_BATCH_SIZE`] queries. Each of them is answered with the
+//! [`BatchOprfResponse`] and the `index` of its point in `evaluated_points`,
+//! since the proof and attestation cover the whole batch. As over HTTP,
+//! responses are passed through unverified, and a request the enclave
+//! refuses is answered with an error whose `data` is its [`ErrorResponse`].
+//! Calls go to the enclaves holding their key, see [`crate::router`].
+
+use crate::router::Router;
+use crate::reload::Upstream;
+use oprf_common::ct;
+use oprf_common::{
+    sha256_hex, AttestationResponse, BatchOprfRequest, BatchOprfResponse, CiphersuiteId,
+    ErrorCode, ErrorResponse, GetAttestationRequest, GetPublicKeyRequest, Message, OprfError,
+    OprfRequest, OprfResponse, PublicKeyResponse, DEFAULT_KEY_ID, MAX_BATCH_SIZE,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+
+/// Largest request line accepted, enough for a full batch of evaluations
+const MAX_LINE_LEN: usize = 1024 * 1024;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// The enclave refused the request; `data` holds its [`ErrorResponse`]
+const ENCLAVE_ERROR: i64 = -32000;
+/// The enclave could not be reached
+const ENCLAVE_UNAVAILABLE: i64 = -32001;
+
+/// JSON-RPC error object
+#[derive(Serialize, Debug, Clone)]
+struct RpcError {
+    code: i64,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<ErrorResponse>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    fn refused(error: ErrorResponse) -> Self {
+        Self {
+            code: ENCLAVE_ERROR,
+            message: error.message.clone(),
+            data: Some(error),
+        }
+    }
+
+    /// The enclave refused the request, or could not be reached at all
+    fn enclave(error: std::io::Error) -> Self {
+        if let Some(response) = error.get_ref().and_then(|e| e.downcast_ref::<ErrorResponse>()) {
+            return Self::refused(response.clone());
+        }
+        println!("[Parent] JSON-RPC call failed at the enclave: {}", error);
+        Self::new(ENCLAVE_UNAVAILABLE, error.to_string())
+    }
+}
+
+type CallResult = Result<Value, RpcError>;
+
+/// One call of a request line. Notifications have no `id` and get no
+/// response.
+struct Call {
+    id: Option<Value>,
+    method: String,
+    params: Value,
+}
+
+impl Call {
+    fn parse(value: Value) -> Result<Self, (Value, RpcError)> {
+        let Value::Object(mut object) = value else {
+            return Err((Value::Null, RpcError::new(INVALID_REQUEST, "Request is not an object")));
+        };
+        let id = object.remove("id");
+        let invalid = |message: &str| {
+            let id = id.clone().unwrap_or(Value::Null);
+            Err((id, RpcError::new(INVALID_REQUEST, message)))
+        };
+        if !matches!(&id, None | Some(Value::Null | Value::String(_) | Value::Number(_))) {
+            return invalid("id must be a string, a number or null");
+        }
+        if object.get("jsonrpc") != Some(&json!("2.0")) {
+            return invalid("jsonrpc must be \"2.0\"");
+        }
+        let method = match object.remove("method") {
+            Some(Value::String(method)) => method,
+            _ => return invalid("method must be a string"),
+        };
+        let params = match object.remove("params") {
+            None => json!({}),
+            Some(params @ Value::Object(_)) => params,
+            Some(_) => return invalid("params must be an object"),
+        };
+        Ok(Self { id, method, params })
+    }
+}
+
+/// Params of `oprf.publicKey`, as the HTTP API's query parameters
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyParams {
+    suite: Option<String>,
+    key_id: Option<String>,
+}
+
+/// Params of `oprf.attestation`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AttestationParams {
+    suite: Option<String>,
+    key_id: Option<String>,
+    nonce: String,
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params)
+        .map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid params: {}", e)))
+}
+
+/// `suite` and `key_id` params, defaulting like the CLI
+fn key(suite: Option<String>, key_id: Option<String>) -> Result<(CiphersuiteId, String), RpcError> {
+    let ciphersuite = match suite {
+        Some(suite) => suite
+            .parse()
+            .map_err(|e: OprfError| RpcError::new(INVALID_PARAMS, e.to_string()))?,
+        None => CiphersuiteId::default(),
+    };
+    Ok((ciphersuite, key_id.unwrap_or_else(|| DEFAULT_KEY_ID.to_string())))
+}
+
+/// Forward `message` to the enclave over a pooled connection, resent if it
+/// breaks, see [`crate::client::OprfClient::request`]
+fn forward<Resp>(message: &Message, upstream: &Router) -> Result<Resp, RpcError>
+where
+    Resp: DeserializeOwned,
+{
+    upstream.request::<Resp>(message).map_err(RpcError::enclave)
+}
+
+fn to_result<T: Serialize>(value: Result<T, RpcError>) -> CallResult {
+    value.and_then(|value| {
+        serde_json::to_value(value).map_err(|e| RpcError::new(ENCLAVE_UNAVAILABLE, e.to_string()))
+    })
+}
+
+/// Serve a call to any method but `oprf.evaluate`
+fn dispatch(call: Call, upstream: &Router) -> CallResult {
+    match call.method.as_str() {
+        "oprf.publicKey" => {
+            let KeyParams { suite, key_id } = params(call.params)?;
+            let (ciphersuite, key_id) = key(suite, key_id)?;
+            let message = Message::GetPublicKey(GetPublicKeyRequest {
+                ciphersuite,
+                key_id,
+            });
+            to_result(forward::<PublicKeyResponse>(&message, upstream))
+        }
+        "oprf.attestation" => {
+            let AttestationParams {
+                suite,
+                key_id,
+                nonce,
+            } = params(call.params)?;
+            let (ciphersuite, key_id) = key(suite, key_id)?;
+            let nonce = hex::decode(nonce)
+                .map_err(|e| RpcError::new(INVALID_PARAMS, format!("Bad nonce: {}", e)))?;
+            let message = Message::GetAttestation(GetAttestationRequest {
+                ciphersuite,
+                key_id,
+                nonce,
+            });
+            to_result(forward::<AttestationResponse>(&message, upstream))
+        }
+        method => Err(RpcError::new(METHOD_NOT_FOUND, format!("Unknown method {:?}", method))),
+    }
+}
+
+/// Whether two evaluations can share a batch request
+fn same_batch(a: &OprfRequest, b: &OprfRequest) -> bool {
+    (a.ciphersuite, a.mode, a.epoch, &a.key_id, a.force_fresh, &a.client_nonce, &a.client_id)
+        == (b.ciphersuite, b.mode, b.epoch, &b.key_id, b.force_fresh, &b.client_nonce, &b.client_id)
+        && a.request_id == b.request_id
+}
+
+/// Evaluate the `oprf.evaluate` calls `evaluations`, each with the index of
+/// its result slot, storing every result in `results`
+fn evaluate(
+    evaluations: Vec<(usize, OprfRequest)>,
+    upstream: &Router,
+    results: &mut [Option<CallResult>],
+) {
+    // The query hash of a batch is recomputed, so check each call's first
+    let mut groups: Vec<Vec<(usize, OprfRequest)>> = Vec::new();
+    for (slot, request) in evaluations {
+        if !ct::eq(request.query_hash.as_bytes(), sha256_hex(&request.blinded_query).as_bytes()) {
+            let error = ErrorResponse::new(ErrorCode::HashMismatch, "Query hash mismatch");
+            results[slot] = Some(Err(RpcError::refused(error)));
+            continue;
+        }
+        match groups.iter_mut().find(|group| same_batch(&group[0].1, &request)) {
+            Some(group) => group.push((slot, request)),
+            None => groups.push(vec![(slot, request)]),
+        }
+    }
+
+    for group in groups {
+        if let [(slot, request)] = group.as_slice() {
+            let message = Message::Evaluate(request.clone());
+            results[*slot] = Some(to_result(forward::<OprfResponse>(&message, upstream)));
+            continue;
+        }
+        for chunk in group.chunks(MAX_BATCH_SIZE) {
+            let first = &chunk[0].1;
+            let blinded_queries: Vec<Vec<u8>> =
+                chunk.iter().map(|(_, request)| request.blinded_query.clone()).collect();
+            let message = Message::BatchEvaluate(BatchOprfRequest {
+                query_hash: sha256_hex(&blinded_queries.concat()),
+                blinded_queries,
+                mode: first.mode,
+                ciphersuite: first.ciphersuite,
+                epoch: first.epoch,
+                key_id: first.key_id.clone(),
+                force_fresh: first.force_fresh,
+                client_nonce: first.client_nonce.clone(),
+                client_id: first.client_id.clone(),
+                request_id: first.request_id.clone(),
+            });
+            let response = to_result(forward::<BatchOprfResponse>(&message, upstream));
+            for (index, (slot, _)) in chunk.iter().enumerate() {
+                results[*slot] = Some(match &response {
+                    Ok(Value::Object(response)) => {
+                        let mut response = response.clone();
+                        response.insert("index".to_string(), json!(index));
+                        Ok(Value::Object(response))
+                    }
+                    other => other.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// Serve the calls of one request line, in order. Returns the responses to
+/// every call but notifications.
+fn handle_calls(calls: Vec<Value>, upstream: &Router) -> Vec<Value> {
+    let mut ids = Vec::with_capacity(calls.len());
+    let mut results: Vec<Option<CallResult>> = Vec::with_capacity(calls.len());
+    let mut evaluations = Vec::new();
+    for value in calls {
+        let slot = results.len();
+        match Call::parse(value) {
+            Ok(call) => {
+                ids.push(call.id.clone());
+                if call.method == "oprf.evaluate" {
+                    match params::<OprfRequest>(call.params) {
+                        Ok(request) => {
+                            evaluations.push((slot, request));
+                            results.push(None);
+                        }
+                        Err(e) => results.push(Some(Err(e))),
+                    }
+                } else {
+                    results.push(Some(dispatch(call, upstream)));
+                }
+            }
+            Err((id, e)) => {
+                ids.push(Some(id));
+                results.push(Some(Err(e)));
+            }
+        }
+    }
+    evaluate(evaluations, upstream, &mut results);
+
+    ids.into_iter()
+        .zip(results)
+        .filter_map(|(id, result)| {
+            let id = id?;
+            Some(match result.expect("Every call has a result") {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+            })
+        })
+        .collect()
+}
+
+/// Serve one request line, returning the response line if any
+fn handle_line(line: &str, upstream: &Router) -> Option<String> {
+    let error = |code, message: &str| {
+        let error = RpcError::new(code, message);
+        Some(json!({ "jsonrpc": "2.0", "id": null, "error": error }).to_string())
+    };
+    match serde_json::from_str::<Value>(line) {
+        Err(e) => error(PARSE_ERROR, &format!("Parse error: {}", e)),
+        Ok(Value::Array(calls)) if calls.is_empty() => error(INVALID_REQUEST, "Empty batch"),
+        Ok(Value::Array(calls)) => {
+            println!("[Parent] JSON-RPC batch of {} calls", calls.len());
+            let responses = handle_calls(calls, upstream);
+            (!responses.is_empty()).then(|| Value::Array(responses).to_string())
+        }
+        Ok(call) => {
+            let responses = handle_calls(vec![call], upstream);
+            responses.into_iter().next().map(|response| response.to_string())
+        }
+    }
+}
+
+/// Serve request lines on `stream` until the client closes it, each through
+/// the router of `upstream` in force as it arrives
+fn handle(stream: TcpStream, upstream: &Upstream) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    loop {
+        let mut line = Vec::new();
+        (&mut reader).take(MAX_LINE_LEN as u64 + 1).read_until(b'\n', &mut line)?;
+        if line.is_empty() {
+            return Ok(());
+        }
+        // The rest of an overlong line cannot be told from the next request
+        if line.len() > MAX_LINE_LEN {
+            let error = RpcError::new(INVALID_REQUEST, "Request line too long");
+            let response = json!({ "jsonrpc": "2.0", "id": null, "error": error });
+            return writeln!(writer, "{}", response);
+        }
+        let line = String::from_utf8_lossy(&line);
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle_line(&line, &upstream.router()) {
+            writeln!(writer, "{}", response)?;
+            writer.flush()?;
+        }
+    }
+}
+
+/// Serve JSON-RPC on `listener` until the process is stopped, reaching the
+/// enclave through `upstream`
+pub fn serve(
+    listener: TcpListener,
+    upstream: Arc<Upstream>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("[Parent] Serving JSON-RPC on {}", listener.local_addr()?);
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let upstream = Arc::clone(&upstream);
+                std::thread::spawn(move || {
+                    if let Err(e) = handle(stream, &upstream) {
+                        println!("[Parent] JSON-RPC connection failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => println!("[Parent] Failed to accept JSON-RPC connection: {}", e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,6 @@
+//! Attestation verification of the parent, shared by the `oprf-parent`
+//! binary and the C bindings of `oprf-ffi`.
+
+pub mod attestation;
+pub mod policy;
+pub mod roots;
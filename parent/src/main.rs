@@ -1,192 +1,2281 @@
-use ark_bn254::Fr;
-use ark_ff::UniformRand;
+mod admin;
+mod batch;
+mod client;
+mod connection;
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(feature = "http")]
+mod http;
+#[cfg(feature = "jsonrpc")]
+mod jsonrpc;
+mod pool;
+#[cfg(feature = "http")]
+mod quota;
+#[cfg(any(feature = "http", feature = "jsonrpc", feature = "grpc"))]
+mod reload;
+mod router;
+mod service;
+mod trace;
+mod watchdog;
+
 use oprf_common::{
-    deserialize_g1, scalar_inverse, scalar_mul, scalar_mul_generator, serialize_g1,
-    sha256_hex, AttestationDocument, OprfRequest, OprfResponse,
+    attested_user_data, blind, deserialize_g1, deserialize_g2, key_attestation_binding,
+    key_injection_binding, replication_binding, sha256_hex, unblind, verify_key_pair,
+    verify_pairing, verify_proof, AttestationResponse, AuditLogResponse, BatchOprfRequest,
+    BatchOprfResponse, BeginKeyInjectionRequest, BeginKeyInjectionResponse,
+    BeginReplicationResponse, Blind, BlindedElement, Bn254Sha256, Ciphersuite, CiphersuiteId,
+    DleqProof, EvaluatedElement, EvaluationUserData, ExportKeysRequest, ExportKeysResponse,
+    ExportReplicaRequest, ExportReplicaResponse, GetAttestationRequest, GetAuditLogRequest,
+    GetPublicKeyRequest, HealthResponse, ImportKeysRequest, ImportKeysResponse,
+    ImportReplicaRequest, ImportReplicaResponse, InjectKeyRequest, InjectKeyResponse, KmsConfig,
+    Message, OprfMode, OprfOutput, OprfRequest, OprfResponse, P256Sha256, PointEncoding,
+    ProvisionSeedRequest, ProvisionSeedResponse, PublicKeyResponse, RotateKeyRequest,
+    RotateKeyResponse, SelfTestResponse, StatsResponse, DEFAULT_KEY_ID, REPLICA_PRIMARY_LABEL,
+    REPLICA_STANDBY_LABEL,
+};
+use oprf_common::{request_tag, validate_key_id, validate_request_id};
+use oprf_common::admin::{
+    AdminCommand, EpochInfo, FlushCachesResponse, KeyListResponse, RateSetting, ReloadResponse,
+    ShutdownResponse, StatusResponse,
 };
+use oprf_common::audit::{checkpoint_binding, verify_chain};
+use oprf_common::config::{Config as ConfigFile, DEFAULT_HEARTBEAT_PORT};
+use oprf_common::ed25519::VerifyingKey;
+use oprf_common::frame::WireFormat;
+use oprf_common::redact::{sensitive, sensitive_text, set_log_sensitive};
+use oprf_common::rng::SharedRng;
+use oprf_common::selftest::report_binding;
+use oprf_common::transport::seal_to;
+use admin::{AdminAccess, AdminConnection};
+use batch::Chunking;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use client::{EnclaveAddress, OprfClient, RetryPolicy};
+use pool::PoolLimits;
+use router::Router;
+use connection::Connection;
+use oprf_parent::attestation::{verify_attestation, verify_evaluation_attestation};
+use oprf_parent::policy::{check_min_protocol_version, Policy};
+use trace::{Span, SpanContext, SpanKind};
+use watchdog::{Event, Watchdog};
+use zeroize::Zeroizing;
 use rand::rngs::OsRng;
-use std::io::{Read, Write};
+use rand::RngCore;
+use std::collections::{BTreeMap, VecDeque};
+use std::net::TcpStream;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
-#[cfg(feature = "nitro")]
-use std::os::unix::io::AsRawFd;
+/// Evaluations `bench` times by default
+const DEFAULT_BENCH_COUNT: usize = 100;
 
-const VSOCK_PORT: u32 = 5000;
-const VSOCK_CID_ENCLAVE: u32 = 16; // Default enclave CID
-const LOCAL_PORT: u16 = 5000;
+/// Where the attestation policy comes from: the `--policy` file, or the
+/// default policy, with the command-line overrides applied over it
+#[derive(Clone)]
+struct PolicySource {
+    path: Option<String>,
+    max_age_secs: Option<u64>,
+    min_protocol_version: Option<u32>,
+    mock_signing_key: Option<VerifyingKey>,
+}
+
+impl PolicySource {
+    /// Read the policy, again on every reload
+    fn load(&self) -> Result<Policy, String> {
+        let mut policy = match &self.path {
+            Some(path) => Policy::load(path)?,
+            None => Policy::default(),
+        };
+        if let Some(max_age_secs) = self.max_age_secs {
+            policy.max_age_secs = max_age_secs;
+        }
+        if let Some(version) = self.min_protocol_version {
+            check_min_protocol_version(version)?;
+            policy.min_protocol_version = version;
+        }
+        policy.mock_signing_key = self.mock_signing_key.clone();
+        Ok(policy)
+    }
+}
 
-/// Verify attestation document
-fn verify_attestation(
-    attestation: &AttestationDocument,
-    expected_user_data: &[u8],
-) -> Result<(), String> {
-    if attestation.is_mock {
-        println!("[Parent] Verifying mock attestation (local mode)");
+/// How the parent checks that the enclave evaluated with its published key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Verification {
+    /// DLEQ proof against g^k, works for every ciphersuite (default)
+    Dleq,
+    /// Pairing check against g2^k, BN254 only and needs no per-request proof
+    Pairing,
+}
 
-        // In local mode, just verify the user data matches
-        if attestation.user_data != expected_user_data {
-            return Err("User data mismatch in attestation".to_string());
+impl Verification {
+    /// OPRF mode to request: only DLEQ verification needs a proof
+    fn mode(self) -> OprfMode {
+        match self {
+            Verification::Dleq => OprfMode::Voprf,
+            Verification::Pairing => OprfMode::Oprf,
         }
+    }
+}
 
-        // Parse and display mock attestation
-        let doc: serde_json::Value = serde_json::from_slice(&attestation.document)
-            .map_err(|e| format!("Failed to parse mock attestation: {}", e))?;
+/// Options shared by every evaluation
+#[derive(Debug, Clone)]
+struct Options {
+    /// How evaluations are verified
+    verification: Verification,
+    /// Key epoch to request; the enclave's current key when absent
+    epoch: Option<u64>,
+    /// Named enclave key to evaluate under
+    key_id: String,
+    /// Ask the enclave for a newly generated attestation
+    force_fresh: bool,
+    /// Client id the enclave rate-limits evaluations by
+    client_id: Option<String>,
+    /// Leave out the log lines of every single evaluation, as `bench` does
+    quiet: bool,
+    /// Request id given by the caller; a random one otherwise
+    request_id: Option<String>,
+    /// Source of blinding factors, seeded by `--blind-seed` for
+    /// reproducible tests
+    rng: SharedRng,
+}
+
+impl Options {
+    /// Id of an evaluation request: the given request id, with `-<part>`
+    /// appended for the parts of a command making several requests, or else
+    /// a random one
+    fn request_id(&self, part: Option<usize>) -> String {
+        match (&self.request_id, part) {
+            (Some(request_id), None) => request_id.clone(),
+            (Some(request_id), Some(part)) => format!("{}-{}", request_id, part),
+            (None, _) => {
+                let mut id = [0u8; 8];
+                OsRng.fill_bytes(&mut id);
+                hex::encode(id)
+            }
+        }
+    }
+}
 
-        println!("[Parent] Mock attestation document: {}",
-                 serde_json::to_string_pretty(&doc). unwrap());
+impl std::str::FromStr for Verification {
+    type Err = String;
 
-        Ok(())
-    } else {
-        println!("[Parent] Verifying NSM attestation (Nitro mode)");
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dleq" => Ok(Verification::Dleq),
+            "pairing" => Ok(Verification::Pairing),
+            _ => Err(format!("Unknown verification method: {}", s)),
+        }
+    }
+}
+
+/// Fresh nonce binding an evaluation attestation to its request
+fn client_nonce() -> Vec<u8> {
+    let mut nonce = vec![0u8; 32];
+    OsRng.fill_bytes(&mut nonce);
+    nonce
+}
 
-        // In production, you would:
-        // 1. Verify the CBOR/COSE signature using AWS root certificate
-        // 2. Check PCR values match expected enclave image
-        // 3. Verify timestamp is recent
-        // 4. Check user_data matches expected value
+/// Check that the enclave answered with the key that was asked for
+fn check_key_id(actual: &str, expected: &str) -> Result<(), String> {
+    if actual != expected {
+        return Err(format!("Expected key id {:?}, got {:?}", expected, actual));
+    }
+    Ok(())
+}
+
+/// Check that `key_id` names a key and `endpoints` are where to find it
+fn check_shard<E>(key_id: &str, endpoints: &[E]) -> Result<(), String> {
+    validate_key_id(key_id).map_err(|e| e.to_string())?;
+    if endpoints.is_empty() {
+        return Err(format!("Shard {:?} has no endpoint", key_id));
+    }
+    Ok(())
+}
+
+/// Parse a `<key_id>=<cid>:<port>[,<cid>:<port>...]` shard
+fn parse_shard(shard: &str) -> Result<(String, Vec<EnclaveAddress>), String> {
+    let (key_id, endpoints) = shard
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid shard {:?}, expected <key_id>=<cid>:<port>", shard))?;
+    let endpoints = endpoints
+        .split(',')
+        .map(|endpoint| endpoint.parse())
+        .collect::<Result<Vec<EnclaveAddress>, _>>()?;
+    check_shard(key_id, &endpoints)?;
+    Ok((key_id.to_string(), endpoints))
+}
 
-        if attestation.user_data != expected_user_data {
-            return Err("User data mismatch in attestation".to_string());
+/// Check that the enclave answered the request that was sent
+fn check_request_id(actual: Option<&str>, expected: Option<&str>) -> Result<(), String> {
+    if actual != expected {
+        return Err(format!("Expected request id {:?}, got {:?}", expected, actual));
+    }
+    Ok(())
+}
+
+/// Prefix `error` with the id of the request it failed, so that it can be
+/// matched with the enclave's log
+fn tag_error(
+    request_id: Option<&str>,
+    error: Box<dyn std::error::Error>,
+) -> Box<dyn std::error::Error> {
+    match request_id {
+        Some(_) => format!("{}{}", request_tag(request_id), error).into(),
+        None => error,
+    }
+}
+
+/// Check that `evaluated[i] == blinded[i]^k` for the enclave key, before unblinding
+fn verify_evaluation<C: Ciphersuite>(
+    options: &Options,
+    public_key: &[u8],
+    public_key_g2: Option<&[u8]>,
+    proof: Option<&[u8]>,
+    blinded: &[BlindedElement<C>],
+    evaluated: &[EvaluatedElement<C>],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let public_key = C::deserialize_element(public_key)?;
+    let blinded: Vec<C::Element> = blinded.iter().map(|element| *element.element()).collect();
+    let evaluated: Vec<C::Element> = evaluated.iter().map(|element| *element.element()).collect();
+    match options.verification {
+        Verification::Dleq => {
+            let proof = proof.ok_or("Enclave did not return a DLEQ proof")?;
+            let proof = DleqProof::<C>::from_bytes(proof)?;
+            verify_proof::<C>(&public_key, &blinded, &evaluated, &proof)?;
+            if !options.quiet {
+                let count = evaluated.len();
+                println!("[Parent] DLEQ proof verified successfully ({} elements)", count);
+            }
         }
+        Verification::Pairing => {
+            if C::ID != CiphersuiteId::Bn254Sha256 {
+                return Err(
+                    format!("Pairing verification is not available for {}", C::IDENTIFIER).into()
+                );
+            }
+            let public_key_g2 = public_key_g2.ok_or("Enclave did not publish a G2 public key")?;
+            let public_key_g2 = deserialize_g2(public_key_g2)?;
 
-        if let Some(pcrs) = &attestation.pcrs {
-            println!("[Parent] PCR0: {}", pcrs. get(0).unwrap_or(&"N/A".to_string()));
-            println!("[Parent] PCR1: {}", pcrs.get(1).unwrap_or(&"N/A". to_string()));
-            println! ("[Parent] PCR2: {}", pcrs.get(2). unwrap_or(&"N/A".to_string()));
+            // C is BN254 here; move the points over through their encoding
+            let to_g1 = |point: &C::Element| {
+                C::serialize_element(point).and_then(|bytes| deserialize_g1(&bytes))
+            };
+            verify_key_pair(&to_g1(&public_key)?, &public_key_g2)?;
+            let blinded = blinded.iter().map(to_g1).collect::<Result<Vec<_>, _>>()?;
+            let evaluated = evaluated.iter().map(to_g1).collect::<Result<Vec<_>, _>>()?;
+            verify_pairing(&public_key_g2, &blinded, &evaluated, &mut OsRng)?;
+            if !options.quiet {
+                let count = evaluated.len();
+                println!("[Parent] Pairing check verified successfully ({} elements)", count);
+            }
         }
+    }
+    Ok(())
+}
+
+/// A blinded evaluation request awaiting the enclave's response
+struct BlindedQuery<C: Ciphersuite> {
+    /// Blinding factor b, with the input it unblinds to
+    blind: Blind<C>,
+    /// H(input)^b
+    blinded: BlindedElement<C>,
+    request: OprfRequest,
+    /// Encoding of the points of the request and its response
+    encoding: PointEncoding,
+}
 
-        // For full production verification, use aws-nitro-enclaves-attestation crate
-        // or implement COSE signature verification with AWS root CA
+/// Map `input` to a curve point and blind it into an evaluation request with
+/// points in `encoding`, tagged with `request_id` if set
+fn blind_query<C: Ciphersuite>(
+    input: &str,
+    encoding: PointEncoding,
+    request_id: Option<String>,
+    options: &Options,
+) -> Result<BlindedQuery<C>, Box<dyn std::error::Error>> {
+    // Map the input to a curve point H(input) and blind it: H(input)^b
+    let mut span = Span::start("oprf.blind", SpanKind::Internal);
+    let mode = options.verification.mode();
+    let (blind, blinded) = blind::<C, _>(input.as_bytes(), mode, &mut options.rng.clone());
+    let blinded_query_bytes = span.check(blinded.encode(encoding))?;
+    drop(span);
+
+    if !options.quiet {
+        println!("[Parent] Computed blinded query H(input)^b");
+        println!("[Parent] Blinded query: {}", sensitive(&blinded_query_bytes));
+    }
 
-        println!("[Parent] WARNING: Full attestation verification not implemented");
-        println!("[Parent] In production, verify COSE signature with AWS root CA");
+    // Create request with hash
+    let query_hash = sha256_hex(&blinded_query_bytes);
+    let request = OprfRequest {
+        blinded_query: blinded_query_bytes,
+        query_hash: query_hash.clone(),
+        mode,
+        ciphersuite: C::ID,
+        epoch: options.epoch,
+        key_id: options.key_id.clone(),
+        force_fresh: options.force_fresh,
+        client_nonce: Some(client_nonce()),
+        client_id: options.client_id.clone(),
+        request_id,
+    };
 
-        Ok(())
+    if !options.quiet {
+        println!("[Parent] Query hash: {}", query_hash);
+        if let Some(request_id) = &request.request_id {
+            println!("[Parent] Request id: {}", request_id);
+        }
     }
+    Ok(BlindedQuery { blind, blinded, request, encoding })
 }
 
-#[cfg(all(feature = "local", not(feature = "nitro")))]
-fn connect_to_enclave() -> std::io::Result<std::net::TcpStream> {
-    use std::net::TcpStream;
+/// Check the enclave's response to `query`, received over a connection of
+/// `protocol_version`, and return its evaluated point
+fn verify_response<C: Ciphersuite>(
+    client: &OprfClient,
+    query: &BlindedQuery<C>,
+    response: &OprfResponse,
+    protocol_version: u32,
+    options: &Options,
+) -> Result<EvaluatedElement<C>, Box<dyn std::error::Error>> {
+    let log = !options.quiet;
+
+    // Verify attestation over the key, evaluated point and nonce, unless the
+    // response came over the attested channel of a version 3 connection
+    check_key_id(&response.key_id, &options.key_id)?;
+    check_request_id(response.request_id.as_deref(), query.request.request_id.as_deref())?;
+    client.check_public_key(&response.public_key)?;
+    if protocol_version >= 3 {
+        if log {
+            println!("[Parent] Response authenticated by the attested channel");
+        }
+    } else {
+        let user_data = EvaluationUserData::new(
+            protocol_version,
+            &options.key_id,
+            response.epoch,
+            &response.public_key,
+            std::slice::from_ref(&response.evaluated_point),
+            query.request.client_nonce.as_deref(),
+            query.request.request_id.as_deref(),
+        );
+        verify_evaluation_attestation(client.policy(), &response.attestation, &user_data)?;
+        if log {
+            println!("[Parent] Attestation verified successfully");
+        }
+    }
 
-    println!("[Parent] Connecting to enclave at 127.0.0.1:{}", LOCAL_PORT);
-    TcpStream::connect(format! ("127.0.0.1:{}", LOCAL_PORT))
+    // Deserialize the evaluated point
+    let evaluated = EvaluatedElement::<C>::decode(&response.evaluated_point, query.encoding)?;
+    if log {
+        println!("[Parent] Evaluated point: {}", sensitive(&response.evaluated_point));
+    }
+
+    // Check the evaluation before unblinding
+    verify_evaluation::<C>(
+        options,
+        &response.public_key,
+        response.public_key_g2.as_deref(),
+        response.proof.as_deref(),
+        std::slice::from_ref(&query.blinded),
+        std::slice::from_ref(&evaluated),
+    )?;
+    Ok(evaluated)
 }
 
-#[cfg(feature = "nitro")]
-fn connect_to_enclave() -> std::io::Result<std::net::TcpStream> {
-    use nix::sys::socket::{connect, socket, AddressFamily, SockFlag, SockType, VsockAddr};
-    use std::os::unix::io::{FromRawFd, IntoRawFd};
+/// Verify the enclave's response to `query`, received over a connection of
+/// `protocol_version`, then unblind and finalize it
+fn finish<C: Ciphersuite>(
+    client: &OprfClient,
+    query: BlindedQuery<C>,
+    response: OprfResponse,
+    protocol_version: u32,
+    options: &Options,
+) -> Result<OprfOutput, Box<dyn std::error::Error>> {
+    if !options.quiet {
+        println!("[Parent] Received response from enclave (key epoch {})", response.epoch);
+    }
+    let mut span = Span::start("oprf.verify", SpanKind::Internal);
+    span.set("oprf.key_epoch", response.epoch);
+    let verified = verify_response(client, &query, &response, protocol_version, options);
+    let evaluated = span.check(verified)?;
+    drop(span);
 
-    let sock_fd = socket(
-        AddressFamily::Vsock,
-        SockType::Stream,
-        SockFlag::empty(),
-        None,
-    )
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    // Unblind, output^(1/b) = H(input)^k, and hash it together with the input
+    let mut span = Span::start("oprf.unblind", SpanKind::Internal);
+    let output = span.check(unblind(query.blind, &evaluated))?;
+    drop(span);
+    if options.quiet {
+        return Ok(output);
+    }
+    println!("[Parent] Unblinded and finalized result");
 
-    let addr = VsockAddr::new(VSOCK_CID_ENCLAVE, VSOCK_PORT);
+    println!("[Parent] ================================================");
+    println!("[Parent] OPRF OUTPUT: {}", hex::encode(output.as_bytes()));
+    println!("[Parent] ================================================");
 
-    println!("[Parent] Connecting to enclave via vsock (CID: {}, Port: {})",
-             VSOCK_CID_ENCLAVE, VSOCK_PORT);
+    // Also display the public key for reference
+    println!("[Parent] Enclave public key (g^k): {}", sensitive(&response.public_key));
 
-    connect(sock_fd. as_raw_fd(), &addr)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))? ;
+    // Verification: compute expected result if we knew k (for testing only)
+    // In real usage, k is never revealed
+    println!("[Parent] OPRF completed successfully!");
 
-    Ok(unsafe { std::net::TcpStream::from_raw_fd(sock_fd. into_raw_fd()) })
+    Ok(output)
 }
 
-fn send_request<S: Read + Write>(
-    stream: &mut S,
-    request: &OprfRequest,
-) -> std::io::Result<OprfResponse> {
-    // Send length-prefixed request
-    let request_bytes = serde_json::to_vec(request)?;
-    let len_bytes = (request_bytes.len() as u32).to_be_bytes();
+/// Run one OPRF evaluation of `input` against the enclave under ciphersuite `C`
+fn run<C: Ciphersuite>(
+    client: &OprfClient,
+    connection: &mut Connection<TcpStream>,
+    input: &str,
+    options: &Options,
+) -> Result<OprfOutput, Box<dyn std::error::Error>> {
+    println!("[Parent] Ciphersuite: {}", C::IDENTIFIER);
+    let request_id = connection.request_ids().then(|| options.request_id(None));
+    let query = blind_query::<C>(input, connection.point_encoding(), request_id, options)?;
+    let request_id = query.request.request_id.clone();
 
-    stream. write_all(&len_bytes)? ;
-    stream.write_all(&request_bytes)?;
-    stream.flush()?;
+    // Send request and get response
+    let version = connection.version();
+    connection
+        .request::<OprfResponse>(&Message::Evaluate(query.request.clone()))
+        .map_err(Into::into)
+        .and_then(|response| finish(client, query, response, version, options))
+        .map_err(|e| tag_error(request_id.as_deref(), e))
+}
 
-    // Read length-prefixed response
-    let mut len_buf = [0u8; 4];
-    stream.read_exact(&mut len_buf)?;
-    let len = u32::from_be_bytes(len_buf) as usize;
+/// Evaluate `inputs` under ciphersuite `C` over one connection: a single
+/// input in its own request, several in one batch request or, if
+/// `sequential`, in requests of their own pipelined `depth` deep
+fn evaluate<C: Ciphersuite>(
+    client: &OprfClient,
+    inputs: &[String],
+    sequential: bool,
+    depth: usize,
+    options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let redacted: Vec<_> = inputs.iter().map(|input| sensitive_text(input)).collect();
+    println!("[Parent] Inputs: {:?}", redacted);
+    let mut connection = client.connect()?;
+    match inputs {
+        [input] => run::<C>(client, &mut connection, input, options).map(drop),
+        _ if sequential => {
+            run_pipelined::<C>(client, &mut connection, inputs, depth, options)?;
+            println!("[Parent] Evaluated {} inputs over one connection", inputs.len());
+            Ok(())
+        }
+        _ => {
+            let outputs = run_batch::<C>(client, &mut connection, inputs, None, options)?;
+            print_outputs(inputs, &outputs);
+            Ok(())
+        }
+    }
+}
 
-    let mut buf = vec![0u8; len];
-    stream.read_exact(&mut buf)?;
+/// Evaluate the inputs of a batch file under ciphersuite `C`, in chunks over
+/// parallel connections or, if `sequential`, pipelined `depth` deep over one
+/// connection.
+/// The results go to the JSON Lines file `output` if set, else to stdout.
+fn evaluate_file<C: Ciphersuite>(
+    client: &OprfClient,
+    inputs: &[String],
+    sequential: bool,
+    depth: usize,
+    chunking: Chunking,
+    output: Option<&str>,
+    options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let outputs = if sequential {
+        let mut connection = client.connect()?;
+        run_pipelined::<C>(client, &mut connection, inputs, depth, options)?
+    } else {
+        batch::evaluate_chunked::<C>(client, inputs, chunking, options)?
+    };
+    match output {
+        Some(path) => {
+            batch::write_results(path, inputs, &outputs)?;
+            println!("[Parent] Wrote {} results to {}", outputs.len(), path);
+        }
+        // Pipelined evaluations print their outputs as they complete
+        None if sequential => {}
+        None => print_outputs(inputs, &outputs),
+    }
+    Ok(())
+}
 
-    serde_json::from_slice(&buf)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+/// Evaluate `input` and check that it gives `expected`, an output recorded
+/// earlier, e.g. when a password was set
+fn verify_output<C: Ciphersuite>(
+    client: &OprfClient,
+    input: &str,
+    expected: &[u8],
+    options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut connection = client.connect()?;
+    let output = run::<C>(client, &mut connection, input, options)?;
+    if !output.matches(expected) {
+        return Err("OPRF output does not match the expected output".into());
+    }
+    println!("[Parent] OPRF output matches the expected output");
+    Ok(())
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    println!("[Parent] Starting OPRF Parent...");
+/// Evaluate every input in its own request, keeping up to `depth` requests
+/// in flight: the first `depth` go out back-to-back, then the next one as
+/// each response comes in. A depth of 1 waits for every response in turn.
+fn run_pipelined<C: Ciphersuite>(
+    client: &OprfClient,
+    connection: &mut Connection<TcpStream>,
+    inputs: &[String],
+    depth: usize,
+    options: &Options,
+) -> Result<Vec<OprfOutput>, Box<dyn std::error::Error>> {
+    if !options.quiet {
+        println!("[Parent] Ciphersuite: {}", C::IDENTIFIER);
+        println!("[Parent] Pipelining {} requests, up to {} at once", inputs.len(), depth);
+    }
+    let mut in_flight = VecDeque::with_capacity(depth);
+    let mut outputs = Vec::with_capacity(inputs.len());
+    let several = inputs.len() > 1;
+    let mut inputs = inputs.iter().enumerate();
+    loop {
+        // Bounding the window keeps the enclave from blocking on a full socket
+        // buffer while the parent is still writing requests
+        while in_flight.len() < depth {
+            let Some((part, input)) = inputs.next() else { break };
+            let request_id = connection
+                .request_ids()
+                .then(|| options.request_id(several.then_some(part)));
+            let query = blind_query::<C>(input, connection.point_encoding(), request_id, options)?;
+            let id = connection.send(&Message::Evaluate(query.request.clone()))?;
+            in_flight.push_back((query, id));
+        }
+        let Some((query, id)) = in_flight.pop_front() else { break };
+        let request_id = query.request.request_id.clone();
+        let version = connection.version();
+        let output = connection
+            .receive::<OprfResponse>(id)
+            .map_err(Into::into)
+            .and_then(|response| finish(client, query, response, version, options))
+            .map_err(|e| tag_error(request_id.as_deref(), e))?;
+        outputs.push(output);
+    }
+    Ok(outputs)
+}
 
-    #[cfg(all(feature = "local", not(feature = "nitro")))]
-    println!("[Parent] Running in LOCAL mode");
+/// Time `count` evaluations under ciphersuite `C` made three ways: over a new
+/// connection each, one at a time over one connection, and pipelined `depth`
+/// deep over one connection
+fn bench<C: Ciphersuite>(
+    client: &OprfClient,
+    count: usize,
+    depth: usize,
+    options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let options = &Options { quiet: true, ..options.clone() };
+    let inputs: Vec<String> = (0..count).map(|i| format!("bench-{}", i)).collect();
+    println!("[Parent] Ciphersuite: {}", C::IDENTIFIER);
+    println!("[Parent] Timing {} evaluations with {:?} verification", count, options.verification);
 
-    #[cfg(feature = "nitro")]
-    println!("[Parent] Running in NITRO mode");
+    let report = |pattern: &str, elapsed: Duration| {
+        let rate = count as f64 / elapsed.as_secs_f64();
+        println!("[Parent] {:<28} {:>10.1?} {:>10.1} requests/s", pattern, elapsed, rate);
+    };
 
-    let mut rng = OsRng;
+    let start = Instant::now();
+    for input in &inputs {
+        let mut connection = client.connect()?;
+        run_pipelined::<C>(client, &mut connection, std::slice::from_ref(input), 1, options)?;
+    }
+    report("connection per request", start.elapsed());
 
-    // Sample random input m and blinding factor b
-    let m = Fr::rand(&mut rng);
-    let b = Fr::rand(&mut rng);
+    let mut connection = client.connect()?;
+    let start = Instant::now();
+    run_pipelined::<C>(client, &mut connection, &inputs, 1, options)?;
+    report("one connection, sequential", start.elapsed());
 
-    println!("[Parent] Sampled random input m");
-    println!("[Parent] Sampled random blinding factor b");
+    let mut connection = client.connect()?;
+    let start = Instant::now();
+    run_pipelined::<C>(client, &mut connection, &inputs, depth, options)?;
+    report(&format!("one connection, depth {}", depth), start.elapsed());
+    Ok(())
+}
 
-    // Compute blinded query: g^(m*b)
-    let m_times_b = m * b;
-    let blinded_query = scalar_mul_generator(&m_times_b);
-    let blinded_query_bytes = serialize_g1(&blinded_query)? ;
+/// Evaluate several inputs in one batch request covered by a single DLEQ
+/// proof, as part `part` of the command if it makes several
+fn run_batch<C: Ciphersuite>(
+    client: &OprfClient,
+    connection: &mut Connection<TcpStream>,
+    inputs: &[String],
+    part: Option<usize>,
+    options: &Options,
+) -> Result<Vec<OprfOutput>, Box<dyn std::error::Error>> {
+    let request_id = connection.request_ids().then(|| options.request_id(part));
+    run_batch_request::<C>(client, connection, inputs, request_id.clone(), options)
+        .map_err(|e| tag_error(request_id.as_deref(), e))
+}
 
-    println!("[Parent] Computed blinded query g^(m*b)");
-    println!("[Parent] Blinded query (hex): {}", hex::encode(&blinded_query_bytes));
+/// Evaluate `inputs` in one batch request tagged with `request_id` if set
+fn run_batch_request<C: Ciphersuite>(
+    client: &OprfClient,
+    connection: &mut Connection<TcpStream>,
+    inputs: &[String],
+    request_id: Option<String>,
+    options: &Options,
+) -> Result<Vec<OprfOutput>, Box<dyn std::error::Error>> {
+    println!("[Parent] Ciphersuite: {}", C::IDENTIFIER);
 
-    // Create request with hash
-    let query_hash = sha256_hex(&blinded_query_bytes);
-    let request = OprfRequest {
-        blinded_query: blinded_query_bytes.clone(),
-        query_hash: query_hash.clone(),
-    };
+    // Blind every input independently: H(input_i)^b_i
+    let mut span = Span::start("oprf.blind", SpanKind::Internal);
+    span.set("oprf.inputs", inputs.len());
+    let mode = options.verification.mode();
+    let (blinds, blinded_queries): (Vec<Blind<C>>, Vec<BlindedElement<C>>) = inputs
+        .iter()
+        .map(|input| blind::<C, _>(input.as_bytes(), mode, &mut options.rng.clone()))
+        .unzip();
+    let blinded_query_bytes = blinded_queries
+        .iter()
+        .map(|blinded| blinded.encode(connection.point_encoding()))
+        .collect::<Result<Vec<_>, _>>();
+    let blinded_query_bytes = span.check(blinded_query_bytes)?;
+    drop(span);
 
-    println!("[Parent] Query hash: {}", query_hash);
+    println!("[Parent] Computed {} blinded queries H(input_i)^b_i", inputs.len());
 
-    // Connect to enclave
-    let mut stream = connect_to_enclave()? ;
-    println!("[Parent] Connected to enclave");
+    let nonce = client_nonce();
+    let request = BatchOprfRequest {
+        query_hash: sha256_hex(&blinded_query_bytes.concat()),
+        blinded_queries: blinded_query_bytes,
+        mode,
+        ciphersuite: C::ID,
+        epoch: options.epoch,
+        key_id: options.key_id.clone(),
+        force_fresh: options.force_fresh,
+        client_nonce: Some(nonce.clone()),
+        client_id: options.client_id.clone(),
+        request_id: request_id.clone(),
+    };
+    if let Some(request_id) = &request_id {
+        println!("[Parent] Request id: {}", request_id);
+    }
 
     // Send request and get response
-    let response = send_request(&mut stream, &request)?;
-    println!("[Parent] Received response from enclave");
+    let response: BatchOprfResponse = connection.request(&Message::BatchEvaluate(request))?;
+    println!("[Parent] Received batch response from enclave (key epoch {})", response.epoch);
 
-    // Verify attestation
-    verify_attestation(&response. attestation, &response.evaluated_point)?;
-    println!("[Parent] Attestation verified successfully");
+    let mut span = Span::start("oprf.verify", SpanKind::Internal);
+    span.set("oprf.key_epoch", response.epoch);
+    let verified = verify_batch_response(
+        client,
+        connection,
+        &blinded_queries,
+        &response,
+        &nonce,
+        request_id.as_deref(),
+        options,
+    );
+    let evaluated = span.check(verified)?;
+    drop(span);
 
-    // Deserialize the evaluated point
-    let evaluated = deserialize_g1(&response.evaluated_point)?;
-    println!("[Parent] Evaluated point (hex): {}", hex::encode(&response. evaluated_point));
+    // Unblind and finalize every element
+    let mut span = Span::start("oprf.unblind", SpanKind::Internal);
+    let outputs = blinds
+        .into_iter()
+        .zip(&evaluated)
+        .map(|(blind, point)| unblind(blind, point))
+        .collect::<Result<Vec<_>, _>>();
+    let outputs = span.check(outputs)?;
+    drop(span);
+
+    println!("[Parent] Enclave public key (g^k): {}", sensitive(&response.public_key));
+    println!("[Parent] Batch OPRF completed successfully!");
+
+    Ok(outputs)
+}
+
+/// Check the enclave's response to a batch of `blinded_queries` sent with
+/// `nonce` and `request_id` over `connection`, and return its evaluated
+/// points
+fn verify_batch_response<C: Ciphersuite>(
+    client: &OprfClient,
+    connection: &Connection<TcpStream>,
+    blinded_queries: &[BlindedElement<C>],
+    response: &BatchOprfResponse,
+    nonce: &[u8],
+    request_id: Option<&str>,
+    options: &Options,
+) -> Result<Vec<EvaluatedElement<C>>, Box<dyn std::error::Error>> {
+    if response.evaluated_points.len() != blinded_queries.len() {
+        return Err(format!(
+            "Expected {} evaluated points, got {}",
+            blinded_queries.len(),
+            response.evaluated_points.len()
+        )
+        .into());
+    }
+
+    // Verify attestation over the whole batch, unless the channel did
+    check_key_id(&response.key_id, &options.key_id)?;
+    check_request_id(response.request_id.as_deref(), request_id)?;
+    client.check_public_key(&response.public_key)?;
+    if connection.version() >= 3 {
+        println!("[Parent] Response authenticated by the attested channel");
+    } else {
+        let user_data = EvaluationUserData::new(
+            connection.version(),
+            &options.key_id,
+            response.epoch,
+            &response.public_key,
+            &response.evaluated_points,
+            Some(nonce),
+            request_id,
+        );
+        verify_evaluation_attestation(client.policy(), &response.attestation, &user_data)?;
+        println!("[Parent] Attestation verified successfully");
+    }
 
-    // Unblind: output^(1/b) = g^(m*k)
-    let b_inv = scalar_inverse(&b). ok_or("Failed to compute inverse of b")?;
-    let unblinded = scalar_mul(&evaluated, &b_inv);
-    let unblinded_bytes = serialize_g1(&unblinded)?;
+    let evaluated = response
+        .evaluated_points
+        .iter()
+        .map(|point| EvaluatedElement::<C>::decode(point, connection.point_encoding()))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    println!("[Parent] Unblinded result computed");
+    // Check the whole batch at once before unblinding
+    verify_evaluation::<C>(
+        options,
+        &response.public_key,
+        response.public_key_g2.as_deref(),
+        response.proof.as_deref(),
+        blinded_queries,
+        &evaluated,
+    )?;
+    Ok(evaluated)
+}
+
+/// Print the output of every input
+fn print_outputs(inputs: &[String], outputs: &[OprfOutput]) {
     println!("[Parent] ================================================");
-    println!("[Parent] OPRF OUTPUT (g^(m*k)): {} {:?}", hex::encode(&unblinded_bytes), unblinded);
+    for (input, output) in inputs.iter().zip(outputs) {
+        println!("[Parent] OPRF OUTPUT {:?}: {}", input, hex::encode(output.as_bytes()));
+    }
     println!("[Parent] ================================================");
+}
 
-    // Also display the public key for reference
-    println! ("[Parent] Enclave public key (g^k): {}", hex::encode(&response.public_key));
+/// Fetch the current public key of `key_id` under ciphersuite `C` without
+/// evaluating. The attestation may come from the enclave's cache.
+fn get_public_key<C: Ciphersuite>(
+    client: &OprfClient,
+    key_id: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let request = GetPublicKeyRequest {
+        ciphersuite: C::ID,
+        key_id: key_id.to_string(),
+    };
 
-    // Verification: compute expected result if we knew k (for testing only)
-    // In real usage, k is never revealed
-    println!("[Parent] OPRF completed successfully!");
+    let response: PublicKeyResponse = client.request(&Message::GetPublicKey(request))?;
+    println!("[Parent] Received public key from enclave (key epoch {})", response.epoch);
+
+    check_key_id(&response.key_id, key_id)?;
+    client.check_public_key(&response.public_key)?;
+    verify_attestation(
+        client.policy(),
+        &response.attestation,
+        &attested_user_data(key_id, &response.public_key),
+    )?;
+    println!("[Parent] Attestation verified successfully");
+
+    if let Some(public_key_g2) = &response.public_key_g2 {
+        // The G2 key is not attested, so check it against the attested g^k
+        verify_key_pair(&deserialize_g1(&response.public_key)?, &deserialize_g2(public_key_g2)?)?;
+    }
+    println!(
+        "[Parent] {} public key {:?} (epoch {}): {}",
+        C::IDENTIFIER,
+        key_id,
+        response.epoch,
+        hex::encode(&response.public_key)
+    );
+    if let Some(public_key_g2) = &response.public_key_g2 {
+        println!("[Parent] G2 public key (g2^k): {}", hex::encode(public_key_g2));
+    }
+
+    Ok(())
+}
+
+/// Ask the enclave for a newly generated attestation of `key_id` under
+/// ciphersuite `C`, bound to a fresh nonce
+fn get_attestation<C: Ciphersuite>(
+    client: &OprfClient,
+    key_id: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let nonce = client_nonce();
+    let request = GetAttestationRequest {
+        ciphersuite: C::ID,
+        key_id: key_id.to_string(),
+        nonce: nonce.clone(),
+    };
+
+    let response: AttestationResponse = client.request(&Message::GetAttestation(request))?;
+    println!("[Parent] Received attestation from enclave (key epoch {})", response.epoch);
+
+    check_key_id(&response.key_id, key_id)?;
+    client.check_public_key(&response.public_key)?;
+    verify_attestation(
+        client.policy(),
+        &response.attestation,
+        &key_attestation_binding(key_id, &response.public_key, &nonce),
+    )?;
+    println!("[Parent] Attestation verified successfully");
+    println!(
+        "[Parent] Enclave attests {} key {:?} (epoch {}): {}",
+        C::IDENTIFIER,
+        key_id,
+        response.epoch,
+        hex::encode(&response.public_key)
+    );
+
+    Ok(())
+}
+
+/// Check that the enclave is up and ready to serve evaluations, failing if
+/// it cannot attest
+fn health(client: &OprfClient) -> Result<(), Box<dyn std::error::Error>> {
+    let response: HealthResponse = client.request(&Message::Health)?;
+    println!(
+        "[Parent] Enclave is up {}s: {} keys, clock at unix time {}",
+        response.uptime_secs, response.key_count, response.timestamp
+    );
+    for suite in &response.epochs {
+        println!(
+            "[Parent] {} default key at epoch {}",
+            suite.ciphersuite.identifier(),
+            suite.epoch
+        );
+    }
+    let attestation = &response.attestation;
+    match attestation.last_success {
+        Some(time) => println!("[Parent] Last attestation succeeded at unix time {}", time),
+        None => println!("[Parent] Enclave has not attested yet"),
+    }
+    if !response.is_ready() {
+        let error = attestation.last_error.as_deref().unwrap_or("unknown error");
+        return Err(format!("Enclave is not ready, attestation failing: {}", error).into());
+    }
+    println!("[Parent] Enclave is ready");
+
+    Ok(())
+}
+
+/// Fetch and print the enclave's request counters and the latency of every
+/// stage of serving evaluations
+fn stats(client: &OprfClient) -> Result<(), Box<dyn std::error::Error>> {
+    let response: StatsResponse = client.request(&Message::GetStats)?;
+    println!(
+        "[Parent] Enclave up {}s: {} requests ({} failed), {} evaluation requests of {} points",
+        response.uptime_secs,
+        response.requests,
+        response.errors,
+        response.evaluation_requests,
+        response.evaluations
+    );
+    println!(
+        "[Parent] {:<12} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        "stage", "count", "mean", "p50", "p99", "max"
+    );
+    for stage in &response.stages {
+        let micros = |micros: u64| format!("{:.1?}", Duration::from_micros(micros));
+        println!(
+            "[Parent] {:<12} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            format!("{:?}", stage.stage),
+            stage.count,
+            micros(stage.mean_micros()),
+            micros(stage.quantile_micros(0.5)),
+            micros(stage.quantile_micros(0.99)),
+            micros(stage.max_micros)
+        );
+    }
+
+    Ok(())
+}
+
+/// Fetch the known-answer tests the enclave ran on boot and check their
+/// attestation
+fn self_test(client: &OprfClient) -> Result<(), Box<dyn std::error::Error>> {
+    let response: SelfTestResponse = client.request(&Message::GetSelfTest)?;
+    // The report was attested on boot, however long ago
+    let mut policy = client.policy().clone();
+    policy.max_age_secs = u64::MAX;
+    verify_attestation(&policy, &response.attestation, &report_binding(&response.report))?;
+    println!("[Parent] Attestation verified successfully");
+
+    for result in &response.report.results {
+        println!(
+            "[Parent] {} {:?}: {}",
+            result.ciphersuite.identifier(),
+            result.test,
+            if result.passed { "passed" } else { "FAILED" }
+        );
+    }
+    if !response.report.passed() {
+        return Err("Enclave reports failed known-answer tests".into());
+    }
+    println!("[Parent] Enclave passed every known-answer test on boot");
+
+    Ok(())
+}
+
+/// Fetch the enclave's audit log from record `from` on, check that the
+/// records chain up to the latest attested checkpoint if it covers them, and
+/// save the log to `output` if set
+fn audit_log(
+    client: &OprfClient,
+    from: u64,
+    output: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut connection = client.connect()?;
+
+    let mut log: Option<AuditLogResponse> = None;
+    loop {
+        let next = from + log.as_ref().map_or(0, |log| log.records.len() as u64);
+        let request = GetAuditLogRequest { from: next };
+        let response: AuditLogResponse = connection.request(&Message::GetAuditLog(request))?;
+        let end = next + response.records.len() as u64;
+        let done = response.records.is_empty() || end >= response.next_sequence;
+        match &mut log {
+            None => log = Some(response),
+            Some(log) => {
+                log.records.extend(response.records);
+                log.next_sequence = response.next_sequence;
+                log.checkpoint = response.checkpoint;
+            }
+        }
+        if done {
+            break;
+        }
+    }
+    let log = log.expect("the log is fetched at least once");
+    println!("[Parent] Received {} audit records from enclave", log.records.len());
+
+    // Every head follows from the one before the first record
+    let start: [u8; 32] =
+        log.previous_head.as_slice().try_into().map_err(|_| "Invalid audit log head")?;
+    let head = verify_chain(&start, from, &log.records)?;
+    for record in &log.records {
+        println!(
+            "[Parent] #{} at {}: {} key {:?} epoch {}, {} queries hashing to {}, nonce {}",
+            record.sequence,
+            record.timestamp,
+            record.ciphersuite.identifier(),
+            record.key_id,
+            record.epoch,
+            record.count,
+            hex::encode(&record.query_hash),
+            record.client_nonce.as_ref().map_or("none".to_string(), hex::encode)
+        );
+    }
+    let end = from + log.records.len() as u64;
+    println!("[Parent] Audit log head after {} records: {}", end, hex::encode(head));
+
+    match &log.checkpoint {
+        Some(checkpoint) => {
+            // A checkpoint proves what the enclave did whenever it was taken
+            let mut policy = client.policy().clone();
+            policy.max_age_secs = u64::MAX;
+            let binding = checkpoint_binding(checkpoint.sequence, &checkpoint.head);
+            verify_attestation(&policy, &checkpoint.attestation, &binding)?;
+            println!(
+                "[Parent] Enclave attests audit log head after {} records: {}",
+                checkpoint.sequence,
+                hex::encode(&checkpoint.head)
+            );
+            let covered = checkpoint.sequence.checked_sub(from);
+            match covered.and_then(|covered| log.records.get(..covered as usize)) {
+                Some(records) if verify_chain(&start, from, records)? == *checkpoint.head => {
+                    println!("[Parent] Records up to the checkpoint match the attested head");
+                }
+                Some(_) => return Err("Audit records do not match the attested head".into()),
+                None => println!("[Parent] Checkpoint lies outside the fetched records"),
+            }
+        }
+        None => println!("[Parent] Enclave has not attested its audit log yet"),
+    }
+
+    if let Some(path) = output {
+        std::fs::write(path, serde_json::to_vec_pretty(&log)?)?;
+        println!("[Parent] Wrote audit log to {}", path);
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Ask the enclave to rotate the key of ciphersuite `C` to a new epoch
+fn rotate_key<C: Ciphersuite>(
+    client: &OprfClient,
+    access: &AdminAccess,
+    key_id: &str,
+    grace_period_secs: Option<u64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let request = RotateKeyRequest {
+        ciphersuite: C::ID,
+        key_id: key_id.to_string(),
+        grace_period_secs,
+    };
+
+    let mut connection = access.connect(client)?;
+
+    let response: RotateKeyResponse = connection.request(&Message::RotateKey(request))?;
+    println!("[Parent] Received key rotation response from enclave");
+    report_rotation(client, key_id, &response)
+}
+
+/// Check the attestation of a rotation of `key_id` and print its new key
+fn report_rotation(
+    client: &OprfClient,
+    key_id: &str,
+    response: &RotateKeyResponse,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // The attestation binds the new public key to the enclave
+    check_key_id(&response.key_id, key_id)?;
+    verify_attestation(
+        client.policy(),
+        &response.attestation,
+        &attested_user_data(key_id, &response.public_key),
+    )?;
+    println!("[Parent] Attestation verified successfully");
+
+    println!(
+        "[Parent] Rotated {} key {:?} to epoch {}",
+        client.ciphersuite().identifier(),
+        key_id,
+        response.epoch
+    );
+    println!("[Parent] New public key (g^k): {}", hex::encode(&response.public_key));
+    println!(
+        "[Parent] Epoch {} accepted until unix time {}",
+        response.epoch - 1,
+        response.previous_expires_at
+    );
+
+    Ok(())
+}
+
+/// Evaluations, errors and last use of a key epoch, as `admin keys` lists
+/// them
+fn epoch_usage(info: &EpochInfo) -> String {
+    let last_used = match info.last_used {
+        Some(time) => format!("last used at unix time {}", time),
+        None => "never used".to_string(),
+    };
+    format!("{} evaluations, {} errors, {}", info.evaluations, info.errors, last_used)
+}
+
+/// Carry out an operator command over `connection` to the admin port.
+/// `key_id` and `grace_period_secs` are those of a rotation.
+fn admin_command(
+    client: &OprfClient,
+    mut connection: AdminConnection<TcpStream>,
+    action: AdminAction,
+    key_id: &str,
+    grace_period_secs: Option<u64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        AdminAction::Keygen { .. } => unreachable!("keygen is answered before connecting"),
+        AdminAction::Status => {
+            let status: StatusResponse = connection.run(AdminCommand::Status)?;
+            println!(
+                "[Parent] Enclave {} up {}s: {} keys{}, {} open connections",
+                status.version,
+                status.uptime_secs,
+                status.key_count,
+                if status.seed_provisioned { " derived from a seed" } else { "" },
+                status.open_connections
+            );
+            println!(
+                "[Parent] {} requests served, {} points evaluated",
+                status.requests, status.evaluations
+            );
+        }
+        AdminAction::Keys => {
+            let response: KeyListResponse = connection.run(AdminCommand::ListKeys)?;
+            for key in &response.keys {
+                println!(
+                    "[Parent] {} key {:?}: epoch {}, public key {}",
+                    key.ciphersuite.identifier(),
+                    key.key_id,
+                    key.current.epoch,
+                    hex::encode(&key.current.public_key)
+                );
+                println!("[Parent]   epoch {}: {}", key.current.epoch, epoch_usage(&key.current));
+                if let Some(previous) = &key.previous {
+                    println!(
+                        "[Parent]   previous epoch {}: {}, accepted until unix time {}",
+                        previous.epoch,
+                        epoch_usage(previous),
+                        previous.expires_at.unwrap_or_default()
+                    );
+                }
+            }
+        }
+        AdminAction::Rotate => {
+            let request = RotateKeyRequest {
+                ciphersuite: client.ciphersuite(),
+                key_id: key_id.to_string(),
+                grace_period_secs,
+            };
+            let response: RotateKeyResponse = connection.run(AdminCommand::RotateKey(request))?;
+            report_rotation(client, key_id, &response)?;
+        }
+        AdminAction::FlushCaches => {
+            let response: FlushCachesResponse = connection.run(AdminCommand::FlushCaches)?;
+            println!(
+                "[Parent] Flushed {} cached attestations and {} cached evaluations",
+                response.attestations, response.evaluations
+            );
+        }
+        AdminAction::Shutdown { .. } => {
+            let response: ShutdownResponse = connection.run(AdminCommand::Shutdown)?;
+            println!(
+                "[Parent] Enclave is shutting down, draining {} open connections",
+                response.open_connections
+            );
+        }
+        AdminAction::Reload { settings } => {
+            let settings: Vec<_> = settings.iter().map(String::as_str).collect();
+            let request = admin::parse_reload(&settings)?;
+            let response: ReloadResponse = connection.run(AdminCommand::Reload(request))?;
+            let rate = |setting| match setting {
+                RateSetting::Unlimited => "unlimited".to_string(),
+                RateSetting::Limited { per_sec, burst } => {
+                    format!("{}/s in bursts of {}", per_sec, burst)
+                }
+            };
+            println!(
+                "[Parent] Enclave rate limits: {} per connection, {} per client",
+                rate(response.connection_rate),
+                rate(response.client_rate)
+            );
+            println!(
+                "[Parent] Enclave connections: at most {}, idle timeout {}s, frame timeout {}s",
+                response.max_connections, response.idle_timeout_secs, response.frame_timeout_secs
+            );
+            println!(
+                "[Parent] Enclave attestation TTL {}s, sensitive values {}",
+                response.attestation_ttl_secs,
+                if response.log_sensitive { "logged in full" } else { "redacted" }
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Serve an API on `addr` with `serve` through a client whose policy is
+/// reloaded from `source` on SIGHUP, if it names a policy file. Once
+/// listening, the process detaches as `daemon` says and records its id in
+/// `pid_file`, watches the heartbeat port and misses of `heartbeat` if
+/// given, then reports itself ready, see [`service`].
+#[cfg(any(feature = "http", feature = "jsonrpc", feature = "grpc"))]
+fn serve_reloading(
+    router: &Router,
+    source: PolicySource,
+    addr: &str,
+    daemon: bool,
+    pid_file: Option<&str>,
+    heartbeat: Option<(u32, u32)>,
+    serve: impl FnOnce(
+        std::net::TcpListener,
+        std::sync::Arc<reload::Upstream>,
+    ) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = std::net::TcpListener::bind(addr)?;
+    // Before any thread starts, since only the calling one survives the fork
+    if daemon {
+        service::daemonize()?;
+    }
+    if let Some(path) = pid_file {
+        service::write_pid_file(path)?;
+    }
+    let upstream = reload::Upstream::new(router.clone());
+    if source.path.is_some() {
+        reload::on_sighup(&upstream, move || source.load())?;
+    }
+    if let Some((port, max_missed)) = heartbeat {
+        let watchdog = Watchdog::new(router.default_client().clone(), port, max_missed);
+        std::thread::Builder::new()
+            .name("oprf-watchdog".to_string())
+            .spawn(move || watchdog.run(|event| report_heartbeat(event, false)))?;
+        println!("[Parent] Watching heartbeats on port {}", port);
+    }
+    service::notify("READY=1");
+    serve(listener, upstream)
+}
+
+/// Log `event` of the heartbeat watchdog, every beat if `verbose`. Alerts
+/// go to stderr and to the service manager's status line, see [`service`].
+fn report_heartbeat(event: Event, verbose: bool) {
+    let alert = |message: String| {
+        eprintln!("[Parent] ALERT: {}", message);
+        service::notify(&format!("STATUS={}", message));
+    };
+    match event {
+        Event::Beat(beat) if verbose => println!(
+            "[Parent] Heartbeat {}: up {}s, {}, {} open connections",
+            beat.sequence,
+            beat.uptime_secs,
+            if beat.ready { "ready" } else { "not ready" },
+            beat.open_connections
+        ),
+        Event::Beat(_) => {}
+        Event::Recovered(beat) => {
+            println!("[Parent] Enclave heartbeats resumed (sequence {})", beat.sequence);
+            service::notify("STATUS=Enclave heartbeats resumed");
+        }
+        Event::Restarted(beat) => alert(format!(
+            "enclave restarted {}s ago; restore its keys with import-keys",
+            beat.uptime_secs
+        )),
+        Event::Stopping(beat) => {
+            println!("[Parent] Enclave is shutting down (sequence {})", beat.sequence)
+        }
+        Event::Lost { missed, silence, error } => alert(format!(
+            "no enclave heartbeat for {}s, {} missed: {}",
+            silence.as_secs(),
+            missed,
+            error
+        )),
+    }
+}
+
+/// Install an existing secret key of ciphersuite `C`, read hex-encoded from
+/// `path`, as `key_id` at `epoch`. The key is sealed to a transport key the
+/// enclave attests to, so it is only readable inside the enclave.
+fn inject_key<C: Ciphersuite>(
+    client: &OprfClient,
+    access: &AdminAccess,
+    path: &str,
+    key_id: &str,
+    epoch: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let secret_key_hex = Zeroizing::new(std::fs::read_to_string(path)?);
+    let secret_key_bytes = Zeroizing::new(hex::decode(secret_key_hex.trim())?);
+    let secret_key = Zeroizing::new(C::deserialize_scalar(&secret_key_bytes)?);
+    let expected_public_key = C::serialize_element(&C::scalar_mul_generator(&secret_key))?;
+
+    let request = BeginKeyInjectionRequest {
+        ciphersuite: C::ID,
+        key_id: key_id.to_string(),
+        epoch,
+    };
+    let mut connection = access.connect(client)?;
+    let response: BeginKeyInjectionResponse =
+        connection.request(&Message::BeginKeyInjection(request))?;
+    println!("[Parent] Received transport key from enclave");
+
+    // Only seal the key to a transport key attested for this key id and epoch
+    verify_attestation(
+        client.policy(),
+        &response.attestation,
+        &key_injection_binding(key_id, epoch, &response.transport_public_key),
+    )?;
+    println!("[Parent] Attestation verified successfully");
+
+    let transport_public_key = C::deserialize_element(&response.transport_public_key)?;
+    let sealed = seal_to::<C, _>(
+        &transport_public_key,
+        &key_injection_binding(key_id, epoch, &[]),
+        &secret_key_bytes,
+        &mut OsRng,
+    )?;
+
+    let request = InjectKeyRequest {
+        ciphersuite: C::ID,
+        key_id: key_id.to_string(),
+        epoch,
+        sender_public_key: sealed.sender_public_key,
+        nonce: sealed.nonce.to_vec(),
+        ciphertext: sealed.ciphertext,
+    };
+    // Send the sealed key over the same connection
+    let response: InjectKeyResponse = connection.request(&Message::InjectKey(request))?;
+    println!("[Parent] Received key injection response from enclave");
+
+    check_key_id(&response.key_id, key_id)?;
+    if response.public_key != expected_public_key || response.epoch != epoch {
+        return Err("Enclave installed a different key".into());
+    }
+    verify_attestation(
+        client.policy(),
+        &response.attestation,
+        &attested_user_data(key_id, &response.public_key),
+    )?;
+    println!("[Parent] Attestation verified successfully");
+
+    println!(
+        "[Parent] Injected {} key {:?} (epoch {}): {}",
+        C::IDENTIFIER,
+        key_id,
+        epoch,
+        hex::encode(&response.public_key)
+    );
+    Ok(())
+}
+
+/// KMS credentials for the enclave, taken from the standard AWS environment
+/// variables (e.g. `eval "$(aws configure export-credentials --format env)"`),
+/// through the proxy on `proxy_port` unless `KMS_PROXY_PORT` names another
+fn kms_config_from_env(proxy_port: u32) -> Result<KmsConfig, Box<dyn std::error::Error>> {
+    let var = |name: &str| std::env::var(name).ok();
+    Ok(KmsConfig {
+        region: var("AWS_REGION")
+            .or_else(|| var("AWS_DEFAULT_REGION"))
+            .unwrap_or_default(),
+        access_key_id: var("AWS_ACCESS_KEY_ID").unwrap_or_default(),
+        secret_access_key: var("AWS_SECRET_ACCESS_KEY").unwrap_or_default(),
+        session_token: var("AWS_SESSION_TOKEN"),
+        proxy_port: match var("KMS_PROXY_PORT") {
+            Some(port) => port.parse()?,
+            None => proxy_port,
+        },
+    })
+}
+
+/// Export every enclave key, sealed under a KMS data key, to `path`
+fn export_keys(
+    client: &OprfClient,
+    access: &AdminAccess,
+    path: &str,
+    kms_key_id: &str,
+    kms: KmsConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let request = ExportKeysRequest {
+        kms,
+        kms_key_id: kms_key_id.to_string(),
+    };
+
+    let mut connection = access.connect(client)?;
+
+    let response: ExportKeysResponse = connection.request(&Message::ExportKeys(request))?;
+    std::fs::write(path, serde_json::to_vec_pretty(&response.backup)?)?;
+
+    println!("[Parent] Wrote key backup to {}", path);
+    if response.backup.is_mock {
+        println!("[Parent] WARNING: Backup uses the mock KMS (local mode) and is not protected");
+    }
+    Ok(())
+}
+
+/// Restore the enclave keys from a backup written by [`export_keys`]
+fn import_keys(
+    client: &OprfClient,
+    access: &AdminAccess,
+    path: &str,
+    kms: KmsConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let request = ImportKeysRequest {
+        kms,
+        backup: serde_json::from_slice(&std::fs::read(path)?)?,
+    };
+
+    let mut connection = access.connect(client)?;
+
+    let response: ImportKeysResponse = connection.request(&Message::ImportKeys(request))?;
+    println!("[Parent] Received import response from enclave");
+
+    // The attestation covers the restored public keys
+    let keys = serde_json::to_vec(&response.keys)?;
+    verify_attestation(client.policy(), &response.attestation, &keys)?;
+    println!("[Parent] Attestation verified successfully");
+
+    for key in &response.keys {
+        println!(
+            "[Parent] Restored {} key {:?} (epoch {}): {}",
+            key.ciphersuite.identifier(),
+            key.key_id,
+            key.epoch,
+            hex::encode(&key.public_key)
+        );
+    }
+    Ok(())
+}
+
+/// Read the seed blob to provision: a KMS ciphertext in Nitro mode
+#[cfg(feature = "nitro")]
+fn read_encrypted_seed(path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    Ok(std::fs::read(path)?)
+}
+
+/// Read the seed blob to provision: in local mode the file holds the raw
+/// seed, which is wrapped under the mock KMS key here
+#[cfg(all(feature = "local", not(feature = "nitro")))]
+fn read_encrypted_seed(path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    use oprf_common::envelope::{mock_kms_encrypt, NONCE_LEN};
+    use rand::RngCore;
+
+    let seed = Zeroizing::new(std::fs::read(path)?);
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    Ok(mock_kms_encrypt(&seed, &nonce))
+}
+
+/// Derive every enclave key from the seed in `path`, over the admin port
+fn provision_seed(
+    client: &OprfClient,
+    access: &AdminAccess,
+    path: &str,
+    kms: KmsConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if access.operator_key.is_none() {
+        return Err("provision-seed requires --operator-key".into());
+    }
+    let request = ProvisionSeedRequest {
+        kms,
+        encrypted_seed: read_encrypted_seed(path)?,
+    };
+
+    let mut connection = access.connect_admin(client)?;
+
+    let response: ProvisionSeedResponse =
+        connection.run(AdminCommand::ProvisionSeed(request))?;
+    println!("[Parent] Received provisioning response from enclave");
+
+    // The attestation covers the derived public keys
+    let keys = serde_json::to_vec(&response.keys)?;
+    verify_attestation(client.policy(), &response.attestation, &keys)?;
+    println!("[Parent] Attestation verified successfully");
+
+    for key in &response.keys {
+        println!(
+            "[Parent] Derived {} key {:?} (epoch {}): {}",
+            key.ciphersuite.identifier(),
+            key.key_id,
+            key.epoch,
+            hex::encode(&key.public_key)
+        );
+    }
+    Ok(())
+}
+
+/// Copy every key of the enclave of `primary` to the enclave of `standby`,
+/// over the admin port of each. Each enclave checks that the other runs the
+/// same image before the keys leave or are installed, see
+/// `server/src/replication.rs`; the parent only relays the sealed keys and
+/// checks both sides against its policy too.
+fn replicate(
+    primary: &OprfClient,
+    standby: &OprfClient,
+    access: &AdminAccess,
+    standby_access: &AdminAccess,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if access.operator_key.is_none() {
+        return Err("replicate requires --operator-key".into());
+    }
+    let mut standby_connection = standby_access.connect_admin(standby)?;
+    let offer: BeginReplicationResponse = standby_connection.run(AdminCommand::BeginReplication)?;
+    println!("[Parent] Received transport key from standby");
+    let transport_public_key = offer.transport_public_key;
+    verify_attestation(
+        standby.policy(),
+        &offer.attestation,
+        &replication_binding(REPLICA_STANDBY_LABEL, &[&transport_public_key]),
+    )?;
+    println!("[Parent] Standby attestation verified successfully");
+
+    let mock_key = |client: &OprfClient| {
+        client.policy().mock_signing_key.as_ref().map(|key| key.to_bytes().to_vec())
+    };
+    let request = ExportReplicaRequest {
+        transport_public_key: transport_public_key.clone(),
+        attestation: offer.attestation,
+        peer_mock_signing_key: mock_key(standby),
+    };
+    let mut connection = access.connect_admin(primary)?;
+    let replica: ExportReplicaResponse = connection.run(AdminCommand::ExportReplica(request))?;
+    println!("[Parent] Received {} sealed keys from primary", replica.keys.len());
+
+    // The primary attests the keys it sealed, to this transport key only
+    let keys = serde_json::to_vec(&replica.keys)?;
+    verify_attestation(
+        primary.policy(),
+        &replica.attestation,
+        &replication_binding(
+            REPLICA_PRIMARY_LABEL,
+            &[&transport_public_key, &replica.sender_public_key, &keys],
+        ),
+    )?;
+    println!("[Parent] Primary attestation verified successfully");
+
+    let expected = replica.keys.clone();
+    let request = ImportReplicaRequest {
+        replica,
+        peer_mock_signing_key: mock_key(primary),
+    };
+    let response: ImportReplicaResponse =
+        standby_connection.run(AdminCommand::ImportReplica(request))?;
+    println!("[Parent] Received replication response from standby");
+
+    let keys = serde_json::to_vec(&response.keys)?;
+    verify_attestation(standby.policy(), &response.attestation, &keys)?;
+    println!("[Parent] Attestation verified successfully");
+    if response.keys != expected {
+        return Err("Standby installed different keys than the primary sealed".into());
+    }
+
+    for key in &response.keys {
+        println!(
+            "[Parent] Replicated {} key {:?} (epoch {}): {}",
+            key.ciphersuite.identifier(),
+            key.key_id,
+            key.epoch,
+            hex::encode(&key.public_key)
+        );
+    }
+    Ok(())
+}
+
+/// Evaluate, attest and manage the keys of the OPRF enclave.
+///
+/// Settings of the [parent] table of the --config file (or OPRF_CONFIG), and
+/// OPRF_PARENT_<SETTING> environment variables, are overridden by the options.
+#[derive(Parser)]
+#[command(
+    name = "oprf-parent",
+    after_help = "Exit status: 0 on success, 3 if an attestation is refused, 4 if the enclave \
+                  cannot be reached or the connection breaks, 1 on any other failure."
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    #[command(flatten)]
+    evaluation: EvaluationArgs,
+    #[command(flatten)]
+    batch: BatchArgs,
+    #[command(flatten)]
+    connection: ConnectionArgs,
+    #[command(flatten)]
+    admin: AdminArgs,
+    #[command(flatten)]
+    service: ServiceArgs,
+}
+
+#[derive(Args)]
+#[command(next_help_heading = "Evaluation options")]
+struct EvaluationArgs {
+    /// Ciphersuite, bn254 or p256 (bn254 by default)
+    #[arg(long, global = true)]
+    suite: Option<CiphersuiteId>,
+    /// Named key ("default" by default)
+    #[arg(long, global = true, value_name = "ID")]
+    key_id: Option<String>,
+    /// Key epoch, the current one by default
+    #[arg(long, global = true, value_name = "N")]
+    epoch: Option<u64>,
+    /// How evaluations are verified, dleq or pairing (dleq by default)
+    #[arg(long = "verify", global = true, value_name = "METHOD")]
+    verification: Option<Verification>,
+    /// Ask for a newly generated attestation
+    #[arg(long, global = true)]
+    fresh_attestation: bool,
+    /// Client the enclave rate-limits evaluations by
+    #[arg(long, global = true, value_name = "ID")]
+    client_id: Option<String>,
+    /// Tag evaluations with <ID>, or <ID>-<n> when there are several requests
+    /// (random by default)
+    #[arg(long, global = true, value_name = "ID")]
+    request_id: Option<String>,
+    /// Send several inputs in requests of their own
+    #[arg(long, global = true)]
+    sequential: bool,
+    /// Requests in flight at once over one connection (32 by default)
+    #[arg(long, global = true, value_name = "N")]
+    pipeline_depth: Option<usize>,
+    /// Draw blinding factors from a stream seeded with 32 hex bytes, for
+    /// tests (deterministic-rng feature)
+    #[arg(long, global = true, value_name = "HEX")]
+    blind_seed: Option<String>,
+}
+
+#[derive(Args)]
+#[command(next_help_heading = "Batch options")]
+struct BatchArgs {
+    /// Inputs, one per line; JSON strings or {"input": ...} objects in a
+    /// .jsonl file
+    #[arg(long, global = true, value_name = "FILE")]
+    input: Option<String>,
+    /// Write {"input", "output"} JSON Lines to <FILE>, or the audit log of
+    /// audit-log
+    #[arg(long, global = true, value_name = "FILE")]
+    output: Option<String>,
+    /// Inputs per batch request (1024 by default)
+    #[arg(long, global = true, value_name = "N")]
+    chunk_size: Option<usize>,
+    /// Connections evaluating chunks at once (4 by default)
+    #[arg(long, global = true, value_name = "N")]
+    parallel: Option<usize>,
+}
+
+#[derive(Args)]
+#[command(next_help_heading = "Connection options")]
+struct ConnectionArgs {
+    /// TOML configuration file, see the README (also OPRF_CONFIG)
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<String>,
+    /// CID of the enclave (also ENCLAVE_CID)
+    #[arg(long, global = true, value_name = "N")]
+    cid: Option<u32>,
+    /// Port of the enclave (also ENCLAVE_PORT)
+    #[arg(long, global = true, value_name = "N")]
+    port: Option<u32>,
+    /// Enclave to fail over to, in the order given, which must attest the
+    /// same public key and epoch of --key-id; may be repeated
+    #[arg(long, global = true, value_name = "CID:PORT")]
+    failover: Vec<EnclaveAddress>,
+    /// Spread connections over --port and the --failover endpoints in turn
+    /// rather than failing over
+    #[arg(long, global = true)]
+    balance: bool,
+    /// Enclaves holding <KEY_ID>, to which its requests go instead; may be
+    /// repeated
+    #[arg(long, global = true, value_name = "KEY_ID=CID:PORT[,CID:PORT...]")]
+    #[arg(value_parser = parse_shard)]
+    shard: Vec<(String, Vec<EnclaveAddress>)>,
+    /// Allowlist of enclave PCRs
+    #[arg(long, global = true, value_name = "FILE")]
+    policy: Option<String>,
+    /// Override the policy's max_age_secs
+    #[arg(long, global = true, value_name = "SECS")]
+    max_attestation_age: Option<u64>,
+    /// Override the policy's min_protocol_version
+    #[arg(long, global = true, value_name = "N")]
+    min_protocol_version: Option<u32>,
+    /// Key printed by a local-mode enclave
+    #[arg(long, global = true, value_name = "HEX")]
+    mock_signing_key: Option<String>,
+    /// Key printed by the local-mode standby of replicate
+    #[arg(long, global = true, value_name = "HEX")]
+    standby_mock_signing_key: Option<String>,
+    /// Encoding of messages, cbor or json (cbor by default)
+    #[arg(long, global = true, value_name = "FORMAT")]
+    wire_format: Option<WireFormat>,
+    /// Encoding of evaluated points, compressed or uncompressed (compressed
+    /// by default)
+    #[arg(long, global = true, value_name = "ENCODING")]
+    point_encoding: Option<PointEncoding>,
+    /// Bound on connecting
+    #[arg(long, global = true, value_name = "SECS")]
+    connect_timeout: Option<u64>,
+    /// Bound on every read and write
+    #[arg(long, global = true, value_name = "SECS")]
+    timeout: Option<u64>,
+    /// Retries of a failed connection attempt, or of a read or evaluation
+    /// whose connection broke
+    #[arg(long, global = true, value_name = "N")]
+    retries: Option<u32>,
+    /// Wait before the first retry, doubled on each (200 by default),
+    /// jittered
+    #[arg(long, global = true, value_name = "MS")]
+    retry_backoff: Option<u64>,
+    /// Bound on the wait (10000 by default)
+    #[arg(long, global = true, value_name = "MS")]
+    max_retry_backoff: Option<u64>,
+    /// Idle connections kept open for later reads and gateway requests (16
+    /// by default, 0 for none)
+    #[arg(long, global = true, value_name = "N")]
+    pool_size: Option<usize>,
+    /// Time an idle connection is kept (20 by default)
+    #[arg(long, global = true, value_name = "SECS")]
+    pool_idle_timeout: Option<u64>,
+    /// Refuse any public key but this one
+    #[arg(long, global = true, value_name = "HEX")]
+    pin_key: Option<String>,
+}
+
+#[derive(Args)]
+#[command(next_help_heading = "Admin options")]
+struct AdminArgs {
+    /// Admin port of the enclave (5001 by default)
+    #[arg(long, global = true, value_name = "N")]
+    admin_port: Option<u32>,
+    /// Admin port of the standby of replicate (--admin-port by default)
+    #[arg(long, global = true, value_name = "N")]
+    standby_admin_port: Option<u32>,
+    /// Operator key signing admin commands, provision-seed and replicate;
+    /// rotate, inject-key, export-keys and import-keys then use the admin
+    /// port too
+    #[arg(long, global = true, value_name = "FILE")]
+    operator_key: Option<String>,
+    /// Time the previous epoch of a rotated key is still accepted
+    #[arg(long, global = true, value_name = "SECS")]
+    grace_period: Option<u64>,
+    /// KMS key export-keys backs the keys up under
+    #[arg(long, global = true, value_name = "KMS_KEY")]
+    kms_key_id: Option<String>,
+}
+
+#[derive(Args)]
+#[command(next_help_heading = "Service options")]
+struct ServiceArgs {
+    /// Heartbeat port of the enclave, watched by watch (5003 by default)
+    /// and by the serve commands if given
+    #[arg(long, global = true, value_name = "N")]
+    heartbeat_port: Option<u32>,
+    /// Beats missed before alerting (3 by default)
+    #[arg(long, global = true, value_name = "N")]
+    max_missed_heartbeats: Option<u32>,
+    /// Export spans to the OTLP/HTTP collector at <URL> (also
+    /// OTEL_EXPORTER_OTLP_ENDPOINT)
+    #[arg(long, global = true, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+    /// W3C traceparent of the caller to trace under (also TRACEPARENT)
+    #[arg(long, global = true, value_name = "HEADER")]
+    traceparent: Option<String>,
+    /// Log inputs, blinded queries, evaluated points and public keys in
+    /// full instead of redacted
+    #[arg(long, global = true)]
+    log_sensitive: bool,
+    /// Detach the serve commands from the terminal once listening
+    #[arg(long, global = true)]
+    daemon: bool,
+    /// Write the id of the serving process to <FILE>
+    #[arg(long, global = true, value_name = "FILE")]
+    pid_file: Option<String>,
+    /// Require an API key listed in <FILE> on the /v1/ endpoints of
+    /// serve-http, within its quotas
+    #[arg(long, global = true, value_name = "FILE")]
+    api_keys: Option<String>,
+}
+
+/// Subcommand of the command line, with its arguments
+#[derive(Subcommand)]
+enum Command {
+    /// Evaluate inputs, several in one batch request
+    Eval {
+        #[arg(required = true)]
+        inputs: Vec<String>,
+    },
+    /// Evaluate every line of <FILE> (or --input <FILE>) in batch requests
+    /// of up to 1024 inputs
+    Batch { file: Option<String> },
+    /// Time <COUNT> evaluations over a connection each, one connection, and
+    /// pipelined
+    Bench {
+        #[arg(default_value_t = DEFAULT_BENCH_COUNT, value_parser = positive)]
+        count: usize,
+    },
+    /// Evaluate <INPUT> and check it gives the hex <OUTPUT>
+    Verify { input: String, output: String },
+    /// Fetch the attested public key
+    #[command(name = "pubkey")]
+    PublicKey,
+    /// Fetch a newly generated attestation of the key
+    Attest,
+    /// Check that the enclave is serving and can attest
+    Health,
+    /// Fetch request counts and evaluation latencies
+    Stats,
+    /// Fetch the attested known-answer tests run on boot
+    SelfTest,
+    /// Fetch and check the audit log from record <FROM> [--output <FILE>]
+    AuditLog {
+        #[arg(default_value_t = 0)]
+        from: u64,
+    },
+    /// Rotate the key to a new epoch [--grace-period <SECS>]
+    Rotate,
+    /// Operator command on the admin port
+    #[command(subcommand)]
+    Admin(AdminAction),
+    /// Install the hex secret key in <FILE> [--epoch <N>]
+    InjectKey { file: String },
+    /// Back up every key to <FILE> under the enclave's KMS key
+    /// [--kms-key-id <KMS_KEY>]
+    ExportKeys { file: String },
+    /// Restore the keys backed up in <FILE>
+    ImportKeys { file: String },
+    /// Derive every key from the seed in <FILE>
+    ProvisionSeed { file: String },
+    /// Copy every key to the standby enclave at <STANDBY>, which must run
+    /// the same image
+    Replicate {
+        #[arg(value_name = "CID:PORT")]
+        standby: EnclaveAddress,
+    },
+    /// Follow the enclave's heartbeats until interrupted, alerting when they
+    /// stop
+    Watch,
+    /// Serve the HTTP API on <ADDR> (with the http feature)
+    ServeHttp { addr: String },
+    /// Serve JSON-RPC on <ADDR> (with the jsonrpc feature)
+    #[command(name = "serve-jsonrpc")]
+    ServeJsonRpc { addr: String },
+    /// Serve the Oprf gRPC service on <ADDR> (with the grpc feature)
+    ServeGrpc { addr: String },
+}
+
+/// Operator command of `admin`
+#[derive(Subcommand)]
+enum AdminAction {
+    /// Write a new operator key to <FILE>
+    Keygen { file: String },
+    /// Report the enclave's version, uptime, keys and connections
+    Status,
+    /// List every key with the usage of its epochs
+    Keys,
+    /// Rotate the key to a new epoch [--grace-period <SECS>]
+    Rotate,
+    /// Drop the cached attestations and evaluations
+    FlushCaches,
+    /// Back up every key to <FILE> if given, then drain and stop the enclave
+    Shutdown { file: Option<String> },
+    /// Change enclave settings: connection-rate and client-rate
+    /// (<n>[/<burst>] or off), max-connections, idle-timeout, frame-timeout,
+    /// attestation-ttl and log-sensitive; none reports the current ones
+    Reload {
+        #[arg(value_name = "NAME=VALUE")]
+        settings: Vec<String>,
+    },
+}
+
+/// A positive request count
+fn positive(count: &str) -> Result<usize, String> {
+    match count.parse() {
+        Ok(0) => Err("must be positive".to_string()),
+        Ok(count) => Ok(count),
+        Err(e) => Err(format!("Invalid request count: {}", e)),
+    }
+}
+
+/// Decode a hex argument, named `what` in errors
+fn decode_hex(value: &str, what: &str) -> Result<Vec<u8>, String> {
+    hex::decode(value.trim()).map_err(|e| format!("Invalid {}: {}", what, e))
+}
+
+fn main() -> ExitCode {
+    match try_main() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            service::exit_code(e.as_ref())
+        }
+    }
+}
+
+fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = match Cli::command().try_get_matches() {
+        Ok(matches) => matches,
+        // Help goes to stdout and succeeds; a mistake fails like any error
+        Err(e) if !e.use_stderr() => return Ok(e.print()?),
+        Err(e) => {
+            e.print()?;
+            return Err("Invalid command line, see oprf-parent help".into());
+        }
+    };
+    let name = matches.subcommand_name().expect("a command is required").to_string();
+    let Cli {
+        command,
+        evaluation,
+        batch,
+        connection,
+        admin,
+        service,
+    } = Cli::from_arg_matches(&matches)?;
+
+    let settings = ConfigFile::read(connection.config)?.parent;
+    let ciphersuite = evaluation.suite.unwrap_or(settings.suite);
+    let request_id = evaluation.request_id;
+    if let Some(request_id) = &request_id {
+        validate_request_id(request_id)?;
+    }
+    let rng = match evaluation.blind_seed {
+        Some(seed) => SharedRng::from_hex_seed(&seed)
+            .map_err(|e| format!("Invalid --blind-seed: {}", e))?,
+        None => SharedRng::os(),
+    };
+    let options = Options {
+        verification: evaluation.verification.unwrap_or(Verification::Dleq),
+        epoch: evaluation.epoch,
+        key_id: evaluation.key_id.unwrap_or_else(|| DEFAULT_KEY_ID.to_string()),
+        force_fresh: evaluation.fresh_attestation,
+        client_id: evaluation.client_id,
+        quiet: false,
+        request_id,
+        rng,
+    };
+    let policy_path = connection.policy.or(settings.policy);
+    let max_attestation_age = connection.max_attestation_age.or(settings.max_attestation_age_secs);
+    let min_protocol_version = connection.min_protocol_version.or(settings.min_protocol_version);
+    let wire_format = connection.wire_format.unwrap_or(settings.wire_format);
+    let point_encoding = connection.point_encoding.unwrap_or(settings.point_encoding);
+    let retries = connection.retries.unwrap_or(settings.retries);
+    let retry = RetryPolicy {
+        attempts: retries.checked_add(1).ok_or("--retries out of range")?,
+        backoff: Duration::from_millis(
+            connection.retry_backoff.unwrap_or(settings.retry_backoff_ms),
+        ),
+        max_backoff: Duration::from_millis(
+            connection.max_retry_backoff.unwrap_or(settings.max_retry_backoff_ms),
+        ),
+    };
+    let pool = PoolLimits {
+        size: connection.pool_size.unwrap_or(settings.pool_size),
+        idle_timeout: Duration::from_secs(
+            connection.pool_idle_timeout.unwrap_or(settings.pool_idle_timeout_secs),
+        ),
+    };
+    let connect_timeout =
+        connection.connect_timeout.or(settings.connect_timeout_secs).map(Duration::from_secs);
+    let timeout = connection.timeout.or(settings.timeout_secs).map(Duration::from_secs);
+    let pinned_key = connection.pin_key.map(|key| decode_hex(&key, "key")).transpose()?;
+    let mock_key = |key: Option<String>| -> Result<_, Box<dyn std::error::Error>> {
+        match key {
+            Some(key) => Ok(Some(VerifyingKey::from_bytes(&decode_hex(&key, "mock key")?)?)),
+            None => Ok(None),
+        }
+    };
+    let mock_signing_key = mock_key(connection.mock_signing_key)?;
+    let standby_mock_signing_key = mock_key(connection.standby_mock_signing_key)?;
+    let sequential = evaluation.sequential;
+    let pipeline_depth = evaluation.pipeline_depth.unwrap_or(settings.pipeline_depth);
+    let chunking = Chunking {
+        chunk_size: batch.chunk_size.unwrap_or(settings.chunk_size),
+        parallel: batch.parallel.unwrap_or(settings.parallel),
+    };
+    let (input_path, output_path) = (batch.input, batch.output);
+    let admin_port = admin.admin_port.unwrap_or(settings.admin_port);
+    let standby_admin_port = admin.standby_admin_port;
+    let operator_key = admin.operator_key.or(settings.operator_key);
+    let grace_period_secs = admin.grace_period;
+    let kms_key_id = admin.kms_key_id;
+    let daemon = service.daemon || settings.daemon;
+    let pid_file = service.pid_file.or(settings.pid_file);
+    let api_keys = service.api_keys.or(settings.api_keys);
+    let heartbeat_port = service.heartbeat_port.or(settings.heartbeat_port);
+    let max_missed_heartbeats =
+        service.max_missed_heartbeats.unwrap_or(settings.max_missed_heartbeats);
+    let kms_proxy_port = settings.kms_proxy_port;
+    set_log_sensitive(service.log_sensitive || settings.log_sensitive);
+    let mut address = EnclaveAddress {
+        cid: settings.cid,
+        port: settings.port,
+    }
+    .with_env()?;
+    address.cid = connection.cid.unwrap_or(address.cid);
+    address.port = connection.port.unwrap_or(address.port);
+    let mut failover = settings
+        .failover
+        .iter()
+        .map(|endpoint| endpoint.parse())
+        .collect::<Result<Vec<EnclaveAddress>, _>>()?;
+    failover.extend(connection.failover);
+    let balance = connection.balance || settings.balance;
+    let mut shards = BTreeMap::new();
+    for (key_id, endpoints) in settings.shards {
+        let endpoints = endpoints
+            .iter()
+            .map(|endpoint| endpoint.parse())
+            .collect::<Result<Vec<EnclaveAddress>, _>>()?;
+        check_shard(&key_id, &endpoints)?;
+        shards.insert(key_id, endpoints);
+    }
+    shards.extend(connection.shard);
+    let otlp_endpoint =
+        service.otlp_endpoint.or_else(|| std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok());
+    let traceparent = service.traceparent.or_else(|| std::env::var("TRACEPARENT").ok());
+    chunking.validate()?;
+    if pipeline_depth == 0 {
+        return Err("--pipeline-depth must be positive".into());
+    }
+    if max_missed_heartbeats == 0 {
+        return Err("--max-missed-heartbeats must be positive".into());
+    }
+    if let Command::Admin(AdminAction::Keygen { file: path }) = &command {
+        let key = admin::generate_key(path)?;
+        println!("[Parent] Wrote operator key to {}", path);
+        println!("[Parent] Start the enclave with --admin-key {}", hex::encode(key.public_key()));
+        return Ok(());
+    }
+    let serving = matches!(
+        command,
+        Command::ServeHttp { .. } | Command::ServeJsonRpc { .. } | Command::ServeGrpc { .. }
+    );
+    if !serving && (daemon || pid_file.is_some()) {
+        return Err("--daemon and --pid-file apply to the serve commands".into());
+    }
+    if api_keys.is_some() && !matches!(command, Command::ServeHttp { .. }) {
+        return Err("--api-keys applies to serve-http only".into());
+    }
+    // The HTTP, JSON-RPC and gRPC APIs relay points their clients encoded
+    if serving && point_encoding == PointEncoding::Uncompressed {
+        return Err("The HTTP, JSON-RPC and gRPC APIs relay compressed points only".into());
+    }
+
+    let remote_parent = traceparent
+        .filter(|traceparent| !traceparent.is_empty())
+        .map(|traceparent| SpanContext::from_traceparent(&traceparent))
+        .transpose()?;
+    if let Some(endpoint) = otlp_endpoint.filter(|endpoint| !endpoint.is_empty()) {
+        trace::init(&endpoint, remote_parent)?;
+    }
+
+    println!("[Parent] Starting OPRF Parent...");
+
+    #[cfg(all(feature = "local", not(feature = "nitro")))]
+    println!("[Parent] Running in LOCAL mode");
+
+    #[cfg(feature = "nitro")]
+    println!("[Parent] Running in NITRO mode");
+
+    let standby_access = AdminAccess {
+        operator_key: operator_key.clone(),
+        port: standby_admin_port.unwrap_or(admin_port),
+        format: wire_format,
+    };
+    let access = AdminAccess {
+        operator_key,
+        port: admin_port,
+        format: wire_format,
+    };
+    let policy_source = PolicySource {
+        path: policy_path,
+        max_age_secs: max_attestation_age,
+        min_protocol_version,
+        mock_signing_key,
+    };
+    let policy = policy_source.load()?;
+
+    let mut builder = OprfClient::builder()
+        .balance(balance)
+        .ciphersuite(ciphersuite)
+        .policy(policy)
+        .wire_format(wire_format)
+        .point_encoding(point_encoding)
+        .retry(retry)
+        .pool(pool);
+    if let Some(timeout) = connect_timeout {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(timeout) = timeout {
+        builder = builder.timeout(timeout);
+    }
+    if let Some(key) = pinned_key {
+        builder = builder.pin_public_key(key);
+    }
+    // The standby attests with a mock key of its own in local mode
+    let standby = match &command {
+        Command::Replicate { standby } => {
+            let mut policy = policy_source.load()?;
+            policy.mock_signing_key = standby_mock_signing_key;
+            Some(builder.clone().address(*standby).policy(policy).build()?)
+        }
+        _ => None,
+    };
+    let mut clients = BTreeMap::new();
+    for (key_id, mut endpoints) in shards {
+        let address = endpoints.remove(0);
+        let client = builder.clone().address(address).failover(endpoints).key_id(key_id.as_str());
+        clients.insert(key_id, client.build()?);
+    }
+    let client = builder.address(address).failover(failover).key_id(options.key_id.as_str());
+    let router = Router::new(client.build()?, clients);
+    let (client, key_id) = (router.client(&options.key_id), &options.key_id);
+
+    // The servers trace each request they answer instead
+    let mut span =
+        (!serving).then(|| Span::start(format!("oprf-parent {}", name), SpanKind::Internal));
+    if let Some(span) = &mut span {
+        span.set("oprf.command", name.as_str());
+        span.set("oprf.ciphersuite", client.ciphersuite().identifier());
+        span.set("oprf.key_id", key_id.as_str());
+    }
+
+    let result = match command {
+        Command::Eval { inputs } => match client.ciphersuite() {
+            CiphersuiteId::Bn254Sha256 => {
+                evaluate::<Bn254Sha256>(client, &inputs, sequential, pipeline_depth, &options)
+            }
+            CiphersuiteId::P256Sha256 => {
+                evaluate::<P256Sha256>(client, &inputs, sequential, pipeline_depth, &options)
+            }
+        },
+        Command::Batch { file: path } => {
+            let path = match (path, input_path) {
+                (Some(_), Some(_)) => return Err("batch takes one input file".into()),
+                (Some(path), None) | (None, Some(path)) => path,
+                (None, None) => return Err("batch requires an input file".into()),
+            };
+            let inputs = batch::read_inputs(&path)?;
+            println!("[Parent] Read {} inputs from {}", inputs.len(), path);
+            let output = output_path.as_deref();
+            match client.ciphersuite() {
+                CiphersuiteId::Bn254Sha256 => evaluate_file::<Bn254Sha256>(
+                    client, &inputs, sequential, pipeline_depth, chunking, output, &options,
+                ),
+                CiphersuiteId::P256Sha256 => evaluate_file::<P256Sha256>(
+                    client, &inputs, sequential, pipeline_depth, chunking, output, &options,
+                ),
+            }
+        }
+        Command::Bench { count } => match client.ciphersuite() {
+            CiphersuiteId::Bn254Sha256 => {
+                bench::<Bn254Sha256>(client, count, pipeline_depth, &options)
+            }
+            CiphersuiteId::P256Sha256 => {
+                bench::<P256Sha256>(client, count, pipeline_depth, &options)
+            }
+        },
+        Command::Verify { input, output } => {
+            let output = decode_hex(&output, "expected output")?;
+            match client.ciphersuite() {
+                CiphersuiteId::Bn254Sha256 => {
+                    verify_output::<Bn254Sha256>(client, &input, &output, &options)
+                }
+                CiphersuiteId::P256Sha256 => {
+                    verify_output::<P256Sha256>(client, &input, &output, &options)
+                }
+            }
+        }
+        Command::PublicKey => match client.ciphersuite() {
+            CiphersuiteId::Bn254Sha256 => get_public_key::<Bn254Sha256>(client, key_id),
+            CiphersuiteId::P256Sha256 => get_public_key::<P256Sha256>(client, key_id),
+        },
+        Command::Attest => match client.ciphersuite() {
+            CiphersuiteId::Bn254Sha256 => get_attestation::<Bn254Sha256>(client, key_id),
+            CiphersuiteId::P256Sha256 => get_attestation::<P256Sha256>(client, key_id),
+        },
+        Command::Health => health(client),
+        Command::Stats => stats(client),
+        Command::SelfTest => self_test(client),
+        Command::AuditLog { from } => audit_log(client, from, output_path.as_deref()),
+        Command::Rotate => match client.ciphersuite() {
+            CiphersuiteId::Bn254Sha256 => {
+                rotate_key::<Bn254Sha256>(client, &access, key_id, grace_period_secs)
+            }
+            CiphersuiteId::P256Sha256 => {
+                rotate_key::<P256Sha256>(client, &access, key_id, grace_period_secs)
+            }
+        },
+        Command::Admin(action) => {
+            if access.operator_key.is_none() {
+                return Err("admin requires --operator-key".into());
+            }
+            if let AdminAction::Shutdown { file: Some(backup) } = &action {
+                // The data path stops with the enclave, so back up first
+                let kms = kms_config_from_env(kms_proxy_port)?;
+                export_keys(client, &access, backup, kms_key_id.as_deref().unwrap_or(""), kms)?;
+            }
+            let connection = access.connect_admin(client)?;
+            admin_command(client, connection, action, key_id, grace_period_secs)
+        }
+        Command::InjectKey { file: path } => {
+            let epoch = options.epoch.unwrap_or(0);
+            match client.ciphersuite() {
+                CiphersuiteId::Bn254Sha256 => {
+                    inject_key::<Bn254Sha256>(client, &access, &path, key_id, epoch)
+                }
+                CiphersuiteId::P256Sha256 => {
+                    inject_key::<P256Sha256>(client, &access, &path, key_id, epoch)
+                }
+            }
+        }
+        Command::ExportKeys { file: path } => {
+            let kms = kms_config_from_env(kms_proxy_port)?;
+            export_keys(client, &access, &path, kms_key_id.as_deref().unwrap_or(""), kms)
+        }
+        Command::ImportKeys { file: path } => {
+            import_keys(client, &access, &path, kms_config_from_env(kms_proxy_port)?)
+        }
+        Command::ProvisionSeed { file: path } => {
+            provision_seed(client, &access, &path, kms_config_from_env(kms_proxy_port)?)
+        }
+        Command::Replicate { .. } => {
+            let standby = standby.as_ref().expect("standby client is built for replicate");
+            replicate(client, standby, &access, &standby_access)
+        }
+        Command::Watch => {
+            let port = heartbeat_port.unwrap_or(DEFAULT_HEARTBEAT_PORT);
+            println!("[Parent] Watching heartbeats on port {}", port);
+            Watchdog::new(client.clone(), port, max_missed_heartbeats)
+                .run(|event| report_heartbeat(event, true))
+        }
+        Command::ServeHttp { addr } => {
+            #[cfg(feature = "http")]
+            return {
+                let api_keys = match api_keys {
+                    Some(path) => {
+                        let keys = quota::ApiKeys::load(&path)?;
+                        println!("[Parent] Admitting {} API keys from {}", keys.len(), path);
+                        Some(std::sync::Arc::new(keys))
+                    }
+                    None => None,
+                };
+                serve_reloading(
+                    &router,
+                    policy_source,
+                    &addr,
+                    daemon,
+                    pid_file.as_deref(),
+                    heartbeat_port.map(|port| (port, max_missed_heartbeats)),
+                    move |listener, upstream| http::serve(listener, upstream, api_keys),
+                )
+            };
+            #[cfg(not(feature = "http"))]
+            Err(format!("Cannot serve HTTP on {}: built without the http feature", addr).into())
+        }
+        Command::ServeJsonRpc { addr } => {
+            #[cfg(feature = "jsonrpc")]
+            return serve_reloading(
+                &router,
+                policy_source,
+                &addr,
+                daemon,
+                pid_file.as_deref(),
+                heartbeat_port.map(|port| (port, max_missed_heartbeats)),
+                jsonrpc::serve,
+            );
+            #[cfg(not(feature = "jsonrpc"))]
+            Err(format!("Cannot serve JSON-RPC on {}: built without the jsonrpc feature", addr)
+                .into())
+        }
+        Command::ServeGrpc { addr } => {
+            #[cfg(feature = "grpc")]
+            return serve_reloading(
+                &router,
+                policy_source,
+                &addr,
+                daemon,
+                pid_file.as_deref(),
+                heartbeat_port.map(|port| (port, max_missed_heartbeats)),
+                grpc::serve,
+            );
+            #[cfg(not(feature = "grpc"))]
+            Err(format!("Cannot serve gRPC on {}: built without the grpc feature", addr).into())
+        }
+    };
+    let result = match &mut span {
+        Some(span) => span.check(result),
+        None => result,
+    };
+    drop(span);
+    trace::flush();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_line() {
+        Cli::command().debug_assert();
+
+        // Options may come after the command, even a nested one
+        let cli = Cli::try_parse_from(["oprf-parent", "admin", "rotate", "--grace-period", "60"])
+            .unwrap();
+        assert!(matches!(cli.command, Command::Admin(AdminAction::Rotate)));
+        assert_eq!(cli.admin.grace_period, Some(60));
+        let cli = Cli::try_parse_from(["oprf-parent", "--shard", "a=1:2,3:4", "bench"]).unwrap();
+        assert!(matches!(cli.command, Command::Bench { count: DEFAULT_BENCH_COUNT }));
+        assert_eq!(cli.connection.shard[0].1.len(), 2);
+
+        for args in [&["eval"][..], &["bench", "0"], &["pubkey", "extra"], &["admin"], &["nope"]] {
+            assert!(Cli::try_parse_from([&["oprf-parent"][..], args].concat()).is_err());
+        }
+    }
+}
@@ -0,0 +1,448 @@
+//! Attestation policy: the enclave images the parent is willing to talk to.
+//!
+//! The policy is a JSON file listing the PCR values of every accepted image
+//! (e.g. the current and the previous release):
+//!
+//! ```json
+//! {
+//!   "allowed_images": [
+//!     { "name": "v1.4.0", "pcr0": "<96 hex chars>", "pcr1": "...", "pcr2": "..." }
+//!   ]
+//! }
+//! ```
+//!
+//! PCR0/1/2 are required for every image, PCR3 (IAM role of the parent) and
+//! PCR4 (parent instance ID) are checked when present. An attestation must
+//! match every listed PCR of at least one image. Without a policy, NSM
+//! attestations are rejected and only mock attestations are accepted.
+//!
+//! Signed images additionally measure their signing certificate into PCR8.
+//! A top-level `"pcr8"` pins it for every image, so only EIFs signed by the
+//! release key are accepted whatever their other PCRs.
+//!
+//! NSM documents must be authenticated before anything else is checked: a
+//! certificate chain from a trusted root, selected by `root_of_trust` (the
+//! embedded AWS root by default, see [`crate::roots`]), to the certificate
+//! whose key signed the document, see [`oprf_common::nitro`]. The PCRs,
+//! timestamp and user data are only read from a verified document.
+//!
+//! Attestations must also be recent: `max_age_secs` (default 300) bounds the
+//! age of the document's timestamp and `max_clock_skew_secs` (default 60) how
+//! far it may lie in the future.
+//!
+//! The enclave picks the protocol version from those the parent offers in an
+//! unauthenticated `Hello`, so `min_protocol_version` (default 4, the
+//! version signing every response) bounds what a man in the middle can
+//! downgrade the connection to; the parent offers no lower version and
+//! refuses an enclave answering with one.
+//!
+//! Mock attestations of local mode are signed with an ephemeral Ed25519 key
+//! of the enclave, whose public key is given with `--mock-signing-key`; they
+//! are rejected without it.
+//!
+//! Checks fail with an [`AttestationError`]; policy refusals are told apart
+//! from malformed documents by [`AttestationError::is_policy_violation`].
+
+use crate::roots::RootOfTrust;
+use oprf_common::claims::Claims;
+use oprf_common::ed25519::VerifyingKey;
+use oprf_common::{AttestationDocument, AttestationError, SUPPORTED_PROTOCOL_VERSIONS};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Length of a PCR value (SHA-384) in bytes
+const PCR_LEN: usize = 48;
+
+/// Oldest attestation accepted unless the policy sets `max_age_secs`
+pub const DEFAULT_MAX_AGE_SECS: u64 = 5 * 60;
+
+/// Clock skew allowed unless the policy sets `max_clock_skew_secs`
+pub const DEFAULT_MAX_CLOCK_SKEW_SECS: u64 = 60;
+
+fn default_max_age_secs() -> u64 {
+    DEFAULT_MAX_AGE_SECS
+}
+
+fn default_max_clock_skew_secs() -> u64 {
+    DEFAULT_MAX_CLOCK_SKEW_SECS
+}
+
+fn default_min_protocol_version() -> u32 {
+    oprf_common::PROTOCOL_VERSION
+}
+
+/// Check that the parent speaks `version`, so a minimum of it leaves a
+/// version to offer
+pub fn check_min_protocol_version(version: u32) -> Result<(), String> {
+    if !SUPPORTED_PROTOCOL_VERSIONS.contains(&version) {
+        return Err(format!(
+            "Minimum protocol version {} is not one of {:?}",
+            version, SUPPORTED_PROTOCOL_VERSIONS
+        ));
+    }
+    Ok(())
+}
+
+/// Expected PCR values of one accepted enclave image, hex-encoded
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AllowedImage {
+    /// Label for log messages, e.g. the release version
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Enclave image file
+    pub pcr0: String,
+    /// Linux kernel and bootstrap
+    pub pcr1: String,
+    /// Application
+    pub pcr2: String,
+    /// IAM role assigned to the parent instance
+    #[serde(default)]
+    pub pcr3: Option<String>,
+    /// Instance ID of the parent instance
+    #[serde(default)]
+    pub pcr4: Option<String>,
+}
+
+impl AllowedImage {
+    /// Every PCR this image pins, by index
+    fn expected(&self) -> Vec<(u64, &str)> {
+        let mut expected = vec![(0, self.pcr0.as_str()), (1, &self.pcr1), (2, &self.pcr2)];
+        expected.extend(self.pcr3.as_deref().map(|pcr| (3, pcr)));
+        expected.extend(self.pcr4.as_deref().map(|pcr| (4, pcr)));
+        expected
+    }
+
+    /// The first pinned PCR whose measured value differs, if any
+    fn mismatch(&self, pcrs: &BTreeMap<u64, Vec<u8>>) -> Option<AttestationError> {
+        self.expected().into_iter().find_map(|(index, expected)| {
+            check_pcr(pcrs, index, expected).err()
+        })
+    }
+
+    fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.pcr0)
+    }
+}
+
+/// Attestation policy loaded by the parent
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Accepted enclave images
+    pub allowed_images: Vec<AllowedImage>,
+    /// Expected PCR8, the hash of the EIF signing certificate, hex-encoded
+    #[serde(default)]
+    pub pcr8: Option<String>,
+    /// Oldest attestation accepted, in seconds
+    #[serde(default = "default_max_age_secs")]
+    pub max_age_secs: u64,
+    /// How far an attestation timestamp may lie in the future, in seconds
+    #[serde(default = "default_max_clock_skew_secs")]
+    pub max_clock_skew_secs: u64,
+    /// Lowest protocol version offered to and accepted from the enclave
+    #[serde(default = "default_min_protocol_version")]
+    pub min_protocol_version: u32,
+    /// Source of the trusted root certificates
+    #[serde(default)]
+    pub root_of_trust: RootOfTrust,
+    /// Fingerprints loaded from `root_of_trust`
+    #[serde(skip)]
+    trusted_roots: Vec<[u8; 32]>,
+    /// Public key of the enclave's mock attestation key (local mode)
+    #[serde(skip)]
+    pub mock_signing_key: Option<VerifyingKey>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            allowed_images: Vec::new(),
+            pcr8: None,
+            max_age_secs: DEFAULT_MAX_AGE_SECS,
+            max_clock_skew_secs: DEFAULT_MAX_CLOCK_SKEW_SECS,
+            min_protocol_version: default_min_protocol_version(),
+            root_of_trust: RootOfTrust::Aws,
+            trusted_roots: RootOfTrust::Aws.load().expect("embedded root loads"),
+            mock_signing_key: None,
+        }
+    }
+}
+
+impl Policy {
+    /// Load and validate a policy file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read(path)
+            .map_err(|e| format!("Failed to read policy {}: {}", path, e))?;
+        let mut policy: Policy = serde_json::from_slice(&contents)
+            .map_err(|e| format!("Failed to parse policy {}: {}", path, e))?;
+        if policy.allowed_images.is_empty() {
+            return Err(format!("Policy {} allows no enclave image", path));
+        }
+
+        let images = policy.allowed_images.iter_mut().flat_map(|image| {
+            [&mut image.pcr0, &mut image.pcr1, &mut image.pcr2]
+                .into_iter()
+                .chain(image.pcr3.as_mut())
+                .chain(image.pcr4.as_mut())
+        });
+        for pcr in images.chain(policy.pcr8.as_mut()) {
+            *pcr = pcr.to_ascii_lowercase();
+            if pcr.len() != 2 * PCR_LEN || hex::decode(&pcr).is_err() {
+                return Err(format!("Invalid PCR value in policy: {:?}", pcr));
+            }
+        }
+        check_min_protocol_version(policy.min_protocol_version)?;
+        policy.trusted_roots = policy.root_of_trust.load()?;
+        Ok(policy)
+    }
+
+    /// Authenticate an attestation, then check its timestamp and PCRs,
+    /// failing closed: an NSM attestation is only accepted if it chains to a
+    /// trusted root, is recent and some allowed image matches it. Returns
+    /// the checked claims.
+    pub fn check(&self, attestation: &AttestationDocument) -> Result<Claims, AttestationError> {
+        let claims =
+            Claims::verify(attestation, &self.trusted_roots, self.mock_signing_key.as_ref())?;
+        match (claims.root, &self.mock_signing_key) {
+            (Some(root), _) => {
+                println!("[Parent] Attestation chains to trusted root {}", hex::encode(root))
+            }
+            (None, Some(key)) => {
+                println!("[Parent] Mock attestation signed by {}", hex::encode(key.to_bytes()))
+            }
+            (None, None) => {}
+        }
+        self.check_timestamp(claims.timestamp_ms)?;
+
+        if self.allowed_images.is_empty() {
+            if attestation.is_mock {
+                println!("[Parent] No PCR policy configured, accepting mock attestation");
+                return Ok(claims);
+            }
+            return Err(AttestationError::NotConfigured(
+                "no PCR policy configured for NSM attestations".to_string(),
+            ));
+        }
+        self.check_pcrs(&claims.pcrs)?;
+        Ok(claims)
+    }
+
+    /// Reject attestations older than `max_age_secs` or too far in the future
+    fn check_timestamp(&self, timestamp_ms: u64) -> Result<(), AttestationError> {
+        let now_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| AttestationError::Clock(e.to_string()))?
+            .as_millis() as u64;
+        self.check_timestamp_at(timestamp_ms, now_ms)
+    }
+
+    fn check_timestamp_at(&self, timestamp_ms: u64, now_ms: u64) -> Result<(), AttestationError> {
+        if timestamp_ms > now_ms.saturating_add(self.max_clock_skew_secs * 1000) {
+            return Err(AttestationError::NotYetValid((timestamp_ms - now_ms) / 1000));
+        }
+        let age_secs = now_ms.saturating_sub(timestamp_ms) / 1000;
+        if age_secs > self.max_age_secs {
+            return Err(AttestationError::Expired { age_secs, max_age_secs: self.max_age_secs });
+        }
+        println!("[Parent] Attestation age: {}s", age_secs);
+        Ok(())
+    }
+
+    /// Check measured PCRs against the signing certificate and the allowlist
+    fn check_pcrs(&self, pcrs: &BTreeMap<u64, Vec<u8>>) -> Result<(), AttestationError> {
+        for index in [0, 1, 2, 3, 4, 8] {
+            if let Some(pcr) = pcrs.get(&index) {
+                println!("[Parent] PCR{}: {}", index, hex::encode(pcr));
+            }
+        }
+
+        if let Some(expected) = &self.pcr8 {
+            check_pcr(pcrs, 8, expected)?;
+            println!("[Parent] PCR8 matches the expected signing certificate");
+        }
+
+        match self.allowed_images.iter().find(|image| image.mismatch(pcrs).is_none()) {
+            Some(image) => {
+                println!("[Parent] PCRs match allowed image {}", image.label());
+                Ok(())
+            }
+            // With a single allowed image, report which PCR differs
+            None => match self.allowed_images.as_slice() {
+                [image] => Err(image.mismatch(pcrs).expect("image does not match")),
+                _ => Err(AttestationError::NoAllowedImage),
+            },
+        }
+    }
+}
+
+/// Compare the measured PCR `index` with its expected hex value
+fn check_pcr(
+    pcrs: &BTreeMap<u64, Vec<u8>>,
+    index: u64,
+    expected: &str,
+) -> Result<(), AttestationError> {
+    let got = pcrs.get(&index).map(hex::encode);
+    if got.as_deref() != Some(expected) {
+        return Err(AttestationError::PcrMismatch { index, expected: expected.to_string(), got });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oprf_common::nitro::testing::TestAuthority;
+
+    fn now_ms() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    }
+
+    fn pcrs() -> BTreeMap<u64, Vec<u8>> {
+        (0..3).map(|index| (index, vec![index as u8 + 1; PCR_LEN])).collect()
+    }
+
+    fn policy(authority: &TestAuthority) -> Policy {
+        let pcr = |index: u64| hex::encode(&pcrs()[&index]);
+        Policy {
+            allowed_images: vec![AllowedImage {
+                name: Some("test".to_string()),
+                pcr0: pcr(0),
+                pcr1: pcr(1),
+                pcr2: pcr(2),
+                pcr3: None,
+                pcr4: None,
+            }],
+            trusted_roots: vec![authority.root_fingerprint()],
+            ..Policy::default()
+        }
+    }
+
+    fn nsm(document: Vec<u8>) -> AttestationDocument {
+        AttestationDocument { is_mock: false, document, pcrs: None, user_data: Vec::new() }
+    }
+
+    #[test]
+    fn test_signed_document_accepted() {
+        let authority = TestAuthority::new(1);
+        let document = authority.document(&pcrs(), now_ms(), b"user data");
+        let claims = policy(&authority).check(&nsm(document)).unwrap();
+        assert_eq!(claims.user_data.as_deref(), Some(&b"user data"[..]));
+        assert_eq!(claims.root, Some(authority.root_fingerprint()));
+    }
+
+    #[test]
+    fn test_unauthenticated_documents_refused() {
+        let authority = TestAuthority::new(1);
+        let document = authority.document(&pcrs(), now_ms(), b"");
+
+        // The default policy trusts the AWS root only
+        let mut default = policy(&authority);
+        default.trusted_roots = Policy::default().trusted_roots;
+        let error = default.check(&nsm(document.clone())).err().unwrap();
+        assert!(matches!(error, AttestationError::UntrustedRoot(_)));
+        assert!(error.is_policy_violation());
+
+        // Claims are not read from a document whose signature fails
+        let mut tampered = document;
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        let error = policy(&authority).check(&nsm(tampered)).err().unwrap();
+        assert!(matches!(error, AttestationError::SignatureInvalid(_)));
+        assert!(!error.is_policy_violation());
+    }
+
+    #[test]
+    fn test_claims_checked_after_verification() {
+        let authority = TestAuthority::new(1);
+        let policy = policy(&authority);
+
+        let mut other_pcrs = pcrs();
+        other_pcrs.insert(2, vec![0xee; PCR_LEN]);
+        let document = authority.document(&other_pcrs, now_ms(), b"");
+        assert!(matches!(
+            policy.check(&nsm(document)),
+            Err(AttestationError::PcrMismatch { index: 2, .. })
+        ));
+
+        let stale = now_ms() - (DEFAULT_MAX_AGE_SECS + 10) * 1000;
+        let document = authority.document(&pcrs(), stale, b"");
+        assert!(matches!(policy.check(&nsm(document)), Err(AttestationError::Expired { .. })));
+
+        let ahead = now_ms() + (DEFAULT_MAX_CLOCK_SKEW_SECS + 10) * 1000;
+        let document = authority.document(&pcrs(), ahead, b"");
+        assert!(matches!(policy.check(&nsm(document)), Err(AttestationError::NotYetValid(_))));
+    }
+
+    #[test]
+    fn test_timestamp_window() {
+        const NOW_MS: u64 = 1_700_000_000_000;
+        let policy = Policy { max_age_secs: 300, max_clock_skew_secs: 60, ..Policy::default() };
+        let check = |timestamp_ms| policy.check_timestamp_at(timestamp_ms, NOW_MS);
+
+        assert!(check(NOW_MS).is_ok());
+        assert!(check(NOW_MS - 300_999).is_ok());
+        assert!(matches!(
+            check(NOW_MS - 301_000),
+            Err(AttestationError::Expired { age_secs: 301, max_age_secs: 300 })
+        ));
+        assert!(check(NOW_MS + 60_000).is_ok());
+        assert!(matches!(check(NOW_MS + 60_001), Err(AttestationError::NotYetValid(60))));
+        assert!(matches!(check(NOW_MS + 3_600_000), Err(AttestationError::NotYetValid(3600))));
+        assert!(check(0).unwrap_err().is_policy_violation());
+
+        // Without skew, only the parent's own clock reading or earlier
+        let strict = Policy { max_clock_skew_secs: 0, ..policy.clone() };
+        assert!(strict.check_timestamp_at(NOW_MS, NOW_MS).is_ok());
+        assert!(strict.check_timestamp_at(NOW_MS + 1, NOW_MS).is_err());
+    }
+
+    #[test]
+    fn test_timestamp_limits_default() {
+        let pcr = "ab".repeat(PCR_LEN);
+        let image = format!(r#"{{"pcr0": "{0}", "pcr1": "{0}", "pcr2": "{0}"}}"#, pcr);
+        let json = format!(r#"{{"allowed_images": [{}]}}"#, image);
+        let policy: Policy = serde_json::from_str(&json).unwrap();
+        assert_eq!(policy.max_age_secs, DEFAULT_MAX_AGE_SECS);
+        assert_eq!(policy.max_clock_skew_secs, DEFAULT_MAX_CLOCK_SKEW_SECS);
+
+        let json = format!(
+            r#"{{"allowed_images": [{}], "max_age_secs": 30, "max_clock_skew_secs": 5}}"#,
+            image
+        );
+        let policy: Policy = serde_json::from_str(&json).unwrap();
+        assert_eq!((policy.max_age_secs, policy.max_clock_skew_secs), (30, 5));
+    }
+
+    #[test]
+    fn test_min_protocol_version() {
+        let pcr = "ab".repeat(PCR_LEN);
+        let image = format!(r#"{{"pcr0": "{0}", "pcr1": "{0}", "pcr2": "{0}"}}"#, pcr);
+        let json = format!(r#"{{"allowed_images": [{}]}}"#, image);
+        let policy: Policy = serde_json::from_str(&json).unwrap();
+        assert_eq!(policy.min_protocol_version, oprf_common::PROTOCOL_VERSION);
+
+        let json = format!(r#"{{"allowed_images": [{}], "min_protocol_version": 3}}"#, image);
+        let policy: Policy = serde_json::from_str(&json).unwrap();
+        assert_eq!(policy.min_protocol_version, 3);
+
+        assert!(check_min_protocol_version(1).is_ok());
+        assert!(check_min_protocol_version(0).is_err());
+        assert!(check_min_protocol_version(oprf_common::PROTOCOL_VERSION + 1).is_err());
+    }
+
+    #[test]
+    fn test_nsm_refused_without_images() {
+        let authority = TestAuthority::new(1);
+        let mut policy = policy(&authority);
+        policy.allowed_images.clear();
+        let document = authority.document(&pcrs(), now_ms(), b"");
+        assert!(matches!(
+            policy.check(&nsm(document)),
+            Err(AttestationError::NotConfigured(_))
+        ));
+    }
+}
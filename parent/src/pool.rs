@@ -0,0 +1,184 @@
+//! Pool of warm connections to the enclave.
+//!
+//! Opening a connection costs a round trip for the handshake and, from
+//! protocol version 3 on, an attestation check and a channel handshake: more
+//! than an evaluation. A client built with a pool keeps up to its size of
+//! connections open once their request is answered, and
+//! [`crate::client::OprfClient::request`] sends over the most recently used
+//! one rather than opening another.
+//!
+//! Idle connections are checked before reuse. One idle for longer than the
+//! pool's idle timeout is closed, as is one the enclave closed, or wrote to
+//! unasked, while it sat in the pool. The enclave closes connections idle
+//! for its own idle timeout (30 seconds by default), so the pool's should be
+//! shorter. A request that still finds its pooled connection broken is sent
+//! again over a new one.
+
+use crate::connection::Connection;
+use oprf_common::config::{DEFAULT_POOL_IDLE_TIMEOUT_SECS, DEFAULT_POOL_SIZE};
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// How many connections a pool keeps open, and for how long
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolLimits {
+    /// Idle connections kept open at most; none are kept if zero
+    pub size: usize,
+    /// Time a connection may sit in the pool
+    pub idle_timeout: Duration,
+}
+
+impl Default for PoolLimits {
+    fn default() -> Self {
+        Self {
+            size: DEFAULT_POOL_SIZE,
+            idle_timeout: Duration::from_secs(DEFAULT_POOL_IDLE_TIMEOUT_SECS),
+        }
+    }
+}
+
+/// A connection waiting in the pool
+struct Idle {
+    connection: Connection<TcpStream>,
+    since: Instant,
+}
+
+/// Open connections waiting for a request, most recently used last
+pub struct ConnectionPool {
+    limits: PoolLimits,
+    idle: Mutex<Vec<Idle>>,
+}
+
+impl std::fmt::Debug for ConnectionPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectionPool")
+            .field("limits", &self.limits)
+            .field("idle", &self.lock().len())
+            .finish()
+    }
+}
+
+impl ConnectionPool {
+    pub fn new(limits: PoolLimits) -> Self {
+        Self {
+            limits,
+            idle: Mutex::new(Vec::with_capacity(limits.size)),
+        }
+    }
+
+    pub fn limits(&self) -> PoolLimits {
+        self.limits
+    }
+
+    /// The most recently used connection that is still usable, if any
+    pub fn take(&self) -> Option<Connection<TcpStream>> {
+        let mut idle = self.lock();
+        self.evict(&mut idle);
+        while let Some(Idle { connection, .. }) = idle.pop() {
+            if is_alive(connection.get_ref()) {
+                return Some(connection);
+            }
+        }
+        None
+    }
+
+    /// Keep `connection`, which has no request outstanding, for the next
+    /// request; the least recently used one is closed if the pool is full
+    pub fn put(&self, connection: Connection<TcpStream>) {
+        if self.limits.size == 0 {
+            return;
+        }
+        let mut idle = self.lock();
+        self.evict(&mut idle);
+        if idle.len() >= self.limits.size {
+            idle.remove(0);
+        }
+        idle.push(Idle {
+            connection,
+            since: Instant::now(),
+        });
+    }
+
+    /// Close the connections idle for longer than the idle timeout
+    fn evict(&self, idle: &mut Vec<Idle>) {
+        idle.retain(|idle| idle.since.elapsed() < self.limits.idle_timeout);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Idle>> {
+        self.idle.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Whether an idle connection is still open with nothing to read: a closed
+/// one reads end of file, and anything else read is a frame never asked for
+fn is_alive(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let alive = matches!(stream.peek(&mut [0]), Err(e) if e.kind() == ErrorKind::WouldBlock);
+    stream.set_nonblocking(false).is_ok() && alive
+}
+
+#[cfg(all(test, feature = "local", not(feature = "nitro")))]
+mod tests {
+    use super::*;
+    use crate::client::tests::{address, builder, enclave};
+    use crate::client::OprfClient;
+
+    fn pool(size: usize, idle_timeout: Duration) -> ConnectionPool {
+        ConnectionPool::new(PoolLimits { size, idle_timeout })
+    }
+
+    /// Local port of a connection, telling connections apart
+    fn port(connection: &Connection<TcpStream>) -> u16 {
+        connection.get_ref().local_addr().unwrap().port()
+    }
+
+    fn connections(client: &OprfClient, count: usize) -> Vec<Connection<TcpStream>> {
+        (0..count).map(|_| client.connect().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_most_recently_used_taken_first() {
+        let (enclave, policy) = enclave();
+        let client = builder(address(&enclave), &policy).build().unwrap();
+        let pool = pool(2, Duration::from_secs(60));
+        let [first, second, third] = connections(&client, 3).try_into().ok().unwrap();
+        let ports = [port(&first), port(&second), port(&third)];
+        pool.put(first);
+        pool.put(second);
+        // The pool is full, so the least recently used is closed
+        pool.put(third);
+        assert_eq!(port(&pool.take().unwrap()), ports[2]);
+        assert_eq!(port(&pool.take().unwrap()), ports[1]);
+        assert!(pool.take().is_none());
+
+        let none = self::pool(0, Duration::from_secs(60));
+        none.put(client.connect().unwrap());
+        assert!(none.take().is_none());
+    }
+
+    #[test]
+    fn test_idle_connections_evicted() {
+        let (enclave, policy) = enclave();
+        let client = builder(address(&enclave), &policy).build().unwrap();
+        let pool = pool(4, Duration::from_millis(50));
+        pool.put(client.connect().unwrap());
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(pool.take().is_none());
+    }
+
+    #[test]
+    fn test_closed_connections_discarded() {
+        let (enclave, policy) = enclave();
+        let client = builder(address(&enclave), &policy).build().unwrap();
+        let pool = pool(4, Duration::from_secs(60));
+        pool.put(client.connect().unwrap());
+        // Stopping closes the enclave's idle connections
+        enclave.stop();
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(pool.take().is_none());
+    }
+}
@@ -0,0 +1,357 @@
+//! API keys of the HTTP API and their evaluation quotas.
+//!
+//! With `--api-keys <file>`, `serve-http` only answers the `/v1/` endpoints
+//! for requests carrying a known key, in an `Authorization: Bearer <key>` or
+//! `X-Api-Key` header. The file lists the SHA-256 of every key, so it holds
+//! no secret, with the evaluations the key may make per UTC day and per UTC
+//! calendar month:
+//!
+//! ```json
+//! {
+//!   "keys": [
+//!     { "name": "payments", "key_sha256": "<64 hex chars>", "daily": 100000, "monthly": 2000000 }
+//!   ]
+//! }
+//! ```
+//!
+//! A quota left out is unlimited. Each evaluation is charged when it is
+//! forwarded, whatever the enclave answers, and refused with `RateLimited`
+//! once either quota of its key is used up. Usage is counted in the parent's
+//! memory, so it starts over when the parent restarts; run one gateway per
+//! set of keys for the quotas to hold. [`ApiKeys::metrics`] reports it in
+//! the Prometheus text format.
+
+use oprf_common::{sha256_hex, ErrorCode, ErrorResponse};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// One key of the API keys file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ApiKey {
+    /// Team or service the key belongs to, the label of its metrics
+    name: String,
+    /// Hex SHA-256 of the key
+    key_sha256: String,
+    /// Evaluations per UTC day
+    #[serde(default)]
+    daily: Option<u64>,
+    /// Evaluations per UTC calendar month
+    #[serde(default)]
+    monthly: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ApiKeysFile {
+    keys: Vec<ApiKey>,
+}
+
+/// Evaluations of one key in the current day and month
+#[derive(Debug, Default)]
+struct Usage {
+    /// Days since the epoch of `daily`
+    day: u64,
+    daily: u64,
+    /// Months since the epoch of `monthly`
+    month: u64,
+    monthly: u64,
+    /// Evaluations charged since startup
+    total: u64,
+    /// Evaluations refused for a used-up quota since startup
+    refused: u64,
+}
+
+/// The keys admitted to the HTTP API, with their usage
+#[derive(Debug)]
+pub struct ApiKeys {
+    keys: Vec<ApiKey>,
+    /// Index into `keys` by key hash
+    by_hash: HashMap<String, usize>,
+    /// Usage of each key of `keys`
+    usage: Mutex<Vec<Usage>>,
+}
+
+impl ApiKeys {
+    /// Load and validate an API keys file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read(path)
+            .map_err(|e| format!("Failed to read API keys {}: {}", path, e))?;
+        Self::parse(&contents, path)
+    }
+
+    /// Validate the contents of the API keys file at `path`
+    fn parse(contents: &[u8], path: &str) -> Result<Self, String> {
+        let file: ApiKeysFile = serde_json::from_slice(contents)
+            .map_err(|e| format!("Failed to parse API keys {}: {}", path, e))?;
+        let mut by_hash = HashMap::new();
+        let mut keys = file.keys;
+        for (index, key) in keys.iter_mut().enumerate() {
+            key.key_sha256 = key.key_sha256.to_ascii_lowercase();
+            if key.key_sha256.len() != 64 || hex::decode(&key.key_sha256).is_err() {
+                return Err(format!("Invalid key_sha256 of API key {:?}", key.name));
+            }
+            if by_hash.insert(key.key_sha256.clone(), index).is_some() {
+                return Err(format!("API key {:?} is listed twice", key.name));
+            }
+        }
+        if keys.is_empty() {
+            return Err(format!("API keys {} list no key", path));
+        }
+        let usage = Mutex::new(keys.iter().map(|_| Usage::default()).collect());
+        Ok(Self { keys, by_hash, usage })
+    }
+
+    /// How many keys are admitted
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Index of the key presented in a request, `None` for an unknown key
+    pub fn authenticate(&self, key: &str) -> Option<usize> {
+        self.by_hash.get(&sha256_hex(key.as_bytes())).copied()
+    }
+
+    /// Charge `count` evaluations to the key at `index`, refusing them if
+    /// they would exceed its daily or monthly quota
+    pub fn charge(&self, index: usize, count: u64) -> Result<(), ErrorResponse> {
+        self.charge_at(index, count, now_secs())
+    }
+
+    /// Charge as [`ApiKeys::charge`] does at unix time `now`
+    fn charge_at(&self, index: usize, count: u64, now: u64) -> Result<(), ErrorResponse> {
+        let key = &self.keys[index];
+        let mut usage = self.usage.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let usage = &mut usage[index];
+        usage.roll_over(now);
+        let exceeded = |used: u64, quota: Option<u64>| {
+            quota.is_some_and(|quota| used.saturating_add(count) > quota)
+        };
+        let period = if exceeded(usage.daily, key.daily) {
+            "daily"
+        } else if exceeded(usage.monthly, key.monthly) {
+            "monthly"
+        } else {
+            usage.daily += count;
+            usage.monthly += count;
+            usage.total += count;
+            return Ok(());
+        };
+        usage.refused += count;
+        Err(ErrorResponse::new(
+            ErrorCode::RateLimited,
+            format!("API key {:?} has used its {} quota", key.name, period),
+        ))
+    }
+
+    /// Usage and quotas of every key in the Prometheus text format
+    pub fn metrics(&self) -> String {
+        let mut usage = self.usage.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = now_secs();
+        for usage in usage.iter_mut() {
+            usage.roll_over(now);
+        }
+
+        let mut totals = Vec::new();
+        let mut refused = Vec::new();
+        let mut used = Vec::new();
+        let mut limits = Vec::new();
+        for (key, usage) in self.keys.iter().zip(usage.iter()) {
+            let label = format!("key=\"{}\"", escape(&key.name));
+            totals.push((label.clone(), usage.total));
+            refused.push((label.clone(), usage.refused));
+            for (period, count, limit) in
+                [("daily", usage.daily, key.daily), ("monthly", usage.monthly, key.monthly)]
+            {
+                let labels = format!("{},period=\"{}\"", label, period);
+                used.push((labels.clone(), count));
+                limits.extend(limit.map(|limit| (labels, limit)));
+            }
+        }
+
+        let mut out = String::new();
+        for (name, kind, help, samples) in [
+            (
+                "oprf_api_key_evaluations_total",
+                "counter",
+                "Evaluations charged to the API key since startup",
+                totals,
+            ),
+            (
+                "oprf_api_key_refused_total",
+                "counter",
+                "Evaluations refused for a used-up quota since startup",
+                refused,
+            ),
+            (
+                "oprf_api_key_quota_used",
+                "gauge",
+                "Evaluations charged to the API key in the current day or month",
+                used,
+            ),
+            (
+                "oprf_api_key_quota_limit",
+                "gauge",
+                "Evaluations the API key may make per day or month",
+                limits,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+            }
+        }
+        out
+    }
+}
+
+impl Usage {
+    /// Start the counts of a new day or month
+    fn roll_over(&mut self, now: u64) {
+        let day = now / SECS_PER_DAY;
+        if day != self.day {
+            self.day = day;
+            self.daily = 0;
+        }
+        let month = month_of_day(day);
+        if month != self.month {
+            self.month = month;
+            self.monthly = 0;
+        }
+    }
+}
+
+/// Months since January 1970 of the UTC day `day` since the epoch
+fn month_of_day(day: u64) -> u64 {
+    // Civil-from-days of the proleptic Gregorian calendar, with years
+    // starting in March so the leap day comes last
+    let days = day + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let march_month = (5 * day_of_year + 2) / 153;
+    let month = if march_month < 10 { march_month + 3 } else { march_month - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year - 1970) * 12 + (month - 1)
+}
+
+/// A label value with its backslashes, quotes and newlines escaped
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2026-01-31T00:00:00Z
+    const JANUARY_31: u64 = 20_484 * SECS_PER_DAY;
+
+    fn keys(json: &str) -> Result<ApiKeys, String> {
+        ApiKeys::parse(json.as_bytes(), "keys.json")
+    }
+
+    fn payments() -> ApiKeys {
+        let hash = sha256_hex(b"secret-payments").to_ascii_uppercase();
+        keys(&format!(
+            r#"{{"keys": [
+                {{"name": "payments", "key_sha256": "{}", "daily": 3, "monthly": 5}},
+                {{"name": "search", "key_sha256": "{}"}}
+            ]}}"#,
+            hash,
+            sha256_hex(b"secret-search")
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let keys = payments();
+        assert_eq!(keys.len(), 2);
+        // Hashes in the file match whatever their case
+        assert_eq!(keys.authenticate("secret-payments"), Some(0));
+        assert_eq!(keys.authenticate("secret-search"), Some(1));
+        assert_eq!(keys.authenticate("secret-Payments"), None);
+        assert_eq!(keys.authenticate(&sha256_hex(b"secret-payments")), None);
+        assert_eq!(keys.authenticate(""), None);
+    }
+
+    #[test]
+    fn test_invalid_files_refused() {
+        let hash = sha256_hex(b"key");
+        let key = |name: &str| format!(r#"{{"name": "{}", "key_sha256": "{}"}}"#, name, hash);
+        let twice = format!(r#"{{"keys": [{}, {}]}}"#, key("a"), key("b"));
+        assert_eq!(keys(&twice).unwrap_err(), "API key \"b\" is listed twice");
+        let short = r#"{"keys": [{"name": "a", "key_sha256": "abcd"}]}"#;
+        assert_eq!(keys(short).unwrap_err(), "Invalid key_sha256 of API key \"a\"");
+        assert_eq!(keys(r#"{"keys": []}"#).unwrap_err(), "API keys keys.json list no key");
+        let unknown = format!(r#"{{"keys": [{}]}}"#, key("a").replace('}', r#", "weekly": 1}"#));
+        assert!(keys(&unknown).unwrap_err().starts_with("Failed to parse API keys keys.json"));
+    }
+
+    #[test]
+    fn test_refused_at_the_limit() {
+        let keys = payments();
+        let now = JANUARY_31 + 60;
+        keys.charge_at(0, 2, now).unwrap();
+        // A charge that would cross the quota is refused whole
+        let error = keys.charge_at(0, 2, now).unwrap_err();
+        assert_eq!(error.code, ErrorCode::RateLimited);
+        assert_eq!(error.message, "API key \"payments\" has used its daily quota");
+        keys.charge_at(0, 1, now).unwrap();
+        assert!(keys.charge_at(0, 1, now).is_err());
+
+        // Unlimited keys are never refused
+        keys.charge_at(1, 1_000_000, now).unwrap();
+        let metrics = keys.metrics();
+        assert!(metrics.contains("oprf_api_key_evaluations_total{key=\"payments\"} 3\n"));
+        assert!(metrics.contains("oprf_api_key_refused_total{key=\"payments\"} 3\n"));
+        assert!(metrics.contains("oprf_api_key_evaluations_total{key=\"search\"} 1000000\n"));
+        assert!(!metrics.contains("oprf_api_key_quota_limit{key=\"search\""));
+    }
+
+    #[test]
+    fn test_daily_and_monthly_rollover() {
+        let keys = payments();
+        keys.charge_at(0, 3, JANUARY_31).unwrap();
+        assert!(keys.charge_at(0, 1, JANUARY_31 + SECS_PER_DAY - 1).is_err());
+
+        // February 1: a new day and a new month
+        let february = JANUARY_31 + SECS_PER_DAY;
+        keys.charge_at(0, 3, february).unwrap();
+        let error = keys.charge_at(0, 1, february).unwrap_err();
+        assert!(error.message.ends_with("its daily quota"));
+
+        // February 2: a new day, but the month has 3 of its 5 used
+        let next_day = february + SECS_PER_DAY;
+        keys.charge_at(0, 2, next_day).unwrap();
+        let error = keys.charge_at(0, 1, next_day).unwrap_err();
+        assert!(error.message.ends_with("its monthly quota"));
+        assert!(keys.charge_at(0, 1, next_day + 20 * SECS_PER_DAY).is_err());
+        keys.charge_at(0, 3, next_day + 27 * SECS_PER_DAY).unwrap();
+    }
+
+    #[test]
+    fn test_month_of_day() {
+        assert_eq!(month_of_day(0), 0);
+        assert_eq!(month_of_day(11_322), 371);
+        // 2024-02-29 and 2024-03-01
+        assert_eq!(month_of_day(19_782), 649);
+        assert_eq!(month_of_day(19_783), 650);
+        assert_eq!(month_of_day(20_484), 672);
+        assert_eq!(month_of_day(20_485), 673);
+    }
+}
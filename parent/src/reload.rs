@@ -0,0 +1,90 @@
+//! Reloading the attestation policy of the API servers on SIGHUP.
+//!
+//! `serve-http`, `serve-jsonrpc` and `serve-grpc` forward through an
+//! [`Upstream`], whose router is replaced when the process receives SIGHUP:
+//! the `--policy` file is read again, with `--max-attestation-age`,
+//! `--min-protocol-version` and `--mock-signing-key` applied over it as on
+//! startup. Requests already
+//! forwarding finish under the policy they started with. A policy that
+//! fails to load is reported and the one in force is kept, so a bad edit
+//! cannot take the servers down.
+//!
+//! The signal handler only sets a flag; a thread of its own checks it every
+//! [`POLL_INTERVAL`] and does the reload.
+
+use crate::router::Router;
+use crate::service;
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use oprf_parent::policy::Policy;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// How often the reload thread checks for a SIGHUP
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Set by the SIGHUP handler, cleared by the reload thread
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_reload(_: nix::libc::c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// The router the API servers reach the enclaves through
+pub struct Upstream {
+    router: RwLock<Arc<Router>>,
+}
+
+impl Upstream {
+    pub fn new(router: Router) -> Arc<Self> {
+        Arc::new(Self {
+            router: RwLock::new(Arc::new(router)),
+        })
+    }
+
+    /// Router of the configuration in force
+    pub fn router(&self) -> Arc<Router> {
+        let router = self.router.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        Arc::clone(&router)
+    }
+
+    fn set_policy(&self, policy: Policy) {
+        let mut router = self.router.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *router = Arc::new(router.with_policy(policy));
+    }
+}
+
+/// Replace the policy of `upstream` with the one `load` returns on every
+/// SIGHUP, for as long as the process runs
+pub fn on_sighup<F>(upstream: &Arc<Upstream>, load: F) -> std::io::Result<()>
+where
+    F: Fn() -> Result<Policy, String> + Send + 'static,
+{
+    let handler = SigHandler::Handler(request_reload);
+    let action = SigAction::new(handler, SaFlags::SA_RESTART, SigSet::empty());
+    // The handler only stores to an atomic, which is async-signal-safe
+    unsafe { sigaction(Signal::SIGHUP, &action) }.map_err(std::io::Error::other)?;
+
+    let upstream = Arc::clone(upstream);
+    std::thread::Builder::new().name("oprf-reload".to_string()).spawn(move || loop {
+        std::thread::sleep(POLL_INTERVAL);
+        if !RELOAD_REQUESTED.swap(false, Ordering::Relaxed) {
+            continue;
+        }
+        service::notify("RELOADING=1");
+        match load() {
+            Ok(policy) => {
+                println!(
+                    "[Parent] Reloaded attestation policy: {} allowed images, max age {}s",
+                    policy.allowed_images.len(),
+                    policy.max_age_secs
+                );
+                upstream.set_policy(policy);
+            }
+            Err(e) => eprintln!("[Parent] Failed to reload, keeping the policy in force: {}", e),
+        }
+        service::notify("READY=1");
+    })?;
+    println!("[Parent] Reloading the attestation policy on SIGHUP");
+    Ok(())
+}
@@ -0,0 +1,50 @@
+//! Trust anchors for NSM attestation documents.
+//!
+//! An NSM document carries its certificate chain in `cabundle`, root first.
+//! The parent only accepts chains whose root is one of the configured trust
+//! anchors, identified by the SHA-256 fingerprint of their DER encoding. The
+//! policy selects either the embedded AWS Nitro Enclaves root or a bundle of
+//! PEM or DER certificates read from a file, e.g. for another partition or a
+//! test root:
+//!
+//! ```json
+//! { "root_of_trust": "aws" }
+//! { "root_of_trust": { "file": "/etc/oprf/nitro-roots.pem" } }
+//! ```
+
+use oprf_common::nitro::{aws_root, fingerprint, parse_certificates};
+use serde::Deserialize;
+
+
+/// Where the trusted root certificates come from
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum RootOfTrust {
+    /// The AWS Nitro Enclaves root embedded in the parent, see
+    /// [`oprf_common::nitro::AWS_NITRO_ROOT_G1_PEM`]
+    #[default]
+    Aws,
+    /// Certificates read from a file, PEM (one or more) or a single DER
+    File(String),
+}
+
+impl RootOfTrust {
+    /// Fingerprints of the trusted root certificates
+    pub fn load(&self) -> Result<Vec<[u8; 32]>, String> {
+        match self {
+            RootOfTrust::Aws => Ok(vec![fingerprint(&aws_root())]),
+            RootOfTrust::File(path) => {
+                let contents = std::fs::read(path)
+                    .map_err(|e| format!("Failed to read root certificates {}: {}", path, e))?;
+                let certificates = parse_certificates(&contents)
+                    .map_err(|e| format!("Invalid root certificates {}: {}", path, e))?;
+                println!(
+                    "[Parent] Loaded {} root certificate(s) from {}",
+                    certificates.len(),
+                    path
+                );
+                Ok(certificates.iter().map(|der| fingerprint(der)).collect())
+            }
+        }
+    }
+}
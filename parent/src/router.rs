@@ -0,0 +1,130 @@
+//! Routing requests to the enclaves holding their keys.
+//!
+//! Keys may be sharded over groups of enclaves, each group serving some of
+//! the key IDs. A [`Router`] holds a client per shard, reaching the
+//! endpoints of its group, and a default client for the other keys and for
+//! the messages about no key in particular, such as health checks.
+//! [`Router::request`] sends a message with the client of its key, see
+//! [`Message::key_id`].
+//!
+//! Within a group, a client fails over between its endpoints or, when
+//! balanced, spreads its connections over them, see [`crate::client`].
+
+use crate::client::OprfClient;
+use oprf_common::Message;
+use oprf_parent::policy::Policy;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+
+/// Clients of the enclaves, by the key IDs they serve
+#[derive(Debug, Clone)]
+pub struct Router {
+    default: OprfClient,
+    shards: BTreeMap<String, OprfClient>,
+}
+
+impl Router {
+    /// Route the keys of `shards` to their clients, and the rest to `default`
+    pub fn new(default: OprfClient, shards: BTreeMap<String, OprfClient>) -> Self {
+        Self { default, shards }
+    }
+
+    /// Client of the enclaves holding `key_id`
+    pub fn client(&self, key_id: &str) -> &OprfClient {
+        self.shards.get(key_id).unwrap_or(&self.default)
+    }
+
+    /// Client of the keys no shard holds
+    #[cfg_attr(
+        not(any(feature = "http", feature = "jsonrpc", feature = "grpc")),
+        allow(dead_code)
+    )]
+    pub fn default_client(&self) -> &OprfClient {
+        &self.default
+    }
+
+    /// Send `request` to the enclaves holding its key, see
+    /// [`OprfClient::request`]
+    #[cfg_attr(
+        not(any(feature = "http", feature = "jsonrpc", feature = "grpc")),
+        allow(dead_code)
+    )]
+    pub fn request<Resp: DeserializeOwned>(&self, request: &Message) -> std::io::Result<Resp> {
+        match request.key_id() {
+            Some(key_id) => self.client(key_id).request(request),
+            None => self.default.request(request),
+        }
+    }
+
+    /// The same router, every client accepting the images of `policy`
+    #[cfg_attr(
+        not(any(feature = "http", feature = "jsonrpc", feature = "grpc")),
+        allow(dead_code)
+    )]
+    pub fn with_policy(&self, policy: Policy) -> Self {
+        Self {
+            default: self.default.with_policy(policy.clone()),
+            shards: self
+                .shards
+                .iter()
+                .map(|(key_id, client)| (key_id.clone(), client.with_policy(policy.clone())))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "local", not(feature = "nitro")))]
+mod tests {
+    use super::*;
+    use crate::client::tests::{address, builder, enclaves};
+    use oprf_common::{
+        CiphersuiteId, GetPublicKeyRequest, HealthResponse, PublicKeyResponse, DEFAULT_KEY_ID,
+    };
+    use oprf_server::ConnectionLimits;
+    use std::io::ErrorKind;
+
+    fn public_key(key_id: &str) -> Message {
+        Message::GetPublicKey(GetPublicKeyRequest {
+            ciphersuite: CiphersuiteId::P256Sha256,
+            key_id: key_id.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_requests_routed_by_key() {
+        let (enclaves, policy) = enclaves(2, ConnectionLimits::default());
+        let default = builder(address(&enclaves[0]), &policy).build().unwrap();
+        let billing = builder(address(&enclaves[1]), &policy).build().unwrap();
+        let shards = BTreeMap::from([("billing".to_string(), billing.clone())]);
+        let router = Router::new(default.clone(), shards);
+
+        let routed: PublicKeyResponse = router.request(&public_key("billing")).unwrap();
+        let direct: PublicKeyResponse = billing.request(&public_key("billing")).unwrap();
+        assert_eq!(routed.public_key, direct.public_key);
+        let routed: PublicKeyResponse = router.request(&public_key(DEFAULT_KEY_ID)).unwrap();
+        let direct: PublicKeyResponse = default.request(&public_key(DEFAULT_KEY_ID)).unwrap();
+        assert_eq!(routed.public_key, direct.public_key);
+
+        // Messages about no key go to the default enclaves, which never made
+        // the billing key
+        let health: HealthResponse = router.request(&Message::Health).unwrap();
+        assert_eq!(health.key_count, 2);
+    }
+
+    #[test]
+    fn test_policy_replaced_on_every_client() {
+        let (enclaves, policy) = enclaves(2, ConnectionLimits::default());
+        let default = builder(address(&enclaves[0]), &policy).build().unwrap();
+        let billing = builder(address(&enclaves[1]), &policy).build().unwrap();
+        let router = Router::new(default, BTreeMap::from([("billing".to_string(), billing)]));
+        let _: PublicKeyResponse = router.request(&public_key("billing")).unwrap();
+
+        let refusing = router.with_policy(Policy::default());
+        for key_id in [DEFAULT_KEY_ID, "billing"] {
+            let error = refusing.request::<PublicKeyResponse>(&public_key(key_id)).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+        // The router it was made from keeps its policy
+        let _: PublicKeyResponse = router.request(&public_key("billing")).unwrap();
+    }
+}
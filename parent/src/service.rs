@@ -0,0 +1,193 @@
+//! Running the parent as a supervised service.
+//!
+//! - Readiness: once `serve-http`, `serve-jsonrpc` or `serve-grpc` is
+//!   listening, the parent sends `READY=1` to the socket a `Type=notify`
+//!   systemd unit names in `NOTIFY_SOCKET`, and brackets every policy
+//!   reload on SIGHUP with `RELOADING=1` and `READY=1`. Heartbeat alerts,
+//!   see [`crate::watchdog`], are set as its `STATUS=`. Without the variable
+//!   nothing is sent.
+//! - `--daemon` detaches the servers from the terminal once they listen, for
+//!   supervisors expecting the process to fork (`Type=forking`), and
+//!   `--pid-file` records the id of the process serving.
+//! - Exit codes tell failures apart, so a supervisor can stop restarting a
+//!   parent whose enclave it must not trust: [`EXIT_ATTESTATION`] when an
+//!   attestation is refused, [`EXIT_TRANSPORT`] when the enclave cannot be
+//!   reached, and 1 for anything else.
+
+use oprf_common::{AttestationError, OprfError};
+use std::error::Error;
+use std::io::{ErrorKind, IsTerminal};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::process::ExitCode;
+
+/// Exit status when the enclave's attestation is refused: malformed,
+/// unauthenticated or against the policy
+pub const EXIT_ATTESTATION: u8 = 3;
+
+/// Exit status when the enclave cannot be reached, or the connection to it
+/// breaks
+pub const EXIT_TRANSPORT: u8 = 4;
+
+/// Environment variable naming the service manager's notification socket
+const NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+
+/// Send `state`, e.g. `READY=1`, to the service manager if it waits for one.
+/// A failure to send is reported, not returned: the service runs on regardless.
+pub fn notify(state: &str) {
+    let Some(path) = std::env::var_os(NOTIFY_SOCKET_ENV) else {
+        return;
+    };
+    let sent = UnixDatagram::unbound().and_then(|socket| {
+        // A leading '@' names a socket in the abstract namespace
+        let address = match path.as_encoded_bytes().strip_prefix(b"@") {
+            Some(name) => SocketAddr::from_abstract_name(name)?,
+            None => SocketAddr::from_pathname(&path)?,
+        };
+        socket.send_to_addr(state.as_bytes(), &address)
+    });
+    if let Err(e) = sent {
+        eprintln!("[Parent] Failed to notify the service manager of {}: {}", state, e);
+    }
+}
+
+/// Detach from the terminal into a new session in the background; the
+/// process that called this exits. Output to a terminal is discarded from
+/// then on, output redirected elsewhere is kept.
+#[cfg_attr(
+    not(any(feature = "http", feature = "jsonrpc", feature = "grpc")),
+    allow(dead_code)
+)]
+pub fn daemonize() -> std::io::Result<()> {
+    nix::unistd::daemon(true, true).map_err(std::io::Error::other)?;
+    let null = std::fs::OpenOptions::new().read(true).write(true).open("/dev/null")?;
+    let streams = [
+        (std::io::stdin().is_terminal(), 0),
+        (std::io::stdout().is_terminal(), 1),
+        (std::io::stderr().is_terminal(), 2),
+    ];
+    for (_, fd) in streams.into_iter().filter(|(terminal, _)| *terminal) {
+        nix::unistd::dup2(null.as_raw_fd(), fd).map_err(std::io::Error::other)?;
+    }
+    Ok(())
+}
+
+/// Write the id of this process to `path`
+#[cfg_attr(
+    not(any(feature = "http", feature = "jsonrpc", feature = "grpc")),
+    allow(dead_code)
+)]
+pub fn write_pid_file(path: &str) -> std::io::Result<()> {
+    std::fs::write(path, format!("{}\n", std::process::id()))
+}
+
+/// Exit status of a command that failed with `error`
+pub fn exit_code(error: &(dyn Error + 'static)) -> ExitCode {
+    let mut next = Some(error);
+    while let Some(error) = next {
+        if is_attestation(error) {
+            return ExitCode::from(EXIT_ATTESTATION);
+        }
+        next = error.source();
+        if let Some(error) = error.downcast_ref::<std::io::Error>() {
+            // `source` skips the error an `io::Error` wraps
+            match error.get_ref() {
+                Some(inner) if is_attestation(inner) => return ExitCode::from(EXIT_ATTESTATION),
+                _ if is_transport(error) => return ExitCode::from(EXIT_TRANSPORT),
+                Some(inner) => next = Some(inner),
+                None => {}
+            }
+        }
+    }
+    ExitCode::FAILURE
+}
+
+fn is_attestation(error: &(dyn Error + 'static)) -> bool {
+    error.is::<AttestationError>()
+        || matches!(error.downcast_ref::<OprfError>(), Some(OprfError::Attestation(_)))
+}
+
+/// Whether `error` is the enclave being unreachable or the connection to it
+/// breaking, as opposed to e.g. a file that cannot be read
+fn is_transport(error: &std::io::Error) -> bool {
+    // vsock connections to a CID without an enclave fail with ENODEV
+    error.raw_os_error() == Some(nix::libc::ENODEV)
+        || matches!(
+            error.kind(),
+            ErrorKind::ConnectionRefused
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::NotConnected
+                | ErrorKind::AddrNotAvailable
+                | ErrorKind::BrokenPipe
+                | ErrorKind::TimedOut
+                | ErrorKind::WouldBlock
+                | ErrorKind::UnexpectedEof
+                | ErrorKind::HostUnreachable
+                | ErrorKind::NetworkUnreachable
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oprf_common::{ErrorCode, ErrorResponse};
+    use std::io::Error as IoError;
+    use std::time::Duration;
+
+    fn refused() -> AttestationError {
+        AttestationError::NotConfigured("no mock signing key".to_string())
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        let attestation = ExitCode::from(EXIT_ATTESTATION);
+        let transport = ExitCode::from(EXIT_TRANSPORT);
+        assert_eq!(exit_code(&refused()), attestation);
+        assert_eq!(exit_code(&OprfError::Attestation(refused())), attestation);
+        // However deep it is wrapped
+        let wrapped = IoError::new(ErrorKind::InvalidData, OprfError::Attestation(refused()));
+        assert_eq!(exit_code(&wrapped), attestation);
+        let boxed: Box<dyn Error> = Box::new(IoError::other(refused()));
+        assert_eq!(exit_code(boxed.as_ref()), attestation);
+
+        for kind in [ErrorKind::ConnectionRefused, ErrorKind::UnexpectedEof, ErrorKind::TimedOut] {
+            assert_eq!(exit_code(&IoError::from(kind)), transport, "{:?}", kind);
+        }
+        assert_eq!(exit_code(&IoError::from_raw_os_error(nix::libc::ENODEV)), transport);
+
+        // A refused request, or a file that cannot be read, is any failure
+        let refusal = ErrorResponse::new(ErrorCode::BadRequest, "Unknown key epoch 7");
+        assert_eq!(exit_code(&IoError::other(refusal)), ExitCode::FAILURE);
+        assert_eq!(exit_code(&IoError::from(ErrorKind::NotFound)), ExitCode::FAILURE);
+    }
+
+    #[test]
+    fn test_notify() {
+        let name = format!("oprf-notify-{}", std::process::id());
+        let socket = UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&name).unwrap())
+            .unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        std::env::set_var(NOTIFY_SOCKET_ENV, format!("@{}", name));
+        notify("READY=1");
+        std::env::remove_var(NOTIFY_SOCKET_ENV);
+        let mut state = [0; 16];
+        let len = socket.recv(&mut state).unwrap();
+        assert_eq!(&state[..len], b"READY=1");
+
+        // Without a service manager nothing is sent
+        notify("STOPPING=1");
+        socket.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        assert!(socket.recv(&mut state).is_err());
+    }
+
+    #[test]
+    fn test_pid_file() {
+        let path = std::env::temp_dir().join(format!("oprf-parent-{}.pid", std::process::id()));
+        write_pid_file(path.to_str().unwrap()).unwrap();
+        let pid = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(pid, format!("{}\n", std::process::id()));
+    }
+}
@@ -0,0 +1,405 @@
+//! OpenTelemetry traces of the client path.
+//!
+//! With an OTLP endpoint configured (`--otlp-endpoint`, or the standard
+//! `OTEL_EXPORTER_OTLP_ENDPOINT`), every command is recorded as a trace: a
+//! root span for the command, with child spans for connecting to the
+//! enclave, blinding, each request sent and awaited, verifying the response
+//! and unblinding. Ended spans are exported in the OTLP/HTTP JSON encoding
+//! to `<endpoint>/v1/traces` when the command is done, as each HTTP API
+//! request is answered, and whenever [`MAX_BUFFERED_SPANS`] are waiting.
+//!
+//! The trace of the calling service is joined through a W3C `traceparent`:
+//! `--traceparent` or the `TRACEPARENT` environment variable for a command,
+//! and the request header for the HTTP API. Without one, each command or
+//! HTTP request starts a trace of its own. The service name is taken from
+//! `OTEL_SERVICE_NAME`.
+//!
+//! The opentelemetry crates are not among this workspace's dependencies, so
+//! spans are recorded and encoded here, and exported over plain `http://`
+//! only; a collector next to the parent can forward them on over TLS. A
+//! failed export is logged and the spans dropped; it never fails the
+//! evaluations they describe.
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Ended spans held before they are exported without waiting for the end
+/// of the command
+const MAX_BUFFERED_SPANS: usize = 512;
+/// Bound on connecting to the collector and on each read and write
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(2);
+/// Longest status line of the collector's response read
+const MAX_STATUS_LINE_LEN: u64 = 1024;
+/// Service name unless `OTEL_SERVICE_NAME` is set
+const DEFAULT_SERVICE_NAME: &str = "oprf-parent";
+
+static TRACER: OnceLock<Tracer> = OnceLock::new();
+
+thread_local! {
+    /// Innermost open span of this thread, the parent of the next one
+    static CURRENT: RefCell<Option<SpanContext>> = const { RefCell::new(None) };
+}
+
+/// Ids of a span within its trace, as carried by a `traceparent`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanContext {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+}
+
+impl SpanContext {
+    /// Parse a version 00 W3C `traceparent`,
+    /// `00-<32 hex trace id>-<16 hex parent id>-<2 hex flags>`
+    pub fn from_traceparent(traceparent: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid traceparent {:?}", traceparent);
+        let fields: Vec<&str> = traceparent.trim().split('-').collect();
+        let ["00", trace_id, span_id, flags] = fields[..] else {
+            return Err(invalid());
+        };
+        let lower_hex = |field: &str| field.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+        if ![trace_id, span_id, flags].into_iter().all(lower_hex) || flags.len() != 2 {
+            return Err(invalid());
+        }
+        let mut context = SpanContext {
+            trace_id: [0; 16],
+            span_id: [0; 8],
+        };
+        hex::decode_to_slice(trace_id, &mut context.trace_id).map_err(|_| invalid())?;
+        hex::decode_to_slice(span_id, &mut context.span_id).map_err(|_| invalid())?;
+        // All-zero ids are invalid
+        if context.trace_id == [0; 16] || context.span_id == [0; 8] {
+            return Err(invalid());
+        }
+        Ok(context)
+    }
+}
+
+/// Value of a span attribute
+#[derive(Debug, Clone)]
+pub enum AttributeValue {
+    String(String),
+    Int(i64),
+    Bool(bool),
+}
+
+impl AttributeValue {
+    fn to_json(&self) -> Value {
+        match self {
+            AttributeValue::String(value) => json!({ "stringValue": value }),
+            // OTLP JSON carries 64-bit integers as strings
+            AttributeValue::Int(value) => json!({ "intValue": value.to_string() }),
+            AttributeValue::Bool(value) => json!({ "boolValue": value }),
+        }
+    }
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> Self {
+        AttributeValue::String(value.to_string())
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(value: String) -> Self {
+        AttributeValue::String(value)
+    }
+}
+
+impl From<bool> for AttributeValue {
+    fn from(value: bool) -> Self {
+        AttributeValue::Bool(value)
+    }
+}
+
+impl From<u32> for AttributeValue {
+    fn from(value: u32) -> Self {
+        AttributeValue::Int(value.into())
+    }
+}
+
+impl From<u64> for AttributeValue {
+    fn from(value: u64) -> Self {
+        AttributeValue::Int(i64::try_from(value).unwrap_or(i64::MAX))
+    }
+}
+
+impl From<usize> for AttributeValue {
+    fn from(value: usize) -> Self {
+        AttributeValue::Int(i64::try_from(value).unwrap_or(i64::MAX))
+    }
+}
+
+/// Role of a span, numbered as in OTLP
+#[derive(Debug, Clone, Copy)]
+pub enum SpanKind {
+    Internal = 1,
+    /// Answering a request of another service
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    Server = 2,
+    /// Awaiting a request to another service, the enclave
+    Client = 3,
+}
+
+/// A span being recorded
+struct OpenSpan {
+    context: SpanContext,
+    parent: Option<SpanContext>,
+    /// Span open on this thread before this one, open again once it ends
+    previous: Option<SpanContext>,
+    name: String,
+    kind: SpanKind,
+    start: SystemTime,
+    attributes: Vec<(&'static str, AttributeValue)>,
+    error: Option<String>,
+}
+
+/// A span of the current thread, ended and queued for export when dropped.
+/// Spans must be dropped in the reverse order they were started. Without an
+/// OTLP endpoint configured they record nothing.
+pub struct Span {
+    open: Option<OpenSpan>,
+}
+
+impl Span {
+    /// Start a span under the innermost open span of this thread. Outside any
+    /// span it joins the trace of `--traceparent`, or starts a new trace.
+    pub fn start(name: impl Into<String>, kind: SpanKind) -> Self {
+        let Some(tracer) = TRACER.get() else {
+            return Span { open: None };
+        };
+        let parent = current().or(tracer.remote_parent);
+        Self::child_of(name, kind, parent)
+    }
+
+    /// Start a span under `parent`, a span of another thread or service, or
+    /// else as the root of a new trace
+    pub fn child_of(name: impl Into<String>, kind: SpanKind, parent: Option<SpanContext>) -> Self {
+        if TRACER.get().is_none() {
+            return Span { open: None };
+        }
+        let context = SpanContext {
+            trace_id: parent.map_or_else(random_id, |parent| parent.trace_id),
+            span_id: random_id(),
+        };
+        let previous = CURRENT.with(|current| current.replace(Some(context)));
+        Span {
+            open: Some(OpenSpan {
+                context,
+                parent,
+                previous,
+                name: name.into(),
+                kind,
+                start: SystemTime::now(),
+                attributes: Vec::new(),
+                error: None,
+            }),
+        }
+    }
+
+    /// Set attribute `key` of the span
+    pub fn set(&mut self, key: &'static str, value: impl Into<AttributeValue>) {
+        if let Some(open) = &mut self.open {
+            open.attributes.push((key, value.into()));
+        }
+    }
+
+    /// Mark the span failed with `error`
+    pub fn fail(&mut self, error: impl std::fmt::Display) {
+        if let Some(open) = &mut self.open {
+            open.error = Some(error.to_string());
+        }
+    }
+
+    /// Mark the span failed with the error of `result`, if any, and pass
+    /// `result` on
+    pub fn check<T, E: std::fmt::Display>(&mut self, result: Result<T, E>) -> Result<T, E> {
+        if let Err(e) = &result {
+            self.fail(e);
+        }
+        result
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(open) = self.open.take() else { return };
+        CURRENT.with(|current| *current.borrow_mut() = open.previous);
+        if let Some(tracer) = TRACER.get() {
+            tracer.end(open.to_json(SystemTime::now()));
+        }
+    }
+}
+
+impl OpenSpan {
+    /// The span in the OTLP JSON encoding, ending at `end`
+    fn to_json(&self, end: SystemTime) -> Value {
+        let nanos = |time: SystemTime| {
+            let nanos = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+            nanos.to_string()
+        };
+        let attributes: Vec<Value> = self
+            .attributes
+            .iter()
+            .map(|(key, value)| json!({ "key": key, "value": value.to_json() }))
+            .collect();
+        let status = match &self.error {
+            Some(message) => json!({ "code": 2, "message": message }),
+            None => json!({}),
+        };
+        let parent_span_id = self.parent.map(|parent| hex::encode(parent.span_id));
+        json!({
+            "traceId": hex::encode(self.context.trace_id),
+            "spanId": hex::encode(self.context.span_id),
+            "parentSpanId": parent_span_id.unwrap_or_default(),
+            "name": self.name,
+            "kind": self.kind as u8,
+            "startTimeUnixNano": nanos(self.start),
+            "endTimeUnixNano": nanos(end),
+            "attributes": attributes,
+            "status": status,
+        })
+    }
+}
+
+/// Innermost open span of this thread, to parent the spans of threads it
+/// spawns
+pub fn current() -> Option<SpanContext> {
+    CURRENT.with(|current| *current.borrow())
+}
+
+/// Random non-zero id
+fn random_id<const N: usize>() -> [u8; N] {
+    let mut id = [0; N];
+    while id == [0; N] {
+        OsRng.fill_bytes(&mut id);
+    }
+    id
+}
+
+/// Exporter of ended spans to an OTLP/HTTP collector
+struct Tracer {
+    /// `host:port` of the collector
+    authority: String,
+    /// Path spans are posted to
+    path: String,
+    service_name: String,
+    /// Span of the calling service that commands are traced under
+    remote_parent: Option<SpanContext>,
+    ended: Mutex<Vec<Value>>,
+}
+
+impl Tracer {
+    fn end(&self, span: Value) {
+        let full = {
+            let mut ended = self.ended.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            ended.push(span);
+            (ended.len() >= MAX_BUFFERED_SPANS).then(|| std::mem::take(&mut *ended))
+        };
+        if let Some(spans) = full {
+            self.export(spans);
+        }
+    }
+
+    fn export(&self, spans: Vec<Value>) {
+        if spans.is_empty() {
+            return;
+        }
+        let count = spans.len();
+        let body = json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [{
+                        "key": "service.name",
+                        "value": { "stringValue": self.service_name },
+                    }],
+                },
+                "scopeSpans": [{
+                    "scope": { "name": "oprf-parent", "version": env!("CARGO_PKG_VERSION") },
+                    "spans": spans,
+                }],
+            }],
+        });
+        if let Err(e) = self.post(&body.to_string()) {
+            eprintln!("[Parent] Failed to export {} spans to {}: {}", count, self.authority, e);
+        }
+    }
+
+    /// POST `body` to the collector and check that it was accepted
+    fn post(&self, body: &str) -> std::io::Result<()> {
+        let addr = self
+            .authority
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "Collector address not found"))?;
+        let mut stream = TcpStream::connect_timeout(&addr, EXPORT_TIMEOUT)?;
+        stream.set_read_timeout(Some(EXPORT_TIMEOUT))?;
+        stream.set_write_timeout(Some(EXPORT_TIMEOUT))?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.authority,
+            body.len(),
+            body
+        )?;
+        stream.flush()?;
+        let mut status_line = String::new();
+        BufReader::new(stream.take(MAX_STATUS_LINE_LEN)).read_line(&mut status_line)?;
+        match status_line.split_whitespace().nth(1) {
+            Some(status) if status.starts_with('2') => Ok(()),
+            _ => Err(Error::other(format!("Collector answered {:?}", status_line.trim()))),
+        }
+    }
+}
+
+/// Trace commands and export their spans to the OTLP/HTTP collector at
+/// `endpoint`, e.g. `http://localhost:4318`, under `remote_parent` if set
+pub fn init(endpoint: &str, remote_parent: Option<SpanContext>) -> Result<(), String> {
+    let rest = endpoint.strip_prefix("http://").ok_or_else(|| {
+        format!("OTLP endpoint {:?} must be an http:// URL", endpoint)
+    })?;
+    let (authority, base) = rest.split_once('/').unwrap_or((rest, ""));
+    if authority.is_empty() {
+        return Err(format!("OTLP endpoint {:?} has no host", endpoint));
+    }
+    let authority = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+    let base = base.trim_end_matches('/');
+    let path = if base.is_empty() {
+        "/v1/traces".to_string()
+    } else {
+        format!("/{}/v1/traces", base)
+    };
+    let service_name = std::env::var("OTEL_SERVICE_NAME")
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_string());
+    let tracer = Tracer {
+        authority,
+        path,
+        service_name,
+        remote_parent,
+        ended: Mutex::new(Vec::new()),
+    };
+    TRACER.set(tracer).map_err(|_| "Tracing is already configured".to_string())
+}
+
+/// Export every span ended so far
+pub fn flush() {
+    if let Some(tracer) = TRACER.get() {
+        let spans = std::mem::take(
+            &mut *tracer.ended.lock().unwrap_or_else(|poisoned| poisoned.into_inner()),
+        );
+        tracer.export(spans);
+    }
+}
@@ -0,0 +1,112 @@
+//! Watching the heartbeats of the enclave, see [`oprf_common::heartbeat`].
+//!
+//! A [`Watchdog`] subscribes to the heartbeat port and reports every beat.
+//! It reports the enclave lost once no beat has arrived for as many intervals
+//! as it may miss, whether the connection stays open and silent, breaks, or
+//! cannot be opened, and keeps reconnecting every interval until beats
+//! resume. A beat with a lower sequence number or uptime than the last one
+//! is from an enclave that restarted, whose keys are gone unless restored.
+//! After a beat marked stopping, the enclave going quiet is expected and not
+//! reported until beats resume.
+
+use crate::client::OprfClient;
+use oprf_common::frame::read_frame;
+use oprf_common::heartbeat::{Heartbeat, DEFAULT_HEARTBEAT_INTERVAL_SECS};
+use std::io::{Error, ErrorKind};
+use std::time::{Duration, Instant};
+
+/// What the watchdog saw
+pub enum Event {
+    /// A beat, the first one included
+    Beat(Heartbeat),
+    /// The first beat after the enclave was lost
+    Recovered(Heartbeat),
+    /// A beat from an enclave started since the last one
+    Restarted(Heartbeat),
+    /// The beat the enclave sends as it shuts down gracefully
+    Stopping(Heartbeat),
+    /// No beat for `silence`, `missed` intervals, the last connection having
+    /// failed with `error`; reported once until beats resume
+    Lost { missed: u32, silence: Duration, error: Error },
+}
+
+/// Subscriber to the heartbeat port of the enclave
+pub struct Watchdog {
+    client: OprfClient,
+    port: u32,
+    max_missed: u32,
+}
+
+impl Watchdog {
+    /// Watch the beats on `port` of the enclave `client` reaches, reporting it
+    /// lost after `max_missed` intervals without one
+    pub fn new(client: OprfClient, port: u32, max_missed: u32) -> Self {
+        Self {
+            client,
+            port,
+            max_missed: max_missed.max(1),
+        }
+    }
+
+    /// Report what happens to `on_event`, for as long as the process runs
+    pub fn run(&self, mut on_event: impl FnMut(Event)) -> ! {
+        let mut interval = Duration::from_secs(DEFAULT_HEARTBEAT_INTERVAL_SECS);
+        let mut last: Option<Heartbeat> = None;
+        let mut last_seen = Instant::now();
+        let mut lost = false;
+        let mut stopping = false;
+        loop {
+            let error = match self.client.connect_heartbeats(self.port) {
+                Ok(mut stream) => loop {
+                    // Wake up at the deadline of the loss to report it
+                    let timeout = (interval * self.max_missed)
+                        .saturating_sub(last_seen.elapsed())
+                        .max(interval);
+                    if let Err(e) = stream.set_read_timeout(Some(timeout)) {
+                        break e;
+                    }
+                    let beat = match read_frame(&mut stream) {
+                        Ok(Some(frame)) => frame
+                            .format
+                            .decode::<Heartbeat>(&frame.payload)
+                            .map_err(|e| Error::new(ErrorKind::InvalidData, e)),
+                        Ok(None) => Err(ErrorKind::UnexpectedEof.into()),
+                        Err(e) => Err(e),
+                    };
+                    let beat = match beat {
+                        Ok(beat) => beat,
+                        Err(e) => break e,
+                    };
+                    last_seen = Instant::now();
+                    if beat.interval_secs > 0 {
+                        interval = Duration::from_secs(beat.interval_secs);
+                    }
+                    let restarted = last.as_ref().is_some_and(|last| {
+                        beat.sequence <= last.sequence || beat.uptime_secs < last.uptime_secs
+                    });
+                    last = Some(beat.clone());
+                    stopping = beat.stopping;
+                    if beat.stopping {
+                        on_event(Event::Stopping(beat));
+                    } else if restarted {
+                        lost = false;
+                        on_event(Event::Restarted(beat));
+                    } else if lost {
+                        lost = false;
+                        on_event(Event::Recovered(beat));
+                    } else {
+                        on_event(Event::Beat(beat));
+                    }
+                },
+                Err(e) => e,
+            };
+            let silence = last_seen.elapsed();
+            let missed = (silence.as_secs_f64() / interval.as_secs_f64()) as u32;
+            if !lost && !stopping && missed >= self.max_missed {
+                lost = true;
+                on_event(Event::Lost { missed, silence, error });
+            }
+            std::thread::sleep(interval);
+        }
+    }
+}
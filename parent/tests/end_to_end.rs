@@ -0,0 +1,464 @@
+//! End-to-end tests: build the enclave binary, start it in local mode on a
+//! free port, and run the parent binary against it, checking the outputs and
+//! exit statuses it reports.
+//!
+//! The parent verifies every DLEQ proof and attestation itself, so these
+//! tests check what the unit tests of `oprf-common` cannot: that the two
+//! binaries agree on the wire, and that outputs are consistent across
+//! connections, requests and commands. They need a parent built in local
+//! mode, which reaches the enclave over TCP.
+
+#![cfg(all(feature = "local", not(feature = "nitro")))]
+
+use oprf_common::ed25519::SigningKey;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::{mpsc, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+/// Bound on the enclave starting to listen
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Exit statuses of the parent, see `oprf_parent::service`
+const EXIT_FAILURE: i32 = 1;
+const EXIT_ATTESTATION: i32 = 3;
+const EXIT_TRANSPORT: i32 = 4;
+
+/// The enclave binary, built once with the profile the tests are built with
+fn enclave_binary() -> &'static Path {
+    static BINARY: OnceLock<PathBuf> = OnceLock::new();
+    BINARY.get_or_init(|| {
+        let parent = Path::new(env!("CARGO_BIN_EXE_oprf-parent"));
+        let dir = parent.parent().expect("binary directory");
+        let profile = match dir.file_name().and_then(|name| name.to_str()) {
+            Some("debug") => "dev",
+            Some(profile) => profile,
+            None => panic!("No profile in {}", dir.display()),
+        };
+        let status = Command::new(env!("CARGO"))
+            .args(["build", "--quiet", "--package", "oprf-enclave", "--profile", profile])
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .status()
+            .expect("run cargo build");
+        assert!(status.success(), "Building oprf-enclave failed");
+        dir.join(format!("oprf-enclave{}", std::env::consts::EXE_SUFFIX))
+    })
+}
+
+/// A local-mode enclave, killed when dropped
+struct Enclave {
+    child: Child,
+    port: u16,
+    /// Key the enclave signs its mock attestations with, in hex
+    mock_signing_key: String,
+}
+
+impl Enclave {
+    fn start(args: &[&str]) -> Self {
+        let mut child = Command::new(enclave_binary())
+            .args(["--port", "0"])
+            .args(args)
+            .env_remove("OPRF_CONFIG")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("spawn oprf-enclave");
+        let stdout = child.stdout.take().expect("enclave stdout");
+        let (sender, receiver) = mpsc::channel();
+        // The admin port is listening on too
+        let admin_port = args
+            .iter()
+            .position(|arg| *arg == "--admin-port")
+            .and_then(|i| args.get(i + 1)?.parse().ok());
+        thread::spawn(move || watch(stdout, admin_port, sender));
+        let (mock_signing_key, port) = match receiver.recv_timeout(STARTUP_TIMEOUT) {
+            Ok(started) => started,
+            Err(e) => {
+                let _ = child.kill();
+                panic!("Enclave did not start: {}", e);
+            }
+        };
+        Self {
+            child,
+            port,
+            mock_signing_key,
+        }
+    }
+
+    /// Run the parent against this enclave, trusting its mock attestations
+    fn parent(&self, args: &[&str]) -> Run {
+        let port = self.port.to_string();
+        run_parent(&[&["--port", &port, "--mock-signing-key", &self.mock_signing_key], args])
+    }
+}
+
+impl Drop for Enclave {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Send the mock signing key and the data port the enclave printed on
+/// startup, then keep reading so its output never fills the pipe
+fn watch(stdout: ChildStdout, admin_port: Option<u16>, started: mpsc::Sender<(String, u16)>) {
+    let mut key = None;
+    for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+        if let Some(hex) = line.strip_prefix("[Enclave] Mock attestation key (hex): ") {
+            key = Some(hex.trim().to_string());
+        } else if let Some(address) = line.strip_prefix("[Enclave] TCP server listening on ") {
+            let port = address.rsplit(':').next().and_then(|port| port.parse().ok());
+            if let Some(port) = port.filter(|port| Some(*port) != admin_port) {
+                if let Some(key) = key.take() {
+                    let _ = started.send((key, port));
+                }
+            }
+        }
+    }
+}
+
+/// What a run of the parent printed and how it exited
+struct Run {
+    code: Option<i32>,
+    stdout: String,
+    stderr: String,
+}
+
+/// A local port nothing listens on
+fn free_port() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port().to_string()
+}
+
+fn run_parent(args: &[&[&str]]) -> Run {
+    let output = Command::new(env!("CARGO_BIN_EXE_oprf-parent"))
+        .args(args.concat())
+        .args(["--timeout", "30"])
+        .env_remove("OPRF_CONFIG")
+        .env_remove("ENCLAVE_CID")
+        .env_remove("ENCLAVE_PORT")
+        .stdin(Stdio::null())
+        .output()
+        .expect("run oprf-parent");
+    Run {
+        code: output.status.code(),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    }
+}
+
+impl Run {
+    fn assert_success(&self) -> &Self {
+        assert_eq!(self.code, Some(0), "Parent failed:\n{}\n{}", self.stdout, self.stderr);
+        self
+    }
+
+    fn assert_failure(&self, code: i32, message: &str) -> &Self {
+        assert_eq!(self.code, Some(code), "Unexpected exit:\n{}\n{}", self.stdout, self.stderr);
+        assert!(self.stderr.contains(message), "No {:?} in:\n{}", message, self.stderr);
+        self
+    }
+
+    fn assert_printed(&self, line: &str) -> &Self {
+        assert!(self.stdout.contains(line), "No {:?} in:\n{}", line, self.stdout);
+        self
+    }
+
+    /// Output of a single evaluation
+    fn output(&self) -> String {
+        let outputs = self.outputs();
+        assert_eq!(outputs.len(), 1, "Expected one output:\n{}", self.stdout);
+        outputs.into_iter().next().unwrap().1
+    }
+
+    /// Inputs and outputs printed, in order; a single evaluation prints its
+    /// output without the input
+    fn outputs(&self) -> Vec<(Option<String>, String)> {
+        self.stdout
+            .lines()
+            .filter_map(|line| line.strip_prefix("[Parent] OPRF OUTPUT"))
+            .map(|rest| {
+                let (input, output) = rest.rsplit_once(": ").expect("output line");
+                let input = input.trim();
+                let input = (!input.is_empty()).then(|| input.trim_matches('"').to_string());
+                assert_eq!(output.len(), 64, "Not a 32-byte output: {}", output);
+                assert!(hex::decode(output).is_ok(), "Not hex: {}", output);
+                (input, output.to_string())
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_single_evaluation() {
+    let enclave = Enclave::start(&[]);
+    let alice = enclave.parent(&["eval", "alice@example.com"]);
+    alice
+        .assert_success()
+        .assert_printed("[Parent] Connected to enclave (protocol version 4)")
+        .assert_printed("[Parent] Response authenticated by the attested channel")
+        .assert_printed("[Parent] DLEQ proof verified successfully");
+
+    // The same input gives the same output over a new connection, and in
+    // every wire format; another input gives another
+    let again = enclave.parent(&["--wire-format", "json", "eval", "alice@example.com"]);
+    assert_eq!(again.assert_success().output(), alice.output());
+    let bob = enclave.parent(&["eval", "bob@example.com"]);
+    assert_ne!(bob.assert_success().output(), alice.output());
+
+    // Outputs are checked with the pairing too, in base mode, whose inputs
+    // hash to the group under another DST, and under P-256
+    let pairing = enclave.parent(&["--verify", "pairing", "eval", "alice@example.com"]);
+    pairing.assert_success().assert_printed("[Parent] Pairing check verified successfully");
+    assert_ne!(pairing.output(), alice.output());
+    let again = enclave.parent(&["--verify", "pairing", "eval", "alice@example.com"]);
+    assert_eq!(again.assert_success().output(), pairing.output());
+    let p256 = enclave.parent(&["--suite", "p256", "eval", "alice@example.com"]);
+    p256.assert_success().assert_printed("[Parent] Ciphersuite: P256-SHA256");
+    assert_ne!(p256.output(), alice.output());
+
+    enclave.parent(&["verify", "alice@example.com", &alice.output()]).assert_success();
+    enclave
+        .parent(&["verify", "bob@example.com", &alice.output()])
+        .assert_failure(EXIT_FAILURE, "");
+}
+
+#[test]
+fn test_batch_evaluation() {
+    let enclave = Enclave::start(&[]);
+    let inputs = ["alice@example.com", "bob@example.com", "carol@example.com"];
+    let batch = enclave.parent(&[&["eval"][..], &inputs].concat());
+    batch.assert_success().assert_printed("[Parent] Batch OPRF completed successfully!");
+    let outputs = batch.outputs();
+    assert_eq!(outputs.len(), inputs.len());
+
+    // Each output is that of the input evaluated alone, or pipelined
+    let sequential = enclave.parent(&[&["--sequential", "eval"][..], &inputs].concat());
+    sequential.assert_success();
+    for (i, (input, output)) in outputs.iter().enumerate() {
+        assert_eq!(input.as_deref(), Some(inputs[i]));
+        assert_eq!(output, &enclave.parent(&["eval", inputs[i]]).assert_success().output());
+        assert_eq!(output, &sequential.outputs()[i].1);
+    }
+
+    // A file split into chunks evaluated over parallel connections
+    let dir = std::env::temp_dir().join(format!("oprf-e2e-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (input_path, output_path) = (dir.join("inputs.txt"), dir.join("results.jsonl"));
+    let lines: Vec<String> = (0..50).map(|i| format!("user-{}", i)).collect();
+    std::fs::write(&input_path, lines.join("\n")).unwrap();
+    enclave
+        .parent(&[
+            "batch",
+            "--input",
+            input_path.to_str().unwrap(),
+            "--output",
+            output_path.to_str().unwrap(),
+            "--chunk-size",
+            "8",
+        ])
+        .assert_success();
+    let results = std::fs::read_to_string(&output_path).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let results: Vec<serde_json::Value> =
+        results.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(results.len(), lines.len());
+    for (line, result) in lines.iter().zip(&results) {
+        assert_eq!(result["input"], line.as_str());
+    }
+    let first = enclave.parent(&["eval", &lines[0]]).assert_success().output();
+    assert_eq!(results[0]["output"], first.as_str());
+}
+
+#[test]
+fn test_refused_requests() {
+    let enclave = Enclave::start(&["--max-evaluations", "2"]);
+    enclave
+        .parent(&["--key-id", "bad!id", "eval", "alice@example.com"])
+        .assert_failure(EXIT_FAILURE, "Enclave refused the request (BadRequest)");
+    enclave
+        .parent(&["--epoch", "7", "eval", "alice@example.com"])
+        .assert_failure(EXIT_FAILURE, "Unknown key epoch 7");
+
+    // Refused requests do not count against the budget
+    enclave.parent(&["eval", "alice@example.com", "bob@example.com"]).assert_success();
+    enclave
+        .parent(&["eval", "carol@example.com"])
+        .assert_failure(EXIT_FAILURE, "Enclave refused the request (RateLimited)");
+    // Budgets are per key
+    enclave.parent(&["--key-id", "other", "eval", "carol@example.com"]).assert_success();
+}
+
+#[test]
+fn test_unreachable_enclave() {
+    let port = free_port();
+    run_parent(&[&["--port", &port, "eval", "alice@example.com"]])
+        .assert_failure(EXIT_TRANSPORT, "Connection refused");
+}
+
+#[test]
+fn test_attestation() {
+    let enclave = Enclave::start(&[]);
+    let pubkey = enclave.parent(&["pubkey"]);
+    pubkey.assert_success().assert_printed("[Parent] Attestation verified successfully");
+    enclave
+        .parent(&["--fresh-attestation", "attest"])
+        .assert_success()
+        .assert_printed("[Parent] Attestation verified successfully");
+    enclave.parent(&["health"]).assert_success().assert_printed("[Parent] Enclave is ready");
+
+    // Attestations signed by any other key are refused, whatever is asked
+    let other = hex::encode(SigningKey::from_seed(&[7; 32]).public_key());
+    let port = enclave.port.to_string();
+    for command in [&["pubkey"][..], &["attest"], &["eval", "alice@example.com"]] {
+        run_parent(&[&["--port", &port, "--mock-signing-key", &other], command])
+            .assert_failure(EXIT_ATTESTATION, "");
+    }
+}
+
+#[test]
+fn test_key_management_on_admin_port() {
+    let admin_port = free_port();
+    let operator = SigningKey::from_seed(&[9; 32]);
+    let enclave = Enclave::start(&[
+        "--admin-key",
+        &hex::encode(operator.public_key()),
+        "--admin-port",
+        &admin_port,
+    ]);
+    let dir = std::env::temp_dir().join(format!("oprf-e2e-admin-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (operator_path, secret_path) = (dir.join("operator.key"), dir.join("secret.key"));
+    std::fs::write(&operator_path, hex::encode([9u8; 32])).unwrap();
+    std::fs::write(&secret_path, hex::encode([1u8; 32])).unwrap();
+    let (operator_path, secret_path) =
+        (operator_path.to_str().unwrap(), secret_path.to_str().unwrap());
+
+    // The data path refuses key management once the enclave has an admin port
+    enclave
+        .parent(&["rotate"])
+        .assert_failure(EXIT_FAILURE, "Key management is reserved to the admin port");
+    enclave
+        .parent(&["--key-id", "injected", "inject-key", secret_path])
+        .assert_failure(EXIT_FAILURE, "Key management is reserved to the admin port");
+
+    // The same commands go through with the operator key
+    let admin = ["--operator-key", operator_path, "--admin-port", &admin_port];
+    enclave
+        .parent(&[&admin[..], &["rotate"]].concat())
+        .assert_success()
+        .assert_printed("[Parent] Rotated BN254-SHA256 key \"default\" to epoch 1");
+    enclave
+        .parent(&[&admin[..], &["--key-id", "injected", "inject-key", secret_path]].concat())
+        .assert_success()
+        .assert_printed("[Parent] Injected BN254-SHA256 key \"injected\" (epoch 0)");
+    let injected = enclave.parent(&["--key-id", "injected", "eval", "alice@example.com"]);
+
+    // An existing key id is never replaced, whether injected or created
+    for key_id in ["injected", "default"] {
+        enclave
+            .parent(&[&admin[..], &["--key-id", key_id, "inject-key", secret_path]].concat())
+            .assert_failure(EXIT_FAILURE, "already exists");
+    }
+    let again = enclave.parent(&["--key-id", "injected", "eval", "alice@example.com"]);
+    assert_eq!(again.assert_success().output(), injected.assert_success().output());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_replication_on_admin_port() {
+    let operator = hex::encode(SigningKey::from_seed(&[9; 32]).public_key());
+    let (primary_admin, standby_admin) = (free_port(), free_port());
+    let primary = Enclave::start(&["--admin-key", &operator, "--admin-port", &primary_admin]);
+    let standby = Enclave::start(&["--admin-key", &operator, "--admin-port", &standby_admin]);
+    let dir = std::env::temp_dir().join(format!("oprf-e2e-replica-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let operator_path = dir.join("operator.key");
+    std::fs::write(&operator_path, hex::encode([9u8; 32])).unwrap();
+
+    let standby_address = format!("3:{}", standby.port);
+    let replicate = ["replicate", &standby_address, "--standby-mock-signing-key"];
+    let replicate = [&replicate[..], &[&standby.mock_signing_key]].concat();
+    primary.parent(&replicate).assert_failure(EXIT_FAILURE, "replicate requires --operator-key");
+    let alice = primary.parent(&["eval", "alice@example.com"]).assert_success().output();
+    assert_ne!(standby.parent(&["eval", "alice@example.com"]).assert_success().output(), alice);
+
+    let admin = [
+        "--operator-key",
+        operator_path.to_str().unwrap(),
+        "--admin-port",
+        &primary_admin,
+        "--standby-admin-port",
+        &standby_admin,
+    ];
+    primary
+        .parent(&[&admin[..], &replicate].concat())
+        .assert_success()
+        .assert_printed("[Parent] Standby attestation verified successfully");
+    assert_eq!(standby.parent(&["eval", "alice@example.com"]).assert_success().output(), alice);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_seed_provisioning() {
+    let operator = hex::encode(SigningKey::from_seed(&[9; 32]).public_key());
+    let (first_admin, second_admin) = (free_port(), free_port());
+    let first = Enclave::start(&["--admin-key", &operator, "--admin-port", &first_admin]);
+    let second = Enclave::start(&["--admin-key", &operator, "--admin-port", &second_admin]);
+    let dir = std::env::temp_dir().join(format!("oprf-e2e-seed-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (operator_path, seed_path) = (dir.join("operator.key"), dir.join("seed.bin"));
+    std::fs::write(&operator_path, hex::encode([9u8; 32])).unwrap();
+    std::fs::write(&seed_path, [5u8; 32]).unwrap();
+    let (operator_path, seed_path) = (operator_path.to_str().unwrap(), seed_path.to_str().unwrap());
+    let provision = ["--operator-key", operator_path, "provision-seed", seed_path];
+
+    first
+        .parent(&["provision-seed", seed_path])
+        .assert_failure(EXIT_FAILURE, "provision-seed requires --operator-key");
+
+    // Enclaves provisioned from one seed agree on every output
+    for (enclave, admin_port) in [(&first, &first_admin), (&second, &second_admin)] {
+        enclave
+            .parent(&[&["--admin-port", admin_port][..], &provision].concat())
+            .assert_success()
+            .assert_printed("[Parent] Attestation verified successfully");
+    }
+    let alice = first.parent(&["eval", "alice@example.com"]).assert_success().output();
+    assert_eq!(second.parent(&["eval", "alice@example.com"]).assert_success().output(), alice);
+
+    // Keys that evaluated are not replaced
+    first
+        .parent(&[&["--admin-port", &first_admin][..], &provision].concat())
+        .assert_failure(EXIT_FAILURE, "Keys have already evaluated points");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_key_backup_under_pinned_kms_key() {
+    let first = Enclave::start(&["--kms-key-id", "alias/oprf-backup"]);
+    let second = Enclave::start(&["--kms-key-id", "alias/oprf-backup"]);
+    let dir = std::env::temp_dir().join(format!("oprf-e2e-backup-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let backup = dir.join("keys.json");
+    let backup = backup.to_str().unwrap();
+
+    let alice = first.parent(&["eval", "alice@example.com"]).assert_success().output();
+
+    // The parent cannot choose a KMS key of its own
+    first
+        .parent(&["--kms-key-id", "alias/other", "export-keys", backup])
+        .assert_failure(EXIT_FAILURE, "is not the enclave's KMS key alias/oprf-backup");
+    assert!(!dir.join("keys.json").exists());
+
+    first.parent(&["export-keys", backup]).assert_success();
+    second
+        .parent(&["import-keys", backup])
+        .assert_success()
+        .assert_printed("[Parent] Attestation verified successfully");
+    assert_eq!(second.parent(&["eval", "alice@example.com"]).assert_success().output(), alice);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
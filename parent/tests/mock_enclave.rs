@@ -0,0 +1,124 @@
+//! The parent's attestation verification against `oprf-server`'s
+//! [`MockEnclave`]: evaluations of the in-process mock pass the same policy
+//! checks as those of a local-mode enclave, given its mock signing key, and
+//! fail them without it.
+
+use oprf_common::ed25519::{SigningKey, VerifyingKey};
+use oprf_common::frame::{read_frame, write_frame, WireFormat};
+use oprf_common::{
+    blind, sha256_hex, unblind, verify_proof, AttestationError, Ciphersuite, CiphersuiteId,
+    DleqProof, EnclaveResponse, EvaluatedElement, EvaluationUserData, HelloRequest, Message,
+    OprfMode, OprfOutput, OprfRequest, OprfResponse, OprfResult, P256Sha256, PointEncoding,
+};
+use oprf_parent::attestation::verify_evaluation_attestation;
+use oprf_parent::policy::Policy;
+use oprf_server::testing::MockEnclave;
+use rand::rngs::OsRng;
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use std::io::{Read, Write};
+
+const PROTOCOL_VERSION: u32 = 2;
+
+fn exchange<T: DeserializeOwned>(stream: &mut (impl Read + Write), request: &Message) -> T {
+    let payload = WireFormat::Json.encode(request).unwrap();
+    write_frame(stream, 1, WireFormat::Json, &payload).unwrap();
+    let frame = read_frame(stream).unwrap().unwrap();
+    frame.format.decode(&frame.payload).unwrap()
+}
+
+/// A policy accepting mock attestations signed with `key`
+fn policy(key: &VerifyingKey) -> Policy {
+    let mut policy = Policy::default();
+    policy.mock_signing_key = Some(key.clone());
+    policy
+}
+
+/// Evaluate `input` in verifiable mode, checking the proof, and return the
+/// output with the response and the user data its attestation must carry
+fn evaluate(mock: &MockEnclave, input: &[u8]) -> (OprfOutput, OprfResponse, EvaluationUserData) {
+    let mut stream = mock.connect().unwrap();
+    let hello = Message::Hello(HelloRequest {
+        versions: vec![PROTOCOL_VERSION],
+        point_encoding: PointEncoding::default(),
+        request_ids: false,
+    });
+    exchange::<EnclaveResponse>(&mut stream, &hello);
+
+    let (blind, blinded) = blind::<P256Sha256, _>(input, OprfMode::Voprf, &mut OsRng);
+    let blinded_query = blinded.to_bytes().unwrap();
+    // The mock refuses replayed nonces like an enclave
+    let client_nonce = OsRng.gen::<[u8; 16]>().to_vec();
+    let request = Message::Evaluate(OprfRequest {
+        query_hash: sha256_hex(&blinded_query),
+        blinded_query,
+        mode: OprfMode::Voprf,
+        ciphersuite: CiphersuiteId::P256Sha256,
+        epoch: None,
+        key_id: "default".to_string(),
+        force_fresh: false,
+        client_nonce: Some(client_nonce.clone()),
+        client_id: None,
+        request_id: None,
+    });
+    let response: OprfResult<EnclaveResponse> = exchange(&mut stream, &request);
+    let Ok(EnclaveResponse::Evaluate(response)) = response else {
+        panic!("unexpected answer {:?}", response);
+    };
+
+    let public_key = P256Sha256::deserialize_element(&response.public_key).unwrap();
+    let evaluated = EvaluatedElement::<P256Sha256>::from_bytes(&response.evaluated_point).unwrap();
+    let proof = DleqProof::<P256Sha256>::from_bytes(response.proof.as_ref().unwrap()).unwrap();
+    verify_proof::<P256Sha256>(&public_key, &[*blinded.element()], &[*evaluated.element()], &proof)
+        .unwrap();
+    let expected = EvaluationUserData::new(
+        PROTOCOL_VERSION,
+        "default",
+        response.epoch,
+        &response.public_key,
+        std::slice::from_ref(&response.evaluated_point),
+        Some(&client_nonce),
+        None,
+    );
+    (unblind(blind, &evaluated).unwrap(), response, expected)
+}
+
+#[test]
+fn test_mock_enclave_passes_parent_verification() {
+    let mock = MockEnclave::new().unwrap();
+    let (output, response, expected) = evaluate(&mock, b"alice@example.com");
+    let policy = policy(mock.mock_signing_key());
+    verify_evaluation_attestation(&policy, &response.attestation, &expected).unwrap();
+
+    // Outputs do not depend on the blinding
+    let (again, _, _) = evaluate(&mock, b"alice@example.com");
+    assert_eq!(again, output);
+    let (other, _, _) = evaluate(&mock, b"bob@example.com");
+    assert_ne!(other, output);
+}
+
+#[test]
+fn test_mock_attestation_needs_its_key() {
+    let mock = MockEnclave::new().unwrap();
+    let (_, response, expected) = evaluate(&mock, b"alice@example.com");
+
+    let error = verify_evaluation_attestation(&Policy::default(), &response.attestation, &expected)
+        .unwrap_err();
+    assert!(matches!(error, AttestationError::NotConfigured(_)), "{:?}", error);
+
+    let other = SigningKey::generate(&mut OsRng);
+    let other = VerifyingKey::from_bytes(&other.public_key()).unwrap();
+    assert!(verify_evaluation_attestation(&policy(&other), &response.attestation, &expected)
+        .is_err());
+
+    // Nor does it verify for another evaluation
+    let mut tampered = expected.clone();
+    tampered.epoch += 1;
+    let error = verify_evaluation_attestation(
+        &policy(mock.mock_signing_key()),
+        &response.attestation,
+        &tampered,
+    )
+    .unwrap_err();
+    assert!(matches!(error, AttestationError::UserDataMismatch(_)), "{:?}", error);
+}
@@ -0,0 +1,137 @@
+//! TCP front-end for an enclave reachable only over vsock.
+//!
+//! Runs on the parent instance, listens on a TCP address and relays every
+//! connection byte for byte to the enclave's vsock port. Frames, the version
+//! handshake and the secure channel pass through unchanged, so TCP-only
+//! clients, the local-mode parent among them, can talk to a Nitro enclave.
+
+use nix::sys::socket::{connect, socket, AddressFamily, SockFlag, SockType, VsockAddr};
+use std::io::Error;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+use std::sync::Arc;
+use std::thread;
+
+/// Where the local-mode parent connects
+const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:5000";
+/// Default enclave CID
+const DEFAULT_ENCLAVE_CID: u32 = 16;
+/// vsock port the enclave listens on
+const DEFAULT_VSOCK_PORT: u32 = 5000;
+
+/// Proxy settings from the command line
+#[derive(Debug)]
+struct Config {
+    /// TCP address to listen on
+    listen: String,
+    /// CID of the enclave
+    cid: u32,
+    /// vsock port of the enclave
+    port: u32,
+}
+
+/// Parse the command line: `[--listen <addr>] [--cid <n>] [--port <n>]`
+///
+/// The `ENCLAVE_CID` and `ENCLAVE_PORT` environment variables set the enclave
+/// address where the flags do not.
+fn parse_args() -> Result<Config, String> {
+    let mut config = Config {
+        listen: DEFAULT_LISTEN_ADDR.to_string(),
+        cid: DEFAULT_ENCLAVE_CID,
+        port: DEFAULT_VSOCK_PORT,
+    };
+    for (name, field) in [("ENCLAVE_CID", &mut config.cid), ("ENCLAVE_PORT", &mut config.port)] {
+        if let Ok(value) = std::env::var(name) {
+            *field = value.parse().map_err(|e| format!("Invalid {}: {}", name, e))?;
+        }
+    }
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or(format!("{} requires a value", arg))?;
+        let number = || {
+            value
+                .parse::<u32>()
+                .map_err(|e| format!("Invalid value for {}: {}", arg, e))
+        };
+        match arg.as_str() {
+            "--listen" => config.listen = value.clone(),
+            "--cid" => config.cid = number()?,
+            "--port" => config.port = number()?,
+            _ => return Err(format!("Unknown argument {}", arg)),
+        }
+    }
+    Ok(config)
+}
+
+fn connect_to_enclave(config: &Config) -> std::io::Result<TcpStream> {
+    let sock_fd = socket(AddressFamily::Vsock, SockType::Stream, SockFlag::empty(), None)
+        .map_err(Error::other)?;
+    let addr = VsockAddr::new(config.cid, config.port);
+    connect(sock_fd.as_raw_fd(), &addr).map_err(Error::other)?;
+
+    // A connected vsock stream socket reads and writes like a TCP stream
+    Ok(unsafe { TcpStream::from_raw_fd(sock_fd.into_raw_fd()) })
+}
+
+/// Copy `from` into `to` until `from` ends, then pass the end on
+fn relay(mut from: TcpStream, mut to: TcpStream) -> std::io::Result<u64> {
+    let copied = std::io::copy(&mut from, &mut to);
+    // The peer of `to` sees the end of the stream even if copying failed;
+    // it may already be gone
+    let _ = to.shutdown(Shutdown::Write);
+    copied
+}
+
+/// Relay one client connection to a new enclave connection until both
+/// directions end
+fn handle_connection(client: TcpStream, config: &Config) -> std::io::Result<()> {
+    let enclave = connect_to_enclave(config)?;
+    let (client_reader, enclave_reader) = (client.try_clone()?, enclave.try_clone()?);
+    let upstream = thread::spawn(move || relay(client_reader, enclave));
+    let received = relay(enclave_reader, client);
+    let sent = upstream
+        .join()
+        .map_err(|_| Error::other("Relay thread panicked"))?;
+    println!(
+        "[Proxy] Connection closed: {} bytes to the enclave, {} bytes back",
+        sent?, received?
+    );
+    Ok(())
+}
+
+fn main() {
+    println!("[Proxy] Starting OPRF vsock proxy...");
+
+    let config = match parse_args() {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("[Proxy] {}", e);
+            std::process::exit(1);
+        }
+    };
+    let listener = match TcpListener::bind(&config.listen) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("[Proxy] Failed to listen on {}: {}", config.listen, e);
+            std::process::exit(1);
+        }
+    };
+    println!(
+        "[Proxy] Forwarding {} to enclave via vsock (CID: {}, Port: {})",
+        config.listen, config.cid, config.port
+    );
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let config = Arc::clone(&config);
+                thread::spawn(move || {
+                    if let Err(e) = handle_connection(stream, &config) {
+                        eprintln!("[Proxy] Connection failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => eprintln!("[Proxy] Connection error: {}", e),
+        }
+    }
+}
@@ -1,6 +1,19 @@
 #!/bin/bash
 set -e
 
+if [ ! -f kmstool/kmstool_enclave_cli ]; then
+    echo "Building kmstool_enclave_cli..."
+    SDK_DIR=$(mktemp -d)
+    git clone --depth 1 https://github.com/aws/aws-nitro-enclaves-sdk-c "$SDK_DIR"
+    docker build --target kmstool-enclave-cli -t kmstool-enclave-cli -f "$SDK_DIR/containers/Dockerfile.al2" "$SDK_DIR"
+    mkdir -p kmstool
+    CONTAINER=$(docker create kmstool-enclave-cli /kmstool_enclave_cli)
+    docker cp "$CONTAINER:/kmstool_enclave_cli" kmstool/
+    docker cp "$CONTAINER:/usr/lib64/libnsm.so" kmstool/
+    docker rm "$CONTAINER"
+    rm -rf "$SDK_DIR"
+fi
+
 echo "Building enclave Docker image..."
 docker build -t oprf-enclave -f enclave.Dockerfile . 
 
@@ -6,18 +6,23 @@ cargo build --release
 
 echo ""
 echo "Starting enclave (local mode)..."
-./target/release/oprf-enclave &
+ENCLAVE_LOG=$(mktemp)
+./target/release/oprf-enclave > >(tee "$ENCLAVE_LOG") &
 ENCLAVE_PID=$!
 
 # Wait for enclave to start
 sleep 2
 
+# The enclave signs its mock attestations with an ephemeral key it prints
+MOCK_KEY=$(grep -m1 "Mock attestation key" "$ENCLAVE_LOG" | awk '{print $NF}')
+
 echo ""
 echo "Running parent (local mode)..."
-./target/release/oprf-parent
+./target/release/oprf-parent --mock-signing-key "$MOCK_KEY" eval "hello world"
 
 # Cleanup
 kill $ENCLAVE_PID 2>/dev/null || true
+rm -f "$ENCLAVE_LOG"
 
 echo ""
 echo "Local test completed!"
\ No newline at end of file
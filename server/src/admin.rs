@@ -0,0 +1,165 @@
+//! The admin port: operator commands on a listener of their own.
+//!
+//! Admin connections are served one at a time on a thread of their own,
+//! outside the worker pool, so an operator still reaches an enclave whose
+//! data path is saturated. Every connection draws a challenge first, and
+//! every command after it must be signed over the challenge by the operator
+//! key, see [`oprf_common::admin`]. A command that does not verify is
+//! refused with `Unauthorized` and ends the connection.
+
+use crate::connection::lock;
+use crate::enclave::Enclave;
+use crate::listener::Listener;
+use crate::reaper::Connections;
+use crate::shutdown::Shutdown;
+use crate::{bad_request, WRITE_TIMEOUT};
+use oprf_common::admin::{
+    verify_command, AdminCommand, AdminRequest, AdminResponse, ChallengeResponse, CHALLENGE_LEN,
+};
+use oprf_common::ed25519::VerifyingKey;
+use oprf_common::frame::{read_frame, write_frame};
+use oprf_common::{ErrorCode, ErrorResponse, OprfResult};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Listener of the admin port and the operator key its commands are
+/// signed with
+pub struct AdminPort {
+    listener: Box<dyn Listener>,
+    operator_key: VerifyingKey,
+}
+
+impl AdminPort {
+    /// Serve operator commands on `listener`, signed by `operator_key`
+    pub fn new(listener: Box<dyn Listener>, operator_key: VerifyingKey) -> Self {
+        Self {
+            listener,
+            operator_key,
+        }
+    }
+}
+
+/// Challenge and command count of one admin connection
+#[derive(Default)]
+struct Session {
+    challenge: Option<[u8; CHALLENGE_LEN]>,
+    /// Commands verified on the connection so far
+    sequence: u64,
+}
+
+/// Serve `port` on a thread of its own against the shared enclave state,
+/// requesting `shutdown` when commanded to. Connections wait at most the
+/// idle timeout of the data path for a command.
+pub(crate) fn start(
+    port: AdminPort,
+    state: Arc<Mutex<Enclave>>,
+    connections: Arc<Connections>,
+    shutdown: Shutdown,
+) -> std::io::Result<()> {
+    thread::Builder::new().name("oprf-admin".to_string()).spawn(move || loop {
+        match port.listener.accept() {
+            Ok(mut stream) => {
+                println!("[Enclave] Admin connection received");
+                let timeouts = stream
+                    .set_read_timeout(Some(connections.limits().idle_timeout))
+                    .and_then(|()| stream.set_write_timeout(Some(WRITE_TIMEOUT)));
+                match timeouts {
+                    Ok(()) => {
+                        handle_connection(&mut stream, &port, &state, &connections, &shutdown)
+                    }
+                    Err(e) => eprintln!("[Enclave] Failed to set admin connection timeouts: {}", e),
+                }
+            }
+            Err(e) => eprintln!("[Enclave] Admin accept error: {}", e),
+        }
+    })?;
+    Ok(())
+}
+
+/// Answer the commands of an admin connection until it is closed, a command
+/// fails to verify or the enclave is commanded to shut down
+fn handle_connection(
+    stream: &mut TcpStream,
+    port: &AdminPort,
+    state: &Mutex<Enclave>,
+    connections: &Connections,
+    shutdown: &Shutdown,
+) {
+    let mut session = Session::default();
+    loop {
+        let frame = match read_frame(stream) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("[Enclave] Failed to read admin request: {}", e);
+                break;
+            }
+        };
+        let request = frame.format.decode::<AdminRequest>(&frame.payload);
+        let response = match &request {
+            Ok(request) => serve(request, &mut session, port, state, connections),
+            Err(e) => Err(bad_request(format!("Failed to parse admin request: {}", e))),
+        };
+        let unauthorized = matches!(&response, Err(e) if e.code == ErrorCode::Unauthorized);
+        if let Err(e) = &response {
+            eprintln!("[Enclave] Admin request {} failed: {}", frame.request_id, e);
+        }
+        let shutdown_requested = response.is_ok()
+            && matches!(&request, Ok(AdminRequest::Command(signed))
+                if matches!(signed.command, AdminCommand::Shutdown));
+
+        let sent = frame.format.encode(&response).map_err(std::io::Error::other).and_then(
+            |bytes| write_frame(stream, frame.request_id, frame.format, &bytes),
+        );
+        if let Err(e) = sent {
+            eprintln!("[Enclave] Failed to send admin response: {}", e);
+            break;
+        }
+        if shutdown_requested {
+            println!("[Enclave] Shutting down at the operator's request");
+            shutdown.request();
+            break;
+        }
+        if unauthorized {
+            break;
+        }
+    }
+    println!("[Enclave] Admin connection closed after {} command(s)", session.sequence);
+}
+
+/// Draw the challenge of the connection, or verify and carry out a command
+fn serve(
+    request: &AdminRequest,
+    session: &mut Session,
+    port: &AdminPort,
+    state: &Mutex<Enclave>,
+    connections: &Connections,
+) -> OprfResult<AdminResponse> {
+    match request {
+        AdminRequest::Challenge => {
+            if session.challenge.is_some() {
+                return Err(bad_request("The challenge is drawn once per connection".to_string()));
+            }
+            let mut challenge = [0u8; CHALLENGE_LEN];
+            OsRng.fill_bytes(&mut challenge);
+            session.challenge = Some(challenge);
+            Ok(AdminResponse::Challenge(ChallengeResponse {
+                challenge: challenge.to_vec(),
+            }))
+        }
+        AdminRequest::Command(signed) => {
+            let challenge = session
+                .challenge
+                .ok_or_else(|| bad_request("Admin commands require a challenge".to_string()))?;
+            verify_command(&port.operator_key, &challenge, session.sequence, signed).map_err(
+                |_| ErrorResponse::new(ErrorCode::Unauthorized, "Not signed by the operator key"),
+            )?;
+            session.sequence += 1;
+            println!("[Enclave] Admin command: {:?}", signed.command);
+            lock(state).admin(&signed.command, connections)
+        }
+    }
+}
@@ -0,0 +1,365 @@
+//! Attestation documents binding user data to the enclave image.
+//!
+//! An [`AttestationProvider`] produces the documents: the Nitro Secure
+//! Module in an enclave, or a [`MockAttestation`] signing key for testing
+//! without one. The [`Attester`] in front of it caches documents so
+//! identical content is attested once per TTL, and keeps the outcome of the
+//! latest attestation for health checks.
+
+use crate::chrono_lite_timestamp;
+use oprf_common::ed25519::SigningKey;
+use oprf_common::{sha256_hex, AttestationDocument, AttestationHealth};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// Source of attestation documents
+pub trait AttestationProvider: Send + Sync {
+    /// Attest `user_data` on behalf of the key whose serialized public key
+    /// is `public_key`
+    fn attest(&self, public_key: &[u8], user_data: &[u8]) -> Result<AttestationDocument, String>;
+}
+
+/// Mock attestations signed with an ephemeral Ed25519 key. Parents accept
+/// them only if told the public key, which the enclave prints at startup.
+pub struct MockAttestation {
+    key: SigningKey,
+}
+
+impl MockAttestation {
+    pub fn new(key: SigningKey) -> Self {
+        Self { key }
+    }
+
+    /// Public key verifying the documents, for the parent's
+    /// `--mock-signing-key`
+    pub fn public_key(&self) -> Vec<u8> {
+        self.key.public_key().to_vec()
+    }
+}
+
+impl AttestationProvider for MockAttestation {
+    fn attest(&self, public_key: &[u8], user_data: &[u8]) -> Result<AttestationDocument, String> {
+        println!("[Enclave] Generating mock attestation (local mode)");
+
+        let pcrs = vec!["0".repeat(96); 3]; // PCR0-2 - mock
+        let payload = serde_json::json!({
+            "module_id": "mock-enclave",
+            "timestamp": chrono_lite_timestamp(),
+            "public_key_hash": sha256_hex(public_key),
+            "pcrs": pcrs,
+            "user_data": hex::encode(oprf_common::nsm_user_data(user_data)),
+        });
+        let document = oprf_common::SignedMockDocument::sign(&self.key, &payload);
+
+        Ok(AttestationDocument {
+            is_mock: true,
+            document: serde_json::to_vec(&document).map_err(|e| e.to_string())?,
+            pcrs: Some(pcrs),
+            user_data: user_data.to_vec(),
+        })
+    }
+}
+
+/// Attestations from the Nitro Secure Module
+#[cfg(feature = "nitro")]
+pub struct NsmAttestation;
+
+#[cfg(feature = "nitro")]
+impl AttestationProvider for NsmAttestation {
+    fn attest(&self, _public_key: &[u8], user_data: &[u8]) -> Result<AttestationDocument, String> {
+        use aws_nitro_enclaves_nsm_api::api::{Request as NsmRequest, Response as NsmResponse};
+        use aws_nitro_enclaves_nsm_api::driver as nsm_driver;
+
+        println!("[Enclave] Generating NSM attestation (Nitro mode)");
+
+        let nsm_fd = nsm_driver::nsm_init();
+        if nsm_fd < 0 {
+            return Err("Failed to initialize NSM driver".to_string());
+        }
+
+        // Embed the user data itself, or its hash when it exceeds the NSM limit
+        let request = NsmRequest::Attestation {
+            user_data: Some(oprf_common::nsm_user_data(user_data).into()),
+            nonce: None,
+            public_key: None,
+        };
+
+        let response = nsm_driver::nsm_process_request(nsm_fd, request);
+
+        match response {
+            NsmResponse::Attestation { document } => {
+                // Parse CBOR to extract PCRs
+                let pcrs = extract_pcrs_from_attestation(&document);
+
+                Ok(AttestationDocument {
+                    is_mock: false,
+                    document,
+                    pcrs,
+                    user_data: user_data.to_vec(),
+                })
+            }
+            NsmResponse::Error(e) => Err(format!("NSM error: {:?}", e)),
+            _ => Err("Unexpected NSM response".to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "nitro")]
+fn extract_pcrs_from_attestation(document: &[u8]) -> Option<Vec<String>> {
+    // Parse CBOR attestation document to extract PCRs
+    let value: serde_cbor::Value = serde_cbor::from_slice(document). ok()?;
+
+    if let serde_cbor::Value::Map(map) = value {
+        for (key, val) in map {
+            if let serde_cbor::Value::Text(k) = key {
+                if k == "pcrs" {
+                    if let serde_cbor::Value::Map(pcr_map) = val {
+                        let mut pcrs = Vec::new();
+                        for i in 0..3 {
+                            if let Some((_, serde_cbor::Value::Bytes(bytes))) =
+                                pcr_map. iter().find(|(k, _)| **k == serde_cbor::Value::Integer(i))
+                            {
+                                pcrs.push(hex::encode(bytes));
+                            }
+                        }
+                        return Some(pcrs);
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Upper bound on cached attestation documents
+const MAX_CACHED_ATTESTATIONS: usize = 1024;
+
+/// Attestation provider with the documents it recently generated, keyed by
+/// the hash of the public key and user data they attest, so identical content
+/// is attested once per TTL. A TTL of zero disables caching.
+pub(crate) struct Attester {
+    provider: Box<dyn AttestationProvider>,
+    ttl_secs: u64,
+    /// Creation time and document by cache key
+    entries: HashMap<[u8; 32], (u64, AttestationDocument)>,
+    /// Outcome of the latest attestation, updated by shared borrows too
+    health: RefCell<AttestationHealth>,
+}
+
+impl Attester {
+    pub(crate) fn new(provider: Box<dyn AttestationProvider>, ttl_secs: u64) -> Self {
+        Self {
+            provider,
+            ttl_secs,
+            entries: HashMap::new(),
+            health: RefCell::new(AttestationHealth::default()),
+        }
+    }
+
+    fn key(public_key: &[u8], user_data: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update((public_key.len() as u64).to_be_bytes());
+        hasher.update(public_key);
+        hasher.update(user_data);
+        hasher.finalize().into()
+    }
+
+    fn is_fresh(&self, created_at: u64, now: u64) -> bool {
+        now < created_at.saturating_add(self.ttl_secs)
+    }
+
+    fn get(&self, key: &[u8; 32], now: u64) -> Option<AttestationDocument> {
+        self.entries
+            .get(key)
+            .filter(|(created_at, _)| self.is_fresh(*created_at, now))
+            .map(|(_, document)| document.clone())
+    }
+
+    fn insert(&mut self, key: [u8; 32], now: u64, document: AttestationDocument) {
+        if self.ttl_secs == 0 {
+            return;
+        }
+        let ttl_secs = self.ttl_secs;
+        self.entries
+            .retain(|_, (created_at, _)| now < created_at.saturating_add(ttl_secs));
+        if self.entries.len() >= MAX_CACHED_ATTESTATIONS {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (created_at, _))| *created_at)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(key, (now, document));
+    }
+
+    /// Drop every cached document, returning how many there were
+    pub(crate) fn clear(&mut self) -> usize {
+        let count = self.entries.len();
+        self.entries.clear();
+        count
+    }
+
+    /// Newly generated attestation over `user_data` for the key `public_key`
+    pub(crate) fn generate(
+        &self,
+        public_key: &[u8],
+        user_data: &[u8],
+    ) -> Result<AttestationDocument, String> {
+        let document = self.provider.attest(public_key, user_data);
+        let mut health = self.health.borrow_mut();
+        health.failing = document.is_err();
+        match &document {
+            Ok(_) => health.last_success = Some(chrono_lite_timestamp()),
+            Err(e) => health.last_error = Some(e.clone()),
+        }
+        document
+    }
+
+    /// How long documents are reused for identical content
+    pub(crate) fn ttl_secs(&self) -> u64 {
+        self.ttl_secs
+    }
+
+    /// Reuse documents for `ttl_secs` from now on, cached ones included
+    pub(crate) fn set_ttl_secs(&mut self, ttl_secs: u64) {
+        self.ttl_secs = ttl_secs;
+    }
+
+    /// Outcome of the latest attestation
+    pub(crate) fn health(&self) -> AttestationHealth {
+        self.health.borrow().clone()
+    }
+
+    /// Attestation over `user_data` for the key `public_key`, reused from the
+    /// cache while it is within its TTL unless `force_fresh` is set
+    pub(crate) fn cached(
+        &mut self,
+        public_key: &[u8],
+        user_data: &[u8],
+        force_fresh: bool,
+    ) -> Result<AttestationDocument, String> {
+        self.cached_at(public_key, user_data, force_fresh, chrono_lite_timestamp())
+    }
+
+    fn cached_at(
+        &mut self,
+        public_key: &[u8],
+        user_data: &[u8],
+        force_fresh: bool,
+        now: u64,
+    ) -> Result<AttestationDocument, String> {
+        let key = Self::key(public_key, user_data);
+        if !force_fresh {
+            if let Some(document) = self.get(&key, now) {
+                println!("[Enclave] Reusing cached attestation");
+                return Ok(document);
+            }
+        }
+        let document = self.generate(public_key, user_data)?;
+        self.insert(key, now, document.clone());
+        Ok(document)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+    use std::sync::Arc;
+
+    /// Numbers its documents, wrapping, and fails while `failing` is set
+    #[derive(Clone, Default)]
+    struct Counting {
+        count: Arc<AtomicU8>,
+        failing: Arc<AtomicBool>,
+    }
+
+    impl AttestationProvider for Counting {
+        fn attest(&self, _: &[u8], user_data: &[u8]) -> Result<AttestationDocument, String> {
+            if self.failing.load(Ordering::SeqCst) {
+                return Err("NSM unavailable".to_string());
+            }
+            Ok(AttestationDocument {
+                is_mock: true,
+                document: vec![self.count.fetch_add(1, Ordering::SeqCst)],
+                pcrs: None,
+                user_data: user_data.to_vec(),
+            })
+        }
+    }
+
+    const NOW: u64 = 1_700_000_000;
+
+    #[test]
+    fn test_reused_within_ttl() {
+        let provider = Counting::default();
+        let mut attester = Attester::new(Box::new(provider.clone()), 60);
+        let mut attest = |user_data: &[u8], force_fresh, now| {
+            attester.cached_at(b"pk", user_data, force_fresh, now).unwrap().document[0]
+        };
+
+        assert_eq!(attest(b"a", false, NOW), 0);
+        assert_eq!(attest(b"a", false, NOW + 59), 0);
+        // Other content, or a forced refresh, is attested anew
+        assert_eq!(attest(b"b", false, NOW + 59), 1);
+        assert_eq!(attest(b"a", true, NOW + 59), 2);
+        assert_eq!(attest(b"a", false, NOW + 60), 2);
+        assert_eq!(attest(b"a", false, NOW + 119), 3);
+        assert_eq!(attest(b"b", false, NOW + 119), 4);
+
+        // The public key is part of the content
+        assert_eq!(attester.cached_at(b"other", b"a", false, NOW + 119).unwrap().document, [5]);
+        assert_eq!(attester.clear(), 3);
+        assert_eq!(attester.cached_at(b"pk", b"a", false, NOW + 119).unwrap().document, [6]);
+    }
+
+    #[test]
+    fn test_zero_ttl_never_reuses() {
+        let mut attester = Attester::new(Box::new(Counting::default()), 0);
+        for expected in 0..3 {
+            assert_eq!(attester.cached_at(b"pk", b"a", false, NOW).unwrap().document, [expected]);
+        }
+        assert_eq!(attester.clear(), 0);
+
+        // A TTL set later applies from then on
+        attester.set_ttl_secs(60);
+        assert_eq!(attester.cached_at(b"pk", b"a", false, NOW).unwrap().document, [3]);
+        assert_eq!(attester.cached_at(b"pk", b"a", false, NOW + 1).unwrap().document, [3]);
+    }
+
+    #[test]
+    fn test_oldest_evicted_at_capacity() {
+        let mut attester = Attester::new(Box::new(Counting::default()), 3600);
+        for i in 0..MAX_CACHED_ATTESTATIONS as u64 {
+            attester.cached_at(b"pk", &i.to_be_bytes(), false, NOW + i % 2).unwrap();
+        }
+        attester.cached_at(b"pk", b"new", false, NOW + 2).unwrap();
+        assert_eq!(attester.entries.len(), MAX_CACHED_ATTESTATIONS);
+        // One of the documents of the first second went
+        let kept = |i: u64| attester.entries.contains_key(&Attester::key(b"pk", &i.to_be_bytes()));
+        let evicted: Vec<u64> = (0..MAX_CACHED_ATTESTATIONS as u64).filter(|i| !kept(*i)).collect();
+        assert!(matches!(evicted[..], [i] if i % 2 == 0), "{:?}", evicted);
+    }
+
+    #[test]
+    fn test_failures_not_cached() {
+        let provider = Counting::default();
+        let mut attester = Attester::new(Box::new(provider.clone()), 60);
+        provider.failing.store(true, Ordering::SeqCst);
+        let error = attester.cached_at(b"pk", b"a", false, NOW).unwrap_err();
+        assert_eq!(error, "NSM unavailable");
+        let health = attester.health();
+        assert!(health.failing);
+        assert_eq!(health.last_error.as_deref(), Some("NSM unavailable"));
+
+        provider.failing.store(false, Ordering::SeqCst);
+        assert_eq!(attester.cached_at(b"pk", b"a", false, NOW).unwrap().document, [0]);
+        let health = attester.health();
+        assert!(!health.failing && health.last_success.is_some());
+        assert_eq!(health.last_error.as_deref(), Some("NSM unavailable"));
+    }
+}
@@ -0,0 +1,135 @@
+//! The enclave's audit log of evaluations, see [`oprf_common::audit`].
+//!
+//! Every successful evaluation request is appended as one record. The most
+//! recent [`MAX_AUDIT_RECORDS`] records are kept for the parent to fetch and
+//! store; older ones are dropped, but stay covered by the head. Once the
+//! checkpoint interval has passed since the last checkpoint, the next
+//! evaluation or fetch of the log attests the current head.
+
+use crate::{bad_request, chrono_lite_timestamp};
+use oprf_common::audit::{AuditCheckpoint, AuditRecord, GENESIS};
+use oprf_common::{
+    AuditLogResponse, EnclaveResponse, GetAuditLogRequest, Message, OprfResult,
+    MAX_AUDIT_RECORDS_PER_RESPONSE,
+};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+
+/// Records kept, about 16 MiB at the largest record size
+const MAX_AUDIT_RECORDS: usize = 64 * 1024;
+
+/// Records of recent evaluations and the head of the chain over all of them
+pub(crate) struct AuditLog {
+    /// Kept records, oldest first, each with the head after it
+    records: VecDeque<(AuditRecord, [u8; 32])>,
+    /// Head before the oldest kept record
+    base: [u8; 32],
+    next_sequence: u64,
+    /// Seconds between checkpoints
+    interval_secs: u64,
+    checkpoint: Option<AuditCheckpoint>,
+    /// Unix time of the latest checkpoint
+    checkpointed_at: u64,
+}
+
+impl AuditLog {
+    pub(crate) fn new(interval_secs: u64) -> Self {
+        Self {
+            records: VecDeque::new(),
+            base: GENESIS,
+            next_sequence: 0,
+            interval_secs,
+            checkpoint: None,
+            checkpointed_at: 0,
+        }
+    }
+
+    /// Head of the chain over every record so far
+    fn head(&self) -> [u8; 32] {
+        self.records.back().map_or(self.base, |(_, head)| *head)
+    }
+
+    /// Append the evaluation `request` answered with `response`; other
+    /// requests are not logged
+    pub(crate) fn append(&mut self, request: &Message, response: &EnclaveResponse) {
+        let (ciphersuite, key_id, epoch, queries, client_nonce) = match (request, response) {
+            (Message::Evaluate(request), EnclaveResponse::Evaluate(response)) => (
+                request.ciphersuite,
+                &response.key_id,
+                response.epoch,
+                std::slice::from_ref(&request.blinded_query),
+                &request.client_nonce,
+            ),
+            (Message::BatchEvaluate(request), EnclaveResponse::BatchEvaluate(response)) => (
+                request.ciphersuite,
+                &response.key_id,
+                response.epoch,
+                request.blinded_queries.as_slice(),
+                &request.client_nonce,
+            ),
+            _ => return,
+        };
+        let record = AuditRecord {
+            sequence: self.next_sequence,
+            timestamp: chrono_lite_timestamp(),
+            ciphersuite,
+            key_id: key_id.clone(),
+            epoch,
+            count: queries.len() as u64,
+            query_hash: Sha256::digest(queries.concat()).to_vec(),
+            client_nonce: client_nonce.clone(),
+        };
+        let head = record.chain(&self.head());
+        if self.records.len() == MAX_AUDIT_RECORDS {
+            if let Some((_, dropped_head)) = self.records.pop_front() {
+                self.base = dropped_head;
+            }
+        }
+        self.records.push_back((record, head));
+        self.next_sequence += 1;
+    }
+
+    /// Number of records and head to attest, if the log grew since the
+    /// latest checkpoint and the interval has passed
+    pub(crate) fn checkpoint_due(&self) -> Option<(u64, [u8; 32])> {
+        let covered = self.checkpoint.as_ref().map_or(0, |checkpoint| checkpoint.sequence);
+        let next_at = self.checkpointed_at.saturating_add(self.interval_secs);
+        let due = chrono_lite_timestamp() >= next_at && covered < self.next_sequence;
+        due.then(|| (self.next_sequence, self.head()))
+    }
+
+    pub(crate) fn set_checkpoint(&mut self, checkpoint: AuditCheckpoint) {
+        println!("[Enclave] Attested audit log head after {} records", checkpoint.sequence);
+        self.checkpoint = Some(checkpoint);
+        self.checkpointed_at = chrono_lite_timestamp();
+    }
+
+    /// Kept records from `request.from` on, with the latest checkpoint
+    pub(crate) fn read(&self, request: &GetAuditLogRequest) -> OprfResult<AuditLogResponse> {
+        let oldest = self.next_sequence - self.records.len() as u64;
+        if request.from < oldest || request.from > self.next_sequence {
+            return Err(bad_request(format!(
+                "Audit records are kept from {} up to {}, not from {}",
+                oldest, self.next_sequence, request.from
+            )));
+        }
+        let skip = (request.from - oldest) as usize;
+        let previous_head = match skip.checked_sub(1) {
+            Some(index) => self.records[index].1,
+            None => self.base,
+        };
+
+        Ok(AuditLogResponse {
+            previous_head: previous_head.to_vec(),
+            records: self
+                .records
+                .iter()
+                .skip(skip)
+                .take(MAX_AUDIT_RECORDS_PER_RESPONSE)
+                .map(|(record, _)| record.clone())
+                .collect(),
+            next_sequence: self.next_sequence,
+            checkpoint: self.checkpoint.clone(),
+        })
+    }
+}
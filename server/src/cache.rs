@@ -0,0 +1,170 @@
+//! Cache of evaluations for repeated blinded queries.
+//!
+//! Clients that retry a request send the same blinded points again. With a
+//! cache of `n` entries, every key epoch remembers the evaluated points of
+//! its `n` most recently used blinded points and answers those from memory
+//! instead of multiplying them by the key again. The cache goes with its
+//! epoch, so a rotation or the expiry of the previous key drops it.
+//!
+//! Cached queries are otherwise served like any other: they count toward
+//! the evaluation budget and rate limits, are logged, proved and attested.
+//! A hit is answered faster than a miss, which tells the parent that the
+//! blinded point was evaluated before; it sees every request anyway.
+
+use std::collections::{BTreeMap, HashMap};
+
+/// Evaluated points by blinded point encoding, least recently used evicted
+/// first
+pub(crate) struct EvaluationCache<E> {
+    /// Evaluated point and last use of every cached blinded point
+    entries: HashMap<Vec<u8>, (E, u64)>,
+    /// Cached blinded points by last use
+    recency: BTreeMap<u64, Vec<u8>>,
+    /// Counter ordering uses
+    clock: u64,
+}
+
+impl<E> Default for EvaluationCache<E> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+}
+
+impl<E: Copy> EvaluationCache<E> {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Evaluation of `query`, if cached, which is then the most recently used
+    pub(crate) fn get(&mut self, query: &[u8]) -> Option<E> {
+        let now = self.tick();
+        let (evaluated, used) = self.entries.get_mut(query)?;
+        let query = self.recency.remove(used).expect("cached query has a last use");
+        *used = now;
+        self.recency.insert(now, query);
+        Some(*evaluated)
+    }
+
+    /// Drop every entry, returning how many there were
+    pub(crate) fn clear(&mut self) -> usize {
+        let count = self.entries.len();
+        self.entries.clear();
+        self.recency.clear();
+        count
+    }
+
+    /// Cache the evaluation of `query`, evicting the least recently used
+    /// entries beyond `capacity`
+    pub(crate) fn insert(&mut self, query: &[u8], evaluated: E, capacity: usize) {
+        if capacity == 0 || self.entries.contains_key(query) {
+            return;
+        }
+        while self.entries.len() >= capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+        let now = self.tick();
+        self.entries.insert(query.to_vec(), (evaluated, now));
+        self.recency.insert(now, query.to_vec());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enclave::Enclave;
+    use crate::in_process::tests::{attestation, blinded_query, evaluation, handle};
+    use crate::keys::{EvaluationOptions, UsageLimits};
+    use crate::reaper::{ConnectionLimits, Connections};
+    use oprf_common::admin::{AdminCommand, AdminResponse};
+    use oprf_common::{CiphersuiteId, EnclaveResponse, Message, OprfRequest, RotateKeyRequest};
+
+    #[test]
+    fn test_least_recently_used_evicted() {
+        let mut cache = EvaluationCache::default();
+        cache.insert(b"a", 1, 2);
+        cache.insert(b"b", 2, 2);
+        assert_eq!(cache.get(b"a"), Some(1));
+
+        // "b" is now the least recently used
+        cache.insert(b"c", 3, 2);
+        assert_eq!(cache.get(b"b"), None);
+        assert_eq!(cache.get(b"a"), Some(1));
+        assert_eq!(cache.get(b"c"), Some(3));
+
+        // Cached queries keep their evaluation, and no capacity caches nothing
+        cache.insert(b"a", 4, 2);
+        assert_eq!(cache.get(b"a"), Some(1));
+        cache.insert(b"d", 5, 0);
+        assert_eq!(cache.get(b"d"), None);
+        assert_eq!(cache.entries.len(), cache.recency.len());
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut cache = EvaluationCache::default();
+        cache.insert(b"a", 1, 4);
+        cache.insert(b"b", 2, 4);
+        assert_eq!(cache.clear(), 2);
+        assert_eq!(cache.get(b"a"), None);
+        assert_eq!(cache.clear(), 0);
+        cache.insert(b"a", 3, 4);
+        assert_eq!(cache.get(b"a"), Some(3));
+    }
+
+    /// Evaluated point of `request`
+    fn evaluate(enclave: &mut Enclave, request: OprfRequest) -> Vec<u8> {
+        match handle(enclave, &Message::Evaluate(request)) {
+            Ok(EnclaveResponse::Evaluate(response)) => response.evaluated_point,
+            other => panic!("unexpected answer {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_caches_of_epochs_apart() {
+        let mut enclave = Enclave::new(
+            UsageLimits::default(),
+            Default::default(),
+            EvaluationOptions {
+                threads: 1,
+                cache_entries: 16,
+            },
+            0,
+            60,
+            attestation(),
+        );
+        let query = blinded_query();
+        let first = evaluate(&mut enclave, evaluation(query.clone()));
+        assert_eq!(evaluate(&mut enclave, evaluation(query.clone())), first);
+
+        // The new epoch does not answer from the cache of the previous one
+        let rotate = RotateKeyRequest {
+            ciphersuite: CiphersuiteId::P256Sha256,
+            key_id: "default".to_string(),
+            grace_period_secs: Some(3600),
+        };
+        handle(&mut enclave, &Message::RotateKey(rotate)).unwrap();
+        let rotated = evaluate(&mut enclave, evaluation(query.clone()));
+        assert_ne!(rotated, first);
+        let previous = OprfRequest {
+            epoch: Some(0),
+            ..evaluation(query.clone())
+        };
+        assert_eq!(evaluate(&mut enclave, previous), first);
+
+        // Each epoch holds its own entry until flushed
+        let connections = Connections::start(ConnectionLimits::default()).unwrap();
+        match enclave.admin(&AdminCommand::FlushCaches, &connections) {
+            Ok(AdminResponse::FlushCaches(response)) => assert_eq!(response.evaluations, 2),
+            other => panic!("unexpected answer {:?}", other),
+        }
+        assert_eq!(evaluate(&mut enclave, evaluation(query)), rotated);
+    }
+}
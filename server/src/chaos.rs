@@ -0,0 +1,239 @@
+//! Fault injection, for testing how clients retry, time out and verify.
+//!
+//! Built with the `chaos` feature only. An enclave given a [`Chaos`] draws,
+//! for every response, whether to spoil it, with the configured probability,
+//! and if so one of the enabled [`Fault`]s:
+//!
+//! - `delay` holds the response back for up to the maximum delay
+//! - `truncate` writes part of the response frame and closes the connection
+//! - `corrupt` flips one bit of the payload, after it is signed and sealed
+//! - `error` answers with an `InternalError` in place of the response
+//!
+//! The request itself is served as usual: an evaluation still counts
+//! against the budget and is recorded in the audit log.
+
+use oprf_common::frame::{write_frame, WireFormat};
+use oprf_common::{ErrorCode, ErrorResponse};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A way to spoil a response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    Delay,
+    Truncate,
+    Corrupt,
+    Error,
+}
+
+impl Fault {
+    pub const ALL: [Fault; 4] = [Fault::Delay, Fault::Truncate, Fault::Corrupt, Fault::Error];
+}
+
+impl FromStr for Fault {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name {
+            "delay" => Ok(Fault::Delay),
+            "truncate" => Ok(Fault::Truncate),
+            "corrupt" => Ok(Fault::Corrupt),
+            "error" => Ok(Fault::Error),
+            _ => Err(format!(
+                "Unknown fault {}, expected delay, truncate, corrupt or error",
+                name
+            )),
+        }
+    }
+}
+
+/// Which responses to spoil and how
+#[derive(Debug, Clone)]
+pub struct Chaos {
+    probability: f64,
+    faults: Vec<Fault>,
+    max_delay: Duration,
+}
+
+impl Chaos {
+    /// Spoil each response with `probability`, by one of `faults` drawn
+    /// uniformly, delays lasting up to `max_delay`
+    pub fn new(probability: f64, faults: Vec<Fault>, max_delay: Duration) -> Result<Self, String> {
+        if !(0.0..=1.0).contains(&probability) {
+            return Err(format!("Fault probability {} is not between 0 and 1", probability));
+        }
+        if faults.is_empty() {
+            return Err("No faults to inject".to_string());
+        }
+        Ok(Self {
+            probability,
+            faults,
+            max_delay,
+        })
+    }
+
+    /// The fault to inject in the next response, if any
+    pub(crate) fn draw(&self) -> Option<Fault> {
+        let mut rng = rand::thread_rng();
+        if !rng.gen_bool(self.probability) {
+            return None;
+        }
+        self.faults.choose(&mut rng).copied()
+    }
+
+    /// Apply `fault` to the `payload` of the response frame about to be
+    /// written on `stream`. Returns false if the frame was cut short, and
+    /// the connection must be closed.
+    pub(crate) fn spoil<S: Write>(
+        &self,
+        fault: Fault,
+        stream: &mut S,
+        request_id: u64,
+        format: WireFormat,
+        payload: &mut [u8],
+    ) -> bool {
+        let mut rng = rand::thread_rng();
+        match fault {
+            Fault::Delay => {
+                let delay = self.max_delay.mul_f64(rng.gen());
+                println!("[Enclave] Chaos: delaying response {} by {:?}", request_id, delay);
+                std::thread::sleep(delay);
+            }
+            Fault::Corrupt if !payload.is_empty() => {
+                let bit = rng.gen_range(0..payload.len() * 8);
+                payload[bit / 8] ^= 1 << (bit % 8);
+                println!("[Enclave] Chaos: corrupted bit {} of response {}", bit, request_id);
+            }
+            Fault::Truncate => {
+                let mut frame = Vec::new();
+                if write_frame(&mut frame, request_id, format, payload).is_ok() {
+                    let len = rng.gen_range(0..frame.len());
+                    let _ = stream.write_all(&frame[..len]).and_then(|_| stream.flush());
+                    println!(
+                        "[Enclave] Chaos: truncated response {} to {} of {} bytes",
+                        request_id,
+                        len,
+                        frame.len()
+                    );
+                }
+                return false;
+            }
+            Fault::Corrupt | Fault::Error => {}
+        }
+        true
+    }
+}
+
+/// Error answered in place of a response by [`Fault::Error`]
+pub(crate) fn injected_error() -> ErrorResponse {
+    ErrorResponse::new(ErrorCode::InternalError, "Injected fault")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enclave::Enclave;
+    use crate::in_process::tests::start;
+    use crate::in_process::InProcess;
+    use oprf_common::frame::read_frame;
+    use oprf_common::{HelloRequest, HelloResponse, Message, OprfResult, PointEncoding};
+    use std::time::Instant;
+
+    fn chaos(faults: Vec<Fault>) -> Chaos {
+        Chaos::new(1.0, faults, Duration::from_millis(50)).unwrap()
+    }
+
+    /// An enclave spoiling every response with one of `faults`
+    fn spoiling(faults: Vec<Fault>) -> InProcess {
+        start(|attestation| {
+            let mut enclave = Enclave::new(
+                Default::default(),
+                Default::default(),
+                Default::default(),
+                0,
+                60,
+                attestation,
+            );
+            enclave.set_chaos(chaos(faults));
+            enclave
+        })
+    }
+
+    #[test]
+    fn test_configuration_checked() {
+        assert!(Chaos::new(1.5, Fault::ALL.to_vec(), Duration::ZERO).is_err());
+        assert!(Chaos::new(-0.1, Fault::ALL.to_vec(), Duration::ZERO).is_err());
+        assert_eq!(Chaos::new(0.5, vec![], Duration::ZERO).unwrap_err(), "No faults to inject");
+        let names = ["delay", "truncate", "corrupt", "error"];
+        let faults: Vec<Fault> = names.iter().map(|name| name.parse().unwrap()).collect();
+        assert_eq!(faults, Fault::ALL);
+        assert!("Delay".parse::<Fault>().is_err());
+    }
+
+    #[test]
+    fn test_draw() {
+        let never = Chaos::new(0.0, Fault::ALL.to_vec(), Duration::ZERO).unwrap();
+        assert!((0..100).all(|_| never.draw().is_none()));
+        let always = chaos(vec![Fault::Corrupt, Fault::Error]);
+        for _ in 0..100 {
+            assert!(matches!(always.draw(), Some(Fault::Corrupt | Fault::Error)));
+        }
+    }
+
+    #[test]
+    fn test_spoil() {
+        let chaos = chaos(Fault::ALL.to_vec());
+        let original = vec![0x5a; 64];
+        let spoil = |fault| {
+            let (mut stream, mut payload) = (Vec::new(), original.clone());
+            let whole = chaos.spoil(fault, &mut stream, 1, WireFormat::Cbor, &mut payload);
+            (whole, stream, payload)
+        };
+
+        let (whole, written, payload) = spoil(Fault::Corrupt);
+        assert!(whole && written.is_empty());
+        let flipped: u32 = payload.iter().zip(&original).map(|(a, b)| (a ^ b).count_ones()).sum();
+        assert_eq!(flipped, 1);
+
+        // Part of the frame is written, and the connection must be closed
+        let (whole, written, _) = spoil(Fault::Truncate);
+        let mut frame = Vec::new();
+        write_frame(&mut frame, 1, WireFormat::Cbor, &original).unwrap();
+        assert!(!whole);
+        assert!(written.len() < frame.len() && frame.starts_with(&written));
+
+        let started = Instant::now();
+        let (whole, _, payload) = spoil(Fault::Delay);
+        assert!(whole && payload == original);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(spoil(Fault::Error).2, original);
+    }
+
+    #[test]
+    fn test_faults_in_responses() {
+        // Every response is spoiled, the Hello's included
+        let enclave = spoiling(vec![Fault::Error]);
+        let mut stream = enclave.connect().unwrap();
+        let hello = Message::Hello(HelloRequest {
+            versions: vec![2],
+            point_encoding: PointEncoding::default(),
+            request_ids: false,
+        });
+        let hello = WireFormat::Json.encode(&hello).unwrap();
+        write_frame(&mut stream, 1, WireFormat::Json, &hello).unwrap();
+        let frame = read_frame(&mut stream).unwrap().unwrap();
+        let response: OprfResult<HelloResponse> = WireFormat::Json.decode(&frame.payload).unwrap();
+        let error = response.unwrap_err();
+        assert_eq!(error.code, ErrorCode::InternalError);
+        assert_eq!(error.message, "Injected fault");
+
+        // A truncated response ends the connection
+        let enclave = spoiling(vec![Fault::Truncate]);
+        let mut stream = enclave.connect().unwrap();
+        write_frame(&mut stream, 1, WireFormat::Json, &hello).unwrap();
+        assert!(!matches!(read_frame(&mut stream), Ok(Some(_))));
+    }
+}
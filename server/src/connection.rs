@@ -0,0 +1,340 @@
+//! One parent connection: the opening Hello and channel handshake, then
+//! request frames answered in order.
+//!
+//! A panic while decoding or serving a request, such as one in a dependency
+//! parsing crafted input, is caught and answered with `InternalError` like
+//! any other failure, so it ends neither the connection nor its worker.
+
+use crate::enclave::Enclave;
+use crate::metrics::{Metrics, Timings};
+use crate::bad_request;
+#[cfg(feature = "chaos")]
+use crate::chaos::{self, Chaos, Fault};
+use oprf_common::channel::Channel;
+use oprf_common::ed25519::SigningKey;
+use oprf_common::frame::{read_frame, write_frame, Frame};
+use oprf_common::signing::sign_response;
+use oprf_common::{
+    negotiate_version, request_tag, validate_request_id, EnclaveResponse, ErrorCode,
+    ErrorResponse, HelloRequest, HelloResponse, Message, OprfResult, PointEncoding, Stage,
+    DEFAULT_PROTOCOL_VERSION, SUPPORTED_PROTOCOL_VERSIONS,
+};
+use std::io::{Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+use zeroize::Zeroizing;
+
+/// What the opening requests of a connection settled, which its
+/// evaluations are answered under
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Negotiated {
+    pub(crate) protocol_version: u32,
+    /// Encoding of blinded and evaluated points
+    pub(crate) point_encoding: PointEncoding,
+}
+
+/// State of a connection, set up by its opening requests
+struct Session {
+    /// Set by the opening request: a handshake or the pre-handshake default
+    protocol_version: Option<u32>,
+    /// Secure channel of a version 3 connection, once its handshake is done
+    channel: Option<Channel>,
+    /// Key signing every response after the Hello of a version 4 connection
+    signing_key: Option<Arc<SigningKey>>,
+    /// Encoding of the evaluated points, set by the Hello
+    point_encoding: PointEncoding,
+    /// Where the requests of the connection are counted and timed
+    metrics: Arc<Metrics>,
+    /// Faults injected in the responses of the connection
+    #[cfg(feature = "chaos")]
+    chaos: Option<Arc<Chaos>>,
+}
+
+/// Serve requests on a connection until the parent closes it
+pub(crate) fn handle_connection<S: Read + Write>(stream: &mut S, state: &Mutex<Enclave>) {
+    let mut served = 0u64;
+    #[cfg(feature = "chaos")]
+    let chaos = lock(state).chaos();
+    let mut session = Session {
+        protocol_version: None,
+        channel: None,
+        signing_key: None,
+        point_encoding: PointEncoding::default(),
+        metrics: lock(state).metrics(),
+        #[cfg(feature = "chaos")]
+        chaos,
+    };
+    while handle_request(stream, state, &mut session) {
+        served += 1;
+    }
+    println!("[Enclave] Connection closed after {} request(s)", served);
+}
+
+/// Answer the opening [`HelloRequest`] of a connection with the version to use
+fn negotiate(request: &HelloRequest) -> OprfResult<HelloResponse> {
+    let version = negotiate_version(&request.versions).ok_or_else(|| {
+        bad_request(format!(
+            "No common protocol version: offered {:?}, supported {:?}",
+            request.versions, SUPPORTED_PROTOCOL_VERSIONS
+        ))
+    })?;
+    println!("[Enclave] Negotiated protocol version {}", version);
+    if !request.point_encoding.is_compressed() {
+        println!("[Enclave] Using {:?} point encoding", request.point_encoding);
+    }
+    Ok(HelloResponse {
+        version,
+        channel_key: None,
+        signing_key: None,
+        attestation: None,
+        point_encoding: request.point_encoding,
+        request_ids: request.request_ids,
+    })
+}
+
+/// The enclave state, also after a request panicked while holding it:
+/// refusing every later request instead would let one crafted request
+/// disable the enclave
+pub(crate) fn lock(state: &Mutex<Enclave>) -> MutexGuard<'_, Enclave> {
+    state.lock().unwrap_or_else(|poisoned| {
+        state.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// Handle a request on the connection of `session`, which the opening
+/// requests set up. A version 3 connection must complete the channel
+/// handshake before anything else.
+fn serve(
+    request: &Message,
+    state: &Mutex<Enclave>,
+    session: &mut Session,
+    timings: &mut Timings,
+) -> OprfResult<EnclaveResponse> {
+    if let (Message::Hello(hello), None) = (request, session.protocol_version) {
+        let mut response = negotiate(hello)?;
+        if response.version >= 3 {
+            let mut enclave = lock(state);
+            enclave.channel_offer(&hello.versions, &mut response)?;
+            if response.version >= 4 {
+                session.signing_key = Some(enclave.signing_key());
+            }
+        }
+        session.protocol_version = Some(response.version);
+        session.point_encoding = response.point_encoding;
+        return Ok(EnclaveResponse::Hello(response));
+    }
+    let version = *session.protocol_version.get_or_insert(DEFAULT_PROTOCOL_VERSION);
+    if version >= 3 && session.channel.is_none() {
+        let Message::Handshake(handshake) = request else {
+            return Err(bad_request("Version 3 requires the channel handshake first".to_string()));
+        };
+        let (channel, response) = lock(state).handshake(handshake)?;
+        session.channel = Some(channel);
+        println!("[Enclave] Secure channel established");
+        return Ok(EnclaveResponse::Handshake(response));
+    }
+    let mut enclave = lock(state);
+    enclave.rate_limit(request)?;
+    let negotiated = Negotiated {
+        protocol_version: version,
+        point_encoding: session.point_encoding,
+    };
+    enclave.handle(request, negotiated, timings)
+}
+
+/// Decode and serve the request of `frame`, failing it with `InternalError`
+/// if that panics. Also returns the request id of an evaluation, if it is a
+/// valid one.
+fn serve_frame(
+    frame: &Frame,
+    state: &Mutex<Enclave>,
+    session: &mut Session,
+    timings: &mut Timings,
+) -> (OprfResult<EnclaveResponse>, Option<String>) {
+    let mut request_id = None;
+    let served = panic::catch_unwind(AssertUnwindSafe(|| {
+        let request =
+            timings.time(Stage::Deserialize, || frame.format.decode::<Message>(&frame.payload));
+        match request {
+            Ok(request) => {
+                // An invalid id is refused with the request, and kept out of the logs
+                request_id = request
+                    .request_id()
+                    .filter(|id| validate_request_id(id).is_ok())
+                    .map(str::to_string);
+                serve(&request, state, session, timings)
+            }
+            Err(e) => Err(bad_request(format!("Failed to parse request: {}", e))),
+        }
+    }));
+    let response = served.unwrap_or_else(|_| {
+        Err(ErrorResponse::new(ErrorCode::InternalError, "Request handling panicked"))
+    });
+    (response, request_id)
+}
+
+/// Serve one request frame, answering with the same request id and wire
+/// format. Returns false once the connection is closed, or dropped after an
+/// error that cannot be answered.
+fn handle_request<S: Read + Write>(
+    stream: &mut S,
+    state: &Mutex<Enclave>,
+    session: &mut Session,
+) -> bool {
+    // The payload may carry KMS credentials and is wiped once handled
+    let mut frame = match read_frame(stream) {
+        Ok(Some(frame)) => frame,
+        Ok(None) => return false,
+        Err(e) => {
+            eprintln!("[Enclave] Failed to read request: {}", e);
+            return false;
+        }
+    };
+
+    let started = Instant::now();
+    let mut timings = Timings::default();
+
+    // Frames after the channel handshake are sealed, and so are their answers;
+    // answers after a version 4 Hello are signed too
+    let sealed = session.channel.is_some();
+    let signed = session.signing_key.is_some();
+    if let Some(channel) = &mut session.channel {
+        if let Err(e) = timings.time(Stage::Deserialize, || channel.open(&mut frame)) {
+            eprintln!("[Enclave] Dropping connection: {}", e);
+            return false;
+        }
+    }
+
+    // Parse and process request, holding the state only while handling it
+    let (response, request_id) = serve_frame(&frame, state, session, &mut timings);
+    #[cfg(feature = "chaos")]
+    let fault = session.chaos.as_ref().and_then(|chaos| chaos.draw());
+    #[cfg(feature = "chaos")]
+    let response = match fault {
+        Some(Fault::Error) => Err(chaos::injected_error()),
+        _ => response,
+    };
+    let tag = request_tag(request_id.as_deref());
+    let failed = response.is_err();
+    if let Err(e) = &response {
+        eprintln!("[Enclave] {}Request {} failed: {}", tag, frame.request_id, e);
+    }
+
+    // Send response
+    let encoding_started = Instant::now();
+    let version = session.protocol_version.unwrap_or(DEFAULT_PROTOCOL_VERSION);
+    let encoded = match response {
+        // The handshake answer is bare, so clients of every version read it
+        Ok(response @ EnclaveResponse::Hello(_)) => frame.format.encode(&response),
+        response if version >= 2 => frame.format.encode(&response),
+        Ok(response) => frame.format.encode(&response),
+        // Version 1 has no error responses: the connection is dropped instead
+        Err(_) => {
+            session.metrics.record(&timings, started.elapsed(), failed);
+            return false;
+        }
+    };
+    let mut response_bytes = match encoded {
+        Ok(bytes) => Zeroizing::new(bytes),
+        Err(e) => {
+            eprintln!("[Enclave] Failed to encode response: {}", e);
+            return false;
+        }
+    };
+    if let Some(key) = session.signing_key.as_ref().filter(|_| signed) {
+        let signature = sign_response(key, frame.request_id, frame.format, &response_bytes);
+        response_bytes = Zeroizing::new([&response_bytes[..], &signature].concat());
+    }
+    #[cfg_attr(not(feature = "chaos"), allow(unused_mut))]
+    let mut response_bytes = match session.channel.as_mut().filter(|_| sealed) {
+        Some(channel) => match channel.seal(frame.request_id, frame.format, &response_bytes) {
+            Ok(bytes) => Zeroizing::new(bytes),
+            Err(e) => {
+                eprintln!("[Enclave] Failed to seal response: {}", e);
+                return false;
+            }
+        },
+        None => response_bytes,
+    };
+    timings.add(Stage::Serialize, encoding_started.elapsed());
+    #[cfg(feature = "chaos")]
+    if let (Some(chaos), Some(fault)) = (&session.chaos, fault) {
+        let (id, format) = (frame.request_id, frame.format);
+        if !chaos.spoil(fault, stream, id, format, &mut response_bytes) {
+            session.metrics.record(&timings, started.elapsed(), true);
+            return false;
+        }
+    }
+    if let Err(e) = write_frame(stream, frame.request_id, frame.format, &response_bytes) {
+        eprintln!("[Enclave] Failed to send response: {}", e);
+        return false;
+    }
+    session.metrics.record(&timings, started.elapsed(), failed);
+    println!("[Enclave] {}Response to request {} sent successfully", tag, frame.request_id);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attestation::AttestationProvider;
+    use crate::in_process::tests::{attestation, blinded_query, connect, evaluation, request};
+    use crate::in_process::InProcess;
+    use crate::keys::UsageLimits;
+    use oprf_common::{AttestationDocument, CiphersuiteId, GetPublicKeyRequest};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Mock attestations, except the one after it is armed, which panics
+    struct Panicking {
+        armed: Arc<AtomicBool>,
+        attestation: Box<dyn AttestationProvider>,
+    }
+
+    impl AttestationProvider for Panicking {
+        fn attest(
+            &self,
+            public_key: &[u8],
+            user_data: &[u8],
+        ) -> Result<AttestationDocument, String> {
+            if self.armed.swap(false, Ordering::SeqCst) {
+                panic!("attestation panicked");
+            }
+            self.attestation.attest(public_key, user_data)
+        }
+    }
+
+    #[test]
+    fn test_panic_answered_with_internal_error() {
+        let armed = Arc::new(AtomicBool::new(false));
+        let provider = Panicking {
+            armed: Arc::clone(&armed),
+            attestation: attestation(),
+        };
+        let enclave = Enclave::new(
+            UsageLimits::default(),
+            Default::default(),
+            Default::default(),
+            0,
+            60,
+            Box::new(provider),
+        );
+        let enclave = InProcess::start(enclave).unwrap();
+        let mut stream = connect(&enclave);
+
+        armed.store(true, Ordering::SeqCst);
+        let evaluate = Message::Evaluate(evaluation(blinded_query()));
+        let error = request(&mut stream, &evaluate).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InternalError);
+        assert_eq!(error.message, "Request handling panicked");
+
+        // The connection and the enclave state it held survive the panic
+        request(&mut stream, &evaluate).unwrap();
+        let public_key = Message::GetPublicKey(GetPublicKeyRequest {
+            ciphersuite: CiphersuiteId::P256Sha256,
+            key_id: "default".to_string(),
+        });
+        request(&mut connect(&enclave), &public_key).unwrap();
+    }
+}
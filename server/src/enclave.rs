@@ -0,0 +1,845 @@
+//! The enclave: key rings of every ciphersuite, their shared attestation
+//! provider and the secure channel key.
+
+use crate::attestation::{AttestationProvider, Attester};
+use crate::audit::AuditLog;
+#[cfg(feature = "chaos")]
+use crate::chaos::Chaos;
+use crate::connection::Negotiated;
+use crate::keys::{BackupEntry, EvaluationOptions, Evaluator, KeyRing, UsageLimits};
+use crate::metrics::{Metrics, Timings};
+use crate::ratelimit::{Rate, RateLimiter, RateLimits};
+use crate::reaper::Connections;
+use crate::replay::ReplayWindow;
+use crate::replication::{self, PendingReplica};
+use crate::{bad_request, chrono_lite_timestamp, kms, memory};
+use oprf_common::admin::{
+    AdminCommand, AdminResponse, FlushCachesResponse, KeyListResponse, ReloadRequest,
+    ReloadResponse, ShutdownResponse, StatusResponse,
+};
+use oprf_common::audit::{checkpoint_binding, AuditCheckpoint};
+use oprf_common::channel::{channel_binding, Channel, StaticKey};
+use oprf_common::ed25519::SigningKey;
+use oprf_common::envelope::{open, seal, NONCE_LEN};
+use oprf_common::kdf::MIN_SEED_LEN;
+use oprf_common::redact::{log_sensitive, set_log_sensitive};
+use oprf_common::rng::SharedRng;
+use oprf_common::selftest::{self, report_binding};
+use oprf_common::signing::session_binding;
+use oprf_common::{
+    AttestationDocument, BeginReplicationResponse, Bn254Sha256, CiphersuiteId, EnclaveResponse,
+    ErrorCode, ErrorResponse, ExportKeysRequest, ExportKeysResponse, ExportReplicaRequest,
+    ExportReplicaResponse, HandshakeRequest, HandshakeResponse, HealthResponse, HelloResponse,
+    ImportKeysRequest, ImportKeysResponse, ImportReplicaRequest, ImportReplicaResponse, KeyBackup,
+    Message, OprfResult, P256Sha256, ProvisionSeedRequest, ProvisionSeedResponse, PublicKeyInfo,
+    SelfTestResponse, DEFAULT_KEY_ID,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Associated data binding sealed key backups to their format
+const BACKUP_AAD: &[u8] = b"oprf-key-backup-v1";
+
+/// Plaintext of a sealed key backup
+#[derive(Serialize, Deserialize)]
+struct BackupContents {
+    /// Provisioned seed, so keys created after a restore are derived from it too
+    seed: Option<Vec<u8>>,
+    keys: Vec<BackupEntry>,
+}
+
+impl Drop for BackupContents {
+    fn drop(&mut self) {
+        self.seed.zeroize();
+    }
+}
+
+/// Enclave keys for every supported ciphersuite
+pub struct Enclave {
+    bn254: KeyRing<Bn254Sha256>,
+    p256: KeyRing<P256Sha256>,
+    limits: UsageLimits,
+    /// Evaluation rates of connections and clients
+    rates: RateLimiter,
+    /// Threads and cache of evaluations
+    evaluation: Evaluator,
+    attestations: Attester,
+    /// Static key of the secure channel, attested to version 3 clients
+    channel_key: StaticKey,
+    /// Session key signing every response of a version 4 connection
+    signing_key: Arc<SigningKey>,
+    /// Client nonces of recent evaluations
+    nonces: ReplayWindow,
+    /// Hash-chained record of every evaluation
+    audit: AuditLog,
+    /// Request counters and stage latencies, see [`Message::GetStats`]
+    metrics: Arc<Metrics>,
+    /// Key management is only accepted from the admin port
+    key_management_reserved: bool,
+    /// Known-answer tests run on boot and their attestation
+    self_test: Option<SelfTestResponse>,
+    /// Replication started by the last [`Message::BeginReplication`]
+    replica: Option<PendingReplica>,
+    /// KMS key backups are made under and seeds and backups are decrypted
+    /// with, see [`crate::kms`]
+    kms_key_id: Option<String>,
+    /// Source of every key the enclave generates
+    rng: SharedRng,
+    /// Faults injected in responses
+    #[cfg(feature = "chaos")]
+    chaos: Option<Arc<Chaos>>,
+}
+
+impl Enclave {
+    /// Fresh random keys, evaluated within `limits` at no more than
+    /// `rate_limits` as `evaluation` sets out, and attested by `provider`.
+    /// Evaluation attestations are reused for identical content for
+    /// `attestation_ttl_secs`, and the head of the audit log is attested at
+    /// most every `audit_checkpoint_secs`.
+    pub fn new(
+        limits: UsageLimits,
+        rate_limits: RateLimits,
+        evaluation: EvaluationOptions,
+        attestation_ttl_secs: u64,
+        audit_checkpoint_secs: u64,
+        provider: Box<dyn AttestationProvider>,
+    ) -> Self {
+        Self::with_rng(
+            limits,
+            rate_limits,
+            evaluation,
+            attestation_ttl_secs,
+            audit_checkpoint_secs,
+            provider,
+            SharedRng::os(),
+        )
+    }
+
+    /// Like [`Enclave::new`], with keys, proof nonces and the channel and
+    /// signing keys drawn from `rng`. A seeded RNG, see
+    /// [`SharedRng::seeded`], makes an enclave reproducible for tests.
+    pub fn with_rng(
+        limits: UsageLimits,
+        rate_limits: RateLimits,
+        evaluation: EvaluationOptions,
+        attestation_ttl_secs: u64,
+        audit_checkpoint_secs: u64,
+        provider: Box<dyn AttestationProvider>,
+        mut rng: SharedRng,
+    ) -> Self {
+        memory::disable_core_dumps();
+        if rng.is_seeded() {
+            eprintln!("[Enclave] WARNING: drawing keys from a seeded RNG, for testing only");
+        }
+        Self {
+            bn254: KeyRing::new(None, rng.clone()),
+            p256: KeyRing::new(None, rng.clone()),
+            limits,
+            rates: RateLimiter::new(rate_limits),
+            evaluation: Evaluator::new(EvaluationOptions {
+                threads: evaluation.threads.max(1),
+                ..evaluation
+            }),
+            attestations: Attester::new(provider, attestation_ttl_secs),
+            channel_key: StaticKey::generate(&mut rng).expect("P-256 key serializes"),
+            signing_key: Arc::new(SigningKey::generate(&mut rng)),
+            nonces: ReplayWindow::default(),
+            audit: AuditLog::new(audit_checkpoint_secs),
+            metrics: Arc::new(Metrics::new()),
+            key_management_reserved: false,
+            self_test: None,
+            replica: None,
+            kms_key_id: None,
+            rng,
+            #[cfg(feature = "chaos")]
+            chaos: None,
+        }
+    }
+
+    /// Spoil responses as `chaos` sets out, on the connections opened from
+    /// now on, see [`crate::Chaos`]
+    #[cfg(feature = "chaos")]
+    pub fn set_chaos(&mut self, chaos: Chaos) {
+        eprintln!("[Enclave] WARNING: injecting faults in responses: {:?}", chaos);
+        self.chaos = Some(Arc::new(chaos));
+    }
+
+    /// Make every key backup under the KMS key `kms_key_id`, and decrypt
+    /// backups and seeds with it only. Nitro enclaves refuse KMS requests
+    /// without one.
+    pub fn set_kms_key_id(&mut self, kms_key_id: String) {
+        println!("[Enclave] KMS key: {}", kms_key_id);
+        self.kms_key_id = Some(kms_key_id);
+    }
+
+    /// Faults to inject in the responses of a new connection
+    #[cfg(feature = "chaos")]
+    pub(crate) fn chaos(&self) -> Option<Arc<Chaos>> {
+        self.chaos.clone()
+    }
+
+    /// Run the known-answer tests of [`selftest`] and attest their report
+    /// by the default BN254 key, failing if any test did. The report is
+    /// attested either way, so it is the enclave's first attestation.
+    pub(crate) fn self_test(&mut self) -> Result<(), String> {
+        let report = selftest::run();
+        let key = &self.bn254.get_or_create(DEFAULT_KEY_ID)?.current;
+        let attestation =
+            self.attestations.generate(&key.public_key_bytes, &report_binding(&report))?;
+        let failures: Vec<String> = report
+            .failures()
+            .map(|failure| format!("{} {:?}", failure.ciphersuite.identifier(), failure.test))
+            .collect();
+        self.self_test = Some(SelfTestResponse {
+            report,
+            attestation,
+        });
+        if !failures.is_empty() {
+            return Err(format!("Known-answer tests failed: {}", failures.join(", ")));
+        }
+        println!("[Enclave] Known-answer self-test passed");
+        Ok(())
+    }
+
+    /// Refuse key management on the data path, once an admin port serves it,
+    /// see [`Message::is_key_management`]
+    pub(crate) fn reserve_key_management(&mut self) {
+        self.key_management_reserved = true;
+    }
+
+    /// Offer the channel key and its attestation in a version 3 `hello`,
+    /// and from version 4 on the response signing key, attested along with
+    /// it and the `offered` versions of the parent's `Hello`. The
+    /// attestation is cached like evaluation attestations.
+    pub(crate) fn channel_offer(
+        &mut self,
+        offered: &[u32],
+        hello: &mut HelloResponse,
+    ) -> OprfResult<()> {
+        let channel_key = self.channel_key.public_key().to_vec();
+        let binding = if hello.version >= 4 {
+            let signing_key = self.signing_key.public_key();
+            hello.signing_key = Some(signing_key.to_vec());
+            session_binding(&channel_key, offered, &signing_key)
+        } else {
+            channel_binding(&channel_key, offered)
+        };
+        let key = &self.bn254.get_or_create(DEFAULT_KEY_ID)?.current;
+        hello.attestation = Some(self.attestations.cached(&key.public_key_bytes, &binding, false)?);
+        hello.channel_key = Some(channel_key);
+        Ok(())
+    }
+
+    /// Session key signing the responses of version 4 connections
+    pub(crate) fn signing_key(&self) -> Arc<SigningKey> {
+        Arc::clone(&self.signing_key)
+    }
+
+    /// Counters connections record their requests in
+    pub(crate) fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// Answer the channel handshake of a version 3 connection
+    pub(crate) fn handshake(
+        &self,
+        request: &HandshakeRequest,
+    ) -> OprfResult<(Channel, HandshakeResponse)> {
+        let (channel, message) = self
+            .channel_key
+            .respond(&request.message, &mut self.rng.clone())
+            .map_err(|e| bad_request(format!("Channel handshake failed: {}", e)))?;
+        Ok((channel, HandshakeResponse { message }))
+    }
+
+    /// Take the evaluations of `request` from the rate limits of the
+    /// connections and of its client
+    pub(crate) fn rate_limit(&mut self, request: &Message) -> OprfResult<()> {
+        self.rates.check(request)
+    }
+
+    /// Dispatch a request to the keys of its ciphersuite, refusing
+    /// evaluations that replay a client nonce and logging the others.
+    /// Seed provisioning and replication are refused here, and so is all key
+    /// management once an admin port serves it.
+    /// Evaluations are answered as the connection `negotiated`, and add the
+    /// time of their stages to `timings`.
+    pub(crate) fn handle(
+        &mut self,
+        request: &Message,
+        negotiated: Negotiated,
+        timings: &mut Timings,
+    ) -> OprfResult<EnclaveResponse> {
+        let client_nonce = match request {
+            Message::Evaluate(request) => request.client_nonce.as_deref(),
+            Message::BatchEvaluate(request) => request.client_nonce.as_deref(),
+            _ => None,
+        };
+        if let Some(nonce) = client_nonce {
+            self.nonces.check(nonce)?;
+        }
+
+        match request {
+            Message::ProvisionSeed(_) => Err(ErrorResponse::new(
+                ErrorCode::Unauthorized,
+                "Seed provisioning is only accepted on the admin port",
+            )),
+            Message::BeginReplication | Message::ExportReplica(_) | Message::ImportReplica(_) => {
+                Err(ErrorResponse::new(
+                    ErrorCode::Unauthorized,
+                    "Replication is only accepted on the admin port",
+                ))
+            }
+            request if self.key_management_reserved && request.is_key_management() => {
+                Err(ErrorResponse::new(
+                    ErrorCode::Unauthorized,
+                    "Key management is reserved to the admin port",
+                ))
+            }
+            Message::Hello(_) => {
+                Err(bad_request("Hello is only accepted as the first request".to_string()))
+            }
+            Message::Handshake(_) => Err(bad_request(
+                "Handshake is only accepted right after a version 3 Hello".to_string(),
+            )),
+            Message::Health => Ok(EnclaveResponse::Health(self.health())),
+            Message::ExportKeys(request) => {
+                Ok(EnclaveResponse::ExportKeys(self.export_keys(request)?))
+            }
+            Message::ImportKeys(request) => {
+                Ok(EnclaveResponse::ImportKeys(self.import_keys(request)?))
+            }
+            Message::GetStats => Ok(EnclaveResponse::Stats(self.metrics.snapshot())),
+            Message::GetSelfTest => match &self.self_test {
+                Some(response) => Ok(EnclaveResponse::SelfTest(response.clone())),
+                None => Err(ErrorResponse::from("The self-test has not run".to_string())),
+            },
+            Message::GetAuditLog(request) => {
+                self.checkpoint_audit_log()?;
+                Ok(EnclaveResponse::AuditLog(self.audit.read(request)?))
+            }
+            request => {
+                let response = match request.ciphersuite() {
+                    Some(CiphersuiteId::Bn254Sha256) => {
+                        let attestations = &mut self.attestations;
+                        self.bn254.handle(
+                            request,
+                            &self.limits,
+                            &self.evaluation,
+                            attestations,
+                            negotiated,
+                            timings,
+                        )
+                    }
+                    Some(CiphersuiteId::P256Sha256) => {
+                        let attestations = &mut self.attestations;
+                        self.p256.handle(
+                            request,
+                            &self.limits,
+                            &self.evaluation,
+                            attestations,
+                            negotiated,
+                            timings,
+                        )
+                    }
+                    None => Err(bad_request("Request is not bound to a ciphersuite".to_string())),
+                }?;
+                self.audit.append(request, &response);
+                // The evaluation is done and logged; a failed checkpoint is
+                // retried with the next one
+                if let Err(e) = self.checkpoint_audit_log() {
+                    eprintln!("[Enclave] Failed to attest the audit log: {}", e);
+                }
+                Ok(response)
+            }
+        }
+    }
+
+    /// Uptime, default key epochs and attestation backend state
+    pub(crate) fn health(&self) -> HealthResponse {
+        HealthResponse {
+            timestamp: chrono_lite_timestamp(),
+            key_count: self.bn254.keys.len() + self.p256.keys.len(),
+            uptime_secs: self.metrics.uptime_secs(),
+            epochs: vec![self.bn254.default_epoch(), self.p256.default_epoch()],
+            attestation: self.attestations.health(),
+        }
+    }
+
+    /// Carry out an operator command of the admin port, with `connections`
+    /// the data path's. A shutdown is only answered here; the admin port
+    /// exits once the answer is sent.
+    pub(crate) fn admin(
+        &mut self,
+        command: &AdminCommand,
+        connections: &Connections,
+    ) -> OprfResult<AdminResponse> {
+        let open_connections = connections.count();
+        match command {
+            AdminCommand::Status => {
+                let stats = self.metrics.snapshot();
+                Ok(AdminResponse::Status(StatusResponse {
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                    uptime_secs: stats.uptime_secs,
+                    key_count: self.bn254.keys.len() + self.p256.keys.len(),
+                    seed_provisioned: self.bn254.seed.is_some(),
+                    open_connections,
+                    requests: stats.requests,
+                    evaluations: stats.evaluations,
+                }))
+            }
+            AdminCommand::ListKeys => {
+                let mut keys = self.bn254.key_epochs();
+                keys.extend(self.p256.key_epochs());
+                Ok(AdminResponse::Keys(KeyListResponse { keys }))
+            }
+            AdminCommand::RotateKey(request) => {
+                let response = match request.ciphersuite {
+                    CiphersuiteId::Bn254Sha256 => self.bn254.rotate(request, &self.attestations),
+                    CiphersuiteId::P256Sha256 => self.p256.rotate(request, &self.attestations),
+                }?;
+                Ok(AdminResponse::RotateKey(response))
+            }
+            AdminCommand::FlushCaches => {
+                let response = FlushCachesResponse {
+                    attestations: self.attestations.clear(),
+                    evaluations: self.bn254.flush_caches() + self.p256.flush_caches(),
+                };
+                println!(
+                    "[Enclave] Flushed {} cached attestations and {} cached evaluations",
+                    response.attestations, response.evaluations
+                );
+                Ok(AdminResponse::FlushCaches(response))
+            }
+            AdminCommand::Shutdown => {
+                Ok(AdminResponse::Shutdown(ShutdownResponse { open_connections }))
+            }
+            AdminCommand::Reload(request) => {
+                Ok(AdminResponse::Reload(self.reload(request, connections)?))
+            }
+            AdminCommand::ExportKeys(request) => {
+                Ok(AdminResponse::ExportKeys(self.export_keys(request)?))
+            }
+            AdminCommand::ImportKeys(request) => {
+                Ok(AdminResponse::ImportKeys(self.import_keys(request)?))
+            }
+            AdminCommand::ProvisionSeed(request) => {
+                Ok(AdminResponse::ProvisionSeed(self.provision_seed(request)?))
+            }
+            AdminCommand::BeginKeyInjection(request) => {
+                let response = match request.ciphersuite {
+                    CiphersuiteId::Bn254Sha256 => {
+                        self.bn254.begin_key_injection(request, &self.attestations)
+                    }
+                    CiphersuiteId::P256Sha256 => {
+                        self.p256.begin_key_injection(request, &self.attestations)
+                    }
+                }?;
+                Ok(AdminResponse::BeginKeyInjection(response))
+            }
+            AdminCommand::InjectKey(request) => {
+                let response = match request.ciphersuite {
+                    CiphersuiteId::Bn254Sha256 => {
+                        self.bn254.inject_key(request, &self.attestations)
+                    }
+                    CiphersuiteId::P256Sha256 => self.p256.inject_key(request, &self.attestations),
+                }?;
+                Ok(AdminResponse::InjectKey(response))
+            }
+            AdminCommand::BeginReplication => {
+                Ok(AdminResponse::BeginReplication(self.begin_replication()?))
+            }
+            AdminCommand::ExportReplica(request) => {
+                Ok(AdminResponse::ExportReplica(self.export_replica(request)?))
+            }
+            AdminCommand::ImportReplica(request) => {
+                Ok(AdminResponse::ImportReplica(self.import_replica(request)?))
+            }
+        }
+    }
+
+    /// Apply the settings of `request`, checked as on the command line,
+    /// and report those in force. Nothing changes if any is invalid.
+    fn reload(
+        &mut self,
+        request: &ReloadRequest,
+        connections: &Connections,
+    ) -> OprfResult<ReloadResponse> {
+        let mut rate_limits = self.rates.limits();
+        if let Some(setting) = request.connection_rate {
+            rate_limits.per_connection = Rate::from_setting(setting, "connection_rate")?;
+        }
+        if let Some(setting) = request.client_rate {
+            rate_limits.per_client = Rate::from_setting(setting, "client_rate")?;
+        }
+        let mut limits = connections.limits();
+        if let Some(max_open) = request.max_connections {
+            limits.max_open = max_open;
+        }
+        if let Some(secs) = request.idle_timeout_secs {
+            limits.idle_timeout = Duration::from_secs(secs);
+        }
+        if let Some(secs) = request.frame_timeout_secs {
+            limits.frame_timeout = Duration::from_secs(secs);
+        }
+        if limits.max_open == 0 {
+            return Err(bad_request("max_connections must be positive".to_string()));
+        }
+        if limits.idle_timeout.is_zero() || limits.frame_timeout.is_zero() {
+            return Err(bad_request("Connection timeouts must be positive".to_string()));
+        }
+
+        self.rates.set_limits(rate_limits);
+        connections.set_limits(limits);
+        if let Some(ttl_secs) = request.attestation_ttl_secs {
+            self.attestations.set_ttl_secs(ttl_secs);
+        }
+        if let Some(enabled) = request.log_sensitive {
+            if enabled && !log_sensitive() {
+                eprintln!("[Enclave] WARNING: logging sensitive values in full (reloaded)");
+            }
+            set_log_sensitive(enabled);
+        }
+        let response = ReloadResponse {
+            connection_rate: Rate::setting(rate_limits.per_connection),
+            client_rate: Rate::setting(rate_limits.per_client),
+            max_connections: limits.max_open,
+            idle_timeout_secs: limits.idle_timeout.as_secs(),
+            frame_timeout_secs: limits.frame_timeout.as_secs(),
+            attestation_ttl_secs: self.attestations.ttl_secs(),
+            log_sensitive: log_sensitive(),
+        };
+        println!("[Enclave] Reloaded settings: {:?}", response);
+        Ok(response)
+    }
+
+    /// Attest the head of the audit log if a checkpoint is due, by the
+    /// default BN254 key
+    fn checkpoint_audit_log(&mut self) -> Result<(), String> {
+        let Some((sequence, head)) = self.audit.checkpoint_due() else {
+            return Ok(());
+        };
+        let key = &self.bn254.get_or_create(DEFAULT_KEY_ID)?.current;
+        let binding = checkpoint_binding(sequence, &head);
+        let attestation = self.attestations.generate(&key.public_key_bytes, &binding)?;
+        self.audit.set_checkpoint(AuditCheckpoint {
+            sequence,
+            head: head.to_vec(),
+            attestation,
+        });
+        Ok(())
+    }
+
+    /// Serialize every key and the seed, as backups and replicas carry them
+    fn key_material(&self) -> Result<(usize, Zeroizing<Vec<u8>>), String> {
+        let mut keys = self.bn254.export()?;
+        keys.extend(self.p256.export()?);
+        let contents = BackupContents {
+            seed: self.bn254.seed.as_ref().map(|seed| seed.to_vec()),
+            keys,
+        };
+        let plaintext = serde_json::to_vec(&contents)
+            .map(Zeroizing::new)
+            .map_err(|e| format!("Failed to serialize keys: {}", e))?;
+        Ok((contents.keys.len(), plaintext))
+    }
+
+    /// Replace every key with those of [`Enclave::key_material`], returning
+    /// how many there were. With `expected`, the keys are only replaced if
+    /// their public keys are those.
+    fn restore_key_material(
+        &mut self,
+        plaintext: &[u8],
+        expected: Option<&[PublicKeyInfo]>,
+    ) -> Result<usize, String> {
+        let contents: BackupContents = serde_json::from_slice(plaintext)
+            .map_err(|e| format!("Failed to parse backup: {}", e))?;
+
+        // Build both key rings before touching the live keys
+        let seed = contents.seed.clone().map(Zeroizing::new);
+        let bn254 = KeyRing::restore(&contents.keys, seed.clone(), self.rng.clone())?;
+        let p256 = KeyRing::restore(&contents.keys, seed, self.rng.clone())?;
+        if let Some(expected) = expected {
+            let mut keys = bn254.public_keys();
+            keys.extend(p256.public_keys());
+            if keys != expected {
+                return Err("Keys differ from those the primary attested".to_string());
+            }
+        }
+        self.bn254 = bn254;
+        self.p256 = p256;
+        Ok(contents.keys.len())
+    }
+
+    /// The KMS key to use: the pinned one, which `requested` must name if
+    /// not empty. A mock KMS takes the requested key without one.
+    fn kms_key_id<'a>(&'a self, requested: &'a str) -> Result<&'a str, String> {
+        match &self.kms_key_id {
+            Some(pinned) if requested.is_empty() || requested == pinned => Ok(pinned),
+            Some(pinned) => Err(format!(
+                "KMS key {} is not the enclave's KMS key {}",
+                requested, pinned
+            )),
+            None if kms::IS_MOCK => Ok(requested),
+            None => Err("No KMS key is set; start the enclave with --kms-key-id".to_string()),
+        }
+    }
+
+    /// Seal every key under a fresh data key of the enclave's KMS key
+    fn export_keys(&self, request: &ExportKeysRequest) -> Result<ExportKeysResponse, String> {
+        let kms_key_id = self.kms_key_id(&request.kms_key_id)?;
+        let (count, plaintext) = self.key_material()?;
+
+        let data_key = kms::generate_data_key(&request.kms, kms_key_id)?;
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng.clone().fill_bytes(&mut nonce);
+        let ciphertext = seal(&data_key.plaintext, &nonce, BACKUP_AAD, &plaintext);
+
+        println!("[Enclave] Exported {} keys under KMS key {}", count, kms_key_id);
+
+        Ok(ExportKeysResponse {
+            backup: KeyBackup {
+                encrypted_data_key: data_key.ciphertext.clone(),
+                nonce: nonce.to_vec(),
+                ciphertext,
+                is_mock: kms::IS_MOCK,
+            },
+        })
+    }
+
+    /// Replace every key with the contents of a backup
+    fn import_keys(&mut self, request: &ImportKeysRequest) -> Result<ImportKeysResponse, String> {
+        let backup = &request.backup;
+        if backup.is_mock != kms::IS_MOCK {
+            return Err("Backup was made with a different KMS mode".to_string());
+        }
+        let nonce: [u8; NONCE_LEN] = backup
+            .nonce
+            .as_slice()
+            .try_into()
+            .map_err(|_| format!("Backup nonce must be {} bytes", NONCE_LEN))?;
+
+        let kms_key_id = self.kms_key_id("")?;
+        let data_key = kms::decrypt_data_key(&request.kms, kms_key_id, &backup.encrypted_data_key)?;
+        let plaintext = open(&data_key, &nonce, BACKUP_AAD, &backup.ciphertext)
+            .map(Zeroizing::new)
+            .map_err(|e| e.to_string())?;
+        let count = self.restore_key_material(&plaintext, None)?;
+        println!("[Enclave] Imported {} keys from backup", count);
+
+        let (keys, attestation) = self.attested_public_keys()?;
+        Ok(ImportKeysResponse { keys, attestation })
+    }
+
+    /// The attestation of the self-test report, whose PCRs a replication
+    /// peer must match
+    fn own_attestation(&self) -> Result<&AttestationDocument, String> {
+        match &self.self_test {
+            Some(response) => Ok(&response.attestation),
+            None => Err("The self-test has not run".to_string()),
+        }
+    }
+
+    /// Create a transport key for receiving the keys of a primary, replacing
+    /// any replication still pending
+    fn begin_replication(&mut self) -> Result<BeginReplicationResponse, String> {
+        let (replica, user_data) = PendingReplica::generate(&mut self.rng)?;
+        let key = &self.bn254.get_or_create(DEFAULT_KEY_ID)?.current;
+        let attestation = self.attestations.generate(&key.public_key_bytes, &user_data)?;
+        println!("[Enclave] Created transport key for replicating a primary's keys");
+
+        let response = replica.response(attestation);
+        self.replica = Some(replica);
+        Ok(response)
+    }
+
+    /// Seal every key to the transport key of a standby of the same image
+    fn export_replica(
+        &mut self,
+        request: &ExportReplicaRequest,
+    ) -> Result<ExportReplicaResponse, String> {
+        let (count, plaintext) = self.key_material()?;
+        let mut keys = self.bn254.public_keys();
+        keys.extend(self.p256.public_keys());
+        let mut rng = self.rng.clone();
+        let (sealed, user_data) =
+            replication::seal(request, self.own_attestation()?, &keys, &plaintext, &mut rng)?;
+        let key = &self.bn254.get_mut(DEFAULT_KEY_ID)?.current;
+        let attestation = self.attestations.generate(&key.public_key_bytes, &user_data)?;
+        println!("[Enclave] Sealed {} keys to an attested standby", count);
+
+        Ok(ExportReplicaResponse {
+            keys,
+            sender_public_key: sealed.sender_public_key,
+            nonce: sealed.nonce.to_vec(),
+            ciphertext: sealed.ciphertext,
+            attestation,
+        })
+    }
+
+    /// Replace every key with those a primary of the same image sealed to the
+    /// pending transport key. The transport key is discarded whether or not
+    /// this succeeds.
+    fn import_replica(
+        &mut self,
+        request: &ImportReplicaRequest,
+    ) -> Result<ImportReplicaResponse, String> {
+        let replica = self.replica.take().ok_or("No replication in progress")?;
+        let plaintext = replica.open(request, self.own_attestation()?)?;
+        let count = self.restore_key_material(&plaintext, Some(&request.replica.keys))?;
+        println!("[Enclave] Replicated {} keys from an attested primary", count);
+
+        let (keys, attestation) = self.attested_public_keys()?;
+        Ok(ImportReplicaResponse { keys, attestation })
+    }
+
+    /// Replace every key with keys derived from a KMS-encrypted seed, unless
+    /// any key has evaluated a point: its outputs would all change
+    fn provision_seed(
+        &mut self,
+        request: &ProvisionSeedRequest,
+    ) -> OprfResult<ProvisionSeedResponse> {
+        if self.bn254.used() || self.p256.used() {
+            return Err(bad_request(
+                "Keys have already evaluated points and cannot be replaced by a seed".to_string(),
+            ));
+        }
+        let seed = kms::decrypt(&request.kms, self.kms_key_id("")?, &request.encrypted_seed)?;
+        if seed.len() < MIN_SEED_LEN {
+            return Err(bad_request(format!("Seed must be at least {} bytes", MIN_SEED_LEN)));
+        }
+
+        self.bn254 = KeyRing::new(Some(seed.clone()), self.rng.clone());
+        self.p256 = KeyRing::new(Some(seed), self.rng.clone());
+        println!("[Enclave] Provisioned key derivation seed");
+
+        let (keys, attestation) = self.attested_public_keys()?;
+        Ok(ProvisionSeedResponse { keys, attestation })
+    }
+
+    /// Current public key of every named key, attested by the default BN254 key
+    fn attested_public_keys(
+        &mut self,
+    ) -> Result<(Vec<PublicKeyInfo>, AttestationDocument), String> {
+        let mut keys = self.bn254.public_keys();
+        keys.extend(self.p256.public_keys());
+        let user_data =
+            serde_json::to_vec(&keys).map_err(|e| format!("Failed to serialize keys: {}", e))?;
+        let key = &self.bn254.get_mut(DEFAULT_KEY_ID)?.current;
+        let attestation = self.attestations.generate(&key.public_key_bytes, &user_data)?;
+
+        Ok((keys, attestation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attestation::MockAttestation;
+    use crate::in_process::tests::handle;
+    use oprf_common::claims::Claims;
+    use oprf_common::ed25519::VerifyingKey;
+    use oprf_common::{
+        attested_user_data, key_attestation_binding, GetAttestationRequest, GetPublicKeyRequest,
+        HelloRequest, PointEncoding, MAX_CLIENT_NONCE_LEN,
+    };
+    use rand::rngs::OsRng;
+
+    /// An enclave, and the key verifying its mock attestations
+    fn enclave() -> (Enclave, VerifyingKey) {
+        let key = SigningKey::generate(&mut OsRng);
+        let verifying_key = VerifyingKey::from_bytes(&key.public_key()).unwrap();
+        let enclave = Enclave::new(
+            UsageLimits::default(),
+            RateLimits::default(),
+            EvaluationOptions::default(),
+            0,
+            60,
+            Box::new(MockAttestation::new(key)),
+        );
+        (enclave, verifying_key)
+    }
+
+    /// User data `attestation` carries, once verified with `key`
+    fn user_data(attestation: &AttestationDocument, key: &VerifyingKey) -> Vec<u8> {
+        Claims::verify(attestation, &[], Some(key)).unwrap().user_data.unwrap()
+    }
+
+    #[test]
+    fn test_public_key_attested() {
+        let (mut enclave, key) = enclave();
+        for ciphersuite in [CiphersuiteId::Bn254Sha256, CiphersuiteId::P256Sha256] {
+            let request = Message::GetPublicKey(GetPublicKeyRequest {
+                ciphersuite,
+                key_id: "billing".to_string(),
+            });
+            let Ok(EnclaveResponse::PublicKey(response)) = handle(&mut enclave, &request) else {
+                panic!("no public key");
+            };
+            assert_eq!(response.epoch, 0);
+            assert_eq!(
+                user_data(&response.attestation, &key),
+                attested_user_data("billing", &response.public_key)
+            );
+        }
+    }
+
+    #[test]
+    fn test_attestation_binds_nonce() {
+        let (mut enclave, key) = enclave();
+        let request = |nonce: Vec<u8>| {
+            Message::GetAttestation(GetAttestationRequest {
+                ciphersuite: CiphersuiteId::P256Sha256,
+                key_id: DEFAULT_KEY_ID.to_string(),
+                nonce,
+            })
+        };
+        let response = handle(&mut enclave, &request(vec![7; 16]));
+        let Ok(EnclaveResponse::Attestation(response)) = response else {
+            panic!("no attestation");
+        };
+        assert_eq!(response.key_id, DEFAULT_KEY_ID);
+        assert_eq!(
+            user_data(&response.attestation, &key),
+            key_attestation_binding(DEFAULT_KEY_ID, &response.public_key, &[7; 16])
+        );
+
+        let error = handle(&mut enclave, &request(vec![7; MAX_CLIENT_NONCE_LEN + 1])).unwrap_err();
+        assert_eq!(error.code, ErrorCode::BadRequest);
+    }
+
+    #[test]
+    fn test_health() {
+        let (mut enclave, _) = enclave();
+        let Ok(EnclaveResponse::Health(health)) = handle(&mut enclave, &Message::Health) else {
+            panic!("no health");
+        };
+        assert!(health.is_ready());
+        assert_eq!(health.key_count, 2);
+        let suites: Vec<_> = health.epochs.iter().map(|epoch| epoch.ciphersuite).collect();
+        assert_eq!(suites, [CiphersuiteId::Bn254Sha256, CiphersuiteId::P256Sha256]);
+
+        // Keys are counted as they are made
+        let request = Message::GetPublicKey(GetPublicKeyRequest {
+            ciphersuite: CiphersuiteId::P256Sha256,
+            key_id: "billing".to_string(),
+        });
+        handle(&mut enclave, &request).unwrap();
+        assert_eq!(enclave.health().key_count, 3);
+    }
+
+    #[test]
+    fn test_hello_only_first() {
+        let (mut enclave, _) = enclave();
+        let hello = Message::Hello(HelloRequest {
+            versions: vec![2],
+            point_encoding: PointEncoding::default(),
+            request_ids: false,
+        });
+        let error = handle(&mut enclave, &hello).unwrap_err();
+        assert_eq!(error.code, ErrorCode::BadRequest);
+    }
+}
@@ -0,0 +1,113 @@
+//! The heartbeat port: beats published to watchdogs, see
+//! [`oprf_common::heartbeat`].
+//!
+//! Subscribers are accepted on a thread of their own and every beat is
+//! written to all of them by another, both outside the worker pool, so a
+//! watchdog holds no worker and a saturated data path delays beats only by
+//! the request in progress. A subscriber whose write stalls for an interval
+//! is dropped.
+
+use crate::connection::lock;
+use crate::enclave::Enclave;
+use crate::listener::Listener;
+use crate::reaper::Connections;
+use crate::shutdown::Shutdown;
+use oprf_common::frame::{write_frame, WireFormat};
+use oprf_common::heartbeat::Heartbeat;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Subscribers served at once; more are refused
+const MAX_SUBSCRIBERS: usize = 16;
+
+/// Listener of the heartbeat port and the interval of its beats
+pub struct HeartbeatPort {
+    listener: Box<dyn Listener>,
+    interval: Duration,
+}
+
+impl HeartbeatPort {
+    /// Publish a beat every `interval` to the connections of `listener`
+    pub fn new(listener: Box<dyn Listener>, interval: Duration) -> Self {
+        Self { listener, interval }
+    }
+}
+
+/// Accept subscribers of `port` and publish beats of the shared enclave
+/// state to them until `shutdown` is requested, then a last one marked
+/// stopping. The handle is that of the publishing thread.
+pub(crate) fn start(
+    port: HeartbeatPort,
+    state: Arc<Mutex<Enclave>>,
+    connections: Arc<Connections>,
+    shutdown: Shutdown,
+) -> std::io::Result<JoinHandle<()>> {
+    let HeartbeatPort { listener, interval } = port;
+    let subscribers = Arc::new(Mutex::new(Vec::new()));
+    let accepted = Arc::clone(&subscribers);
+    thread::Builder::new().name("oprf-heartbeat-accept".to_string()).spawn(move || loop {
+        match listener.accept() {
+            Ok(stream) => subscribe(&accepted, stream, interval),
+            Err(e) => eprintln!("[Enclave] Heartbeat accept error: {}", e),
+        }
+    })?;
+    thread::Builder::new().name("oprf-heartbeat".to_string()).spawn(move || {
+        for sequence in 0.. {
+            let stopping = shutdown.wait_timeout(interval);
+            let health = lock(&state).health();
+            let beat = Heartbeat {
+                sequence,
+                timestamp: health.timestamp,
+                interval_secs: interval.as_secs(),
+                uptime_secs: health.uptime_secs,
+                ready: health.is_ready(),
+                open_connections: connections.count(),
+                stopping,
+            };
+            publish(&subscribers, &beat);
+            if stopping {
+                break;
+            }
+        }
+    })
+}
+
+fn lock_subscribers(subscribers: &Mutex<Vec<TcpStream>>) -> MutexGuard<'_, Vec<TcpStream>> {
+    subscribers.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn subscribe(subscribers: &Mutex<Vec<TcpStream>>, stream: TcpStream, interval: Duration) {
+    let mut subscribers = lock_subscribers(subscribers);
+    if subscribers.len() >= MAX_SUBSCRIBERS {
+        eprintln!("[Enclave] Refusing heartbeat subscriber: {} already", MAX_SUBSCRIBERS);
+        return;
+    }
+    if let Err(e) = stream.set_write_timeout(Some(interval)) {
+        eprintln!("[Enclave] Failed to set heartbeat timeouts: {}", e);
+        return;
+    }
+    println!("[Enclave] Heartbeat subscriber connected");
+    subscribers.push(stream);
+}
+
+/// Write `beat` to every subscriber, dropping those that fail
+fn publish(subscribers: &Mutex<Vec<TcpStream>>, beat: &Heartbeat) {
+    let payload = match WireFormat::Cbor.encode(beat) {
+        Ok(payload) => payload,
+        Err(e) => {
+            eprintln!("[Enclave] Failed to encode heartbeat: {}", e);
+            return;
+        }
+    };
+    lock_subscribers(subscribers).retain_mut(|stream| {
+        match write_frame(stream, beat.sequence, WireFormat::Cbor, &payload) {
+            Ok(()) => true,
+            Err(e) => {
+                println!("[Enclave] Heartbeat subscriber dropped: {}", e);
+                false
+            }
+        }
+    });
+}
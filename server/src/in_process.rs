@@ -0,0 +1,126 @@
+//! An enclave served to clients in the same process.
+//!
+//! [`InProcess`] runs the request loop every socket connection gets, from
+//! the Hello to the last request, over the in-memory connections of
+//! [`oprf_common::duplex`]. Tests can then drive the whole protocol, the
+//! secure channel and response signing included, against a real [`Enclave`]
+//! without binding a port or starting the enclave binary.
+
+use crate::connection::handle_connection;
+use crate::enclave::Enclave;
+use oprf_common::duplex::{self, DuplexStream};
+use std::sync::{Arc, Mutex};
+
+/// An [`Enclave`] answering in-memory connections, each on a thread of its
+/// own
+pub struct InProcess {
+    state: Arc<Mutex<Enclave>>,
+}
+
+impl InProcess {
+    /// Serve `enclave` once it passes its self-test, as [`crate::serve`] does
+    pub fn start(mut enclave: Enclave) -> Result<Self, String> {
+        enclave.self_test()?;
+        Ok(Self {
+            state: Arc::new(Mutex::new(enclave)),
+        })
+    }
+
+    /// Open a connection to the enclave and return the client's end. The
+    /// enclave serves it until that end is dropped.
+    pub fn connect(&self) -> std::io::Result<DuplexStream> {
+        let (client, mut server) = duplex::pair();
+        let state = Arc::clone(&self.state);
+        std::thread::Builder::new()
+            .name("oprf-in-process".to_string())
+            .spawn(move || handle_connection(&mut server, &state))?;
+        Ok(client)
+    }
+}
+
+/// Requests of the tests of the enclave, over in-process connections
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::attestation::MockAttestation;
+    use crate::connection::Negotiated;
+    use crate::metrics::Timings;
+    use oprf_common::client::blind;
+    use oprf_common::ed25519::SigningKey;
+    use oprf_common::frame::{read_frame, write_frame, WireFormat};
+    use oprf_common::{
+        sha256_hex, CiphersuiteId, EnclaveResponse, HelloRequest, Message, OprfMode, OprfRequest,
+        OprfResult, P256Sha256, PointEncoding,
+    };
+    use rand::rngs::OsRng;
+    use serde::de::DeserializeOwned;
+
+    /// Mock attestations with a fresh key
+    pub(crate) fn attestation() -> Box<MockAttestation> {
+        Box::new(MockAttestation::new(SigningKey::generate(&mut OsRng)))
+    }
+
+    /// A mock-attested enclave, as `new` builds it from `attestation`
+    pub(crate) fn start(new: impl FnOnce(Box<MockAttestation>) -> Enclave) -> InProcess {
+        InProcess::start(new(attestation())).expect("the self-test passes")
+    }
+
+    /// Answer of the enclave to `request` on `stream`, bare for a Hello
+    fn exchange<T: DeserializeOwned>(stream: &mut DuplexStream, request: &Message) -> T {
+        let payload = WireFormat::Json.encode(request).unwrap();
+        write_frame(stream, 1, WireFormat::Json, &payload).unwrap();
+        let frame = read_frame(stream).unwrap().unwrap();
+        frame.format.decode(&frame.payload).unwrap()
+    }
+
+    /// Answer of `enclave` to `request` on a version 2 connection
+    pub(crate) fn handle(enclave: &mut Enclave, request: &Message) -> OprfResult<EnclaveResponse> {
+        let negotiated = Negotiated {
+            protocol_version: 2,
+            point_encoding: PointEncoding::default(),
+        };
+        enclave.handle(request, negotiated, &mut Timings::default())
+    }
+
+    /// A version 2 connection, which answers errors without a channel
+    pub(crate) fn connect(enclave: &InProcess) -> DuplexStream {
+        let mut stream = enclave.connect().unwrap();
+        let hello = Message::Hello(HelloRequest {
+            versions: vec![2],
+            point_encoding: PointEncoding::default(),
+            request_ids: false,
+        });
+        exchange::<EnclaveResponse>(&mut stream, &hello);
+        stream
+    }
+
+    /// Answer of the enclave to `request` on a [`connect`]ed `stream`
+    pub(crate) fn request(
+        stream: &mut DuplexStream,
+        request: &Message,
+    ) -> OprfResult<EnclaveResponse> {
+        exchange(stream, request)
+    }
+
+    /// Evaluation of `blinded_query` by the default P-256 key
+    pub(crate) fn evaluation(blinded_query: Vec<u8>) -> OprfRequest {
+        OprfRequest {
+            query_hash: sha256_hex(&blinded_query),
+            blinded_query,
+            mode: OprfMode::Oprf,
+            ciphersuite: CiphersuiteId::P256Sha256,
+            epoch: None,
+            key_id: "default".to_string(),
+            force_fresh: false,
+            client_nonce: None,
+            client_id: None,
+            request_id: None,
+        }
+    }
+
+    /// A freshly blinded P-256 point
+    pub(crate) fn blinded_query() -> Vec<u8> {
+        let (_, blinded) = blind::<P256Sha256, _>(b"alice@example.com", OprfMode::Oprf, &mut OsRng);
+        blinded.to_bytes().unwrap()
+    }
+}
@@ -0,0 +1,1215 @@
+//! Secret keys and their evaluation.
+//!
+//! Every ciphersuite has a [`KeyRing`] of independent named keys. A named key
+//! is an [`EnclaveState`] holding its current [`EpochKey`] and, after a
+//! rotation, the previous one for a grace period.
+
+use crate::attestation::Attester;
+use crate::cache::EvaluationCache;
+use crate::connection::Negotiated;
+use crate::memory::LockedBox;
+use crate::metrics::Timings;
+use crate::parallel::Pool;
+use crate::{bad_request, chrono_lite_timestamp};
+use oprf_common::admin::{EpochInfo, KeyEpochs};
+use oprf_common::ct;
+use oprf_common::envelope::NONCE_LEN;
+use oprf_common::kdf::derive_secret_key;
+use oprf_common::redact::sensitive;
+use oprf_common::transport::open_with;
+use oprf_common::{
+    attested_user_data, deserialize_fr, generate_proof, key_attestation_binding,
+    key_injection_binding, public_key_g2, request_tag, serialize_g2, sha256_hex,
+    validate_key_id, validate_request_id,
+    AttestationResponse, BatchOprfRequest, BatchOprfResponse, BeginKeyInjectionRequest,
+    BeginKeyInjectionResponse, Ciphersuite, CiphersuiteId, EnclaveResponse, ErrorCode,
+    ErrorResponse, EvaluationUserData, GetAttestationRequest, GetPublicKeyRequest,
+    InjectKeyRequest, InjectKeyResponse, Message, OprfMode, OprfRequest, OprfResponse,
+    OprfResult, PointEncoding, PublicKeyInfo, PublicKeyResponse, RotateKeyRequest,
+    RotateKeyResponse, Stage, SuiteEpoch, DEFAULT_KEY_ID, MAX_BATCH_SIZE, MAX_CLIENT_NONCE_LEN,
+};
+use oprf_common::rng::SharedRng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Serialized evaluated points and the optional proof covering them
+type Evaluation = (Vec<Vec<u8>>, Option<Vec<u8>>);
+
+/// Evaluated points, not yet serialized, and the optional proof
+type EvaluatedPoints<C> = (Vec<<C as Ciphersuite>::Element>, Option<Vec<u8>>);
+
+/// Blinded queries of a request with their decoded points
+struct BlindedQueries<'a, C: Ciphersuite> {
+    /// Encodings as received, which the cache is keyed by
+    queries: &'a [Vec<u8>],
+    points: Vec<C::Element>,
+    /// Encoding of the received and the evaluated points
+    encoding: PointEncoding,
+}
+
+impl<'a, C: Ciphersuite> BlindedQueries<'a, C> {
+    /// Decode every query in `encoding`, refusing the request with `BadPoint`
+    /// if any is not a point of the group
+    fn decode(
+        queries: &'a [Vec<u8>],
+        encoding: PointEncoding,
+        timings: &mut Timings,
+    ) -> OprfResult<Self> {
+        let points = timings
+            .time(Stage::Deserialize, || {
+                queries
+                    .iter()
+                    .map(|query| C::decode_element(query, encoding))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|e| {
+                ErrorResponse::new(ErrorCode::BadPoint, format!("Invalid blinded query: {}", e))
+            })?;
+        Ok(Self {
+            queries,
+            points,
+            encoding,
+        })
+    }
+}
+
+/// Grace period for the previous key after a rotation, unless the request sets one
+const DEFAULT_GRACE_PERIOD_SECS: u64 = 24 * 60 * 60;
+
+/// Upper bound on named keys per ciphersuite
+const MAX_KEYS: usize = 64;
+
+/// Key derivation seed, wiped on drop
+pub(crate) type Seed = Zeroizing<Vec<u8>>;
+
+/// Length of the evaluation budget window, unless `--window-secs` sets one
+const DEFAULT_WINDOW_SECS: u64 = 60 * 60;
+
+/// Operator-configured evaluation budget of every secret key. Set it where
+/// the parent cannot, such as the measured enclave command line.
+#[derive(Clone, Copy, Debug)]
+pub struct UsageLimits {
+    /// Evaluations allowed over the lifetime of a key
+    pub max_total: Option<u64>,
+    /// Evaluations allowed per window of `window_secs`
+    pub max_per_window: Option<u64>,
+    pub window_secs: u64,
+}
+
+impl Default for UsageLimits {
+    fn default() -> Self {
+        Self {
+            max_total: None,
+            max_per_window: None,
+            window_secs: DEFAULT_WINDOW_SECS,
+        }
+    }
+}
+
+/// How the enclave evaluates queries
+#[derive(Clone, Copy, Debug)]
+pub struct EvaluationOptions {
+    /// Threads one batch evaluation may use
+    pub threads: usize,
+    /// Evaluations each key epoch caches for repeated blinded points, none
+    /// if 0
+    pub cache_entries: usize,
+}
+
+impl Default for EvaluationOptions {
+    fn default() -> Self {
+        Self {
+            threads: 1,
+            cache_entries: 0,
+        }
+    }
+}
+
+/// [`EvaluationOptions`] and the threads they call for
+pub(crate) struct Evaluator {
+    pub(crate) options: EvaluationOptions,
+    pool: Pool,
+}
+
+impl Evaluator {
+    pub(crate) fn new(options: EvaluationOptions) -> Self {
+        Self {
+            options,
+            pool: Pool::new(options.threads),
+        }
+    }
+}
+
+/// Evaluations made with one secret key
+#[derive(Default)]
+struct Usage {
+    total: u64,
+    /// Unix time the current window started at
+    window_start: u64,
+    window_count: u64,
+    /// Evaluation requests for the key that were refused or failed
+    errors: u64,
+    /// Unix time of the last evaluation request for the key, refused ones
+    /// included
+    last_used: Option<u64>,
+}
+
+impl Usage {
+    /// Count `count` evaluations at unix time `now`, refusing all of them if
+    /// they would exceed `limits`
+    fn record(&mut self, count: u64, limits: &UsageLimits, now: u64) -> OprfResult<()> {
+        if now >= self.window_start.saturating_add(limits.window_secs) {
+            self.window_start = now;
+            self.window_count = 0;
+        }
+        if let Some(max) = limits.max_total {
+            if self.total + count > max {
+                return Err(ErrorResponse::new(
+                    ErrorCode::RateLimited,
+                    format!("Key has used its budget of {} evaluations", max),
+                ));
+            }
+        }
+        if let Some(max) = limits.max_per_window {
+            if self.window_count + count > max {
+                return Err(ErrorResponse::new(
+                    ErrorCode::RateLimited,
+                    format!(
+                        "Key has used its budget of {} evaluations per {}s",
+                        max, limits.window_secs
+                    ),
+                ));
+            }
+        }
+        self.total += count;
+        self.window_count += count;
+        self.last_used = Some(now);
+        Ok(())
+    }
+
+    /// Count an evaluation request for the key that failed at unix time `now`
+    fn record_error(&mut self, now: u64) {
+        self.errors += 1;
+        self.last_used = Some(now);
+    }
+}
+
+/// One generation of the secret key and its public keys
+pub(crate) struct EpochKey<C: Ciphersuite> {
+    /// Epoch number, incremented on every rotation
+    epoch: u64,
+    /// Secret key k, on locked memory
+    secret_key: LockedBox<C::Scalar>,
+    /// Public key g^k
+    public_key: C::Element,
+    /// Public key g^k (serialized)
+    pub(crate) public_key_bytes: Vec<u8>,
+    /// Public key g2^k (serialized), published for pairing-based verification
+    public_key_g2_bytes: Option<Vec<u8>>,
+    /// Evaluations made with this key
+    usage: Usage,
+    /// Recent evaluations with this key
+    cache: EvaluationCache<C::Element>,
+    /// Source of the nonces of its proofs
+    rng: SharedRng,
+}
+
+impl<C: Ciphersuite> EpochKey<C> {
+    /// Create the key of `key_id` at `epoch`, derived from the provisioned
+    /// seed if there is one and drawn from `rng` otherwise
+    fn new(key_id: &str, epoch: u64, seed: Option<&[u8]>, rng: &SharedRng) -> Self {
+        let secret_key = match seed {
+            Some(seed) => {
+                println!(
+                    "[Enclave] Derived {} secret key {:?} from seed (epoch {})",
+                    C::IDENTIFIER,
+                    key_id,
+                    epoch
+                );
+                derive_secret_key::<C>(seed, key_id, epoch)
+            }
+            None => {
+                println!(
+                    "[Enclave] Generated {} secret key and public key (epoch {})",
+                    C::IDENTIFIER,
+                    epoch
+                );
+                C::random_scalar(&mut rng.clone())
+            }
+        };
+        Self::from_secret_key(epoch, secret_key, rng)
+    }
+
+    /// Derive the public keys for an existing secret key, proving its
+    /// evaluations with nonces from `rng`
+    fn from_secret_key(epoch: u64, secret_key: C::Scalar, rng: &SharedRng) -> Self {
+        let public_key = C::scalar_mul_generator(&secret_key);
+        let public_key_bytes =
+            C::serialize_element(&public_key).expect("Failed to serialize public key");
+
+        println!("[Enclave] Public key: {}", sensitive(&public_key_bytes));
+
+        // BN254 is pairing-friendly, so also publish g2^k for clients that
+        // verify evaluations with a pairing instead of a DLEQ proof
+        let public_key_g2_bytes = if C::ID == CiphersuiteId::Bn254Sha256 {
+            let secret_key = C::serialize_scalar(&secret_key)
+                .and_then(|bytes| deserialize_fr(&Zeroizing::new(bytes)))
+                .map(Zeroizing::new)
+                .expect("Failed to convert BN254 secret key");
+            let bytes =
+                serialize_g2(&public_key_g2(&secret_key)).expect("Failed to serialize G2 key");
+            println!("[Enclave] G2 public key: {}", sensitive(&bytes));
+            Some(bytes)
+        } else {
+            None
+        };
+
+        Self {
+            epoch,
+            secret_key: LockedBox::new(secret_key),
+            public_key,
+            public_key_bytes,
+            public_key_g2_bytes,
+            usage: Usage::default(),
+            cache: EvaluationCache::default(),
+            rng: rng.clone(),
+        }
+    }
+
+    /// What the admin port lists of the key, which stops being accepted at
+    /// `expires_at` if it is a previous key
+    fn info(&self, expires_at: Option<u64>) -> EpochInfo {
+        EpochInfo {
+            epoch: self.epoch,
+            public_key: self.public_key_bytes.clone(),
+            evaluations: self.usage.total,
+            errors: self.usage.errors,
+            last_used: self.usage.last_used,
+            expires_at,
+        }
+    }
+
+    /// Charge the decoded queries to the key within `limits`, then compute
+    /// blinded_query^k for every query not in the cache, on the threads of
+    /// `evaluator`. In verifiable mode one DLEQ proof covers all of them. Log
+    /// lines start with `tag`.
+    fn evaluate_queries(
+        &mut self,
+        blinded: &BlindedQueries<C>,
+        limits: &UsageLimits,
+        mode: OprfMode,
+        evaluator: &Evaluator,
+        timings: &mut Timings,
+        tag: &str,
+    ) -> OprfResult<Evaluation> {
+        let (queries, encoding) = (blinded.queries, blinded.encoding);
+        self.usage.record(queries.len() as u64, limits, chrono_lite_timestamp())?;
+
+        println!("[Enclave] {}Received {} blinded queries", tag, queries.len());
+        timings.evaluated(queries.len());
+        let (evaluated, proof) = timings.time(Stage::Evaluate, || {
+            self.evaluate_points(queries, &blinded.points, mode, evaluator, tag)
+        })?;
+        let evaluated_bytes = timings
+            .time(Stage::Serialize, || C::encode_elements(&evaluated, encoding))
+            .map_err(|e| format!("Failed to serialize result: {}", e))?;
+
+        Ok((evaluated_bytes, proof))
+    }
+
+    /// Multiply the blinded points of `queries` by the key, or find them in
+    /// the cache, and prove the evaluation in verifiable mode
+    fn evaluate_points(
+        &mut self,
+        queries: &[Vec<u8>],
+        blinded_queries: &[C::Element],
+        mode: OprfMode,
+        evaluator: &Evaluator,
+        tag: &str,
+    ) -> OprfResult<EvaluatedPoints<C>> {
+        // Compute output = blinded_query^k for the queries not cached
+        let cached: Vec<_> = queries.iter().map(|query| self.cache.get(query)).collect();
+        let missed: Vec<_> = blinded_queries
+            .iter()
+            .zip(&cached)
+            .filter(|(_, cached)| cached.is_none())
+            .map(|(query, _)| *query)
+            .collect();
+        let secret_key: &C::Scalar = &self.secret_key;
+        let mut computed = evaluator.pool.map_chunks(&missed, |chunk| {
+            C::scalar_mul_many(chunk, secret_key)
+        })
+        .into_iter();
+        let mut evaluated = Vec::with_capacity(queries.len());
+        for (query, cached) in queries.iter().zip(cached) {
+            let point = match cached {
+                Some(point) => point,
+                None => {
+                    let point = computed.next().expect("one evaluation per missed query");
+                    self.cache.insert(query, point, evaluator.options.cache_entries);
+                    point
+                }
+            };
+            evaluated.push(point);
+        }
+        if missed.len() < queries.len() {
+            let cached = queries.len() - missed.len();
+            println!("[Enclave] {}Answered {} queries from the cache", tag, cached);
+        }
+
+        println!("[Enclave] {}Computed OPRF evaluation", tag);
+
+        // In verifiable mode, prove evaluated = blinded_query^k for the published g^k
+        let proof = match mode {
+            OprfMode::Oprf => None,
+            OprfMode::Voprf => {
+                let proof = generate_proof::<C, _>(
+                    &self.secret_key,
+                    &self.public_key,
+                    blinded_queries,
+                    &evaluated,
+                    &mut self.rng,
+                )
+                .map_err(|e| format!("Failed to generate DLEQ proof: {}", e))?;
+                println!("[Enclave] {}Generated DLEQ proof", tag);
+                Some(proof.to_bytes().map_err(|e| format!("Failed to serialize proof: {}", e))?)
+            }
+        };
+
+        Ok((evaluated, proof))
+    }
+}
+
+/// Reject client nonces longer than [`MAX_CLIENT_NONCE_LEN`]
+pub(crate) fn check_client_nonce(nonce: Option<&[u8]>) -> OprfResult<()> {
+    match nonce {
+        Some(nonce) if nonce.len() > MAX_CLIENT_NONCE_LEN => Err(bad_request(format!(
+            "Client nonce must be at most {} bytes, got {}",
+            MAX_CLIENT_NONCE_LEN,
+            nonce.len()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Reject request ids that could not tag log lines, see [`validate_request_id`]
+fn check_request_id(request_id: Option<&str>) -> OprfResult<()> {
+    match request_id {
+        Some(request_id) => validate_request_id(request_id).map_err(|e| bad_request(e.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Enclave key state for one ciphersuite
+pub(crate) struct EnclaveState<C: Ciphersuite> {
+    /// Key used for requests that do not name an epoch
+    pub(crate) current: EpochKey<C>,
+    /// Key replaced by the last rotation and the unix time it expires at
+    previous: Option<(EpochKey<C>, u64)>,
+}
+
+impl<C: Ciphersuite> EnclaveState<C> {
+    /// First epoch of `key_id`, derived from `seed` if given, with keys and
+    /// proof nonces drawn from `rng`
+    fn new(key_id: &str, seed: Option<&[u8]>, rng: &SharedRng) -> Self {
+        Self {
+            current: EpochKey::new(key_id, 0, seed, rng),
+            previous: None,
+        }
+    }
+
+    /// Look up the key for `epoch`, defaulting to the current one
+    fn key(&mut self, epoch: Option<u64>) -> OprfResult<&mut EpochKey<C>> {
+        let epoch = match epoch {
+            None => return Ok(&mut self.current),
+            Some(epoch) if epoch == self.current.epoch => return Ok(&mut self.current),
+            Some(epoch) => epoch,
+        };
+        match &mut self.previous {
+            Some((key, expires_at)) if key.epoch == epoch => {
+                if chrono_lite_timestamp() >= *expires_at {
+                    return Err(bad_request(format!("Key epoch {} has expired", epoch)));
+                }
+                Ok(key)
+            }
+            _ => Err(bad_request(format!("Unknown key epoch {}", epoch))),
+        }
+    }
+
+    /// Count a failed evaluation request against the key of `epoch`, if
+    /// the enclave holds it
+    fn record_error(&mut self, epoch: Option<u64>) {
+        let key = match (epoch, &mut self.previous) {
+            (Some(epoch), Some((key, _))) if key.epoch == epoch => key,
+            (Some(epoch), _) if epoch != self.current.epoch => return,
+            _ => &mut self.current,
+        };
+        key.usage.record_error(chrono_lite_timestamp());
+    }
+
+    fn evaluate(
+        &mut self,
+        request: &OprfRequest,
+        limits: &UsageLimits,
+        evaluator: &Evaluator,
+        attestations: &mut Attester,
+        negotiated: Negotiated,
+        timings: &mut Timings,
+    ) -> OprfResult<OprfResponse> {
+        // Verify hash
+        let computed_hash = sha256_hex(&request.blinded_query);
+        if !ct::eq(computed_hash.as_bytes(), request.query_hash.as_bytes()) {
+            return Err(ErrorResponse::new(ErrorCode::HashMismatch, "Query hash mismatch"));
+        }
+
+        check_client_nonce(request.client_nonce.as_deref())?;
+        check_request_id(request.request_id.as_deref())?;
+
+        let key = self.key(request.epoch)?;
+        let queries = std::slice::from_ref(&request.blinded_query);
+        let blinded = BlindedQueries::decode(queries, negotiated.point_encoding, timings)?;
+        let (mut evaluated_points, proof) = key.evaluate_queries(
+            &blinded,
+            limits,
+            request.mode,
+            evaluator,
+            timings,
+            &request_tag(request.request_id.as_deref()),
+        )?;
+
+        // Generate attestation
+        let user_data = EvaluationUserData::new(
+            negotiated.protocol_version,
+            &request.key_id,
+            key.epoch,
+            &key.public_key_bytes,
+            &evaluated_points,
+            request.client_nonce.as_deref(),
+            request.request_id.as_deref(),
+        );
+        let attestation = timings.time(Stage::Attest, || {
+            attestations.cached(&key.public_key_bytes, &user_data.to_cbor(), request.force_fresh)
+        })?;
+        let evaluated_bytes = evaluated_points.remove(0);
+
+        Ok(OprfResponse {
+            evaluated_point: evaluated_bytes,
+            public_key: key.public_key_bytes.clone(),
+            attestation,
+            proof,
+            public_key_g2: key.public_key_g2_bytes.clone(),
+            epoch: key.epoch,
+            key_id: request.key_id.clone(),
+            request_id: request.request_id.clone(),
+        })
+    }
+
+    fn evaluate_batch(
+        &mut self,
+        request: &BatchOprfRequest,
+        limits: &UsageLimits,
+        evaluator: &Evaluator,
+        attestations: &mut Attester,
+        negotiated: Negotiated,
+        timings: &mut Timings,
+    ) -> OprfResult<BatchOprfResponse> {
+        if request.blinded_queries.is_empty() || request.blinded_queries.len() > MAX_BATCH_SIZE {
+            return Err(bad_request(format!(
+                "Batch size must be between 1 and {}, got {}",
+                MAX_BATCH_SIZE,
+                request.blinded_queries.len()
+            )));
+        }
+
+        // Verify hash over the concatenated queries
+        let computed_hash = sha256_hex(&request.blinded_queries.concat());
+        if !ct::eq(computed_hash.as_bytes(), request.query_hash.as_bytes()) {
+            return Err(ErrorResponse::new(ErrorCode::HashMismatch, "Query hash mismatch"));
+        }
+
+        check_client_nonce(request.client_nonce.as_deref())?;
+        check_request_id(request.request_id.as_deref())?;
+
+        let key = self.key(request.epoch)?;
+        let blinded =
+            BlindedQueries::decode(&request.blinded_queries, negotiated.point_encoding, timings)?;
+        let (evaluated_points, proof) = key.evaluate_queries(
+            &blinded,
+            limits,
+            request.mode,
+            evaluator,
+            timings,
+            &request_tag(request.request_id.as_deref()),
+        )?;
+
+        // A single attestation covers the whole batch
+        let user_data = EvaluationUserData::new(
+            negotiated.protocol_version,
+            &request.key_id,
+            key.epoch,
+            &key.public_key_bytes,
+            &evaluated_points,
+            request.client_nonce.as_deref(),
+            request.request_id.as_deref(),
+        );
+        let attestation = timings.time(Stage::Attest, || {
+            attestations.cached(&key.public_key_bytes, &user_data.to_cbor(), request.force_fresh)
+        })?;
+
+        Ok(BatchOprfResponse {
+            evaluated_points,
+            public_key: key.public_key_bytes.clone(),
+            attestation,
+            proof,
+            public_key_g2: key.public_key_g2_bytes.clone(),
+            epoch: key.epoch,
+            key_id: request.key_id.clone(),
+            request_id: request.request_id.clone(),
+        })
+    }
+
+    /// Replace the current key with a fresh one under the next epoch. The
+    /// replaced key stays usable for `grace_period_secs`; any older key is
+    /// dropped.
+    fn rotate(
+        &mut self,
+        request: &RotateKeyRequest,
+        seed: Option<&[u8]>,
+        attestations: &Attester,
+    ) -> Result<RotateKeyResponse, String> {
+        let grace_period_secs = request.grace_period_secs.unwrap_or(DEFAULT_GRACE_PERIOD_SECS);
+        let rng = self.current.rng.clone();
+        let new_key = EpochKey::new(&request.key_id, self.current.epoch + 1, seed, &rng);
+        let previous = std::mem::replace(&mut self.current, new_key);
+        let previous_expires_at = chrono_lite_timestamp() + grace_period_secs;
+
+        println!(
+            "[Enclave] Rotated {} key {:?} to epoch {}, epoch {} accepted for {}s",
+            C::IDENTIFIER,
+            request.key_id,
+            self.current.epoch,
+            previous.epoch,
+            grace_period_secs
+        );
+        self.previous = Some((previous, previous_expires_at));
+
+        let public_key = &self.current.public_key_bytes;
+        let attestation =
+            attestations.generate(public_key, &attested_user_data(&request.key_id, public_key))?;
+
+        Ok(RotateKeyResponse {
+            key_id: request.key_id.clone(),
+            epoch: self.current.epoch,
+            public_key: self.current.public_key_bytes.clone(),
+            previous_expires_at,
+            attestation,
+        })
+    }
+}
+
+/// Backed-up secret key of one epoch
+#[derive(Serialize, Deserialize)]
+struct BackupEpoch {
+    epoch: u64,
+    secret_key: Vec<u8>,
+    /// Unix time the key stops being accepted, for a previous key
+    expires_at: Option<u64>,
+    /// Evaluations made with the key, so a restore keeps counting
+    #[serde(default)]
+    evaluations: u64,
+    /// Failed evaluation requests and the last request, kept likewise
+    #[serde(default)]
+    errors: u64,
+    #[serde(default)]
+    last_used: Option<u64>,
+}
+
+impl Drop for BackupEpoch {
+    fn drop(&mut self) {
+        self.secret_key.zeroize();
+    }
+}
+
+/// Backed-up state of one named key
+#[derive(Serialize, Deserialize)]
+pub(crate) struct BackupEntry {
+    ciphersuite: CiphersuiteId,
+    key_id: String,
+    current: BackupEpoch,
+    previous: Option<BackupEpoch>,
+}
+
+impl<C: Ciphersuite> EnclaveState<C> {
+    fn to_backup(&self, key_id: &str) -> Result<BackupEntry, String> {
+        let backup_epoch = |key: &EpochKey<C>, expires_at| {
+            Ok::<_, String>(BackupEpoch {
+                epoch: key.epoch,
+                secret_key: C::serialize_scalar(&key.secret_key).map_err(|e| e.to_string())?,
+                expires_at,
+                evaluations: key.usage.total,
+                errors: key.usage.errors,
+                last_used: key.usage.last_used,
+            })
+        };
+        Ok(BackupEntry {
+            ciphersuite: C::ID,
+            key_id: key_id.to_string(),
+            current: backup_epoch(&self.current, None)?,
+            previous: match &self.previous {
+                Some((key, expires_at)) => Some(backup_epoch(key, Some(*expires_at))?),
+                None => None,
+            },
+        })
+    }
+
+    fn from_backup(entry: &BackupEntry, rng: &SharedRng) -> Result<Self, String> {
+        let restore = |backup: &BackupEpoch| {
+            let secret_key = C::deserialize_scalar(&backup.secret_key).map_err(|e| e.to_string())?;
+            let mut key = EpochKey::from_secret_key(backup.epoch, secret_key, rng);
+            key.usage.total = backup.evaluations;
+            key.usage.errors = backup.errors;
+            key.usage.last_used = backup.last_used;
+            Ok::<_, String>(key)
+        };
+        let previous = match &entry.previous {
+            Some(backup) => {
+                let expires_at = backup.expires_at.ok_or("Previous key without expiry")?;
+                Some((restore(backup)?, expires_at))
+            }
+            None => None,
+        };
+        Ok(Self {
+            current: restore(&entry.current)?,
+            previous,
+        })
+    }
+}
+
+/// Transport key of a key injection awaiting its [`InjectKeyRequest`]
+struct PendingInjection<C: Ciphersuite> {
+    key_id: String,
+    epoch: u64,
+    transport_key: LockedBox<C::Scalar>,
+}
+
+/// Independent named keys for one ciphersuite
+pub(crate) struct KeyRing<C: Ciphersuite> {
+    pub(crate) keys: HashMap<String, EnclaveState<C>>,
+    /// Seed new keys are derived from, if one was provisioned
+    pub(crate) seed: Option<Seed>,
+    /// Key injection started by the last [`BeginKeyInjectionRequest`]
+    injection: Option<PendingInjection<C>>,
+    /// Source of new keys, transport keys and proof nonces
+    rng: SharedRng,
+}
+
+impl<C: Ciphersuite> KeyRing<C> {
+    pub(crate) fn new(seed: Option<Seed>, rng: SharedRng) -> Self {
+        let mut keys = HashMap::new();
+        keys.insert(
+            DEFAULT_KEY_ID.to_string(),
+            EnclaveState::new(DEFAULT_KEY_ID, seed.as_ref().map(|seed| seed.as_slice()), &rng),
+        );
+        Self {
+            keys,
+            seed,
+            injection: None,
+            rng,
+        }
+    }
+
+    /// Look up a named key, creating it on first use
+    pub(crate) fn get_or_create(&mut self, key_id: &str) -> Result<&mut EnclaveState<C>, String> {
+        validate_key_id(key_id).map_err(|e| e.to_string())?;
+        if !self.keys.contains_key(key_id) {
+            if self.keys.len() >= MAX_KEYS {
+                return Err(format!("Limit of {} {} keys reached", MAX_KEYS, C::IDENTIFIER));
+            }
+            println!("[Enclave] Creating {} key {:?}", C::IDENTIFIER, key_id);
+            let seed = self.seed.as_ref().map(|seed| seed.as_slice());
+            let state = EnclaveState::new(key_id, seed, &self.rng);
+            self.keys.insert(key_id.to_string(), state);
+        }
+        Ok(self.keys.get_mut(key_id).expect("key was just inserted"))
+    }
+
+    /// Look up an existing named key
+    pub(crate) fn get_mut(&mut self, key_id: &str) -> Result<&mut EnclaveState<C>, String> {
+        self.keys
+            .get_mut(key_id)
+            .ok_or_else(|| format!("Unknown key id {:?}", key_id))
+    }
+
+    /// Serialize every key for a backup
+    pub(crate) fn export(&self) -> Result<Vec<BackupEntry>, String> {
+        let mut entries = self
+            .keys
+            .iter()
+            .map(|(key_id, state)| state.to_backup(key_id))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by(|a, b| a.key_id.cmp(&b.key_id));
+        Ok(entries)
+    }
+
+    /// Rebuild a key ring from the backup entries of this ciphersuite
+    pub(crate) fn restore(
+        entries: &[BackupEntry],
+        seed: Option<Seed>,
+        rng: SharedRng,
+    ) -> Result<Self, String> {
+        let mut keys = HashMap::new();
+        for entry in entries.iter().filter(|entry| entry.ciphersuite == C::ID) {
+            validate_key_id(&entry.key_id).map_err(|e| e.to_string())?;
+            println!("[Enclave] Restoring {} key {:?}", C::IDENTIFIER, entry.key_id);
+            let state = EnclaveState::from_backup(entry, &rng)?;
+            if keys.insert(entry.key_id.clone(), state).is_some() {
+                return Err(format!("Duplicate key id {:?} in backup", entry.key_id));
+            }
+        }
+        if keys.len() > MAX_KEYS {
+            return Err(format!("Backup holds more than {} {} keys", MAX_KEYS, C::IDENTIFIER));
+        }
+        if !keys.contains_key(DEFAULT_KEY_ID) {
+            let state =
+                EnclaveState::new(DEFAULT_KEY_ID, seed.as_ref().map(|seed| seed.as_slice()), &rng);
+            keys.insert(DEFAULT_KEY_ID.to_string(), state);
+        }
+        Ok(Self {
+            keys,
+            seed,
+            injection: None,
+            rng,
+        })
+    }
+
+    /// Whether any epoch of any key has evaluated a point
+    pub(crate) fn used(&self) -> bool {
+        self.keys.values().any(|state| {
+            let previous = state.previous.as_ref().map_or(0, |(key, _)| key.usage.total);
+            state.current.usage.total + previous > 0
+        })
+    }
+
+    /// Current public key of every named key
+    pub(crate) fn public_keys(&self) -> Vec<PublicKeyInfo> {
+        let mut keys: Vec<_> = self
+            .keys
+            .iter()
+            .map(|(key_id, state)| PublicKeyInfo {
+                ciphersuite: C::ID,
+                key_id: key_id.clone(),
+                epoch: state.current.epoch,
+                public_key: state.current.public_key_bytes.clone(),
+            })
+            .collect();
+        keys.sort_by(|a, b| a.key_id.cmp(&b.key_id));
+        keys
+    }
+
+    /// Current epoch of the default key
+    pub(crate) fn default_epoch(&self) -> SuiteEpoch {
+        SuiteEpoch {
+            ciphersuite: C::ID,
+            epoch: self.keys.get(DEFAULT_KEY_ID).map_or(0, |state| state.current.epoch),
+        }
+    }
+
+    /// Every epoch of every named key
+    pub(crate) fn key_epochs(&self) -> Vec<KeyEpochs> {
+        let mut keys: Vec<_> = self
+            .keys
+            .iter()
+            .map(|(key_id, state)| KeyEpochs {
+                ciphersuite: C::ID,
+                key_id: key_id.clone(),
+                current: state.current.info(None),
+                previous: state
+                    .previous
+                    .as_ref()
+                    .map(|(key, expires_at)| key.info(Some(*expires_at))),
+            })
+            .collect();
+        keys.sort_by(|a, b| a.key_id.cmp(&b.key_id));
+        keys
+    }
+
+    /// Drop the cached evaluations of every key epoch, returning how many
+    /// there were
+    pub(crate) fn flush_caches(&mut self) -> usize {
+        self.keys
+            .values_mut()
+            .map(|state| {
+                let previous = state.previous.as_mut().map_or(0, |(key, _)| key.cache.clear());
+                state.current.cache.clear() + previous
+            })
+            .sum()
+    }
+
+    /// Rotate an existing named key to its next epoch
+    pub(crate) fn rotate(
+        &mut self,
+        request: &RotateKeyRequest,
+        attestations: &Attester,
+    ) -> OprfResult<RotateKeyResponse> {
+        let state = self
+            .keys
+            .get_mut(&request.key_id)
+            .ok_or_else(|| bad_request(format!("Unknown key id {:?}", request.key_id)))?;
+        let seed = self.seed.as_ref().map(|seed| seed.as_slice());
+        Ok(state.rotate(request, seed, attestations)?)
+    }
+
+    /// Create a transport key for injecting a secret key under a new key id,
+    /// replacing any injection still pending
+    pub(crate) fn begin_key_injection(
+        &mut self,
+        request: &BeginKeyInjectionRequest,
+        attestations: &Attester,
+    ) -> Result<BeginKeyInjectionResponse, String> {
+        validate_key_id(&request.key_id).map_err(|e| e.to_string())?;
+        self.check_unused_key_id(&request.key_id)?;
+
+        let transport_key = LockedBox::new(C::random_scalar(&mut self.rng));
+        let transport_public_key = C::serialize_element(&C::scalar_mul_generator(&transport_key))
+            .map_err(|e| format!("Failed to serialize transport key: {}", e))?;
+        self.injection = Some(PendingInjection {
+            key_id: request.key_id.clone(),
+            epoch: request.epoch,
+            transport_key,
+        });
+        println!(
+            "[Enclave] Created {} transport key for injecting key {:?} (epoch {})",
+            C::IDENTIFIER,
+            request.key_id,
+            request.epoch
+        );
+
+        let key = &self.get_or_create(DEFAULT_KEY_ID)?.current;
+        let attestation = attestations.generate(
+            &key.public_key_bytes,
+            &key_injection_binding(&request.key_id, request.epoch, &transport_public_key),
+        )?;
+
+        Ok(BeginKeyInjectionResponse {
+            transport_public_key,
+            attestation,
+        })
+    }
+
+    /// Install a secret key sealed to the pending transport key. The transport
+    /// key is discarded whether or not this succeeds.
+    pub(crate) fn inject_key(
+        &mut self,
+        request: &InjectKeyRequest,
+        attestations: &Attester,
+    ) -> Result<InjectKeyResponse, String> {
+        let pending = self.injection.take().ok_or("No key injection in progress")?;
+        if pending.key_id != request.key_id || pending.epoch != request.epoch {
+            return Err("Key injection does not match the pending transport key".to_string());
+        }
+        self.check_unused_key_id(&request.key_id)?;
+        if self.keys.len() >= MAX_KEYS {
+            return Err(format!("Limit of {} {} keys reached", MAX_KEYS, C::IDENTIFIER));
+        }
+        let nonce: [u8; NONCE_LEN] = request
+            .nonce
+            .as_slice()
+            .try_into()
+            .map_err(|_| format!("Nonce must be {} bytes", NONCE_LEN))?;
+
+        let aad = key_injection_binding(&request.key_id, request.epoch, &[]);
+        let secret_key_bytes = open_with::<C>(
+            &pending.transport_key,
+            &request.sender_public_key,
+            &nonce,
+            &aad,
+            &request.ciphertext,
+        )
+        .map(Zeroizing::new)
+        .map_err(|e| format!("Failed to open injected key: {}", e))?;
+        let secret_key = C::deserialize_scalar(&secret_key_bytes)
+            .map(Zeroizing::new)
+            .map_err(|e| format!("Invalid injected key: {}", e))?;
+        if ct::scalar_eq::<C>(&secret_key, &C::Scalar::default()) {
+            return Err("Injected secret key is zero".to_string());
+        }
+
+        let key = EpochKey::from_secret_key(request.epoch, *secret_key, &self.rng);
+        let public_key = &key.public_key_bytes;
+        let attestation =
+            attestations.generate(public_key, &attested_user_data(&request.key_id, public_key))?;
+        let response = InjectKeyResponse {
+            key_id: request.key_id.clone(),
+            epoch: key.epoch,
+            public_key: key.public_key_bytes.clone(),
+            attestation,
+        };
+
+        println!(
+            "[Enclave] Injected {} key {:?} (epoch {})",
+            C::IDENTIFIER,
+            request.key_id,
+            request.epoch
+        );
+        self.keys.insert(
+            request.key_id.clone(),
+            EnclaveState {
+                current: key,
+                previous: None,
+            },
+        );
+        Ok(response)
+    }
+
+    /// Refuse to inject a key under an id that already names one: replacing
+    /// it would change its outputs, which only a rotation may do
+    fn check_unused_key_id(&self, key_id: &str) -> Result<(), String> {
+        if self.keys.contains_key(key_id) {
+            return Err(format!(
+                "{} key {:?} already exists; inject under a new key id or rotate it",
+                C::IDENTIFIER,
+                key_id
+            ));
+        }
+        Ok(())
+    }
+
+    /// Current public key of a named key, attested from the cache
+    fn public_key(
+        &mut self,
+        request: &GetPublicKeyRequest,
+        attestations: &mut Attester,
+    ) -> OprfResult<PublicKeyResponse> {
+        let key = &self.get_or_create(&request.key_id).map_err(bad_request)?.current;
+        let user_data = attested_user_data(&request.key_id, &key.public_key_bytes);
+        let attestation = attestations.cached(&key.public_key_bytes, &user_data, false)?;
+        println!("[Enclave] Sent {} public key {:?}", C::IDENTIFIER, request.key_id);
+
+        Ok(PublicKeyResponse {
+            key_id: request.key_id.clone(),
+            epoch: key.epoch,
+            public_key: key.public_key_bytes.clone(),
+            public_key_g2: key.public_key_g2_bytes.clone(),
+            attestation,
+        })
+    }
+
+    /// Newly generated attestation of a named key bound to the client nonce
+    fn attest(
+        &mut self,
+        request: &GetAttestationRequest,
+        attestations: &Attester,
+    ) -> OprfResult<AttestationResponse> {
+        check_client_nonce(Some(&request.nonce))?;
+        let key = &self.get_or_create(&request.key_id).map_err(bad_request)?.current;
+        let public_key = &key.public_key_bytes;
+        let binding = key_attestation_binding(&request.key_id, public_key, &request.nonce);
+        let attestation = attestations.generate(public_key, &binding)?;
+
+        Ok(AttestationResponse {
+            key_id: request.key_id.clone(),
+            epoch: key.epoch,
+            public_key: key.public_key_bytes.clone(),
+            attestation,
+        })
+    }
+
+    pub(crate) fn handle(
+        &mut self,
+        request: &Message,
+        limits: &UsageLimits,
+        evaluator: &Evaluator,
+        attestations: &mut Attester,
+        negotiated: Negotiated,
+        timings: &mut Timings,
+    ) -> OprfResult<EnclaveResponse> {
+        match request {
+            Message::Evaluate(request) => {
+                let state = self.get_or_create(&request.key_id).map_err(bad_request)?;
+                let response =
+                    state.evaluate(request, limits, evaluator, attestations, negotiated, timings);
+                if response.is_err() {
+                    state.record_error(request.epoch);
+                }
+                Ok(EnclaveResponse::Evaluate(response?))
+            }
+            Message::BatchEvaluate(request) => {
+                let state = self.get_or_create(&request.key_id).map_err(bad_request)?;
+                let response = state.evaluate_batch(
+                    request,
+                    limits,
+                    evaluator,
+                    attestations,
+                    negotiated,
+                    timings,
+                );
+                if response.is_err() {
+                    state.record_error(request.epoch);
+                }
+                Ok(EnclaveResponse::BatchEvaluate(response?))
+            }
+            Message::GetPublicKey(request) => {
+                Ok(EnclaveResponse::PublicKey(self.public_key(request, attestations)?))
+            }
+            Message::GetAttestation(request) => {
+                Ok(EnclaveResponse::Attestation(self.attest(request, attestations)?))
+            }
+            Message::RotateKey(request) => {
+                Ok(EnclaveResponse::RotateKey(self.rotate(request, attestations)?))
+            }
+            Message::BeginKeyInjection(request) => {
+                let response = self.begin_key_injection(request, attestations)?;
+                Ok(EnclaveResponse::BeginKeyInjection(response))
+            }
+            Message::InjectKey(request) => {
+                Ok(EnclaveResponse::InjectKey(self.inject_key(request, attestations)?))
+            }
+            Message::Hello(_)
+            | Message::Handshake(_)
+            | Message::Health
+            | Message::ExportKeys(_)
+            | Message::ImportKeys(_)
+            | Message::ProvisionSeed(_)
+            | Message::BeginReplication
+            | Message::ExportReplica(_)
+            | Message::ImportReplica(_)
+            | Message::GetAuditLog(_)
+            | Message::GetStats
+            | Message::GetSelfTest => {
+                Err(bad_request("Request covers every ciphersuite".to_string()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enclave::Enclave;
+    use crate::in_process::tests::{attestation, blinded_query, evaluation, handle};
+    use crate::reaper::{ConnectionLimits, Connections};
+    use oprf_common::admin::{AdminCommand, AdminResponse};
+    use oprf_common::P256Sha256;
+
+    /// A point that is not on P-256, with a valid SEC1 prefix
+    fn bad_point() -> Vec<u8> {
+        let mut point = blinded_query();
+        point[1..].fill(0xff);
+        point
+    }
+
+    /// Usage of the default P-256 key: evaluations and failed requests
+    fn usage(enclave: &mut Enclave) -> (u64, u64) {
+        let connections = Connections::start(ConnectionLimits::default()).unwrap();
+        let Ok(AdminResponse::Keys(list)) = enclave.admin(&AdminCommand::ListKeys, &connections)
+        else {
+            panic!("keys are listed");
+        };
+        let key = list
+            .keys
+            .iter()
+            .find(|key| key.ciphersuite == CiphersuiteId::P256Sha256 && key.key_id == "default")
+            .expect("the default key is listed");
+        (key.current.evaluations, key.current.errors)
+    }
+
+    fn batch(blinded_queries: Vec<Vec<u8>>) -> Message {
+        Message::BatchEvaluate(BatchOprfRequest {
+            query_hash: sha256_hex(&blinded_queries.concat()),
+            blinded_queries,
+            mode: OprfMode::Oprf,
+            ciphersuite: CiphersuiteId::P256Sha256,
+            epoch: None,
+            key_id: DEFAULT_KEY_ID.to_string(),
+            force_fresh: false,
+            client_nonce: None,
+            client_id: None,
+            request_id: None,
+        })
+    }
+
+    #[test]
+    fn test_bad_points_not_charged() {
+        let limits = UsageLimits {
+            max_total: Some(3),
+            ..UsageLimits::default()
+        };
+        let options = EvaluationOptions::default();
+        let mut enclave = Enclave::new(limits, Default::default(), options, 0, 60, attestation());
+
+        let error = handle(&mut enclave, &Message::Evaluate(evaluation(bad_point()))).unwrap_err();
+        assert_eq!(error.code, ErrorCode::BadPoint);
+        // One bad point fails the whole batch, which charges none of it
+        let error = handle(&mut enclave, &batch(vec![blinded_query(), bad_point()])).unwrap_err();
+        assert_eq!(error.code, ErrorCode::BadPoint);
+        assert_eq!(usage(&mut enclave), (0, 2));
+
+        // The budget is intact for valid points
+        handle(&mut enclave, &batch(vec![blinded_query(), blinded_query()])).unwrap();
+        handle(&mut enclave, &Message::Evaluate(evaluation(blinded_query()))).unwrap();
+        assert_eq!(usage(&mut enclave), (3, 2));
+        let error =
+            handle(&mut enclave, &Message::Evaluate(evaluation(blinded_query()))).unwrap_err();
+        assert_eq!(error.code, ErrorCode::RateLimited);
+        assert_eq!(error.message, "Key has used its budget of 3 evaluations");
+        assert_eq!(usage(&mut enclave), (3, 3));
+    }
+
+    #[test]
+    fn test_window_budget() {
+        let limits = UsageLimits {
+            max_total: None,
+            max_per_window: Some(2),
+            window_secs: 60,
+        };
+        let mut usage = Usage::default();
+        usage.record(2, &limits, 1000).unwrap();
+        let error = usage.record(1, &limits, 1059).unwrap_err();
+        assert_eq!(error.code, ErrorCode::RateLimited);
+        assert_eq!(usage.total, 2);
+
+        // A new window starts once the last has passed
+        usage.record(2, &limits, 1060).unwrap();
+        assert_eq!((usage.total, usage.window_count, usage.last_used), (4, 2, Some(1060)));
+    }
+
+    #[test]
+    fn test_errors_counted_by_epoch() {
+        let rng = SharedRng::os();
+        let mut state = EnclaveState::<P256Sha256>::new(DEFAULT_KEY_ID, None, &rng);
+        state.record_error(None);
+        let usage = &state.current.usage;
+        assert_eq!((usage.total, usage.errors), (0, 1));
+        assert!(usage.last_used.is_some());
+
+        let current = EpochKey::new(DEFAULT_KEY_ID, 1, None, &rng);
+        let previous = std::mem::replace(&mut state.current, current);
+        state.previous = Some((previous, u64::MAX));
+        state.record_error(Some(0));
+        state.record_error(Some(0));
+        state.record_error(Some(1));
+        // Requests for an epoch the enclave does not hold count against none
+        state.record_error(Some(7));
+        let (previous, _) = state.previous.as_ref().unwrap();
+        assert_eq!((previous.usage.errors, state.current.usage.errors), (3, 1));
+    }
+
+    #[test]
+    fn test_usage_kept_in_backups() {
+        let rng = SharedRng::os();
+        let mut state = EnclaveState::<P256Sha256>::new(DEFAULT_KEY_ID, None, &rng);
+        state.current.usage.record(5, &UsageLimits::default(), 1000).unwrap();
+        state.current.usage.record_error(1200);
+
+        let restored = EnclaveState::<P256Sha256>::from_backup(
+            &state.to_backup(DEFAULT_KEY_ID).unwrap(),
+            &rng,
+        )
+        .unwrap();
+        let usage = &restored.current.usage;
+        assert_eq!((usage.total, usage.errors, usage.last_used), (5, 1, Some(1200)));
+        let info = restored.current.info(None);
+        assert_eq!((info.evaluations, info.errors, info.last_used), (5, 1, Some(1200)));
+    }
+}
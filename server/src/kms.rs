@@ -0,0 +1,187 @@
+//! KMS access for key backup and restore and for seed provisioning.
+//!
+//! In Nitro mode the enclave has no network access, so KMS calls go through
+//! `kmstool_enclave_cli` from aws-nitro-enclaves-sdk-c, which reaches KMS via
+//! the parent's `vsock-proxy` and attaches an NSM attestation document to
+//! every request. KMS then returns the plaintext data key encrypted to the
+//! enclave only, and only if the attestation satisfies the key policy (e.g. a
+//! `kms:RecipientAttestation:PCR0` condition).
+//!
+//! The CMK is the one pinned on the enclave command line, which is part of
+//! the measured image; the parent only supplies credentials for the calls.
+//! Every decryption names it, so KMS refuses ciphertexts under any other
+//! key, and a fresh data key is only used once KMS has released it again to
+//! the enclave's attestation, so a backup is never made that the image
+//! cannot restore.
+//!
+//! Local mode wraps data under the fixed mock key of
+//! [`oprf_common::envelope::mock_kms_encrypt`] instead, so backups and seeds
+//! can be exercised without AWS. Mock backups are rejected in Nitro mode.
+
+use oprf_common::envelope::DATA_KEY_LEN;
+use oprf_common::KmsConfig;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Whether data keys come from the local mock KMS
+pub const IS_MOCK: bool = cfg!(not(feature = "nitro"));
+
+/// A fresh data key, in plaintext and wrapped under the KMS key
+pub struct DataKey {
+    pub plaintext: [u8; DATA_KEY_LEN],
+    pub ciphertext: Vec<u8>,
+}
+
+impl Drop for DataKey {
+    fn drop(&mut self) {
+        self.plaintext.zeroize();
+    }
+}
+
+fn to_data_key(bytes: &[u8]) -> Result<[u8; DATA_KEY_LEN], String> {
+    bytes
+        .try_into()
+        .map_err(|_| format!("Data key must be {} bytes, got {}", DATA_KEY_LEN, bytes.len()))
+}
+
+#[cfg(feature = "nitro")]
+const KMSTOOL_PATH: &str = "/app/kmstool_enclave_cli";
+
+/// Decoded `NAME: <base64>` lines printed by kmstool
+#[cfg(feature = "nitro")]
+type KmstoolOutputs = Vec<(String, Zeroizing<Vec<u8>>)>;
+
+/// Run `kmstool_enclave_cli <command>` and return its `NAME: <base64>` outputs
+#[cfg(feature = "nitro")]
+fn run_kmstool(
+    command: &str,
+    config: &KmsConfig,
+    args: &[(&str, &str)],
+) -> Result<KmstoolOutputs, String> {
+    use base64ct::{Base64, Encoding};
+
+    let port = config.proxy_port.to_string();
+    let mut cmd = std::process::Command::new(KMSTOOL_PATH);
+    cmd.arg(command)
+        .args(["--region", &config.region])
+        .args(["--proxy-port", &port])
+        .args(["--aws-access-key-id", &config.access_key_id])
+        .args(["--aws-secret-access-key", &config.secret_access_key]);
+    if let Some(token) = &config.session_token {
+        cmd.args(["--aws-session-token", token]);
+    }
+    for (flag, value) in args {
+        cmd.args([flag, value]);
+    }
+
+    let output = cmd
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", KMSTOOL_PATH, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "kmstool {} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    // stdout holds plaintext keys in base64
+    let stdout = Zeroizing::new(output.stdout);
+    String::from_utf8_lossy(&stdout)
+        .lines()
+        .filter_map(|line| line.split_once(": "))
+        .map(|(name, value)| {
+            let bytes = Base64::decode_vec(value.trim())
+                .map_err(|e| format!("Invalid base64 in kmstool output: {}", e))?;
+            Ok((name.to_string(), Zeroizing::new(bytes)))
+        })
+        .collect()
+}
+
+#[cfg(feature = "nitro")]
+fn find_output(outputs: &KmstoolOutputs, name: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+    outputs
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, value)| value.clone())
+        .ok_or_else(|| format!("kmstool output is missing {}", name))
+}
+
+/// Generate an AES-256 data key under `kms_key_id` (KMS GenerateDataKey),
+/// checked to decrypt again to the enclave's attestation
+#[cfg(feature = "nitro")]
+pub fn generate_data_key(config: &KmsConfig, kms_key_id: &str) -> Result<DataKey, String> {
+    let outputs = run_kmstool(
+        "genkey",
+        config,
+        &[("--key-id", kms_key_id), ("--key-spec", "AES-256")],
+    )?;
+    let data_key = DataKey {
+        plaintext: to_data_key(&find_output(&outputs, "PLAINTEXT")?)?,
+        ciphertext: find_output(&outputs, "CIPHERTEXT")?.to_vec(),
+    };
+    let unwrapped = decrypt_data_key(config, kms_key_id, &data_key.ciphertext)?;
+    if !oprf_common::ct::eq(&unwrapped[..], &data_key.plaintext) {
+        return Err(format!("KMS key {} returned a data key it does not decrypt", kms_key_id));
+    }
+    Ok(data_key)
+}
+
+/// Decrypt a KMS ciphertext blob under `kms_key_id` (KMS Decrypt), with
+/// the plaintext returned encrypted to the enclave's attestation
+#[cfg(feature = "nitro")]
+pub fn decrypt(
+    config: &KmsConfig,
+    kms_key_id: &str,
+    ciphertext: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, String> {
+    use base64ct::{Base64, Encoding};
+
+    let ciphertext = Base64::encode_string(ciphertext);
+    let outputs = run_kmstool(
+        "decrypt",
+        config,
+        &[("--key-id", kms_key_id), ("--ciphertext", &ciphertext)],
+    )?;
+    find_output(&outputs, "PLAINTEXT")
+}
+
+/// Generate a data key wrapped by the mock KMS
+#[cfg(not(feature = "nitro"))]
+pub fn generate_data_key(_config: &KmsConfig, kms_key_id: &str) -> Result<DataKey, String> {
+    use oprf_common::envelope::{mock_kms_encrypt, NONCE_LEN};
+    use rand::RngCore;
+
+    println!("[Enclave] Using mock KMS (local mode) for key {}", kms_key_id);
+
+    let mut plaintext = [0u8; DATA_KEY_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut plaintext);
+    rand::rngs::OsRng.fill_bytes(&mut nonce);
+
+    Ok(DataKey {
+        plaintext,
+        ciphertext: mock_kms_encrypt(&plaintext, &nonce),
+    })
+}
+
+/// Decrypt a blob wrapped by the mock KMS, which has a single key
+#[cfg(not(feature = "nitro"))]
+pub fn decrypt(
+    _config: &KmsConfig,
+    _kms_key_id: &str,
+    ciphertext: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, String> {
+    println!("[Enclave] Using mock KMS (local mode)");
+    oprf_common::envelope::mock_kms_decrypt(ciphertext)
+        .map(Zeroizing::new)
+        .map_err(|e| e.to_string())
+}
+
+/// Unwrap a data key produced by [`generate_data_key`]
+pub fn decrypt_data_key(
+    config: &KmsConfig,
+    kms_key_id: &str,
+    ciphertext: &[u8],
+) -> Result<Zeroizing<[u8; DATA_KEY_LEN]>, String> {
+    to_data_key(&decrypt(config, kms_key_id, ciphertext)?).map(Zeroizing::new)
+}
@@ -0,0 +1,232 @@
+//! Reusable OPRF enclave server.
+//!
+//! Holds the enclave's keys and serves the framed protocol of
+//! [`oprf_common::frame`] to its parent: version negotiation, the attested
+//! secure channel, evaluation, key rotation, injection, backup, seed
+//! provisioning and replication. How the enclave is reached and how it
+//! attests are plugged in: any [`Listener`] accepts connections, and any
+//! [`AttestationProvider`] produces attestation documents, so the evaluator
+//! can be embedded in other enclave applications.
+//!
+//! An application creates an [`Enclave`] with its provider, binds its
+//! listeners and hands both to [`serve`], with an [`AdminPort`] for operator
+//! commands and a [`HeartbeatPort`] for watchdogs if it wants them, and a
+//! [`Shutdown`] to stop it gracefully. Tests can instead reach an enclave
+//! in their own process through [`InProcess`], or, with the `testing`
+//! feature, a [`testing::MockEnclave`] attested like a local-mode enclave.
+//! The `chaos` feature adds fault injection in responses, see
+//! [`Enclave::set_chaos`].
+
+mod admin;
+mod attestation;
+mod audit;
+mod cache;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod connection;
+mod enclave;
+mod heartbeat;
+mod in_process;
+mod keys;
+mod kms;
+mod listener;
+mod memory;
+mod metrics;
+mod parallel;
+mod pool;
+mod ratelimit;
+mod reaper;
+mod replay;
+mod replication;
+mod shutdown;
+#[cfg(feature = "testing")]
+pub mod testing;
+
+pub use admin::AdminPort;
+pub use attestation::{AttestationProvider, MockAttestation};
+#[cfg(feature = "chaos")]
+pub use chaos::{Chaos, Fault};
+#[cfg(feature = "nitro")]
+pub use attestation::NsmAttestation;
+pub use enclave::Enclave;
+pub use heartbeat::HeartbeatPort;
+pub use in_process::InProcess;
+pub use keys::{EvaluationOptions, UsageLimits};
+pub use listener::{bind_tcp, local_listener, Listener, LocalConnector, LocalListener};
+pub use ratelimit::{Rate, RateLimits};
+pub use reaper::ConnectionLimits;
+pub use shutdown::Shutdown;
+#[cfg(feature = "nitro")]
+pub use listener::VsockListener;
+
+use connection::handle_connection;
+use oprf_common::{ErrorCode, ErrorResponse};
+use pool::WorkerPool;
+use reaper::{Connections, TimedStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long a connection may stall on a write before it is dropped; reads
+/// are bounded by [`ConnectionLimits`]
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Refusal of a malformed request, or one naming something that does not exist
+fn bad_request(message: String) -> ErrorResponse {
+    ErrorResponse::new(ErrorCode::BadRequest, message)
+}
+
+fn chrono_lite_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Worker pool handling connections against the shared enclave state, with
+/// room to queue `queue` connections
+fn start_workers(
+    state: Arc<Mutex<Enclave>>,
+    workers: usize,
+    queue: usize,
+) -> std::io::Result<WorkerPool> {
+    println!("[Enclave] Handling connections on {} worker threads", workers);
+    WorkerPool::new(workers, queue, move |stream, tracked| {
+        if let Err(e) = stream.set_write_timeout(Some(WRITE_TIMEOUT)) {
+            eprintln!("[Enclave] Failed to set connection timeouts: {}", e);
+            return;
+        }
+        handle_connection(&mut TimedStream::new(stream, tracked), &state);
+    })
+}
+
+/// Serve `enclave` on every listener with `workers` connection handler
+/// threads, within `limits`, until `shutdown` is requested and the requests
+/// in progress are answered, see [`shutdown`]. With an `admin` port, key
+/// management is only accepted there, and so is a shutdown. With a `heartbeat`
+/// port, beats are published on it. Nothing is served if the known-answer
+/// self-test fails.
+pub fn serve(
+    mut enclave: Enclave,
+    workers: usize,
+    limits: ConnectionLimits,
+    listeners: Vec<Box<dyn Listener>>,
+    admin: Option<AdminPort>,
+    heartbeat: Option<HeartbeatPort>,
+    shutdown: &Shutdown,
+) -> std::io::Result<()> {
+    enclave.self_test().map_err(std::io::Error::other)?;
+    let connections = Connections::start(limits)?;
+    if admin.is_some() {
+        enclave.reserve_key_management();
+    }
+    let state = Arc::new(Mutex::new(enclave));
+    if let Some(admin) = admin {
+        admin::start(admin, Arc::clone(&state), Arc::clone(&connections), shutdown.clone())?;
+    }
+    let publisher = heartbeat
+        .map(|port| {
+            heartbeat::start(port, Arc::clone(&state), Arc::clone(&connections), shutdown.clone())
+        })
+        .transpose()?;
+    let pool = start_workers(state, workers, limits.max_open)?;
+    listener::serve_all(listeners, pool, &connections, shutdown)?;
+
+    shutdown.wait();
+    println!("[Enclave] Draining {} open connections", connections.count());
+    let closed = connections.drain();
+    if closed > 0 {
+        eprintln!("[Enclave] Closed {} connections still busy after the drain timeout", closed);
+    }
+    // Let the last beat, marked stopping, go out
+    if let Some(publisher) = publisher {
+        let _ = publisher.join();
+    }
+    println!("[Enclave] Shut down");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::in_process::tests::attestation;
+    use oprf_common::frame::{read_frame, write_frame, WireFormat};
+    use oprf_common::{HelloRequest, HelloResponse, Message, PointEncoding};
+    use std::io::Read;
+    use std::net::TcpStream;
+
+    #[test]
+    fn test_serve_until_shutdown() {
+        let enclave = Enclave::new(
+            UsageLimits::default(),
+            RateLimits::default(),
+            EvaluationOptions::default(),
+            0,
+            60,
+            attestation(),
+        );
+        let listener = bind_tcp(0).unwrap();
+        let address = listener.local_addr().unwrap();
+        let shutdown = Shutdown::new();
+        let serving = shutdown.clone();
+        let server = std::thread::spawn(move || {
+            let listeners: Vec<Box<dyn Listener>> = vec![Box::new(listener)];
+            serve(enclave, 2, ConnectionLimits::default(), listeners, None, None, &serving)
+        });
+
+        // A connection that was served and sits idle
+        let mut idle = TcpStream::connect(address).unwrap();
+        idle.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let hello = Message::Hello(HelloRequest {
+            versions: vec![2],
+            point_encoding: PointEncoding::default(),
+            request_ids: false,
+        });
+        write_frame(&mut idle, 1, WireFormat::Json, &WireFormat::Json.encode(&hello).unwrap())
+            .unwrap();
+        read_frame(&mut idle).unwrap().unwrap();
+
+        shutdown.request();
+        server.join().unwrap().unwrap();
+        assert_eq!(idle.read(&mut [0; 1]).unwrap(), 0);
+
+        // Connections arriving from now on are closed unread
+        let mut late = TcpStream::connect(address).unwrap();
+        late.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(late.read(&mut [0; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_serve_local_connections() {
+        let enclave = Enclave::new(
+            UsageLimits::default(),
+            RateLimits::default(),
+            EvaluationOptions::default(),
+            0,
+            60,
+            attestation(),
+        );
+        let (listener, connector) = local_listener();
+        let shutdown = Shutdown::new();
+        let serving = shutdown.clone();
+        let server = std::thread::spawn(move || {
+            let listeners: Vec<Box<dyn Listener>> = vec![Box::new(listener)];
+            serve(enclave, 2, ConnectionLimits::default(), listeners, None, None, &serving)
+        });
+
+        let mut stream = connector.connect().unwrap();
+        let hello = Message::Hello(HelloRequest {
+            versions: vec![2],
+            point_encoding: PointEncoding::default(),
+            request_ids: false,
+        });
+        write_frame(&mut stream, 1, WireFormat::Json, &WireFormat::Json.encode(&hello).unwrap())
+            .unwrap();
+        let frame = read_frame(&mut stream).unwrap().unwrap();
+        let response: HelloResponse = frame.format.decode(&frame.payload).unwrap();
+        assert_eq!(response.version, 2);
+
+        shutdown.request();
+        server.join().unwrap().unwrap();
+        assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+    }
+}
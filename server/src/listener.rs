@@ -0,0 +1,163 @@
+//! Listening sockets of the enclave.
+//!
+//! Connections arrive through any [`Listener`]: vsock in a Nitro enclave,
+//! loopback TCP in local mode, or both at once, e.g. an extra loopback TCP
+//! port for debugging a deployed enclave from inside it. A [`LocalListener`]
+//! takes connections from the enclave's own process, for front-ends serving
+//! another protocol such as gRPC. Every listener has its own accept thread
+//! feeding the shared worker pool, until the server shuts down.
+
+use crate::pool::WorkerPool;
+use crate::reaper::Connections;
+use crate::shutdown::Shutdown;
+use std::io::{Error, ErrorKind};
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+
+/// A bound listening socket handing out connections. Connections are
+/// `TcpStream`s whatever the transport, since a connected stream socket of
+/// any family reads and writes like one.
+pub trait Listener: Send {
+    /// Wait for the next connection
+    fn accept(&self) -> std::io::Result<TcpStream>;
+}
+
+impl Listener for TcpListener {
+    fn accept(&self) -> std::io::Result<TcpStream> {
+        TcpListener::accept(self).map(|(stream, _)| stream)
+    }
+}
+
+/// Listen on 127.0.0.1:`port`, or on a free port if `port` is 0
+pub fn bind_tcp(port: u32) -> std::io::Result<TcpListener> {
+    let port = u16::try_from(port)
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "Port out of range"))?;
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("[Enclave] TCP server listening on {}", listener.local_addr()?);
+    Ok(listener)
+}
+
+/// Connections opened by a [`LocalConnector`] in the same process. Each is
+/// an end of a Unix socket pair, which reads and writes like a `TcpStream`.
+pub struct LocalListener {
+    incoming: Receiver<TcpStream>,
+    /// Keeps `accept` waiting rather than failing once every other
+    /// connector is dropped
+    _connector: LocalConnector,
+}
+
+/// Opens connections to its [`LocalListener`]
+#[derive(Clone)]
+pub struct LocalConnector(Sender<TcpStream>);
+
+/// A listener of in-process connections and what opens them
+pub fn local_listener() -> (LocalListener, LocalConnector) {
+    let (sender, incoming) = mpsc::channel();
+    let connector = LocalConnector(sender);
+    let listener = LocalListener {
+        incoming,
+        _connector: connector.clone(),
+    };
+    (listener, connector)
+}
+
+impl LocalConnector {
+    /// Open a connection and return the client's end
+    pub fn connect(&self) -> std::io::Result<UnixStream> {
+        let (client, server) = UnixStream::pair()?;
+        self.0
+            .send(TcpStream::from(OwnedFd::from(server)))
+            .map_err(|_| Error::new(ErrorKind::NotConnected, "Local listener closed"))?;
+        Ok(client)
+    }
+}
+
+impl Listener for LocalListener {
+    fn accept(&self) -> std::io::Result<TcpStream> {
+        self.incoming
+            .recv()
+            .map_err(|_| Error::new(ErrorKind::NotConnected, "Local listener closed"))
+    }
+}
+
+/// A vsock socket bound to a CID and port
+#[cfg(feature = "nitro")]
+pub struct VsockListener(std::os::fd::OwnedFd);
+
+#[cfg(feature = "nitro")]
+impl VsockListener {
+    /// Listen on vsock `port` of `cid`
+    pub fn bind(cid: u32, port: u32) -> std::io::Result<Self> {
+        use nix::sys::socket::{bind, listen, socket, AddressFamily, SockFlag, SockType, VsockAddr};
+        use std::os::unix::io::AsRawFd;
+
+        let sock_fd = socket(AddressFamily::Vsock, SockType::Stream, SockFlag::empty(), None)
+            .map_err(Error::other)?;
+        bind(sock_fd.as_raw_fd(), &VsockAddr::new(cid, port)).map_err(Error::other)?;
+        listen(&sock_fd, 128).map_err(Error::other)?;
+        println!("[Enclave] Nitro vsock server listening on CID {:#x}, port {}", cid, port);
+        Ok(Self(sock_fd))
+    }
+}
+
+#[cfg(feature = "nitro")]
+impl Listener for VsockListener {
+    fn accept(&self) -> std::io::Result<TcpStream> {
+        use std::os::unix::io::{AsRawFd, FromRawFd};
+
+        let client_fd = nix::sys::socket::accept(self.0.as_raw_fd()).map_err(Error::other)?;
+        Ok(unsafe { TcpStream::from_raw_fd(client_fd) })
+    }
+}
+
+/// Hand every connection of `listener` that `connections` admits to `pool`,
+/// until `shutdown` is requested. A connection accepted after it is closed
+/// unread; the loop then ends without waiting for another.
+fn accept_loop(
+    listener: &dyn Listener,
+    pool: &WorkerPool,
+    connections: &Arc<Connections>,
+    shutdown: &Shutdown,
+) {
+    while !shutdown.is_requested() {
+        match listener.accept() {
+            Ok(_) if shutdown.is_requested() => {
+                println!("[Enclave] Refusing connection: shutting down");
+            }
+            Ok(stream) => match connections.admit(&stream) {
+                Some(tracked) => {
+                    println!("[Enclave] Connection received");
+                    pool.submit(stream, tracked);
+                }
+                None => eprintln!("[Enclave] Refusing connection: every open connection is busy"),
+            },
+            Err(e) => eprintln!("[Enclave] Accept error: {}", e),
+        }
+    }
+}
+
+/// Serve every listener in `listeners` on a thread of its own until
+/// `shutdown` is requested
+pub(crate) fn serve_all(
+    listeners: Vec<Box<dyn Listener>>,
+    pool: WorkerPool,
+    connections: &Arc<Connections>,
+    shutdown: &Shutdown,
+) -> std::io::Result<()> {
+    if listeners.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "No listener to serve"));
+    }
+    for (index, listener) in listeners.into_iter().enumerate() {
+        let pool = pool.clone();
+        let connections = Arc::clone(connections);
+        let shutdown = shutdown.clone();
+        thread::Builder::new()
+            .name(format!("oprf-accept-{}", index))
+            .spawn(move || accept_loop(listener.as_ref(), &pool, &connections, &shutdown))?;
+    }
+    Ok(())
+}
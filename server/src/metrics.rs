@@ -0,0 +1,140 @@
+//! Request counters and latency histograms, read with `GetStats`.
+//!
+//! Every evaluation request records how long each [`Stage`] of serving it
+//! took: decoding, evaluating, attesting and encoding, and the whole. The
+//! histograms are counters of atomics, so connection workers record their
+//! requests without taking the enclave lock; a snapshot may catch one
+//! request half recorded.
+
+use oprf_common::{Stage, StageStats, StatsResponse, LATENCY_BUCKETS};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Latency histogram of one stage
+struct Histogram {
+    count: AtomicU64,
+    total_micros: AtomicU64,
+    max_micros: AtomicU64,
+    buckets: [AtomicU64; LATENCY_BUCKETS],
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            total_micros: AtomicU64::new(0),
+            max_micros: AtomicU64::new(0),
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+
+    fn record(&self, duration: Duration) {
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+        self.buckets[StageStats::bucket(micros)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self, stage: Stage) -> StageStats {
+        StageStats {
+            stage,
+            count: self.count.load(Ordering::Relaxed),
+            total_micros: self.total_micros.load(Ordering::Relaxed),
+            max_micros: self.max_micros.load(Ordering::Relaxed),
+            buckets: self.buckets.iter().map(|bucket| bucket.load(Ordering::Relaxed)).collect(),
+        }
+    }
+}
+
+/// Time each stage of one request took, recorded into [`Metrics`] once it
+/// is answered
+#[derive(Debug, Default)]
+pub(crate) struct Timings {
+    stages: [Duration; Stage::ALL.len()],
+    /// Points the request evaluated, if it is an evaluation
+    evaluations: Option<u64>,
+}
+
+impl Timings {
+    /// Add `duration` to the time of `stage`
+    pub(crate) fn add(&mut self, stage: Stage, duration: Duration) {
+        self.stages[stage as usize] += duration;
+    }
+
+    /// Run `f`, adding the time it takes to `stage`
+    pub(crate) fn time<T>(&mut self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.add(stage, start.elapsed());
+        result
+    }
+
+    /// Mark the request as an evaluation of `count` points
+    pub(crate) fn evaluated(&mut self, count: usize) {
+        self.evaluations = Some(count as u64);
+    }
+}
+
+/// Counters and stage histograms since the enclave started
+pub(crate) struct Metrics {
+    started: Instant,
+    requests: AtomicU64,
+    errors: AtomicU64,
+    evaluation_requests: AtomicU64,
+    evaluations: AtomicU64,
+    stages: [Histogram; Stage::ALL.len()],
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            requests: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            evaluation_requests: AtomicU64::new(0),
+            evaluations: AtomicU64::new(0),
+            stages: std::array::from_fn(|_| Histogram::new()),
+        }
+    }
+
+    /// Count an answered request, and record the stages of an evaluation.
+    /// Refused evaluations are only counted as errors, so that fast
+    /// refusals do not hide the latency of the others.
+    pub(crate) fn record(&self, timings: &Timings, total: Duration, failed: bool) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if failed {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let Some(evaluations) = timings.evaluations else { return };
+        self.evaluation_requests.fetch_add(1, Ordering::Relaxed);
+        self.evaluations.fetch_add(evaluations, Ordering::Relaxed);
+        for stage in Stage::ALL {
+            let duration = match stage {
+                Stage::Total => total,
+                stage => timings.stages[stage as usize],
+            };
+            self.stages[stage as usize].record(duration);
+        }
+    }
+
+    /// Seconds since the enclave started
+    pub(crate) fn uptime_secs(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+
+    pub(crate) fn snapshot(&self) -> StatsResponse {
+        StatsResponse {
+            uptime_secs: self.uptime_secs(),
+            requests: self.requests.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            evaluation_requests: self.evaluation_requests.load(Ordering::Relaxed),
+            evaluations: self.evaluations.load(Ordering::Relaxed),
+            stages: Stage::ALL
+                .iter()
+                .map(|stage| self.stages[*stage as usize].snapshot(*stage))
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! Batch evaluation across cores.
+//!
+//! The enclave serves one request at a time, so without this a large batch
+//! keeps one vCPU busy while the others idle. A batch is split into
+//! contiguous chunks evaluated on a rayon pool built once per enclave: at
+//! most the configured number of threads, and no chunk smaller than
+//! [`MIN_CHUNK`] points, below which handing a chunk to another thread costs
+//! more than it saves.
+
+use rayon::prelude::*;
+
+/// Fewest points worth a thread of their own
+const MIN_CHUNK: usize = 32;
+
+/// Threads one batch evaluation may use
+pub(crate) struct Pool {
+    threads: usize,
+    /// None for one thread, which evaluates on the calling thread
+    pool: Option<rayon::ThreadPool>,
+}
+
+impl Pool {
+    /// A pool of `threads` threads, at least one
+    pub(crate) fn new(threads: usize) -> Self {
+        let threads = threads.max(1);
+        let pool = (threads > 1).then(|| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .thread_name(|i| format!("oprf-eval-{}", i))
+                .build()
+                .expect("evaluation threads spawn")
+        });
+        Self { threads, pool }
+    }
+
+    /// `f` applied to contiguous chunks of `items` on the threads of the
+    /// pool, the results concatenated in order. A panic in `f` is resumed on
+    /// the calling thread.
+    pub(crate) fn map_chunks<T, U, F>(&self, items: &[T], f: F) -> Vec<U>
+    where
+        T: Sync,
+        U: Send,
+        F: Fn(&[T]) -> Vec<U> + Send + Sync,
+    {
+        let threads = self.threads.min(items.len() / MIN_CHUNK).max(1);
+        match &self.pool {
+            Some(pool) if threads > 1 => pool.install(|| {
+                items.par_chunks(items.len().div_ceil(threads)).flat_map_iter(&f).collect()
+            }),
+            _ => f(items),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_chunks_in_order() {
+        let items: Vec<u32> = (0..1000).collect();
+        let doubled: Vec<u32> = items.iter().map(|item| item * 2).collect();
+        for threads in [0, 1, 4, 64] {
+            let pool = Pool::new(threads);
+            let mapped = pool.map_chunks(&items, |chunk| chunk.iter().map(|i| i * 2).collect());
+            assert_eq!(mapped, doubled);
+            assert_eq!(pool.map_chunks(&items[..10], |chunk| chunk.to_vec()), &items[..10]);
+        }
+    }
+}
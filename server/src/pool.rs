@@ -0,0 +1,140 @@
+//! Bounded pool of connection handler threads.
+//!
+//! The accept loop hands every connection to a fixed set of worker threads
+//! through a bounded queue, so a slow or stalled client only ties up its own
+//! worker. The queue holds as many connections as may be open at once (see
+//! [`crate::reaper`]), so the accept loop never waits on it.
+
+use crate::reaper::Tracked;
+use std::net::TcpStream;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// An accepted connection and its registration
+type Connection = (TcpStream, Tracked);
+
+/// Worker threads handling accepted connections; clones feed the same
+/// workers
+#[derive(Clone)]
+pub struct WorkerPool {
+    sender: SyncSender<Connection>,
+}
+
+impl WorkerPool {
+    /// Start `workers` threads running `handler` on each connection, with
+    /// room for `queue` connections waiting for a worker
+    pub fn new<F>(workers: usize, queue: usize, handler: F) -> std::io::Result<Self>
+    where
+        F: Fn(TcpStream, Tracked) + Send + Sync + 'static,
+    {
+        let (sender, receiver) = sync_channel(queue);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handler = Arc::new(handler);
+        for index in 0..workers {
+            let receiver = Arc::clone(&receiver);
+            let handler = Arc::clone(&handler);
+            thread::Builder::new()
+                .name(format!("oprf-worker-{}", index))
+                .spawn(move || work(&receiver, handler.as_ref()))?;
+        }
+        Ok(Self { sender })
+    }
+
+    /// Queue a connection, waiting while the queue is full
+    pub fn submit(&self, stream: TcpStream, tracked: Tracked) {
+        if self.sender.send((stream, tracked)).is_err() {
+            eprintln!("[Enclave] No worker left to handle the connection");
+        }
+    }
+}
+
+/// Handle connections until the pool is dropped
+fn work(receiver: &Mutex<Receiver<Connection>>, handler: &dyn Fn(TcpStream, Tracked)) {
+    loop {
+        // Only wait for the next connection under the lock, not handle it
+        let stream = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        match stream {
+            Ok((stream, tracked)) => handler(stream, tracked),
+            Err(_) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reaper::{ConnectionLimits, Connections};
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    /// Clients of connections handed to `pool`, in order
+    fn submit(pool: &WorkerPool, connections: &Arc<Connections>, count: usize) -> Vec<TcpStream> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        (0..count)
+            .map(|_| {
+                let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+                let (server, _) = listener.accept().unwrap();
+                let tracked = connections.admit(&server).unwrap();
+                pool.submit(server, tracked);
+                client
+            })
+            .collect()
+    }
+
+    /// Send a byte and wait up to `timeout` for its echo
+    fn echoed(client: &mut TcpStream, timeout: Duration) -> bool {
+        client.set_read_timeout(Some(timeout)).unwrap();
+        let mut byte = [0u8];
+        match client.read(&mut byte) {
+            Ok(1) => true,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => false,
+            other => panic!("connection failed: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_stalled_clients_hold_only_their_workers() {
+        let connections = Connections::start(ConnectionLimits::default()).unwrap();
+        let active = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let (counter, peak) = (Arc::clone(&active), Arc::clone(&most));
+        // Echo one byte, however long it takes to arrive
+        let pool = WorkerPool::new(2, 8, move |mut stream, _tracked| {
+            peak.fetch_max(counter.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+            let mut byte = [0u8];
+            if stream.read_exact(&mut byte).is_ok() {
+                let _ = stream.write_all(&byte);
+            }
+            counter.fetch_sub(1, Ordering::SeqCst);
+        })
+        .unwrap();
+
+        let mut clients = submit(&pool, &connections, 3);
+        let start = Instant::now();
+        while active.load(Ordering::SeqCst) < 2 {
+            assert!(start.elapsed() < Duration::from_secs(5), "workers never started");
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        // Both workers wait on stalled clients, so the third is queued
+        clients[2].write_all(b"c").unwrap();
+        assert!(!echoed(&mut clients[2], Duration::from_millis(200)));
+        clients[0].write_all(b"a").unwrap();
+        assert!(echoed(&mut clients[0], Duration::from_secs(5)));
+        assert!(echoed(&mut clients[2], Duration::from_secs(5)));
+
+        // A fresh client is served while the second still stalls
+        let mut late = submit(&pool, &connections, 1).remove(0);
+        late.write_all(b"d").unwrap();
+        assert!(echoed(&mut late, Duration::from_secs(5)));
+        clients[1].write_all(b"b").unwrap();
+        assert!(echoed(&mut clients[1], Duration::from_secs(5)));
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+    }
+}
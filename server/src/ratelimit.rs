@@ -0,0 +1,297 @@
+//! Token-bucket rate limits on evaluations.
+//!
+//! The connections of the data path share a bucket of evaluations, and every
+//! client id named by evaluation requests has one too, shared by all
+//! connections of that client. A bucket holds at most `burst` tokens and
+//! refills at `per_sec` tokens a second. An evaluation request takes one
+//! token per query from the connections' bucket and from that of its client,
+//! and is refused with `RateLimited`, taking none, if either is short. This
+//! bounds how fast a parent that loops on a bug spends the keys' evaluation
+//! budget ([`crate::UsageLimits`]), which counts evaluations however they
+//! arrive.
+//!
+//! Every connection comes from the parent, its only peer, so the connection
+//! bucket is the parent's: a bucket per connection would start full and let
+//! the parent reconnect to refill it. Reloaded limits apply to existing
+//! buckets from their next use, which caps their tokens at the new burst.
+//! Client ids are chosen by the parent, so they keep well-behaved clients
+//! apart rather than contain a hostile parent.
+
+use crate::bad_request;
+use oprf_common::admin::RateSetting;
+use oprf_common::{ErrorCode, ErrorResponse, Message, OprfResult};
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Client buckets kept; the least recently used is dropped beyond this
+const MAX_CLIENTS: usize = 4096;
+
+/// Sustained rate and burst of a token bucket
+#[derive(Clone, Copy, Debug)]
+pub struct Rate {
+    /// Evaluations per second
+    pub per_sec: u64,
+    /// Evaluations allowed at once, at least the largest batch to serve
+    pub burst: u64,
+}
+
+impl Rate {
+    /// Rate of a reloaded `setting`, none if it is unlimited. `name` is the
+    /// setting in errors.
+    pub(crate) fn from_setting(setting: RateSetting, name: &str) -> OprfResult<Option<Self>> {
+        match setting {
+            RateSetting::Unlimited => Ok(None),
+            RateSetting::Limited { per_sec, burst } if per_sec == 0 || burst == 0 => {
+                Err(bad_request(format!("The rate and burst of {} must be positive", name)))
+            }
+            RateSetting::Limited { per_sec, burst } => Ok(Some(Self { per_sec, burst })),
+        }
+    }
+
+    /// Setting reporting `rate`
+    pub(crate) fn setting(rate: Option<Self>) -> RateSetting {
+        match rate {
+            Some(rate) => RateSetting::Limited {
+                per_sec: rate.per_sec,
+                burst: rate.burst,
+            },
+            None => RateSetting::Unlimited,
+        }
+    }
+}
+
+/// Operator-configured evaluation rates, unlimited where absent. Set them
+/// where the parent cannot, such as the measured enclave command line.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RateLimits {
+    /// Rate of evaluations over all connections of the parent
+    pub per_connection: Option<Rate>,
+    /// Rate of evaluations naming one client id, across connections
+    pub per_client: Option<Rate>,
+}
+
+/// Tokens of a bucket, as of `updated`
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn full(rate: &Rate, now: Instant) -> Self {
+        Self {
+            tokens: rate.burst as f64,
+            updated: now,
+        }
+    }
+
+    /// Add the tokens that accrued up to `now`, and whether `count` are there
+    fn refill(&mut self, rate: &Rate, now: Instant, count: u64) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate.per_sec as f64).min(rate.burst as f64);
+        self.updated = now;
+        self.tokens >= count as f64
+    }
+}
+
+fn rate_limited(who: String, rate: &Rate) -> ErrorResponse {
+    ErrorResponse::new(
+        ErrorCode::RateLimited,
+        format!(
+            "{} is limited to {} evaluations per second in bursts of at most {}",
+            who, rate.per_sec, rate.burst
+        ),
+    )
+}
+
+/// Rate limits of the enclave, the bucket of its connections and those of
+/// the clients it has seen
+pub(crate) struct RateLimiter {
+    limits: RateLimits,
+    connections: Option<TokenBucket>,
+    clients: HashMap<String, TokenBucket>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limits: RateLimits) -> Self {
+        Self {
+            limits,
+            connections: None,
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Rate limits in force
+    pub(crate) fn limits(&self) -> RateLimits {
+        self.limits
+    }
+
+    /// Replace the rate limits, keeping the buckets
+    pub(crate) fn set_limits(&mut self, limits: RateLimits) {
+        self.limits = limits;
+    }
+
+    /// Bucket of `client_id`, full if it is new
+    fn client(&mut self, client_id: &str, rate: &Rate, now: Instant) -> &mut TokenBucket {
+        if !self.clients.contains_key(client_id) && self.clients.len() >= MAX_CLIENTS {
+            let oldest = self
+                .clients
+                .iter()
+                .min_by_key(|(_, bucket)| bucket.updated)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                self.clients.remove(&oldest);
+            }
+        }
+        self.clients
+            .entry(client_id.to_string())
+            .or_insert_with(|| TokenBucket::full(rate, now))
+    }
+
+    /// Take the evaluations of `request` from the bucket of the connections
+    /// and from the bucket of its client id
+    pub(crate) fn check(&mut self, request: &Message) -> OprfResult<()> {
+        self.check_at(request, Instant::now())
+    }
+
+    fn check_at(&mut self, request: &Message, now: Instant) -> OprfResult<()> {
+        let count = match request {
+            Message::Evaluate(_) => 1,
+            Message::BatchEvaluate(request) => request.blinded_queries.len() as u64,
+            _ => return Ok(()),
+        };
+
+        if let Some(rate) = self.limits.per_connection {
+            let bucket = self.connections.get_or_insert_with(|| TokenBucket::full(&rate, now));
+            if !bucket.refill(&rate, now, count) {
+                return Err(rate_limited("The parent".to_string(), &rate));
+            }
+        }
+        if let (Some(rate), Some(client_id)) = (self.limits.per_client, request.client_id()) {
+            let bucket = self.client(client_id, &rate, now);
+            if !bucket.refill(&rate, now, count) {
+                return Err(rate_limited(format!("Client {:?}", client_id), &rate));
+            }
+            bucket.tokens -= count as f64;
+        }
+        if let (Some(_), Some(bucket)) = (self.limits.per_connection, &mut self.connections) {
+            bucket.tokens -= count as f64;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enclave::Enclave;
+    use crate::in_process::tests::{blinded_query, connect, evaluation, request, start};
+    use crate::keys::UsageLimits;
+    use oprf_common::{CiphersuiteId, GetPublicKeyRequest, OprfRequest};
+    use std::time::Duration;
+
+    const RATE: Rate = Rate {
+        per_sec: 10,
+        burst: 3,
+    };
+
+    fn public_key() -> Message {
+        Message::GetPublicKey(GetPublicKeyRequest {
+            ciphersuite: CiphersuiteId::P256Sha256,
+            key_id: "default".to_string(),
+        })
+    }
+
+    fn evaluate(client_id: Option<&str>) -> Message {
+        Message::Evaluate(OprfRequest {
+            client_id: client_id.map(str::to_string),
+            ..evaluation(blinded_query())
+        })
+    }
+    fn limiter(per_connection: Option<Rate>, per_client: Option<Rate>) -> RateLimiter {
+        RateLimiter::new(RateLimits {
+            per_connection,
+            per_client,
+        })
+    }
+
+    #[test]
+    fn test_burst_then_refill() {
+        let mut limiter = limiter(Some(RATE), None);
+        let start = Instant::now();
+        for _ in 0..RATE.burst {
+            limiter.check_at(&evaluate(None), start).unwrap();
+        }
+        let error = limiter.check_at(&evaluate(None), start).unwrap_err();
+        assert_eq!(error.code, ErrorCode::RateLimited);
+        assert_eq!(
+            error.message,
+            "The parent is limited to 10 evaluations per second in bursts of at most 3"
+        );
+
+        // A token accrues every 100 ms, up to the burst
+        let later = start + Duration::from_millis(150);
+        limiter.check_at(&evaluate(None), later).unwrap();
+        assert!(limiter.check_at(&evaluate(None), later).is_err());
+        let much_later = start + Duration::from_secs(60);
+        for _ in 0..RATE.burst {
+            limiter.check_at(&evaluate(None), much_later).unwrap();
+        }
+        assert!(limiter.check_at(&evaluate(None), much_later).is_err());
+
+        // Other requests take no tokens and are never limited
+        limiter.check_at(&public_key(), much_later).unwrap();
+    }
+
+    #[test]
+    fn test_client_buckets_keyed_by_client_id() {
+        let mut limiter = limiter(None, Some(RATE));
+        let now = Instant::now();
+        for _ in 0..RATE.burst {
+            limiter.check_at(&evaluate(Some("alice")), now).unwrap();
+        }
+        let error = limiter.check_at(&evaluate(Some("alice")), now).unwrap_err();
+        assert_eq!(error.code, ErrorCode::RateLimited);
+        assert!(error.message.starts_with("Client \"alice\" is limited"));
+
+        // Other clients and requests without a client id are unaffected
+        limiter.check_at(&evaluate(Some("bob")), now).unwrap();
+        limiter.check_at(&evaluate(None), now).unwrap();
+    }
+
+    #[test]
+    fn test_refused_request_takes_no_tokens() {
+        let mut limiter = limiter(Some(RATE), Some(Rate { per_sec: 10, burst: 1 }));
+        let now = Instant::now();
+        limiter.check_at(&evaluate(Some("alice")), now).unwrap();
+        // Refused by the client bucket, so the parent's keeps its tokens
+        assert!(limiter.check_at(&evaluate(Some("alice")), now).is_err());
+        limiter.check_at(&evaluate(Some("bob")), now).unwrap();
+        limiter.check_at(&evaluate(Some("carol")), now).unwrap();
+        assert!(limiter.check_at(&evaluate(Some("dave")), now).is_err());
+    }
+
+    #[test]
+    fn test_rate_limited_frame_across_reconnects() {
+        let enclave = start(|attestation| {
+            let rates = RateLimits {
+                per_connection: Some(Rate { per_sec: 1, burst: 2 }),
+                per_client: None,
+            };
+            Enclave::new(UsageLimits::default(), rates, Default::default(), 0, 60, attestation)
+        });
+
+        let mut first = connect(&enclave);
+        request(&mut first, &evaluate(None)).unwrap();
+        request(&mut first, &evaluate(None)).unwrap();
+        let error = request(&mut first, &evaluate(None)).unwrap_err();
+        assert_eq!(error.code, ErrorCode::RateLimited);
+
+        // A new connection draws on the same bucket
+        drop(first);
+        let mut second = connect(&enclave);
+        let error = request(&mut second, &evaluate(None)).unwrap_err();
+        assert_eq!(error.code, ErrorCode::RateLimited);
+        // and the connection survives the refusal
+        request(&mut second, &public_key()).unwrap();
+    }
+}
@@ -0,0 +1,418 @@
+//! Bounds on open connections, so clients that hold connections open
+//! without finishing their requests cannot starve everyone else.
+//!
+//! - Read deadlines: a connection waits at most `idle_timeout` for the next
+//!   frame to begin, and a frame must arrive in full within `frame_timeout`
+//!   of its first byte, however slowly the bytes trickle in. Every read is
+//!   given the time left, not a fresh timeout.
+//! - A cap of `max_open` connections, counting those still queued for a
+//!   worker. At the cap the longest idle connection is closed to make room
+//!   for a new one; if none is idle the new one is refused.
+//! - A reaper thread closing connections idle for longer than
+//!   `idle_timeout`, including queued ones no worker is reading yet.
+//! - On shutdown, a bound of `drain_timeout` on the requests in progress,
+//!   see [`crate::shutdown`].
+//!
+//! A connection is idle from its acceptance or its last response until the
+//! first byte of its next request. Reloaded limits apply to open connections
+//! from their next read; a lower cap closes none of them.
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the reaper looks for idle connections
+const REAP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often a shutdown checks whether every connection has closed
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Operator-configured bounds on the connections of the enclave
+#[derive(Clone, Copy, Debug)]
+pub struct ConnectionLimits {
+    /// Connections open at once, queued ones included
+    pub max_open: usize,
+    /// How long a connection may wait between requests
+    pub idle_timeout: Duration,
+    /// How long a request frame may take to arrive from its first byte
+    pub frame_timeout: Duration,
+    /// How long a shutdown waits for the requests in progress
+    pub drain_timeout: Duration,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_open: 64,
+            idle_timeout: Duration::from_secs(30),
+            frame_timeout: Duration::from_secs(10),
+            drain_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// An open connection, as seen by the reaper
+struct Entry {
+    /// Handle to shut the connection down with
+    stream: TcpStream,
+    /// Since when the connection is idle, `None` while serving a request
+    idle_since: Option<Instant>,
+}
+
+/// Every open connection of the enclave
+pub(crate) struct Connections {
+    limits: Mutex<ConnectionLimits>,
+    open: Mutex<HashMap<u64, Entry>>,
+    next_id: AtomicU64,
+    /// Set on shutdown: connections close once idle
+    draining: AtomicBool,
+}
+
+impl Connections {
+    /// Track connections within `limits`, reaping idle ones on a thread of
+    /// their own
+    pub(crate) fn start(limits: ConnectionLimits) -> std::io::Result<Arc<Self>> {
+        let connections = Arc::new(Self {
+            limits: Mutex::new(limits),
+            open: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+            draining: AtomicBool::new(false),
+        });
+        let reaper = Arc::clone(&connections);
+        thread::Builder::new().name("oprf-reaper".to_string()).spawn(move || loop {
+            thread::sleep(REAP_INTERVAL);
+            reaper.reap();
+        })?;
+        Ok(connections)
+    }
+
+    /// Limits in force
+    pub(crate) fn limits(&self) -> ConnectionLimits {
+        *self.limits.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Replace the limits of every connection
+    pub(crate) fn set_limits(&self, limits: ConnectionLimits) {
+        *self.limits.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = limits;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Entry>> {
+        // The map stays consistent whatever panicked while holding it
+        self.open.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Register a newly accepted connection, closing the longest idle one if
+    /// the cap is reached. `None` if every open connection is busy.
+    pub(crate) fn admit(self: &Arc<Self>, stream: &TcpStream) -> Option<Tracked> {
+        let handle = match stream.try_clone() {
+            Ok(handle) => handle,
+            Err(e) => {
+                eprintln!("[Enclave] Failed to track connection: {}", e);
+                return None;
+            }
+        };
+        let max_open = self.limits().max_open;
+        let mut open = self.lock();
+        if open.len() >= max_open {
+            let longest_idle = open
+                .iter()
+                .filter_map(|(id, entry)| entry.idle_since.map(|since| (since, *id)))
+                .min();
+            let (_, id) = longest_idle?;
+            if let Some(entry) = open.remove(&id) {
+                println!("[Enclave] Closing the longest idle connection to admit a new one");
+                let _ = entry.stream.shutdown(Shutdown::Both);
+            }
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        if self.draining.load(Ordering::Relaxed) {
+            // Admitted as the shutdown began
+            let _ = handle.shutdown(Shutdown::Read);
+        }
+        let entry = Entry {
+            stream: handle,
+            idle_since: Some(Instant::now()),
+        };
+        open.insert(id, entry);
+        Some(Tracked {
+            connections: Arc::clone(self),
+            id,
+        })
+    }
+
+    /// Number of open connections, queued ones included
+    pub(crate) fn count(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Shut down connections idle for longer than the idle timeout
+    fn reap(&self) {
+        let idle_timeout = self.limits().idle_timeout;
+        let now = Instant::now();
+        let mut open = self.lock();
+        open.retain(|_, entry| {
+            let expired = entry
+                .idle_since
+                .is_some_and(|since| now.duration_since(since) > idle_timeout);
+            if expired {
+                let _ = entry.stream.shutdown(Shutdown::Both);
+            }
+            !expired
+        });
+    }
+
+    /// Close idle connections, and every other one once it is idle, waiting
+    /// at most the drain timeout for all of them to close. Those still open
+    /// then are shut down; returns how many.
+    pub(crate) fn drain(&self) -> usize {
+        let deadline = Instant::now() + self.limits().drain_timeout;
+        self.draining.store(true, Ordering::Relaxed);
+        for entry in self.lock().values().filter(|entry| entry.idle_since.is_some()) {
+            // Reads end, so the worker, or the next one to take a queued
+            // connection, closes it
+            let _ = entry.stream.shutdown(Shutdown::Read);
+        }
+        while self.count() > 0 && Instant::now() < deadline {
+            thread::sleep(DRAIN_POLL_INTERVAL);
+        }
+        let open = self.lock();
+        for entry in open.values() {
+            let _ = entry.stream.shutdown(Shutdown::Both);
+        }
+        open.len()
+    }
+
+    fn set_idle(&self, id: u64, idle: bool) {
+        if let Some(entry) = self.lock().get_mut(&id) {
+            entry.idle_since = idle.then(Instant::now);
+            if idle && self.draining.load(Ordering::Relaxed) {
+                // The response being written still goes out
+                let _ = entry.stream.shutdown(Shutdown::Read);
+            }
+        }
+    }
+}
+
+/// Registration of one open connection, dropped with it
+pub(crate) struct Tracked {
+    connections: Arc<Connections>,
+    id: u64,
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.connections.lock().remove(&self.id);
+    }
+}
+
+/// A connection read under the deadlines of its [`ConnectionLimits`]
+pub(crate) struct TimedStream {
+    stream: TcpStream,
+    tracked: Tracked,
+    /// When the request being read must be complete, once it has begun
+    deadline: Option<Instant>,
+}
+
+impl TimedStream {
+    pub(crate) fn new(stream: TcpStream, tracked: Tracked) -> Self {
+        Self {
+            stream,
+            tracked,
+            deadline: None,
+        }
+    }
+}
+
+impl Read for TimedStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let limits = self.tracked.connections.limits();
+        let timed_out = || {
+            let reason = match self.deadline {
+                None => "Connection idle too long",
+                Some(_) => "Request frame too slow",
+            };
+            Error::new(ErrorKind::TimedOut, reason)
+        };
+        let timeout = match self.deadline {
+            None => limits.idle_timeout,
+            Some(deadline) => deadline
+                .checked_duration_since(Instant::now())
+                .filter(|left| !left.is_zero())
+                .ok_or_else(timed_out)?,
+        };
+        self.stream.set_read_timeout(Some(timeout))?;
+        // Timeouts surface as `WouldBlock` on Unix
+        let read = self.stream.read(buf).map_err(|e| match e.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => timed_out(),
+            _ => e,
+        })?;
+        if read > 0 && self.deadline.is_none() {
+            self.deadline = Some(Instant::now() + limits.frame_timeout);
+            self.tracked.connections.set_idle(self.tracked.id, false);
+        }
+        Ok(read)
+    }
+}
+
+/// Writing the response ends the request: the connection is idle again
+impl Write for TimedStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.deadline.take().is_some() {
+            self.tracked.connections.set_idle(self.tracked.id, true);
+        }
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn limits(max_open: usize, idle_timeout_ms: u64, frame_timeout_ms: u64) -> ConnectionLimits {
+        ConnectionLimits {
+            max_open,
+            idle_timeout: Duration::from_millis(idle_timeout_ms),
+            frame_timeout: Duration::from_millis(frame_timeout_ms),
+            ..ConnectionLimits::default()
+        }
+    }
+
+    /// The client and the enclave's end of a loopback connection
+    fn connect() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, server)
+    }
+
+    /// Whether the enclave closed the client's connection
+    fn closed(client: &mut TcpStream) -> bool {
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        matches!(client.read(&mut [0; 1]), Ok(0) | Err(_))
+    }
+
+    #[test]
+    fn test_idle_connection_reaped() {
+        let connections = Connections::start(limits(8, 50, 1000)).unwrap();
+        let (mut idle, idle_server) = connect();
+        let (_busy, busy_server) = connect();
+        let _idle = connections.admit(&idle_server).unwrap();
+        let busy = connections.admit(&busy_server).unwrap();
+        connections.set_idle(busy.id, false);
+
+        thread::sleep(Duration::from_millis(100));
+        connections.reap();
+        assert_eq!(connections.count(), 1);
+        assert!(closed(&mut idle));
+    }
+
+    #[test]
+    fn test_slow_frame_times_out() {
+        let connections = Connections::start(limits(8, 5000, 100)).unwrap();
+        let (mut client, server) = connect();
+        let tracked = connections.admit(&server).unwrap();
+        let mut stream = TimedStream::new(server, tracked);
+
+        // A byte at a time, each within the idle timeout, but the frame
+        // misses its deadline
+        let trickle = thread::spawn(move || {
+            for _ in 0..10 {
+                if client.write_all(&[0]).is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(30));
+            }
+        });
+        let error = stream.read_exact(&mut [0; 10]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        assert_eq!(error.to_string(), "Request frame too slow");
+        trickle.join().unwrap();
+    }
+
+    #[test]
+    fn test_idle_read_times_out() {
+        let connections = Connections::start(limits(8, 50, 1000)).unwrap();
+        let (_client, server) = connect();
+        let tracked = connections.admit(&server).unwrap();
+        let mut stream = TimedStream::new(server, tracked);
+        let error = stream.read(&mut [0; 1]).unwrap_err();
+        assert_eq!(error.to_string(), "Connection idle too long");
+    }
+
+    #[test]
+    fn test_cap_closes_longest_idle() {
+        let connections = Connections::start(limits(2, 5000, 1000)).unwrap();
+        let (mut first, first_server) = connect();
+        let (_second, second_server) = connect();
+        let (_third, third_server) = connect();
+        let (_fourth, fourth_server) = connect();
+        let _first = connections.admit(&first_server).unwrap();
+        thread::sleep(Duration::from_millis(10));
+        let second = connections.admit(&second_server).unwrap();
+
+        // The first connection was idle the longest
+        let third = connections.admit(&third_server).unwrap();
+        assert!(closed(&mut first));
+        assert_eq!(connections.count(), 2);
+
+        // With every connection busy, a new one is refused
+        connections.set_idle(second.id, false);
+        connections.set_idle(third.id, false);
+        assert!(connections.admit(&fourth_server).is_none());
+        drop(second);
+        assert_eq!(connections.count(), 1);
+    }
+
+    #[test]
+    fn test_drain_waits_for_busy_connections() {
+        let connections = Connections::start(ConnectionLimits::default()).unwrap();
+        let (_idle_client, mut idle_server) = connect();
+        let (_busy_client, mut busy_server) = connect();
+        let idle = connections.admit(&idle_server).unwrap();
+        let busy = connections.admit(&busy_server).unwrap();
+        connections.set_idle(busy.id, false);
+        let draining = Arc::clone(&connections);
+        let draining = thread::spawn(move || draining.drain());
+
+        // The idle connection reads its end, as its worker would
+        idle_server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(idle_server.read(&mut [0; 1]).unwrap(), 0);
+        drop(idle);
+
+        // The busy one reads on until its response is written
+        busy_server.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        assert!(busy_server.read(&mut [0; 1]).is_err());
+        connections.set_idle(busy.id, true);
+        assert_eq!(busy_server.read(&mut [0; 1]).unwrap(), 0);
+
+        // So does one admitted as the shutdown began
+        let (_late_client, mut late_server) = connect();
+        let late = connections.admit(&late_server).unwrap();
+        assert_eq!(late_server.read(&mut [0; 1]).unwrap(), 0);
+        drop((busy, late));
+        assert_eq!(draining.join().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_drain_timeout_closes_the_rest() {
+        let connections = Connections::start(ConnectionLimits {
+            drain_timeout: Duration::from_millis(100),
+            ..ConnectionLimits::default()
+        })
+        .unwrap();
+        let (mut client, server) = connect();
+        let busy = connections.admit(&server).unwrap();
+        connections.set_idle(busy.id, false);
+        assert_eq!(connections.drain(), 1);
+        assert!(closed(&mut client));
+    }
+}
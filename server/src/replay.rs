@@ -0,0 +1,93 @@
+//! Anti-replay window of client nonces.
+//!
+//! The enclave remembers the client nonces of the last [`REPLAY_WINDOW`]
+//! evaluation requests and refuses a request whose nonce it has seen, so a
+//! request recorded between parent and enclave cannot be evaluated again. A
+//! nonce is used up by the request carrying it even if the request is then
+//! refused. Once the window is full the oldest nonce is forgotten; a response
+//! to a replay of it still fails the parent's check of the attested nonce
+//! against the one it sent.
+
+use crate::bad_request;
+use crate::keys::check_client_nonce;
+use oprf_common::OprfResult;
+use std::collections::{HashSet, VecDeque};
+
+/// Client nonces remembered, 4 MiB at the largest nonce size
+pub(crate) const REPLAY_WINDOW: usize = 64 * 1024;
+
+/// Recently seen client nonces, oldest first
+#[derive(Default)]
+pub(crate) struct ReplayWindow {
+    seen: HashSet<Vec<u8>>,
+    order: VecDeque<Vec<u8>>,
+}
+
+impl ReplayWindow {
+    /// Record `nonce`, refusing it if it is still in the window
+    pub(crate) fn check(&mut self, nonce: &[u8]) -> OprfResult<()> {
+        check_client_nonce(Some(nonce))?;
+        if self.seen.contains(nonce) {
+            return Err(bad_request("Replayed client nonce".to_string()));
+        }
+        if self.order.len() == REPLAY_WINDOW {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.seen.insert(nonce.to_vec());
+        self.order.push_back(nonce.to_vec());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oprf_common::{ErrorCode, MAX_CLIENT_NONCE_LEN};
+
+    fn nonce(index: usize) -> Vec<u8> {
+        index.to_be_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_replayed_nonce_refused() {
+        let mut window = ReplayWindow::default();
+        window.check(b"first").unwrap();
+        window.check(b"second").unwrap();
+
+        let error = window.check(b"first").unwrap_err();
+        assert_eq!(error.code, ErrorCode::BadRequest);
+        assert_eq!(error.message, "Replayed client nonce");
+        // The refusal does not reset the nonce
+        assert!(window.check(b"first").is_err());
+
+        let error = window.check(&[7; MAX_CLIENT_NONCE_LEN + 1]).unwrap_err();
+        assert_eq!(error.code, ErrorCode::BadRequest);
+        window.check(&[7; MAX_CLIENT_NONCE_LEN]).unwrap();
+    }
+
+    #[test]
+    fn test_oldest_nonce_evicted_at_capacity() {
+        let mut window = ReplayWindow::default();
+        for index in 0..REPLAY_WINDOW {
+            window.check(&nonce(index)).unwrap();
+        }
+        assert_eq!(window.order.len(), REPLAY_WINDOW);
+        assert!(window.check(&nonce(0)).is_err());
+
+        // One more nonce forgets the oldest and only the oldest
+        window.check(&nonce(REPLAY_WINDOW)).unwrap();
+        assert_eq!(window.order.len(), REPLAY_WINDOW);
+        assert_eq!(window.seen.len(), REPLAY_WINDOW);
+        assert!(window.check(&nonce(1)).is_err());
+        assert!(window.check(&nonce(REPLAY_WINDOW)).is_err());
+
+        // The evicted nonce is accepted again, evicting the next oldest
+        window.check(&nonce(0)).unwrap();
+        assert!(window.check(&nonce(2)).is_err());
+        window.check(&nonce(1)).unwrap();
+        assert!(window.check(&nonce(0)).is_err());
+        window.check(&nonce(2)).unwrap();
+    }
+}
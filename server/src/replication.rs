@@ -0,0 +1,205 @@
+//! Replicating every key from a primary enclave to a hot standby.
+//!
+//! The parent relays three admin commands, which the data path refuses, and
+//! neither enclave trusts it with the keys or with the identity of the
+//! other:
+//!
+//! 1. [`Message::BeginReplication`] to the standby, which creates an
+//!    ephemeral P-256 transport key and attests it.
+//! 2. [`ExportReplicaRequest`] to the primary with that attestation. The
+//!    primary checks that it measures the same enclave image as its own, then
+//!    seals its keys to the transport key and attests them.
+//! 3. [`ImportReplicaRequest`] to the standby with the primary's response.
+//!    The standby checks the primary's attestation the same way, opens the
+//!    keys and replaces its own with them.
+//!
+//! The standby then evaluates to the same outputs as the primary, so a
+//! parent failing over between them keeps its PRF. The enclave verifies the
+//! peer's attestation itself, see [`oprf_common::claims`]: an NSM document
+//! must chain to the AWS Nitro Enclaves root compiled into the enclave and
+//! carry a valid COSE signature before its claims are read. It must then be
+//! recent, bind the expected user data and measure exactly the PCR0-2 this
+//! enclave attested its self-test report with. A mock attestation is only
+//! accepted from a peer when this enclave is itself mock-attested, with the
+//! peer's mock signing key from the request.
+//!
+//! [`Message::BeginReplication`]: oprf_common::Message::BeginReplication
+
+use crate::memory::LockedBox;
+use oprf_common::ed25519::VerifyingKey;
+use oprf_common::envelope::NONCE_LEN;
+use oprf_common::rng::SharedRng;
+use oprf_common::transport::{open_with, seal_to, SealedPayload};
+use oprf_common::claims::Claims;
+use oprf_common::nitro::{aws_root, fingerprint};
+use oprf_common::{
+    nsm_user_data, replication_binding, AttestationDocument, BeginReplicationResponse,
+    Ciphersuite, ExportReplicaRequest, ImportReplicaRequest, P256Sha256, PublicKeyInfo,
+    REPLICA_PRIMARY_LABEL, REPLICA_STANDBY_LABEL,
+};
+use zeroize::Zeroizing;
+
+/// Oldest peer attestation accepted, in seconds
+const MAX_PEER_AGE_SECS: u64 = 5 * 60;
+
+/// How far a peer attestation may lie in the future, in seconds
+const MAX_PEER_CLOCK_SKEW_SECS: u64 = 60;
+
+/// Transport key of a standby awaiting its [`ImportReplicaRequest`]
+pub(crate) struct PendingReplica {
+    transport_key: LockedBox<<P256Sha256 as Ciphersuite>::Scalar>,
+    transport_public_key: Vec<u8>,
+}
+
+impl PendingReplica {
+    /// A fresh transport key from `rng`, with the user data to attest it
+    /// under
+    pub(crate) fn generate(rng: &mut SharedRng) -> Result<(Self, Vec<u8>), String> {
+        let transport_key = LockedBox::new(P256Sha256::random_scalar(rng));
+        let transport_public_key =
+            P256Sha256::serialize_element(&P256Sha256::scalar_mul_generator(&transport_key))
+                .map_err(|e| format!("Failed to serialize transport key: {}", e))?;
+        let user_data = replication_binding(REPLICA_STANDBY_LABEL, &[&transport_public_key]);
+        Ok((
+            Self {
+                transport_key,
+                transport_public_key,
+            },
+            user_data,
+        ))
+    }
+
+    /// Response carrying the transport key and its `attestation`
+    pub(crate) fn response(&self, attestation: AttestationDocument) -> BeginReplicationResponse {
+        BeginReplicationResponse {
+            transport_public_key: self.transport_public_key.clone(),
+            attestation,
+        }
+    }
+
+    /// Check the primary's attestation of `request` against `own`, then open
+    /// the keys it sealed to this transport key
+    pub(crate) fn open(
+        &self,
+        request: &ImportReplicaRequest,
+        own: &AttestationDocument,
+    ) -> Result<Zeroizing<Vec<u8>>, String> {
+        let replica = &request.replica;
+        let keys = serde_json::to_vec(&replica.keys)
+            .map_err(|e| format!("Failed to serialize keys: {}", e))?;
+        let user_data = primary_binding(
+            &self.transport_public_key,
+            &replica.sender_public_key,
+            &keys,
+        );
+        verify_peer(
+            own,
+            &replica.attestation,
+            &user_data,
+            request.peer_mock_signing_key.as_deref(),
+        )
+        .map_err(|e| format!("Primary failed attestation: {}", e))?;
+
+        let nonce: [u8; NONCE_LEN] = replica
+            .nonce
+            .as_slice()
+            .try_into()
+            .map_err(|_| format!("Nonce must be {} bytes", NONCE_LEN))?;
+        open_with::<P256Sha256>(
+            &self.transport_key,
+            &replica.sender_public_key,
+            &nonce,
+            REPLICA_PRIMARY_LABEL,
+            &replica.ciphertext,
+        )
+        .map(Zeroizing::new)
+        .map_err(|e| format!("Failed to open replicated keys: {}", e))
+    }
+}
+
+/// Check the standby's attestation of `request` against `own`, then seal
+/// `plaintext`, holding `keys`, to its transport key with an ephemeral key
+/// from `rng`. Returns the sealed keys with the user data to attest them
+/// under.
+pub(crate) fn seal(
+    request: &ExportReplicaRequest,
+    own: &AttestationDocument,
+    keys: &[PublicKeyInfo],
+    plaintext: &[u8],
+    rng: &mut SharedRng,
+) -> Result<(SealedPayload, Vec<u8>), String> {
+    let user_data = replication_binding(REPLICA_STANDBY_LABEL, &[&request.transport_public_key]);
+    verify_peer(
+        own,
+        &request.attestation,
+        &user_data,
+        request.peer_mock_signing_key.as_deref(),
+    )
+    .map_err(|e| format!("Standby failed attestation: {}", e))?;
+
+    let transport_public_key = P256Sha256::deserialize_element(&request.transport_public_key)
+        .map_err(|e| format!("Invalid transport key: {}", e))?;
+    let sealed = seal_to::<P256Sha256, _>(
+        &transport_public_key,
+        REPLICA_PRIMARY_LABEL,
+        plaintext,
+        rng,
+    )
+    .map_err(|e| format!("Failed to seal keys: {}", e))?;
+
+    let keys = serde_json::to_vec(keys).map_err(|e| format!("Failed to serialize keys: {}", e))?;
+    let user_data =
+        primary_binding(&request.transport_public_key, &sealed.sender_public_key, &keys);
+    Ok((sealed, user_data))
+}
+
+/// User data of the primary's attestation of keys sealed to a standby
+fn primary_binding(transport_public_key: &[u8], sender_public_key: &[u8], keys: &[u8]) -> Vec<u8> {
+    replication_binding(REPLICA_PRIMARY_LABEL, &[transport_public_key, sender_public_key, keys])
+}
+
+/// Verify that `peer` attests `user_data` from the enclave image that `own`
+/// measures
+fn verify_peer(
+    own: &AttestationDocument,
+    peer: &AttestationDocument,
+    user_data: &[u8],
+    mock_signing_key: Option<&[u8]>,
+) -> Result<(), String> {
+    if peer.is_mock != own.is_mock {
+        return Err("Peer attestation mode differs from this enclave's".to_string());
+    }
+    let own_pcrs = match own.pcrs.as_deref() {
+        Some([pcr0, pcr1, pcr2, ..]) => [pcr0, pcr1, pcr2],
+        _ => return Err("This enclave's attestation has no PCRs".to_string()),
+    };
+    let mock_signing_key = match mock_signing_key {
+        Some(key) if own.is_mock => {
+            Some(VerifyingKey::from_bytes(key).map_err(|e| e.to_string())?)
+        }
+        None if own.is_mock => return Err("No mock signing key given for the peer".to_string()),
+        _ => None,
+    };
+    let claims = Claims::verify(peer, &[fingerprint(&aws_root())], mock_signing_key.as_ref())
+        .map_err(|e| e.to_string())?;
+
+    for (index, expected) in own_pcrs.iter().enumerate() {
+        let got = claims.pcrs.get(&(index as u64)).map(hex::encode);
+        if got.as_deref() != Some(expected.to_ascii_lowercase().as_str()) {
+            return Err(format!("PCR{} differs from this enclave's", index));
+        }
+    }
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_millis() as u64;
+    if claims.timestamp_ms > now_ms.saturating_add(MAX_PEER_CLOCK_SKEW_SECS * 1000)
+        || now_ms.saturating_sub(claims.timestamp_ms) > MAX_PEER_AGE_SECS * 1000
+    {
+        return Err("Peer attestation is not recent".to_string());
+    }
+    if peer.user_data != user_data || claims.user_data != Some(nsm_user_data(user_data)) {
+        return Err("Peer attestation does not bind the expected user data".to_string());
+    }
+    Ok(())
+}
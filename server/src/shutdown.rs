@@ -0,0 +1,123 @@
+//! Graceful shutdown of the server.
+//!
+//! A [`Shutdown`] requested by SIGTERM or SIGINT (see
+//! [`Shutdown::on_signals`]), by the admin port's `Shutdown` command or by
+//! the application itself stops [`crate::serve`] in three steps:
+//!
+//! 1. The accept loops stop: connections accepted after the request are
+//!    closed unread.
+//! 2. Open connections drain: idle ones, queued ones included, are closed at
+//!    once, and busy ones as soon as the response to the request in progress
+//!    is written. Requests pipelined behind it are not read.
+//! 3. Once none is left, or after the drain timeout of the
+//!    [`crate::ConnectionLimits`], the rest are closed and `serve` returns.
+//!
+//! Keys live only in enclave memory, which does not survive the enclave; the
+//! parent backs them up with `export-keys`, or `admin shutdown <file>`, to
+//! restore them into the next one.
+
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How often the signal thread checks for a signal
+const SIGNAL_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Set by the signal handler, read by the signal thread
+static SIGNALED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_shutdown(_: nix::libc::c_int) {
+    SIGNALED.store(true, Ordering::Relaxed);
+}
+
+/// Request to stop serving, shared by whatever may request it; clones
+/// request the same shutdown
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop serving; later requests change nothing
+    pub fn request(&self) {
+        let (requested, changed) = &*self.requested;
+        let mut requested = requested.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if !*requested {
+            *requested = true;
+            changed.notify_all();
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.requested.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Wait until the shutdown is requested
+    pub(crate) fn wait(&self) {
+        let (requested, changed) = &*self.requested;
+        let requested = requested.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let _requested = changed
+            .wait_while(requested, |requested| !*requested)
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+    }
+
+    /// Wait at most `timeout` for the shutdown to be requested; whether it is
+    pub(crate) fn wait_timeout(&self, timeout: Duration) -> bool {
+        let (requested, changed) = &*self.requested;
+        let requested = requested.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (requested, _) = changed
+            .wait_timeout_while(requested, timeout, |requested| !*requested)
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *requested
+    }
+
+    /// Request this shutdown when the process receives SIGTERM or SIGINT.
+    /// The handlers are process-wide, so only one shutdown should be wired
+    /// to them.
+    pub fn on_signals(&self) -> std::io::Result<()> {
+        let handler = SigHandler::Handler(request_shutdown);
+        let action = SigAction::new(handler, SaFlags::SA_RESTART, SigSet::empty());
+        for signal in [Signal::SIGTERM, Signal::SIGINT] {
+            // The handler only stores to an atomic, which is async-signal-safe
+            unsafe { sigaction(signal, &action) }.map_err(std::io::Error::other)?;
+        }
+        let shutdown = self.clone();
+        thread::Builder::new().name("oprf-signals".to_string()).spawn(move || {
+            while !SIGNALED.load(Ordering::Relaxed) {
+                thread::sleep(SIGNAL_POLL_INTERVAL);
+            }
+            println!("[Enclave] Shutting down on a signal");
+            shutdown.request();
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clones_request_the_same_shutdown() {
+        let shutdown = Shutdown::new();
+        assert!(!shutdown.wait_timeout(Duration::from_millis(10)));
+
+        let requester = shutdown.clone();
+        let waiter = shutdown.clone();
+        let waiting = thread::spawn(move || waiter.wait());
+        requester.request();
+        waiting.join().unwrap();
+        assert!(shutdown.is_requested());
+        assert!(shutdown.wait_timeout(Duration::ZERO));
+
+        // Later requests change nothing
+        requester.request();
+        assert!(shutdown.is_requested());
+    }
+}
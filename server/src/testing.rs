@@ -0,0 +1,136 @@
+//! A mock enclave for the tests of its clients.
+//!
+//! [`MockEnclave`] is a real [`Enclave`], attested by mock documents signed
+//! with a key of its own as in local mode, and served in the test's process
+//! over [`InProcess`] connections. A client under test speaks the whole
+//! protocol to it, from the Hello through the secure channel, evaluations,
+//! proofs and key management, and checks its attestations with
+//! [`MockEnclave::mock_signing_key`], without a port or the enclave binary.
+//! A [`LocalMockEnclave`] is the same enclave served on a loopback TCP port,
+//! as in local mode, for clients that open their own sockets.
+//!
+//! Built with the `testing` feature, for the dev-dependencies of clients:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! oprf-server = { path = "../server", features = ["testing"] }
+//! ```
+
+use crate::attestation::MockAttestation;
+use crate::enclave::Enclave;
+use crate::in_process::InProcess;
+use crate::keys::{EvaluationOptions, UsageLimits};
+use crate::listener::{bind_tcp, Listener};
+use crate::ratelimit::RateLimits;
+use crate::reaper::ConnectionLimits;
+use crate::serve;
+use crate::shutdown::Shutdown;
+use oprf_common::duplex::DuplexStream;
+use oprf_common::ed25519::{SigningKey, VerifyingKey};
+use oprf_common::rng::SharedRng;
+
+/// An enclave with mock attestations, served in-process
+pub struct MockEnclave {
+    enclave: InProcess,
+    mock_signing_key: VerifyingKey,
+}
+
+impl MockEnclave {
+    /// A mock with fresh random keys and no limits
+    pub fn new() -> Result<Self, String> {
+        Self::with_rng(SharedRng::os())
+    }
+
+    /// A mock whose keys, proofs and mock signing key are drawn from `rng`,
+    /// reproducible if it is seeded, see [`oprf_common::rng`]
+    pub fn with_rng(mut rng: SharedRng) -> Result<Self, String> {
+        let key = SigningKey::generate(&mut rng);
+        let (enclave, mock_signing_key) = mock(key, rng)?;
+        Ok(Self {
+            enclave: InProcess::start(enclave)?,
+            mock_signing_key,
+        })
+    }
+
+    /// Key verifying the mock's attestations, what the parent takes as
+    /// `--mock-signing-key`
+    pub fn mock_signing_key(&self) -> &VerifyingKey {
+        &self.mock_signing_key
+    }
+
+    /// Open a connection and return the client's end, served until it is
+    /// dropped
+    pub fn connect(&self) -> std::io::Result<DuplexStream> {
+        self.enclave.connect()
+    }
+}
+
+/// An enclave with mock attestations, served on a free loopback TCP port
+/// until it is stopped or dropped
+pub struct LocalMockEnclave {
+    port: u16,
+    mock_signing_key: VerifyingKey,
+    shutdown: Shutdown,
+}
+
+impl LocalMockEnclave {
+    /// A mock with fresh random keys and no usage limits, whose connections
+    /// are bounded by `limits`. Its attestations are signed with `key`, so
+    /// mocks started with the same key pass the same policy.
+    pub fn start(key: SigningKey, limits: ConnectionLimits) -> Result<Self, String> {
+        let (enclave, mock_signing_key) = mock(key, SharedRng::os())?;
+        let listener = bind_tcp(0).map_err(|e| e.to_string())?;
+        let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+        let shutdown = Shutdown::new();
+        let serving = shutdown.clone();
+        std::thread::spawn(move || {
+            let listeners: Vec<Box<dyn Listener>> = vec![Box::new(listener)];
+            if let Err(e) = serve(enclave, 4, limits, listeners, None, None, &serving) {
+                eprintln!("[Enclave] Mock enclave failed: {}", e);
+            }
+        });
+        Ok(Self {
+            port,
+            mock_signing_key,
+            shutdown,
+        })
+    }
+
+    /// Port the mock listens on, on 127.0.0.1
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Key verifying the mock's attestations
+    pub fn mock_signing_key(&self) -> &VerifyingKey {
+        &self.mock_signing_key
+    }
+
+    /// Stop serving, as on SIGTERM: idle connections are closed, and those
+    /// accepted from now on are closed unread
+    pub fn stop(&self) {
+        self.shutdown.request();
+    }
+}
+
+impl Drop for LocalMockEnclave {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// An enclave with no limits whose keys are drawn from `rng` and whose mock
+/// attestations are signed with `key`, and the key verifying them
+fn mock(key: SigningKey, rng: SharedRng) -> Result<(Enclave, VerifyingKey), String> {
+    let mock_signing_key = VerifyingKey::from_bytes(&key.public_key()).map_err(|e| e.to_string())?;
+    let enclave = Enclave::with_rng(
+        UsageLimits::default(),
+        RateLimits::default(),
+        EvaluationOptions::default(),
+        0,
+        60,
+        Box::new(MockAttestation::new(key)),
+        rng,
+    );
+    Ok((enclave, mock_signing_key))
+}
@@ -0,0 +1,134 @@
+//! Constant-time comparisons and scalar multiplication.
+//!
+//! Byte strings are compared with `subtle`, so the time taken does not tell
+//! how long a prefix of a guess was right.
+//!
+//! Arkworks multiplies points by scalars with double-and-add over the bits
+//! of the scalar from its highest set bit, so both the number of iterations
+//! and the additions performed follow the secret. [`mul`] recodes the scalar
+//! into a fixed number of odd signed 4-bit digits and performs, for every
+//! digit, four doublings and one addition of a table entry read with a masked
+//! scan of the whole table: the sequence of operations and memory accesses
+//! is the same for every scalar.
+//!
+//! That leaves the arkworks field arithmetic, whose reductions end with a
+//! subtraction of the modulus taken or skipped by a branch on the value, and
+//! the group law, which branches on the identity and on adding a point to
+//! itself. [`mul`] therefore first scales the Jacobian coordinates of the
+//! point by a random field element: the point is the same, but every
+//! intermediate value, and with it every such branch, is fresh on each call
+//! whatever the scalar.
+
+use ark_bn254::{Fq, Fr, G1Projective};
+use ark_ec::Group;
+use ark_ff::{BigInt, BigInteger, Field, Fp, PrimeField, UniformRand, Zero};
+use std::marker::PhantomData;
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq};
+
+/// Width in bits of a digit of the recoded scalar
+const WINDOW: usize = 4;
+/// Entries of the table of odd multiples `P, 3P, ..., 15P`
+const TABLE_LEN: usize = 1 << (WINDOW - 1);
+
+/// Whether `a` and `b` are equal, in time independent of their contents.
+/// Lengths are not secret.
+pub fn eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// `b` if `choice` is set, otherwise `a`
+fn select_fq(a: &Fq, b: &Fq, choice: Choice) -> Fq {
+    let mut limbs = [0u64; 4];
+    for (i, limb) in limbs.iter_mut().enumerate() {
+        *limb = u64::conditional_select(&a.0 .0[i], &b.0 .0[i], choice);
+    }
+    Fp(BigInt(limbs), PhantomData)
+}
+
+/// `b` if `choice` is set, otherwise `a`
+fn select_point(a: &G1Projective, b: &G1Projective, choice: Choice) -> G1Projective {
+    G1Projective::new_unchecked(
+        select_fq(&a.x, &b.x, choice),
+        select_fq(&a.y, &b.y, choice),
+        select_fq(&a.z, &b.z, choice),
+    )
+}
+
+/// The same point with its Jacobian coordinates `(X, Y, Z)` scaled to
+/// `(λ²X, λ³Y, λZ)` for a random non-zero `λ`
+fn rescale(point: &G1Projective) -> G1Projective {
+    let mut rng = rand::thread_rng();
+    let lambda = loop {
+        let lambda = Fq::rand(&mut rng);
+        if !lambda.is_zero() {
+            break lambda;
+        }
+    };
+    let lambda2 = lambda.square();
+    G1Projective::new_unchecked(point.x * lambda2, point.y * lambda2 * lambda, point.z * lambda)
+}
+
+/// Recode `scalar` into digits `d_i` in `{±1, ±3, ..., ±15}`, least
+/// significant first, and whether the result must be negated: the digits
+/// encode `scalar` if it is odd, otherwise the odd `n - scalar` for the odd
+/// group order `n`. The count of digits is the same for every scalar.
+fn recode(scalar: &Fr) -> (Vec<i8>, Choice) {
+    let k = scalar.into_bigint();
+    let mut negated = Fr::MODULUS;
+    negated.sub_with_borrow(&k);
+    let even = !Choice::from((k.0[0] & 1) as u8);
+    let mut limbs = [0u64; 4];
+    for (i, limb) in limbs.iter_mut().enumerate() {
+        *limb = u64::conditional_select(&k.0[i], &negated.0[i], even);
+    }
+
+    let steps = (Fr::MODULUS_BIT_SIZE as usize - 1) / WINDOW;
+    let mut digits = Vec::with_capacity(steps + 1);
+    for _ in 0..steps {
+        // d = (e mod 32) - 16 is odd, and e - d is e with its low five bits
+        // replaced by 16, so (e - d) / 16 is odd again
+        let low = (limbs[0] & 0x1f) as i8;
+        digits.push(low - 16);
+        limbs[0] = (limbs[0] & !0x1f) | 0x10;
+        for i in 0..limbs.len() {
+            let next = limbs.get(i + 1).copied().unwrap_or(0);
+            limbs[i] = (limbs[i] >> WINDOW) | (next << (64 - WINDOW));
+        }
+    }
+    // What remains is odd and below 16
+    digits.push(limbs[0] as i8);
+    (digits, even)
+}
+
+/// `scalar * point`, with the same operations and memory accesses for every
+/// scalar, on coordinates rescaled at random
+pub fn mul(point: &G1Projective, scalar: &Fr) -> G1Projective {
+    let point = rescale(point);
+
+    // Odd multiples P, 3P, ..., 15P
+    let double = point + point;
+    let mut table = [point; TABLE_LEN];
+    for i in 1..TABLE_LEN {
+        table[i] = table[i - 1] + double;
+    }
+    let lookup = |digit: i8| {
+        let sign = digit >> 7;
+        let index = (((digit ^ sign) - sign) >> 1) as u8;
+        let mut entry = table[0];
+        for (i, candidate) in table.iter().enumerate() {
+            entry = select_point(&entry, candidate, (i as u8).ct_eq(&index));
+        }
+        select_point(&entry, &-entry, Choice::from((sign & 1) as u8))
+    };
+
+    let (digits, negate) = recode(scalar);
+    let (top, rest) = digits.split_last().expect("at least one digit");
+    let mut acc = lookup(*top);
+    for digit in rest.iter().rev() {
+        for _ in 0..WINDOW {
+            acc.double_in_place();
+        }
+        acc += lookup(*digit);
+    }
+    select_point(&acc, &-acc, negate)
+}
@@ -4,7 +4,12 @@ use ark_ff::{Field, UniformRand};
 use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
 use ark_std::rand::Rng;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
+use sha2::{Digest, Sha256, Sha384};
+
+pub mod ct;
+pub mod quote;
+pub mod tls;
+pub mod tpm;
 
 /// Request from parent to enclave
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -26,12 +31,25 @@ pub struct OprfResponse {
     pub attestation: AttestationDocument,
 }
 
+/// Format of the `document` of a real attestation
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvidenceFormat {
+    /// TDX or SGX DCAP quote
+    #[default]
+    DcapQuote,
+    /// JSON [`tpm::VtpmEvidence`] of a confidential VM's vTPM
+    VtpmQuote,
+}
+
 /// Attestation document structure
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AttestationDocument {
-    /// Whether this is a mock attestation or real TDX
+    /// Whether this is a mock attestation or real evidence
     pub is_mock: bool,
-    /// The attestation data (TDX quote in real mode)
+    /// Format of `document` in real mode
+    #[serde(default)]
+    pub format: EvidenceFormat,
+    /// The attestation data (TDX or SGX quote, or vTPM evidence, in real mode)
     pub document: Vec<u8>,
     /// MRTD (Measurement of TDX module)
     pub mrtd: Option<String>,
@@ -51,13 +69,29 @@ pub fn serialize_g1(point: &G1Projective) -> Result<Vec<u8>, String> {
     Ok(bytes)
 }
 
-/// Deserialize bytes to a G1 point
+/// Deserialize bytes to a G1 point, rejecting the identity and any encoding
+/// but the canonical one
 pub fn deserialize_g1(bytes: &[u8]) -> Result<G1Projective, String> {
     let affine = G1Affine::deserialize_compressed(bytes)
-        .map_err(|e| format!("Deserialization error: {}", e))?;
+        .map_err(|e| format!("Invalid point: {}", e))?;
+    if affine.is_zero() {
+        return Err("Invalid point: identity element".to_string());
+    }
+    if serialize_g1(&affine.into_group())? != bytes {
+        return Err("Invalid point: non-canonical encoding".to_string());
+    }
     Ok(affine.into_group())
 }
 
+/// Serialize a scalar field element to bytes
+pub fn serialize_fr(scalar: &Fr) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    scalar
+        .serialize_compressed(&mut bytes)
+        .map_err(|e| format!("Serialization error: {}", e))?;
+    Ok(bytes)
+}
+
 /// Compute SHA256 hash and return hex string
 pub fn sha256_hex(data: &[u8]) -> String {
     let mut hasher = Sha256::new();
@@ -65,6 +99,24 @@ pub fn sha256_hex(data: &[u8]) -> String {
     hex::encode(hasher.finalize())
 }
 
+/// RTMR that the enclave extends with its public key at key generation
+pub const PUBLIC_KEY_RTMR: usize = 3;
+
+/// SHA-384 digest of the public key, the value extended into RTMR3
+pub fn public_key_measurement(public_key: &[u8]) -> [u8; 48] {
+    Sha384::digest(public_key).into()
+}
+
+/// Replay an RTMR extension: `RTMR' = SHA384(RTMR || digest)`
+pub fn extend_rtmr(rtmr: &[u8; 48], digest: &[u8; 48]) -> [u8; 48] {
+    Sha384::new().chain_update(rtmr).chain_update(digest).finalize().into()
+}
+
+/// Expected RTMR3 of an enclave whose only extension is its public key
+pub fn expected_public_key_rtmr(public_key: &[u8]) -> [u8; 48] {
+    extend_rtmr(&[0u8; 48], &public_key_measurement(public_key))
+}
+
 /// Get the generator of G1
 pub fn g1_generator() -> G1Projective {
     G1Projective::generator()
@@ -75,14 +127,14 @@ pub fn random_scalar<R: Rng>(rng: &mut R) -> Fr {
     Fr::rand(rng)
 }
 
-/// Compute g^scalar
+/// Compute g^scalar, in time independent of `scalar`
 pub fn scalar_mul_generator(scalar: &Fr) -> G1Projective {
-    g1_generator() * scalar
+    ct::mul(&g1_generator(), scalar)
 }
 
-/// Compute point^scalar
+/// Compute point^scalar, in time independent of `scalar`
 pub fn scalar_mul(point: &G1Projective, scalar: &Fr) -> G1Projective {
-    *point * scalar
+    ct::mul(point, scalar)
 }
 
 /// Compute the multiplicative inverse of a scalar
@@ -90,6 +142,24 @@ pub fn scalar_inverse(scalar: &Fr) -> Option<Fr> {
     scalar.inverse()
 }
 
+/// Compute the final PRF output (RFC 9497 `Finalize`).
+///
+/// Hashes `I2OSP(len(input), 2) || input || I2OSP(len(element), 2) || element
+/// || "Finalize"` with SHA-256, binding the unblinded element to the input.
+pub fn finalize(input: &[u8], unblinded_element: &G1Projective) -> Result<[u8; 32], String> {
+    let element = serialize_g1(unblinded_element)?;
+    let input_len =
+        u16::try_from(input.len()).map_err(|_| "Input longer than 65535 bytes".to_string())?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(input_len.to_be_bytes());
+    hasher.update(input);
+    hasher.update((element.len() as u16).to_be_bytes());
+    hasher.update(&element);
+    hasher.update(b"Finalize");
+    Ok(hasher.finalize().into())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -107,6 +177,36 @@ mod tests {
         assert_eq!(point, recovered);
     }
 
+    #[test]
+    fn test_invalid_g1() {
+        let bytes = serialize_g1(&scalar_mul_generator(&Fr::from(7u64))).unwrap();
+        // The identity, a trailing byte, a truncated point and the infinity
+        // flag on a point's own x are rejected
+        let mut identity = vec![0u8; 32];
+        identity[31] = 0x40;
+        assert!(deserialize_g1(&identity).is_err());
+        assert!(deserialize_g1(&[bytes.as_slice(), &[0]].concat()).is_err());
+        assert!(deserialize_g1(&bytes[1..]).is_err());
+        let mut flagged = bytes.clone();
+        flagged[31] |= 0x40;
+        assert!(deserialize_g1(&flagged).is_err());
+    }
+
+    #[test]
+    fn test_constant_time_mul() {
+        let mut rng = test_rng();
+        let point = scalar_mul_generator(&random_scalar(&mut rng));
+        let mut scalars: Vec<Fr> = (0..16).map(|_| random_scalar(&mut rng)).collect();
+        scalars.extend([0u64, 1, 2, 15, 16, 17].map(Fr::from));
+        scalars.push(-Fr::from(1u64));
+        for k in &scalars {
+            assert_eq!(scalar_mul(&point, k), point * k);
+            assert_eq!(scalar_mul_generator(k), g1_generator() * k);
+        }
+        assert!(ct::eq(b"query hash", b"query hash"));
+        assert!(!ct::eq(b"query hash", b"query hasi"));
+    }
+
     #[test]
     fn test_oprf_correctness() {
         let mut rng = test_rng();
@@ -133,4 +233,154 @@ mod tests {
         
         assert_eq!(unblinded, expected);
     }
+
+    #[test]
+    fn test_finalize() {
+        let mut rng = test_rng();
+        let element = scalar_mul_generator(&random_scalar(&mut rng));
+
+        let output = finalize(b"input", &element).unwrap();
+        assert_eq!(output, finalize(b"input", &element).unwrap());
+        assert_ne!(output, finalize(b"other", &element).unwrap());
+    }
+
+    #[test]
+    fn test_public_key_rtmr() {
+        let rtmr = expected_public_key_rtmr(b"public key");
+        let digest = public_key_measurement(b"public key");
+        assert_eq!(rtmr, extend_rtmr(&[0u8; 48], &digest));
+        assert_eq!(&rtmr[..], &Sha384::digest([[0u8; 48], digest].concat())[..]);
+        assert_ne!(rtmr, expected_public_key_rtmr(b"other key"));
+    }
+
+    #[test]
+    fn test_parse_quote() {
+        use quote::{Quote, ReportBody};
+
+        let mut cert_data = vec![0u8; quote::SGX_REPORT_LEN];
+        cert_data[128..160].fill(0xbb); // QE MRSIGNER
+        cert_data.extend_from_slice(&[0x01; 64]);
+        cert_data.extend_from_slice(&2u16.to_le_bytes());
+        cert_data.extend_from_slice(&[0xaa, 0xaa]);
+        cert_data.extend_from_slice(&quote::CERT_DATA_PCK_CHAIN.to_le_bytes());
+        cert_data.extend_from_slice(&4u32.to_le_bytes());
+        cert_data.extend_from_slice(b"PEM\0");
+
+        let build = |version: u16, tee_type: u32, body: &[u8]| {
+            let mut signature_data = [[0x02; 64], [0x03; 64]].concat();
+            if version == quote::QUOTE_VERSION {
+                signature_data.extend_from_slice(&quote::CERT_DATA_QE_REPORT.to_le_bytes());
+                signature_data.extend_from_slice(&(cert_data.len() as u32).to_le_bytes());
+            }
+            signature_data.extend_from_slice(&cert_data);
+
+            let mut bytes = Vec::new();
+            bytes.extend_from_slice(&version.to_le_bytes());
+            bytes.extend_from_slice(&quote::ATTESTATION_KEY_TYPE_ECDSA_P256.to_le_bytes());
+            bytes.extend_from_slice(&tee_type.to_le_bytes());
+            bytes.extend_from_slice(&[0u8; 40]);
+            bytes.extend_from_slice(body);
+            bytes.extend_from_slice(&(signature_data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&signature_data);
+            bytes
+        };
+        let check_signature_data = |parsed: &Quote| {
+            assert_eq!(parsed.signature, [0x02; 64]);
+            assert_eq!(parsed.attestation_key, [0x03; 64]);
+            assert_eq!(parsed.qe_report.mr_signer, [0xbb; 32]);
+            assert_eq!(parsed.qe_report_signature, [0x01; 64]);
+            assert_eq!(parsed.qe_auth_data, [0xaa, 0xaa]);
+            assert_eq!(parsed.pck_chain, b"PEM\0");
+        };
+
+        // TDX, version 4
+        let mut body = vec![0u8; 584];
+        body[136..184].fill(0x11); // MRTD
+        body[520..584].copy_from_slice(&[0x42; 64]); // report data
+        let bytes = build(quote::QUOTE_VERSION, quote::TEE_TYPE_TDX, &body);
+        let parsed = Quote::parse(&bytes).unwrap();
+        let ReportBody::Td(report) = &parsed.body else { panic!("expected a TD report") };
+        assert_eq!(report.mr_td, [0x11; 48]);
+        assert_eq!(report.report_data, [0x42; 64]);
+        assert_eq!(parsed.signed_data, bytes[..632]);
+        check_signature_data(&parsed);
+
+        assert!(Quote::parse(&bytes[..bytes.len() - 1]).is_err());
+        let mut unknown_tee = bytes.clone();
+        unknown_tee[4] = 0x42;
+        assert!(Quote::parse(&unknown_tee).is_err());
+
+        // SGX, version 3
+        let mut body = vec![0u8; quote::SGX_REPORT_LEN];
+        body[48] = 0x02; // DEBUG attribute
+        body[64..96].fill(0x33); // MRENCLAVE
+        body[320..384].copy_from_slice(&[0x42; 64]); // report data
+        let bytes = build(quote::QUOTE_VERSION_SGX_V3, quote::TEE_TYPE_SGX, &body);
+        let parsed = Quote::parse(&bytes).unwrap();
+        let ReportBody::Enclave(report) = &parsed.body else { panic!("expected an SGX report") };
+        assert_eq!(report.mr_enclave, [0x33; 32]);
+        assert_eq!(report.report_data, [0x42; 64]);
+        assert!(report.is_debug());
+        assert_eq!(parsed.signed_data, bytes[..432]);
+        check_signature_data(&parsed);
+
+        // A version 3 quote is never a TDX quote
+        let bytes = build(quote::QUOTE_VERSION_SGX_V3, quote::TEE_TYPE_TDX, &body);
+        assert!(Quote::parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_parse_tpm_quote() {
+        use tpm::{PcrSelection, TpmQuote, TpmSignature};
+
+        let pcrs = vec![vec![0x01; 32], vec![0x02; 32]];
+        let digest = tpm::pcr_digest(&pcrs);
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&tpm::TPM_GENERATED_VALUE.to_be_bytes());
+        bytes.extend_from_slice(&tpm::TPM_ST_ATTEST_QUOTE.to_be_bytes());
+        bytes.extend_from_slice(&[0, 2, 0xaa, 0xbb]); // qualified signer
+        bytes.extend_from_slice(&[0, 3, 1, 2, 3]); // extra data
+        bytes.extend_from_slice(&1000u64.to_be_bytes()); // clock
+        bytes.extend_from_slice(&[0, 0, 0, 4, 0, 0, 0, 5, 1]); // resets, restarts, safe
+        bytes.extend_from_slice(&7u64.to_be_bytes()); // firmware version
+        bytes.extend_from_slice(&1u32.to_be_bytes()); // one bank
+        bytes.extend_from_slice(&tpm::TPM_ALG_SHA256.to_be_bytes());
+        bytes.extend_from_slice(&[3, 0b0000_0001, 0, 0b1000_0000]); // PCR0 and PCR23
+        bytes.extend_from_slice(&[0, 32]);
+        bytes.extend_from_slice(&digest);
+
+        let quote = TpmQuote::parse(&bytes).unwrap();
+        assert_eq!(quote.qualified_signer, [0xaa, 0xbb]);
+        assert_eq!(quote.extra_data, [1, 2, 3]);
+        assert_eq!((quote.clock, quote.reset_count, quote.restart_count), (1000, 4, 5));
+        assert!(quote.safe);
+        assert_eq!(quote.firmware_version, 7);
+        assert_eq!(
+            quote.pcr_selection,
+            [PcrSelection { hash: tpm::TPM_ALG_SHA256, pcrs: vec![0, 23] }]
+        );
+        assert_eq!(quote.pcr_digest, digest);
+
+        assert!(TpmQuote::parse(&bytes[..bytes.len() - 1]).is_err());
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(TpmQuote::parse(&trailing).is_err());
+        let mut certify = bytes.clone();
+        certify[5] = 0x17; // TPM_ST_ATTEST_CERTIFY
+        assert!(TpmQuote::parse(&certify).is_err());
+
+        let mut signature = vec![0x00, 0x18, 0x00, 0x0b];
+        signature.extend_from_slice(&[0, 2, 0x11, 0x22, 0, 1, 0x33]);
+        assert_eq!(
+            TpmSignature::parse(&signature).unwrap(),
+            TpmSignature::Ecdsa { hash: tpm::TPM_ALG_SHA256, r: vec![0x11, 0x22], s: vec![0x33] }
+        );
+        let rsa = [0x00, 0x14, 0x00, 0x0b, 0, 2, 0x44, 0x55];
+        assert_eq!(
+            TpmSignature::parse(&rsa).unwrap(),
+            TpmSignature::RsaSsa { hash: tpm::TPM_ALG_SHA256, signature: vec![0x44, 0x55] }
+        );
+        assert!(TpmSignature::parse(&rsa[..7]).is_err());
+        assert!(TpmSignature::parse(&[0x00, 0x16, 0x00, 0x0b]).is_err()); // RSAPSS
+    }
 }
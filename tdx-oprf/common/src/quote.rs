@@ -0,0 +1,246 @@
+//! DCAP quote layout, as produced by the TD or SGX Quoting Enclave.
+//!
+//! ```text
+//! header (48) || report body || signature data length (4) || signature data
+//! ```
+//!
+//! The report body is a TD report (584 bytes) in TDX quotes and an SGX
+//! enclave report (384 bytes) in SGX quotes. The signature data holds the
+//! ECDSA-P256 quote signature, the attestation key, and the QE certification
+//! data: the QE report, its signature by the PCK key, the QE authentication
+//! data and the PCK certificate chain. Version 4 quotes wrap the
+//! certification data in a QE report certification data entry; version 3
+//! (SGX only) inlines it. All integers are little-endian.
+
+/// Quote format version of TDX quotes and current SGX quotes
+pub const QUOTE_VERSION: u16 = 4;
+/// Quote format version of SGX quotes from older Quoting Enclaves
+pub const QUOTE_VERSION_SGX_V3: u16 = 3;
+/// Attestation key type: ECDSA-256 with P-256
+pub const ATTESTATION_KEY_TYPE_ECDSA_P256: u16 = 2;
+/// TEE type of a TDX quote
+pub const TEE_TYPE_TDX: u32 = 0x81;
+/// TEE type of an SGX quote (reserved and zero in version 3)
+pub const TEE_TYPE_SGX: u32 = 0x00;
+/// Certification data type: QE report certification data
+pub const CERT_DATA_QE_REPORT: u16 = 6;
+/// Certification data type: concatenated PEM PCK certificate chain
+pub const CERT_DATA_PCK_CHAIN: u16 = 5;
+
+/// Length of an SGX report body, e.g. the QE report
+pub const SGX_REPORT_LEN: usize = 384;
+/// Length of an MRTD or RTMR value (SHA-384)
+pub const MEASUREMENT_LEN: usize = 48;
+
+/// Little-endian reader over a byte slice that fails on truncation
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize, what: &str) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err(format!("Quote truncated in {}", what));
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self, what: &str) -> Result<[u8; N], String> {
+        Ok(self.take(N, what)?.try_into().expect("took N bytes"))
+    }
+
+    fn u16(&mut self, what: &str) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.array(what)?))
+    }
+
+    fn u32(&mut self, what: &str) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array(what)?))
+    }
+}
+
+/// TD report body: the measurements of the TD and the TDX module
+#[derive(Debug, Clone)]
+pub struct TdReport {
+    pub tee_tcb_svn: [u8; 16],
+    pub mr_seam: [u8; MEASUREMENT_LEN],
+    pub mr_signer_seam: [u8; MEASUREMENT_LEN],
+    pub seam_attributes: [u8; 8],
+    pub td_attributes: [u8; 8],
+    pub xfam: [u8; 8],
+    pub mr_td: [u8; MEASUREMENT_LEN],
+    pub mr_config_id: [u8; MEASUREMENT_LEN],
+    pub mr_owner: [u8; MEASUREMENT_LEN],
+    pub mr_owner_config: [u8; MEASUREMENT_LEN],
+    pub rtmrs: [[u8; MEASUREMENT_LEN]; 4],
+    pub report_data: [u8; 64],
+}
+
+impl TdReport {
+    fn read(reader: &mut Reader) -> Result<Self, String> {
+        let what = "TD report";
+        Ok(Self {
+            tee_tcb_svn: reader.array(what)?,
+            mr_seam: reader.array(what)?,
+            mr_signer_seam: reader.array(what)?,
+            seam_attributes: reader.array(what)?,
+            td_attributes: reader.array(what)?,
+            xfam: reader.array(what)?,
+            mr_td: reader.array(what)?,
+            mr_config_id: reader.array(what)?,
+            mr_owner: reader.array(what)?,
+            mr_owner_config: reader.array(what)?,
+            rtmrs: [
+                reader.array(what)?,
+                reader.array(what)?,
+                reader.array(what)?,
+                reader.array(what)?,
+            ],
+            report_data: reader.array(what)?,
+        })
+    }
+}
+
+/// SGX report body, here the report of the Quoting Enclave
+#[derive(Debug, Clone)]
+pub struct SgxReport {
+    /// Raw report, the message signed by the PCK key
+    pub raw: [u8; SGX_REPORT_LEN],
+    pub cpu_svn: [u8; 16],
+    pub misc_select: u32,
+    pub attributes: [u8; 16],
+    pub mr_enclave: [u8; 32],
+    pub mr_signer: [u8; 32],
+    pub isv_prod_id: u16,
+    pub isv_svn: u16,
+    pub report_data: [u8; 64],
+}
+
+impl SgxReport {
+    /// Whether the enclave runs in debug mode (ATTRIBUTES.DEBUG)
+    pub fn is_debug(&self) -> bool {
+        self.attributes[0] & 0x02 != 0
+    }
+
+    fn parse(raw: [u8; SGX_REPORT_LEN]) -> Self {
+        fn field<const N: usize>(raw: &[u8], offset: usize) -> [u8; N] {
+            raw[offset..offset + N].try_into().expect("field within the report")
+        }
+        Self {
+            cpu_svn: field(&raw, 0),
+            misc_select: u32::from_le_bytes(field(&raw, 16)),
+            attributes: field(&raw, 48),
+            mr_enclave: field(&raw, 64),
+            mr_signer: field(&raw, 128),
+            isv_prod_id: u16::from_le_bytes(field(&raw, 256)),
+            isv_svn: u16::from_le_bytes(field(&raw, 258)),
+            report_data: field(&raw, 320),
+            raw,
+        }
+    }
+}
+
+/// Report body of a quote
+#[derive(Debug, Clone)]
+pub enum ReportBody {
+    /// TD report of a TDX quote
+    Td(TdReport),
+    /// Enclave report of an SGX quote
+    Enclave(SgxReport),
+}
+
+/// A parsed DCAP quote
+#[derive(Debug, Clone)]
+pub struct Quote {
+    pub version: u16,
+    pub attestation_key_type: u16,
+    pub tee_type: u32,
+    pub qe_vendor_id: [u8; 16],
+    pub body: ReportBody,
+    /// Header and report body, the message of the quote signature
+    pub signed_data: Vec<u8>,
+    /// Quote signature `r || s`
+    pub signature: [u8; 64],
+    /// Attestation public key `x || y`
+    pub attestation_key: [u8; 64],
+    pub qe_report: SgxReport,
+    /// QE report signature `r || s` by the PCK key
+    pub qe_report_signature: [u8; 64],
+    pub qe_auth_data: Vec<u8>,
+    /// PEM PCK certificate chain, leaf first
+    pub pck_chain: Vec<u8>,
+}
+
+impl Quote {
+    /// Parse a version 4 TDX or version 3/4 SGX quote with an ECDSA-P256
+    /// attestation key
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { data: bytes };
+        let version = reader.u16("header")?;
+        let attestation_key_type = reader.u16("header")?;
+        let tee_type = reader.u32("header")?;
+        match (version, tee_type) {
+            (QUOTE_VERSION, TEE_TYPE_TDX | TEE_TYPE_SGX)
+            | (QUOTE_VERSION_SGX_V3, TEE_TYPE_SGX) => {}
+            (QUOTE_VERSION | QUOTE_VERSION_SGX_V3, _) => {
+                return Err(format!("Unsupported TEE type {:#x}", tee_type));
+            }
+            _ => return Err(format!("Unsupported quote version {}", version)),
+        }
+        if attestation_key_type != ATTESTATION_KEY_TYPE_ECDSA_P256 {
+            return Err(format!("Unsupported attestation key type {}", attestation_key_type));
+        }
+        reader.take(4, "header")?;
+        let qe_vendor_id = reader.array("header")?;
+        reader.take(20, "header")?;
+        let body = if tee_type == TEE_TYPE_TDX {
+            ReportBody::Td(TdReport::read(&mut reader)?)
+        } else {
+            ReportBody::Enclave(SgxReport::parse(reader.array("enclave report")?))
+        };
+        let signed_data = bytes[..bytes.len() - reader.data.len()].to_vec();
+
+        let signature_len = reader.u32("signature data")? as usize;
+        let mut signature_data = Reader { data: reader.take(signature_len, "signature data")? };
+        let signature = signature_data.array("quote signature")?;
+        let attestation_key = signature_data.array("attestation key")?;
+
+        let mut cert_data = if version == QUOTE_VERSION {
+            let cert_type = signature_data.u16("certification data")?;
+            if cert_type != CERT_DATA_QE_REPORT {
+                return Err(format!("Unsupported certification data type {}", cert_type));
+            }
+            let cert_len = signature_data.u32("certification data")? as usize;
+            Reader { data: signature_data.take(cert_len, "certification data")? }
+        } else {
+            signature_data
+        };
+        let qe_report = SgxReport::parse(cert_data.array("QE report")?);
+        let qe_report_signature = cert_data.array("QE report signature")?;
+        let auth_len = cert_data.u16("QE authentication data")? as usize;
+        let qe_auth_data = cert_data.take(auth_len, "QE authentication data")?.to_vec();
+
+        let chain_type = cert_data.u16("PCK certification data")?;
+        if chain_type != CERT_DATA_PCK_CHAIN {
+            return Err(format!("Unsupported PCK certification data type {}", chain_type));
+        }
+        let chain_len = cert_data.u32("PCK certification data")? as usize;
+        let pck_chain = cert_data.take(chain_len, "PCK certificate chain")?.to_vec();
+
+        Ok(Self {
+            version,
+            attestation_key_type,
+            tee_type,
+            qe_vendor_id,
+            body,
+            signed_data,
+            signature,
+            attestation_key,
+            qe_report,
+            qe_report_signature,
+            qe_auth_data,
+            pck_chain,
+        })
+    }
+}
@@ -0,0 +1,205 @@
+//! TLS between the parent and the enclave, with rustls over ring.
+//!
+//! The enclave presents a certificate chain and private key read from PEM
+//! files. The parent trusts the CA certificates of a PEM file and checks
+//! the enclave's certificate against a server name, `localhost` unless
+//! configured. TLS hides the blinded queries, evaluations and attestations
+//! from the network; trust in the evaluations still comes from the
+//! attestation, whatever terminates the connection.
+
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{
+    ClientConfig, ClientConnection, ConnectionCommon, RootCertStore, ServerConfig,
+    ServerConnection, SideData, StreamOwned,
+};
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+/// Name the enclave certificate must hold unless configured
+pub const DEFAULT_SERVER_NAME: &str = "localhost";
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+/// Every certificate of the PEM file at `path`, in order
+fn load_certificates(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Failed to read certificates from {}: {}", path, e))?;
+    if certificates.is_empty() {
+        return Err(format!("No certificate in {}", path));
+    }
+    Ok(certificates)
+}
+
+/// Run the handshake of `connection` over `stream` to completion, so that a
+/// failure shows before any request is read or written
+fn handshake<C, D, S>(mut connection: C, mut stream: S) -> std::io::Result<StreamOwned<C, S>>
+where
+    C: std::ops::DerefMut<Target = ConnectionCommon<D>>,
+    D: SideData,
+    S: Read + Write,
+{
+    while connection.is_handshaking() {
+        connection.complete_io(&mut stream)?;
+    }
+    Ok(StreamOwned::new(connection, stream))
+}
+
+/// Server side of the enclave's connections
+pub struct TlsAcceptor {
+    config: Arc<ServerConfig>,
+}
+
+impl TlsAcceptor {
+    /// Present the certificate chain at `certificate` (leaf first) with the
+    /// PKCS #8, PKCS #1 or SEC1 key at `key`
+    pub fn load(certificate: &str, key: &str) -> Result<Self, String> {
+        let chain = load_certificates(certificate)?;
+        let key = PrivateKeyDer::from_pem_file(key)
+            .map_err(|e| format!("Failed to read private key from {}: {}", key, e))?;
+        let config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .and_then(|builder| builder.with_no_client_auth().with_single_cert(chain, key))
+            .map_err(|e| format!("Invalid TLS certificate or key: {}", e))?;
+        Ok(Self { config: Arc::new(config) })
+    }
+
+    /// Accept a TLS connection over `stream`
+    pub fn accept<S: Read + Write>(
+        &self,
+        stream: S,
+    ) -> std::io::Result<StreamOwned<ServerConnection, S>> {
+        let connection =
+            ServerConnection::new(Arc::clone(&self.config)).map_err(std::io::Error::other)?;
+        handshake(connection, stream)
+    }
+}
+
+/// Client side of the parent's connections to the enclave
+pub struct TlsConnector {
+    config: Arc<ClientConfig>,
+    server_name: ServerName<'static>,
+}
+
+impl TlsConnector {
+    /// Trust the CA certificates at `ca` for certificates of `server_name`
+    pub fn load(ca: &str, server_name: &str) -> Result<Self, String> {
+        let mut roots = RootCertStore::empty();
+        for certificate in load_certificates(ca)? {
+            roots
+                .add(certificate)
+                .map_err(|e| format!("Invalid CA certificate in {}: {}", ca, e))?;
+        }
+        let config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| format!("Invalid TLS configuration: {}", e))?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let server_name = ServerName::try_from(server_name.to_string())
+            .map_err(|e| format!("Invalid TLS server name {}: {}", server_name, e))?;
+        Ok(Self { config: Arc::new(config), server_name })
+    }
+
+    /// Open a TLS connection over `stream`
+    pub fn connect<S: Read + Write>(
+        &self,
+        stream: S,
+    ) -> std::io::Result<StreamOwned<ClientConnection, S>> {
+        let connection = ClientConnection::new(Arc::clone(&self.config), self.server_name.clone())
+            .map_err(std::io::Error::other)?;
+        handshake(connection, stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+    use std::path::PathBuf;
+    use std::thread::JoinHandle;
+
+    /// PEM files of a self-signed certificate for `names` and its key,
+    /// named after `test`
+    fn certificate(test: &str, names: &[&str]) -> (String, String) {
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        let certified = rcgen::generate_simple_self_signed(names).unwrap();
+        let path = |suffix: &str| -> PathBuf {
+            let name = format!("tdx-oprf-tls-{}-{}-{}", test, std::process::id(), suffix);
+            std::env::temp_dir().join(name)
+        };
+        let (cert, key) = (path("cert.pem"), path("key.pem"));
+        std::fs::write(&cert, certified.cert.pem()).unwrap();
+        std::fs::write(&key, certified.key_pair.serialize_pem()).unwrap();
+        (cert.to_string_lossy().to_string(), key.to_string_lossy().to_string())
+    }
+
+    /// Serve one TLS connection, echoing five bytes back, from a thread
+    /// listening on the returned port
+    fn echo_server(acceptor: TlsAcceptor) -> (u16, JoinHandle<std::io::Result<()>>) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept()?;
+            let mut stream = acceptor.accept(stream)?;
+            let mut message = [0u8; 5];
+            stream.read_exact(&mut message)?;
+            stream.write_all(&message)?;
+            stream.flush()
+        });
+        (port, server)
+    }
+
+    #[test]
+    fn test_tls_round_trip() {
+        let (cert, key) = certificate("round-trip", &[DEFAULT_SERVER_NAME]);
+        let (port, server) = echo_server(TlsAcceptor::load(&cert, &key).unwrap());
+
+        let connector = TlsConnector::load(&cert, DEFAULT_SERVER_NAME).unwrap();
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut stream = connector.connect(stream).unwrap();
+        stream.write_all(b"hello").unwrap();
+        let mut echo = [0u8; 5];
+        stream.read_exact(&mut echo).unwrap();
+        assert_eq!(&echo, b"hello");
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_untrusted_certificate_refused() {
+        let (cert, key) = certificate("untrusted", &[DEFAULT_SERVER_NAME]);
+        let (other, _) = certificate("untrusted-other", &[DEFAULT_SERVER_NAME]);
+        let (port, server) = echo_server(TlsAcceptor::load(&cert, &key).unwrap());
+
+        let connector = TlsConnector::load(&other, DEFAULT_SERVER_NAME).unwrap();
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let error = connector.connect(stream).err().unwrap().to_string();
+        assert!(error.starts_with("invalid peer certificate"), "{}", error);
+        assert!(server.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_other_server_name_refused() {
+        let (cert, key) = certificate("server-name", &["enclave.example"]);
+        let (port, server) = echo_server(TlsAcceptor::load(&cert, &key).unwrap());
+
+        let connector = TlsConnector::load(&cert, DEFAULT_SERVER_NAME).unwrap();
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let error = connector.connect(stream).err().unwrap().to_string();
+        assert!(error.contains("not valid for name"), "{}", error);
+        assert!(server.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_invalid_files_refused() {
+        let (cert, key) = certificate("files", &[DEFAULT_SERVER_NAME]);
+        let error = TlsAcceptor::load(&key, &key).err().unwrap();
+        assert!(error.starts_with("No certificate in"), "{}", error);
+        assert!(TlsAcceptor::load(&cert, &cert).is_err());
+        assert!(TlsAcceptor::load("/nonexistent.pem", &key).is_err());
+        assert!(TlsConnector::load(&cert, "not a name").is_err());
+    }
+}
@@ -0,0 +1,197 @@
+//! TPM 2.0 quotes from the vTPM of a confidential VM (Azure confidential
+//! VMs, GCP Confidential VMs and Confidential Space).
+//!
+//! The attestation key (AK) of the vTPM signs a `TPMS_ATTEST` structure of
+//! type `TPM_ST_ATTEST_QUOTE`: the caller's qualifying data (`extraData`),
+//! the TPM clock, the selected PCRs and the digest of their values. The AK
+//! certificate, issued by the cloud provider's vTPM CA, vouches that the key
+//! belongs to the vTPM of one of its confidential VMs. All integers are
+//! big-endian.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Magic of every structure the TPM signs
+pub const TPM_GENERATED_VALUE: u32 = 0xff54_4347;
+/// Attestation structure type of a quote
+pub const TPM_ST_ATTEST_QUOTE: u16 = 0x8018;
+/// Hash algorithm identifiers
+pub const TPM_ALG_SHA256: u16 = 0x000b;
+pub const TPM_ALG_SHA384: u16 = 0x000c;
+/// Signature scheme identifiers
+pub const TPM_ALG_RSASSA: u16 = 0x0014;
+pub const TPM_ALG_ECDSA: u16 = 0x0018;
+
+/// Number of PCRs quoted from the SHA-256 bank, PCR0-23
+pub const QUOTED_PCRS: usize = 24;
+/// Length of a SHA-256 PCR value
+pub const PCR_LEN: usize = 32;
+
+/// vTPM evidence carried in the `document` of an attestation
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VtpmEvidence {
+    /// Cloud platform that provisioned the AK, for logging
+    pub platform: String,
+    /// Marshalled `TPMS_ATTEST` of the quote
+    pub quote: Vec<u8>,
+    /// Marshalled `TPMT_SIGNATURE` over `quote`
+    pub signature: Vec<u8>,
+    /// Values of the quoted SHA-256 PCRs, in selection order
+    pub pcrs: Vec<Vec<u8>>,
+    /// DER AK certificate, read from the vTPM's NV storage
+    pub ak_certificate: Vec<u8>,
+}
+
+/// Big-endian reader over a byte slice that fails on truncation
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize, what: &str) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err(format!("TPM structure truncated in {}", what));
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self, what: &str) -> Result<[u8; N], String> {
+        Ok(self.take(N, what)?.try_into().expect("took N bytes"))
+    }
+
+    fn u8(&mut self, what: &str) -> Result<u8, String> {
+        Ok(self.take(1, what)?[0])
+    }
+
+    fn u16(&mut self, what: &str) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.array(what)?))
+    }
+
+    fn u32(&mut self, what: &str) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.array(what)?))
+    }
+
+    fn u64(&mut self, what: &str) -> Result<u64, String> {
+        Ok(u64::from_be_bytes(self.array(what)?))
+    }
+
+    /// A `TPM2B_*`: a 16-bit size followed by that many bytes
+    fn sized(&mut self, what: &str) -> Result<&'a [u8], String> {
+        let len = self.u16(what)? as usize;
+        self.take(len, what)
+    }
+
+    fn finish(&self, what: &str) -> Result<(), String> {
+        if !self.data.is_empty() {
+            return Err(format!("Trailing data after {}", what));
+        }
+        Ok(())
+    }
+}
+
+/// PCRs selected from one bank
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PcrSelection {
+    /// Hash algorithm of the bank
+    pub hash: u16,
+    /// Selected PCR indices, ascending
+    pub pcrs: Vec<usize>,
+}
+
+/// A parsed `TPMS_ATTEST` of type quote
+#[derive(Debug, Clone)]
+pub struct TpmQuote {
+    /// Qualified name of the signing key
+    pub qualified_signer: Vec<u8>,
+    /// Qualifying data supplied by the caller
+    pub extra_data: Vec<u8>,
+    /// Milliseconds the TPM has been powered on
+    pub clock: u64,
+    pub reset_count: u32,
+    pub restart_count: u32,
+    /// Whether `clock` never went backwards
+    pub safe: bool,
+    pub firmware_version: u64,
+    pub pcr_selection: Vec<PcrSelection>,
+    /// Digest of the selected PCR values, concatenated in selection order
+    pub pcr_digest: Vec<u8>,
+}
+
+impl TpmQuote {
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { data: bytes };
+        if reader.u32("magic")? != TPM_GENERATED_VALUE {
+            return Err("Not a TPM-generated structure".to_string());
+        }
+        if reader.u16("type")? != TPM_ST_ATTEST_QUOTE {
+            return Err("TPM attestation is not a quote".to_string());
+        }
+        let qualified_signer = reader.sized("qualified signer")?.to_vec();
+        let extra_data = reader.sized("extra data")?.to_vec();
+        let clock = reader.u64("clock info")?;
+        let reset_count = reader.u32("clock info")?;
+        let restart_count = reader.u32("clock info")?;
+        let safe = reader.u8("clock info")? != 0;
+        let firmware_version = reader.u64("firmware version")?;
+
+        let mut pcr_selection = Vec::new();
+        for _ in 0..reader.u32("PCR selection")? {
+            let hash = reader.u16("PCR selection")?;
+            let size = reader.u8("PCR selection")? as usize;
+            let bitmap = reader.take(size, "PCR selection")?;
+            let pcrs = (0..size * 8).filter(|i| bitmap[i / 8] & (1 << (i % 8)) != 0).collect();
+            pcr_selection.push(PcrSelection { hash, pcrs });
+        }
+        let pcr_digest = reader.sized("PCR digest")?.to_vec();
+        reader.finish("TPM quote")?;
+
+        Ok(Self {
+            qualified_signer,
+            extra_data,
+            clock,
+            reset_count,
+            restart_count,
+            safe,
+            firmware_version,
+            pcr_selection,
+            pcr_digest,
+        })
+    }
+}
+
+/// A parsed `TPMT_SIGNATURE`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TpmSignature {
+    /// RSASSA-PKCS1-v1_5 with the hash `hash`
+    RsaSsa { hash: u16, signature: Vec<u8> },
+    /// ECDSA with the hash `hash`, as big-endian `r` and `s`
+    Ecdsa { hash: u16, r: Vec<u8>, s: Vec<u8> },
+}
+
+impl TpmSignature {
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { data: bytes };
+        let what = "TPM signature";
+        let signature = match reader.u16(what)? {
+            TPM_ALG_RSASSA => TpmSignature::RsaSsa {
+                hash: reader.u16(what)?,
+                signature: reader.sized(what)?.to_vec(),
+            },
+            TPM_ALG_ECDSA => TpmSignature::Ecdsa {
+                hash: reader.u16(what)?,
+                r: reader.sized(what)?.to_vec(),
+                s: reader.sized(what)?.to_vec(),
+            },
+            scheme => return Err(format!("Unsupported TPM signature scheme {:#06x}", scheme)),
+        };
+        reader.finish(what)?;
+        Ok(signature)
+    }
+}
+
+/// PCR digest of a quote over SHA-256 PCR values
+pub fn pcr_digest(pcrs: &[Vec<u8>]) -> [u8; 32] {
+    pcrs.iter().fold(Sha256::new(), |hasher, pcr| hasher.chain_update(pcr)).finalize().into()
+}
@@ -0,0 +1,234 @@
+//! Attestation providers, selected at runtime with `--attestation`.
+//!
+//! Every provider attests the same user data, the evaluated point, so the
+//! enclave's evaluation path is the same whichever is in use:
+//!
+//! - `mock`: an unsigned document for local testing
+//! - `tdx`: a configfs-tsm report, a TDX quote or the report of another
+//!   confidential-computing guest (see [`crate::tsm`])
+//! - `sgx`: a DCAP quote through Gramine's `/dev/attestation` interface
+//! - `vtpm`: a quote from the vTPM of an Azure or GCP confidential VM
+//!
+//! The default is the provider of the build's mode, so existing deployments
+//! keep attesting as before.
+
+use crate::{tsm, vtpm};
+use tdx_oprf_common::quote::{Quote, ReportBody};
+use tdx_oprf_common::{
+    expected_public_key_rtmr, sha256_hex, AttestationDocument, EvidenceFormat, PUBLIC_KEY_RTMR,
+};
+
+/// Provider used unless `--attestation` names another
+#[cfg(all(feature = "local", not(any(feature = "tdx", feature = "sgx", feature = "vtpm"))))]
+pub const DEFAULT_PROVIDER: &str = "mock";
+#[cfg(all(feature = "sgx", not(feature = "tdx")))]
+pub const DEFAULT_PROVIDER: &str = "sgx";
+#[cfg(all(feature = "vtpm", not(any(feature = "tdx", feature = "sgx"))))]
+pub const DEFAULT_PROVIDER: &str = "vtpm";
+#[cfg(feature = "tdx")]
+pub const DEFAULT_PROVIDER: &str = "tdx";
+
+/// TDX guest driver; its measurements directory exists from Linux 6.16
+const TDX_GUEST_PATH: &str = "/sys/class/misc/tdx_guest";
+
+/// Source of attestation documents
+pub trait AttestationProvider: Send + Sync {
+    /// Name accepted by `--attestation`
+    fn name(&self) -> &'static str;
+
+    /// Attest `user_data` on behalf of the key whose serialized public key
+    /// is `public_key`
+    fn attest(&self, public_key: &[u8], user_data: &[u8]) -> Result<AttestationDocument, String>;
+
+    /// Bind the enclave's public key into the platform's measurements at
+    /// startup, where the platform has a place for it
+    fn bind_public_key(&self, _public_key: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// The provider named `name`
+pub fn select(name: &str) -> Result<Box<dyn AttestationProvider>, String> {
+    match name {
+        "mock" => Ok(Box::new(MockAttestation)),
+        "tdx" => Ok(Box::new(TsmAttestation)),
+        "sgx" => Ok(Box::new(SgxAttestation)),
+        "vtpm" => Ok(Box::new(VtpmAttestation)),
+        _ => Err(format!(
+            "Unknown attestation provider {} (expected mock, tdx, sgx or vtpm)",
+            name
+        )),
+    }
+}
+
+/// Mock attestation for local testing
+pub struct MockAttestation;
+
+impl AttestationProvider for MockAttestation {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn attest(&self, public_key: &[u8], user_data: &[u8]) -> Result<AttestationDocument, String> {
+        println!("[Enclave] Generating mock attestation (local mode)");
+
+        // Create a mock attestation for local testing
+        let mock_doc = serde_json::json!({
+            "module_id": "tdx-mock-enclave",
+            "timestamp": std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            "public_key_hash": sha256_hex(public_key),
+            "user_data_hash": sha256_hex(user_data),
+        });
+
+        // RTMR3 as the TDX backend extends it with the public key
+        let mut rtmrs = vec!["0".repeat(96); 4]; // mock
+        rtmrs[PUBLIC_KEY_RTMR] = hex::encode(expected_public_key_rtmr(public_key));
+
+        Ok(AttestationDocument {
+            is_mock: true,
+            format: EvidenceFormat::DcapQuote,
+            document: serde_json::to_vec(&mock_doc).unwrap(),
+            mrtd: Some("0".repeat(96)), // Mock MRTD
+            rtmrs: Some(rtmrs),
+            user_data: user_data.to_vec(),
+        })
+    }
+}
+
+/// Reports through the Linux configfs-tsm interface
+pub struct TsmAttestation;
+
+impl AttestationProvider for TsmAttestation {
+    fn name(&self) -> &'static str {
+        "tdx"
+    }
+
+    fn attest(&self, _public_key: &[u8], user_data: &[u8]) -> Result<AttestationDocument, String> {
+        println!("[Enclave] Generating configfs-tsm attestation");
+
+        // Hash the evaluated point to include in attestation
+        let report_data = sha256_hex(user_data);
+        let tsm::TsmReport { provider, report } = tsm::generate(report_data.as_bytes())?;
+
+        println!("[Enclave] Read {} report ({} bytes)", provider.platform(), report.len());
+
+        let measurements = provider.measurements(&report).unwrap_or_else(|e| {
+            eprintln!("[Enclave] Failed to parse {} report: {}", provider.platform(), e);
+            tsm::Measurements::default()
+        });
+
+        Ok(AttestationDocument {
+            is_mock: false,
+            format: EvidenceFormat::DcapQuote,
+            document: report,
+            mrtd: measurements.launch,
+            rtmrs: measurements.runtime,
+            user_data: user_data.to_vec(),
+        })
+    }
+
+    /// Extend RTMR3 with the SHA-384 of the public key, binding the key to
+    /// the measured runtime in every later quote
+    fn bind_public_key(&self, public_key: &[u8]) -> Result<(), String> {
+        use std::path::Path;
+
+        let tdx_guest = Path::new(TDX_GUEST_PATH);
+        if !tdx_guest.exists() {
+            println!("[Enclave] Not a TDX guest, public key not extended into an RTMR");
+            return Ok(());
+        }
+
+        let rtmr = tdx_guest.join(format!("measurements/rtmr{}:sha384", PUBLIC_KEY_RTMR));
+        let digest = tdx_oprf_common::public_key_measurement(public_key);
+        std::fs::write(&rtmr, digest)
+            .map_err(|e| format!("Failed to extend {}: {}", rtmr.display(), e))?;
+
+        println!("[Enclave] Extended RTMR{} with the public key hash", PUBLIC_KEY_RTMR);
+        Ok(())
+    }
+}
+
+/// DCAP quotes of a Gramine SGX enclave
+pub struct SgxAttestation;
+
+impl AttestationProvider for SgxAttestation {
+    fn name(&self) -> &'static str {
+        "sgx"
+    }
+
+    fn attest(&self, _public_key: &[u8], user_data: &[u8]) -> Result<AttestationDocument, String> {
+        println!("[Enclave] Generating SGX DCAP attestation");
+
+        // Gramine exposes the quoting interface as pseudo-files
+        use std::fs;
+
+        let attestation_type = fs::read_to_string("/dev/attestation/attestation_type")
+            .map_err(|e| format!("Gramine attestation interface unavailable: {}", e))?;
+        if attestation_type.trim() != "dcap" {
+            return Err(format!(
+                "Gramine attestation type is {}, expected dcap",
+                attestation_type.trim()
+            ));
+        }
+
+        // Hash the evaluated point to include in attestation
+        let report_data = sha256_hex(user_data);
+        fs::write("/dev/attestation/user_report_data", report_data.as_bytes())
+            .map_err(|e| format!("Failed to write user report data: {}", e))?;
+
+        let quote = fs::read("/dev/attestation/quote")
+            .map_err(|e| format!("Failed to read SGX quote: {}", e))?;
+
+        println!("[Enclave] Read SGX quote ({} bytes)", quote.len());
+        if let Ok(Quote { body: ReportBody::Enclave(report), .. }) = Quote::parse(&quote) {
+            println!("[Enclave] MRENCLAVE: {}", hex::encode(report.mr_enclave));
+        }
+
+        // MRTD and RTMRs are TDX measurements; the parent reads MRENCLAVE
+        // from the verified quote
+        Ok(AttestationDocument {
+            is_mock: false,
+            format: EvidenceFormat::DcapQuote,
+            document: quote,
+            mrtd: None,
+            rtmrs: None,
+            user_data: user_data.to_vec(),
+        })
+    }
+}
+
+/// Quotes from a cloud vTPM
+pub struct VtpmAttestation;
+
+impl AttestationProvider for VtpmAttestation {
+    fn name(&self) -> &'static str {
+        "vtpm"
+    }
+
+    fn attest(&self, _public_key: &[u8], user_data: &[u8]) -> Result<AttestationDocument, String> {
+        println!("[Enclave] Generating vTPM attestation");
+
+        // Hash the evaluated point to use as the quote's qualifying data
+        let report_data = sha256_hex(user_data);
+        let evidence = vtpm::generate(report_data.as_bytes())?;
+
+        println!(
+            "[Enclave] Quoted PCR0-{} with the {} vTPM AK",
+            evidence.pcrs.len() - 1,
+            evidence.platform
+        );
+
+        // The PCRs are in the evidence, whose quote covers them
+        Ok(AttestationDocument {
+            is_mock: false,
+            format: EvidenceFormat::VtpmQuote,
+            document: serde_json::to_vec(&evidence).map_err(|e| e.to_string())?,
+            mrtd: None,
+            rtmrs: None,
+            user_data: user_data.to_vec(),
+        })
+    }
+}
@@ -1,35 +1,45 @@
 use ark_bn254::Fr;
 use ark_ff::UniformRand;
+use attestation::AttestationProvider;
+use memory::LockedBox;
+use tdx_oprf_common::ct;
+use tdx_oprf_common::tls::TlsAcceptor;
 use tdx_oprf_common::{
-    deserialize_g1, scalar_mul, scalar_mul_generator, serialize_g1, sha256_hex,
-    AttestationDocument, OprfRequest, OprfResponse,
+    deserialize_g1, scalar_mul, scalar_mul_generator, serialize_g1, sha256_hex, OprfRequest,
+    OprfResponse,
 };
 use rand::rngs::OsRng;
 use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+mod attestation;
+mod memory;
+mod tsm;
+mod vtpm;
 
 #[cfg(feature = "tdx")]
 use std::os::unix::io::AsRawFd;
 
-#[cfg(all(feature = "local", not(feature = "tdx")))]
-const LOCAL_PORT: u16 = 5000;
-
-#[cfg(feature = "tdx")]
-const VSOCK_PORT: u32 = 5000;
-#[cfg(feature = "tdx")]
+/// vsock port, or local TCP port, unless configured
+const DEFAULT_PORT: u32 = 5000;
 const VSOCK_CID_ANY: u32 = 0xFFFFFFFF;
 
 /// Enclave state holding the secret key and public key
 struct EnclaveState {
-    /// Secret key k
-    secret_key: Fr,
+    /// Secret key k, on locked memory
+    secret_key: LockedBox<Fr>,
     /// Public key g^k (serialized)
     public_key_bytes: Vec<u8>,
+    /// Source of the attestation of every evaluation
+    attestation: Box<dyn AttestationProvider>,
 }
 
 impl EnclaveState {
-    fn new() -> Self {
+    fn new(attestation: Box<dyn AttestationProvider>) -> Self {
+        memory::disable_core_dumps();
         let mut rng = OsRng;
-        let secret_key = Fr::rand(&mut rng);
+        let secret_key = LockedBox::new(Fr::rand(&mut rng));
         let public_key = scalar_mul_generator(&secret_key);
         let public_key_bytes = serialize_g1(&public_key).expect("Failed to serialize public key");
 
@@ -39,13 +49,14 @@ impl EnclaveState {
         Self {
             secret_key,
             public_key_bytes,
+            attestation,
         }
     }
 
     fn evaluate(&self, request: &OprfRequest) -> Result<OprfResponse, String> {
         // Verify hash
         let computed_hash = sha256_hex(&request.blinded_query);
-        if computed_hash != request.query_hash {
+        if !ct::eq(computed_hash.as_bytes(), request.query_hash.as_bytes()) {
             return Err("Query hash mismatch".to_string());
         }
 
@@ -63,7 +74,7 @@ impl EnclaveState {
         println!("[Enclave] Computed OPRF evaluation");
 
         // Generate attestation
-        let attestation = self.generate_attestation(&evaluated_bytes)?;
+        let attestation = self.attestation.attest(&self.public_key_bytes, &evaluated_bytes)?;
 
         Ok(OprfResponse {
             evaluated_point: evaluated_bytes,
@@ -71,130 +82,25 @@ impl EnclaveState {
             attestation,
         })
     }
-
-    #[cfg(all(feature = "local", not(feature = "tdx")))]
-    fn generate_attestation(&self, user_data: &[u8]) -> Result<AttestationDocument, String> {
-        println!("[Enclave] Generating mock attestation (local mode)");
-
-        // Create a mock attestation for local testing
-        let mock_doc = serde_json::json!({
-            "module_id": "tdx-mock-enclave",
-            "timestamp": std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
-            "public_key_hash": sha256_hex(&self.public_key_bytes),
-            "user_data_hash": sha256_hex(user_data),
-        });
-
-        Ok(AttestationDocument {
-            is_mock: true,
-            document: serde_json::to_vec(&mock_doc).unwrap(),
-            mrtd: Some("0".repeat(96)), // Mock MRTD
-            rtmrs: Some(vec![
-                "0".repeat(96), // RTMR0 - mock
-                "0".repeat(96), // RTMR1 - mock
-                "0".repeat(96), // RTMR2 - mock
-                "0".repeat(96), // RTMR3 - mock
-            ]),
-            user_data: user_data.to_vec(),
-        })
-    }
-
-    #[cfg(feature = "tdx")]
-    fn generate_attestation(&self, user_data: &[u8]) -> Result<AttestationDocument, String> {
-        println!("[Enclave] Generating TDX attestation");
-
-        // Use configfs-tsm interface to generate TDX quote
-        use std::fs;
-        use std::path::Path;
-
-        let report_path = Path::new("/sys/kernel/config/tsm/report/tdx0");
-        
-        // Create report directory if it doesn't exist
-        if !report_path.exists() {
-            fs::create_dir_all(report_path)
-                .map_err(|e| format!("Failed to create report directory: {}", e))?;
-        }
-
-        let inblob_path = report_path.join("inblob");
-        let outblob_path = report_path.join("outblob");
-
-        // Hash the evaluated point to include in attestation
-        let report_data = sha256_hex(user_data);
-        
-        // Write report data to inblob
-        fs::write(&inblob_path, report_data.as_bytes())
-            .map_err(|e| format!("Failed to write to inblob: {}", e))?;
-
-        println!("[Enclave] Wrote report data to configfs-tsm");
-
-        // Read the TDX quote from outblob
-        let quote = fs::read(&outblob_path)
-            .map_err(|e| format!("Failed to read quote from outblob: {}", e))?;
-
-        println!("[Enclave] Read TDX quote ({} bytes)", quote.len());
-
-        // Extract MRTD and RTMR values from the quote
-        // TDX quote format includes these at specific offsets
-        let (mrtd, rtmrs) = extract_tdx_measurements(&quote);
-
-        Ok(AttestationDocument {
-            is_mock: false,
-            document: quote,
-            mrtd,
-            rtmrs,
-            user_data: user_data.to_vec(),
-        })
-    }
-}
-
-#[cfg(feature = "tdx")]
-fn extract_tdx_measurements(quote: &[u8]) -> (Option<String>, Option<Vec<String>>) {
-    // TDX quote structure (simplified):
-    // The quote contains a TD Report which includes:
-    // - MRTD at offset 0x20 (48 bytes)
-    // - RTMR0-3 at offsets starting from 0x60 (48 bytes each)
-    
-    if quote.len() < 432 {
-        return (None, None);
-    }
-
-    // Extract MRTD (48 bytes at offset 32)
-    let mrtd = if quote.len() >= 80 {
-        Some(hex::encode(&quote[32..80]))
-    } else {
-        None
-    };
-
-    // Extract RTMRs (4 registers, 48 bytes each, starting at offset 96)
-    let rtmrs = if quote.len() >= 288 {
-        Some(vec![
-            hex::encode(&quote[96..144]),   // RTMR0
-            hex::encode(&quote[144..192]),  // RTMR1
-            hex::encode(&quote[192..240]),  // RTMR2
-            hex::encode(&quote[240..288]),  // RTMR3
-        ])
-    } else {
-        None
-    };
-
-    (mrtd, rtmrs)
 }
 
-#[cfg(all(feature = "local", not(feature = "tdx")))]
-fn run_server(state: EnclaveState) -> std::io::Result<()> {
+#[cfg(all(any(feature = "local", feature = "sgx", feature = "vtpm"), not(feature = "tdx")))]
+fn run_server(
+    state: Arc<EnclaveState>,
+    address: ListenAddress,
+    tls: Option<TlsAcceptor>,
+) -> std::io::Result<()> {
+    use std::io::{Error, ErrorKind};
     use std::net::TcpListener;
 
-    let listener = TcpListener::bind(format!("127.0.0.1:{}", LOCAL_PORT))?;
-    println!("[Enclave] Local server listening on 127.0.0.1:{}", LOCAL_PORT);
+    let port = u16::try_from(address.port)
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "Port out of range"))?;
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("[Enclave] Local server listening on 127.0.0.1:{}", port);
 
     for stream in listener.incoming() {
         match stream {
-            Ok(mut stream) => {
-                println!("[Enclave] Connection received");
-                handle_connection(&mut stream, &state);
-            }
+            Ok(stream) => serve(stream, &state, tls.as_ref()),
             Err(e) => eprintln!("[Enclave] Connection error: {}", e),
         }
     }
@@ -202,7 +108,11 @@ fn run_server(state: EnclaveState) -> std::io::Result<()> {
 }
 
 #[cfg(feature = "tdx")]
-fn run_server(state: EnclaveState) -> std::io::Result<()> {
+fn run_server(
+    state: Arc<EnclaveState>,
+    address: ListenAddress,
+    tls: Option<TlsAcceptor>,
+) -> std::io::Result<()> {
     use nix::sys::socket::{
         accept, bind, listen, socket, AddressFamily, SockFlag, SockType, VsockAddr,
     };
@@ -216,27 +126,41 @@ fn run_server(state: EnclaveState) -> std::io::Result<()> {
     )
     .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-    let addr = VsockAddr::new(VSOCK_CID_ANY, VSOCK_PORT);
+    let addr = VsockAddr::new(address.cid, address.port);
     bind(sock_fd.as_raw_fd(), &addr)
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
     listen(&sock_fd, 128)
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-    println!("[Enclave] TDX vsock server listening on port {}", VSOCK_PORT);
+    println!(
+        "[Enclave] TDX vsock server listening on CID {:#x}, port {}",
+        address.cid, address.port
+    );
 
     loop {
         match accept(sock_fd.as_raw_fd()) {
             Ok(client_fd) => {
-                println!("[Enclave] Connection received");
-                let mut stream = unsafe { std::net::TcpStream::from_raw_fd(client_fd) };
-                handle_connection(&mut stream, &state);
+                let stream = unsafe { std::net::TcpStream::from_raw_fd(client_fd) };
+                serve(stream, &state, tls.as_ref());
             }
             Err(e) => eprintln!("[Enclave] Accept error: {}", e),
         }
     }
 }
 
+/// Serve a connection of the listener, over TLS when configured
+fn serve<S: Read + Write>(mut stream: S, state: &EnclaveState, tls: Option<&TlsAcceptor>) {
+    println!("[Enclave] Connection received");
+    match tls {
+        Some(tls) => match tls.accept(stream) {
+            Ok(mut stream) => handle_connection(&mut stream, state),
+            Err(e) => eprintln!("[Enclave] TLS handshake failed: {}", e),
+        },
+        None => handle_connection(&mut stream, state),
+    }
+}
+
 fn handle_connection<S: Read + Write>(stream: &mut S, state: &EnclaveState) {
     // Read length-prefixed message
     let mut len_buf = [0u8; 4];
@@ -293,15 +217,139 @@ fn handle_connection<S: Read + Write>(stream: &mut S, state: &EnclaveState) {
     println!("[Enclave] Response sent successfully");
 }
 
+/// Serve on the Unix domain socket at `path` from a thread of its own,
+/// replacing a socket left behind by an earlier run
+fn spawn_uds_server(state: Arc<EnclaveState>, path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixListener;
+
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    println!("[Enclave] Unix socket server listening on {}", path.display());
+    std::thread::Builder::new()
+        .name("uds-server".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(mut stream) => {
+                        println!("[Enclave] Connection received on the Unix socket");
+                        handle_connection(&mut stream, &state);
+                    }
+                    Err(e) => eprintln!("[Enclave] Connection error: {}", e),
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// Where the server listens
+struct ListenAddress {
+    /// vsock CID to bind, any by default; unused outside TDX mode
+    #[cfg_attr(not(feature = "tdx"), allow(dead_code))]
+    cid: u32,
+    /// vsock port, or TCP port on 127.0.0.1 outside TDX mode
+    port: u32,
+    /// Unix domain socket served alongside `port`
+    uds: Option<PathBuf>,
+}
+
+/// Enclave settings from the command line
+struct Config {
+    address: ListenAddress,
+    /// Name of the attestation provider (see [`attestation::select`])
+    attestation: String,
+    /// PEM certificate chain and private key of the listener's TLS
+    tls: Option<(String, String)>,
+}
+
+/// Parse the command line:
+/// `[--cid <n>] [--port <n>] [--uds <path>] [--attestation <mock|tdx|sgx|vtpm>]
+/// [--tls-cert <file> --tls-key <file>]`
+///
+/// The `ENCLAVE_CID` and `ENCLAVE_PORT` environment variables set the listen
+/// address where the flags do not. With a TLS certificate and key, the
+/// TCP or vsock listener only speaks TLS; the Unix socket stays plain.
+fn parse_args() -> Result<Config, String> {
+    let mut address = ListenAddress {
+        cid: VSOCK_CID_ANY,
+        port: DEFAULT_PORT,
+        uds: None,
+    };
+    let mut attestation = attestation::DEFAULT_PROVIDER.to_string();
+    let (mut tls_cert, mut tls_key) = (None, None);
+    for (name, field) in [("ENCLAVE_CID", &mut address.cid), ("ENCLAVE_PORT", &mut address.port)] {
+        if let Ok(value) = std::env::var(name) {
+            *field = value.parse().map_err(|e| format!("Invalid {}: {}", name, e))?;
+        }
+    }
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or(format!("{} requires a value", arg))?;
+        let number = || {
+            value
+                .parse()
+                .map_err(|e| format!("Invalid value for {}: {}", arg, e))
+        };
+        match arg.as_str() {
+            "--cid" => address.cid = number()?,
+            "--port" => address.port = number()?,
+            "--uds" => address.uds = Some(PathBuf::from(value)),
+            "--attestation" => attestation = value,
+            "--tls-cert" => tls_cert = Some(value),
+            "--tls-key" => tls_key = Some(value),
+            _ => return Err(format!("Unknown argument {}", arg)),
+        }
+    }
+    let tls = match (tls_cert, tls_key) {
+        (Some(cert), Some(key)) => Some((cert, key)),
+        (None, None) => None,
+        _ => return Err("--tls-cert and --tls-key must be given together".to_string()),
+    };
+    Ok(Config {
+        address,
+        attestation,
+        tls,
+    })
+}
+
 fn main() -> std::io::Result<()> {
     println!("[Enclave] Starting TDX OPRF Enclave...");
 
-    #[cfg(all(feature = "local", not(feature = "tdx")))]
+    let config = parse_args().map_err(std::io::Error::other)?;
+    let provider = attestation::select(&config.attestation).map_err(std::io::Error::other)?;
+    let tls = match &config.tls {
+        Some((cert, key)) => {
+            let tls = TlsAcceptor::load(cert, key).map_err(std::io::Error::other)?;
+            println!("[Enclave] Serving TLS with the certificate {}", cert);
+            Some(tls)
+        }
+        None => None,
+    };
+    let address = config.address;
+
+    #[cfg(all(feature = "local", not(any(feature = "tdx", feature = "sgx", feature = "vtpm"))))]
     println!("[Enclave] Running in LOCAL mode");
 
+    #[cfg(all(feature = "sgx", not(feature = "tdx")))]
+    println!("[Enclave] Running in SGX mode");
+
+    #[cfg(all(feature = "vtpm", not(any(feature = "tdx", feature = "sgx"))))]
+    println!("[Enclave] Running in vTPM mode");
+
     #[cfg(feature = "tdx")]
     println!("[Enclave] Running in TDX mode");
 
-    let state = EnclaveState::new();
-    run_server(state)
+    println!("[Enclave] Attestation provider: {}", provider.name());
+    let state = Arc::new(EnclaveState::new(provider));
+    state
+        .attestation
+        .bind_public_key(&state.public_key_bytes)
+        .map_err(std::io::Error::other)?;
+
+    if let Some(path) = &address.uds {
+        spawn_uds_server(Arc::clone(&state), path)?;
+    }
+    run_server(state, address, tls)
 }
@@ -0,0 +1,109 @@
+//! Secret memory kept out of swap, core dumps and other processes.
+//!
+//! Secret keys live in a [`LockedBox`]: a page-aligned allocation of their
+//! own, locked into RAM with `mlock` so it is never written to swap, and
+//! marked `MADV_DONTDUMP` so it is left out of core dumps. On drop the value
+//! is wiped before its pages are unlocked and freed. [`disable_core_dumps`]
+//! sets the core file size limit to zero and clears the process's dumpable
+//! flag, which also keeps processes of the same user from attaching with
+//! ptrace or reading `/proc/<pid>/mem`.
+//!
+//! A TD's memory is encrypted against the host; these matter in local mode,
+//! where the enclave is an ordinary host process, and against other
+//! processes inside the guest.
+//! Failures, such as a `RLIMIT_MEMLOCK` too low to lock another page, are
+//! logged and the key is used anyway.
+
+use nix::sys::mman::{madvise, mlock, munlock, MmapAdvise};
+use nix::sys::prctl;
+use nix::sys::resource::{setrlimit, Resource};
+use nix::unistd::{sysconf, SysconfVar};
+use std::alloc::{alloc, dealloc, handle_alloc_error, Layout};
+use std::ffi::c_void;
+use std::ops::Deref;
+use std::ptr::NonNull;
+use zeroize::Zeroize;
+
+/// Page size assumed if the system does not report one
+const FALLBACK_PAGE_SIZE: usize = 4096;
+
+fn page_size() -> usize {
+    match sysconf(SysconfVar::PAGE_SIZE) {
+        Ok(Some(size)) if size > 0 => size as usize,
+        _ => FALLBACK_PAGE_SIZE,
+    }
+}
+
+/// Stop the process from writing core dumps and from being inspected by
+/// other processes of its user
+pub(crate) fn disable_core_dumps() {
+    if let Err(e) = setrlimit(Resource::RLIMIT_CORE, 0, 0) {
+        eprintln!("[Enclave] Warning: failed to disable core files: {}", e);
+    }
+    if let Err(e) = prctl::set_dumpable(false) {
+        eprintln!("[Enclave] Warning: failed to clear the dumpable flag: {}", e);
+    }
+}
+
+/// A secret on pages of its own, locked into memory and excluded from core
+/// dumps, wiped on drop. The value passed to [`LockedBox::new`] is wiped
+/// too, but copies the compiler made of it before are out of reach.
+pub(crate) struct LockedBox<T: Copy + Zeroize> {
+    ptr: NonNull<T>,
+    layout: Layout,
+}
+
+// The box owns its value like a `Box<T>`
+unsafe impl<T: Copy + Zeroize + Send> Send for LockedBox<T> {}
+unsafe impl<T: Copy + Zeroize + Sync> Sync for LockedBox<T> {}
+
+impl<T: Copy + Zeroize> LockedBox<T> {
+    pub(crate) fn new(mut value: T) -> Self {
+        let page = page_size();
+        let size = std::mem::size_of::<T>().max(1).next_multiple_of(page);
+        let layout = Layout::from_size_align(size, page.max(std::mem::align_of::<T>()))
+            .expect("page-aligned layout");
+        // Safety: the layout has a non-zero size
+        let ptr = NonNull::new(unsafe { alloc(layout) }.cast::<T>())
+            .unwrap_or_else(|| handle_alloc_error(layout));
+
+        // Lock the pages before the secret is written to them
+        let addr = ptr.as_ptr().cast::<c_void>();
+        // Safety: the range is exactly the allocation made above
+        if let Err(e) = unsafe { mlock(addr, size) } {
+            eprintln!("[Enclave] Warning: failed to lock secret key memory: {}", e);
+        }
+        // Safety: as above, and the advice only affects core dumps
+        if let Err(e) = unsafe { madvise(addr, size, MmapAdvise::MADV_DONTDUMP) } {
+            eprintln!("[Enclave] Warning: failed to exclude secret key from core dumps: {}", e);
+        }
+
+        // Safety: the allocation is large enough and aligned for a `T`
+        unsafe { ptr.as_ptr().write(value) };
+        value.zeroize();
+        Self { ptr, layout }
+    }
+}
+
+impl<T: Copy + Zeroize> Deref for LockedBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: the value was written in `new` and lives until drop
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T: Copy + Zeroize> Drop for LockedBox<T> {
+    fn drop(&mut self) {
+        // Safety: the value is live and owned by the box; the range is the
+        // allocation made in `new`, which is released exactly once here
+        unsafe {
+            self.ptr.as_mut().zeroize();
+            let addr = self.ptr.as_ptr().cast::<c_void>();
+            let _ = madvise(addr, self.layout.size(), MmapAdvise::MADV_DODUMP);
+            let _ = munlock(addr, self.layout.size());
+            dealloc(self.ptr.as_ptr().cast::<u8>(), self.layout);
+        }
+    }
+}
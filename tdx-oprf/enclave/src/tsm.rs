@@ -0,0 +1,167 @@
+//! Attestation reports through the Linux configfs-tsm interface.
+//!
+//! Creating a directory under `/sys/kernel/config/tsm/report` gives a report
+//! entry: `provider` names the confidential-computing guest driver, `inblob`
+//! takes the report data and `outblob` returns the signed report. The same
+//! interface serves TDX, SEV-SNP and Arm CCA guests; a [`TsmReportProvider`]
+//! interprets the report of its platform.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tdx_oprf_common::quote::{Quote, ReportBody};
+
+/// configfs-tsm report directory
+pub const TSM_REPORT_ROOT: &str = "/sys/kernel/config/tsm/report";
+
+/// Measurements extracted from a report, hex encoded
+#[derive(Debug, Default)]
+pub struct Measurements {
+    /// Launch measurement (MRTD on TDX, MEASUREMENT on SEV-SNP)
+    pub launch: Option<String>,
+    /// Runtime measurement registers (RTMR0-3 on TDX)
+    pub runtime: Option<Vec<String>>,
+}
+
+/// Platform-specific handling of configfs-tsm reports
+pub trait TsmReportProvider: Sync {
+    /// Value of the entry's `provider` attribute on this platform
+    fn name(&self) -> &'static str;
+
+    /// Platform name for logging
+    fn platform(&self) -> &'static str;
+
+    /// Extract the measurements from a report produced by this provider
+    fn measurements(&self, report: &[u8]) -> Result<Measurements, String>;
+}
+
+/// Intel TDX: the report is a version 4 TDX quote
+pub struct Tdx;
+
+impl TsmReportProvider for Tdx {
+    fn name(&self) -> &'static str {
+        "tdx_guest"
+    }
+
+    fn platform(&self) -> &'static str {
+        "TDX"
+    }
+
+    fn measurements(&self, report: &[u8]) -> Result<Measurements, String> {
+        let ReportBody::Td(report) = Quote::parse(report)?.body else {
+            return Err("Not a TDX quote".to_string());
+        };
+        Ok(Measurements {
+            launch: Some(hex::encode(report.mr_td)),
+            runtime: Some(report.rtmrs.iter().map(hex::encode).collect()),
+        })
+    }
+}
+
+/// AMD SEV-SNP: the report is an SNP attestation report
+pub struct SevSnp;
+
+impl SevSnp {
+    /// MEASUREMENT field of the attestation report
+    const MEASUREMENT: std::ops::Range<usize> = 0x90..0xC0;
+    /// Size of the attestation report, signature included
+    const REPORT_LEN: usize = 0x4A0;
+}
+
+impl TsmReportProvider for SevSnp {
+    fn name(&self) -> &'static str {
+        "sev_guest"
+    }
+
+    fn platform(&self) -> &'static str {
+        "SEV-SNP"
+    }
+
+    fn measurements(&self, report: &[u8]) -> Result<Measurements, String> {
+        if report.len() < Self::REPORT_LEN {
+            return Err(format!("SNP report too short ({} bytes)", report.len()));
+        }
+        Ok(Measurements {
+            launch: Some(hex::encode(&report[Self::MEASUREMENT])),
+            runtime: None,
+        })
+    }
+}
+
+/// Arm CCA: the report is a CBOR attestation token
+pub struct ArmCca;
+
+impl TsmReportProvider for ArmCca {
+    fn name(&self) -> &'static str {
+        "arm_cca_guest"
+    }
+
+    fn platform(&self) -> &'static str {
+        "Arm CCA"
+    }
+
+    fn measurements(&self, _report: &[u8]) -> Result<Measurements, String> {
+        // The realm measurements stay inside the token for the verifier
+        Ok(Measurements::default())
+    }
+}
+
+/// Providers known to the enclave
+pub static PROVIDERS: &[&dyn TsmReportProvider] = &[&Tdx, &SevSnp, &ArmCca];
+
+/// A report and the provider that produced it
+pub struct TsmReport {
+    pub provider: &'static dyn TsmReportProvider,
+    pub report: Vec<u8>,
+}
+
+/// Report entry directory, removed on drop
+struct Entry(PathBuf);
+
+impl Entry {
+    fn create() -> Result<Self, String> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let name = format!("oprf-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+        let path = Path::new(TSM_REPORT_ROOT).join(name);
+        fs::create_dir(&path).map_err(|e| format!("Failed to create report entry: {}", e))?;
+        Ok(Self(path))
+    }
+
+    fn read(&self, attribute: &str) -> Result<Vec<u8>, String> {
+        fs::read(self.0.join(attribute)).map_err(|e| format!("Failed to read {}: {}", attribute, e))
+    }
+
+    fn generation(&self) -> Result<String, String> {
+        Ok(String::from_utf8_lossy(&self.read("generation")?).trim().to_string())
+    }
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir(&self.0);
+    }
+}
+
+/// Produce a report over `report_data` with the platform's provider
+pub fn generate(report_data: &[u8]) -> Result<TsmReport, String> {
+    let entry = Entry::create()?;
+
+    let name = String::from_utf8_lossy(&entry.read("provider")?).trim().to_string();
+    let provider = PROVIDERS
+        .iter()
+        .copied()
+        .find(|provider| provider.name() == name)
+        .ok_or_else(|| format!("Unsupported configfs-tsm provider {}", name))?;
+
+    fs::write(entry.0.join("inblob"), report_data)
+        .map_err(|e| format!("Failed to write to inblob: {}", e))?;
+    let generation = entry.generation()?;
+    let report = entry.read("outblob")?;
+
+    // A concurrent write to the entry would change the report data under us
+    if entry.generation()? != generation {
+        return Err("Report entry was modified while generating the report".to_string());
+    }
+
+    Ok(TsmReport { provider, report })
+}
@@ -0,0 +1,119 @@
+//! Quotes from the vTPM of an Azure or GCP confidential VM, through
+//! `tpm2-tools`.
+//!
+//! The cloud provisions an attestation key (AK) in the vTPM and stores its
+//! certificate in an NV index; the index present tells the platform apart.
+//! Azure persists the AK at a well-known handle. GCE only stores the AK
+//! template, so the AK must be created from it and persisted at
+//! [`Platform::ak_handle`] once per boot before the enclave starts.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use tdx_oprf_common::tpm::{VtpmEvidence, PCR_LEN, QUOTED_PCRS};
+
+/// Where a cloud's vTPM keeps its attestation key
+pub struct Platform {
+    pub name: &'static str,
+    /// Persistent handle of the AK
+    pub ak_handle: u32,
+    /// NV index of the DER AK certificate
+    pub ak_certificate_index: u32,
+}
+
+/// Platforms known to the enclave
+pub static PLATFORMS: &[Platform] = &[
+    Platform { name: "Azure", ak_handle: 0x8100_0003, ak_certificate_index: 0x01c1_01d0 },
+    Platform { name: "GCE", ak_handle: 0x8100_0003, ak_certificate_index: 0x01c1_0000 },
+];
+
+/// Scratch directory for tpm2-tools outputs, removed on drop
+struct WorkDir(PathBuf);
+
+impl WorkDir {
+    fn create() -> Result<Self, String> {
+        let path = std::env::temp_dir().join(format!("oprf-vtpm-{}", std::process::id()));
+        fs::create_dir_all(&path)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        Ok(Self(path))
+    }
+
+    fn read(&self, name: &str) -> Result<Vec<u8>, String> {
+        fs::read(self.0.join(name)).map_err(|e| format!("Failed to read {}: {}", name, e))
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Run a tpm2-tools command
+fn tpm2(tool: &str, args: &[&str]) -> Result<Vec<u8>, String> {
+    let output = Command::new(tool)
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", tool, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed: {}",
+            tool,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// The platform whose AK certificate index is defined, with the certificate
+pub fn detect() -> Result<(&'static Platform, Vec<u8>), String> {
+    for platform in PLATFORMS {
+        let index = format!("{:#x}", platform.ak_certificate_index);
+        if let Ok(certificate) = tpm2("tpm2_nvread", &[&index]) {
+            return Ok((platform, certificate));
+        }
+    }
+    Err("No vTPM AK certificate found; not an Azure or GCP confidential VM?".to_string())
+}
+
+/// Quote PCR0-23 of the SHA-256 bank with `qualifying_data` (at most 64 bytes)
+pub fn generate(qualifying_data: &[u8]) -> Result<VtpmEvidence, String> {
+    let (platform, ak_certificate) = detect()?;
+    let dir = WorkDir::create()?;
+    let path = |name: &str| dir.0.join(name).to_string_lossy().into_owned();
+
+    let pcr_list = (0..QUOTED_PCRS).map(|i| i.to_string()).collect::<Vec<_>>().join(",");
+    tpm2(
+        "tpm2_quote",
+        &[
+            "--key-context",
+            &format!("{:#x}", platform.ak_handle),
+            "--pcr-list",
+            &format!("sha256:{}", pcr_list),
+            "--qualification",
+            &hex::encode(qualifying_data),
+            "--hash-algorithm",
+            "sha256",
+            "--message",
+            &path("quote.msg"),
+            "--signature",
+            &path("quote.sig"),
+            "--pcr",
+            &path("quote.pcrs"),
+            "--pcrs_format",
+            "values",
+        ],
+    )?;
+
+    let pcrs = dir.read("quote.pcrs")?;
+    if pcrs.len() != QUOTED_PCRS * PCR_LEN {
+        return Err(format!("Unexpected PCR output of {} bytes", pcrs.len()));
+    }
+    Ok(VtpmEvidence {
+        platform: platform.name.to_string(),
+        quote: dir.read("quote.msg")?,
+        signature: dir.read("quote.sig")?,
+        pcrs: pcrs.chunks(PCR_LEN).map(<[u8]>::to_vec).collect(),
+        ak_certificate,
+    })
+}
@@ -0,0 +1,13 @@
+//! BN254 G1 points as the enclave receives them: every point accepted is a
+//! valid non-identity point and the only encoding of it.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tdx_oprf_common::{deserialize_g1, serialize_g1};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(point) = deserialize_g1(data) {
+        assert_eq!(serialize_g1(&point).unwrap(), data);
+    }
+});
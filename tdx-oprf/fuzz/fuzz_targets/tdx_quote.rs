@@ -0,0 +1,13 @@
+//! DCAP quotes, TDX and SGX, as the parent reads them from the enclave's
+//! attestations: the quote signature covers the start of the quote.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tdx_oprf_common::quote::Quote;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(quote) = Quote::parse(data) {
+        assert!(data.starts_with(&quote.signed_data));
+    }
+});
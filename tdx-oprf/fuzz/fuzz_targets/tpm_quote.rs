@@ -0,0 +1,12 @@
+//! Marshalled `TPMS_ATTEST` quotes and `TPMT_SIGNATURE`s of vTPM evidence,
+//! as the parent reads them from the enclave's attestations.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tdx_oprf_common::tpm::{TpmQuote, TpmSignature};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = TpmQuote::parse(data);
+    let _ = TpmSignature::parse(data);
+});
@@ -0,0 +1,281 @@
+//! Collateral for DCAP verification: pre-provisioned for fully offline use,
+//! or fetched from Intel PCS or a PCCS and cached on disk until it expires.
+//!
+//! Collateral depends on the TEE (`tdx` or `sgx`), the platform FMSPC and
+//! the PCK CA (`platform` or `processor`) of the quote. A cache entry
+//! `<cache>/<tee>-<fmspc>-<ca>/` has the layout of a pre-provisioned
+//! collateral directory (see [`crate::dcap`]) and is fetched again once any
+//! of its documents is past its `nextUpdate`. PCK certificates come with the
+//! quote itself and are not fetched. Downloads go through `curl`.
+
+use crate::dcap::{Collateral, PckTcb};
+use crate::x509::Certificate;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tdx_oprf_common::quote::{Quote, ReportBody};
+
+/// Intel Provisioning Certification Service
+pub const PCS_URL: &str = "https://api.trustedservices.intel.com";
+/// CRL of the Intel SGX Root CA, published outside of the PCS API
+const ROOT_CA_CRL_URL: &str = "https://certificates.trustedservices.intel.com/IntelSGXRootCA.der";
+/// Seconds a download may take
+const FETCH_TIMEOUT_SECS: u32 = 30;
+
+/// Which collateral a quote needs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollateralKey {
+    /// `tdx` or `sgx`, the PCS API family
+    pub tee: &'static str,
+    pub fmspc: [u8; 6],
+    /// `platform` or `processor`, the CA that issued the PCK certificate
+    pub ca: &'static str,
+}
+
+impl CollateralKey {
+    /// Key for `quote`, whose PCK chain `pck_chain` has been verified
+    pub fn new(quote: &Quote, pck: &PckTcb, pck_chain: &[Certificate]) -> Result<Self, String> {
+        let tee = match quote.body {
+            ReportBody::Td(_) => "tdx",
+            ReportBody::Enclave(_) => "sgx",
+        };
+        // Subject common name "Intel SGX PCK Platform CA" or "... Processor CA"
+        let pck_ca = pck_chain.get(1).ok_or("PCK chain has no intermediate CA")?;
+        let names = |name: &[u8]| pck_ca.subject.windows(name.len()).any(|window| window == name);
+        let ca = if names(b"PCK Platform CA") {
+            "platform"
+        } else if names(b"PCK Processor CA") {
+            "processor"
+        } else {
+            return Err("PCK certificate is issued by an unknown CA".to_string());
+        };
+        Ok(Self { tee, fmspc: pck.fmspc, ca })
+    }
+
+    fn dir_name(&self) -> String {
+        format!("{}-{}-{}", self.tee, hex::encode(self.fmspc), self.ca)
+    }
+}
+
+/// Provides the collateral for each quote
+pub struct CollateralManager {
+    /// Pre-provisioned collateral directory, or cache directory
+    dir: PathBuf,
+    /// PCS or PCCS base URL; `None` in offline mode
+    url: Option<String>,
+}
+
+impl CollateralManager {
+    /// Use the pre-provisioned collateral in `dir` for every quote, never fetching
+    pub fn offline(dir: &str) -> Self {
+        Self { dir: PathBuf::from(dir), url: None }
+    }
+
+    /// Fetch collateral from the PCS or PCCS at `url` into the cache `dir`
+    pub fn cached(dir: &str, url: &str) -> Self {
+        Self { dir: PathBuf::from(dir), url: Some(url.trim_end_matches('/').to_string()) }
+    }
+
+    /// Collateral for `key` at unix time `now`
+    pub fn get(&self, key: &CollateralKey, now: u64) -> Result<Collateral, String> {
+        let Some(url) = &self.url else {
+            return Collateral::load(&self.dir.to_string_lossy());
+        };
+
+        let entry = self.dir.join(key.dir_name());
+        if entry.exists() {
+            match Collateral::load(&entry.to_string_lossy()) {
+                Ok(collateral) if collateral.next_update()? > now => {
+                    println!("[Parent] Using cached collateral {}", entry.display());
+                    return Ok(collateral);
+                }
+                Ok(_) => println!("[Parent] Cached collateral {} expired", entry.display()),
+                Err(e) => println!("[Parent] Ignoring cached collateral: {}", e),
+            }
+        }
+
+        println!("[Parent] Fetching {} collateral from {}", key.dir_name(), url);
+        fetch(url, key, &entry)?;
+        Collateral::load(&entry.to_string_lossy())
+    }
+}
+
+/// Download the collateral for `key` from `url` into `entry`, replacing it
+fn fetch(url: &str, key: &CollateralKey, entry: &Path) -> Result<(), String> {
+    let staging = entry.with_extension(format!("tmp{}", std::process::id()));
+    let result = fetch_into(url, key, &staging).and_then(|()| {
+        if entry.exists() {
+            fs::remove_dir_all(entry).map_err(|e| format!("Failed to replace cache: {}", e))?;
+        }
+        fs::rename(&staging, entry).map_err(|e| format!("Failed to update cache: {}", e))
+    });
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&staging);
+    }
+    result
+}
+
+fn fetch_into(url: &str, key: &CollateralKey, dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let write = |name: &str, contents: &[u8]| {
+        fs::write(dir.join(name), contents).map_err(|e| format!("Failed to write {}: {}", name, e))
+    };
+    let api = format!("{}/{}/certification/v4", url, key.tee);
+
+    let (tcb_info, headers) =
+        http_get(&format!("{}/tcb?fmspc={}", api, hex::encode(key.fmspc)), dir)?;
+    write("tcb_info.json", &tcb_info)?;
+    write("tcb_info_issuer_chain.pem", &issuer_chain(&headers, "TCB-Info-Issuer-Chain")?)?;
+
+    let (qe_identity, headers) = http_get(&format!("{}/qe/identity", api), dir)?;
+    write("qe_identity.json", &qe_identity)?;
+    let chain = issuer_chain(&headers, "SGX-Enclave-Identity-Issuer-Chain")?;
+    write("qe_identity_issuer_chain.pem", &chain)?;
+
+    // The PCK CRLs are served by the SGX API for TDX platforms too
+    let pck_crl_url = format!("{}/sgx/certification/v4/pckcrl?ca={}&encoding=der", url, key.ca);
+    write("pck_crl.der", &http_get(&pck_crl_url, dir)?.0)?;
+
+    let root_ca_crl_url = if url == PCS_URL {
+        ROOT_CA_CRL_URL.to_string()
+    } else {
+        format!("{}/sgx/certification/v4/rootcacrl", url)
+    };
+    write("root_ca_crl.der", &http_get(&root_ca_crl_url, dir)?.0)
+}
+
+/// GET `url` with curl, returning the body and the response headers
+fn http_get(url: &str, dir: &Path) -> Result<(Vec<u8>, String), String> {
+    let headers = dir.join("headers");
+    let output = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--location"])
+        .args(["--max-time", &FETCH_TIMEOUT_SECS.to_string()])
+        .arg("--dump-header")
+        .arg(&headers)
+        .arg(url)
+        .output()
+        .map_err(|e| format!("Failed to run curl: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "GET {} failed: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let header_text = fs::read_to_string(&headers).unwrap_or_default();
+    let _ = fs::remove_file(&headers);
+    Ok((output.stdout, header_text))
+}
+
+/// URL-decoded PEM chain from the response header `name`. With redirects,
+/// the headers of the last response count.
+fn issuer_chain(headers: &str, name: &str) -> Result<Vec<u8>, String> {
+    let last_response = headers.rsplit("HTTP/").next().unwrap_or(headers);
+    let value = last_response
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(header, _)| header.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+        .ok_or_else(|| format!("Response has no {} header", name))?;
+    percent_decode(value)
+}
+
+fn percent_decode(text: &str) -> Result<Vec<u8>, String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3).ok_or("Truncated percent-encoding")?;
+            let hex = std::str::from_utf8(hex).map_err(|_| "Invalid percent-encoding")?;
+            decoded.push(u8::from_str_radix(hex, 16).map_err(|_| "Invalid percent-encoding")?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dcap::tests::{temp_dir, Platform, FMSPC, NEXT_UPDATE};
+    use crate::x509::parse_iso8601;
+    use crate::x509::tests::NOW;
+
+    #[test]
+    fn test_collateral_key() {
+        let (quote, pck, chain) = Platform::up_to_date().parsed_quote();
+        let key = CollateralKey::new(&quote, &pck, &chain).unwrap();
+        assert_eq!(key, CollateralKey { tee: "tdx", fmspc: FMSPC, ca: "platform" });
+        assert_eq!(key.dir_name(), "tdx-00806f050000-platform");
+
+        let (quote, pck, chain) = Platform::new("Processor", [3; 16]).parsed_quote();
+        assert_eq!(CollateralKey::new(&quote, &pck, &chain).unwrap().ca, "processor");
+        let (quote, pck, chain) = Platform::new("Other", [3; 16]).parsed_quote();
+        assert_eq!(
+            CollateralKey::new(&quote, &pck, &chain).unwrap_err(),
+            "PCK certificate is issued by an unknown CA"
+        );
+        assert!(CollateralKey::new(&quote, &pck, &chain[..1]).is_err());
+    }
+
+    #[test]
+    fn test_issuer_chain() {
+        let headers = "HTTP/1.1 302 Found\r\nLocation: /tcb\r\nTCB-Info-Issuer-Chain: stale\r\n\r\n\
+            HTTP/1.1 200 OK\r\ntcb-info-issuer-chain: -----BEGIN%20CERTIFICATE-----%0A\r\n\r\n";
+        assert_eq!(
+            issuer_chain(headers, "TCB-Info-Issuer-Chain").unwrap(),
+            b"-----BEGIN CERTIFICATE-----\n"
+        );
+        assert_eq!(
+            issuer_chain(headers, "SGX-Enclave-Identity-Issuer-Chain").unwrap_err(),
+            "Response has no SGX-Enclave-Identity-Issuer-Chain header"
+        );
+        assert_eq!(percent_decode("a%2").unwrap_err(), "Truncated percent-encoding");
+        assert_eq!(percent_decode("a%zz").unwrap_err(), "Invalid percent-encoding");
+    }
+
+    #[test]
+    fn test_offline_collateral() {
+        let platform = Platform::up_to_date();
+        let (quote, pck, chain) = platform.parsed_quote();
+        let key = CollateralKey::new(&quote, &pck, &chain).unwrap();
+        let dir = temp_dir("collateral-offline");
+        let manager = CollateralManager::offline(&dir.to_string_lossy());
+        assert!(manager.get(&key, NOW).is_err());
+
+        // Used even once expired, the verifier refuses it then
+        platform.write_collateral(&dir);
+        let collateral = manager.get(&key, NOW).unwrap();
+        let next_update = parse_iso8601(NEXT_UPDATE).unwrap();
+        assert_eq!(collateral.next_update().unwrap(), next_update);
+        manager.get(&key, next_update + 1).unwrap();
+
+        // The CRLs expire first here
+        platform.write_crls(&dir, "250115000000Z", &[]);
+        let collateral = manager.get(&key, NOW).unwrap();
+        assert_eq!(collateral.next_update().unwrap(), NOW + 14 * 86_400);
+    }
+
+    #[test]
+    fn test_cached_collateral_until_expired() {
+        let platform = Platform::up_to_date();
+        let (quote, pck, chain) = platform.parsed_quote();
+        let key = CollateralKey::new(&quote, &pck, &chain).unwrap();
+        let dir = temp_dir("collateral-cached");
+        let entry = dir.join(key.dir_name());
+        fs::create_dir(&entry).unwrap();
+        platform.write_collateral(&entry);
+
+        // Nothing listens on the discard port, so only the cache can serve
+        let manager = CollateralManager::cached(&dir.to_string_lossy(), "http://127.0.0.1:9/");
+        manager.get(&key, NOW).unwrap();
+        let next_update = parse_iso8601(NEXT_UPDATE).unwrap();
+        assert!(manager.get(&key, next_update).is_err());
+        // A failed fetch leaves no staging directory behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,1031 @@
+//! DCAP verification of TDX and SGX quotes against pre-fetched Intel collateral.
+//!
+//! A quote is only trusted once every link from the Intel SGX root CA down
+//! to the report body checks out:
+//!
+//! 1. The PCK certificate chain embedded in the quote ends in the root CA.
+//! 2. The PCK key signed the QE report, and the QE report binds the
+//!    attestation key through `SHA-256(attestation key || QE auth data)`.
+//! 3. The attestation key signed the quote header and report body.
+//! 4. The TCB info and QE identity collateral are signed by Intel's TCB
+//!    signing key (chaining to the same root) and have not expired.
+//! 5. When the collateral holds CRLs, no certificate above is revoked: the
+//!    Root CA CRL covers the intermediate CAs and the TCB signing
+//!    certificate, the PCK CRL covers the PCK certificate.
+//! 6. The QE matches the QE identity, and the platform TCB (from the PCK
+//!    certificate) and, for TDX, the TDX module TCB (from the TD report) map
+//!    to a TCB level whose status is allowed, `UpToDate` unless configured
+//!    otherwise.
+//!
+//! Collateral is read from a directory holding the Intel PCS responses
+//! as fetched from `https://api.trustedservices.intel.com/tdx/certification/v4/`
+//! for TDX quotes, or `.../sgx/certification/v4/` for SGX quotes:
+//!
+//! ```text
+//! tcb_info.json                 GET tcb?fmspc=<fmspc>
+//! tcb_info_issuer_chain.pem     its TCB-Info-Issuer-Chain header, URL-decoded
+//! qe_identity.json              GET qe/identity
+//! qe_identity_issuer_chain.pem  its SGX-Enclave-Identity-Issuer-Chain header
+//! pck_crl.der                   GET sgx/certification/v4/pckcrl?ca=<ca>&encoding=der
+//! root_ca_crl.der               Intel SGX Root CA CRL (IntelSGXRootCA.der)
+//! ```
+//!
+//! The CRLs are optional; without them revocation is not checked. See
+//! [`crate::collateral`] for fetching and caching the collateral.
+
+use crate::collateral::{CollateralKey, CollateralManager};
+use crate::x509::{self, parse_iso8601, parse_pem_chain, verify_chain, Certificate, Crl, Der};
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tdx_oprf_common::quote::{Quote, ReportBody, TdReport};
+
+/// TCB status accepted unless configured otherwise
+pub const DEFAULT_ALLOWED_STATUS: &str = "UpToDate";
+
+/// SGX extension of PCK certificates, 1.2.840.113741.1.13.1
+const OID_SGX_EXTENSION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf8, 0x4d, 0x01, 0x0d, 0x01];
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TcbInfoResponse<'a> {
+    #[serde(borrow)]
+    tcb_info: &'a RawValue,
+    signature: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QeIdentityResponse<'a> {
+    #[serde(borrow)]
+    enclave_identity: &'a RawValue,
+    signature: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TcbInfo {
+    id: String,
+    version: u32,
+    next_update: String,
+    fmspc: String,
+    pce_id: String,
+    #[serde(default)]
+    tdx_module: Option<TdxModule>,
+    #[serde(default)]
+    tdx_module_identities: Vec<TdxModuleIdentity>,
+    tcb_levels: Vec<TcbLevel>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TdxModule {
+    mrsigner: String,
+    attributes: String,
+    attributes_mask: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TdxModuleIdentity {
+    id: String,
+    mrsigner: String,
+    attributes: String,
+    attributes_mask: String,
+    tcb_levels: Vec<IsvTcbLevel>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TcbLevel {
+    tcb: Tcb,
+    tcb_status: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Tcb {
+    sgxtcbcomponents: Vec<TcbComponent>,
+    pcesvn: u16,
+    #[serde(default)]
+    tdxtcbcomponents: Vec<TcbComponent>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TcbComponent {
+    svn: u8,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IsvTcbLevel {
+    tcb: IsvTcb,
+    tcb_status: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IsvTcb {
+    isvsvn: u16,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QeIdentity {
+    id: String,
+    version: u32,
+    next_update: String,
+    miscselect: String,
+    miscselect_mask: String,
+    attributes: String,
+    attributes_mask: String,
+    mrsigner: String,
+    isvprodid: u16,
+    tcb_levels: Vec<IsvTcbLevel>,
+}
+
+/// A signed collateral document: the exact signed JSON text, its signature
+/// and the issuer chain of the signing key
+struct Signed {
+    body: String,
+    signature: [u8; 64],
+    chain: Vec<Certificate>,
+}
+
+impl Signed {
+    fn verify(&self, root: &Certificate, now: u64, what: &str) -> Result<(), String> {
+        verify_chain(&self.chain, root, now).map_err(|e| format!("{} issuer: {}", what, e))?;
+        self.chain[0]
+            .public_key
+            .verify(self.body.as_bytes(), &self.signature)
+            .map_err(|e| format!("{} signature: {}", what, e))
+    }
+}
+
+/// TCB info, QE identity and CRL collateral
+pub struct Collateral {
+    tcb_info: TcbInfo,
+    tcb_info_signed: Signed,
+    qe_identity: QeIdentity,
+    qe_identity_signed: Signed,
+    pck_crl: Option<Crl>,
+    root_ca_crl: Option<Crl>,
+}
+
+impl Collateral {
+    /// Load the collateral files from `dir`
+    pub fn load(dir: &str) -> Result<Self, String> {
+        let read = |name: &str| {
+            let path = Path::new(dir).join(name);
+            std::fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+        };
+        let signature = |hex_signature: &str, what: &str| -> Result<[u8; 64], String> {
+            let mut signature = [0u8; 64];
+            hex::decode_to_slice(hex_signature, &mut signature)
+                .map_err(|_| format!("Invalid {} signature", what))?;
+            Ok(signature)
+        };
+
+        let tcb_info_json = read("tcb_info.json")?;
+        let response: TcbInfoResponse = serde_json::from_slice(&tcb_info_json)
+            .map_err(|e| format!("Invalid TCB info: {}", e))?;
+        let tcb_info_signed = Signed {
+            body: response.tcb_info.get().to_string(),
+            signature: signature(&response.signature, "TCB info")?,
+            chain: parse_pem_chain(&read("tcb_info_issuer_chain.pem")?)?,
+        };
+        let tcb_info = serde_json::from_str(response.tcb_info.get())
+            .map_err(|e| format!("Invalid TCB info: {}", e))?;
+
+        let qe_identity_json = read("qe_identity.json")?;
+        let response: QeIdentityResponse = serde_json::from_slice(&qe_identity_json)
+            .map_err(|e| format!("Invalid QE identity: {}", e))?;
+        let qe_identity_signed = Signed {
+            body: response.enclave_identity.get().to_string(),
+            signature: signature(&response.signature, "QE identity")?,
+            chain: parse_pem_chain(&read("qe_identity_issuer_chain.pem")?)?,
+        };
+        let qe_identity = serde_json::from_str(response.enclave_identity.get())
+            .map_err(|e| format!("Invalid QE identity: {}", e))?;
+
+        let crl = |name: &str| -> Result<Option<Crl>, String> {
+            if !Path::new(dir).join(name).exists() {
+                return Ok(None);
+            }
+            Crl::from_bytes(&read(name)?).map(Some).map_err(|e| format!("{}: {}", name, e))
+        };
+
+        Ok(Self {
+            tcb_info,
+            tcb_info_signed,
+            qe_identity,
+            qe_identity_signed,
+            pck_crl: crl("pck_crl.der")?,
+            root_ca_crl: crl("root_ca_crl.der")?,
+        })
+    }
+
+    /// Earliest `nextUpdate` of the collateral documents, in unix seconds
+    pub fn next_update(&self) -> Result<u64, String> {
+        let documents = [
+            parse_iso8601(&self.tcb_info.next_update)?,
+            parse_iso8601(&self.qe_identity.next_update)?,
+        ];
+        let crls = [&self.pck_crl, &self.root_ca_crl];
+        Ok(documents
+            .into_iter()
+            .chain(crls.into_iter().flatten().filter_map(|crl| crl.next_update))
+            .min()
+            .expect("at least one document"))
+    }
+
+    /// Check that no certificate of the PCK and collateral chains is revoked.
+    /// `pck_chain` has already been verified up to `root`.
+    fn check_revocation(
+        &self,
+        pck_chain: &[Certificate],
+        root: &Certificate,
+        now: u64,
+    ) -> Result<(), String> {
+        let (Some(pck_crl), Some(root_ca_crl)) = (&self.pck_crl, &self.root_ca_crl) else {
+            println!("[Parent] Collateral has no CRLs, certificate revocation not checked");
+            return Ok(());
+        };
+        root_ca_crl.verify(root, now).map_err(|e| format!("Root CA CRL: {}", e))?;
+        let pck_ca = pck_chain.get(1).ok_or("PCK chain has no intermediate CA")?;
+        pck_crl.verify(pck_ca, now).map_err(|e| format!("PCK CRL: {}", e))?;
+
+        let certificates = pck_chain
+            .iter()
+            .chain(&self.tcb_info_signed.chain)
+            .chain(&self.qe_identity_signed.chain)
+            .filter(|certificate| certificate.der != root.der);
+        for certificate in certificates {
+            if root_ca_crl.is_revoked(certificate) || pck_crl.is_revoked(certificate) {
+                return Err(format!(
+                    "Certificate {} is revoked",
+                    hex::encode(certificate.fingerprint())
+                ));
+            }
+        }
+        println!("[Parent] No certificate in the chains is revoked");
+        Ok(())
+    }
+}
+
+/// Platform TCB from the SGX extension of a PCK certificate
+pub struct PckTcb {
+    pub fmspc: [u8; 6],
+    pce_id: [u8; 2],
+    components: [u8; 16],
+    pce_svn: u16,
+}
+
+impl PckTcb {
+    fn from_certificate(pck: &Certificate) -> Result<Self, String> {
+        let extension = pck
+            .extension(OID_SGX_EXTENSION)
+            .ok_or("PCK certificate has no SGX extension")?;
+        let malformed = || "Malformed SGX extension".to_string();
+
+        let mut tcb = Self { fmspc: [0; 6], pce_id: [0; 2], components: [0; 16], pce_svn: 0 };
+        let mut entries = Der::new(Der::new(extension).expect(0x30)?.value);
+        while !entries.is_empty() {
+            let mut entry = Der::new(entries.expect(0x30)?.value);
+            let oid = entry.expect(0x06)?.value;
+            let value = entry.next()?;
+            match oid.strip_prefix(OID_SGX_EXTENSION) {
+                Some([0x02]) => {
+                    let mut components = Der::new(value.value);
+                    while !components.is_empty() {
+                        let mut component = Der::new(components.expect(0x30)?.value);
+                        let oid = component.expect(0x06)?.value;
+                        let value = component.next()?;
+                        match oid.strip_prefix(OID_SGX_EXTENSION) {
+                            Some([0x02, index @ 1..=16]) => {
+                                tcb.components[*index as usize - 1] = x509::der_uint(value.value)?
+                                    .try_into()
+                                    .map_err(|_| malformed())?;
+                            }
+                            Some([0x02, 17]) => {
+                                tcb.pce_svn = x509::der_uint(value.value)?
+                                    .try_into()
+                                    .map_err(|_| malformed())?;
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Some([0x03]) => tcb.pce_id = value.value.try_into().map_err(|_| malformed())?,
+                Some([0x04]) => tcb.fmspc = value.value.try_into().map_err(|_| malformed())?,
+                _ => {}
+            }
+        }
+        Ok(tcb)
+    }
+}
+
+/// `value & mask == expected & mask` over hex-encoded `expected` and `mask`
+fn masked_eq(value: &[u8], expected: &str, mask: &str) -> Result<bool, String> {
+    let expected = hex::decode(expected).map_err(|_| "Invalid hex in collateral")?;
+    let mask = hex::decode(mask).map_err(|_| "Invalid hex in collateral")?;
+    if expected.len() != value.len() || mask.len() != value.len() {
+        return Ok(false);
+    }
+    Ok(value.iter().zip(&expected).zip(&mask).all(|((v, e), m)| v & m == e & m))
+}
+
+fn hex_eq(value: &[u8], expected: &str) -> bool {
+    hex::decode(expected).is_ok_and(|expected| expected == value)
+}
+
+/// Verifies TDX and SGX quotes against a root CA and collateral
+pub struct QuoteVerifier {
+    root: Certificate,
+    collateral: CollateralManager,
+    allowed_statuses: Vec<String>,
+}
+
+impl QuoteVerifier {
+    pub fn new(
+        root: Certificate,
+        collateral: CollateralManager,
+        allowed_statuses: Vec<String>,
+    ) -> Self {
+        Self { root, collateral, allowed_statuses }
+    }
+
+    /// Verify `quote` at unix time `now` and return it parsed, with its
+    /// report body now trustworthy
+    pub fn verify(&self, quote: &[u8], now: u64) -> Result<Quote, String> {
+        let quote = Quote::parse(quote)?;
+
+        // PCK chain -> QE report -> attestation key -> quote
+        let pck_chain = parse_pem_chain(&quote.pck_chain)?;
+        verify_chain(&pck_chain, &self.root, now).map_err(|e| format!("PCK chain: {}", e))?;
+        let pck = &pck_chain[0];
+        pck.public_key
+            .verify(&quote.qe_report.raw, &quote.qe_report_signature)
+            .map_err(|e| format!("QE report signature: {}", e))?;
+
+        let binding = Sha256::new()
+            .chain_update(quote.attestation_key)
+            .chain_update(&quote.qe_auth_data)
+            .finalize();
+        let report_data = &quote.qe_report.report_data;
+        if report_data[..32] != binding[..] || report_data[32..].iter().any(|b| *b != 0) {
+            return Err("QE report does not bind the attestation key".to_string());
+        }
+        x509::PublicKey::from_bytes(&quote.attestation_key)?
+            .verify(&quote.signed_data, &quote.signature)
+            .map_err(|e| format!("Quote signature: {}", e))?;
+        println!("[Parent] DCAP quote signature chains to the Intel root CA");
+
+        // Collateral for this platform
+        let pck_tcb = PckTcb::from_certificate(pck)?;
+        let key = CollateralKey::new(&quote, &pck_tcb, &pck_chain)?;
+        let collateral = self.collateral.get(&key, now)?;
+        collateral.tcb_info_signed.verify(&self.root, now, "TCB info")?;
+        collateral.qe_identity_signed.verify(&self.root, now, "QE identity")?;
+        for (what, next_update) in [
+            ("TCB info", &collateral.tcb_info.next_update),
+            ("QE identity", &collateral.qe_identity.next_update),
+        ] {
+            if parse_iso8601(next_update)? < now {
+                return Err(format!("{} collateral expired at {}", what, next_update));
+            }
+        }
+        collateral.check_revocation(&pck_chain, &self.root, now)?;
+
+        let qe_status = self.qe_status(&collateral, &quote)?;
+        println!("[Parent] QE identity status: {}", qe_status);
+        self.check_status(qe_status, "QE")?;
+
+        let tcb_status = self.tcb_status(&collateral, &quote, &pck_tcb)?;
+        println!("[Parent] Platform TCB status: {}", tcb_status);
+        self.check_status(tcb_status, "TCB")?;
+
+        Ok(quote)
+    }
+
+    fn check_status(&self, status: &str, what: &str) -> Result<(), String> {
+        if status == "Revoked" || !self.allowed_statuses.iter().any(|allowed| allowed == status) {
+            return Err(format!("{} status {} is not allowed", what, status));
+        }
+        Ok(())
+    }
+
+    /// Match the QE report against the QE identity and return its TCB status
+    fn qe_status<'c>(&self, collateral: &'c Collateral, quote: &Quote) -> Result<&'c str, String> {
+        let identity = &collateral.qe_identity;
+        let report = &quote.qe_report;
+        let expected_id = match quote.body {
+            ReportBody::Td(_) => "TD_QE",
+            ReportBody::Enclave(_) => "QE",
+        };
+        if identity.id != expected_id || identity.version != 2 {
+            return Err(format!("Unsupported QE identity {} v{}", identity.id, identity.version));
+        }
+        if !hex_eq(&report.mr_signer, &identity.mrsigner)
+            || report.isv_prod_id != identity.isvprodid
+        {
+            return Err("QE does not match the QE identity".to_string());
+        }
+        let misc_select = report.misc_select.to_be_bytes();
+        if !masked_eq(&misc_select, &identity.miscselect, &identity.miscselect_mask)?
+            || !masked_eq(&report.attributes, &identity.attributes, &identity.attributes_mask)?
+        {
+            return Err("QE attributes do not match the QE identity".to_string());
+        }
+        identity
+            .tcb_levels
+            .iter()
+            .find(|level| level.tcb.isvsvn <= report.isv_svn)
+            .map(|level| level.tcb_status.as_str())
+            .ok_or_else(|| "QE TCB is below every level".to_string())
+    }
+
+    /// Find the TCB level of the platform, and of the TDX module for TDX
+    /// quotes, and return its status
+    fn tcb_status<'c>(
+        &self,
+        collateral: &'c Collateral,
+        quote: &Quote,
+        pck: &PckTcb,
+    ) -> Result<&'c str, String> {
+        let info = &collateral.tcb_info;
+        let expected_id = match quote.body {
+            ReportBody::Td(_) => "TDX",
+            ReportBody::Enclave(_) => "SGX",
+        };
+        if info.id != expected_id || info.version != 3 {
+            return Err(format!("Unsupported TCB info {} v{}", info.id, info.version));
+        }
+        if !hex_eq(&pck.fmspc, &info.fmspc) || !hex_eq(&pck.pce_id, &info.pce_id) {
+            return Err("TCB info is for another platform (FMSPC or PCE ID)".to_string());
+        }
+
+        // SGX levels only constrain the platform; TDX levels also the TEE TCB SVN
+        let tee_tcb_svn = match &quote.body {
+            ReportBody::Td(report) => {
+                Some((report.tee_tcb_svn, self.check_module(collateral, report)?))
+            }
+            ReportBody::Enclave(_) => None,
+        };
+
+        info.tcb_levels
+            .iter()
+            .find(|level| {
+                let tcb = &level.tcb;
+                tcb.sgxtcbcomponents.len() == 16
+                    && pck.pce_svn >= tcb.pcesvn
+                    && (0..16).all(|i| pck.components[i] >= tcb.sgxtcbcomponents[i].svn)
+                    && match tee_tcb_svn {
+                        Some((svn, first_component)) => {
+                            tcb.tdxtcbcomponents.len() == 16
+                                && (first_component..16)
+                                    .all(|i| svn[i] >= tcb.tdxtcbcomponents[i].svn)
+                        }
+                        None => true,
+                    }
+            })
+            .map(|level| level.tcb_status.as_str())
+            .ok_or_else(|| "Platform TCB is below every level".to_string())
+    }
+
+    /// Check the TDX module against the TCB info and return the first TEE
+    /// TCB SVN component that the TCB levels constrain
+    fn check_module(&self, collateral: &Collateral, report: &TdReport) -> Result<usize, String> {
+        // TDX modules from version 1 report their own identity in TEE_TCB_SVN[0..2]
+        let module_version = report.tee_tcb_svn[1];
+        if module_version > 0 {
+            let module_status = self.module_status(collateral, report)?;
+            println!("[Parent] TDX module status: {}", module_status);
+            self.check_status(module_status, "TDX module")?;
+            return Ok(2);
+        }
+        let module = collateral
+            .tcb_info
+            .tdx_module
+            .as_ref()
+            .ok_or("TCB info has no TDX module identity")?;
+        if !hex_eq(&report.mr_signer_seam, &module.mrsigner)
+            || !masked_eq(&report.seam_attributes, &module.attributes, &module.attributes_mask)?
+        {
+            return Err("TDX module does not match the TCB info".to_string());
+        }
+        Ok(0)
+    }
+
+    /// Status of the TDX module named by TEE_TCB_SVN[1]
+    fn module_status<'c>(
+        &self,
+        collateral: &'c Collateral,
+        report: &TdReport,
+    ) -> Result<&'c str, String> {
+        let id = format!("TDX_{:02X}", report.tee_tcb_svn[1]);
+        let identity = collateral
+            .tcb_info
+            .tdx_module_identities
+            .iter()
+            .find(|identity| identity.id == id)
+            .ok_or_else(|| format!("TCB info has no identity for TDX module {}", id))?;
+        if !hex_eq(&report.mr_signer_seam, &identity.mrsigner)
+            || !masked_eq(&report.seam_attributes, &identity.attributes, &identity.attributes_mask)?
+        {
+            return Err(format!("TDX module does not match identity {}", id));
+        }
+        identity
+            .tcb_levels
+            .iter()
+            .find(|level| level.tcb.isvsvn <= report.tee_tcb_svn[0] as u16)
+            .map(|level| level.tcb_status.as_str())
+            .ok_or_else(|| format!("TDX module {} is below every level", id))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::x509::tests::{certificate, crl, der, key, pem, public_key, serial, sign, NOW};
+    use crate::x509::tests::{NOT_AFTER, NOT_BEFORE};
+    use p256::Scalar;
+    use serde_json::{json, Value};
+    use std::path::PathBuf;
+    use tdx_oprf_common::quote::{
+        ATTESTATION_KEY_TYPE_ECDSA_P256, CERT_DATA_PCK_CHAIN, CERT_DATA_QE_REPORT, QUOTE_VERSION,
+        SGX_REPORT_LEN, TEE_TYPE_TDX,
+    };
+
+    pub(crate) const FMSPC: [u8; 6] = [0x00, 0x80, 0x6f, 0x05, 0x00, 0x00];
+    const QE_MR_SIGNER: [u8; 32] = [0x8c; 32];
+    const QE_ISV_PROD_ID: u16 = 2;
+    const QE_ISV_SVN: u16 = 8;
+    const QE_AUTH_DATA: &[u8] = b"QE authentication data";
+    const PCE_SVN: u8 = 13;
+    /// TEE TCB SVN of an up-to-date TD: the SEAM SVN of a version 0 module
+    pub(crate) const TEE_TCB_SVN: [u8; 16] = [3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    /// When the test collateral expires
+    pub(crate) const NEXT_UPDATE: &str = "2025-02-01T00:00:00Z";
+    /// Offset of the QE report in a test quote: header, TD report, signature
+    /// data length, quote signature, attestation key and certification data
+    /// type and length
+    const QE_REPORT_OFFSET: usize = 48 + 584 + 4 + 64 + 64 + 2 + 4;
+
+    const ROOT: &str = "Intel SGX Root CA";
+    const TCB_SIGNING: &str = "Intel SGX TCB Signing";
+    const PCK: &str = "Intel SGX PCK Certificate";
+
+    /// Empty temporary directory unique to the test `name`
+    pub(crate) fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tdx-oprf-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Value of the SGX extension of a PCK certificate
+    fn sgx_extension(components: [u8; 16]) -> Vec<u8> {
+        let oid = |suffix: &[u8]| der(0x06, &[OID_SGX_EXTENSION, suffix]);
+        let entry = |suffix: &[u8], value: &[u8]| der(0x30, &[&oid(suffix), value]);
+        let mut tcb: Vec<u8> = (1..=16)
+            .flat_map(|i| entry(&[0x02, i], &der(0x02, &[&[components[i as usize - 1]]])))
+            .collect();
+        tcb.extend(entry(&[0x02, 17], &der(0x02, &[&[PCE_SVN]])));
+        der(
+            0x30,
+            &[
+                &entry(&[0x02], &der(0x30, &[&tcb])),
+                &entry(&[0x03], &der(0x04, &[&[0, 0]])),
+                &entry(&[0x04], &der(0x04, &[&FMSPC])),
+            ],
+        )
+    }
+
+    fn tcb_components(svns: [u8; 16]) -> Value {
+        svns.iter().map(|svn| json!({ "svn": svn })).collect()
+    }
+
+    /// A TDX platform: the Intel certificates, its TD quotes and collateral
+    pub(crate) struct Platform {
+        root: Vec<u8>,
+        pck_ca_name: String,
+        pck_ca_key: Scalar,
+        pck_chain: Vec<u8>,
+        pck_key: Scalar,
+        tcb_chain: Vec<u8>,
+        tcb_key: Scalar,
+        attestation_key: Scalar,
+        /// TCB info and QE identity, signed when written
+        pub(crate) tcb_info: Value,
+        pub(crate) qe_identity: Value,
+    }
+
+    impl Platform {
+        /// Platform whose PCK certificate is issued by the PCK `ca` CA
+        /// (`Platform` or `Processor`) and reports the SGX TCB `components`
+        pub(crate) fn new(ca: &str, components: [u8; 16]) -> Self {
+            let (root_key, pck_ca_key, pck_key) = (key(1), key(2), key(3));
+            let (tcb_key, attestation_key) = (key(4), key(5));
+            let validity = (NOT_BEFORE, NOT_AFTER);
+            let root = certificate(ROOT, &root_key, (ROOT, &root_key), validity, &[]);
+            let pck_ca_name = format!("Intel SGX PCK {} CA", ca);
+            let pck_ca =
+                certificate(&pck_ca_name, &pck_ca_key, (ROOT, &root_key), validity, &[]);
+            let extension = [(OID_SGX_EXTENSION, sgx_extension(components))];
+            let pck = certificate(PCK, &pck_key, (&pck_ca_name, &pck_ca_key), validity, &extension);
+            let tcb = certificate(TCB_SIGNING, &tcb_key, (ROOT, &root_key), validity, &[]);
+
+            let tcb_info = json!({
+                "id": "TDX",
+                "version": 3,
+                "issueDate": "2025-01-01T00:00:00Z",
+                "nextUpdate": NEXT_UPDATE,
+                "fmspc": hex::encode(FMSPC),
+                "pceId": "0000",
+                "tcbType": 0,
+                "tcbEvaluationDataNumber": 17,
+                "tdxModule": {
+                    "mrsigner": hex::encode([0u8; 48]),
+                    "attributes": "0000000000000000",
+                    "attributesMask": "FFFFFFFFFFFFFFFF"
+                },
+                "tdxModuleIdentities": [{
+                    "id": "TDX_01",
+                    "mrsigner": hex::encode([0u8; 48]),
+                    "attributes": "0000000000000000",
+                    "attributesMask": "FFFFFFFFFFFFFFFF",
+                    "tcbLevels": [{ "tcb": { "isvsvn": 3 }, "tcbStatus": "UpToDate" }]
+                }],
+                "tcbLevels": [
+                    {
+                        "tcb": {
+                            "sgxtcbcomponents": tcb_components([3; 16]),
+                            "pcesvn": PCE_SVN,
+                            "tdxtcbcomponents": tcb_components(TEE_TCB_SVN)
+                        },
+                        "tcbStatus": "UpToDate"
+                    },
+                    {
+                        "tcb": {
+                            "sgxtcbcomponents": tcb_components([0; 16]),
+                            "pcesvn": 0,
+                            "tdxtcbcomponents": tcb_components([0; 16])
+                        },
+                        "tcbStatus": "OutOfDate"
+                    }
+                ]
+            });
+            let qe_identity = json!({
+                "id": "TD_QE",
+                "version": 2,
+                "issueDate": "2025-01-01T00:00:00Z",
+                "nextUpdate": NEXT_UPDATE,
+                "tcbEvaluationDataNumber": 17,
+                "miscselect": "00000000",
+                "miscselectMask": "FFFFFFFF",
+                "attributes": "11000000000000000000000000000000",
+                "attributesMask": "FBFFFFFFFFFFFFFF0000000000000000",
+                "mrsigner": hex::encode(QE_MR_SIGNER),
+                "isvprodid": QE_ISV_PROD_ID,
+                "tcbLevels": [
+                    { "tcb": { "isvsvn": QE_ISV_SVN }, "tcbStatus": "UpToDate" },
+                    { "tcb": { "isvsvn": 0 }, "tcbStatus": "OutOfDate" }
+                ]
+            });
+
+            Self {
+                pck_chain: pem(&[&pck, &pck_ca, &root]),
+                tcb_chain: pem(&[&tcb, &root]),
+                root,
+                pck_ca_name,
+                pck_ca_key,
+                pck_key,
+                tcb_key,
+                attestation_key,
+                tcb_info,
+                qe_identity,
+            }
+        }
+
+        /// Up-to-date platform with a PCK Platform CA certificate
+        pub(crate) fn up_to_date() -> Self {
+            Self::new("Platform", [3; 16])
+        }
+
+        pub(crate) fn root(&self) -> Certificate {
+            Certificate::from_der(&self.root).unwrap()
+        }
+
+        /// Verifier of this platform's quotes against the collateral in `dir`
+        pub(crate) fn verifier(&self, dir: &Path, allowed_statuses: &[&str]) -> QuoteVerifier {
+            let statuses = allowed_statuses.iter().map(|status| status.to_string()).collect();
+            QuoteVerifier::new(
+                self.root(),
+                CollateralManager::offline(&dir.to_string_lossy()),
+                statuses,
+            )
+        }
+
+        /// TD quote of a TD with TEE TCB SVN `tee_tcb_svn` and report data
+        /// `report_data`
+        pub(crate) fn quote(&self, tee_tcb_svn: [u8; 16], report_data: [u8; 64]) -> Vec<u8> {
+            let mut signed_data = Vec::new();
+            signed_data.extend(QUOTE_VERSION.to_le_bytes());
+            signed_data.extend(ATTESTATION_KEY_TYPE_ECDSA_P256.to_le_bytes());
+            signed_data.extend(TEE_TYPE_TDX.to_le_bytes());
+            signed_data.extend([0; 4 + 16 + 20]);
+            // TD report: TEE TCB SVN, the TDX module and TD measurements,
+            // then the report data
+            signed_data.extend(tee_tcb_svn);
+            signed_data.extend([0; 584 - 16 - 64]);
+            signed_data.extend(report_data);
+
+            let attestation_key = &public_key(&self.attestation_key)[1..];
+            let mut qe_report = [0u8; SGX_REPORT_LEN];
+            qe_report[48] = 0x11;
+            qe_report[128..160].copy_from_slice(&QE_MR_SIGNER);
+            qe_report[256..258].copy_from_slice(&QE_ISV_PROD_ID.to_le_bytes());
+            qe_report[258..260].copy_from_slice(&QE_ISV_SVN.to_le_bytes());
+            let binding =
+                Sha256::new().chain_update(attestation_key).chain_update(QE_AUTH_DATA).finalize();
+            qe_report[320..352].copy_from_slice(&binding);
+
+            let mut cert_data = qe_report.to_vec();
+            cert_data.extend(sign(&self.pck_key, &qe_report));
+            cert_data.extend((QE_AUTH_DATA.len() as u16).to_le_bytes());
+            cert_data.extend(QE_AUTH_DATA);
+            cert_data.extend(CERT_DATA_PCK_CHAIN.to_le_bytes());
+            cert_data.extend((self.pck_chain.len() as u32).to_le_bytes());
+            cert_data.extend(&self.pck_chain);
+
+            let mut signature_data = sign(&self.attestation_key, &signed_data).to_vec();
+            signature_data.extend(attestation_key);
+            signature_data.extend(CERT_DATA_QE_REPORT.to_le_bytes());
+            signature_data.extend((cert_data.len() as u32).to_le_bytes());
+            signature_data.extend(cert_data);
+
+            let mut quote = signed_data;
+            quote.extend((signature_data.len() as u32).to_le_bytes());
+            quote.extend(signature_data);
+            quote
+        }
+
+        /// The quote parsed, with the platform TCB and PCK chain the
+        /// collateral key is derived from
+        pub(crate) fn parsed_quote(&self) -> (Quote, PckTcb, Vec<Certificate>) {
+            let quote = Quote::parse(&self.quote([0; 16], [0; 64])).unwrap();
+            let chain = parse_pem_chain(&quote.pck_chain).unwrap();
+            let pck = PckTcb::from_certificate(&chain[0]).unwrap();
+            (quote, pck, chain)
+        }
+
+        /// Write the TCB info and QE identity into `dir`, signed by the TCB
+        /// signing key
+        pub(crate) fn write_collateral(&self, dir: &Path) {
+            for (name, field, document) in [
+                ("tcb_info", "tcbInfo", &self.tcb_info),
+                ("qe_identity", "enclaveIdentity", &self.qe_identity),
+            ] {
+                let body = document.to_string();
+                let signature = hex::encode(sign(&self.tcb_key, body.as_bytes()));
+                let json = format!(r#"{{"{}":{},"signature":"{}"}}"#, field, body, signature);
+                std::fs::write(dir.join(format!("{}.json", name)), json).unwrap();
+                let chain = dir.join(format!("{}_issuer_chain.pem", name));
+                std::fs::write(chain, &self.tcb_chain).unwrap();
+            }
+        }
+
+        /// Write CRLs current until `next_update` (UTCTime) into `dir`, the
+        /// PCK CRL revoking the certificates of `revoked`
+        pub(crate) fn write_crls(&self, dir: &Path, next_update: &str, revoked: &[&str]) {
+            let revoked: Vec<Vec<u8>> = revoked.iter().map(|subject| serial(subject)).collect();
+            let validity = ("241201000000Z", next_update);
+            let pck_crl = crl((&self.pck_ca_name, &self.pck_ca_key), validity, &revoked);
+            let root_ca_crl = crl((ROOT, &key(1)), validity, &[]);
+            std::fs::write(dir.join("pck_crl.der"), pck_crl).unwrap();
+            std::fs::write(dir.join("root_ca_crl.der"), root_ca_crl).unwrap();
+        }
+    }
+
+    /// Collateral of `platform` written into a fresh directory for `name`
+    fn collateral(name: &str, platform: &Platform) -> PathBuf {
+        let dir = temp_dir(name);
+        platform.write_collateral(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_quote_verified() {
+        let platform = Platform::up_to_date();
+        let dir = collateral("dcap-verified", &platform);
+        let verifier = platform.verifier(&dir, &[DEFAULT_ALLOWED_STATUS]);
+        let quote = verifier.verify(&platform.quote(TEE_TCB_SVN, [7; 64]), NOW).unwrap();
+        let ReportBody::Td(report) = quote.body else {
+            panic!("not a TD quote");
+        };
+        assert_eq!(report.report_data, [7; 64]);
+
+        // With CRLs revoking nothing
+        platform.write_crls(&dir, "250201000000Z", &[]);
+        verifier.verify(&platform.quote(TEE_TCB_SVN, [7; 64]), NOW).unwrap();
+    }
+
+    /// Verify `quote` against the collateral of `platform`, accepting only
+    /// `UpToDate`
+    fn verify(name: &str, platform: &Platform, quote: &[u8]) -> Result<Quote, String> {
+        let dir = collateral(name, platform);
+        platform.verifier(&dir, &[DEFAULT_ALLOWED_STATUS]).verify(quote, NOW)
+    }
+
+    fn flip(quote: &[u8], offset: usize) -> Vec<u8> {
+        let mut quote = quote.to_vec();
+        quote[offset] ^= 1;
+        quote
+    }
+
+    #[test]
+    fn test_bad_signatures_refused() {
+        let platform = Platform::up_to_date();
+        let quote = platform.quote(TEE_TCB_SVN, [7; 64]);
+
+        // The report data, the quote signature, the QE report
+        for (offset, expected) in [
+            (48 + 584 - 1, "Quote signature: Invalid ECDSA signature"),
+            (48 + 584 + 4, "Quote signature: Invalid ECDSA signature"),
+            (QE_REPORT_OFFSET + 200, "QE report signature: Invalid ECDSA signature"),
+            (QE_REPORT_OFFSET + 384 + 64 + 2, "QE report does not bind the attestation key"),
+        ] {
+            let error = verify("dcap-signatures", &platform, &flip(&quote, offset)).unwrap_err();
+            assert_eq!(error, expected);
+        }
+
+        // A PCK chain to another root
+        let dir = collateral("dcap-signatures", &platform);
+        let mut verifier = platform.verifier(&dir, &[DEFAULT_ALLOWED_STATUS]);
+        verifier.root = Certificate::from_der(&certificate(
+            ROOT,
+            &key(9),
+            (ROOT, &key(9)),
+            (NOT_BEFORE, NOT_AFTER),
+            &[],
+        ))
+        .unwrap();
+        let error = verifier.verify(&quote, NOW).unwrap_err();
+        let expected = "PCK chain: Certificate chain ends in untrusted root";
+        assert!(error.starts_with(expected), "{}", error);
+    }
+
+    #[test]
+    fn test_bad_collateral_signature_refused() {
+        let platform = Platform::up_to_date();
+        let quote = platform.quote(TEE_TCB_SVN, [7; 64]);
+        for (file, expected) in [
+            ("tcb_info.json", "TCB info signature: Invalid ECDSA signature"),
+            ("qe_identity.json", "QE identity signature: Invalid ECDSA signature"),
+        ] {
+            let dir = collateral("dcap-collateral-signature", &platform);
+            // Still valid JSON, no longer what was signed
+            let json = std::fs::read_to_string(dir.join(file)).unwrap();
+            std::fs::write(dir.join(file), json.replace("UpToDate", "OutOfDate")).unwrap();
+            let verifier = platform.verifier(&dir, &["UpToDate", "OutOfDate"]);
+            assert_eq!(verifier.verify(&quote, NOW).unwrap_err(), expected);
+        }
+    }
+
+    #[test]
+    fn test_expired_collateral_refused() {
+        let mut platform = Platform::up_to_date();
+        let quote = platform.quote(TEE_TCB_SVN, [7; 64]);
+        let dir = collateral("dcap-expired", &platform);
+        let verifier = platform.verifier(&dir, &[DEFAULT_ALLOWED_STATUS]);
+        let next_update = parse_iso8601(NEXT_UPDATE).unwrap();
+        verifier.verify(&quote, next_update).unwrap();
+        assert_eq!(
+            verifier.verify(&quote, next_update + 1).unwrap_err(),
+            "TCB info collateral expired at 2025-02-01T00:00:00Z"
+        );
+
+        platform.tcb_info["nextUpdate"] = json!("2025-03-01T00:00:00Z");
+        platform.write_collateral(&dir);
+        assert_eq!(
+            verifier.verify(&quote, next_update + 1).unwrap_err(),
+            "QE identity collateral expired at 2025-02-01T00:00:00Z"
+        );
+    }
+
+    #[test]
+    fn test_wrong_qe_identity_refused() {
+        let quote = Platform::up_to_date().quote(TEE_TCB_SVN, [7; 64]);
+        for (field, value, expected) in [
+            ("mrsigner", json!(hex::encode([0x8d; 32])), "QE does not match the QE identity"),
+            ("isvprodid", json!(1), "QE does not match the QE identity"),
+            ("attributes", json!("13000000000000000000000000000000"), "QE attributes"),
+            ("miscselect", json!("00000001"), "QE attributes"),
+            ("id", json!("QE"), "Unsupported QE identity QE v2"),
+            ("version", json!(1), "Unsupported QE identity TD_QE v1"),
+            (
+                "tcbLevels",
+                json!([{ "tcb": { "isvsvn": QE_ISV_SVN + 1 }, "tcbStatus": "UpToDate" }]),
+                "QE TCB is below every level",
+            ),
+        ] {
+            let mut platform = Platform::up_to_date();
+            platform.qe_identity[field] = value;
+            let error = verify("dcap-qe-identity", &platform, &quote).unwrap_err();
+            assert!(error.starts_with(expected), "{}: {}", field, error);
+        }
+    }
+
+    #[test]
+    fn test_tcb_status_allowed() {
+        // A platform below the up-to-date SGX TCB components
+        let platform = Platform::new("Platform", [2; 16]);
+        let quote = platform.quote(TEE_TCB_SVN, [7; 64]);
+        let dir = collateral("dcap-status", &platform);
+        let error = platform.verifier(&dir, &[DEFAULT_ALLOWED_STATUS]).verify(&quote, NOW);
+        assert_eq!(error.unwrap_err(), "TCB status OutOfDate is not allowed");
+        platform.verifier(&dir, &["UpToDate", "OutOfDate"]).verify(&quote, NOW).unwrap();
+
+        // A TD below the up-to-date TEE TCB SVN
+        let platform = Platform::up_to_date();
+        let error = verify("dcap-status", &platform, &platform.quote([0; 16], [7; 64]));
+        assert_eq!(error.unwrap_err(), "TCB status OutOfDate is not allowed");
+
+        // An out-of-date QE
+        let mut platform = Platform::up_to_date();
+        platform.qe_identity["tcbLevels"][0]["tcb"]["isvsvn"] = json!(QE_ISV_SVN + 1);
+        let error = verify("dcap-status", &platform, &platform.quote(TEE_TCB_SVN, [7; 64]));
+        assert_eq!(error.unwrap_err(), "QE status OutOfDate is not allowed");
+
+        // Revoked is never allowed
+        let mut platform = Platform::up_to_date();
+        platform.tcb_info["tcbLevels"][0]["tcbStatus"] = json!("Revoked");
+        let quote = platform.quote(TEE_TCB_SVN, [7; 64]);
+        let dir = collateral("dcap-status", &platform);
+        let error = platform.verifier(&dir, &["UpToDate", "Revoked"]).verify(&quote, NOW);
+        assert_eq!(error.unwrap_err(), "TCB status Revoked is not allowed");
+    }
+
+    #[test]
+    fn test_tcb_info_of_another_platform_refused() {
+        for (field, value) in [("fmspc", json!("00906ed50000")), ("pceId", json!("0100"))] {
+            let mut platform = Platform::up_to_date();
+            platform.tcb_info[field] = value;
+            let error = verify("dcap-platform", &platform, &platform.quote(TEE_TCB_SVN, [7; 64]));
+            assert_eq!(error.unwrap_err(), "TCB info is for another platform (FMSPC or PCE ID)");
+        }
+        let mut platform = Platform::up_to_date();
+        platform.tcb_info["id"] = json!("SGX");
+        let error = verify("dcap-platform", &platform, &platform.quote(TEE_TCB_SVN, [7; 64]));
+        assert_eq!(error.unwrap_err(), "Unsupported TCB info SGX v3");
+    }
+
+    #[test]
+    fn test_tdx_module_checked() {
+        let mut platform = Platform::up_to_date();
+        platform.tcb_info["tdxModule"]["mrsigner"] = json!(hex::encode([1u8; 48]));
+        let error = verify("dcap-module", &platform, &platform.quote(TEE_TCB_SVN, [7; 64]));
+        assert_eq!(error.unwrap_err(), "TDX module does not match the TCB info");
+
+        // Modules from version 1 are matched against their own identity
+        let platform = Platform::up_to_date();
+        let svn = |major, minor| {
+            let mut svn = [0; 16];
+            svn[..2].copy_from_slice(&[major, minor]);
+            svn
+        };
+        verify("dcap-module", &platform, &platform.quote(svn(3, 1), [7; 64])).unwrap();
+        let error = verify("dcap-module", &platform, &platform.quote(svn(2, 1), [7; 64]));
+        assert_eq!(error.unwrap_err(), "TDX module TDX_01 is below every level");
+        let error = verify("dcap-module", &platform, &platform.quote(svn(3, 2), [7; 64]));
+        assert_eq!(error.unwrap_err(), "TCB info has no identity for TDX module TDX_02");
+    }
+
+    #[test]
+    fn test_revoked_certificates_refused() {
+        let platform = Platform::up_to_date();
+        let quote = platform.quote(TEE_TCB_SVN, [7; 64]);
+        let dir = collateral("dcap-revoked", &platform);
+        let verifier = platform.verifier(&dir, &[DEFAULT_ALLOWED_STATUS]);
+
+        platform.write_crls(&dir, "250201000000Z", &[PCK]);
+        let error = verifier.verify(&quote, NOW).unwrap_err();
+        assert!(error.starts_with("Certificate ") && error.ends_with(" is revoked"), "{}", error);
+
+        platform.write_crls(&dir, "241215000000Z", &[]);
+        assert_eq!(verifier.verify(&quote, NOW).unwrap_err(), "Root CA CRL: CRL is not current");
+    }
+
+    #[test]
+    fn test_pck_tcb() {
+        let (_, tcb, chain) = Platform::new("Platform", [5; 16]).parsed_quote();
+        assert_eq!(tcb.fmspc, FMSPC);
+        assert_eq!(tcb.pce_id, [0, 0]);
+        assert_eq!(tcb.components, [5; 16]);
+        assert_eq!(tcb.pce_svn, PCE_SVN as u16);
+
+        // The intermediate CA has no SGX extension
+        let error = PckTcb::from_certificate(&chain[1]).err().unwrap();
+        assert_eq!(error, "PCK certificate has no SGX extension");
+    }
+}
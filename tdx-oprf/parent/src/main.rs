@@ -1,27 +1,92 @@
+mod collateral;
+mod dcap;
+mod vtpm;
+mod x509;
+
 use ark_bn254::Fr;
 use ark_ff::UniformRand;
 use tdx_oprf_common::{
-    deserialize_g1, scalar_inverse, scalar_mul, scalar_mul_generator, serialize_g1, sha256_hex,
-    AttestationDocument, OprfRequest, OprfResponse,
+    deserialize_g1, finalize, scalar_inverse, scalar_mul, scalar_mul_generator, serialize_fr,
+    serialize_g1, sha256_hex, expected_public_key_rtmr, AttestationDocument, EvidenceFormat,
+    OprfRequest, OprfResponse, PUBLIC_KEY_RTMR,
 };
+use collateral::{CollateralManager, PCS_URL};
+use dcap::{QuoteVerifier, DEFAULT_ALLOWED_STATUS};
 use rand::rngs::OsRng;
+use std::collections::BTreeMap;
 use std::io::{Read, Write};
+use tdx_oprf_common::quote::{ReportBody, SgxReport, TdReport};
+use tdx_oprf_common::tls::{TlsConnector, DEFAULT_SERVER_NAME};
+use vtpm::VtpmVerifier;
 
 #[cfg(feature = "tdx")]
 use std::os::unix::io::AsRawFd;
 
-#[cfg(all(feature = "local", not(feature = "tdx")))]
-const LOCAL_PORT: u16 = 5000;
+/// vsock port, or local TCP port, of the enclave unless configured
+const DEFAULT_ENCLAVE_PORT: u32 = 5000;
+/// TDX guest CID unless configured (the host is 2, the first guest 3)
+const DEFAULT_ENCLAVE_CID: u32 = 3;
+
+/// Where the enclave listens: `--cid`/`--port`, else the `ENCLAVE_CID` and
+/// `ENCLAVE_PORT` environment variables, else the defaults. The local, SGX
+/// and vTPM modes connect to the port on 127.0.0.1 and ignore the CID.
+struct EnclaveAddress {
+    #[cfg_attr(not(feature = "tdx"), allow(dead_code))]
+    cid: u32,
+    port: u32,
+}
 
-#[cfg(feature = "tdx")]
-const VSOCK_PORT: u32 = 5000;
-#[cfg(feature = "tdx")]
-const VSOCK_CID_GUEST: u32 = 3; // TDX guest CID (parent is 2, guest is 3)
+impl EnclaveAddress {
+    fn from_env() -> Result<Self, String> {
+        let mut address = Self {
+            cid: DEFAULT_ENCLAVE_CID,
+            port: DEFAULT_ENCLAVE_PORT,
+        };
+        let fields = [("ENCLAVE_CID", &mut address.cid), ("ENCLAVE_PORT", &mut address.port)];
+        for (name, field) in fields {
+            if let Ok(value) = std::env::var(name) {
+                *field = value.parse().map_err(|e| format!("Invalid {}: {}", name, e))?;
+            }
+        }
+        Ok(address)
+    }
+}
+
+/// Expected identity of an SGX enclave
+#[derive(Default)]
+struct SgxPolicy {
+    /// Expected MRENCLAVE, the measurement of the enclave
+    mr_enclave: Option<[u8; 32]>,
+    /// Expected MRSIGNER, the hash of the enclave signing key
+    mr_signer: Option<[u8; 32]>,
+}
+
+impl SgxPolicy {
+    fn check(&self, report: &SgxReport) -> Result<(), String> {
+        if self.mr_enclave.is_none() && self.mr_signer.is_none() {
+            return Err("SGX quote verification requires --mrenclave or --mrsigner".to_string());
+        }
+        if report.is_debug() {
+            return Err("SGX enclave runs in debug mode".to_string());
+        }
+        if self.mr_enclave.is_some_and(|expected| expected != report.mr_enclave) {
+            return Err("MRENCLAVE does not match the policy".to_string());
+        }
+        if self.mr_signer.is_some_and(|expected| expected != report.mr_signer) {
+            return Err("MRSIGNER does not match the policy".to_string());
+        }
+        Ok(())
+    }
+}
 
 /// Verify attestation document
 fn verify_attestation(
+    verifier: Option<&QuoteVerifier>,
+    sgx_policy: &SgxPolicy,
+    vtpm_verifier: Option<&VtpmVerifier>,
     attestation: &AttestationDocument,
     expected_user_data: &[u8],
+    public_key: &[u8],
 ) -> Result<(), String> {
     if attestation.is_mock {
         println!("[Parent] Verifying mock attestation (local mode)");
@@ -40,51 +105,118 @@ fn verify_attestation(
             serde_json::to_string_pretty(&doc).unwrap()
         );
 
+        // The mock reports RTMR3 as the TDX backend would extend it
+        let rtmr = attestation.rtmrs.as_ref().and_then(|rtmrs| rtmrs.get(PUBLIC_KEY_RTMR));
+        if rtmr.is_some_and(|rtmr| *rtmr != hex::encode(expected_public_key_rtmr(public_key))) {
+            return Err(format!("Mock RTMR{} does not match the public key", PUBLIC_KEY_RTMR));
+        }
+
+        Ok(())
+    } else if attestation.format == EvidenceFormat::VtpmQuote {
+        println!("[Parent] Verifying vTPM attestation");
+
+        if attestation.user_data != expected_user_data {
+            return Err("User data mismatch in attestation".to_string());
+        }
+
+        // The enclave puts the hex SHA-256 of the user data into the quote's
+        // qualifying data
+        let vtpm_verifier =
+            vtpm_verifier.ok_or("vTPM quote verification requires --vtpm-root and --pcr")?;
+        let report_data = sha256_hex(expected_user_data);
+        vtpm_verifier.verify(&attestation.document, report_data.as_bytes(), now()?)?;
         Ok(())
     } else {
-        println!("[Parent] Verifying TDX attestation");
+        println!("[Parent] Verifying DCAP attestation");
 
         // Verify user data matches
         if attestation.user_data != expected_user_data {
             return Err("User data mismatch in attestation".to_string());
         }
 
-        // Display TDX measurements
-        if let Some(mrtd) = &attestation.mrtd {
-            println!("[Parent] MRTD: {}", mrtd);
+        println!("[Parent] Quote size: {} bytes", attestation.document.len());
+
+        // Nothing in the quote is trusted before DCAP verification
+        let verifier = verifier
+            .ok_or("DCAP quote verification requires --root-ca and collateral")?;
+        let quote = verifier.verify(&attestation.document, now()?)?;
+
+        // The enclave puts the hex SHA-256 of the user data into REPORTDATA
+        let report_data = match &quote.body {
+            ReportBody::Td(report) => report.report_data,
+            ReportBody::Enclave(report) => report.report_data,
+        };
+        if report_data[..] != sha256_hex(expected_user_data).as_bytes()[..] {
+            return Err("Quote report data does not match the user data".to_string());
         }
 
-        if let Some(rtmrs) = &attestation.rtmrs {
-            for (i, rtmr) in rtmrs.iter().enumerate() {
-                println!("[Parent] RTMR{}: {}", i, rtmr);
+        match &quote.body {
+            ReportBody::Td(report) => check_td_report(report, public_key),
+            ReportBody::Enclave(report) => {
+                println!("[Parent] MRENCLAVE: {}", hex::encode(report.mr_enclave));
+                println!("[Parent] MRSIGNER: {}", hex::encode(report.mr_signer));
+                println!(
+                    "[Parent] ISV product ID: {}, ISV SVN: {}",
+                    report.isv_prod_id, report.isv_svn
+                );
+                sgx_policy.check(report)
             }
         }
+    }
+}
 
-        println!("[Parent] TDX quote size: {} bytes", attestation.document.len());
-
-        // In production, you would:
-        // 1. Verify the quote signature using Intel's attestation service
-        // 2. Check MRTD matches expected TDX module measurement
-        // 3. Check RTMR values match expected initial state
-        // 4. Verify the quote is recent (check timestamp)
+/// Current unix time in seconds
+fn now() -> Result<u64, String> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_secs())
+}
 
-        println!("[Parent] WARNING: Full TDX quote verification not implemented");
-        println!("[Parent] In production, verify quote with Intel Attestation Service");
+/// Display the verified TDX measurements and check that RTMR3 holds the
+/// enclave's extension with its public key
+fn check_td_report(report: &TdReport, public_key: &[u8]) -> Result<(), String> {
+    println!("[Parent] MRTD: {}", hex::encode(report.mr_td));
+    for (i, rtmr) in report.rtmrs.iter().enumerate() {
+        println!("[Parent] RTMR{}: {}", i, hex::encode(rtmr));
+    }
 
-        Ok(())
+    // The enclave extends RTMR3 once, with SHA384(public key), at key generation
+    if report.rtmrs[PUBLIC_KEY_RTMR] != expected_public_key_rtmr(public_key) {
+        return Err(format!(
+            "RTMR{} does not match the extension with the public key",
+            PUBLIC_KEY_RTMR
+        ));
     }
+    println!("[Parent] RTMR{} binds the enclave public key", PUBLIC_KEY_RTMR);
+
+    // In production, you would also:
+    // 1. Check MRTD matches the expected TD image
+    // 2. Check RTMR0-2 values match expected initial state
+    Ok(())
+}
+
+/// Parse a hex-encoded 32-byte SGX measurement
+fn parse_measurement(value: &str, flag: &str) -> Result<[u8; 32], String> {
+    let mut measurement = [0u8; 32];
+    hex::decode_to_slice(value, &mut measurement)
+        .map_err(|_| format!("{} expects 64 hex characters", flag))?;
+    Ok(measurement)
 }
 
-#[cfg(all(feature = "local", not(feature = "tdx")))]
-fn connect_to_enclave() -> std::io::Result<std::net::TcpStream> {
+#[cfg(all(any(feature = "local", feature = "sgx", feature = "vtpm"), not(feature = "tdx")))]
+fn connect_to_enclave(address: &EnclaveAddress) -> std::io::Result<std::net::TcpStream> {
+    use std::io::{Error, ErrorKind};
     use std::net::TcpStream;
 
-    println!("[Parent] Connecting to enclave at 127.0.0.1:{}", LOCAL_PORT);
-    TcpStream::connect(format!("127.0.0.1:{}", LOCAL_PORT))
+    let port = u16::try_from(address.port)
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "Enclave port out of range"))?;
+    println!("[Parent] Connecting to enclave at 127.0.0.1:{}", port);
+    TcpStream::connect(("127.0.0.1", port))
 }
 
 #[cfg(feature = "tdx")]
-fn connect_to_enclave() -> std::io::Result<std::net::TcpStream> {
+fn connect_to_enclave(address: &EnclaveAddress) -> std::io::Result<std::net::TcpStream> {
     use nix::sys::socket::{connect, socket, AddressFamily, SockFlag, SockType, VsockAddr};
     use std::os::unix::io::{FromRawFd, IntoRawFd};
 
@@ -96,11 +228,11 @@ fn connect_to_enclave() -> std::io::Result<std::net::TcpStream> {
     )
     .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-    let addr = VsockAddr::new(VSOCK_CID_GUEST, VSOCK_PORT);
+    let addr = VsockAddr::new(address.cid, address.port);
 
     println!(
         "[Parent] Connecting to enclave via vsock (CID: {}, Port: {})",
-        VSOCK_CID_GUEST, VSOCK_PORT
+        address.cid, address.port
     );
 
     connect(sock_fd.as_raw_fd(), &addr)
@@ -136,12 +268,134 @@ fn send_request<S: Read + Write>(
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("[Parent] Starting TDX OPRF Parent...");
 
-    #[cfg(all(feature = "local", not(feature = "tdx")))]
+    #[cfg(all(feature = "local", not(any(feature = "tdx", feature = "sgx", feature = "vtpm"))))]
     println!("[Parent] Running in LOCAL mode");
 
+    #[cfg(all(feature = "sgx", not(feature = "tdx")))]
+    println!("[Parent] Running in SGX mode");
+
+    #[cfg(all(feature = "vtpm", not(any(feature = "tdx", feature = "sgx"))))]
+    println!("[Parent] Running in vTPM mode");
+
     #[cfg(feature = "tdx")]
     println!("[Parent] Running in TDX mode");
 
+    // Usage: tdx-oprf-parent [--root-ca <file>]
+    //                        [--collateral <dir> | --collateral-cache <dir> [--pccs-url <url>]]
+    //                        [--allow-tcb-status <status> ...]
+    //                        [--mrenclave <hex>] [--mrsigner <hex>]
+    //                        [--vtpm-root <file> [--vtpm-intermediates <file>]
+    //                         --pcr <index>=<hex> ...]
+    //                        [--cid <n>] [--port <n> | --uds <path>]
+    //                        [--tls-ca <file> [--tls-server-name <name>]]
+    // TDX and SGX quotes are verified against the Intel SGX root CA and
+    // collateral, either pre-provisioned or fetched from Intel PCS (or a
+    // PCCS) into a cache; the TCB must be UpToDate unless other statuses are
+    // allowed. SGX enclaves must also match the expected MRENCLAVE and/or
+    // MRSIGNER. vTPM quotes of confidential VMs are verified against the
+    // cloud's vTPM root CA and must match every expected SHA-256 PCR.
+    // --cid and --port locate the enclave (see EnclaveAddress); --uds
+    // connects to the Unix socket an enclave serves with --uds instead.
+    // --tls-ca connects over TLS, trusting the CA certificates of the file
+    // for an enclave certificate valid for --tls-server-name (localhost).
+    let mut root_ca = None;
+    let mut collateral = None;
+    let mut collateral_cache = None;
+    let mut pccs_url = None;
+    let mut allowed_statuses = Vec::new();
+    let mut sgx_policy = SgxPolicy::default();
+    let mut vtpm_root = None;
+    let mut vtpm_intermediates = None;
+    let mut expected_pcrs = BTreeMap::new();
+    let mut address = EnclaveAddress::from_env()?;
+    let mut uds = None;
+    let mut tls_ca = None;
+    let mut tls_server_name = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--root-ca" {
+            root_ca = Some(args.next().ok_or("--root-ca requires a file")?);
+        } else if arg == "--collateral" {
+            collateral = Some(args.next().ok_or("--collateral requires a directory")?);
+        } else if arg == "--collateral-cache" {
+            collateral_cache = Some(args.next().ok_or("--collateral-cache requires a directory")?);
+        } else if arg == "--pccs-url" {
+            pccs_url = Some(args.next().ok_or("--pccs-url requires a URL")?);
+        } else if arg == "--allow-tcb-status" {
+            allowed_statuses.push(args.next().ok_or("--allow-tcb-status requires a value")?);
+        } else if arg == "--mrenclave" || arg == "--mrsigner" {
+            let value = args.next().ok_or_else(|| format!("{} requires a value", arg))?;
+            let measurement = Some(parse_measurement(&value, &arg)?);
+            if arg == "--mrenclave" {
+                sgx_policy.mr_enclave = measurement;
+            } else {
+                sgx_policy.mr_signer = measurement;
+            }
+        } else if arg == "--vtpm-root" {
+            vtpm_root = Some(args.next().ok_or("--vtpm-root requires a file")?);
+        } else if arg == "--vtpm-intermediates" {
+            vtpm_intermediates = Some(args.next().ok_or("--vtpm-intermediates requires a file")?);
+        } else if arg == "--pcr" {
+            let (index, pcr) = vtpm::parse_pcr(&args.next().ok_or("--pcr requires a value")?)?;
+            expected_pcrs.insert(index, pcr);
+        } else if arg == "--cid" {
+            address.cid = args.next().ok_or("--cid requires a value")?.parse()?;
+        } else if arg == "--port" {
+            address.port = args.next().ok_or("--port requires a value")?.parse()?;
+        } else if arg == "--uds" {
+            uds = Some(args.next().ok_or("--uds requires a path")?);
+        } else if arg == "--tls-ca" {
+            tls_ca = Some(args.next().ok_or("--tls-ca requires a file")?);
+        } else if arg == "--tls-server-name" {
+            tls_server_name = Some(args.next().ok_or("--tls-server-name requires a name")?);
+        } else {
+            return Err(format!("Unknown argument: {}", arg).into());
+        }
+    }
+    if allowed_statuses.is_empty() {
+        allowed_statuses.push(DEFAULT_ALLOWED_STATUS.to_string());
+    }
+    let collateral = match (collateral, collateral_cache, pccs_url) {
+        (Some(dir), None, None) => Some(CollateralManager::offline(&dir)),
+        (None, Some(cache), url) => Some(CollateralManager::cached(
+            &cache,
+            url.as_deref().unwrap_or(PCS_URL),
+        )),
+        (None, None, None) => None,
+        (None, None, Some(_)) => return Err("--pccs-url requires --collateral-cache".into()),
+        _ => return Err("--collateral and --collateral-cache are exclusive".into()),
+    };
+    let verifier = match (root_ca, collateral) {
+        (Some(root_ca), Some(collateral)) => Some(QuoteVerifier::new(
+            x509::load_root(&root_ca)?,
+            collateral,
+            allowed_statuses,
+        )),
+        (None, None) => None,
+        _ => return Err("--root-ca and collateral must be given together".into()),
+    };
+    let vtpm_verifier = match vtpm_root {
+        Some(root) => Some(VtpmVerifier::load(
+            &root,
+            vtpm_intermediates.as_deref(),
+            expected_pcrs,
+        )?),
+        None if vtpm_intermediates.is_some() || !expected_pcrs.is_empty() => {
+            return Err("--vtpm-intermediates and --pcr require --vtpm-root".into());
+        }
+        None => None,
+    };
+
+    let tls = match (tls_ca, tls_server_name) {
+        (Some(_), _) if uds.is_some() => return Err("--tls-ca does not apply to --uds".into()),
+        (Some(ca), name) => Some(TlsConnector::load(
+            &ca,
+            name.as_deref().unwrap_or(DEFAULT_SERVER_NAME),
+        )?),
+        (None, Some(_)) => return Err("--tls-server-name requires --tls-ca".into()),
+        (None, None) => None,
+    };
+
     let mut rng = OsRng;
 
     // Sample random input m and blinding factor b
@@ -171,16 +425,38 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("[Parent] Query hash: {}", query_hash);
 
-    // Connect to enclave
-    let mut stream = connect_to_enclave()?;
-    println!("[Parent] Connected to enclave");
-
-    // Send request and get response
-    let response = send_request(&mut stream, &request)?;
+    // Connect to enclave, send request and get response
+    let response = match &uds {
+        Some(path) => {
+            println!("[Parent] Connecting to enclave at {}", path);
+            let mut stream = std::os::unix::net::UnixStream::connect(path)?;
+            println!("[Parent] Connected to enclave");
+            send_request(&mut stream, &request)?
+        }
+        None => {
+            let mut stream = connect_to_enclave(&address)?;
+            println!("[Parent] Connected to enclave");
+            match &tls {
+                Some(tls) => {
+                    let mut stream = tls.connect(stream)?;
+                    println!("[Parent] TLS established with the enclave");
+                    send_request(&mut stream, &request)?
+                }
+                None => send_request(&mut stream, &request)?,
+            }
+        }
+    };
     println!("[Parent] Received response from enclave");
 
     // Verify attestation
-    verify_attestation(&response.attestation, &response.evaluated_point)?;
+    verify_attestation(
+        verifier.as_ref(),
+        &sgx_policy,
+        vtpm_verifier.as_ref(),
+        &response.attestation,
+        &response.evaluated_point,
+        &response.public_key,
+    )?;
     println!("[Parent] Attestation verified successfully");
 
     // Deserialize the evaluated point
@@ -196,11 +472,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let unblinded_bytes = serialize_g1(&unblinded)?;
 
     println!("[Parent] Unblinded result computed");
-    println!("[Parent] ================================================");
     println!(
-        "[Parent] OPRF OUTPUT (g^(m*k)): {}",
+        "[Parent] Unblinded element (g^(m*k)): {}",
         hex::encode(&unblinded_bytes)
     );
+
+    // Finalize: hash the unblinded element together with the input m
+    let output = finalize(&serialize_fr(&m)?, &unblinded)?;
+
+    println!("[Parent] ================================================");
+    println!("[Parent] OPRF OUTPUT: {}", hex::encode(output));
     println!("[Parent] ================================================");
 
     // Also display the public key for reference
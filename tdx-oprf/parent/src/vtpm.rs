@@ -0,0 +1,154 @@
+//! Verification of vTPM quotes from Azure and GCP confidential VMs.
+//!
+//! The AK certificate must chain to the cloud's vTPM root CA (e.g. "Azure
+//! Virtual TPM Root Certificate Authority 2023" or Google's "EK/AK CA
+//! Root"), through intermediates given with the root since the vTPM only
+//! stores the leaf. The AK then signs the quote, whose qualifying data binds
+//! the user data and whose PCR digest covers the PCR values shipped with it.
+//! The PCR values are finally checked against the expected runtime
+//! measurements.
+//!
+//! The AK certificate vouches that the key lives in a confidential VM's
+//! vTPM; the hardware report that the cloud's paravisor binds to the AK is
+//! not checked here.
+
+use crate::x509::{self, verify_chain, Certificate, Der, PublicKey, RsaHash};
+use std::collections::BTreeMap;
+use tdx_oprf_common::tpm::{
+    pcr_digest, TpmQuote, TpmSignature, VtpmEvidence, PCR_LEN, TPM_ALG_SHA256,
+};
+
+/// Trust anchors and expected PCR values for vTPM quotes
+pub struct VtpmVerifier {
+    root: Certificate,
+    /// CA certificates between the AK certificate and the root
+    intermediates: Vec<Certificate>,
+    /// Expected SHA-256 PCR values by index
+    expected_pcrs: BTreeMap<usize, [u8; PCR_LEN]>,
+}
+
+impl VtpmVerifier {
+    /// Load the root CA file and the optional PEM bundle of intermediates
+    pub fn load(
+        root: &str,
+        intermediates: Option<&str>,
+        expected_pcrs: BTreeMap<usize, [u8; PCR_LEN]>,
+    ) -> Result<Self, String> {
+        if expected_pcrs.is_empty() {
+            return Err("vTPM quote verification requires at least one --pcr".to_string());
+        }
+        let intermediates = match intermediates {
+            Some(path) => {
+                let pem = std::fs::read(path)
+                    .map_err(|e| format!("Failed to read intermediates {}: {}", path, e))?;
+                x509::parse_pem_chain(&pem)?
+            }
+            None => Vec::new(),
+        };
+        Ok(Self { root: x509::load_root(root)?, intermediates, expected_pcrs })
+    }
+
+    /// Verify vTPM evidence whose qualifying data must be `report_data`,
+    /// at unix time `now`. Returns the verified evidence.
+    pub fn verify(
+        &self,
+        document: &[u8],
+        report_data: &[u8],
+        now: u64,
+    ) -> Result<VtpmEvidence, String> {
+        let evidence: VtpmEvidence = serde_json::from_slice(document)
+            .map_err(|e| format!("Failed to parse vTPM evidence: {}", e))?;
+
+        // The NV index may be larger than the certificate it holds
+        let ak_der = Der::new(&evidence.ak_certificate).next()?.raw;
+        let ak = Certificate::from_der(ak_der)?;
+        let mut chain = vec![ak];
+        chain.extend(self.intermediates.iter().cloned());
+        if chain.last().is_some_and(|last| last.der != self.root.der) {
+            chain.push(self.root.clone());
+        }
+        verify_chain(&chain, &self.root, now).map_err(|e| format!("AK chain: {}", e))?;
+
+        let signature = TpmSignature::parse(&evidence.signature)?;
+        verify_signature(&chain[0].public_key, &evidence.quote, &signature)
+            .map_err(|e| format!("vTPM quote signature: {}", e))?;
+        println!(
+            "[Parent] vTPM quote signed by the {} AK, which chains to the vTPM root CA",
+            evidence.platform
+        );
+
+        let quote = TpmQuote::parse(&evidence.quote)?;
+        if quote.extra_data != report_data {
+            return Err("vTPM quote qualifying data does not match the user data".to_string());
+        }
+
+        // The quote signs the digest of the PCR values, not the values
+        let [selection] = quote.pcr_selection.as_slice() else {
+            return Err("vTPM quote must select PCRs from exactly one bank".to_string());
+        };
+        if selection.hash != TPM_ALG_SHA256 || selection.pcrs.len() != evidence.pcrs.len() {
+            return Err("vTPM quote PCR selection does not match the PCR values".to_string());
+        }
+        if quote.pcr_digest != pcr_digest(&evidence.pcrs) {
+            return Err("vTPM quote PCR digest does not match the PCR values".to_string());
+        }
+        let pcrs: BTreeMap<usize, &[u8]> = selection
+            .pcrs
+            .iter()
+            .copied()
+            .zip(evidence.pcrs.iter().map(Vec::as_slice))
+            .collect();
+
+        println!(
+            "[Parent] TPM clock: {} ms, resets: {}, restarts: {}",
+            quote.clock, quote.reset_count, quote.restart_count
+        );
+        for (index, expected) in &self.expected_pcrs {
+            let measured = pcrs
+                .get(index)
+                .ok_or_else(|| format!("vTPM quote does not cover PCR{}", index))?;
+            println!("[Parent] PCR{}: {}", index, hex::encode(measured));
+            if measured != expected {
+                return Err(format!("PCR{} does not match the expected value", index));
+            }
+        }
+        println!("[Parent] PCRs match the expected runtime measurements");
+
+        Ok(evidence)
+    }
+}
+
+/// Verify the AK's `signature` over the marshalled `TPMS_ATTEST`
+fn verify_signature(
+    key: &PublicKey,
+    quote: &[u8],
+    signature: &TpmSignature,
+) -> Result<(), String> {
+    match signature {
+        TpmSignature::RsaSsa { hash: TPM_ALG_SHA256, signature } => {
+            key.verify_rsa(RsaHash::Sha256, quote, signature)
+        }
+        TpmSignature::Ecdsa { hash: TPM_ALG_SHA256, r, s } => {
+            let mut raw = [0u8; 64];
+            for (half, integer) in raw.chunks_mut(32).zip([r, s]) {
+                let integer = &integer[integer.iter().take_while(|b| **b == 0).count()..];
+                if integer.len() > 32 {
+                    return Err("Invalid ECDSA signature".to_string());
+                }
+                half[32 - integer.len()..].copy_from_slice(integer);
+            }
+            key.verify(quote, &raw)
+        }
+        _ => Err("Quote is not signed with SHA-256".to_string()),
+    }
+}
+
+/// Parse `--pcr <index>=<hex>`
+pub fn parse_pcr(value: &str) -> Result<(usize, [u8; PCR_LEN]), String> {
+    let invalid = || format!("--pcr expects <index>=<{} hex characters>", 2 * PCR_LEN);
+    let (index, digest) = value.split_once('=').ok_or_else(invalid)?;
+    let index = index.parse::<usize>().map_err(|_| invalid())?;
+    let mut pcr = [0u8; PCR_LEN];
+    hex::decode_to_slice(digest, &mut pcr).map_err(|_| invalid())?;
+    Ok((index, pcr))
+}
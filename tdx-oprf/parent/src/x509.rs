@@ -0,0 +1,957 @@
+//! Just enough DER, X.509, ECDSA-P256 and RSA to verify Intel SGX/TDX
+//! certificate chains and CRLs, where everything is signed with
+//! ecdsa-with-SHA256 over P-256, and the RSA (PKCS #1 v1.5) chains of cloud
+//! vTPM attestation keys.
+
+use base64ct::{Base64, Encoding};
+use num_bigint::BigUint;
+use p256::elliptic_curve::ops::Reduce;
+use p256::elliptic_curve::point::AffineCoordinates;
+use p256::elliptic_curve::sec1::FromEncodedPoint;
+use p256::elliptic_curve::{Field, PrimeField};
+use p256::{AffinePoint, EncodedPoint, FieldBytes, ProjectivePoint, Scalar, U256};
+use sha2::{Digest, Sha256, Sha384};
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_OID: u8 = 0x06;
+const TAG_UTC_TIME: u8 = 0x17;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_VERSION: u8 = 0xa0;
+const TAG_CRL_EXTENSIONS: u8 = 0xa0;
+const TAG_EXTENSIONS: u8 = 0xa3;
+
+/// ecdsa-with-SHA256, 1.2.840.10045.4.3.2
+const OID_ECDSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+/// sha256WithRSAEncryption, 1.2.840.113549.1.1.11
+const OID_RSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+/// sha384WithRSAEncryption, 1.2.840.113549.1.1.12
+const OID_RSA_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c];
+/// id-ecPublicKey, 1.2.840.10045.2.1
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+/// rsaEncryption, 1.2.840.113549.1.1.1
+const OID_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+/// prime256v1, 1.2.840.10045.3.1.7
+const OID_P256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+
+/// DER DigestInfo prefixes of PKCS #1 v1.5 signatures, per hash
+const DIGEST_INFO_SHA256: &[u8] = &[
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05,
+    0x00, 0x04, 0x20,
+];
+const DIGEST_INFO_SHA384: &[u8] = &[
+    0x30, 0x41, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02, 0x05,
+    0x00, 0x04, 0x30,
+];
+/// Smallest RSA modulus accepted, in bits
+const MIN_RSA_BITS: u64 = 2048;
+
+/// One DER TLV
+#[derive(Debug, Clone, Copy)]
+pub struct Tlv<'a> {
+    pub tag: u8,
+    pub value: &'a [u8],
+    /// The whole encoding, tag and length included
+    pub raw: &'a [u8],
+}
+
+/// Reader over a sequence of DER TLVs
+pub struct Der<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Der<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Next TLV, whatever its tag
+    pub fn next(&mut self) -> Result<Tlv<'a>, String> {
+        let truncated = || "Truncated DER".to_string();
+        let tag = *self.data.first().ok_or_else(truncated)?;
+        let first = *self.data.get(1).ok_or_else(truncated)?;
+        let (len, header) = if first < 0x80 {
+            (first as usize, 2)
+        } else {
+            let count = (first & 0x7f) as usize;
+            if count == 0 || count > 4 {
+                return Err("Unsupported DER length".to_string());
+            }
+            let bytes = self.data.get(2..2 + count).ok_or_else(truncated)?;
+            (bytes.iter().fold(0usize, |len, b| len << 8 | *b as usize), 2 + count)
+        };
+        let end = header.checked_add(len).ok_or_else(truncated)?;
+        let raw = self.data.get(..end).ok_or_else(truncated)?;
+        self.data = &self.data[end..];
+        Ok(Tlv { tag, value: &raw[header..], raw })
+    }
+
+    /// Next TLV, which must have tag `tag`
+    pub fn expect(&mut self, tag: u8) -> Result<Tlv<'a>, String> {
+        let tlv = self.next()?;
+        if tlv.tag != tag {
+            return Err(format!("Expected DER tag {:#04x}, got {:#04x}", tag, tlv.tag));
+        }
+        Ok(tlv)
+    }
+
+    /// Next TLV if it has tag `tag`
+    fn optional(&mut self, tag: u8) -> Result<Option<Tlv<'a>>, String> {
+        if self.data.first() == Some(&tag) {
+            self.next().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Non-negative DER INTEGER as an unsigned integer
+pub fn der_uint(value: &[u8]) -> Result<u64, String> {
+    let value = match value {
+        [0, rest @ ..] => rest,
+        value => value,
+    };
+    if value.len() > 8 {
+        return Err("DER integer too large".to_string());
+    }
+    Ok(value.iter().fold(0u64, |n, b| n << 8 | *b as u64))
+}
+
+/// Hash of an RSA signature
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RsaHash {
+    Sha256,
+    Sha384,
+}
+
+impl RsaHash {
+    fn digest_info(self, message: &[u8]) -> Vec<u8> {
+        match self {
+            RsaHash::Sha256 => [DIGEST_INFO_SHA256, &Sha256::digest(message)].concat(),
+            RsaHash::Sha384 => [DIGEST_INFO_SHA384, &Sha384::digest(message)].concat(),
+        }
+    }
+}
+
+/// Signature algorithm of a certificate or CRL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SignatureAlgorithm {
+    EcdsaSha256,
+    Rsa(RsaHash),
+}
+
+/// An ECDSA-P256 or RSA public key
+#[derive(Debug, Clone)]
+pub enum PublicKey {
+    P256(AffinePoint),
+    Rsa { modulus: BigUint, exponent: BigUint },
+}
+
+impl PublicKey {
+    /// Key from an SEC1 encoding, or from raw `x || y`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let uncompressed;
+        let bytes = if bytes.len() == 64 {
+            uncompressed = [&[0x04], bytes].concat();
+            &uncompressed
+        } else {
+            bytes
+        };
+        let point = EncodedPoint::from_bytes(bytes).map_err(|_| "Invalid public key encoding")?;
+        Option::from(AffinePoint::from_encoded_point(&point))
+            .map(Self::P256)
+            .ok_or_else(|| "Public key is not on P-256".to_string())
+    }
+
+    /// RSA key from a DER RSAPublicKey
+    fn rsa_from_der(der: &[u8]) -> Result<Self, String> {
+        let mut key = Der::new(Der::new(der).expect(TAG_SEQUENCE)?.value);
+        let modulus = BigUint::from_bytes_be(key.expect(TAG_INTEGER)?.value);
+        let exponent = BigUint::from_bytes_be(key.expect(TAG_INTEGER)?.value);
+        if modulus.bits() < MIN_RSA_BITS {
+            return Err(format!("RSA key shorter than {} bits", MIN_RSA_BITS));
+        }
+        Ok(Self::Rsa { modulus, exponent })
+    }
+
+    /// Verify an ECDSA-P256/SHA-256 signature `r || s` over `message`
+    pub fn verify(&self, message: &[u8], signature: &[u8; 64]) -> Result<(), String> {
+        let invalid = || "Invalid ECDSA signature".to_string();
+        let Self::P256(key) = self else {
+            return Err("Not an ECDSA-P256 key".to_string());
+        };
+        let scalar = |bytes: &[u8]| {
+            Option::<Scalar>::from(Scalar::from_repr(FieldBytes::clone_from_slice(bytes)))
+                .filter(|scalar| !bool::from(scalar.is_zero()))
+        };
+        let r = scalar(&signature[..32]).ok_or_else(invalid)?;
+        let s = scalar(&signature[32..]).ok_or_else(invalid)?;
+        let z = <Scalar as Reduce<U256>>::reduce_bytes(&Sha256::digest(message));
+
+        let s_inv = Option::<Scalar>::from(s.invert()).ok_or_else(invalid)?;
+        let point = (ProjectivePoint::GENERATOR * (z * s_inv)
+            + ProjectivePoint::from(*key) * (r * s_inv))
+            .to_affine();
+        if point == AffinePoint::IDENTITY {
+            return Err(invalid());
+        }
+        if <Scalar as Reduce<U256>>::reduce_bytes(&point.x()) != r {
+            return Err(invalid());
+        }
+        Ok(())
+    }
+
+    /// Verify a DER-encoded ECDSA-Sig-Value over `message`
+    pub fn verify_der(&self, message: &[u8], signature: &[u8]) -> Result<(), String> {
+        let mut sequence = Der::new(Der::new(signature).expect(TAG_SEQUENCE)?.value);
+        let mut raw = [0u8; 64];
+        for half in raw.chunks_mut(32) {
+            let integer = sequence.expect(TAG_INTEGER)?.value;
+            let integer = integer.strip_prefix(&[0]).unwrap_or(integer);
+            if integer.len() > 32 {
+                return Err("Invalid ECDSA signature".to_string());
+            }
+            half[32 - integer.len()..].copy_from_slice(integer);
+        }
+        self.verify(message, &raw)
+    }
+
+    /// Verify an RSASSA-PKCS1-v1_5 signature over `message`
+    pub fn verify_rsa(
+        &self,
+        hash: RsaHash,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), String> {
+        let invalid = || "Invalid RSA signature".to_string();
+        let Self::Rsa { modulus, exponent } = self else {
+            return Err("Not an RSA key".to_string());
+        };
+        let len = modulus.to_bytes_be().len();
+        if signature.len() != len {
+            return Err(invalid());
+        }
+        let s = BigUint::from_bytes_be(signature);
+        if &s >= modulus {
+            return Err(invalid());
+        }
+
+        // EM = 0x00 || 0x01 || 0xff... || 0x00 || DigestInfo
+        let digest_info = hash.digest_info(message);
+        let padding = len.checked_sub(digest_info.len() + 3).filter(|n| *n >= 8);
+        let padding = padding.ok_or_else(invalid)?;
+        let mut expected = vec![0x00, 0x01];
+        expected.resize(2 + padding, 0xff);
+        expected.push(0x00);
+        expected.extend_from_slice(&digest_info);
+
+        let m = s.modpow(exponent, modulus).to_bytes_be();
+        // `m` drops the leading zero byte of EM
+        if m.len() + 1 != len || m[..] != expected[1..] {
+            return Err(invalid());
+        }
+        Ok(())
+    }
+
+    fn verify_signed(
+        &self,
+        algorithm: SignatureAlgorithm,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), String> {
+        match algorithm {
+            SignatureAlgorithm::EcdsaSha256 => self.verify_der(message, signature),
+            SignatureAlgorithm::Rsa(hash) => self.verify_rsa(hash, message, signature),
+        }
+    }
+}
+
+/// Split a signed DER structure (certificate or CRL) into the signed part,
+/// the signature algorithm and the signature
+fn parse_signed<'a>(
+    der: &'a [u8],
+    what: &str,
+) -> Result<(Tlv<'a>, SignatureAlgorithm, &'a [u8]), String> {
+    let mut outer = Der::new(der);
+    let signed = outer.expect(TAG_SEQUENCE)?;
+    if !outer.is_empty() {
+        return Err(format!("Trailing data after {}", what));
+    }
+    let mut signed = Der::new(signed.value);
+    let tbs = signed.expect(TAG_SEQUENCE)?;
+    let algorithm = signed.expect(TAG_SEQUENCE)?;
+    let algorithm = match Der::new(algorithm.value).expect(TAG_OID)?.value {
+        OID_ECDSA_SHA256 => SignatureAlgorithm::EcdsaSha256,
+        OID_RSA_SHA256 => SignatureAlgorithm::Rsa(RsaHash::Sha256),
+        OID_RSA_SHA384 => SignatureAlgorithm::Rsa(RsaHash::Sha384),
+        _ => return Err(format!("{} has an unsupported signature algorithm", what)),
+    };
+    let signature = signed.expect(TAG_BIT_STRING)?.value;
+    let signature = signature.strip_prefix(&[0]).ok_or("Invalid signature bit string")?;
+    Ok((tbs, algorithm, signature))
+}
+
+/// The parts of an X.509 certificate the verifiers use
+#[derive(Debug, Clone)]
+pub struct Certificate {
+    /// The whole DER encoding
+    pub der: Vec<u8>,
+    tbs: Vec<u8>,
+    signature_algorithm: SignatureAlgorithm,
+    signature: Vec<u8>,
+    /// Serial number, as its DER INTEGER contents
+    pub serial: Vec<u8>,
+    pub issuer: Vec<u8>,
+    pub subject: Vec<u8>,
+    /// Validity period, unix seconds
+    pub not_before: u64,
+    pub not_after: u64,
+    pub public_key: PublicKey,
+    /// `(OID, value)` of every extension
+    pub extensions: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Certificate {
+    /// Parse a DER certificate with an ECDSA-P256 or RSA key
+    pub fn from_der(der: &[u8]) -> Result<Self, String> {
+        let (tbs, signature_algorithm, signature) = parse_signed(der, "Certificate")?;
+
+        let mut fields = Der::new(tbs.value);
+        fields.optional(TAG_VERSION)?;
+        let serial = fields.expect(TAG_INTEGER)?.value.to_vec();
+        fields.expect(TAG_SEQUENCE)?;
+        let issuer = fields.expect(TAG_SEQUENCE)?.raw.to_vec();
+        let mut validity = Der::new(fields.expect(TAG_SEQUENCE)?.value);
+        let not_before = parse_time(validity.next()?)?;
+        let not_after = parse_time(validity.next()?)?;
+        let subject = fields.expect(TAG_SEQUENCE)?.raw.to_vec();
+
+        let mut spki = Der::new(fields.expect(TAG_SEQUENCE)?.value);
+        let mut key_algorithm = Der::new(spki.expect(TAG_SEQUENCE)?.value);
+        let key_type = key_algorithm.expect(TAG_OID)?.value;
+        let key = spki.expect(TAG_BIT_STRING)?.value;
+        let key = key.strip_prefix(&[0]).ok_or("Invalid key")?;
+        let public_key = match key_type {
+            OID_EC_PUBLIC_KEY => {
+                if key_algorithm.expect(TAG_OID)?.value != OID_P256 {
+                    return Err("Certificate key is not on P-256".to_string());
+                }
+                PublicKey::from_bytes(key)?
+            }
+            OID_RSA_ENCRYPTION => PublicKey::rsa_from_der(key)?,
+            _ => return Err("Certificate key type is not supported".to_string()),
+        };
+
+        let mut extensions = Vec::new();
+        while !fields.is_empty() {
+            let field = fields.next()?;
+            if field.tag != TAG_EXTENSIONS {
+                continue;
+            }
+            let mut list = Der::new(Der::new(field.value).expect(TAG_SEQUENCE)?.value);
+            while !list.is_empty() {
+                let mut extension = Der::new(list.expect(TAG_SEQUENCE)?.value);
+                let oid = extension.expect(TAG_OID)?.value.to_vec();
+                let mut value = extension.next()?;
+                if value.tag != TAG_OCTET_STRING {
+                    // BOOLEAN critical
+                    value = extension.expect(TAG_OCTET_STRING)?;
+                }
+                extensions.push((oid, value.value.to_vec()));
+            }
+        }
+
+        Ok(Self {
+            der: der.to_vec(),
+            tbs: tbs.raw.to_vec(),
+            signature_algorithm,
+            signature: signature.to_vec(),
+            serial,
+            issuer,
+            subject,
+            not_before,
+            not_after,
+            public_key,
+            extensions,
+        })
+    }
+
+    /// Value of the extension `oid`
+    pub fn extension(&self, oid: &[u8]) -> Option<&[u8]> {
+        self.extensions
+            .iter()
+            .find(|(id, _)| id == oid)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// SHA-256 fingerprint of the DER encoding
+    pub fn fingerprint(&self) -> [u8; 32] {
+        Sha256::digest(&self.der).into()
+    }
+
+    /// Check that `issuer` issued and signed this certificate
+    fn verify_issued_by(&self, issuer: &Certificate) -> Result<(), String> {
+        if self.issuer != issuer.subject {
+            return Err("Certificate issuer does not match the next certificate".to_string());
+        }
+        issuer.public_key.verify_signed(self.signature_algorithm, &self.tbs, &self.signature)
+    }
+}
+
+/// The parts of an X.509 certificate revocation list the verifiers use
+#[derive(Debug, Clone)]
+pub struct Crl {
+    tbs: Vec<u8>,
+    signature_algorithm: SignatureAlgorithm,
+    signature: Vec<u8>,
+    pub issuer: Vec<u8>,
+    /// Unix seconds
+    pub this_update: u64,
+    pub next_update: Option<u64>,
+    /// Serial numbers of the revoked certificates
+    pub revoked: Vec<Vec<u8>>,
+}
+
+impl Crl {
+    /// Parse a DER CRL, or the hex encoding of one as some PCCS versions serve it
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.first() == Some(&TAG_SEQUENCE) {
+            return Self::from_der(bytes);
+        }
+        let text = std::str::from_utf8(bytes).map_err(|_| "CRL is neither DER nor hex")?;
+        Self::from_der(&hex::decode(text.trim()).map_err(|_| "CRL is neither DER nor hex")?)
+    }
+
+    /// Parse a DER CRL
+    pub fn from_der(der: &[u8]) -> Result<Self, String> {
+        let (tbs, signature_algorithm, signature) = parse_signed(der, "CRL")?;
+
+        let mut fields = Der::new(tbs.value);
+        fields.optional(TAG_INTEGER)?;
+        fields.expect(TAG_SEQUENCE)?;
+        let issuer = fields.expect(TAG_SEQUENCE)?.raw.to_vec();
+        let this_update = parse_time(fields.next()?)?;
+        let next_update = match fields.optional(TAG_UTC_TIME)? {
+            Some(time) => Some(parse_time(time)?),
+            None => fields.optional(TAG_GENERALIZED_TIME)?.map(parse_time).transpose()?,
+        };
+
+        let mut revoked = Vec::new();
+        if let Some(entries) = fields.optional(TAG_SEQUENCE)? {
+            let mut entries = Der::new(entries.value);
+            while !entries.is_empty() {
+                let mut entry = Der::new(entries.expect(TAG_SEQUENCE)?.value);
+                revoked.push(entry.expect(TAG_INTEGER)?.value.to_vec());
+            }
+        }
+        fields.optional(TAG_CRL_EXTENSIONS)?;
+        if !fields.is_empty() {
+            return Err("Trailing data in CRL".to_string());
+        }
+
+        Ok(Self {
+            tbs: tbs.raw.to_vec(),
+            signature_algorithm,
+            signature: signature.to_vec(),
+            issuer,
+            this_update,
+            next_update,
+            revoked,
+        })
+    }
+
+    /// Check that `issuer` signed this CRL and that it is current at `now`
+    pub fn verify(&self, issuer: &Certificate, now: u64) -> Result<(), String> {
+        if self.issuer != issuer.subject {
+            return Err("CRL issuer does not match its signing certificate".to_string());
+        }
+        issuer.public_key.verify_signed(self.signature_algorithm, &self.tbs, &self.signature)?;
+        if now < self.this_update || self.next_update.is_some_and(|next| now > next) {
+            return Err("CRL is not current".to_string());
+        }
+        Ok(())
+    }
+
+    /// Whether this CRL covers `certificate` and lists it as revoked
+    pub fn is_revoked(&self, certificate: &Certificate) -> bool {
+        certificate.issuer == self.issuer && self.revoked.contains(&certificate.serial)
+    }
+}
+
+/// Certificates of a PEM bundle, in order
+pub fn parse_pem_chain(pem: &[u8]) -> Result<Vec<Certificate>, String> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+
+    // Quotes pad the chain with NUL bytes
+    let text = std::str::from_utf8(pem).map_err(|_| "Certificate chain is not PEM")?;
+    let mut certificates = Vec::new();
+    let mut rest = text.trim_end_matches('\0');
+    while let Some(start) = rest.find(BEGIN) {
+        let body = &rest[start + BEGIN.len()..];
+        let end = body.find(END).ok_or("Unterminated PEM certificate")?;
+        let base64: String = body[..end].split_whitespace().collect();
+        let der = Base64::decode_vec(&base64).map_err(|e| format!("Invalid PEM: {}", e))?;
+        certificates.push(Certificate::from_der(&der)?);
+        rest = &body[end + END.len()..];
+    }
+    if certificates.is_empty() {
+        return Err("No certificate in PEM chain".to_string());
+    }
+    Ok(certificates)
+}
+
+/// Load a root certificate from a PEM or DER file
+pub fn load_root(path: &str) -> Result<Certificate, String> {
+    let contents =
+        std::fs::read(path).map_err(|e| format!("Failed to read root CA {}: {}", path, e))?;
+    let root = if contents.first() == Some(&TAG_SEQUENCE) {
+        Certificate::from_der(&contents)?
+    } else {
+        let mut chain = parse_pem_chain(&contents)?;
+        if chain.len() != 1 {
+            return Err(format!("Root CA file {} must hold one certificate", path));
+        }
+        chain.remove(0)
+    };
+    root.verify_issued_by(&root)
+        .map_err(|e| format!("Root CA {} is not self-signed: {}", path, e))?;
+    Ok(root)
+}
+
+/// Verify `chain` (leaf first) at unix time `now`: every certificate is valid
+/// and signed by the next one, and the last one is `root`
+pub fn verify_chain(chain: &[Certificate], root: &Certificate, now: u64) -> Result<(), String> {
+    let last = chain.last().ok_or("Empty certificate chain")?;
+    if last.der != root.der {
+        return Err(format!(
+            "Certificate chain ends in untrusted root {}",
+            hex::encode(last.fingerprint())
+        ));
+    }
+    for (index, certificate) in chain.iter().enumerate() {
+        if now < certificate.not_before || now > certificate.not_after {
+            return Err(format!("Certificate {} of the chain is not valid now", index));
+        }
+        let issuer = chain.get(index + 1).unwrap_or(root);
+        certificate
+            .verify_issued_by(issuer)
+            .map_err(|e| format!("Certificate {} of the chain: {}", index, e))?;
+    }
+    Ok(())
+}
+
+/// UTCTime or GeneralizedTime in UTC as unix seconds
+fn parse_time(tlv: Tlv) -> Result<u64, String> {
+    let text = std::str::from_utf8(tlv.value).map_err(|_| "Invalid certificate time")?;
+    let digits = text.strip_suffix('Z').ok_or("Certificate time is not UTC")?;
+    let full = match (tlv.tag, digits.len()) {
+        (TAG_UTC_TIME, 12) => {
+            // Two-digit years 50-99 are 19xx
+            let century = if &digits[..2] >= "50" { "19" } else { "20" };
+            format!("{}{}", century, digits)
+        }
+        (TAG_GENERALIZED_TIME, 14) => digits.to_string(),
+        _ => return Err(format!("Unsupported certificate time {:?}", text)),
+    };
+    let field = |range: std::ops::Range<usize>| {
+        full[range].parse::<u64>().map_err(|_| format!("Invalid certificate time {:?}", text))
+    };
+    Ok(unix_time(
+        field(0..4)?,
+        field(4..6)?,
+        field(6..8)?,
+        field(8..10)? * 3600 + field(10..12)? * 60 + field(12..14)?,
+    ))
+}
+
+/// ISO 8601 UTC timestamp `YYYY-MM-DDTHH:MM:SSZ` (fractional seconds
+/// ignored) as unix seconds
+pub fn parse_iso8601(text: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid timestamp {:?}", text);
+    let text_utc = text.strip_suffix('Z').ok_or_else(invalid)?;
+    let (date, time) = text_utc.split_once('T').ok_or_else(invalid)?;
+    let time = time.split('.').next().unwrap_or(time);
+    let numbers = |part: &str, separator: char| {
+        part.split(separator)
+            .map(|n| n.parse::<u64>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()
+    };
+    match (numbers(date, '-')?.as_slice(), numbers(time, ':')?.as_slice()) {
+        (&[year, month, day], &[hour, minute, second]) => {
+            Ok(unix_time(year, month, day, hour * 3600 + minute * 60 + second))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// Unix seconds of a proleptic Gregorian date plus `seconds` into the day
+fn unix_time(year: u64, month: u64, day: u64, seconds: u64) -> u64 {
+    // Days from civil, counting years from March so leap days come last
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    days * 86_400 + seconds
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use p256::elliptic_curve::sec1::ToEncodedPoint;
+
+    /// Validity of test certificates unless given otherwise
+    pub(crate) const NOT_BEFORE: &str = "20200101000000Z";
+    pub(crate) const NOT_AFTER: &str = "20491231235959Z";
+    /// 2025-01-01T00:00:00Z, when the test certificates are checked
+    pub(crate) const NOW: u64 = 1_735_689_600;
+
+    const TAG_SET: u8 = 0x31;
+    const TAG_UTF8_STRING: u8 = 0x0c;
+    /// commonName, 2.5.4.3
+    const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+
+    /// DER TLV of `tag` around the concatenated `parts`
+    pub(crate) fn der(tag: u8, parts: &[&[u8]]) -> Vec<u8> {
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        let mut encoded = vec![tag];
+        match len {
+            0..=0x7f => encoded.push(len as u8),
+            0x80..=0xff => encoded.extend([0x81, len as u8]),
+            _ => encoded.extend([0x82, (len >> 8) as u8, len as u8]),
+        }
+        for part in parts {
+            encoded.extend_from_slice(part);
+        }
+        encoded
+    }
+
+    /// P-256 private key derived from `seed`
+    pub(crate) fn key(seed: u8) -> Scalar {
+        <Scalar as Reduce<U256>>::reduce_bytes(&Sha256::digest([seed]))
+    }
+
+    /// Uncompressed SEC1 public key of `key`
+    pub(crate) fn public_key(key: &Scalar) -> Vec<u8> {
+        (ProjectivePoint::GENERATOR * key).to_affine().to_encoded_point(false).as_bytes().to_vec()
+    }
+
+    /// ECDSA-P256/SHA-256 signature `r || s` over `message`, with a nonce
+    /// derived from the key and the message
+    pub(crate) fn sign(key: &Scalar, message: &[u8]) -> [u8; 64] {
+        let reduce = |bytes: &FieldBytes| <Scalar as Reduce<U256>>::reduce_bytes(bytes);
+        let z = reduce(&Sha256::digest(message));
+        let k = reduce(&Sha256::new().chain_update(key.to_repr()).chain_update(message).finalize());
+        let r = reduce(&(ProjectivePoint::GENERATOR * k).to_affine().x());
+        let s = k.invert().unwrap() * (z + r * key);
+        let mut signature = [0u8; 64];
+        signature[..32].copy_from_slice(&r.to_repr());
+        signature[32..].copy_from_slice(&s.to_repr());
+        signature
+    }
+
+    /// The same signature as a DER ECDSA-Sig-Value
+    fn der_signature(signature: &[u8; 64]) -> Vec<u8> {
+        let integer = |half: &[u8]| {
+            let half = &half[half.iter().take_while(|b| **b == 0).count()..];
+            let pad: &[u8] = if half[0] & 0x80 != 0 { &[0] } else { &[] };
+            der(TAG_INTEGER, &[pad, half])
+        };
+        der(TAG_SEQUENCE, &[&integer(&signature[..32]), &integer(&signature[32..])])
+    }
+
+    /// `tbs` signed with ecdsa-with-SHA256 by `key`
+    fn signed(tbs: &[u8], key: &Scalar) -> Vec<u8> {
+        let algorithm = der(TAG_SEQUENCE, &[&der(TAG_OID, &[OID_ECDSA_SHA256])]);
+        let signature = der_signature(&sign(key, tbs));
+        der(TAG_SEQUENCE, &[tbs, &algorithm, &der(TAG_BIT_STRING, &[&[0], &signature])])
+    }
+
+    /// Name with a single common name
+    fn name(common_name: &str) -> Vec<u8> {
+        let attribute = der(
+            TAG_SEQUENCE,
+            &[&der(TAG_OID, &[OID_COMMON_NAME]), &der(TAG_UTF8_STRING, &[common_name.as_bytes()])],
+        );
+        der(TAG_SEQUENCE, &[&der(TAG_SET, &[&attribute])])
+    }
+
+    /// Serial number of the test certificate of `subject`
+    pub(crate) fn serial(subject: &str) -> Vec<u8> {
+        [&[0x01], &Sha256::digest(subject.as_bytes())[..8]].concat()
+    }
+
+    /// Certificate for `subject` and its key, issued by `issuer` and signed
+    /// with `issuer_key`, valid between two GeneralizedTimes and carrying
+    /// the extensions `(OID, value)`
+    pub(crate) fn certificate(
+        subject: &str,
+        key: &Scalar,
+        (issuer, issuer_key): (&str, &Scalar),
+        (not_before, not_after): (&str, &str),
+        extensions: &[(&[u8], Vec<u8>)],
+    ) -> Vec<u8> {
+        let algorithm = der(TAG_SEQUENCE, &[&der(TAG_OID, &[OID_ECDSA_SHA256])]);
+        let key_algorithm =
+            der(TAG_SEQUENCE, &[&der(TAG_OID, &[OID_EC_PUBLIC_KEY]), &der(TAG_OID, &[OID_P256])]);
+        let spki =
+            der(TAG_SEQUENCE, &[&key_algorithm, &der(TAG_BIT_STRING, &[&[0], &public_key(key)])]);
+        let validity = der(
+            TAG_SEQUENCE,
+            &[
+                &der(TAG_GENERALIZED_TIME, &[not_before.as_bytes()]),
+                &der(TAG_GENERALIZED_TIME, &[not_after.as_bytes()]),
+            ],
+        );
+        let extensions: Vec<u8> = extensions
+            .iter()
+            .flat_map(|(oid, value)| {
+                der(TAG_SEQUENCE, &[&der(TAG_OID, &[oid]), &der(TAG_OCTET_STRING, &[value])])
+            })
+            .collect();
+        let tbs = der(
+            TAG_SEQUENCE,
+            &[
+                &der(TAG_VERSION, &[&der(TAG_INTEGER, &[&[2]])]),
+                &der(TAG_INTEGER, &[&serial(subject)]),
+                &algorithm,
+                &name(issuer),
+                &validity,
+                &name(subject),
+                &spki,
+                &der(TAG_EXTENSIONS, &[&der(TAG_SEQUENCE, &[&extensions])]),
+            ],
+        );
+        signed(&tbs, issuer_key)
+    }
+
+    /// CRL of `issuer` signed with `issuer_key`, current between two
+    /// UTCTimes (`YYMMDDHHMMSSZ`), revoking the serial numbers `revoked`
+    pub(crate) fn crl(
+        (issuer, issuer_key): (&str, &Scalar),
+        (this_update, next_update): (&str, &str),
+        revoked: &[Vec<u8>],
+    ) -> Vec<u8> {
+        let algorithm = der(TAG_SEQUENCE, &[&der(TAG_OID, &[OID_ECDSA_SHA256])]);
+        let entries: Vec<u8> = revoked
+            .iter()
+            .flat_map(|serial| {
+                der(
+                    TAG_SEQUENCE,
+                    &[&der(TAG_INTEGER, &[serial]), &der(TAG_UTC_TIME, &[this_update.as_bytes()])],
+                )
+            })
+            .collect();
+        let mut fields = vec![
+            der(TAG_INTEGER, &[&[1]]),
+            algorithm,
+            name(issuer),
+            der(TAG_UTC_TIME, &[this_update.as_bytes()]),
+            der(TAG_UTC_TIME, &[next_update.as_bytes()]),
+        ];
+        if !revoked.is_empty() {
+            fields.push(der(TAG_SEQUENCE, &[&entries]));
+        }
+        let fields: Vec<&[u8]> = fields.iter().map(Vec::as_slice).collect();
+        signed(&der(TAG_SEQUENCE, &fields), issuer_key)
+    }
+
+    /// PEM bundle of the DER certificates `certificates`
+    pub(crate) fn pem(certificates: &[&[u8]]) -> Vec<u8> {
+        let mut pem = String::new();
+        for der in certificates {
+            pem.push_str("-----BEGIN CERTIFICATE-----\n");
+            let base64 = Base64::encode_string(der);
+            for line in base64.as_bytes().chunks(64) {
+                pem.push_str(std::str::from_utf8(line).unwrap());
+                pem.push('\n');
+            }
+            pem.push_str("-----END CERTIFICATE-----\n");
+        }
+        pem.into_bytes()
+    }
+
+    /// A root, an intermediate and a leaf certificate, leaf first
+    fn chain(leaf_validity: (&str, &str)) -> Vec<Certificate> {
+        let (root_key, ca_key, leaf_key) = (key(1), key(2), key(3));
+        let validity = (NOT_BEFORE, NOT_AFTER);
+        let root = certificate("Test Root", &root_key, ("Test Root", &root_key), validity, &[]);
+        let ca = certificate("Test CA", &ca_key, ("Test Root", &root_key), validity, &[]);
+        let leaf = certificate("Test Leaf", &leaf_key, ("Test CA", &ca_key), leaf_validity, &[]);
+        [leaf, ca, root].iter().map(|der| Certificate::from_der(der).unwrap()).collect()
+    }
+
+    fn flip_last_byte(der: &[u8]) -> Vec<u8> {
+        let mut der = der.to_vec();
+        *der.last_mut().unwrap() ^= 1;
+        der
+    }
+
+    #[test]
+    fn test_der_reader() {
+        let long = der(TAG_OCTET_STRING, &[&[7; 300]]);
+        assert_eq!(&long[..4], &[TAG_OCTET_STRING, 0x82, 0x01, 0x2c]);
+        let mut reader = Der::new(&long);
+        assert_eq!(reader.expect(TAG_OCTET_STRING).unwrap().value, &[7; 300][..]);
+        assert!(reader.is_empty());
+
+        assert!(Der::new(&long).expect(TAG_SEQUENCE).is_err());
+        assert_eq!(Der::new(&long[..100]).next().unwrap_err(), "Truncated DER");
+        let too_long = [TAG_INTEGER, 0x85, 1, 1, 1, 1, 1];
+        assert_eq!(Der::new(&too_long).next().unwrap_err(), "Unsupported DER length");
+
+        assert_eq!(der_uint(&[0x00, 0xff, 0x01]).unwrap(), 0xff01);
+        assert!(der_uint(&[1; 9]).is_err());
+    }
+
+    #[test]
+    fn test_ecdsa_signatures() {
+        let signing_key = key(1);
+        let verifier = PublicKey::from_bytes(&public_key(&signing_key)).unwrap();
+        let signature = sign(&signing_key, b"message");
+        verifier.verify(b"message", &signature).unwrap();
+        verifier.verify_der(b"message", &der_signature(&signature)).unwrap();
+
+        // Raw `x || y` keys are accepted too
+        PublicKey::from_bytes(&public_key(&signing_key)[1..])
+            .unwrap()
+            .verify(b"message", &signature)
+            .unwrap();
+
+        assert!(verifier.verify(b"other message", &signature).is_err());
+        let mut tampered = signature;
+        tampered[63] ^= 1;
+        assert!(verifier.verify(b"message", &tampered).is_err());
+        let mut zero = signature;
+        zero[..32].fill(0);
+        assert_eq!(verifier.verify(b"message", &zero).unwrap_err(), "Invalid ECDSA signature");
+        let other = PublicKey::from_bytes(&public_key(&key(2))).unwrap();
+        assert!(other.verify(b"message", &signature).is_err());
+
+        let mut off_curve = public_key(&signing_key);
+        off_curve[64] ^= 1;
+        assert!(PublicKey::from_bytes(&off_curve).is_err());
+    }
+
+    #[test]
+    fn test_chain_verified() {
+        let chain = chain((NOT_BEFORE, NOT_AFTER));
+        let root = chain[2].clone();
+        verify_chain(&chain, &root, NOW).unwrap();
+        assert_eq!(chain[0].serial, serial("Test Leaf"));
+        assert_eq!(chain[0].not_before, 1_577_836_800);
+
+        // Out of order, or up to another root
+        let swapped = [chain[1].clone(), chain[0].clone(), root.clone()];
+        assert!(verify_chain(&swapped, &root, NOW).is_err());
+        let error = verify_chain(&chain[..2], &root, NOW).unwrap_err();
+        assert!(error.contains("untrusted root"), "{}", error);
+        assert_eq!(verify_chain(&[], &root, NOW).unwrap_err(), "Empty certificate chain");
+    }
+
+    #[test]
+    fn test_chain_bad_signature() {
+        let chain = chain((NOT_BEFORE, NOT_AFTER));
+        let leaf = Certificate::from_der(&flip_last_byte(&chain[0].der)).unwrap();
+        let tampered = [leaf, chain[1].clone(), chain[2].clone()];
+        let error = verify_chain(&tampered, &chain[2], NOW).unwrap_err();
+        assert_eq!(error, "Certificate 0 of the chain: Invalid ECDSA signature");
+    }
+
+    #[test]
+    fn test_chain_expired() {
+        let chain = chain(("20200101000000Z", "20241231235959Z"));
+        let error = verify_chain(&chain, &chain[2], NOW).unwrap_err();
+        assert_eq!(error, "Certificate 0 of the chain is not valid now");
+        verify_chain(&chain, &chain[2], NOW - 86_400).unwrap();
+    }
+
+    #[test]
+    fn test_crl() {
+        let chain = chain((NOT_BEFORE, NOT_AFTER));
+        let ca = ("Test CA", &key(2));
+        let der = crl(ca, ("241201000000Z", "250201000000Z"), &[serial("Test Leaf")]);
+        let list = Crl::from_der(&der).unwrap();
+        list.verify(&chain[1], NOW).unwrap();
+        assert!(list.is_revoked(&chain[0]));
+        // Only certificates of its issuer are covered
+        assert!(!list.is_revoked(&chain[1]));
+
+        // Some PCCS versions serve CRLs hex-encoded
+        let hex_crl = Crl::from_bytes(hex::encode(&der).as_bytes()).unwrap();
+        assert_eq!(hex_crl.revoked, list.revoked);
+        assert!(Crl::from_bytes(b"not a CRL").is_err());
+
+        assert_eq!(list.verify(&chain[1], NOW + 32 * 86_400).unwrap_err(), "CRL is not current");
+        assert_eq!(list.verify(&chain[1], NOW - 32 * 86_400).unwrap_err(), "CRL is not current");
+        assert!(list.verify(&chain[2], NOW).is_err());
+        let tampered = Crl::from_der(&flip_last_byte(&der)).unwrap();
+        assert_eq!(tampered.verify(&chain[1], NOW).unwrap_err(), "Invalid ECDSA signature");
+
+        let empty = Crl::from_der(&crl(ca, ("241201000000Z", "250201000000Z"), &[])).unwrap();
+        empty.verify(&chain[1], NOW).unwrap();
+        assert!(!empty.is_revoked(&chain[0]));
+    }
+
+    #[test]
+    fn test_pem_chain() {
+        let chain = chain((NOT_BEFORE, NOT_AFTER));
+        let mut bundle = pem(&[&chain[0].der, &chain[1].der]);
+        // Quotes pad the chain with NUL bytes
+        bundle.extend([0; 16]);
+        let parsed = parse_pem_chain(&bundle).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[1].der, chain[1].der);
+
+        assert_eq!(parse_pem_chain(b"").unwrap_err(), "No certificate in PEM chain");
+        let truncated = &bundle[..bundle.len() - 40];
+        assert_eq!(parse_pem_chain(truncated).unwrap_err(), "Unterminated PEM certificate");
+    }
+
+    #[test]
+    fn test_load_root() {
+        let chain = chain((NOT_BEFORE, NOT_AFTER));
+        let dir = std::env::temp_dir();
+        let path = |name: &str| {
+            dir.join(format!("tdx-oprf-x509-{}-{}", std::process::id(), name))
+                .to_string_lossy()
+                .to_string()
+        };
+        let (der_root, pem_root, not_root) = (path("root.der"), path("root.pem"), path("ca.pem"));
+        std::fs::write(&der_root, &chain[2].der).unwrap();
+        std::fs::write(&pem_root, pem(&[&chain[2].der])).unwrap();
+        std::fs::write(&not_root, pem(&[&chain[1].der])).unwrap();
+
+        assert_eq!(load_root(&der_root).unwrap().der, chain[2].der);
+        assert_eq!(load_root(&pem_root).unwrap().der, chain[2].der);
+        let error = load_root(&not_root).unwrap_err();
+        assert!(error.contains("is not self-signed"), "{}", error);
+        for path in [der_root, pem_root, not_root] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_times() {
+        let time = |tag: u8, text: &str| parse_time(Tlv { tag, value: text.as_bytes(), raw: &[] });
+        assert_eq!(time(TAG_UTC_TIME, "700101000000Z").unwrap(), 0);
+        assert_eq!(time(TAG_UTC_TIME, "250101000000Z").unwrap(), NOW);
+        assert_eq!(time(TAG_GENERALIZED_TIME, "20250101000000Z").unwrap(), NOW);
+        assert_eq!(time(TAG_GENERALIZED_TIME, "20240229120000Z").unwrap(), 1_709_208_000);
+        assert!(time(TAG_UTC_TIME, "250101000000+0100").is_err());
+        assert!(time(TAG_UTC_TIME, "20250101000000Z").is_err());
+
+        assert_eq!(parse_iso8601("2025-01-01T00:00:00Z").unwrap(), NOW);
+        assert_eq!(parse_iso8601("2024-02-29T12:00:00.123Z").unwrap(), 1_709_208_000);
+        assert!(parse_iso8601("2025-01-01T00:00:00").is_err());
+        assert!(parse_iso8601("2025-01-01").is_err());
+    }
+}
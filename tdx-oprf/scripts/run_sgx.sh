@@ -0,0 +1,67 @@
+#!/bin/bash
+# Script for running the OPRF enclave in SGX with Gramine
+# This script provides instructions and commands for SGX deployment
+
+cat << 'INSTRUCTIONS'
+================================================================================
+SGX OPRF Deployment Instructions
+================================================================================
+
+Prerequisites:
+1. SGX-capable host with Flexible Launch Control (FLC)
+2. Intel SGX DCAP quoting stack (libsgx-dcap-default-qpl) configured for a PCCS
+3. Gramine 1.5 or later
+4. Rust toolchain installed
+
+================================================================================
+Build Instructions:
+================================================================================
+
+1. Build the enclave for SGX mode:
+   cargo build --release --package tdx-oprf-enclave --no-default-features --features sgx
+
+2. Build the parent for SGX mode:
+   cargo build --release --package tdx-oprf-parent --no-default-features --features sgx
+
+3. Generate and sign the Gramine manifest:
+   gramine-manifest -Dentrypoint=$(realpath target/release/tdx-oprf-enclave) \
+       enclave/tdx-oprf-enclave.manifest.template tdx-oprf-enclave.manifest
+   gramine-sgx-sign --manifest tdx-oprf-enclave.manifest \
+       --output tdx-oprf-enclave.manifest.sgx
+
+4. Note the enclave measurement for the parent:
+   gramine-sgx-sigstruct-view tdx-oprf-enclave.sig
+
+================================================================================
+Deployment Steps:
+================================================================================
+
+1. Start the enclave:
+   gramine-sgx tdx-oprf-enclave
+
+2. Fetch the Intel root CA and the SGX collateral for the platform FMSPC
+   (sgx/certification/v4, see "Quote Verification" in README.md) into ./collateral
+
+3. Run the parent application on the same host:
+   ./target/release/tdx-oprf-parent \
+       --root-ca Intel_SGX_Provisioning_Certification_RootCA.pem \
+       --collateral ./collateral \
+       --mrenclave <mr_enclave from step 4 of the build>
+
+================================================================================
+Troubleshooting:
+================================================================================
+
+If /dev/attestation is missing inside the enclave:
+- Check sgx.remote_attestation = "dcap" in the manifest
+
+If quote generation fails:
+- Check the AESM service is running: systemctl status aesmd
+- Check the PCCS URL in /etc/sgx_default_qcnl.conf
+
+If the parent rejects the enclave:
+- Debug enclaves (sgx.debug = true) are always rejected
+- MRENCLAVE changes with every rebuild of the binary or manifest
+
+================================================================================
+INSTRUCTIONS
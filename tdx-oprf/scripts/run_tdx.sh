@@ -34,8 +34,13 @@ Host/Parent VM (CID 2):
 2. Verify vsock is available:
    ls -l /dev/vsock
 
-3. Run the parent application:
-   sudo ./target/release/tdx-oprf-parent
+3. Fetch the Intel root CA and the DCAP collateral for the platform FMSPC
+   (see "Quote Verification" in README.md) into ./collateral
+
+4. Run the parent application:
+   sudo ./target/release/tdx-oprf-parent \
+       --root-ca Intel_SGX_Provisioning_Certification_RootCA.pem \
+       --collateral ./collateral
 
 Guest/Enclave VM (CID 3):
 -------------------------
@@ -75,7 +80,8 @@ Notes:
 - The enclave (guest) typically runs on CID 3
 - Port 5000 is used by default for vsock communication
 - TDX attestation requires root privileges for configfs-tsm access
-- In production, implement full quote verification with Intel Attestation Service
+- Quote verification uses pre-fetched collateral; refresh it before nextUpdate,
+  or let the parent fetch it with --collateral-cache <dir>
 
 ================================================================================
 EOF
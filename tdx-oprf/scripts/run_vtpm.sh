@@ -0,0 +1,69 @@
+#!/bin/bash
+# Script for running the OPRF enclave in an Azure or GCP confidential VM
+# This script provides instructions and commands for vTPM deployment
+
+cat << 'INSTRUCTIONS'
+================================================================================
+vTPM OPRF Deployment Instructions
+================================================================================
+
+Prerequisites:
+1. Azure confidential VM (DCasv5/ECasv5 or DCesv5/ECesv5) or GCP Confidential VM
+2. tpm2-tools 5.x and read/write access to /dev/tpmrm0
+3. Rust toolchain installed
+
+================================================================================
+Build Instructions:
+================================================================================
+
+1. Build the enclave for vTPM mode:
+   cargo build --release --package tdx-oprf-enclave --no-default-features --features vtpm
+
+2. Build the parent for vTPM mode:
+   cargo build --release --package tdx-oprf-parent --no-default-features --features vtpm
+
+================================================================================
+Deployment Steps:
+================================================================================
+
+1. On GCP only, create the AK from its template and persist it, once per boot:
+   tpm2_nvread 0x01c10001 -o ak.template
+   tpm2_createprimary -C e --template-data ak.template -c ak.ctx
+   tpm2_evictcontrol -C o -c ak.ctx 0x81000003
+   (Azure persists its AK at 0x81000003 already.)
+
+2. Read the expected PCR values on a reference VM with the same image:
+   tpm2_pcrread sha256:4,7,8,9
+
+3. Start the enclave:
+   ./target/release/tdx-oprf-enclave
+
+4. Get the cloud's vTPM CA certificates:
+   - Azure: "Azure Virtual TPM Root Certificate Authority 2023" and the
+     "Global Virtual TPM CA" intermediates
+   - GCP: the "EK/AK CA Root" and "EK/AK CA Intermediate" certificates
+     referenced by the AK certificate's AIA extension
+
+5. Run the parent application on the same VM:
+   ./target/release/tdx-oprf-parent \
+       --vtpm-root vtpm_root.pem \
+       --vtpm-intermediates vtpm_intermediates.pem \
+       --pcr 4=<hex> --pcr 7=<hex>
+
+================================================================================
+Troubleshooting:
+================================================================================
+
+If no AK certificate is found:
+- Check the VM is a confidential VM with the vTPM enabled
+- Check tpm2_nvreadpublic lists 0x1c101d0 (Azure) or 0x1c10000 (GCP)
+
+If tpm2_quote fails:
+- Check /dev/tpmrm0 permissions (the tss group) or run as root
+- On GCP, check the AK is persisted: tpm2_getcap handles-persistent
+
+If the parent rejects the PCRs:
+- PCR4 and PCR8-9 change with every kernel, initrd or command line update
+
+================================================================================
+INSTRUCTIONS